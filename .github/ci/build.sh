@@ -1,10 +1,24 @@
 #!/bin/bash
 set -euo pipefail
 
+for dir in audio audio-pipeline protocol usb-stream;
+do
+    pushd $dir
+    cargo fmt --check
+    cargo clippy --all-targets -- -D warnings
+    cargo clippy --all-targets --all-features -- -D warnings
+    cargo test
+    popd
+done
+
+pushd protocol
+cargo +nightly fuzz build
+popd
+
 for dir in blus-mini-mk1 blus-mini-mk2 blackpill-usb-dac/v1.2 blackpill-usb-dac/v3.1;
 do
     pushd $dir
-    cargo fmt
+    cargo fmt --check
     cargo clippy -- -D warnings
     cargo build --release
     cargo objcopy --release -- -O binary fw-${dir//\//-}.bin
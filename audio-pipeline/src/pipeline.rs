@@ -0,0 +1,159 @@
+//! The processing of sample blocks: routing of input to output channels, per-channel processing, and gain.
+//!
+//! Input blocks are interleaved by input channel, output blocks by output channel. Every output channel is fed by one
+//! input channel (e.g. the left and right input to a two-way speaker each), and processed by a board-specific stage
+//! (e.g. biquads and a FIR). The master gain ramps towards its target, such that mute and volume changes do not click.
+
+use crate::{sample_to_f32, sample_to_u32};
+
+/// Levels of the output channels over a processed block.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct BlockLevels<const OUTPUTS: usize> {
+    /// The peak level (full-scale is 1.0).
+    pub peak_levels: [f32; OUTPUTS],
+    /// The mean of the samples (the DC component).
+    pub means: [f32; OUTPUTS],
+    /// The mean of the squared samples (the power into a unit load).
+    pub mean_squares: [f32; OUTPUTS],
+    /// The number of frames (samples per output channel).
+    pub frame_count: usize,
+}
+
+impl<const OUTPUTS: usize> BlockLevels<OUTPUTS> {
+    /// Whether any sample reached full-scale, and was thus clipped by the conversion.
+    pub fn clipped(&self) -> bool {
+        self.peak_levels.iter().any(|level| *level >= 1.0)
+    }
+
+    /// The number of output samples that were written.
+    pub fn sample_count(&self) -> usize {
+        self.frame_count * OUTPUTS
+    }
+}
+
+/// A routing that feeds consecutive, equally sized groups of output channels from one input channel each (e.g. for
+/// two inputs and four outputs, the left input to the first two-way speaker, and the right input to the second).
+///
+/// Outputs that do not divide into the inputs make the earlier groups one channel larger.
+pub const fn grouped_routing<const INPUTS: usize, const OUTPUTS: usize>() -> [usize; OUTPUTS] {
+    let mut routing = [0; OUTPUTS];
+    let mut output = 0;
+
+    while output < OUTPUTS {
+        routing[output] = output * INPUTS / OUTPUTS;
+        output += 1;
+    }
+
+    routing
+}
+
+/// The processing state of the audio path, for `INPUTS` input and `OUTPUTS` output channels.
+pub struct Pipeline<const INPUTS: usize, const OUTPUTS: usize> {
+    /// The input channel of every output channel.
+    routing: [usize; OUTPUTS],
+    /// The ramped master gain.
+    master_gain: f32,
+    /// The change of the master gain per frame.
+    ramp_step: f32,
+}
+
+impl<const INPUTS: usize, const OUTPUTS: usize> Pipeline<INPUTS, OUTPUTS> {
+    /// Create a pipeline with a routing of input channels to output channels, whose master gain ramps between zero and
+    /// full-scale within `ramp_frame_count` frames. The master gain starts silent.
+    ///
+    /// Panics, if any output channel is routed to a non-existing input channel.
+    pub fn new(routing: [usize; OUTPUTS], ramp_frame_count: u32) -> Self {
+        if routing.iter().any(|input| *input >= INPUTS) {
+            panic!("Output routed to a non-existing input.");
+        }
+
+        Pipeline {
+            routing,
+            master_gain: 0.0,
+            ramp_step: 1.0 / ramp_frame_count.max(1) as f32,
+        }
+    }
+
+    /// The present (ramped) master gain.
+    pub fn master_gain(&self) -> f32 {
+        self.master_gain
+    }
+
+    /// Set the master gain immediately, without ramping.
+    pub fn reset_master_gain(&mut self, gain: f32) {
+        self.master_gain = gain;
+    }
+
+    /// Process a block of input samples into an output buffer, as many frames as fit. The written samples are the
+    /// first [`BlockLevels::sample_count`] of the output.
+    ///
+    /// - `input_gains` apply to the input channels (e.g. the volume of the source), `output_gains` to the output
+    ///   channels (e.g. trims).
+    /// - The master gain ramps once per frame towards `target_gain`.
+    /// - `stage` processes a sample of an output channel (by index), before any gain applies.
+    ///
+    /// An incomplete frame at the end of the block is ignored.
+    #[inline]
+    pub fn process(
+        &mut self,
+        samples: &[u32],
+        output: &mut [u32],
+        input_gains: [f32; INPUTS],
+        output_gains: [f32; OUTPUTS],
+        target_gain: f32,
+        mut stage: impl FnMut(usize, f32) -> f32,
+    ) -> BlockLevels<OUTPUTS> {
+        self.process_frames(samples, output, input_gains, output_gains, target_gain, |frame| {
+            for (channel, sample) in frame.iter_mut().enumerate() {
+                *sample = stage(channel, *sample);
+            }
+        })
+    }
+
+    /// Process a block like [`Self::process`], with a `stage` that processes a frame of all output channels at
+    /// once (e.g. interleaved biquads of all channels).
+    #[inline]
+    pub fn process_frames(
+        &mut self,
+        samples: &[u32],
+        output: &mut [u32],
+        input_gains: [f32; INPUTS],
+        output_gains: [f32; OUTPUTS],
+        target_gain: f32,
+        mut stage: impl FnMut(&mut [f32; OUTPUTS]),
+    ) -> BlockLevels<OUTPUTS> {
+        let mut levels = BlockLevels {
+            peak_levels: [0.0; OUTPUTS],
+            means: [0.0; OUTPUTS],
+            mean_squares: [0.0; OUTPUTS],
+            frame_count: 0,
+        };
+
+        for (frame, output_frame) in samples.chunks_exact(INPUTS).zip(output.chunks_exact_mut(OUTPUTS)) {
+            self.master_gain += (target_gain - self.master_gain).clamp(-self.ramp_step, self.ramp_step);
+
+            let mut processed = self.routing.map(|input| sample_to_f32(frame[input]));
+            stage(&mut processed);
+
+            for (channel, (input, output)) in self.routing.iter().zip(output_frame.iter_mut()).enumerate() {
+                let gain = input_gains[*input] * self.master_gain * output_gains[channel];
+                let sample = processed[channel] * gain;
+
+                levels.peak_levels[channel] = levels.peak_levels[channel].max(sample).max(-sample);
+                levels.means[channel] += sample;
+                levels.mean_squares[channel] += sample * sample;
+                *output = sample_to_u32(sample);
+            }
+
+            levels.frame_count += 1;
+        }
+
+        let frame_count = levels.frame_count.max(1) as f32;
+        for channel in 0..OUTPUTS {
+            levels.means[channel] /= frame_count;
+            levels.mean_squares[channel] /= frame_count;
+        }
+
+        levels
+    }
+}
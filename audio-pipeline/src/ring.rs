@@ -0,0 +1,140 @@
+//! A lock-free ring buffer of samples, between one producer (e.g. a USB streaming task) and one consumer (e.g. the
+//! routing of a board), which may run at different priorities.
+//!
+//! The ring is sized in samples, not in blocks: the producer writes packets of any size, and the consumer reads as
+//! many samples as it processes at once (e.g. by a latency mode), also partially. Both sides only load and store
+//! atomic positions, such that neither ever waits on the other. The positions count samples since the start, and
+//! wrap, which is why the size must be a power of two.
+//!
+//! A ring is split once into its [`Producer`] and [`Consumer`], which may be used from different execution contexts.
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// A ring buffer of `N` samples.
+pub struct SampleRing<const N: usize> {
+    samples: UnsafeCell<[u32; N]>,
+    /// The number of samples written since the start, wrapping.
+    write: AtomicUsize,
+    /// The number of samples read since the start, wrapping.
+    read: AtomicUsize,
+}
+
+// SAFETY: The producer only writes samples outside of the range between the positions, the consumer only reads them
+// within it. The positions are only stored by their own side, after the samples were written or read.
+unsafe impl<const N: usize> Sync for SampleRing<N> {}
+
+impl<const N: usize> Default for SampleRing<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> SampleRing<N> {
+    /// Create an empty ring. Fails to compile, unless `N` is a power of two.
+    pub const fn new() -> Self {
+        const { assert!(N.is_power_of_two(), "The size of a ring must be a power of two") };
+
+        SampleRing {
+            samples: UnsafeCell::new([0; N]),
+            write: AtomicUsize::new(0),
+            read: AtomicUsize::new(0),
+        }
+    }
+
+    /// Split the ring into its producer and consumer.
+    pub fn split(&mut self) -> (Producer<'_, N>, Consumer<'_, N>) {
+        let ring = &*self;
+        (Producer { ring }, Consumer { ring })
+    }
+
+    /// The number of samples that can be read.
+    fn len(&self) -> usize {
+        self.write
+            .load(Ordering::Acquire)
+            .wrapping_sub(self.read.load(Ordering::Acquire))
+    }
+
+    fn buffer(&self) -> *mut u32 {
+        self.samples.get().cast::<u32>()
+    }
+}
+
+/// The writing side of a ring.
+pub struct Producer<'a, const N: usize> {
+    ring: &'a SampleRing<N>,
+}
+
+impl<const N: usize> Producer<'_, N> {
+    /// The number of samples that can be written.
+    pub fn free(&self) -> usize {
+        N - self.ring.len()
+    }
+
+    /// The position of the next written sample: the number of samples written since the start, wrapping.
+    pub fn position(&self) -> usize {
+        self.ring.write.load(Ordering::Relaxed)
+    }
+
+    /// Write samples, all or none. Returns whether they fit; otherwise, the ring stays unchanged (an overrun).
+    ///
+    /// Writing all or none keeps the ring aligned to frames, as long as every write holds whole frames.
+    pub fn push(&mut self, samples: impl ExactSizeIterator<Item = u32>) -> bool {
+        let count = samples.len();
+        if count > self.free() {
+            return false;
+        }
+
+        let write = self.ring.write.load(Ordering::Relaxed);
+
+        for (offset, sample) in samples.take(count).enumerate() {
+            // SAFETY: The position is free, as checked above, such that the consumer does not read it (see `Sync`).
+            unsafe { self.ring.buffer().add(write.wrapping_add(offset) % N).write(sample) };
+        }
+
+        self.ring.write.store(write.wrapping_add(count), Ordering::Release);
+        true
+    }
+}
+
+/// The reading side of a ring.
+pub struct Consumer<'a, const N: usize> {
+    ring: &'a SampleRing<N>,
+}
+
+impl<const N: usize> Consumer<'_, N> {
+    /// The number of samples that can be read.
+    pub fn len(&self) -> usize {
+        self.ring.len()
+    }
+
+    /// Whether there are no samples to read.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The position of the next read sample: the number of samples read since the start, wrapping.
+    pub fn position(&self) -> usize {
+        self.ring.read.load(Ordering::Relaxed)
+    }
+
+    /// Read up to `samples.len()` samples, and return their number. Reads fewer, if the ring holds fewer.
+    pub fn pop(&mut self, samples: &mut [u32]) -> usize {
+        let count = samples.len().min(self.len());
+        let read = self.ring.read.load(Ordering::Relaxed);
+
+        for (offset, sample) in samples[..count].iter_mut().enumerate() {
+            // SAFETY: The position was written, as checked above, such that the producer does not write it.
+            *sample = unsafe { self.ring.buffer().add(read.wrapping_add(offset) % N).read() };
+        }
+
+        self.ring.read.store(read.wrapping_add(count), Ordering::Release);
+        count
+    }
+
+    /// Drop all samples that were written so far, e.g. when the source changes.
+    pub fn clear(&mut self) {
+        self.ring
+            .read
+            .store(self.ring.write.load(Ordering::Acquire), Ordering::Release);
+    }
+}
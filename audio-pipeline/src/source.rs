@@ -0,0 +1,57 @@
+//! Audio sources, and the arbitration between them.
+
+/// An audio source.
+#[repr(u8)]
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum AudioSource {
+    None = 0,
+    Usb = 1,
+    Spdif = 2,
+    Ext = 3,
+    Rpi = 4,
+}
+
+impl TryFrom<u8> for AudioSource {
+    type Error = u8;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(AudioSource::None),
+            1 => Ok(AudioSource::Usb),
+            2 => Ok(AudioSource::Spdif),
+            3 => Ok(AudioSource::Ext),
+            4 => Ok(AudioSource::Rpi),
+            _ => Err(value),
+        }
+    }
+}
+
+/// A block of interleaved samples (1q31), as received from a source.
+pub trait AudioInput {
+    /// The source that the block originates from.
+    fn source(&self) -> AudioSource;
+
+    /// The samples, interleaved by input channel.
+    fn samples(&self) -> &[u32];
+}
+
+/// Select the source to play, after a block was received (or `None`, in case of errors or timeouts).
+///
+/// - A source that is no longer allowed is released.
+/// - Without an active source, the source of the block becomes active, if it is allowed.
+/// - Without a block, the active source is released.
+///
+/// Otherwise, the active source stays, and blocks of other sources are to be dropped.
+pub fn select_source(
+    active: AudioSource,
+    block: Option<AudioSource>,
+    allowed: impl Fn(AudioSource) -> bool,
+) -> AudioSource {
+    match (block, active) {
+        (_, active) if active != AudioSource::None && !allowed(active) => AudioSource::None,
+        (Some(source), AudioSource::None) if source != AudioSource::None && allowed(source) => source,
+        (None, _) => AudioSource::None,
+        _ => active,
+    }
+}
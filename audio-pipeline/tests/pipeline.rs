@@ -0,0 +1,175 @@
+use audio_pipeline::pipeline::{grouped_routing, Pipeline};
+use audio_pipeline::source::select_source;
+use audio_pipeline::{sample_to_f32, sample_to_u32, AudioSource};
+
+/// A half-scale frame of both input channels, of opposite signs.
+fn frames(count: usize) -> Vec<u32> {
+    (0..count)
+        .flat_map(|_| [sample_to_u32(0.5), sample_to_u32(-0.5)])
+        .collect()
+}
+
+#[test]
+fn routes_inputs_to_outputs() {
+    let mut pipeline: Pipeline<2, 4> = Pipeline::new([0, 0, 1, 1], 1);
+    pipeline.reset_master_gain(1.0);
+
+    let mut output = [0u32; 16];
+    let levels = pipeline.process(&frames(2), &mut output, [1.0; 2], [1.0; 4], 1.0, |_, sample| sample);
+
+    assert_eq!(levels.frame_count, 2);
+    assert_eq!(levels.sample_count(), 8);
+    assert_eq!(
+        output[..4],
+        [
+            sample_to_u32(0.5),
+            sample_to_u32(0.5),
+            sample_to_u32(-0.5),
+            sample_to_u32(-0.5)
+        ]
+    );
+    assert_eq!(levels.peak_levels, [0.5; 4]);
+    assert_eq!(levels.means, [0.5, 0.5, -0.5, -0.5]);
+    assert_eq!(levels.mean_squares, [0.25; 4]);
+    assert!(!levels.clipped());
+}
+
+#[test]
+fn groups_outputs_by_input() {
+    assert_eq!(grouped_routing::<2, 4>(), [0, 0, 1, 1]);
+    assert_eq!(grouped_routing::<2, 2>(), [0, 1]);
+    assert_eq!(grouped_routing::<1, 3>(), [0, 0, 0]);
+    assert_eq!(grouped_routing::<2, 5>(), [0, 0, 0, 1, 1]);
+    assert_eq!(grouped_routing::<2, 8>(), [0, 0, 0, 0, 1, 1, 1, 1]);
+}
+
+#[test]
+fn applies_stage_and_gains() {
+    let mut pipeline: Pipeline<2, 2> = Pipeline::new([0, 1], 1);
+    pipeline.reset_master_gain(0.5);
+
+    let mut output = [0u32; 16];
+    let levels = pipeline.process(
+        &frames(1),
+        &mut output,
+        [1.0, 0.5],
+        [0.5, 1.0],
+        0.5,
+        |channel, sample| {
+            if channel == 0 {
+                -sample
+            } else {
+                sample
+            }
+        },
+    );
+
+    assert_eq!(sample_to_f32(output[0]), -0.125);
+    assert_eq!(sample_to_f32(output[1]), -0.125);
+    assert_eq!(levels.peak_levels, [0.125; 2]);
+}
+
+#[test]
+fn ramps_master_gain() {
+    let mut pipeline: Pipeline<2, 2> = Pipeline::new([0, 1], 4);
+    assert_eq!(pipeline.master_gain(), 0.0);
+
+    let mut output = [0u32; 16];
+    pipeline.process(&frames(2), &mut output, [1.0; 2], [1.0; 2], 1.0, |_, sample| sample);
+    assert_eq!(pipeline.master_gain(), 0.5);
+    assert_eq!(sample_to_f32(output[0]), 0.125);
+    assert_eq!(sample_to_f32(output[2]), 0.25);
+
+    pipeline.process(&frames(4), &mut output, [1.0; 2], [1.0; 2], 1.0, |_, sample| sample);
+    assert_eq!(pipeline.master_gain(), 1.0);
+
+    pipeline.process(&frames(1), &mut output, [1.0; 2], [1.0; 2], 0.0, |_, sample| sample);
+    assert_eq!(pipeline.master_gain(), 0.75);
+}
+
+#[test]
+fn reports_clipping() {
+    let mut pipeline: Pipeline<2, 2> = Pipeline::new([0, 1], 1);
+    pipeline.reset_master_gain(1.0);
+
+    let mut output = [0u32; 16];
+    let levels = pipeline.process(&frames(1), &mut output, [2.0; 2], [1.0; 2], 1.0, |_, sample| sample);
+
+    assert!(levels.clipped());
+    assert_eq!(output[0], sample_to_u32(1.0));
+}
+
+#[test]
+fn ignores_incomplete_frames() {
+    let mut pipeline: Pipeline<2, 2> = Pipeline::new([0, 1], 1);
+
+    let mut output = [0u32; 16];
+    let levels = pipeline.process(&frames(1)[..1], &mut output, [1.0; 2], [1.0; 2], 1.0, |_, sample| {
+        sample
+    });
+
+    assert_eq!(levels.frame_count, 0);
+    assert_eq!(levels.sample_count(), 0);
+    assert_eq!(levels.means, [0.0; 2]);
+}
+
+#[test]
+fn processes_frames() {
+    let mut pipeline: Pipeline<2, 4> = Pipeline::new([0, 0, 1, 1], 1);
+    pipeline.reset_master_gain(1.0);
+
+    // The stage sees the routed samples of all output channels at once.
+    let mut output = [0u32; 8];
+    let levels = pipeline.process_frames(&frames(2), &mut output, [1.0; 2], [1.0; 4], 1.0, |frame| {
+        assert_eq!(*frame, [0.5, 0.5, -0.5, -0.5]);
+        frame.swap(0, 3);
+    });
+
+    assert_eq!(levels.frame_count, 2);
+    assert_eq!(sample_to_f32(output[0]), -0.5);
+    assert_eq!(sample_to_f32(output[3]), 0.5);
+}
+
+#[test]
+fn fills_output_buffer() {
+    let mut pipeline: Pipeline<2, 4> = Pipeline::new([0, 0, 1, 1], 1);
+    pipeline.reset_master_gain(1.0);
+
+    // Only whole frames are written, and the rest of the buffer is left as it was.
+    let mut output = [u32::MAX; 10];
+    let levels = pipeline.process(&frames(4), &mut output, [1.0; 2], [1.0; 4], 1.0, |_, sample| sample);
+
+    assert_eq!(levels.frame_count, 2);
+    assert_eq!(levels.sample_count(), 8);
+    assert_eq!(output[8..], [u32::MAX; 2]);
+}
+
+#[test]
+fn selects_sources() {
+    let all = |_| true;
+    let no_usb = |source| source != AudioSource::Usb;
+
+    // The first block selects its source.
+    assert_eq!(
+        select_source(AudioSource::None, Some(AudioSource::Usb), all),
+        AudioSource::Usb
+    );
+    assert_eq!(
+        select_source(AudioSource::None, Some(AudioSource::Usb), no_usb),
+        AudioSource::None
+    );
+
+    // The active source stays, while blocks of other sources arrive.
+    assert_eq!(
+        select_source(AudioSource::Usb, Some(AudioSource::Spdif), all),
+        AudioSource::Usb
+    );
+
+    // Errors and disallowed sources release the active source.
+    assert_eq!(select_source(AudioSource::Usb, None, all), AudioSource::None);
+    assert_eq!(
+        select_source(AudioSource::Usb, Some(AudioSource::Usb), no_usb),
+        AudioSource::None
+    );
+    assert_eq!(select_source(AudioSource::None, None, all), AudioSource::None);
+}
@@ -0,0 +1,96 @@
+use audio_pipeline::ring::SampleRing;
+
+#[test]
+fn passes_samples_in_order() {
+    let mut ring: SampleRing<8> = SampleRing::new();
+    let (mut producer, mut consumer) = ring.split();
+
+    assert!(consumer.is_empty());
+    assert!(producer.push([1, 2, 3].into_iter()));
+    assert_eq!(consumer.len(), 3);
+    assert_eq!(producer.free(), 5);
+
+    let mut samples = [0; 3];
+    assert_eq!(consumer.pop(&mut samples), 3);
+    assert_eq!(samples, [1, 2, 3]);
+    assert!(consumer.is_empty());
+}
+
+#[test]
+fn wraps_around() {
+    let mut ring: SampleRing<4> = SampleRing::new();
+    let (mut producer, mut consumer) = ring.split();
+    let mut samples = [0; 3];
+
+    for start in 0..10 {
+        assert!(producer.push(start..start + 3));
+        assert_eq!(consumer.pop(&mut samples), 3);
+        assert_eq!(samples, [start, start + 1, start + 2]);
+    }
+}
+
+#[test]
+fn rejects_writes_that_do_not_fit() {
+    let mut ring: SampleRing<4> = SampleRing::new();
+    let (mut producer, mut consumer) = ring.split();
+
+    assert!(producer.push([1, 2, 3].into_iter()));
+    assert!(!producer.push([4, 5].into_iter()));
+    assert_eq!(consumer.len(), 3);
+    assert!(producer.push([4].into_iter()));
+    assert_eq!(producer.free(), 0);
+
+    let mut samples = [0; 4];
+    assert_eq!(consumer.pop(&mut samples), 4);
+    assert_eq!(samples, [1, 2, 3, 4]);
+}
+
+#[test]
+fn reads_partially() {
+    let mut ring: SampleRing<8> = SampleRing::new();
+    let (mut producer, mut consumer) = ring.split();
+    let mut samples = [0; 4];
+
+    assert!(producer.push([1, 2].into_iter()));
+    assert_eq!(consumer.pop(&mut samples), 2);
+    assert_eq!(samples[..2], [1, 2]);
+    assert_eq!(consumer.pop(&mut samples), 0);
+
+    assert!(producer.push([3, 4, 5, 6, 7].into_iter()));
+    assert_eq!(consumer.pop(&mut samples[..2]), 2);
+    assert_eq!(samples[..2], [3, 4]);
+    assert_eq!(consumer.len(), 3);
+}
+
+#[test]
+fn clears() {
+    let mut ring: SampleRing<8> = SampleRing::new();
+    let (mut producer, mut consumer) = ring.split();
+
+    assert!(producer.push([1, 2, 3].into_iter()));
+    consumer.clear();
+    assert!(consumer.is_empty());
+    assert_eq!(producer.free(), 8);
+
+    let mut samples = [0; 2];
+    assert!(producer.push([4].into_iter()));
+    assert_eq!(consumer.pop(&mut samples), 1);
+    assert_eq!(samples[0], 4);
+}
+
+#[test]
+fn counts_positions() {
+    let mut ring: SampleRing<4> = SampleRing::new();
+    let (mut producer, mut consumer) = ring.split();
+    let mut samples = [0; 3];
+
+    for round in 0..3 {
+        assert_eq!(producer.position(), 3 * round);
+        assert!(producer.push([1, 2, 3].into_iter()));
+        assert_eq!(consumer.position(), 3 * round);
+        consumer.pop(&mut samples);
+    }
+
+    assert_eq!(producer.position(), 9);
+    assert_eq!(consumer.position(), 9);
+}
@@ -1,26 +1,10 @@
+pub use audio_pipeline::{sample_to_f32, sample_to_u32};
 use biquad::*;
 
-const MAX_DELAY_LENGTH: usize = 32;
+use crate::filter_config::ConfigError;
 
-const Q31_SCALING_FACTOR: f32 = 2147483648.0;
-
-fn clip_q63_to_q31(sample: i64) -> i32 {
-    if (sample >> 32) as i32 != (sample as i32) >> 31 {
-        0x7FFFFFFF ^ ((sample >> 63) as i32)
-    } else {
-        sample as i32
-    }
-}
-
-/// Convert a float sample to its 1q31 representation. Clips to [-1, 1).
-pub fn sample_to_u32(sample: f32) -> u32 {
-    clip_q63_to_q31((sample * Q31_SCALING_FACTOR) as i64) as u32
-}
-
-/// Convert a 1q31 sample to float in the range [-1, 1)
-pub fn sample_to_f32(sample: u32) -> f32 {
-    (sample as i32 as f32) / Q31_SCALING_FACTOR
-}
+/// The maximum filter delay in number of samples.
+pub const MAX_DELAY_LENGTH: usize = 32;
 
 struct Delay {
     read_index: usize,
@@ -43,17 +27,18 @@ impl Delay {
         }
     }
 
-    /// Increments read and write positions in a circular way.
+    /// Increments read and write positions in a circular way, over `length + 1` positions.
     fn increment(&mut self) {
         for index in [&mut self.read_index, &mut self.write_index] {
             *index += 1;
-            if *index >= self.length {
+            if *index > self.length {
                 *index = 0;
             }
         }
     }
 
     /// Consume a sample, and give back a delayed sample.
+    #[inline]
     fn tick(&mut self, sample: f32) -> f32 {
         self.increment();
 
@@ -71,6 +56,8 @@ pub struct Filter<'d, B: Biquad<f32>> {
     /// The chain of biquad filters.
     /// FIXME: A vector is slower than an array, why is that?
     biquads: &'d mut [B],
+    /// The number of biquads in the chain that are in use.
+    stage_count: usize,
 }
 
 impl<'d, B: Biquad<f32>> Filter<'d, B> {
@@ -85,20 +72,63 @@ impl<'d, B: Biquad<f32>> Filter<'d, B> {
         Filter {
             gain,
             delay: Delay::new(delay_length),
+            stage_count: biquads.len(),
             biquads,
         }
     }
 
+    /// The maximum number of biquads that the filter can run.
+    pub fn max_stage_count(&self) -> usize {
+        self.biquads.len()
+    }
+
+    /// Reconfigure the filter, and reset its state.
+    ///
+    /// # Arguments
+    ///
+    /// * `gain` - A linear gain for the filter.
+    /// * `delay_length` - A delay to apply, in number of samples.
+    /// * `coefficients` - The coefficients of the biquads to run.
+    ///
+    /// Fails with [`ConfigError::TooManyStages`] for more coefficients than [`Self::max_stage_count`], and with
+    /// [`ConfigError::InvalidDelay`] for a delay above [`MAX_DELAY_LENGTH`]. The filter is unchanged then.
+    pub fn configure(
+        &mut self,
+        gain: f32,
+        delay_length: usize,
+        coefficients: &[Coefficients<f32>],
+    ) -> Result<(), ConfigError> {
+        if coefficients.len() > self.biquads.len() {
+            return Err(ConfigError::TooManyStages);
+        }
+
+        if delay_length > MAX_DELAY_LENGTH {
+            return Err(ConfigError::InvalidDelay);
+        }
+
+        self.gain = gain;
+        self.delay = Delay::new(delay_length);
+        self.stage_count = coefficients.len();
+
+        for (biquad, coefficients) in self.biquads.iter_mut().zip(coefficients) {
+            biquad.update_coefficients(*coefficients);
+        }
+
+        self.reset_state();
+        Ok(())
+    }
+
     /// Resets the state of the internal biquad filters.
     pub fn reset_state(&mut self) {
-        for biquad in self.biquads.as_mut() {
+        for biquad in self.biquads.iter_mut() {
             biquad.reset_state();
         }
     }
 
     /// Run the filter on a provided sample.
+    #[inline]
     pub fn run(&mut self, mut sample: f32) -> f32 {
-        for b in self.biquads.as_mut() {
+        for b in self.biquads[..self.stage_count].iter_mut() {
             sample = b.run(sample);
         }
         sample *= self.gain;
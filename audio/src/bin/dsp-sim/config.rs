@@ -0,0 +1,220 @@
+//! The configuration of the simulation, from a JSON document as exported by the device (shell `config export`).
+//!
+//! The volume and the filter configuration of all output channels are used; mute and the source selection are
+//! ignored. Unlike the import on the device, a document that does not fit is rejected with its first error.
+use std::fs;
+use std::path::Path;
+
+use audio::filter_config::{ConfigError, FilterConfig, StageConfig, StageKind};
+use audio::filter_graph::FilterGraph;
+use protocol::json::{self, Parser};
+
+use crate::{OUTPUT_CHANNEL_COUNT, SAMPLE_RATE_HZ};
+
+/// The version of the document format, as written by the device.
+const CONFIG_VERSION: u32 = 1;
+
+/// The name of stages that are given by coefficients.
+const COEFFICIENTS_TYPE: &str = "coefficients";
+
+/// The configuration of the signal processing.
+pub struct Config {
+    /// The master volume in dB.
+    pub volume_db: f32,
+    /// The filter configuration of every output channel.
+    pub channels: FilterGraph<OUTPUT_CHANNEL_COUNT>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Config {
+    /// A configuration that passes samples unchanged, at full volume.
+    pub fn new() -> Self {
+        Config {
+            volume_db: 0.0,
+            channels: FilterGraph::new(),
+        }
+    }
+}
+
+/// Describe an error of the parser at a field.
+fn field<T>(result: Result<T, json::Error>, path: &str) -> Result<T, String> {
+    result.map_err(|error| format!("{}: {:?}", path, error))
+}
+
+fn number(parser: &mut Parser, path: &str) -> Result<f32, String> {
+    field(parser.number::<f32>(), path)
+}
+
+fn document(parser: &mut Parser, config: &mut Config) -> Result<(), String> {
+    field(parser.begin_object(), "document")?;
+
+    while let Some(key) = field(parser.next_key(), "document")? {
+        match key {
+            "version" => {
+                let version = field(parser.number::<u32>(), key)?;
+
+                if version != CONFIG_VERSION {
+                    return Err(format!("version: unsupported version {}", version));
+                }
+            }
+            "volume_db" => config.volume_db = number(parser, key)?,
+            "channels" => {
+                field(parser.begin_array(), key)?;
+
+                let mut channel = 0;
+                while field(parser.next_element(), key)? {
+                    let filter = config
+                        .channels
+                        .iter_mut()
+                        .nth(channel)
+                        .ok_or_else(|| format!("channels[{}]: too many channels", channel))?;
+
+                    self::channel(parser, &format!("channels[{}]", channel), filter)?;
+                    channel += 1;
+                }
+            }
+            _ => field(parser.skip_value(), key)?,
+        }
+    }
+
+    field(parser.end(), "document")
+}
+
+fn channel(parser: &mut Parser, path: &str, config: &mut FilterConfig) -> Result<(), String> {
+    field(parser.begin_object(), path)?;
+
+    while let Some(key) = field(parser.next_key(), path)? {
+        let field_path = format!("{}.{}", path, key);
+
+        match key {
+            "gain_db" => config.gain_db = number(parser, &field_path)?,
+            "inverted" => config.inverted = field(parser.boolean(), &field_path)?,
+            "delay" => config.delay = field(parser.number::<usize>(), &field_path)?,
+            "stages" => {
+                field(parser.begin_array(), &field_path)?;
+                config.stages.clear();
+
+                let mut index = 0;
+                while field(parser.next_element(), &field_path)? {
+                    let stage_path = format!("{}[{}]", field_path, index);
+                    let stage = stage(parser, &stage_path)?;
+
+                    if config.stages.push(stage).is_err() {
+                        return Err(format!("{}: too many stages", stage_path));
+                    }
+
+                    index += 1;
+                }
+            }
+            _ => return Err(format!("{}: unknown field", field_path)),
+        }
+    }
+
+    Ok(())
+}
+
+fn stage(parser: &mut Parser, path: &str) -> Result<StageConfig, String> {
+    field(parser.begin_object(), path)?;
+
+    let mut kind = None;
+    let (mut frequency_hz, mut q, mut gain_db) = (None, None, None);
+    let mut coefficients = [None; 5];
+
+    while let Some(key) = field(parser.next_key(), path)? {
+        let field_path = format!("{}.{}", path, key);
+
+        match key {
+            "type" => {
+                let name = field(parser.string(), &field_path)?;
+
+                kind = match (name, StageKind::from_name(name)) {
+                    (COEFFICIENTS_TYPE, _) => Some(None),
+                    (_, Some(kind)) => Some(Some(kind)),
+                    (_, None) => return Err(format!("{}: unknown stage type {}", field_path, name)),
+                };
+            }
+            "frequency_hz" => frequency_hz = Some(number(parser, &field_path)?),
+            "q" => q = Some(number(parser, &field_path)?),
+            "gain_db" => gain_db = Some(number(parser, &field_path)?),
+            "b0" => coefficients[0] = Some(number(parser, &field_path)?),
+            "b1" => coefficients[1] = Some(number(parser, &field_path)?),
+            "b2" => coefficients[2] = Some(number(parser, &field_path)?),
+            "a1" => coefficients[3] = Some(number(parser, &field_path)?),
+            "a2" => coefficients[4] = Some(number(parser, &field_path)?),
+            _ => return Err(format!("{}: unknown field", field_path)),
+        }
+    }
+
+    match (kind, frequency_hz, q, coefficients) {
+        (Some(Some(kind)), Some(frequency_hz), Some(q), _) => Ok(StageConfig::Parametric {
+            kind,
+            frequency_hz,
+            q,
+            gain_db: gain_db.unwrap_or(0.0),
+        }),
+        (Some(Some(_)), _, _, _) => Err(format!("{}: requires frequency_hz and q", path)),
+        (Some(None), _, _, [Some(b0), Some(b1), Some(b2), Some(a1), Some(a2)]) => {
+            Ok(StageConfig::Coefficients { a1, a2, b0, b1, b2 })
+        }
+        (Some(None), _, _, _) => Err(format!("{}: requires b0, b1, b2, a1, and a2", path)),
+        (None, _, _, _) => Err(format!("{}: requires a type", path)),
+    }
+}
+
+/// Read a configuration document, and check it for the sample rate of the device.
+pub fn read(path: &Path) -> Result<Config, String> {
+    let text = fs::read_to_string(path).map_err(|error| format!("Cannot read {}: {}", path.display(), error))?;
+    let mut config = Config::new();
+
+    document(&mut Parser::new(&text), &mut config)
+        .map_err(|error| format!("Invalid configuration {}: {}", path.display(), error))?;
+
+    for (channel, filter) in config.channels.iter().enumerate() {
+        let message = match filter.validate(SAMPLE_RATE_HZ) {
+            Ok(()) => continue,
+            Err(ConfigError::InvalidFrequency) => "invalid frequency",
+            Err(ConfigError::InvalidQ) => "invalid quality factor",
+            Err(ConfigError::InvalidGain) => "invalid gain",
+            Err(ConfigError::UnstableCoefficients) => "unstable coefficients",
+            Err(ConfigError::InvalidDelay) => "exceeds the maximum delay",
+            Err(ConfigError::TooManyStages) => "too many stages",
+            Err(ConfigError::InvalidLimiter) => "invalid limiter",
+            Err(ConfigError::InvalidTap) => "invalid tap",
+        };
+
+        return Err(format!(
+            "Invalid configuration {}: channels[{}]: {}",
+            path.display(),
+            channel,
+            message
+        ));
+    }
+
+    Ok(config)
+}
+
+/// Read FIR taps from a text file, with one tap per line.
+pub fn read_fir_taps(path: &Path) -> Result<Vec<f32>, String> {
+    let text = fs::read_to_string(path).map_err(|error| format!("Cannot read {}: {}", path.display(), error))?;
+
+    text.lines()
+        .map(str::trim)
+        .enumerate()
+        .filter(|(_, line)| !line.is_empty())
+        .map(|(index, line)| {
+            line.parse().map_err(|_| {
+                format!(
+                    "Invalid FIR taps {}: line {}: {} is not a number",
+                    path.display(),
+                    index + 1,
+                    line
+                )
+            })
+        })
+        .collect()
+}
@@ -0,0 +1,197 @@
+//! Simulation of the signal processing of the Blus Mini Mk2 on a host, over WAV files.
+//!
+//! Feeds a WAV file through the same chain as the device: the routing of the inputs to the output channels, the biquad
+//! bank (with the crossovers), gain, inversion, and delay, the FIR, the limiters, and the master volume, in blocks of
+//! 1 ms. Samples beyond full-scale are clipped by the conversion, like on the device, and reported. The output file has
+//! one channel per output channel (32 bit integers), and holds exactly the samples that the device sends to its
+//! amplifiers. Trims, thermal throttling, and the soft mute ramp are not simulated.
+//!
+//! ```text
+//! cargo run --features std --bin dsp-sim -- [--config FILE] [--fir CHANNEL=FILE]... INPUT OUTPUT
+//! ```
+//!
+//! - The configuration is a JSON document, as exported by the device (shell `config export`, see [`config`]). Without
+//!   it, samples pass unchanged.
+//! - FIR taps are text files with one tap per line, for an output channel (0 to 3).
+//! - The input must have one or two channels at 48 kHz. A single channel feeds both inputs.
+mod config;
+mod wav;
+
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use audio::chain::Chain;
+use audio::db_to_linear;
+use audio::fir::MAX_FIR_LENGTH;
+use audio_pipeline::pipeline::Pipeline;
+
+use crate::wav::Wav;
+
+/// The sample rate of the device.
+pub const SAMPLE_RATE_HZ: u32 = 48_000;
+
+/// The number of input channels (left and right).
+pub const INPUT_CHANNEL_COUNT: usize = 2;
+
+/// The number of output channels (amplifier channels).
+pub const OUTPUT_CHANNEL_COUNT: usize = 4;
+
+/// The input channel of every output channel, as on the device (see `blus-mini-mk2/src/audio_routing.rs`).
+const ROUTING: [usize; OUTPUT_CHANNEL_COUNT] = [0, 0, 1, 1];
+
+/// The number of frames per block (1 ms).
+const FRAME_COUNT: usize = 48;
+
+const USAGE: &str = "Usage: dsp-sim [--config FILE] [--fir CHANNEL=FILE]... INPUT OUTPUT";
+
+/// The command line arguments.
+struct Arguments {
+    config: Option<PathBuf>,
+    /// FIR taps by output channel.
+    firs: Vec<(usize, PathBuf)>,
+    input: PathBuf,
+    output: PathBuf,
+}
+
+fn parse_arguments() -> Result<Arguments, String> {
+    let mut args = std::env::args().skip(1);
+    let mut config = None;
+    let mut firs = Vec::new();
+    let mut paths = Vec::new();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--config" => config = Some(PathBuf::from(args.next().ok_or("--config requires a file")?)),
+            "--fir" => {
+                let value = args.next().ok_or("--fir requires CHANNEL=FILE")?;
+                let (channel, path) = value.split_once('=').ok_or("--fir requires CHANNEL=FILE")?;
+                let channel = channel
+                    .parse()
+                    .ok()
+                    .filter(|channel| *channel < OUTPUT_CHANNEL_COUNT)
+                    .ok_or_else(|| format!("--fir: invalid channel {}", channel))?;
+
+                firs.push((channel, PathBuf::from(path)));
+            }
+            _ if arg.starts_with('-') => return Err(USAGE.into()),
+            _ => paths.push(PathBuf::from(arg)),
+        }
+    }
+
+    let [input, output] = <[PathBuf; 2]>::try_from(paths).map_err(|_| USAGE.to_string())?;
+
+    Ok(Arguments {
+        config,
+        firs,
+        input,
+        output,
+    })
+}
+
+fn run() -> Result<(), String> {
+    let arguments = parse_arguments()?;
+
+    let config = match arguments.config {
+        Some(path) => config::read(&path)?,
+        None => config::Config::new(),
+    };
+
+    let input = wav::read(&arguments.input)?;
+    if input.sample_rate_hz != SAMPLE_RATE_HZ {
+        return Err(format!(
+            "{}: sample rate of {} Hz (the device processes {} Hz)",
+            arguments.input.display(),
+            input.sample_rate_hz,
+            SAMPLE_RATE_HZ
+        ));
+    }
+
+    let samples: Vec<u32> = match input.channel_count {
+        1 => input
+            .samples
+            .iter()
+            .flat_map(|sample| [*sample; INPUT_CHANNEL_COUNT])
+            .collect(),
+        INPUT_CHANNEL_COUNT => input.samples,
+        count => {
+            return Err(format!(
+                "{}: {} channels instead of 1 or 2",
+                arguments.input.display(),
+                count
+            ))
+        }
+    };
+
+    let mut chain: Box<Chain<OUTPUT_CHANNEL_COUNT>> = Box::default();
+
+    chain
+        .configure_graph(&config.channels, SAMPLE_RATE_HZ)
+        .map_err(|(channel, error)| format!("Channel {}: {:?}", channel, error))?;
+
+    for (channel, path) in arguments.firs {
+        let taps = config::read_fir_taps(&path)?;
+        if taps.len() > MAX_FIR_LENGTH {
+            return Err(format!("{}: more than {} taps", path.display(), MAX_FIR_LENGTH));
+        }
+
+        chain.set_fir_taps(channel, &taps);
+    }
+
+    let gain = db_to_linear(config.volume_db);
+    let mut pipeline: Pipeline<INPUT_CHANNEL_COUNT, OUTPUT_CHANNEL_COUNT> = Pipeline::new(ROUTING, 1);
+    pipeline.reset_master_gain(gain);
+
+    let frame_count = samples.len() / INPUT_CHANNEL_COUNT;
+    let mut output = vec![0u32; frame_count * OUTPUT_CHANNEL_COUNT];
+    let mut peak_levels = [0.0f32; OUTPUT_CHANNEL_COUNT];
+    let (mut block_count, mut clipped_block_count) = (0, 0);
+
+    for (block, output_block) in samples
+        .chunks(FRAME_COUNT * INPUT_CHANNEL_COUNT)
+        .zip(output.chunks_mut(FRAME_COUNT * OUTPUT_CHANNEL_COUNT))
+    {
+        let levels = pipeline.process_frames(
+            block,
+            output_block,
+            [1.0; INPUT_CHANNEL_COUNT],
+            [1.0; OUTPUT_CHANNEL_COUNT],
+            gain,
+            |frame| chain.run(frame),
+        );
+
+        for (peak_level, level) in peak_levels.iter_mut().zip(levels.peak_levels) {
+            *peak_level = peak_level.max(level);
+        }
+
+        block_count += 1;
+        if levels.clipped() {
+            clipped_block_count += 1;
+        }
+    }
+
+    wav::write(
+        &arguments.output,
+        &Wav {
+            channel_count: OUTPUT_CHANNEL_COUNT,
+            sample_rate_hz: SAMPLE_RATE_HZ,
+            samples: output,
+        },
+    )?;
+
+    for (channel, peak_level) in peak_levels.iter().enumerate() {
+        println!("Channel {}: peak {:.1} dBFS", channel, 20.0 * peak_level.log10());
+    }
+    println!("Clipped blocks: {} of {}", clipped_block_count, block_count);
+
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("{}", error);
+            ExitCode::FAILURE
+        }
+    }
+}
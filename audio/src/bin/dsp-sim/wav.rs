@@ -0,0 +1,145 @@
+//! Reading and writing of WAV files with integer or float samples.
+//!
+//! Samples are held in the 1q31 format of the signal processing (see [`audio::audio_filter::sample_to_f32`]),
+//! interleaved by channel. Files are read as 16, 24, or 32 bit integers, or 32 bit floats, and written as 32 bit
+//! integers.
+use std::fs;
+use std::path::Path;
+
+use audio::audio_filter::sample_to_u32;
+
+/// The format tag of integer samples.
+const FORMAT_PCM: u16 = 1;
+
+/// The format tag of float samples.
+const FORMAT_FLOAT: u16 = 3;
+
+/// The format tag of files that give their format in an extension, in the first two bytes of the subformat.
+const FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+
+/// The contents of a WAV file.
+pub struct Wav {
+    /// The number of channels.
+    pub channel_count: usize,
+    /// The sample rate in Hz.
+    pub sample_rate_hz: u32,
+    /// The samples in 1q31 format, interleaved by channel.
+    pub samples: Vec<u32>,
+}
+
+fn u16_at(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+fn u32_at(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([data[offset], data[offset + 1], data[offset + 2], data[offset + 3]])
+}
+
+/// Convert the sample data of a file to 1q31 samples.
+fn decode(data: &[u8], format: u16, bits: u16) -> Result<Vec<u32>, String> {
+    let samples = match (format, bits) {
+        (FORMAT_PCM, 16) => data
+            .chunks_exact(2)
+            .map(|bytes| ((i16::from_le_bytes([bytes[0], bytes[1]]) as i32) << 16) as u32)
+            .collect(),
+        (FORMAT_PCM, 24) => data
+            .chunks_exact(3)
+            .map(|bytes| u32::from_le_bytes([0, bytes[0], bytes[1], bytes[2]]))
+            .collect(),
+        (FORMAT_PCM, 32) => data.chunks_exact(4).map(|bytes| u32_at(bytes, 0)).collect(),
+        (FORMAT_FLOAT, 32) => data
+            .chunks_exact(4)
+            .map(|bytes| sample_to_u32(f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])))
+            .collect(),
+        (FORMAT_FLOAT, _) => return Err(format!("{} bit float samples are not supported", bits)),
+        _ => return Err(format!("{} bit integer samples are not supported", bits)),
+    };
+
+    Ok(samples)
+}
+
+/// Read a WAV file.
+pub fn read(path: &Path) -> Result<Wav, String> {
+    let file = fs::read(path).map_err(|error| format!("Cannot read {}: {}", path.display(), error))?;
+    let invalid = |message: &str| format!("{} is not a valid WAV file: {}", path.display(), message);
+
+    if file.len() < 12 || &file[0..4] != b"RIFF" || &file[8..12] != b"WAVE" {
+        return Err(invalid("no RIFF/WAVE header"));
+    }
+
+    let mut format = None;
+    let mut offset = 12;
+
+    while offset + 8 <= file.len() {
+        let id = &file[offset..offset + 4];
+        let size = u32_at(&file, offset + 4) as usize;
+        let body = file
+            .get(offset + 8..offset + 8 + size)
+            .ok_or_else(|| invalid("truncated chunk"))?;
+
+        match id {
+            b"fmt " => {
+                if body.len() < 16 {
+                    return Err(invalid("short format chunk"));
+                }
+
+                let mut tag = u16_at(body, 0);
+                if tag == FORMAT_EXTENSIBLE && body.len() >= 26 {
+                    tag = u16_at(body, 24);
+                }
+
+                // The format tag, channel count, sample rate, and bits per sample.
+                format = Some((tag, u16_at(body, 2) as usize, u32_at(body, 4), u16_at(body, 14)));
+            }
+            b"data" => {
+                let (tag, channel_count, sample_rate_hz, bits) =
+                    format.ok_or_else(|| invalid("data before the format chunk"))?;
+
+                if channel_count == 0 {
+                    return Err(invalid("no channels"));
+                }
+
+                let samples = decode(body, tag, bits).map_err(|message| invalid(&message))?;
+                return Ok(Wav {
+                    channel_count,
+                    sample_rate_hz,
+                    samples,
+                });
+            }
+            _ => (),
+        }
+
+        // Chunks are padded to an even size.
+        offset += 8 + size + size % 2;
+    }
+
+    Err(invalid("no data chunk"))
+}
+
+/// Write a WAV file with 32 bit integer samples.
+pub fn write(path: &Path, wav: &Wav) -> Result<(), String> {
+    let data_size = (wav.samples.len() * 4) as u32;
+    let block_align = (wav.channel_count * 4) as u16;
+
+    let mut file = Vec::with_capacity(44 + data_size as usize);
+    file.extend_from_slice(b"RIFF");
+    file.extend_from_slice(&(36 + data_size).to_le_bytes());
+    file.extend_from_slice(b"WAVE");
+
+    file.extend_from_slice(b"fmt ");
+    file.extend_from_slice(&16u32.to_le_bytes());
+    file.extend_from_slice(&FORMAT_PCM.to_le_bytes());
+    file.extend_from_slice(&(wav.channel_count as u16).to_le_bytes());
+    file.extend_from_slice(&wav.sample_rate_hz.to_le_bytes());
+    file.extend_from_slice(&(wav.sample_rate_hz * block_align as u32).to_le_bytes());
+    file.extend_from_slice(&block_align.to_le_bytes());
+    file.extend_from_slice(&32u16.to_le_bytes());
+
+    file.extend_from_slice(b"data");
+    file.extend_from_slice(&data_size.to_le_bytes());
+    for sample in wav.samples.iter() {
+        file.extend_from_slice(&sample.to_le_bytes());
+    }
+
+    fs::write(path, file).map_err(|error| format!("Cannot write {}: {}", path.display(), error))
+}
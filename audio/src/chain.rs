@@ -0,0 +1,102 @@
+//! The processing of all output channels: biquads, gain, delay, a FIR, and a limiter per channel.
+//!
+//! The channel count is a parameter, such that boards with different numbers of output channels (e.g. 2 or 8)
+//! instantiate the same chain. The biquads of all channels run interleaved in a bank (see [`crate::biquad_bank`]),
+//! which holds up to [`MAX_STAGE_COUNT`] stages per channel (EQ and crossover), such that they can be reconfigured at
+//! runtime. Filters apply gain and delay after the bank, followed by the FIRs, and the limiters run last.
+use crate::biquad_bank::BiquadBank;
+use crate::filter_config::{ConfigError, FilterConfig, MAX_STAGE_COUNT};
+use crate::filter_graph::FilterGraph;
+use crate::fir::Fir;
+use crate::limiter::Limiter;
+use crate::AudioFilter;
+
+/// The processing of `CHANNELS` output channels.
+pub struct Chain<const CHANNELS: usize> {
+    bank: BiquadBank<CHANNELS, MAX_STAGE_COUNT>,
+    /// Gain and delay of every channel, without biquads of their own.
+    filters: [AudioFilter<'static>; CHANNELS],
+    firs: [Fir; CHANNELS],
+    limiters: [Limiter; CHANNELS],
+}
+
+impl<const CHANNELS: usize> Default for Chain<CHANNELS> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const CHANNELS: usize> Chain<CHANNELS> {
+    /// Create a chain that passes samples unchanged.
+    pub fn new() -> Self {
+        Chain {
+            bank: BiquadBank::new(),
+            filters: core::array::from_fn(|_| AudioFilter::new(1.0, 0, &mut [])),
+            firs: [const { Fir::new() }; CHANNELS],
+            limiters: [const { Limiter::new() }; CHANNELS],
+        }
+    }
+
+    /// Apply a filter configuration to a channel, and reset its state.
+    pub fn configure(&mut self, channel: usize, config: &FilterConfig, sample_rate_hz: u32) -> Result<(), ConfigError> {
+        config.apply_to_bank(&mut self.bank, channel, &mut self.filters[channel], sample_rate_hz)?;
+        self.limiters[channel].configure(config.limiter.as_ref(), sample_rate_hz);
+        Ok(())
+    }
+
+    /// Apply the filter configurations of all channels. Channels with invalid configurations keep theirs, and the
+    /// first error is returned with its channel.
+    pub fn configure_graph(
+        &mut self,
+        graph: &FilterGraph<CHANNELS>,
+        sample_rate_hz: u32,
+    ) -> Result<(), (usize, ConfigError)> {
+        let mut result = Ok(());
+
+        for (channel, config) in graph.iter().enumerate() {
+            let configured = self.configure(channel, config, sample_rate_hz);
+
+            if let (Err(error), Ok(())) = (configured, result) {
+                result = Err((channel, error));
+            }
+        }
+
+        result
+    }
+
+    /// Load the FIR taps of a channel. An empty slice disables its FIR.
+    ///
+    /// Panics, if there are more than [`crate::fir::MAX_FIR_LENGTH`] taps.
+    pub fn set_fir_taps(&mut self, channel: usize, taps: &[f32]) {
+        self.firs[channel].set_taps(taps);
+    }
+
+    /// Reset the state of the biquads and FIRs of all channels, e.g. for a new source.
+    pub fn reset_state(&mut self) {
+        self.bank.reset_state();
+
+        for fir in self.firs.iter_mut() {
+            fir.reset_state();
+        }
+
+        for limiter in self.limiters.iter_mut() {
+            limiter.reset_state();
+        }
+    }
+
+    /// Run the chain on a frame of samples, one per channel.
+    #[inline]
+    pub fn run(&mut self, frame: &mut [f32; CHANNELS]) {
+        self.bank.run(frame);
+
+        let stages = self
+            .filters
+            .iter_mut()
+            .zip(self.firs.iter_mut())
+            .zip(self.limiters.iter_mut());
+
+        for (sample, ((filter, fir), limiter)) in frame.iter_mut().zip(stages) {
+            *sample = limiter.run(fir.run(filter.run(*sample)));
+        }
+    }
+}
@@ -0,0 +1,380 @@
+//! Description of audio filters, which can be changed at runtime.
+//!
+//! The filter of a channel is a chain of typed nodes, which run in a fixed order: the parametric or given biquad
+//! stages (EQ), a Linkwitz-Riley crossover, gain and polarity, delay, the FIR of the channel, and a peak limiter. The
+//! stages and the crossover share the biquads of the channel, up to [`MAX_STAGE_COUNT`].
+use biquad::{Coefficients, ToHertz, Type, Q_BUTTERWORTH_F32};
+use heapless::Vec;
+
+use crate::audio_filter::MAX_DELAY_LENGTH;
+use crate::biquad_bank::BiquadBank;
+use crate::{db_to_linear, AudioFilter};
+
+/// The maximum number of biquad stages per filter.
+pub const MAX_STAGE_COUNT: usize = 12;
+
+/// The lowest gain in dB, of a channel or a stage.
+pub const MIN_GAIN_DB: f32 = -60.0;
+
+/// The highest gain in dB, of a channel or a stage.
+pub const MAX_GAIN_DB: f32 = 24.0;
+
+/// The kind of a parametric filter stage.
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum StageKind {
+    LowPass,
+    HighPass,
+    BandPass,
+    Notch,
+    AllPass,
+    PeakingEq,
+    LowShelf,
+    HighShelf,
+}
+
+impl StageKind {
+    /// All stage kinds.
+    pub const ALL: [StageKind; 8] = [
+        StageKind::LowPass,
+        StageKind::HighPass,
+        StageKind::BandPass,
+        StageKind::Notch,
+        StageKind::AllPass,
+        StageKind::PeakingEq,
+        StageKind::LowShelf,
+        StageKind::HighShelf,
+    ];
+
+    /// The short name of the stage kind.
+    pub fn name(&self) -> &'static str {
+        match self {
+            StageKind::LowPass => "lp",
+            StageKind::HighPass => "hp",
+            StageKind::BandPass => "bp",
+            StageKind::Notch => "notch",
+            StageKind::AllPass => "ap",
+            StageKind::PeakingEq => "peak",
+            StageKind::LowShelf => "ls",
+            StageKind::HighShelf => "hs",
+        }
+    }
+
+    /// Find a stage kind by its short name.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|kind| kind.name() == name)
+    }
+
+    /// Whether the stage kind uses a gain parameter.
+    pub fn has_gain(&self) -> bool {
+        matches!(self, StageKind::PeakingEq | StageKind::LowShelf | StageKind::HighShelf)
+    }
+}
+
+/// A single biquad filter stage.
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum StageConfig {
+    /// A filter that is designed from its parameters.
+    Parametric {
+        kind: StageKind,
+        frequency_hz: f32,
+        q: f32,
+        /// The gain in dB, only for peaking and shelving filters.
+        gain_db: f32,
+    },
+    /// A filter that is given by its normalized coefficients.
+    Coefficients {
+        a1: f32,
+        a2: f32,
+        b0: f32,
+        b1: f32,
+        b2: f32,
+    },
+}
+
+/// The side of a crossover.
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum CrossoverKind {
+    /// Passes frequencies below the crossover frequency, e.g. to a woofer.
+    LowPass,
+    /// Passes frequencies above the crossover frequency, e.g. to a tweeter.
+    HighPass,
+}
+
+/// The slope of a Linkwitz-Riley crossover.
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum CrossoverOrder {
+    /// 12 dB per octave, in one biquad stage. The sides are in opposite polarity at the crossover frequency, so one
+    /// of them is usually inverted.
+    Lr2,
+    /// 24 dB per octave, in two biquad stages.
+    Lr4,
+}
+
+/// A Linkwitz-Riley crossover, whose low-pass and high-pass sides sum to a flat response.
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Crossover {
+    pub kind: CrossoverKind,
+    pub order: CrossoverOrder,
+    pub frequency_hz: f32,
+}
+
+impl Crossover {
+    /// The number of biquad stages of the crossover.
+    pub fn stage_count(&self) -> usize {
+        match self.order {
+            CrossoverOrder::Lr2 => 1,
+            CrossoverOrder::Lr4 => 2,
+        }
+    }
+
+    /// The biquad stages of the crossover: a second order filter with a Q of 0.5 for LR2, and two Butterworth filters
+    /// for LR4.
+    pub fn stages(&self) -> Vec<StageConfig, 2> {
+        let kind = match self.kind {
+            CrossoverKind::LowPass => StageKind::LowPass,
+            CrossoverKind::HighPass => StageKind::HighPass,
+        };
+        let q = match self.order {
+            CrossoverOrder::Lr2 => 0.5,
+            CrossoverOrder::Lr4 => Q_BUTTERWORTH_F32,
+        };
+        let stage = StageConfig::Parametric {
+            kind,
+            frequency_hz: self.frequency_hz,
+            q,
+            gain_db: 0.0,
+        };
+
+        let mut stages = Vec::new();
+        for _ in 0..self.stage_count() {
+            // Cannot fail, there are at most two stages.
+            stages.push(stage).unwrap();
+        }
+
+        stages
+    }
+}
+
+/// A peak limiter, which keeps the output of a channel below a threshold (see [`crate::limiter`]).
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct LimiterConfig {
+    /// The highest output level in dBFS, at most 0 dB.
+    pub threshold_db: f32,
+    /// The time in ms, in which the gain recovers by about 63 % after a peak.
+    pub release_ms: f32,
+}
+
+/// Errors in filter configurations.
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ConfigError {
+    /// The frequency is not between zero and half the sample rate.
+    InvalidFrequency,
+    /// The quality factor is not positive.
+    InvalidQ,
+    /// The gain is not between [`MIN_GAIN_DB`] and [`MAX_GAIN_DB`].
+    InvalidGain,
+    /// The given coefficients are not finite, or their poles are not inside the unit circle.
+    UnstableCoefficients,
+    /// The delay exceeds the maximum delay.
+    InvalidDelay,
+    /// There are more stages than the maximum stage count.
+    TooManyStages,
+    /// The threshold of the limiter is above full-scale, or its release time is not positive.
+    InvalidLimiter,
+    /// A FIR tap is not finite.
+    InvalidTap,
+}
+
+/// Check a gain in dB. Rejects NaN.
+fn validate_gain_db(gain_db: f32) -> Result<(), ConfigError> {
+    if !(MIN_GAIN_DB..=MAX_GAIN_DB).contains(&gain_db) {
+        return Err(ConfigError::InvalidGain);
+    }
+
+    Ok(())
+}
+
+impl StageConfig {
+    /// Calculate the biquad coefficients of the stage for a given sample rate. Every value must be finite, and given
+    /// coefficients must be stable.
+    pub fn coefficients(&self, sample_rate_hz: u32) -> Result<Coefficients<f32>, ConfigError> {
+        match *self {
+            StageConfig::Parametric {
+                kind,
+                frequency_hz,
+                q,
+                gain_db,
+            } => {
+                // Written as negations, such that NaN fails.
+                if !(frequency_hz > 0.0 && frequency_hz < (sample_rate_hz as f32) / 2.0) {
+                    return Err(ConfigError::InvalidFrequency);
+                }
+
+                if !(q > 0.0 && q.is_finite()) {
+                    return Err(ConfigError::InvalidQ);
+                }
+
+                validate_gain_db(gain_db)?;
+
+                let filter_type = match kind {
+                    StageKind::LowPass => Type::LowPass,
+                    StageKind::HighPass => Type::HighPass,
+                    StageKind::BandPass => Type::BandPass,
+                    StageKind::Notch => Type::Notch,
+                    StageKind::AllPass => Type::AllPass,
+                    StageKind::PeakingEq => Type::PeakingEQ(gain_db),
+                    StageKind::LowShelf => Type::LowShelf(gain_db),
+                    StageKind::HighShelf => Type::HighShelf(gain_db),
+                };
+
+                Coefficients::<f32>::from_params(filter_type, (sample_rate_hz as f32).hz(), frequency_hz.hz(), q)
+                    .map_err(|_| ConfigError::InvalidFrequency)
+            }
+            StageConfig::Coefficients { a1, a2, b0, b1, b2 } => {
+                let finite = [a1, a2, b0, b1, b2].iter().all(|value| value.is_finite());
+
+                // The poles of `1 + a1 z^-1 + a2 z^-2` are inside the unit circle (the stability triangle).
+                if !(finite && a2 > -1.0 && a2 < 1.0 && a1 > -(1.0 + a2) && a1 < 1.0 + a2) {
+                    return Err(ConfigError::UnstableCoefficients);
+                }
+
+                Ok(Coefficients { a1, a2, b0, b1, b2 })
+            }
+        }
+    }
+}
+
+/// The chain of filter nodes of one output channel: biquad stages, crossover, gain, delay, and limiter.
+#[derive(Clone, PartialEq, Debug)]
+pub struct FilterConfig {
+    /// The gain in dB, between [`MIN_GAIN_DB`] and [`MAX_GAIN_DB`].
+    pub gain_db: f32,
+    /// Whether the output is inverted.
+    pub inverted: bool,
+    /// A delay in number of samples.
+    pub delay: usize,
+    /// The chain of biquad stages.
+    pub stages: Vec<StageConfig, MAX_STAGE_COUNT>,
+    /// A crossover after the stages, if any.
+    pub crossover: Option<Crossover>,
+    /// A limiter at the end of the chain, if any.
+    pub limiter: Option<LimiterConfig>,
+}
+
+impl Default for FilterConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FilterConfig {
+    /// A filter configuration that passes samples unchanged.
+    pub const fn new() -> Self {
+        FilterConfig {
+            gain_db: 0.0,
+            inverted: false,
+            delay: 0,
+            stages: Vec::new(),
+            crossover: None,
+            limiter: None,
+        }
+    }
+
+    /// The number of biquad stages, including those of the crossover.
+    pub fn stage_count(&self) -> usize {
+        self.stages.len() + self.crossover.map_or(0, |crossover| crossover.stage_count())
+    }
+
+    /// The linear gain of the filter. Negative for inverted filters.
+    pub fn linear_gain(&self) -> f32 {
+        let gain = db_to_linear(self.gain_db);
+
+        if self.inverted {
+            -gain
+        } else {
+            gain
+        }
+    }
+
+    /// Check the configuration for a given sample rate.
+    pub fn validate(&self, sample_rate_hz: u32) -> Result<(), ConfigError> {
+        if self.delay > MAX_DELAY_LENGTH {
+            return Err(ConfigError::InvalidDelay);
+        }
+
+        if self.stage_count() > MAX_STAGE_COUNT {
+            return Err(ConfigError::TooManyStages);
+        }
+
+        validate_gain_db(self.gain_db)?;
+
+        for stage in self.all_stages() {
+            stage.coefficients(sample_rate_hz)?;
+        }
+
+        if let Some(limiter) = self.limiter {
+            if !(limiter.threshold_db <= 0.0
+                && limiter.threshold_db.is_finite()
+                && limiter.release_ms > 0.0
+                && limiter.release_ms.is_finite())
+            {
+                return Err(ConfigError::InvalidLimiter);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The biquad stages, followed by those of the crossover.
+    fn all_stages(&self) -> impl Iterator<Item = StageConfig> + '_ {
+        let crossover_stages = self.crossover.map(|crossover| crossover.stages()).unwrap_or_default();
+        self.stages.iter().copied().chain(crossover_stages)
+    }
+
+    /// Calculate the biquad coefficients of all stages, including those of the crossover, for a given sample rate.
+    pub fn coefficients(&self, sample_rate_hz: u32) -> Result<Vec<Coefficients<f32>, MAX_STAGE_COUNT>, ConfigError> {
+        self.validate(sample_rate_hz)?;
+
+        let mut coefficients = Vec::new();
+        for stage in self.all_stages() {
+            // Cannot fail, stages were validated, and their count was checked.
+            coefficients.push(stage.coefficients(sample_rate_hz)?).unwrap();
+        }
+
+        Ok(coefficients)
+    }
+
+    /// Apply the configuration to a filter. The limiter is not part of a filter, it runs in a chain (see
+    /// [`crate::chain`]).
+    pub fn apply(&self, filter: &mut AudioFilter, sample_rate_hz: u32) -> Result<(), ConfigError> {
+        let coefficients = self.coefficients(sample_rate_hz)?;
+        filter.configure(self.linear_gain(), self.delay, &coefficients)
+    }
+
+    /// Apply the configuration to a channel of a biquad bank, which runs the stages, and to a filter, which applies
+    /// only gain and delay after the bank.
+    pub fn apply_to_bank<const CHANNELS: usize, const STAGES: usize>(
+        &self,
+        bank: &mut BiquadBank<CHANNELS, STAGES>,
+        channel: usize,
+        filter: &mut AudioFilter,
+        sample_rate_hz: u32,
+    ) -> Result<(), ConfigError> {
+        let coefficients = self.coefficients(sample_rate_hz)?;
+
+        if coefficients.len() > STAGES {
+            return Err(ConfigError::TooManyStages);
+        }
+
+        filter.configure(self.linear_gain(), self.delay, &[])?;
+        bank.configure(channel, &coefficients);
+        Ok(())
+    }
+}
@@ -0,0 +1,69 @@
+//! The filter configuration of all output channels, as one chain of filter nodes per channel.
+//!
+//! The channel count is a parameter, like for [`crate::chain::Chain`], which runs the graph. The chain of a channel is
+//! a [`FilterConfig`]; its stages and crossover are configurable in length and type, and it converts to and from the
+//! settings of a channel.
+use core::ops::{Index, IndexMut};
+
+use crate::filter_config::{ConfigError, FilterConfig};
+
+/// The filter chains of `CHANNELS` output channels.
+#[derive(Clone, PartialEq, Debug)]
+pub struct FilterGraph<const CHANNELS: usize> {
+    /// The chain of every channel.
+    pub channels: [FilterConfig; CHANNELS],
+}
+
+impl<const CHANNELS: usize> Default for FilterGraph<CHANNELS> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const CHANNELS: usize> FilterGraph<CHANNELS> {
+    /// A graph that passes the samples of all channels unchanged.
+    pub const fn new() -> Self {
+        FilterGraph {
+            channels: [const { FilterConfig::new() }; CHANNELS],
+        }
+    }
+
+    /// The chains of all channels, in order.
+    pub fn iter(&self) -> core::slice::Iter<'_, FilterConfig> {
+        self.channels.iter()
+    }
+
+    /// The chains of all channels, in order, for modification.
+    pub fn iter_mut(&mut self) -> core::slice::IterMut<'_, FilterConfig> {
+        self.channels.iter_mut()
+    }
+
+    /// Check the chains of all channels for a given sample rate. Fails with the first invalid channel.
+    pub fn validate(&self, sample_rate_hz: u32) -> Result<(), (usize, ConfigError)> {
+        for (channel, config) in self.iter().enumerate() {
+            config.validate(sample_rate_hz).map_err(|error| (channel, error))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<const CHANNELS: usize> From<[FilterConfig; CHANNELS]> for FilterGraph<CHANNELS> {
+    fn from(channels: [FilterConfig; CHANNELS]) -> Self {
+        FilterGraph { channels }
+    }
+}
+
+impl<const CHANNELS: usize> Index<usize> for FilterGraph<CHANNELS> {
+    type Output = FilterConfig;
+
+    fn index(&self, channel: usize) -> &FilterConfig {
+        &self.channels[channel]
+    }
+}
+
+impl<const CHANNELS: usize> IndexMut<usize> for FilterGraph<CHANNELS> {
+    fn index_mut(&mut self, channel: usize) -> &mut FilterConfig {
+        &mut self.channels[channel]
+    }
+}
@@ -0,0 +1,87 @@
+//! A direct-form FIR filter with runtime-loadable taps.
+
+/// The maximum number of FIR taps.
+pub const MAX_FIR_LENGTH: usize = 256;
+
+/// A FIR filter. Passes samples unchanged, while no taps are loaded.
+pub struct Fir {
+    /// The taps in reverse order, such that they line up with the history.
+    taps: [f32; MAX_FIR_LENGTH],
+    /// The number of taps in use.
+    length: usize,
+    /// Past samples. Every sample is stored twice, such that the last `length` samples
+    /// are always available as a contiguous slice.
+    history: [f32; 2 * MAX_FIR_LENGTH],
+    /// The position of the oldest sample in the history.
+    index: usize,
+}
+
+impl Default for Fir {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Fir {
+    /// Create a new filter without taps.
+    pub const fn new() -> Self {
+        Fir {
+            taps: [0.0; MAX_FIR_LENGTH],
+            length: 0,
+            history: [0.0; 2 * MAX_FIR_LENGTH],
+            index: 0,
+        }
+    }
+
+    /// Load new taps, and reset the filter state. An empty slice disables the filter.
+    ///
+    /// Panics, if there are more than [`MAX_FIR_LENGTH`] taps.
+    pub fn set_taps(&mut self, taps: &[f32]) {
+        if taps.len() > MAX_FIR_LENGTH {
+            panic!("FIR exceeds maximum number of taps.");
+        }
+
+        self.length = taps.len();
+        for (reversed, tap) in self.taps.iter_mut().zip(taps.iter().rev()) {
+            *reversed = *tap;
+        }
+
+        self.reset_state();
+    }
+
+    /// The number of taps in use.
+    pub fn length(&self) -> usize {
+        self.length
+    }
+
+    /// Clear past samples.
+    pub fn reset_state(&mut self) {
+        self.history.fill(0.0);
+        self.index = 0;
+    }
+
+    /// Run the filter on a provided sample.
+    #[inline]
+    pub fn run(&mut self, sample: f32) -> f32 {
+        if self.length == 0 {
+            return sample;
+        }
+
+        self.history[self.index] = sample;
+        self.history[self.index + self.length] = sample;
+
+        self.index += 1;
+        if self.index >= self.length {
+            self.index = 0;
+        }
+
+        // The window holds the last `length` samples, oldest first.
+        let window = &self.history[self.index..self.index + self.length];
+
+        window
+            .iter()
+            .zip(self.taps[..self.length].iter())
+            .map(|(sample, tap)| sample * tap)
+            .sum()
+    }
+}
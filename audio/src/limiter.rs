@@ -0,0 +1,74 @@
+//! A peak limiter with instant attack, which keeps the samples of a channel below a threshold.
+//!
+//! The limiter follows the envelope of the sample magnitudes: it rises with every peak immediately, and decays with
+//! the release time. Above the threshold, the gain scales the envelope down to the threshold, such that no sample
+//! exceeds it. Being instant, the attack distorts the peaks it catches; the limiter protects speakers and amplifiers
+//! from overload, and is not meant to compress.
+#[cfg(not(any(test, feature = "std")))]
+use micromath::F32Ext;
+
+use crate::db_to_linear;
+use crate::filter_config::LimiterConfig;
+
+/// A peak limiter, which passes samples unchanged until it is configured.
+pub struct Limiter {
+    /// The linear threshold, or `None` for no limiting.
+    threshold: Option<f32>,
+    /// The factor by which the envelope decays per sample.
+    release: f32,
+    envelope: f32,
+}
+
+impl Default for Limiter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Limiter {
+    /// Create a limiter that passes samples unchanged.
+    pub const fn new() -> Self {
+        Limiter {
+            threshold: None,
+            release: 0.0,
+            envelope: 0.0,
+        }
+    }
+
+    /// Apply a limiter configuration, or none for passing samples unchanged, and reset the state.
+    pub fn configure(&mut self, config: Option<&LimiterConfig>, sample_rate_hz: u32) {
+        match config {
+            Some(config) => {
+                let release_samples = config.release_ms * sample_rate_hz as f32 / 1000.0;
+
+                self.threshold = Some(db_to_linear(config.threshold_db));
+                self.release = (-1.0 / release_samples.max(1.0)).exp();
+            }
+            None => self.threshold = None,
+        }
+
+        self.reset_state();
+    }
+
+    /// Reset the envelope, e.g. for a new source.
+    pub fn reset_state(&mut self) {
+        self.envelope = 0.0;
+    }
+
+    /// Limit a sample.
+    #[inline]
+    pub fn run(&mut self, sample: f32) -> f32 {
+        let Some(threshold) = self.threshold else {
+            return sample;
+        };
+
+        let magnitude = sample.abs();
+        self.envelope = magnitude.max(self.envelope * self.release);
+
+        if self.envelope > threshold {
+            sample * threshold / self.envelope
+        } else {
+            sample
+        }
+    }
+}
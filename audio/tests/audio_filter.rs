@@ -0,0 +1,43 @@
+//! Checks that a filter rejects configurations that it cannot run, and keeps its previous configuration.
+use audio::audio_filter::MAX_DELAY_LENGTH;
+use audio::filter_config::ConfigError;
+use audio::{AudioFilter, BiquadType};
+use biquad::Coefficients;
+
+const HALF: Coefficients<f32> = Coefficients {
+    a1: 0.0,
+    a2: 0.0,
+    b0: 0.5,
+    b1: 0.0,
+    b2: 0.0,
+};
+
+#[test]
+fn rejects_too_many_stages() {
+    let mut biquads = [BiquadType::new(HALF); 2];
+    let mut filter = AudioFilter::new(1.0, 0, &mut biquads);
+
+    assert_eq!(filter.configure(1.0, 0, &[HALF; 3]), Err(ConfigError::TooManyStages));
+    assert_eq!(filter.run(1.0), 0.25);
+}
+
+#[test]
+fn rejects_too_long_delay() {
+    let mut biquads = [BiquadType::new(HALF); 1];
+    let mut filter = AudioFilter::new(1.0, 0, &mut biquads);
+
+    assert_eq!(
+        filter.configure(1.0, MAX_DELAY_LENGTH + 1, &[]),
+        Err(ConfigError::InvalidDelay)
+    );
+    assert_eq!(filter.run(1.0), 0.5);
+}
+
+#[test]
+fn accepts_maximum_configuration() {
+    let mut biquads = [BiquadType::new(HALF); 2];
+    let mut filter = AudioFilter::new(1.0, 0, &mut biquads);
+
+    assert_eq!(filter.configure(2.0, MAX_DELAY_LENGTH, &[HALF; 2]), Ok(()));
+    assert_eq!(filter.run(1.0), 0.0);
+}
@@ -0,0 +1,120 @@
+//! Checks that filter configurations with values that are not finite, or out of range, are rejected.
+use audio::filter_config::{
+    ConfigError, FilterConfig, LimiterConfig, StageConfig, StageKind, MAX_GAIN_DB, MIN_GAIN_DB,
+};
+use heapless::Vec;
+
+const SAMPLE_RATE_HZ: u32 = 48_000;
+
+fn peaking(frequency_hz: f32, q: f32, gain_db: f32) -> StageConfig {
+    StageConfig::Parametric {
+        kind: StageKind::PeakingEq,
+        frequency_hz,
+        q,
+        gain_db,
+    }
+}
+
+fn coefficients(a1: f32, a2: f32) -> StageConfig {
+    StageConfig::Coefficients {
+        a1,
+        a2,
+        b0: 1.0,
+        b1: 0.0,
+        b2: 0.0,
+    }
+}
+
+fn with_stage(stage: StageConfig) -> FilterConfig {
+    FilterConfig {
+        stages: Vec::from_slice(&[stage]).unwrap(),
+        ..FilterConfig::new()
+    }
+}
+
+#[test]
+fn rejects_stage_values_that_are_not_finite() {
+    let cases = [
+        (peaking(f32::NAN, 1.0, 0.0), ConfigError::InvalidFrequency),
+        (peaking(f32::INFINITY, 1.0, 0.0), ConfigError::InvalidFrequency),
+        (peaking(1000.0, f32::NAN, 0.0), ConfigError::InvalidQ),
+        (peaking(1000.0, f32::INFINITY, 0.0), ConfigError::InvalidQ),
+        (peaking(1000.0, 1.0, f32::NAN), ConfigError::InvalidGain),
+        (peaking(1000.0, 1.0, f32::INFINITY), ConfigError::InvalidGain),
+    ];
+
+    for (stage, error) in cases {
+        assert_eq!(stage.coefficients(SAMPLE_RATE_HZ).err(), Some(error), "{:?}", stage);
+    }
+}
+
+#[test]
+fn bounds_stage_gains() {
+    assert!(peaking(1000.0, 1.0, MAX_GAIN_DB).coefficients(SAMPLE_RATE_HZ).is_ok());
+    assert!(peaking(1000.0, 1.0, MIN_GAIN_DB).coefficients(SAMPLE_RATE_HZ).is_ok());
+    assert_eq!(
+        peaking(1000.0, 1.0, 1000.0).coefficients(SAMPLE_RATE_HZ).err(),
+        Some(ConfigError::InvalidGain)
+    );
+}
+
+#[test]
+fn rejects_unstable_coefficients() {
+    // Poles at 0.9 ± 0.3i, inside the unit circle.
+    assert!(coefficients(-1.8, 0.9).coefficients(SAMPLE_RATE_HZ).is_ok());
+
+    let cases = [
+        // A pole at 1.
+        coefficients(-2.0, 1.0),
+        // Poles on a circle of radius 1.05.
+        coefficients(0.0, 1.1025),
+        // A real pole at 1.1.
+        coefficients(-1.1, 0.0),
+        coefficients(f32::NAN, 0.0),
+        StageConfig::Coefficients {
+            a1: 0.0,
+            a2: 0.0,
+            b0: f32::INFINITY,
+            b1: 0.0,
+            b2: 0.0,
+        },
+    ];
+
+    for stage in cases {
+        assert_eq!(
+            stage.coefficients(SAMPLE_RATE_HZ).err(),
+            Some(ConfigError::UnstableCoefficients),
+            "{:?}",
+            stage
+        );
+    }
+}
+
+#[test]
+fn rejects_invalid_channel_values() {
+    for gain_db in [f32::NAN, f32::INFINITY, MAX_GAIN_DB + 1.0, MIN_GAIN_DB - 1.0] {
+        let config = FilterConfig {
+            gain_db,
+            ..FilterConfig::new()
+        };
+        assert_eq!(
+            config.validate(SAMPLE_RATE_HZ),
+            Err(ConfigError::InvalidGain),
+            "{}",
+            gain_db
+        );
+    }
+
+    for (threshold_db, release_ms) in [(f32::NEG_INFINITY, 50.0), (-1.0, f32::INFINITY), (f32::NAN, 50.0)] {
+        let config = FilterConfig {
+            limiter: Some(LimiterConfig {
+                threshold_db,
+                release_ms,
+            }),
+            ..FilterConfig::new()
+        };
+        assert_eq!(config.validate(SAMPLE_RATE_HZ), Err(ConfigError::InvalidLimiter));
+    }
+
+    assert_eq!(with_stage(peaking(1000.0, 1.0, 3.0)).validate(SAMPLE_RATE_HZ), Ok(()));
+}
@@ -0,0 +1,218 @@
+//! Compares the sample conversion, the filters, and the audio pipeline with golden vectors from reference models.
+//!
+//! The vectors are generated by `golden/generate.py`, and must be regenerated, whenever the reference changes.
+use audio::audio_filter::{sample_to_f32, sample_to_u32};
+use audio::biquad_bank::BiquadBank;
+use audio::chain::Chain;
+use audio::filter_config::{FilterConfig, StageConfig, StageKind};
+use audio::fir::Fir;
+use audio::{AudioFilter, BiquadType};
+use audio_pipeline::pipeline::Pipeline;
+use biquad::{Biquad, Coefficients};
+
+#[path = "golden/vectors.rs"]
+mod vectors;
+
+use vectors::*;
+
+const SAMPLE_RATE_HZ: u32 = 48_000;
+
+/// The tolerance between the single precision implementation and the double precision reference.
+const TOLERANCE: f32 = 1e-5;
+
+const IDENTITY: Coefficients<f32> = Coefficients {
+    a1: 0.0,
+    a2: 0.0,
+    b0: 1.0,
+    b1: 0.0,
+    b2: 0.0,
+};
+
+fn assert_close(actual: &[f32], expected: &[f32]) {
+    assert_eq!(actual.len(), expected.len());
+
+    for (index, (actual, expected)) in actual.iter().zip(expected).enumerate() {
+        assert!(
+            (actual - expected).abs() <= TOLERANCE,
+            "at {index}: {actual} instead of {expected}"
+        );
+    }
+}
+
+fn stage((kind, frequency_hz, q, gain_db): (StageKind, f32, f32, f32)) -> StageConfig {
+    StageConfig::Parametric {
+        kind,
+        frequency_hz,
+        q,
+        gain_db,
+    }
+}
+
+#[test]
+fn converts_samples() {
+    for (sample, expected) in CONVERSIONS {
+        assert_eq!(sample_to_u32(sample), expected, "{sample}");
+
+        if (-1.0..1.0).contains(&sample) {
+            assert_eq!(sample_to_f32(expected), sample);
+        }
+    }
+}
+
+#[test]
+fn designs_coefficients() {
+    for (parameters, expected) in STAGES.into_iter().zip(COEFFICIENTS) {
+        let coefficients = stage(parameters).coefficients(SAMPLE_RATE_HZ).unwrap();
+        let Coefficients { a1, a2, b0, b1, b2 } = coefficients;
+
+        assert_close(&[b0, b1, b2, a1, a2], &expected);
+    }
+}
+
+#[test]
+fn runs_filter() {
+    let mut biquads = [BiquadType::new(IDENTITY); 2];
+    let mut filter = AudioFilter::new(1.0, 0, &mut biquads);
+
+    let mut config = FilterConfig {
+        gain_db: -3.0,
+        inverted: true,
+        delay: 5,
+        ..FilterConfig::new()
+    };
+    config.stages.push(stage(STAGES[3])).unwrap();
+    config.stages.push(stage(STAGES[0])).unwrap();
+    config.apply(&mut filter, SAMPLE_RATE_HZ).unwrap();
+
+    let output: Vec<f32> = INPUT.iter().map(|sample| filter.run(*sample)).collect();
+    assert_close(&output, &FILTER_OUTPUT);
+}
+
+#[test]
+fn runs_biquad_bank() {
+    let mut biquads = [BiquadType::new(IDENTITY); 2];
+    let mut filter = AudioFilter::new(1.0, 0, &mut biquads);
+
+    let mut config = FilterConfig {
+        gain_db: -3.0,
+        inverted: true,
+        delay: 5,
+        ..FilterConfig::new()
+    };
+    config.stages.push(stage(STAGES[3])).unwrap();
+    config.stages.push(stage(STAGES[0])).unwrap();
+    config.apply(&mut filter, SAMPLE_RATE_HZ).unwrap();
+
+    // The second channel runs fewer stages, and passes the remaining stage of the bank.
+    let mut other_config = FilterConfig::new();
+    other_config.stages.push(stage(STAGES[1])).unwrap();
+
+    let mut bank: BiquadBank<2, 4> = BiquadBank::new();
+    let mut post_filters = [AudioFilter::new(1.0, 0, &mut []), AudioFilter::new(1.0, 0, &mut [])];
+    config
+        .apply_to_bank(&mut bank, 0, &mut post_filters[0], SAMPLE_RATE_HZ)
+        .unwrap();
+    other_config
+        .apply_to_bank(&mut bank, 1, &mut post_filters[1], SAMPLE_RATE_HZ)
+        .unwrap();
+    assert_eq!(bank.stage_count(0), 2);
+    assert_eq!(bank.stage_count(1), 1);
+
+    let mut other_biquad = BiquadType::new(stage(STAGES[1]).coefficients(SAMPLE_RATE_HZ).unwrap());
+    let mut output = Vec::new();
+
+    for input in INPUT {
+        let mut frame = [input, -input];
+        bank.run(&mut frame);
+
+        // The bank computes exactly like the biquads of a filter.
+        let sample = post_filters[0].run(frame[0]);
+        assert_eq!(sample, filter.run(input));
+        assert_eq!(post_filters[1].run(frame[1]), other_biquad.run(-input));
+        output.push(sample);
+    }
+
+    assert_close(&output, &FILTER_OUTPUT);
+}
+
+#[test]
+fn runs_fir() {
+    let mut fir = Fir::new();
+    fir.set_taps(&FIR_TAPS);
+
+    let output: Vec<f32> = INPUT.iter().map(|sample| fir.run(*sample)).collect();
+    assert_close(&output, &FIR_OUTPUT);
+}
+
+/// Run the filter of the golden vectors on every channel of a chain, and the FIR on its last channel.
+fn run_chain<const CHANNELS: usize>() {
+    let mut config = FilterConfig {
+        gain_db: -3.0,
+        inverted: true,
+        delay: 5,
+        ..FilterConfig::new()
+    };
+    config.stages.push(stage(STAGES[3])).unwrap();
+    config.stages.push(stage(STAGES[0])).unwrap();
+
+    let mut chain: Box<Chain<CHANNELS>> = Box::default();
+    for channel in 0..CHANNELS {
+        chain.configure(channel, &config, SAMPLE_RATE_HZ).unwrap();
+    }
+    chain.set_fir_taps(CHANNELS - 1, &FIR_TAPS);
+
+    let mut fir = Fir::new();
+    fir.set_taps(&FIR_TAPS);
+    let mut output = Vec::new();
+
+    for input in INPUT {
+        let mut frame = [input; CHANNELS];
+        chain.run(&mut frame);
+
+        assert!(frame[..CHANNELS - 1].iter().all(|sample| *sample == frame[0]));
+        assert_eq!(frame[CHANNELS - 1], fir.run(frame[0]));
+        output.push(frame[0]);
+    }
+
+    assert_close(&output, &FILTER_OUTPUT);
+}
+
+#[test]
+fn runs_chain() {
+    run_chain::<2>();
+    run_chain::<8>();
+}
+
+#[test]
+fn processes_pipeline() {
+    let low_pass = stage(STAGES[0]).coefficients(SAMPLE_RATE_HZ).unwrap();
+    let high_pass = stage((StageKind::HighPass, 2000.0, 0.707, 0.0))
+        .coefficients(SAMPLE_RATE_HZ)
+        .unwrap();
+
+    let mut biquads = [[BiquadType::new(IDENTITY); 1]; 4];
+    let mut filters = biquads.each_mut().map(|biquads| AudioFilter::new(1.0, 0, biquads));
+    for (channel, filter) in filters.iter_mut().enumerate() {
+        filter
+            .configure(1.0, 0, &[if channel % 2 == 0 { low_pass } else { high_pass }])
+            .unwrap();
+    }
+
+    let input = PIPELINE_INPUT.map(sample_to_u32);
+    let mut output = [0u32; PIPELINE_OUTPUT.len()];
+
+    let mut pipeline: Pipeline<2, 4> = Pipeline::new(PIPELINE_ROUTING, PIPELINE_RAMP_FRAME_COUNT);
+    let levels = pipeline.process(
+        &input,
+        &mut output,
+        PIPELINE_INPUT_GAINS,
+        PIPELINE_OUTPUT_GAINS,
+        1.0,
+        |channel, sample| filters[channel].run(sample),
+    );
+
+    let output: Vec<f32> = output.iter().map(|sample| sample_to_f32(*sample)).collect();
+    assert_close(&output, &PIPELINE_OUTPUT);
+    assert_eq!(levels.sample_count(), PIPELINE_OUTPUT.len());
+    assert_eq!(pipeline.master_gain(), 1.0);
+}
@@ -0,0 +1,183 @@
+#!/usr/bin/env python3
+"""Generates the golden vectors for the host tests of the audio crates (`vectors.rs`).
+
+The reference models run in double precision, independently of the firmware implementation:
+- sample conversion to and from 1q31, with clipping
+- biquad coefficients after the Audio EQ Cookbook (R. Bristow-Johnson)
+- biquads in transposed direct form II, followed by gain and delay
+- FIR filters in direct form
+- the interleaving of the audio pipeline, with a ramped master gain
+
+Usage: `python3 generate.py > vectors.rs`
+"""
+
+import math
+import struct
+
+SAMPLE_RATE_HZ = 48000
+
+
+def f32(value):
+    """Round to single precision, as the firmware stores values."""
+    return struct.unpack("<f", struct.pack("<f", value))[0]
+
+
+def sample_to_u32(sample):
+    scaled = int(f32(sample) * 2.0**31)
+    return min(max(scaled, -(2**31)), 2**31 - 1) & 0xFFFFFFFF
+
+
+def coefficients(kind, frequency_hz, q, gain_db=0.0):
+    """Normalized coefficients (b0, b1, b2, a1, a2) of a parametric stage."""
+    omega = 2.0 * math.pi * frequency_hz / SAMPLE_RATE_HZ
+    cos, alpha = math.cos(omega), math.sin(omega) / (2.0 * q)
+    amplitude = 10.0 ** (gain_db / 40.0)
+
+    b, a = {
+        "LowPass": ([(1 - cos) / 2, 1 - cos, (1 - cos) / 2], [1 + alpha, -2 * cos, 1 - alpha]),
+        "HighPass": ([(1 + cos) / 2, -(1 + cos), (1 + cos) / 2], [1 + alpha, -2 * cos, 1 - alpha]),
+        "Notch": ([1, -2 * cos, 1], [1 + alpha, -2 * cos, 1 - alpha]),
+        "PeakingEq": (
+            [1 + alpha * amplitude, -2 * cos, 1 - alpha * amplitude],
+            [1 + alpha / amplitude, -2 * cos, 1 - alpha / amplitude],
+        ),
+    }[kind]
+
+    return [f32(value / a[0]) for value in (b[0], b[1], b[2], a[1], a[2])]
+
+
+def biquad(stage, samples):
+    b0, b1, b2, a1, a2 = stage
+    s1 = s2 = 0.0
+    output = []
+
+    for x in samples:
+        y = b0 * x + s1
+        s1 = b1 * x - a1 * y + s2
+        s2 = b2 * x - a2 * y
+        output.append(y)
+
+    return output
+
+
+def filter_chain(stages, gain, delay, samples):
+    for stage in stages:
+        samples = biquad(stage, samples)
+
+    return ([0.0] * delay + [sample * gain for sample in samples])[: len(samples)]
+
+
+def fir(taps, samples):
+    return [sum(tap * samples[n - k] for k, tap in enumerate(taps) if n >= k) for n in range(len(samples))]
+
+
+def pipeline(frames, routing, stages, input_gains, output_gains, ramp_frame_count):
+    """Interleaved output of the pipeline, starting silent, with a target master gain of 1.0."""
+    channels = [
+        filter_chain(chain, 1.0, 0, [frame[input] for frame in frames]) for chain, input in zip(stages, routing)
+    ]
+    output = []
+
+    for index in range(len(frames)):
+        master_gain = min(1.0, (index + 1) / ramp_frame_count)
+
+        for channel, input in enumerate(routing):
+            output.append(channels[channel][index] * input_gains[input] * master_gain * output_gains[channel])
+
+    return output
+
+
+def signal(length, seed):
+    """A deterministic test signal: an impulse, followed by pseudo-random samples within ±0.5."""
+    state = seed
+    samples = [0.5]
+
+    while len(samples) < length:
+        state = (1103515245 * state + 12345) % 2**31
+        samples.append(f32(state / 2**31 - 0.5))
+
+    return samples
+
+
+def array(name, kind, items):
+    lines = [f"pub const {name}: [{kind}; {len(items)}] = ["]
+    lines += [f"    {item}," for item in items]
+    return "\n".join(lines + ["];"])
+
+
+def rust_float(value):
+    """The shortest literal that parses to the same single precision value."""
+    value = f32(value)
+    texts = (repr(float(f"{value:.{digits}g}")) for digits in range(1, 10))
+    return next(text for text in texts if f32(float(text)) == value)
+
+
+def main():
+    lines = ["// Generated by `generate.py`, do not edit.", "use audio::filter_config::StageKind;", ""]
+
+    conversions = [0.0, 0.5, -0.5, 0.1, -0.75, 0.999, 1.0, -1.0, 1.5, -2.0]
+    conversions = [f"({rust_float(v)}, 0x{sample_to_u32(v):08X})" for v in conversions]
+    lines.append(array("CONVERSIONS", "(f32, u32)", conversions))
+    lines.append("")
+
+    stages = [
+        ("LowPass", 2000.0, 0.707, 0.0),
+        ("HighPass", 120.0, 0.5, 0.0),
+        ("Notch", 50.0, 4.0, 0.0),
+        ("PeakingEq", 1000.0, 1.4, 6.0),
+        ("PeakingEq", 8000.0, 2.0, -9.5),
+    ]
+    lines.append("/// Parametric stages: kind, frequency, quality factor, and gain.")
+    lines.append(
+        array(
+            "STAGES",
+            "(StageKind, f32, f32, f32)",
+            [f"(StageKind::{kind}, {', '.join(rust_float(v) for v in (f, q, g))})" for kind, f, q, g in stages],
+        )
+    )
+    lines.append("/// The normalized coefficients of [`STAGES`]: b0, b1, b2, a1, and a2.")
+    lines.append(
+        array(
+            "COEFFICIENTS",
+            "[f32; 5]",
+            [f"[{', '.join(rust_float(v) for v in coefficients(*stage))}]" for stage in stages],
+        )
+    )
+    lines.append("")
+
+    input = signal(48, 1)
+    lines.append(array("INPUT", "f32", [rust_float(v) for v in input]))
+    lines.append("")
+
+    # A peaking filter and a low-pass, at -3 dB, inverted, and delayed.
+    chain = [coefficients(*stages[3]), coefficients(*stages[0])]
+    gain = -f32(10.0 ** (-3.0 / 20.0))
+    lines.append("/// The input through [`STAGES`] 3 and 0, at -3 dB, inverted, and delayed by 5 samples.")
+    lines.append(array("FILTER_OUTPUT", "f32", [rust_float(v) for v in filter_chain(chain, gain, 5, input)]))
+    lines.append("")
+
+    taps = [f32(0.5), f32(-0.25), f32(0.125), f32(0.0625), f32(-0.03125)]
+    lines.append(array("FIR_TAPS", "f32", [rust_float(v) for v in taps]))
+    lines.append(array("FIR_OUTPUT", "f32", [rust_float(v) for v in fir(taps, input)]))
+    lines.append("")
+
+    # A two-way crossover per input channel, with a master gain that ramps within 8 frames.
+    left, right = signal(16, 2), signal(16, 3)
+    routing = [0, 0, 1, 1]
+    crossover = [[coefficients(*stages[0])], [coefficients("HighPass", 2000.0, 0.707)]] * 2
+    input_gains, output_gains = [f32(0.8), f32(0.6)], [f32(1.0), f32(0.5), f32(0.9), f32(0.7)]
+    lines.append("/// Interleaved stereo frames.")
+    lines.append(array("PIPELINE_INPUT", "f32", [rust_float(v) for frame in zip(left, right) for v in frame]))
+    lines.append(array("PIPELINE_INPUT_GAINS", "f32", [rust_float(v) for v in input_gains]))
+    lines.append(array("PIPELINE_OUTPUT_GAINS", "f32", [rust_float(v) for v in output_gains]))
+    lines.append(array("PIPELINE_ROUTING", "usize", [str(input) for input in routing]))
+    lines.append("pub const PIPELINE_RAMP_FRAME_COUNT: u32 = 8;")
+    lines.append("/// Interleaved output frames, with a low-pass and a high-pass output per input.")
+    output = pipeline(list(zip(left, right)), routing, crossover, input_gains, output_gains, 8)
+    lines.append(array("PIPELINE_OUTPUT", "f32", [rust_float(v) for v in output]))
+
+    print("\n".join(lines))
+
+
+if __name__ == "__main__":
+    main()
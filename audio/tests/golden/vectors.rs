@@ -0,0 +1,294 @@
+// Generated by `generate.py`, do not edit.
+use audio::filter_config::StageKind;
+
+pub const CONVERSIONS: [(f32, u32); 10] = [
+    (0.0, 0x00000000),
+    (0.5, 0x40000000),
+    (-0.5, 0xC0000000),
+    (0.1, 0x0CCCCCD0),
+    (-0.75, 0xA0000000),
+    (0.999, 0x7FDF3B80),
+    (1.0, 0x7FFFFFFF),
+    (-1.0, 0x80000000),
+    (1.5, 0x7FFFFFFF),
+    (-2.0, 0x80000000),
+];
+
+/// Parametric stages: kind, frequency, quality factor, and gain.
+pub const STAGES: [(StageKind, f32, f32, f32); 5] = [
+    (StageKind::LowPass, 2000.0, 0.707, 0.0),
+    (StageKind::HighPass, 120.0, 0.5, 0.0),
+    (StageKind::Notch, 50.0, 4.0, 0.0),
+    (StageKind::PeakingEq, 1000.0, 1.4, 6.0),
+    (StageKind::PeakingEq, 8000.0, 2.0, -9.5),
+];
+/// The normalized coefficients of [`STAGES`]: b0, b1, b2, a1, and a2.
+pub const COEFFICIENTS: [[f32; 5]; 5] = [
+    [0.014401104, 0.028802209, 0.014401104, -1.632955, 0.69055945],
+    [0.98447484, -1.9689497, 0.98447484, -1.9688282, 0.96907115],
+    [0.9991825, -1.9983222, 0.9991825, -1.9983222, 0.9983651],
+    [1.0317962, -1.9195411, 0.9043085, -1.9195411, 0.9361048],
+    [0.8189489, -0.72775686, 0.6365648, -0.72775686, 0.45551372],
+];
+
+pub const INPUT: [f32; 48] = [
+    0.5,
+    0.013870078,
+    -0.3242587,
+    -0.19134848,
+    0.034533888,
+    0.44762793,
+    -0.3282637,
+    0.20223117,
+    -0.27356932,
+    -0.0052265534,
+    -0.3752797,
+    -0.4161012,
+    -0.11035288,
+    -0.22277421,
+    -0.13192928,
+    0.48343718,
+    0.03539794,
+    0.2656819,
+    0.14647315,
+    0.2671388,
+    0.28023693,
+    0.32295144,
+    -0.3480677,
+    0.12547675,
+    -0.18531518,
+    -0.15309893,
+    0.41720447,
+    0.019759936,
+    -0.098845795,
+    0.106758386,
+    0.28540218,
+    0.43152288,
+    0.3699211,
+    0.3665247,
+    0.17452034,
+    0.2583996,
+    0.08189346,
+    -0.11075228,
+    -0.14436527,
+    -0.2997679,
+    0.32692683,
+    -0.084096685,
+    -0.036478072,
+    0.479163,
+    -0.37356356,
+    -0.2873633,
+    0.45845136,
+    0.23746294,
+];
+
+/// The input through [`STAGES`] 3 and 0, at -3 dB, inverted, and delayed by 5 samples.
+pub const FILTER_OUTPUT: [f32; 48] = [
+    0.0,
+    0.0,
+    0.0,
+    0.0,
+    0.0,
+    -0.0052596848,
+    -0.01956523,
+    -0.031371806,
+    -0.029982906,
+    -0.020556988,
+    -0.016133744,
+    -0.018497076,
+    -0.019343682,
+    -0.017010149,
+    -0.010811914,
+    0.0012291415,
+    0.022451604,
+    0.05105129,
+    0.079017,
+    0.10349238,
+    0.11713488,
+    0.11302491,
+    0.096562356,
+    0.07284544,
+    0.043616418,
+    0.01013431,
+    -0.027081518,
+    -0.059483625,
+    -0.07833437,
+    -0.08612261,
+    -0.08430975,
+    -0.07899995,
+    -0.07956048,
+    -0.080826156,
+    -0.0780221,
+    -0.077256575,
+    -0.085418165,
+    -0.10401018,
+    -0.12955955,
+    -0.15624507,
+    -0.17927203,
+    -0.19635159,
+    -0.20331286,
+    -0.19611906,
+    -0.17430815,
+    -0.1455108,
+    -0.11989529,
+    -0.09600865,
+];
+
+pub const FIR_TAPS: [f32; 5] = [0.5, -0.25, 0.125, 0.0625, -0.03125];
+pub const FIR_OUTPUT: [f32; 48] = [
+    0.25,
+    -0.11806496,
+    -0.103096865,
+    0.018374192,
+    0.009813608,
+    0.17056233,
+    -0.2735483,
+    0.24727301,
+    -0.20147786,
+    0.056553096,
+    -0.19763169,
+    -0.13830179,
+    0.010161276,
+    -0.15910318,
+    -0.038344033,
+    0.25296023,
+    -0.13012634,
+    0.18313722,
+    0.04557846,
+    0.11726631,
+    0.10714184,
+    0.12566085,
+    -0.2076232,
+    0.19929095,
+    -0.15610817,
+    -0.04638254,
+    0.24243198,
+    -0.12906186,
+    -0.0059899064,
+    0.11142025,
+    0.09185313,
+    0.15096033,
+    0.12251643,
+    0.15922387,
+    0.059920497,
+    0.14102028,
+    0.0095096305,
+    -0.044095926,
+    -0.023561668,
+    -0.13059331,
+    0.21087855,
+    -0.16681285,
+    0.029426908,
+    0.2679896,
+    -0.32660478,
+    0.009952759,
+    0.28545868,
+    -0.070123345,
+];
+
+/// Interleaved stereo frames.
+pub const PIPELINE_INPUT: [f32; 32] = [
+    0.5,
+    0.5,
+    -0.4722656,
+    0.041598737,
+    0.19632855,
+    -0.28308418,
+    -0.1875113,
+    -0.1836741,
+    -0.10589286,
+    -0.2463196,
+    0.28848737,
+    0.1293468,
+    0.32689816,
+    -0.01793999,
+    0.414796,
+    -0.37263918,
+    0.35038915,
+    -0.02565235,
+    0.11959214,
+    0.24441083,
+    0.4927736,
+    0.36082685,
+    0.2936154,
+    0.0033319993,
+    0.45370927,
+    0.017771404,
+    -0.118268326,
+    -0.013762442,
+    -0.061670415,
+    0.008588454,
+    -0.17665628,
+    0.16325025,
+];
+pub const PIPELINE_INPUT_GAINS: [f32; 2] = [0.8, 0.6];
+pub const PIPELINE_OUTPUT_GAINS: [f32; 4] = [1.0, 0.5, 0.9, 0.7];
+pub const PIPELINE_ROUTING: [usize; 4] = [0, 0, 1, 1];
+pub const PIPELINE_RAMP_FRAME_COUNT: u32 = 8;
+/// Interleaved output frames, with a low-pass and a high-pass output per input.
+pub const PIPELINE_OUTPUT: [f32; 64] = [
+    0.00072005525,
+    0.020771965,
+    0.0004860373,
+    0.021810563,
+    0.0038716272,
+    -0.054488033,
+    0.0036123772,
+    -0.012381751,
+    0.0069192485,
+    0.028005706,
+    0.008716558,
+    -0.0580139,
+    0.00817919,
+    -0.038658455,
+    0.011235344,
+    -0.035213783,
+    0.00668254,
+    -0.014457232,
+    0.008542796,
+    -0.040453315,
+    0.0036649504,
+    0.08967446,
+    0.0019118377,
+    0.07192365,
+    0.008565837,
+    0.08129849,
+    -0.0046546366,
+    0.016449686,
+    0.028246127,
+    0.08560173,
+    -0.01261797,
+    -0.10790494,
+    0.056724984,
+    0.02500344,
+    -0.023065817,
+    0.055680506,
+    0.08735392,
+    -0.0735836,
+    -0.030348137,
+    0.13859457,
+    0.11594264,
+    0.063308045,
+    -0.027220936,
+    0.13424988,
+    0.14512092,
+    -0.036024872,
+    -0.015954766,
+    -0.04186399,
+    0.17458038,
+    0.016852982,
+    -0.0042597097,
+    -0.031276,
+    0.1973417,
+    -0.19090754,
+    0.0042570885,
+    -0.038205966,
+    0.20348348,
+    -0.11447342,
+    0.009884157,
+    -0.021986578,
+    0.1911844,
+    -0.11212304,
+    0.014496694,
+    0.036652807,
+];
@@ -5,15 +5,17 @@ use embassy_sync::blocking_mutex::raw::NoopRawMutex;
 use embassy_sync::zerocopy_channel;
 use embassy_usb::class::uac1::speaker;
 use embassy_usb::driver::EndpointError;
-use micromath::F32Ext;
+use usb_stream::{FeedbackEncoder, FeedbackFormat, StreamConfig};
 
 use crate::*;
 
-// Number of ticks of the feedback timer per audio sample period.
-const TICKS_PER_SAMPLE: f32 = (FEEDBACK_COUNTER_TICK_RATE as f32) / (SAMPLE_RATE_HZ as f32);
-
 // Feedback is provided in 10.14 format for full-speed endpoints.
-const FEEDBACK_SHIFT: usize = 14;
+const STREAM_CONFIG: StreamConfig = StreamConfig {
+    sample_rate_hz: SAMPLE_RATE_HZ,
+    feedback_counter_tick_rate_hz: FEEDBACK_COUNTER_TICK_RATE,
+    feedback_refresh_frame_count: FEEDBACK_REFRESH_PERIOD.frame_count() as u32,
+    feedback_format: FeedbackFormat::FullSpeed,
+};
 
 struct Disconnected {}
 
@@ -29,28 +31,11 @@ impl From<EndpointError> for Disconnected {
 /// Sends feedback messages to the host.
 async fn feedback_handler<'d, T: usb::Instance + 'd>(
     feedback: &mut speaker::Feedback<'d, usb::Driver<'d, T>>,
-    feedback_factor: f32,
+    encoder: &mut FeedbackEncoder,
 ) -> Result<(), Disconnected> {
-    let mut packet: Vec<u8, 4> = Vec::new();
-
-    // Collects the fractional component of the feedback value that is lost by rounding.
-    let mut rest = 0.0_f32;
-
     loop {
-        let counter = FEEDBACK_SIGNAL.wait().await;
-
-        packet.clear();
-
-        let raw_value = counter as f32 * feedback_factor + rest;
-        let value = raw_value.round();
-        rest = raw_value - value;
-
-        let value = value as u32;
-        packet.push(value as u8).unwrap();
-        packet.push((value >> 8) as u8).unwrap();
-        packet.push((value >> 16) as u8).unwrap();
-
-        feedback.write_packet(&packet).await?;
+        let packet = encoder.encode(FEEDBACK_SIGNAL.wait().await);
+        feedback.write_packet(&packet[..encoder.format().size()]).await?;
     }
 }
 
@@ -97,12 +82,11 @@ pub async fn streaming_task(
 
 #[embassy_executor::task]
 pub async fn feedback_task(mut feedback: speaker::Feedback<'static, usb::Driver<'static, peripherals::USB_OTG_FS>>) {
-    let feedback_factor =
-        ((1 << FEEDBACK_SHIFT) as f32 / TICKS_PER_SAMPLE) / FEEDBACK_REFRESH_PERIOD.frame_count() as f32;
-
     loop {
         feedback.wait_connection().await;
-        _ = feedback_handler(&mut feedback, feedback_factor).await;
+
+        let mut encoder = FeedbackEncoder::new(&STREAM_CONFIG);
+        _ = feedback_handler(&mut feedback, &mut encoder).await;
     }
 }
 
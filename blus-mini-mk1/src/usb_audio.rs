@@ -1,86 +1,46 @@
-use audio::db_to_linear;
-use defmt::{debug, panic};
+use defmt::debug;
 use embassy_stm32::{peripherals, usb};
 use embassy_sync::blocking_mutex::raw::NoopRawMutex;
 use embassy_sync::zerocopy_channel;
 use embassy_usb::class::uac1::speaker;
-use embassy_usb::driver::EndpointError;
-use micromath::F32Ext;
+use static_assertions;
+use usb_stream::handler::{self, SampleSink};
+use usb_stream::{FeedbackFormat, StreamConfig};
 
 use crate::*;
 
-// Number of ticks of the feedback timer per audio sample period.
-const TICKS_PER_SAMPLE: f32 = (FEEDBACK_COUNTER_TICK_RATE as f32) / (SAMPLE_RATE_HZ as f32);
+// The stream carries 32 bit samples.
+static_assertions::const_assert_eq!(SAMPLE_SIZE, usb_stream::SAMPLE_SIZE);
 
 // Feedback is provided in 10.14 format for full-speed endpoints.
-const FEEDBACK_SHIFT: usize = 14;
-
-struct Disconnected {}
-
-impl From<EndpointError> for Disconnected {
-    fn from(val: EndpointError) -> Self {
-        match val {
-            EndpointError::BufferOverflow => panic!("Buffer overflow"),
-            EndpointError::Disabled => Disconnected {},
-        }
-    }
+const STREAM_CONFIG: StreamConfig = StreamConfig {
+    sample_rate_hz: SAMPLE_RATE_HZ,
+    feedback_counter_tick_rate_hz: FEEDBACK_COUNTER_TICK_RATE,
+    feedback_refresh_frame_count: FEEDBACK_REFRESH_PERIOD.frame_count() as u32,
+    feedback_format: FeedbackFormat::FullSpeed,
+};
+
+/// Hands the sample blocks from the host to the audio routing.
+struct ChannelSink<'a> {
+    sender: &'a mut zerocopy_channel::Sender<'static, NoopRawMutex, UsbSampleBlock>,
 }
 
-/// Sends feedback messages to the host.
-async fn feedback_handler<'d, T: usb::Instance + 'd>(
-    feedback: &mut speaker::Feedback<'d, usb::Driver<'d, T>>,
-    feedback_factor: f32,
-) -> Result<(), Disconnected> {
-    let mut packet: Vec<u8, 4> = Vec::new();
-
-    // Collects the fractional component of the feedback value that is lost by rounding.
-    let mut rest = 0.0_f32;
-
-    loop {
-        let counter = FEEDBACK_SIGNAL.wait().await;
-
-        packet.clear();
+impl SampleSink for ChannelSink<'_> {
+    async fn send(&mut self, samples: impl ExactSizeIterator<Item = u32>) {
+        // Obtain a buffer from the channel
+        let block = self.sender.send().await;
+        block.clear();
 
-        let raw_value = counter as f32 * feedback_factor + rest;
-        let value = raw_value.round();
-        rest = raw_value - value;
-
-        let value = value as u32;
-        packet.push(value as u8).unwrap();
-        packet.push((value >> 8) as u8).unwrap();
-        packet.push((value >> 16) as u8).unwrap();
+        for sample in samples {
+            // Fill the sample buffer with data.
+            block.push(sample).unwrap();
+        }
 
-        feedback.write_packet(&packet).await?;
+        self.sender.send_done();
     }
-}
-
-async fn stream_handler<'d, T: usb::Instance + 'd>(
-    stream: &mut speaker::Stream<'d, usb::Driver<'d, T>>,
-    sender: &mut zerocopy_channel::Sender<'static, NoopRawMutex, UsbSampleBlock>,
-) -> Result<(), Disconnected> {
-    loop {
-        let mut usb_data = [0u8; USB_MAX_PACKET_SIZE];
-        let data_size = stream.read_packet(&mut usb_data).await?;
-
-        let word_count = data_size / SAMPLE_SIZE;
-
-        if word_count * SAMPLE_SIZE == data_size {
-            // Obtain a buffer from the channel
-            let samples = sender.send().await;
-            samples.clear();
 
-            for w in 0..word_count {
-                let byte_offset = w * SAMPLE_SIZE;
-                let sample = u32::from_le_bytes(usb_data[byte_offset..byte_offset + SAMPLE_SIZE].try_into().unwrap());
-
-                // Fill the sample buffer with data.
-                samples.push(sample).unwrap();
-            }
-
-            sender.send_done();
-        } else {
-            debug!("Invalid USB buffer size of {}, skipped.", data_size);
-        }
+    fn skip(&mut self, size: usize) {
+        debug!("Invalid USB buffer size of {}, skipped.", size);
     }
 }
 
@@ -89,21 +49,15 @@ pub async fn streaming_task(
     mut stream: speaker::Stream<'static, usb::Driver<'static, peripherals::USB_OTG_FS>>,
     mut sender: zerocopy_channel::Sender<'static, NoopRawMutex, UsbSampleBlock>,
 ) {
-    loop {
-        stream.wait_connection().await;
-        _ = stream_handler(&mut stream, &mut sender).await;
-    }
+    let mut packet = [0u8; USB_MAX_PACKET_SIZE];
+    let mut sink = ChannelSink { sender: &mut sender };
+
+    handler::stream(&mut stream, &mut packet, &mut sink).await
 }
 
 #[embassy_executor::task]
 pub async fn feedback_task(mut feedback: speaker::Feedback<'static, usb::Driver<'static, peripherals::USB_OTG_FS>>) {
-    let feedback_factor =
-        ((1 << FEEDBACK_SHIFT) as f32 / TICKS_PER_SAMPLE) / FEEDBACK_REFRESH_PERIOD.frame_count() as f32;
-
-    loop {
-        feedback.wait_connection().await;
-        _ = feedback_handler(&mut feedback, feedback_factor).await;
-    }
+    handler::feedback(&mut feedback, &FEEDBACK_SIGNAL, &STREAM_CONFIG).await
 }
 
 #[embassy_executor::task]
@@ -122,33 +76,7 @@ pub async fn control_task(control_monitor: speaker::ControlMonitor<'static>) {
     loop {
         control_monitor.changed().await;
 
-        let mut usb_gain_left = 0.0_f32;
-        let mut usb_gain_right = 0.0_f32;
-
-        for channel in AUDIO_CHANNELS {
-            let volume = control_monitor.volume(channel).unwrap();
-
-            let gain = match volume {
-                speaker::Volume::Muted => 0.0,
-                speaker::Volume::DeciBel(volume_db) => {
-                    if volume_db > 0.0 {
-                        panic!("Volume must not be positive.")
-                    }
-
-                    db_to_linear(volume_db)
-                }
-            };
-
-            match channel {
-                uac1::Channel::LeftFront => {
-                    usb_gain_left = gain;
-                }
-                uac1::Channel::RightFront => {
-                    usb_gain_right = gain;
-                }
-                _ => (),
-            }
-        }
+        let [usb_gain_left, usb_gain_right] = handler::channel_gains(&control_monitor, AUDIO_CHANNELS);
 
         VOLUME_SIGNAL.signal((usb_gain_left, usb_gain_right));
     }
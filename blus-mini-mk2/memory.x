@@ -10,7 +10,9 @@ MEMORY
   /* - STM32H730xB                                 128K */
   /* - STM32H723xE/725xE                           512K */
   /* - STM32H723xG/725xG/733xG/735xG                 1M */
-  FLASH1  : ORIGIN = 0x08000000, LENGTH = 1M
+  /* The upper two sectors are reserved for persistent storage (see `src/storage.rs`). */
+  FLASH1  : ORIGIN = 0x08000000, LENGTH = 768K
+  STORAGE : ORIGIN = 0x080C0000, LENGTH = 256K
 
   /* Data TCM  */
   /* - Two contiguous 64KB RAMs.                                     */
@@ -25,6 +27,7 @@ MEMORY
   /* - More memory can be assigned to ITCM. See AXI SRAM notes, below. */
   /* - Used for latency-critical interrupt handlers etc.               */
   /* - Zero wait-states.                                               */
+  /* - Its code is copied from flash at startup (see `src/tcm.rs`).    */
   ITCM    : ORIGIN = 0x00000000, LENGTH = 64K + 0K
 
   /* AXI SRAM */
@@ -73,34 +76,79 @@ _stack_start = ORIGIN(RAM) + LENGTH(RAM);
 /* - ITCM, DTCM and AXISRAM connect to a 64-bit wide bus -> align to 8 bytes. */
 /* - All other memories     connect to a 32-bit wide bus -> align to 4 bytes. */
 SECTIONS {
-  .itcm (NOLOAD) : ALIGN(8) {
-    *(.itcm .itcm.*);
-    . = ALIGN(8);
-    } > ITCM
-
   .axisram (NOLOAD) : ALIGN(8) {
+    __saxisram = .;
     *(.axisram .axisram.*);
     . = ALIGN(8);
+    __eaxisram = .;
     } > AXISRAM
 
   .sram1 (NOLOAD) : ALIGN(4) {
+    __ssram1 = .;
     *(.sram1 .sram1.*);
     . = ALIGN(4);
+    __esram1 = .;
     } > SRAM1
 
   .sram2 (NOLOAD) : ALIGN(4) {
+    __ssram2 = .;
     *(.sram2 .sram2.*);
     . = ALIGN(4);
+    __esram2 = .;
     } > SRAM2
 
   .sram4 (NOLOAD) : ALIGN(4) {
+    __ssram4 = .;
     *(.sram4 .sram4.*);
     . = ALIGN(4);
+    __esram4 = .;
     } > SRAM4
 
   .bsram (NOLOAD) : ALIGN(4) {
+    __sbsram = .;
     *(.bsram .bsram.*);
     . = ALIGN(4);
+    __ebsram = .;
     } > BSRAM
 
 };
+
+/* Sections for the hot code and data of the signal processing in the TCMs, initialized by `src/tcm.rs`. */
+/* - ITCM code is loaded from flash, as part of the firmware image.                                       */
+/* - DTCM data is zeroed, like `.bss`, and stays in DTCM, even if RAM is moved elsewhere.                 */
+SECTIONS {
+  .itcm : ALIGN(8) {
+    __sitcm = .;
+    *(.itcm .itcm.*);
+    . = ALIGN(8);
+    __eitcm = .;
+    } > ITCM AT > FLASH
+
+  __siitcm = LOADADDR(.itcm);
+
+  .dtcm (NOLOAD) : ALIGN(8) {
+    __sdtcm = .;
+    *(.dtcm .dtcm.*);
+    . = ALIGN(8);
+    __edtcm = .;
+    } > DTCM
+} INSERT AFTER .bss;
+
+/* The task arena of `embassy-executor`, bracketed for the report of its usage (see `src/stack_usage.rs`). */
+/* It follows `.bss`, and is zeroed with it.                                                              */
+SECTIONS {
+  .task_arena (NOLOAD) : ALIGN(4) {
+    __stask_arena = .;
+    *(.bss._ZN16embassy_executor7_export5ARENA*);
+    __etask_arena = .;
+    } > RAM
+} INSERT AFTER .bss;
+
+/* The sizes of the memory regions, for the report of the memory usage at startup (see `src/memory_usage.rs`). */
+__itcm_size    = LENGTH(ITCM);
+__dtcm_size    = LENGTH(DTCM);
+__axisram_size = LENGTH(AXISRAM);
+__sram1_size   = LENGTH(SRAM1);
+__sram2_size   = LENGTH(SRAM2);
+__sram4_size   = LENGTH(SRAM4);
+__bsram_size   = LENGTH(BSRAM);
@@ -0,0 +1,587 @@
+//! Power sequencing and protection of the four TAS2780 amplifiers, as an explicit state machine.
+//!
+//! The audio routing requests the amplifiers for a source (see [`request`]), or releases them with
+//! `AudioSource::None`, and waits until the [`amplifier_task`] settled (see [`settled`]), but at most for
+//! [`SETTLE_TIMEOUT`], such that a stuck amplifier sequence never stalls the audio routing. Powering up steps through:
+//! 1. [`AmplifierState::WaitingForSupply`]: the supply must be above its threshold (see [`crate::supply`]), within
+//!    [`SUPPLY_TIMEOUT`].
+//! 2. [`AmplifierState::ReleasingShutdown`]: the shutdown pin is released, and the amplifiers start up.
+//! 3. [`AmplifierState::Configuring`]: every amplifier must respond on I2C, and is configured for the TDM format of
+//!    the source within [`CONFIGURATION_TIMEOUT`].
+//! 4. [`AmplifierState::CheckingLoads`]: the amplifiers are powered up with muted outputs for [`LOAD_CHECK_TIME`].
+//!    The state counts as settled, such that the audio routing starts the clocks, but the output gain stays zero
+//!    until running (see [`crate::control::Control::silent`]). A shorted output or speaker wire trips the
+//!    over-current protection, and a damaged output stage the DC detection. Such an amplifier stays shut down until
+//!    the next request, and is reported like a protected one. An open load causes no fault, and is not detected.
+//! 5. [`AmplifierState::Unmuting`] the amplifiers that passed the check, and finally [`AmplifierState::Running`].
+//!
+//! Powering down mutes the amplifiers first ([`AmplifierState::Muting`]), such that their outputs ramp down, and then
+//! shuts them down by software and by the shutdown pin ([`AmplifierState::ShuttingDown`]), before
+//! [`AmplifierState::Off`]. A failed step powers down. A power-up, in which an amplifier did not respond or configure,
+//! is retried after [`RETRY_DELAY`], up to [`POWER_UP_ATTEMPTS`] times, unless another source was requested
+//! meanwhile; then, as after an undervoltage, the sequence ends in [`AmplifierState::Error`] until the next request.
+//! Supply undervoltage also powers down running amplifiers into an error state, from which they power up again once
+//! the supply recovers.
+//!
+//! While running, the amplifiers are checked for faults every [`FAULT_CHECK_INTERVAL`]. Protective faults
+//! (over-temperature, over-current, and DC at the output) mute an amplifier, until it ran for [`RECOVERY_TIME`]
+//! without them. After [`SHUTDOWN_FAULT_COUNT`] faults, it is shut down until the next request. The die temperatures
+//! are passed on to the thermal throttling (see [`crate::thermal`]).
+//!
+//! The analog gain of the amplifiers is stored with the settings (see [`set_gain`]). A lower gain lowers the noise
+//! floor, a higher gain raises the maximum output. The digital signal path keeps its full-scale headroom at any gain,
+//! while the output power estimate, and thus the thermal anticipation, follows the gain (see
+//! [`crate::output_power`]). A changed gain applies to running amplifiers right away.
+use core::cell::Cell;
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use embassy_embedded_hal::shared_bus::blocking::i2c::I2cDevice;
+use embassy_futures::select::{select4, Either4};
+use embassy_stm32::gpio::{Input, Output};
+use embassy_stm32::i2c::I2c;
+use embassy_stm32::mode::Async;
+use embassy_sync::blocking_mutex::raw::{CriticalSectionRawMutex, ThreadModeRawMutex};
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{with_timeout, Duration, Instant, Timer};
+use protocol::event_log::EventKind;
+use tas2780::tas2780::{
+    Config, Gain, Tas2780, TdmTimeSlotLength, TdmWordLength, FAULT_CLOCK_ERROR, FAULT_DC_DETECTION, FAULT_OVER_CURRENT,
+    PROTECTIVE_FAULTS,
+};
+
+use crate::control::CONTROL;
+use crate::event_log;
+use crate::faults::{self, Fault};
+use crate::gpio_expander;
+use crate::led;
+use crate::log;
+use crate::startup;
+use crate::supply;
+use crate::thermal;
+use crate::*;
+
+/// The I2C addresses of the amplifiers, by output channel.
+const ADDRESSES: [u8; OUTPUT_CHANNEL_COUNT] = [0x39, 0x3a, 0x3d, 0x3e];
+
+/// The default analog gain of the amplifiers.
+pub const DEFAULT_GAIN: Gain = Gain::Gain11_0dBV;
+
+/// The time for the supply to rise above its threshold, when powering up.
+pub const SUPPLY_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The time for the amplifiers to start up, after releasing the shutdown pin.
+const STARTUP_TIME: Duration = Duration::from_millis(10);
+
+/// The time for configuring an amplifier.
+pub const CONFIGURATION_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// The time for which the amplifiers run muted, while their outputs are checked for shorts.
+pub const LOAD_CHECK_TIME: Duration = Duration::from_millis(100);
+
+/// The faults that indicate a faulty output or load during the load check.
+const LOAD_FAULTS: u8 = FAULT_OVER_CURRENT | FAULT_DC_DETECTION;
+
+/// The time for the outputs to ramp down, after muting.
+const MUTE_TIME: Duration = Duration::from_millis(10);
+
+/// The number of attempts to power up, when amplifiers do not respond or configure.
+pub const POWER_UP_ATTEMPTS: u8 = 3;
+
+/// The time between a failed attempt to power up (which powered down again), and the next attempt.
+pub const RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// The longest wait for the amplifiers to settle. Longer than powering down, and powering up with all attempts: the
+/// supply timeout, and per attempt the startup, the configuration of every amplifier, and the retry delay.
+pub const SETTLE_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// The interval between checks for amplifier faults, while the amplifiers run.
+pub const FAULT_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The time without protective faults, after which a muted amplifier plays again.
+pub const RECOVERY_TIME: Duration = Duration::from_secs(5);
+
+/// The number of protective faults while running, after which an amplifier stays shut down.
+pub const SHUTDOWN_FAULT_COUNT: u8 = 3;
+
+/// A failed power-up of the amplifiers.
+#[derive(Clone, Copy, PartialEq, Debug, defmt::Format)]
+pub enum AmplifierError {
+    /// The supply is below its threshold.
+    Undervoltage,
+    /// An amplifier (by output channel) does not respond on I2C.
+    NotResponding(u8),
+    /// Configuring an amplifier (by output channel) took too long.
+    ConfigurationTimeout(u8),
+}
+
+/// The power state of the amplifiers.
+#[derive(Clone, Copy, PartialEq, Debug, defmt::Format)]
+pub enum AmplifierState {
+    /// Shut down.
+    Off,
+    /// Waiting for the supply, before powering up.
+    WaitingForSupply,
+    /// Starting up, after releasing the shutdown pin.
+    ReleasingShutdown,
+    /// Configuring the amplifiers for the source.
+    Configuring,
+    /// Checking the outputs for shorts, while muted.
+    CheckingLoads,
+    /// Enabling the outputs.
+    Unmuting,
+    /// Playing.
+    Running,
+    /// Ramping down the outputs, before shutting down.
+    Muting,
+    /// Shutting down.
+    ShuttingDown,
+    /// Shut down after a failure.
+    Error(AmplifierError),
+}
+
+impl AmplifierState {
+    /// Whether the state is final, rather than a step of powering up or down.
+    pub fn is_settled(&self) -> bool {
+        matches!(
+            self,
+            AmplifierState::Off | AmplifierState::CheckingLoads | AmplifierState::Running | AmplifierState::Error(_)
+        )
+    }
+
+    /// Whether the amplifiers are powered up, and must be powered down for a new request.
+    pub fn is_powered(&self) -> bool {
+        matches!(self, AmplifierState::CheckingLoads | AmplifierState::Running)
+    }
+}
+
+/// The name of an amplifier state, as used by the text interfaces.
+pub fn state_name(state: AmplifierState) -> &'static str {
+    match state {
+        AmplifierState::Off => "off",
+        AmplifierState::WaitingForSupply => "waiting for supply",
+        AmplifierState::ReleasingShutdown => "releasing shutdown",
+        AmplifierState::Configuring => "configuring",
+        AmplifierState::CheckingLoads => "checking loads",
+        AmplifierState::Unmuting => "unmuting",
+        AmplifierState::Running => "running",
+        AmplifierState::Muting => "muting",
+        AmplifierState::ShuttingDown => "shutting down",
+        AmplifierState::Error(AmplifierError::Undervoltage) => "error (supply undervoltage)",
+        AmplifierState::Error(AmplifierError::NotResponding(_)) => "error (not responding)",
+        AmplifierState::Error(AmplifierError::ConfigurationTimeout(_)) => "error (configuration timeout)",
+    }
+}
+
+/// Resources that are required for the amplifiers.
+#[allow(missing_docs)]
+pub struct AmplifierResources {
+    pub i2c_bus: &'static gpio_expander::I2cBus,
+    pub pin_nsd: Output<'static>,
+    #[allow(unused)]
+    pub pin_irqz: Input<'static>,
+}
+
+/// An amplifier on the shared I2C bus.
+type Amplifier<'d> = Tas2780<'d, I2cDevice<'static, ThreadModeRawMutex, I2c<'static, Async>>>;
+
+/// The requested source, and the source and state that the amplifiers reached for it.
+#[derive(Clone, Copy)]
+struct Sequence {
+    requested: AudioSource,
+    source: AudioSource,
+    state: AmplifierState,
+}
+
+static SEQUENCE: Mutex<CriticalSectionRawMutex, Cell<Sequence>> = Mutex::new(Cell::new(Sequence {
+    requested: AudioSource::None,
+    source: AudioSource::None,
+    state: AmplifierState::Off,
+}));
+
+/// Signal that is emitted when a source is requested.
+static REQUEST_SIGNAL: Signal<CriticalSectionRawMutex, AudioSource> = Signal::new();
+
+/// Signal that is emitted when the state changes.
+static STATE_CHANGED_SIGNAL: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// The analog gain of the amplifiers, as its register value.
+static GAIN: AtomicU8 = AtomicU8::new(DEFAULT_GAIN as u8);
+
+/// Signal that is emitted when the analog gain changes.
+static GAIN_CHANGED_SIGNAL: Signal<ThreadModeRawMutex, ()> = Signal::new();
+
+/// The analog gain of the amplifiers.
+pub fn gain() -> Gain {
+    Gain::try_from(GAIN.load(Ordering::Relaxed)).unwrap_or(DEFAULT_GAIN)
+}
+
+/// The gain setting with an output level in dBV, if there is one.
+pub fn gain_from_dbv(dbv: f32) -> Option<Gain> {
+    Gain::ALL.into_iter().find(|gain| (gain.dbv() - dbv).abs() < 0.01)
+}
+
+/// Set the analog gain of the amplifiers, which also applies to running amplifiers.
+pub fn set_gain(gain: Gain) {
+    if GAIN.swap(gain as u8, Ordering::Relaxed) != gain as u8 {
+        GAIN_CHANGED_SIGNAL.signal(());
+    }
+}
+
+/// The power state of the amplifiers.
+pub fn state() -> AmplifierState {
+    SEQUENCE.lock(|sequence| sequence.get().state)
+}
+
+/// Request the amplifiers for a source, or power them down with `AudioSource::None`.
+pub fn request(source: AudioSource) {
+    SEQUENCE.lock(|sequence| {
+        let mut current = sequence.get();
+        current.requested = source;
+        sequence.set(current);
+    });
+
+    REQUEST_SIGNAL.signal(source);
+}
+
+/// Wait until the amplifiers settled for the last requested source, and return their state. After
+/// [`SETTLE_TIMEOUT`], returns the state that they are in.
+pub async fn settled() -> AmplifierState {
+    let settle = async {
+        loop {
+            let sequence = SEQUENCE.lock(|sequence| sequence.get());
+
+            if sequence.source == sequence.requested && sequence.state.is_settled() {
+                return sequence.state;
+            }
+
+            STATE_CHANGED_SIGNAL.wait().await;
+        }
+    };
+
+    match with_timeout(SETTLE_TIMEOUT, settle).await {
+        Ok(state) => state,
+        Err(_) => {
+            let state = state();
+            log!(warn, "Amplifiers did not settle, still {}", state_name(state));
+            state
+        }
+    }
+}
+
+/// Enter a state for a source.
+fn set_state(source: AudioSource, state: AmplifierState) {
+    let previous = SEQUENCE.lock(|sequence| {
+        let mut current = sequence.get();
+        let previous = current.state;
+
+        current.source = source;
+        current.state = state;
+        sequence.set(current);
+        previous
+    });
+
+    if previous != state {
+        log!(debug, "Amplifiers: {}", state_name(state));
+    }
+
+    CONTROL.set_amplifier_ready(state == AmplifierState::Running);
+    STATE_CHANGED_SIGNAL.signal(());
+}
+
+/// The protection of an amplifier, while running.
+#[derive(Clone, Copy, Default)]
+struct Protection {
+    /// The number of protective faults since powering up.
+    fault_count: u8,
+    /// The time of the last protective fault, while the amplifier is muted or shut down.
+    muted_since: Option<Instant>,
+}
+
+/// The mask of amplifiers that are muted or shut down by their protection.
+fn protected_amplifiers(protections: &[Protection]) -> u8 {
+    protections
+        .iter()
+        .enumerate()
+        .filter(|(_, protection)| protection.muted_since.is_some())
+        .fold(0, |mask, (index, _)| mask | 1 << index)
+}
+
+/// The amplifier configuration for a source: the TDM slot of the output channel, and the TDM format of the source.
+fn config(source: AudioSource, channel: usize) -> Config {
+    let (tdm_word_length, tdm_time_slot_length) = match source {
+        AudioSource::Spdif => (TdmWordLength::Word16Bit, TdmTimeSlotLength::Slot16Bit),
+        _ => (TdmWordLength::Word32Bit, TdmTimeSlotLength::Slot32Bit),
+    };
+
+    Config {
+        gain: gain(),
+        tdm_slot: channel as u8,
+        tdm_word_length,
+        tdm_time_slot_length,
+        ..Default::default()
+    }
+}
+
+/// Power up the amplifiers for a source, and return the reached state.
+async fn power_up(amplifiers: &mut [Amplifier<'_>], pin_nsd: &mut Output<'_>, source: AudioSource) -> AmplifierState {
+    set_state(source, AmplifierState::WaitingForSupply);
+
+    let supply = with_timeout(SUPPLY_TIMEOUT, async {
+        while supply::undervoltage() {
+            Timer::after_millis(10).await;
+        }
+    });
+
+    if supply.await.is_err() {
+        return AmplifierState::Error(AmplifierError::Undervoltage);
+    }
+
+    set_state(source, AmplifierState::ReleasingShutdown);
+    pin_nsd.set_high();
+    Timer::after(STARTUP_TIME).await;
+
+    set_state(source, AmplifierState::Configuring);
+
+    for (channel, amplifier) in amplifiers.iter_mut().enumerate() {
+        if amplifier.probe().is_err() {
+            return AmplifierState::Error(AmplifierError::NotResponding(channel as u8));
+        }
+
+        if with_timeout(CONFIGURATION_TIMEOUT, amplifier.init(config(source, channel)))
+            .await
+            .is_err()
+        {
+            return AmplifierState::Error(AmplifierError::ConfigurationTimeout(channel as u8));
+        }
+    }
+
+    for amplifier in amplifiers.iter_mut() {
+        amplifier.take_faults();
+        amplifier.enable_muted();
+    }
+
+    AmplifierState::CheckingLoads
+}
+
+/// Power up the amplifiers for a source like [`power_up`], and retry, if amplifiers did not respond or configure.
+/// Failed attempts are powered down. Returns the reached state.
+async fn power_up_with_retries(
+    amplifiers: &mut [Amplifier<'_>],
+    pin_nsd: &mut Output<'_>,
+    source: AudioSource,
+) -> AmplifierState {
+    let mut attempt = 1;
+
+    loop {
+        let state = power_up(amplifiers, pin_nsd, source).await;
+        let AmplifierState::Error(error) = state else {
+            return state;
+        };
+
+        log!(
+            warn,
+            "Amplifier power-up failed (attempt {} of {}): {}",
+            attempt,
+            POWER_UP_ATTEMPTS,
+            state_name(state)
+        );
+
+        let responding = !matches!(error, AmplifierError::NotResponding(_));
+        power_down(amplifiers, pin_nsd, source, responding).await;
+
+        // An undervoltage recovers with the supply, and a new request starts over.
+        if error == AmplifierError::Undervoltage || attempt >= POWER_UP_ATTEMPTS || REQUEST_SIGNAL.signaled() {
+            return state;
+        }
+
+        attempt += 1;
+        Timer::after(RETRY_DELAY).await;
+    }
+}
+
+/// Finish the load check: shut down the amplifiers with a faulty output or load, and unmute the others.
+fn check_loads(
+    amplifiers: &mut [Amplifier<'_>],
+    protections: &mut [Protection],
+    source: AudioSource,
+) -> AmplifierState {
+    set_state(source, AmplifierState::Unmuting);
+
+    for (index, (amplifier, protection)) in amplifiers.iter_mut().zip(protections.iter_mut()).enumerate() {
+        // Clock errors are expected, until the audio routing started the clocks.
+        let faults = amplifier.take_faults() & LOAD_FAULTS;
+
+        if faults == 0 {
+            amplifier.set_muted(false);
+            continue;
+        }
+
+        log!(warn, "Amplifier {} load check failed: {:#x}", index, faults);
+        event_log::record(EventKind::AmplifierFault, (index as u8) << 4 | faults);
+
+        protection.fault_count = SHUTDOWN_FAULT_COUNT;
+        protection.muted_since = Some(Instant::now());
+        amplifier.shutdown();
+    }
+
+    CONTROL.set_protected_amplifiers(protected_amplifiers(protections));
+    AmplifierState::Running
+}
+
+/// Power down the amplifiers. The software shutdown is skipped, if they do not respond.
+async fn power_down(amplifiers: &mut [Amplifier<'_>], pin_nsd: &mut Output<'_>, source: AudioSource, responding: bool) {
+    if responding {
+        set_state(source, AmplifierState::Muting);
+
+        for amplifier in amplifiers.iter_mut() {
+            amplifier.set_muted(true);
+        }
+
+        Timer::after(MUTE_TIME).await;
+    }
+
+    set_state(source, AmplifierState::ShuttingDown);
+
+    if responding {
+        for amplifier in amplifiers.iter_mut() {
+            amplifier.shutdown();
+        }
+    }
+
+    pin_nsd.set_low();
+    thermal::reset();
+}
+
+/// Check the amplifiers for faults, and mute, shut down, or unmute them by their protection.
+fn check_faults(amplifiers: &mut [Amplifier<'_>], protections: &mut [Protection]) {
+    for (index, (amplifier, protection)) in amplifiers.iter_mut().zip(protections.iter_mut()).enumerate() {
+        let faults = amplifier.take_faults();
+        thermal::update(index, amplifier.temperature());
+
+        if faults != 0 {
+            log!(warn, "Amplifier {} fault: {:#x}", index, faults);
+            event_log::record(EventKind::AmplifierFault, (index as u8) << 4 | faults);
+        }
+
+        if faults & FAULT_CLOCK_ERROR != 0 {
+            faults::raise(Fault::ClockLoss);
+        }
+
+        if faults & PROTECTIVE_FAULTS != 0 {
+            protection.fault_count = protection.fault_count.saturating_add(1);
+            protection.muted_since = Some(Instant::now());
+
+            if protection.fault_count >= SHUTDOWN_FAULT_COUNT {
+                log!(warn, "Amplifier {} shut down after repeated faults", index);
+                amplifier.shutdown();
+            } else {
+                amplifier.set_muted(true);
+            }
+        } else if protection.fault_count < SHUTDOWN_FAULT_COUNT
+            && protection
+                .muted_since
+                .is_some_and(|since| since.elapsed() >= RECOVERY_TIME)
+        {
+            log!(info, "Amplifier {} recovered", index);
+            protection.muted_since = None;
+            amplifier.set_muted(false);
+        }
+    }
+
+    CONTROL.set_protected_amplifiers(protected_amplifiers(protections));
+}
+
+/// Sequences the power states of the amplifiers, and protects them while they run.
+#[embassy_executor::task]
+pub async fn amplifier_task(resources: AmplifierResources) {
+    let mut pin_nsd = resources.pin_nsd;
+    pin_nsd.set_low();
+
+    let mut devices = [(); OUTPUT_CHANNEL_COUNT].map(|_| I2cDevice::new(resources.i2c_bus));
+    let [device_a, device_b, device_c, device_d] = &mut devices;
+    let mut amplifiers = [
+        Tas2780::new(device_a, ADDRESSES[0]),
+        Tas2780::new(device_b, ADDRESSES[1]),
+        Tas2780::new(device_c, ADDRESSES[2]),
+        Tas2780::new(device_d, ADDRESSES[3]),
+    ];
+
+    let mut source = AudioSource::None;
+    let mut state = AmplifierState::Off;
+    let mut protections = [Protection::default(); OUTPUT_CHANNEL_COUNT];
+
+    set_state(source, state);
+    led::boot_progress(led::BootStage::Amplifiers);
+    startup::complete(startup::Step::Amplifiers);
+
+    loop {
+        let check_time = match state {
+            AmplifierState::CheckingLoads => LOAD_CHECK_TIME,
+            _ => FAULT_CHECK_INTERVAL,
+        };
+
+        let power_up_source = match select4(
+            REQUEST_SIGNAL.wait(),
+            supply::UNDERVOLTAGE_SIGNAL.wait(),
+            GAIN_CHANGED_SIGNAL.wait(),
+            Timer::after(check_time),
+        )
+        .await
+        {
+            Either4::First(requested) => {
+                // Failed power-ups already powered down.
+                if state.is_powered() {
+                    power_down(&mut amplifiers, &mut pin_nsd, source, true).await;
+                }
+
+                source = requested;
+                Some(requested).filter(|requested| *requested != AudioSource::None)
+            }
+            Either4::Second(true) if state.is_powered() => {
+                log!(warn, "Supply undervoltage, powering down the amplifiers");
+                power_down(&mut amplifiers, &mut pin_nsd, source, true).await;
+
+                state = AmplifierState::Error(AmplifierError::Undervoltage);
+                set_state(source, state);
+                continue;
+            }
+            Either4::Second(false) if state == AmplifierState::Error(AmplifierError::Undervoltage) => Some(source),
+            Either4::Second(_) => continue,
+            Either4::Third(_) => {
+                // Amplifiers that are not powered get the gain with their configuration.
+                if state.is_powered() {
+                    log!(info, "Amplifier gain: {} dBV", gain().dbv());
+
+                    for (index, amplifier) in amplifiers.iter_mut().enumerate() {
+                        if let Err(error) = amplifier.set_gain(gain()) {
+                            log!(warn, "Amplifier {} gain not set: {:?}", index, error);
+                        }
+                    }
+                }
+                continue;
+            }
+            Either4::Fourth(_) => {
+                match state {
+                    AmplifierState::CheckingLoads => {
+                        state = check_loads(&mut amplifiers, &mut protections, source);
+                        set_state(source, state);
+                    }
+                    AmplifierState::Running => check_faults(&mut amplifiers, &mut protections),
+                    _ => (),
+                }
+                continue;
+            }
+        };
+
+        protections = [Protection::default(); OUTPUT_CHANNEL_COUNT];
+        CONTROL.set_protected_amplifiers(0);
+
+        state = match power_up_source {
+            Some(source) => power_up_with_retries(&mut amplifiers, &mut pin_nsd, source).await,
+            None => AmplifierState::Off,
+        };
+
+        set_state(source, state);
+    }
+}
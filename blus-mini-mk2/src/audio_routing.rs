@@ -1,18 +1,105 @@
 //! Audio routing (source selection), signal processing, and playback module.
-use audio::{audio_filter, AudioFilter};
-use defmt::{debug, info, panic, trace};
-use embassy_futures::select::{select, select3, Either, Either3};
-use embassy_stm32::gpio::Output;
+//!
+//! The audio routing task runs on its own interrupt executor at [`EXECUTOR_PRIORITY`], which preempts the thread
+//! executor of all other tasks (USB control, the consoles, logging, and the user interfaces). Their traffic can
+//! therefore not delay the processing of sample blocks; only the interrupt handlers of the drivers run at a higher
+//! priority. The state that the task shares with other tasks is guarded by atomics, or by mutexes and signals with a
+//! [`CriticalSectionRawMutex`], since a `ThreadModeRawMutex` is only accessible from thread mode.
+//!
+//! The signal processing runs at [`SAMPLE_RATE_HZ`]. A source at another rate (e.g. an S/PDIF transmitter at
+//! 44.1 kHz) fails the setup of the interfaces with a [`SetupError`]. The routing then logs the error, falls back to no
+//! source, and tries the source again after [`SETUP_RETRY_DELAY`], such that playback resumes once the source
+//! switches to a supported rate.
+//!
+//! The routing is assembled from its inputs, the signal processing, and its output with an [`AudioPipeline`], which
+//! spawns the task. Sources without an input are never selected, and without signal processing, samples pass with
+//! their gains only.
+//!
+//! USB packets arrive in a ring buffer of samples (see [`UsbRing`]), from which the routing reads blocks of 1 ms, no
+//! matter how the host sizes its packets. S/PDIF blocks arrive in a channel, and the Raspberry Pi is read directly.
+//!
+//! A new source that starts from none reconfigures the idle SAIs in place (see [`reconfigure_sai_amp_rpi`]), such that
+//! it plays without the gap of recreating their drivers. Releasing a source, errors, and changes of the latency mode
+//! recreate the drivers, which also clears their error states.
+//!
+//! The routing counts underruns, overruns, dropped sample blocks, and restarts of the SAIs in [`STATS`], which the
+//! control register map exposes (see [`crate::registers`]).
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use audio_pipeline::pipeline::{grouped_routing, Pipeline};
+use audio_pipeline::ring::Consumer;
+use audio_pipeline::source::{select_source, AudioInput};
+use defmt::{debug, unwrap};
+use embassy_executor::{InterruptExecutor, SendSpawner, SpawnError};
+use embassy_futures::select::{select, select4, Either, Either4};
+use embassy_stm32::interrupt::{InterruptExt, Priority};
 use embassy_stm32::sai::word;
-use embassy_stm32::{peripherals, sai};
-use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+use embassy_stm32::{interrupt, peripherals, sai};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
 use embassy_sync::channel;
-use grounded::uninit::GroundedArrayCell;
-
+use embassy_time::{Duration, Instant, Timer};
+use protocol::event_log::EventKind;
+use protocol::led::LedFunction;
+use static_cell::StaticCell;
+
+use crate::amplifiers;
+use crate::auto_standby;
+use crate::calibration;
+use crate::control::CONTROL;
+use crate::cpu_load;
+use crate::dc_protection;
+use crate::dma_buffers;
+use crate::event_log;
+use crate::latency::{self, LatencyMode};
+use crate::led::Led;
+use crate::log;
+use crate::output_power;
+use crate::potentiometer;
+use crate::self_test;
+use crate::startup;
+use crate::thermal;
+use crate::watchdog::{self, Task};
 use crate::*;
 
-// Sample buffer for writing to the amplifier SAI
-const SAI_AMP_SAMPLE_COUNT: usize = (OUTPUT_CHANNEL_COUNT / INPUT_CHANNEL_COUNT) * MAX_SAMPLE_COUNT;
+/// The time in which the master gain ramps from full-scale to zero (soft mute), and back. Smaller gain changes (e.g.
+/// of the volume) ramp at the same rate.
+pub const SOFT_MUTE_TIME: Duration = Duration::from_millis(20);
+
+/// The input channel of every output channel: the left input to the first two-way speaker, the right input to the
+/// second.
+pub const ROUTING: [usize; OUTPUT_CHANNEL_COUNT] = grouped_routing::<INPUT_CHANNEL_COUNT, OUTPUT_CHANNEL_COUNT>();
+
+/// The size of the sample buffer for writing to the amplifier SAI.
+pub const SAI_AMP_SAMPLE_COUNT: usize = MAX_OUTPUT_SAMPLE_COUNT;
+
+/// The time, after which a source that failed the setup of the interfaces is tried again.
+pub const SETUP_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// The frequency of the kernel clock of the S/PDIF receiver (PLL3 R).
+const SPDIFRX_KERNEL_CLOCK_HZ: u32 = 96_000_000;
+
+/// The standard sample rates of S/PDIF.
+const SPDIF_SAMPLE_RATES_HZ: [u32; 7] = [32_000, 44_100, 48_000, 88_200, 96_000, 176_400, 192_000];
+
+/// The interrupt priority of the audio executor. Above the thread executor, but below the interrupts of the drivers
+/// (DMA, USB, and the time driver), which only wake tasks.
+pub const EXECUTOR_PRIORITY: Priority = Priority::P6;
+
+/// The executor of the audio routing task, which runs in the otherwise unused UART4 interrupt.
+static EXECUTOR: InterruptExecutor = InterruptExecutor::new();
+
+#[interrupt]
+unsafe fn UART4() {
+    EXECUTOR.on_interrupt()
+}
+
+/// Start the audio executor, and return its spawner.
+///
+/// Panics, if called twice.
+pub fn start_executor() -> SendSpawner {
+    interrupt::UART4.set_priority(EXECUTOR_PRIORITY);
+    EXECUTOR.start(interrupt::UART4)
+}
 
 /// Resources that are required for instantiating SAI1.
 #[allow(missing_docs)]
@@ -46,23 +133,117 @@ pub struct Sai4Resources {
     pub dma_b: peripherals::BDMA_CH1,
 }
 
-// Accessible by BDMA (Zone D3)
-#[link_section = ".sram4"]
-static SAI_AMP_WRITE_BUFFER: GroundedArrayCell<u32, SAI_AMP_SAMPLE_COUNT> = GroundedArrayCell::uninit();
+/// Counters of the audio routing since startup.
+pub struct Stats {
+    underrun_count: AtomicU32,
+    overrun_count: AtomicU32,
+    dropped_block_count: AtomicU32,
+    sai_restart_count: AtomicU32,
+}
 
-#[link_section = ".sram4"]
-static SAI_RPI_READ_BUFFER: GroundedArrayCell<u32, DEFAULT_SAMPLE_COUNT> = GroundedArrayCell::uninit();
+/// The counters of the audio routing.
+pub static STATS: Stats = Stats::new();
 
-fn new_sai_amp_rpi<'d>(
-    resources: &'d mut Sai4Resources,
-    sai_amp_write_buffer: &'d mut [u32],
-    sai_rpi_read_buffer: &'d mut [u32],
-    sample_rate_hz: u32,
-    audio_source: AudioSource,
-) -> (
-    sai::Sai<'d, peripherals::SAI4, u32>,
-    sai::Sai<'d, peripherals::SAI4, u32>,
-) {
+impl Stats {
+    const fn new() -> Self {
+        Stats {
+            underrun_count: AtomicU32::new(0),
+            overrun_count: AtomicU32::new(0),
+            dropped_block_count: AtomicU32::new(0),
+            sai_restart_count: AtomicU32::new(0),
+        }
+    }
+
+    /// The number of underruns of the amplifier SAI, which ran out of samples.
+    pub fn underrun_count(&self) -> u32 {
+        self.underrun_count.load(Ordering::Relaxed)
+    }
+
+    /// The number of overruns: USB packets that found the USB ring full, sample blocks that found the audio channel
+    /// full, and samples of the Raspberry Pi that were overwritten before they were read.
+    pub fn overrun_count(&self) -> u32 {
+        self.overrun_count.load(Ordering::Relaxed)
+    }
+
+    /// The number of sample blocks that were dropped, since they were not of the active source.
+    pub fn dropped_block_count(&self) -> u32 {
+        self.dropped_block_count.load(Ordering::Relaxed)
+    }
+
+    /// The number of restarts of the SAIs, which recreate their drivers: on releasing a source, changes of the latency
+    /// mode, and after the self-test. A source that starts from none reconfigures them in place, without a restart.
+    pub fn sai_restart_count(&self) -> u32 {
+        self.sai_restart_count.load(Ordering::Relaxed)
+    }
+
+    /// Count an underrun of the amplifier SAI.
+    pub fn count_underrun(&self) {
+        self.underrun_count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count an overrun.
+    pub fn count_overrun(&self) {
+        self.overrun_count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a dropped sample block.
+    fn count_dropped_block(&self) {
+        self.dropped_block_count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a restart of the SAIs.
+    fn count_sai_restart(&self) {
+        self.sai_restart_count.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// A driver of a SAI4 sub-block.
+type Sai4<'d> = sai::Sai<'d, peripherals::SAI4, u32>;
+
+/// An error of the setup of the audio interfaces for a source.
+#[derive(Clone, Copy, PartialEq, Debug, defmt::Format)]
+pub enum SetupError {
+    /// The sample rate of the source in Hz is not supported.
+    UnsupportedSampleRate(u32),
+}
+
+/// The sample rate of the S/PDIF input, estimated from the symbol width that the receiver measures, and rounded to the
+/// nearest standard rate. `None`, if the receiver is not synchronized.
+pub fn spdif_sample_rate_hz() -> Option<u32> {
+    // The duration of 5 symbols in cycles of the kernel clock.
+    let width5 = embassy_stm32::pac::SPDIFRX1.sr().read().width() as u32;
+    if width5 == 0 {
+        return None;
+    }
+
+    // A frame of two sub-frames takes 128 symbols (bi-phase mark code).
+    let estimate_hz = 5 * SPDIFRX_KERNEL_CLOCK_HZ / (128 * width5);
+    SPDIF_SAMPLE_RATES_HZ
+        .into_iter()
+        .min_by_key(|rate_hz| rate_hz.abs_diff(estimate_hz))
+}
+
+/// The sample rate of a source. The internal clock of the SAIs runs at [`SAMPLE_RATE_HZ`], while S/PDIF provides its
+/// own.
+fn source_sample_rate_hz(source: AudioSource) -> u32 {
+    match source {
+        AudioSource::Spdif => spdif_sample_rate_hz().unwrap_or(SAMPLE_RATE_HZ),
+        _ => SAMPLE_RATE_HZ,
+    }
+}
+
+/// The master clock divider of both SAIs at a sample rate, which must be [`SAMPLE_RATE_HZ`].
+///
+/// The signal processing is designed for the sample rate, which also divides the internal SAI clock.
+fn master_clock_divider(sample_rate_hz: u32) -> Result<sai::MasterClockDivider, SetupError> {
+    match sample_rate_hz {
+        SAMPLE_RATE_HZ => Ok(sai::MasterClockDivider::Div2),
+        _ => Err(SetupError::UnsupportedSampleRate(sample_rate_hz)),
+    }
+}
+
+/// Select the kernel clock of SAI4 A for a source: the recovered clock of S/PDIF, or the internal clock.
+fn select_sai_clock(audio_source: AudioSource) {
     let clk_source = match audio_source {
         AudioSource::Spdif => embassy_stm32::pac::rcc::vals::Saiasel::_RESERVED_5,
         _ => embassy_stm32::pac::rcc::vals::Saiasel::PLL1_Q,
@@ -71,6 +252,46 @@ fn new_sai_amp_rpi<'d>(
     embassy_stm32::pac::RCC.d3ccipr().modify(|w| {
         w.set_sai4asel(clk_source);
     });
+}
+
+/// The length of the amplifier SAI's ring buffer for a source, out of the whole write buffer.
+///
+/// In low-latency mode, the ring buffer holds two blocks for the Raspberry Pi, such that the DMA runs block by block.
+fn sai_amp_write_length(buffer_length: usize, audio_source: AudioSource, latency_mode: LatencyMode) -> usize {
+    match (latency_mode, audio_source) {
+        (LatencyMode::Low, AudioSource::Rpi) => 2 * latency::LOW_FRAME_COUNT * OUTPUT_CHANNEL_COUNT,
+        _ => buffer_length,
+    }
+}
+
+/// Set up the SAIs of the amplifiers and the Raspberry Pi for a source, at its sample rate.
+///
+/// Fails with [`SetupError::UnsupportedSampleRate`] for rates other than [`SAMPLE_RATE_HZ`], before the interfaces
+/// are touched.
+pub fn new_sai_amp_rpi<'d>(
+    resources: &'d mut Sai4Resources,
+    sai_amp_write_buffer: &'d mut [u32],
+    sai_rpi_read_buffer: &'d mut [u32],
+    sample_rate_hz: u32,
+    audio_source: AudioSource,
+    latency_mode: LatencyMode,
+) -> Result<(Sai4<'d>, Sai4<'d>), SetupError> {
+    let master_clock_divider = master_clock_divider(sample_rate_hz)?;
+    select_sai_clock(audio_source);
+
+    // In low-latency mode, the ring buffers of the Raspberry Pi hold two blocks, such that the DMA runs block by block.
+    let amp_write_length = sai_amp_write_length(sai_amp_write_buffer.len(), audio_source, latency_mode);
+    let (sai_amp_write_buffer, sai_rpi_read_buffer) = match latency_mode {
+        LatencyMode::Low => {
+            let rpi_read_length = 2 * latency::LOW_FRAME_COUNT * INPUT_CHANNEL_COUNT;
+
+            (
+                &mut sai_amp_write_buffer[..amp_write_length],
+                &mut sai_rpi_read_buffer[..rpi_read_length],
+            )
+        }
+        LatencyMode::Normal => (sai_amp_write_buffer, sai_rpi_read_buffer),
+    };
 
     let (sai_amp, sai_rpi) = sai::split_subblocks(&mut resources.sai);
 
@@ -95,11 +316,7 @@ fn new_sai_amp_rpi<'d>(
                 assert_eq!(SAMPLE_WIDTH_BIT, 32);
                 config.data_size = sai::DataSize::Data32;
                 config.frame_length = (OUTPUT_CHANNEL_COUNT * 32) as u8;
-
-                match sample_rate_hz {
-                    SAMPLE_RATE_HZ => config.master_clock_divider = sai::MasterClockDivider::Div2,
-                    _ => panic!("Unsupported SAI sample rate."),
-                }
+                config.master_clock_divider = master_clock_divider;
             }
         };
 
@@ -126,11 +343,7 @@ fn new_sai_amp_rpi<'d>(
         config.frame_sync_active_level_length = sai::word::U7(SAMPLE_WIDTH_BIT as u8);
         config.bit_order = sai::BitOrder::MsbFirst;
         config.mute_value = sai::MuteValue::LastValue;
-
-        match sample_rate_hz {
-            SAMPLE_RATE_HZ => config.master_clock_divider = sai::MasterClockDivider::Div2,
-            _ => panic!("Unsupported SAI sample rate."),
-        }
+        config.master_clock_divider = master_clock_divider;
 
         sai::Sai::new_asynchronous(
             sai_rpi,
@@ -143,37 +356,217 @@ fn new_sai_amp_rpi<'d>(
         )
     };
 
-    (sai_amp_driver, sai_rpi_driver)
+    Ok((sai_amp_driver, sai_rpi_driver))
 }
 
-fn process(
-    samples: &[u32],
-    processed_samples: &mut Vec<u32, { 2 * MAX_SAMPLE_COUNT }>,
-    filters: &mut [AudioFilter; OUTPUT_CHANNEL_COUNT],
-    gain_left: f32,
-    gain_right: f32,
-) {
-    for (index, sample) in samples.iter().enumerate() {
-        let sample = audio_filter::sample_to_f32(*sample);
-
-        if index % 2 == 0 {
-            // Left channel
-            processed_samples
-                .push(audio_filter::sample_to_u32(filters[0].run(sample) * gain_left))
-                .unwrap();
-            processed_samples
-                .push(audio_filter::sample_to_u32(filters[1].run(sample) * gain_left))
-                .unwrap();
+/// Reconfigure the SAIs of the amplifiers and the Raspberry Pi for a source in place, while their drivers (as set up
+/// by [`new_sai_amp_rpi`] for no source) stay, with their pins and DMA ring buffers.
+///
+/// Both sub-blocks are disabled, the kernel clock is switched, and the format of the amplifier sub-block is rewritten
+/// as [`new_sai_amp_rpi`] sets it up for the source. Then the sub-blocks are enabled again, as far as they were, with
+/// flushed FIFOs. The amplifier sub-block must not have been written since its setup, and the length of its ring
+/// buffer must fit the source (see [`sai_amp_write_length`]).
+///
+/// Fails like [`new_sai_amp_rpi`], before the interfaces are touched.
+fn reconfigure_sai_amp_rpi(sample_rate_hz: u32, audio_source: AudioSource) -> Result<(), SetupError> {
+    use embassy_stm32::pac::sai::vals;
+
+    let master_clock_divider = master_clock_divider(sample_rate_hz)?;
+    let (sai_amp, sai_rpi) = (embassy_stm32::pac::SAI4.ch(0), embassy_stm32::pac::SAI4.ch(1));
+    let enabled = [sai_amp, sai_rpi].map(|sub_block| sub_block.cr1().read().saien());
+
+    // The enable bits clear, once the current frames are complete.
+    for sub_block in [sai_amp, sai_rpi] {
+        sub_block.cr1().modify(|w| w.set_saien(false));
+        while sub_block.cr1().read().saien() {}
+    }
+
+    select_sai_clock(audio_source);
+
+    let (data_size, sample_width_bit, master_clock_divider) = match audio_source {
+        AudioSource::Spdif => (vals::Ds::BIT16, 16, None),
+        // The dividers are numbered by their MCKDIV values.
+        _ => (vals::Ds::BIT32, SAMPLE_WIDTH_BIT, Some(master_clock_divider as u8)),
+    };
+
+    sai_amp.cr1().modify(|w| {
+        w.set_ds(data_size);
+        w.set_nodiv(if master_clock_divider.is_none() {
+            vals::Nodiv::NO_DIV
         } else {
-            // Right channel
-            processed_samples
-                .push(audio_filter::sample_to_u32(filters[2].run(sample) * gain_right))
-                .unwrap();
-            processed_samples
-                .push(audio_filter::sample_to_u32(filters[3].run(sample) * gain_right))
-                .unwrap();
+            vals::Nodiv::MASTER_CLOCK
+        });
+        w.set_mckdiv(master_clock_divider.unwrap_or(0));
+    });
+    sai_amp
+        .frcr()
+        .modify(|w| w.set_frl((OUTPUT_CHANNEL_COUNT * sample_width_bit - 1) as u8));
+
+    for (sub_block, enabled) in [sai_amp, sai_rpi].into_iter().zip(enabled) {
+        sub_block.cr2().modify(|w| w.set_fflush(true));
+        sub_block.clrfr().write(|w| w.0 = 0xFFFF_FFFF);
+
+        if enabled {
+            sub_block.cr1().modify(|w| w.set_saien(true));
         }
     }
+
+    Ok(())
+}
+
+/// Process a sample block of the active source into samples for the amplifiers, and report the output levels.
+///
+/// Returns the number of processed samples, at the start of the buffer. Runs from ITCM (see [`crate::tcm`]).
+#[inline(never)]
+#[link_section = ".itcm.process"]
+fn process(
+    pipeline: &mut Pipeline<INPUT_CHANNEL_COUNT, OUTPUT_CHANNEL_COUNT>,
+    sample_block: &SampleBlock,
+    processed_samples: &mut [u32; MAX_OUTPUT_SAMPLE_COUNT],
+    mut chain: Option<&mut Chain>,
+) -> usize {
+    let input_gains = match sample_block.source() {
+        AudioSource::Usb => CONTROL.usb_gains(),
+        _ => [1.0; INPUT_CHANNEL_COUNT],
+    };
+
+    let trims = calibration::output_trims();
+    let throttle_gains = thermal::throttle_gains();
+    let adjustment_gains = potentiometer::adjustment_gains();
+    let output_gains =
+        core::array::from_fn(|channel| trims[channel] * throttle_gains[channel] * adjustment_gains[channel]);
+
+    let levels = pipeline.process_frames(
+        sample_block.samples(),
+        processed_samples,
+        input_gains,
+        output_gains,
+        CONTROL.gain(),
+        |frame| {
+            if let Some(chain) = &mut chain {
+                chain.run(frame);
+            }
+        },
+    );
+
+    // Samples beyond full-scale are clipped by the conversion.
+    if levels.clipped() {
+        CONTROL.count_clipped_block();
+    }
+
+    CONTROL.set_meter_levels(&levels.peak_levels);
+    output_power::update(&levels.mean_squares, levels.frame_count);
+    dc_protection::update(&levels.means, levels.frame_count);
+
+    levels.sample_count()
+}
+
+/// The receiver of the sample blocks of the inputs that are served by other tasks (S/PDIF).
+pub type SampleReceiver = channel::Receiver<'static, CriticalSectionRawMutex, SampleBlock, SAMPLE_BLOCK_COUNT>;
+
+/// The reading side of the USB ring.
+pub type UsbConsumer = Consumer<'static, USB_RING_SIZE>;
+
+/// An input of the audio pipeline.
+pub enum Input {
+    /// USB audio, whose samples arrive in the USB ring from the streaming task (see [`crate::usb_audio`]).
+    Usb(UsbConsumer),
+    /// S/PDIF, whose blocks arrive from the S/PDIF task.
+    Spdif(SampleReceiver),
+    /// The Raspberry Pi header, which the pipeline reads on the SAI of its output.
+    Rpi,
+}
+
+/// The bit of a source in a set of sources.
+fn source_bit(source: AudioSource) -> u8 {
+    1 << source as u8
+}
+
+/// A builder of the audio routing, from its inputs, the signal processing, and the output.
+///
+/// ```ignore
+/// AudioPipeline::new()
+///     .input(Input::Usb(usb_consumer))
+///     .input(Input::Spdif(audio_channel.receiver()))
+///     .input(Input::Rpi)
+///     .dsp(chain)
+///     .output(sai4_resources)
+///     .spawn(audio_routing::start_executor())
+/// ```
+pub struct AudioPipeline {
+    /// The sources of all inputs.
+    sources: u8,
+    usb: Option<UsbConsumer>,
+    receiver: Option<SampleReceiver>,
+    chain: Option<&'static mut Chain>,
+    output: Option<Sai4Resources>,
+}
+
+impl Default for AudioPipeline {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AudioPipeline {
+    /// Create a pipeline without inputs, signal processing, or output.
+    pub const fn new() -> Self {
+        AudioPipeline {
+            sources: 0,
+            usb: None,
+            receiver: None,
+            chain: None,
+            output: None,
+        }
+    }
+
+    /// Add an input, whose source may then be selected.
+    pub fn input(mut self, input: Input) -> Self {
+        let source = match input {
+            Input::Usb(usb) => {
+                self.usb = Some(usb);
+                AudioSource::Usb
+            }
+            Input::Spdif(receiver) => {
+                self.receiver = Some(receiver);
+                AudioSource::Spdif
+            }
+            Input::Rpi => AudioSource::Rpi,
+        };
+
+        self.sources |= source_bit(source);
+        self
+    }
+
+    /// Process all output channels with a chain, which follows the changes of the signal processing configuration
+    /// (see [`crate::dsp`]).
+    pub fn dsp(mut self, chain: &'static mut Chain) -> Self {
+        self.chain = Some(chain);
+        self
+    }
+
+    /// Play on the amplifier SAI of SAI4, which also reads the Raspberry Pi header.
+    pub fn output(mut self, sai4_resources: Sai4Resources) -> Self {
+        self.output = Some(sai4_resources);
+        self
+    }
+
+    /// Spawn the audio routing task on the audio executor (see [`start_executor`]).
+    ///
+    /// Panics, if the pipeline has no output.
+    pub fn spawn(self, spawner: SendSpawner) -> Result<(), SpawnError> {
+        let Some(output) = self.output else {
+            panic!("The audio pipeline has no output")
+        };
+
+        spawner.spawn(audio_routing_task(
+            self.sources,
+            self.usb,
+            self.receiver,
+            self.chain,
+            output,
+        ))
+    }
 }
 
 /// The task that performs audio playback.
@@ -183,117 +576,235 @@ fn process(
 /// - Signal processing
 /// - Playback on SAI
 #[embassy_executor::task]
-pub async fn audio_routing_task(
-    mut filters: [AudioFilter<'static>; OUTPUT_CHANNEL_COUNT],
+async fn audio_routing_task(
+    sources: u8,
+    mut usb: Option<UsbConsumer>,
+    receiver: Option<SampleReceiver>,
+    mut chain: Option<&'static mut Chain>,
     mut sai4_resources: Sai4Resources,
-    audio_channel: channel::Receiver<'static, NoopRawMutex, SampleBlock, SAMPLE_BLOCK_COUNT>,
-    mut led_usb: Output<'static>,
-    mut led_rpi: Output<'static>,
-    mut led_spdif: Output<'static>,
 ) {
+    let mut led_usb = Led::Function(LedFunction::UsbSource);
+    let mut led_rpi = Led::Function(LedFunction::RpiSource);
+    let mut led_spdif = Led::Function(LedFunction::SpdifSource);
+
     debug!("Amplifier SAI write buffer: {} samples", SAI_AMP_SAMPLE_COUNT);
     debug!("Default SAI read buffer: {} samples", DEFAULT_SAMPLE_COUNT);
 
-    let sai_amp_write_buffer: &mut [u32] = unsafe {
-        SAI_AMP_WRITE_BUFFER.initialize_all_copied(0);
-        let (ptr, len) = SAI_AMP_WRITE_BUFFER.get_ptr_len();
-        core::slice::from_raw_parts_mut(ptr, len)
-    };
-
-    let sai_rpi_read_buffer: &mut [u32] = unsafe {
-        SAI_RPI_READ_BUFFER.initialize_all_copied(0);
-        let (ptr, len) = SAI_RPI_READ_BUFFER.get_ptr_len();
-        core::slice::from_raw_parts_mut(ptr, len)
-    };
+    let sai_amp_write_buffer = dma_buffers::sai_amp_write();
+    let sai_amp_buffer_length = sai_amp_write_buffer.len();
+    let sai_rpi_read_buffer = dma_buffers::sai_rpi_read();
 
     let mut source = AudioSource::None;
     let mut new_source: AudioSource;
+    let mut latency_mode = latency::mode();
+    // The source that failed the setup of the interfaces, and the time of the failure.
+    let mut failed_setup: Option<(AudioSource, Instant)> = None;
+
+    let ramp_frame_count = (SOFT_MUTE_TIME.as_micros() * SAMPLE_RATE_HZ as u64 / 1_000_000) as u32;
+    let mut pipeline = Pipeline::new(ROUTING, ramp_frame_count);
 
-    let (mut sai_amp, mut sai_rpi) = new_sai_amp_rpi(
+    // Every block is processed into the same buffer in DTCM, which holds the samples of all output channels.
+    #[link_section = ".dtcm"]
+    static PROCESSED_SAMPLES: StaticCell<[u32; MAX_OUTPUT_SAMPLE_COUNT]> = StaticCell::new();
+    let processed_samples = PROCESSED_SAMPLES.init([0; MAX_OUTPUT_SAMPLE_COUNT]);
+
+    // Without a source, the SAIs run from the internal clock at the supported rate.
+    let (mut sai_amp, mut sai_rpi) = unwrap!(new_sai_amp_rpi(
         &mut sai4_resources,
         sai_amp_write_buffer,
         sai_rpi_read_buffer,
         SAMPLE_RATE_HZ,
         source,
-    );
-
-    let mut usb_gain = (0.0, 0.0);
-    let mut pot_gain = (0.0, 0.0);
+        latency_mode,
+    ));
 
     sai_rpi.start().unwrap();
+    startup::complete(startup::Step::Pipeline);
 
     loop {
-        // Get `Some` sample block from the Raspberry Pi header or audio channel, or `None`,
+        watchdog::check_in(Task::Audio);
+
+        // The reception of a marked sample in the block from the USB ring.
+        #[cfg(feature = "latency_probe")]
+        let mut usb_received = None;
+
+        // Get `Some` sample block from the Raspberry Pi header, the USB ring, or the audio channel, or `None`,
         // in case of errors when writing to the amplifier SAI.
         let sample_block = {
             let sai_rpi_read_fut = async {
-                let mut rpi_data = [0u32; DEFAULT_SAMPLE_COUNT];
+                let mut rpi_data = RpiSampleBlock::new();
+                rpi_data
+                    .resize(latency::frame_count(latency_mode) * INPUT_CHANNEL_COUNT, 0)
+                    .unwrap();
                 let read_error = sai_rpi.read(&mut rpi_data).await.is_err();
 
+                if read_error && source == AudioSource::Rpi {
+                    STATS.count_overrun();
+                    event_log::record(EventKind::SourceError, AudioSource::Rpi as u8);
+                }
+
                 if sai_rpi.is_muted().unwrap() || read_error {
                     None
                 } else {
                     Some(SampleBlock::Rpi(rpi_data))
                 }
             };
-            let audio_channel_receive_fut = async { Some(audio_channel.receive().await) };
+            let usb_read_fut = async {
+                let Some(usb) = &mut usb else {
+                    return core::future::pending().await;
+                };
+
+                while usb.len() < DEFAULT_SAMPLE_COUNT {
+                    USB_SAMPLES_SIGNAL.wait().await;
+                }
+
+                let mut usb_data = UsbSampleBlock::new();
+                usb_data.resize(DEFAULT_SAMPLE_COUNT, 0).unwrap();
+
+                #[cfg(feature = "latency_probe")]
+                let position = usb.position();
+                usb.pop(&mut usb_data);
+
+                #[cfg(feature = "latency_probe")]
+                {
+                    usb_received = latency_probe::usb_read(position, usb_data.len());
+                }
+                SampleBlock::Usb(usb_data)
+            };
+            let audio_channel_receive_fut = async {
+                match receiver {
+                    Some(receiver) => receiver.receive().await,
+                    None => core::future::pending().await,
+                }
+            };
+            let input_receive_fut = async {
+                match select(usb_read_fut, audio_channel_receive_fut).await {
+                    Either::First(sample_block) | Either::Second(sample_block) => Some(sample_block),
+                }
+            };
             let sai_write_error_fut = sai_amp.wait_write_error();
 
             match source {
                 AudioSource::None => {
-                    match select3(audio_channel_receive_fut, sai_rpi_read_fut, sai_write_error_fut).await {
-                        Either3::First(sample_block) => sample_block,
-                        Either3::Second(sample_block) => sample_block,
-                        Either3::Third(_) => None,
+                    // Idles without source, but still checks in with the watchdog.
+                    match select4(
+                        input_receive_fut,
+                        sai_rpi_read_fut,
+                        sai_write_error_fut,
+                        Timer::after(watchdog::CHECK_IN_INTERVAL),
+                    )
+                    .await
+                    {
+                        Either4::First(sample_block) => sample_block,
+                        Either4::Second(sample_block) => sample_block,
+                        Either4::Third(_) => {
+                            STATS.count_underrun();
+                            None
+                        }
+                        Either4::Fourth(_) => None,
                     }
                 }
                 AudioSource::Rpi => match select(sai_rpi_read_fut, sai_write_error_fut).await {
                     Either::First(sample_block) => sample_block,
-                    Either::Second(_) => None,
+                    Either::Second(_) => {
+                        STATS.count_underrun();
+                        event_log::record(EventKind::Underrun, source as u8);
+                        None
+                    }
                 },
-                _ => match select(audio_channel_receive_fut, sai_write_error_fut).await {
+                _ => match select(input_receive_fut, sai_write_error_fut).await {
                     Either::First(sample_block) => sample_block,
-                    Either::Second(_) => None,
+                    Either::Second(_) => {
+                        STATS.count_underrun();
+                        event_log::record(EventKind::Underrun, source as u8);
+                        None
+                    }
                 },
             }
         };
+        // The USB ring counts in blocks of 1 ms.
+        let usb_block_count = usb.as_ref().map_or(0, |usb| usb.len() / DEFAULT_SAMPLE_COUNT);
+        CONTROL.set_buffer_fill(receiver.map_or(0, |receiver| receiver.len()) + usb_block_count);
+
+        if let Some(sample_block) = &sample_block {
+            auto_standby::detect_signal(sample_block);
+        }
 
-        new_source = match (&sample_block, source) {
-            (Some(SampleBlock::Spdif(_)), AudioSource::None) => AudioSource::Spdif,
-            (Some(SampleBlock::Usb(_)), AudioSource::None) => AudioSource::Usb,
-            (Some(SampleBlock::Rpi(_)), AudioSource::None) => AudioSource::Rpi,
-            (None, _) => AudioSource::None,
-            _ => source,
+        // A requested self-test releases the source.
+        new_source = if self_test::requested() {
+            AudioSource::None
+        } else {
+            // A source that failed the setup is held off until the retry.
+            let held_off = |source| {
+                failed_setup.is_some_and(|(failed, time)| failed == source && time.elapsed() < SETUP_RETRY_DELAY)
+            };
+
+            select_source(source, sample_block.as_ref().map(|block| block.source()), |source| {
+                sources & source_bit(source) != 0 && CONTROL.source_allowed(source) && !held_off(source)
+            })
         };
 
-        // Reset SAI if the source changes.
+        // Reset SAI if the source or the latency mode changes.
         // The source is reset to `None` in case of errors, thus also resetting the SAI.
-        if source != new_source {
-            source = new_source;
+        if source != new_source || latency_mode != latency::mode() {
+            // The idle SAIs are reconfigured in place for a new source, if their ring buffers fit it.
+            let in_place = source == AudioSource::None
+                && latency_mode == latency::mode()
+                && sai_amp_write_length(sai_amp_buffer_length, new_source, latency_mode)
+                    == sai_amp_write_length(sai_amp_buffer_length, AudioSource::None, latency_mode);
 
-            drop(sai_amp);
-            drop(sai_rpi);
-
-            (sai_amp, sai_rpi) = new_sai_amp_rpi(
-                &mut sai4_resources,
-                sai_amp_write_buffer,
-                sai_rpi_read_buffer,
-                SAMPLE_RATE_HZ,
-                source,
-            );
+            source = new_source;
+            latency_mode = latency::mode();
+
+            if in_place {
+                // A source at an unsupported rate stays without source, for which the SAIs are set up.
+                if let Err(error) = reconfigure_sai_amp_rpi(source_sample_rate_hz(source), source) {
+                    log!(warn, "Failed to set up source {:?}: {:?}", source, error);
+                    event_log::record(EventKind::SourceError, source as u8);
+                    failed_setup = Some((source, Instant::now()));
+                    source = AudioSource::None;
+                }
+            } else {
+                drop(sai_amp);
+                drop(sai_rpi);
+                STATS.count_sai_restart();
+
+                // A source at an unsupported rate falls back to no source, which always sets up.
+                (sai_amp, sai_rpi) = loop {
+                    match new_sai_amp_rpi(
+                        &mut sai4_resources,
+                        sai_amp_write_buffer,
+                        sai_rpi_read_buffer,
+                        source_sample_rate_hz(source),
+                        source,
+                        latency_mode,
+                    ) {
+                        Ok(sais) => break sais,
+                        Err(error) => {
+                            log!(warn, "Failed to set up source {:?}: {:?}", source, error);
+                            event_log::record(EventKind::SourceError, source as u8);
+                            failed_setup = Some((source, Instant::now()));
+                            source = AudioSource::None;
+                        }
+                    }
+                };
+            }
 
-            SAI_ACTIVE_SIGNAL.signal(source);
-            AMP_SETUP_SIGNAL.wait().await;
+            amplifiers::request(source);
+            amplifiers::settled().await;
+            CONTROL.set_active_source(source);
+            output_power::reset();
+            dc_protection::reset();
 
-            for filter in filters.as_mut() {
-                filter.reset_state();
+            if let Some(chain) = &mut chain {
+                chain.reset_state();
             }
 
             for led in [&mut led_usb, &mut led_rpi, &mut led_spdif] {
                 led.set_low();
             }
 
-            info!("New source: {}", source);
+            log!(info, "New source: {:?}, latency mode: {:?}", source, latency_mode);
             match source {
                 AudioSource::Spdif => led_spdif.set_high(),
                 AudioSource::Usb => led_usb.set_high(),
@@ -301,58 +812,92 @@ pub async fn audio_routing_task(
                 _ => (),
             }
 
-            audio_channel.clear();
-            sai_rpi.start().unwrap();
+            if let Some(receiver) = receiver {
+                receiver.clear();
+            }
+            if let Some(usb) = &mut usb {
+                usb.clear();
+            }
+
+            // Reconfigured SAIs keep running.
+            if !in_place {
+                sai_rpi.start().unwrap();
+            }
         }
 
-        // Only process/play, if `Some` sample block was received.
-        let Some(sample_block) = sample_block else { continue };
+        // The self-test takes over the idle interfaces, and leaves them to be reset.
+        if source == AudioSource::None && self_test::requested() {
+            self_test::run(&mut sai_amp, &mut sai_rpi).await;
 
-        let mut processed_samples: Vec<u32, { 2 * MAX_SAMPLE_COUNT }> = Vec::new();
-        match (sample_block, source) {
-            (SampleBlock::Spdif(samples), AudioSource::Spdif) => {
-                if let Some(gain) = POT_GAIN_SIGNAL.try_take() {
-                    pot_gain = (gain, gain);
-                }
+            drop(sai_amp);
+            drop(sai_rpi);
+            STATS.count_sai_restart();
 
-                process(
-                    samples.as_slice(),
-                    &mut processed_samples,
-                    &mut filters,
-                    pot_gain.0,
-                    pot_gain.1,
-                );
-
-                // 16 bit playback in 32 bit DMA mode.
-                for sample in processed_samples.iter_mut() {
-                    *sample >>= 16;
-                }
+            (sai_amp, sai_rpi) = unwrap!(new_sai_amp_rpi(
+                &mut sai4_resources,
+                sai_amp_write_buffer,
+                sai_rpi_read_buffer,
+                SAMPLE_RATE_HZ,
+                source,
+                latency_mode,
+            ));
+
+            if let Some(receiver) = receiver {
+                receiver.clear();
             }
-            (SampleBlock::Usb(samples), AudioSource::Usb) => {
-                if let Some(gain) = USB_GAIN_SIGNAL.try_take() {
-                    usb_gain = gain;
-                }
+            if let Some(usb) = &mut usb {
+                usb.clear();
+            }
+            sai_rpi.start().unwrap();
+            continue;
+        }
 
-                process(
-                    samples.as_slice(),
-                    &mut processed_samples,
-                    &mut filters,
-                    usb_gain.0,
-                    usb_gain.1,
-                );
+        // Apply changes to the signal processing configuration.
+        if let (Some(chain), Some(_)) = (&mut chain, dsp::DSP_CONFIG_CHANGED_SIGNAL.try_take()) {
+            for (channel, filter_config) in dsp::dsp_config().iter().enumerate() {
+                if let Err(error) = chain.configure(channel, filter_config, SAMPLE_RATE_HZ) {
+                    log!(warn, "Failed to apply filter configuration: {:?}", error);
+                }
             }
-            (SampleBlock::Rpi(samples), AudioSource::Rpi) => {
-                process(samples.as_slice(), &mut processed_samples, &mut filters, 1.0, 1.0);
+        }
+
+        // Load new FIR taps.
+        if let (Some(chain), Some(_)) = (&mut chain, dsp::FIR_TAPS_CHANGED_SIGNAL.try_take()) {
+            for channel in 0..OUTPUT_CHANNEL_COUNT {
+                dsp::with_fir_taps(channel, |taps| chain.set_fir_taps(channel, taps));
             }
-            _ => {
-                trace!("Drop sample block with source {}", source);
-                continue;
+        }
+
+        // Only process/play, if `Some` sample block was received.
+        let Some(sample_block) = sample_block else { continue };
+
+        if sample_block.source() != source {
+            log!(trace, "Drop sample block with source {:?}", source);
+            STATS.count_dropped_block();
+            continue;
+        }
+
+        let frame_count = sample_block.samples().len() / INPUT_CHANNEL_COUNT;
+        let sample_count = cpu_load::measure_dsp(frame_count, || {
+            process(&mut pipeline, &sample_block, processed_samples, chain.as_deref_mut())
+        });
+        let samples = &mut processed_samples[..sample_count];
+
+        // 16 bit playback in 32 bit DMA mode.
+        if source == AudioSource::Spdif {
+            for sample in samples.iter_mut() {
+                *sample >>= 16;
             }
-        };
+        }
 
         // Ignore errors here, `wait_write_error()` will catch them in the next loop iteration.
-        if sai_amp.write(&processed_samples).await.is_err() {
-            debug!("Spurious SAI write error");
+        if sai_amp.write(samples).await.is_err() {
+            log!(debug, "Spurious SAI write error");
         };
+
+        #[cfg(feature = "latency_probe")]
+        if let Some(received) = usb_received {
+            latency_probe::submitted(received);
+        }
     }
 }
@@ -0,0 +1,124 @@
+//! Automatic standby after inactivity, and waking up when a source delivers a signal again.
+//!
+//! Sources often keep delivering samples without signal: USB hosts stream silence, and S/PDIF transmitters stay
+//! locked. Therefore, the audio routing checks every received sample block for a signal above [`SIGNAL_LEVEL`] (see
+//! [`detect_signal`]). Once there was no signal for the auto-standby delay (shell `auto-standby`), the device enters
+//! standby, which stops the playback SAI and shuts down the amplifiers.
+//!
+//! The audio routing keeps receiving USB and S/PDIF sample blocks in standby. The first one with a signal leaves an
+//! automatically entered standby, such that playback resumes within the time for setting up the amplifiers. A standby
+//! that was requested otherwise (e.g. by the remote control) is only left on request. Samples from the Raspberry Pi
+//! are only read while it plays, so it does not wake the device.
+//!
+//! The delay is not stored with the settings; a startup script can configure it (see [`crate::startup_script`]).
+use core::sync::atomic::{AtomicBool, AtomicU16, AtomicU32, Ordering};
+
+use audio::audio_filter;
+use embassy_time::{Duration, Instant, Ticker};
+
+use crate::control::CONTROL;
+use crate::*;
+
+/// The sample magnitude (out of 1) that counts as signal (-80 dBFS).
+pub const SIGNAL_LEVEL: f32 = 1e-4;
+
+/// The default time without signal, after which the device enters standby.
+pub const DEFAULT_DELAY: Duration = Duration::from_secs(20 * 60);
+
+/// The maximum auto-standby delay.
+pub const MAX_DELAY: Duration = Duration::from_secs(240 * 60);
+
+/// The interval between checks for inactivity.
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The auto-standby delay in minutes, or `0`, if automatic standby is disabled.
+static DELAY_MIN: AtomicU16 = AtomicU16::new((DEFAULT_DELAY.as_secs() / 60) as u16);
+
+/// The uptime in seconds, at which the last signal was detected, or activity was otherwise noticed.
+static LAST_ACTIVITY_S: AtomicU32 = AtomicU32::new(0);
+
+/// Whether the device is in a standby that was entered automatically.
+static AUTO_STANDBY: AtomicBool = AtomicBool::new(false);
+
+/// The auto-standby delay, or `None`, if automatic standby is disabled.
+pub fn delay() -> Option<Duration> {
+    match DELAY_MIN.load(Ordering::Relaxed) {
+        0 => None,
+        delay_min => Some(Duration::from_secs(delay_min as u64 * 60)),
+    }
+}
+
+/// Set the auto-standby delay (whole minutes, up to [`MAX_DELAY`]), or disable automatic standby with `None`.
+pub fn set_delay(delay: Option<Duration>) {
+    let delay_min = delay.map_or(0, |delay| (delay.min(MAX_DELAY).as_secs() / 60).max(1));
+    DELAY_MIN.store(delay_min as u16, Ordering::Relaxed);
+    note_activity();
+}
+
+/// Whether the device is in a standby that was entered automatically.
+pub fn auto_standby() -> bool {
+    AUTO_STANDBY.load(Ordering::Relaxed)
+}
+
+/// Restart the inactivity timer.
+fn note_activity() {
+    LAST_ACTIVITY_S.store(Instant::now().as_secs() as u32, Ordering::Relaxed);
+}
+
+/// Check a sample block for a signal. A signal restarts the inactivity timer, and leaves an automatically entered
+/// standby.
+pub fn detect_signal(sample_block: &SampleBlock) {
+    let samples = match sample_block {
+        SampleBlock::Usb(samples) => samples.as_slice(),
+        SampleBlock::Spdif(samples) => samples.as_slice(),
+        SampleBlock::Rpi(samples) => samples.as_slice(),
+    };
+
+    if !samples
+        .iter()
+        .any(|sample| audio_filter::sample_to_f32(*sample).abs() > SIGNAL_LEVEL)
+    {
+        return;
+    }
+
+    note_activity();
+
+    if AUTO_STANDBY.swap(false, Ordering::Relaxed) {
+        log!(info, "Signal detected, leaving standby");
+        CONTROL.set_standby(false);
+    }
+}
+
+/// Enters standby after the auto-standby delay without signal.
+#[embassy_executor::task]
+pub async fn auto_standby_task() {
+    let mut ticker = Ticker::every(CHECK_INTERVAL);
+    let mut standby = CONTROL.standby();
+
+    note_activity();
+
+    loop {
+        ticker.next().await;
+
+        // Leaving standby by other means restarts the timer.
+        if standby && !CONTROL.standby() {
+            AUTO_STANDBY.store(false, Ordering::Relaxed);
+            note_activity();
+        }
+        standby = CONTROL.standby();
+
+        let Some(delay) = delay() else {
+            continue;
+        };
+
+        let inactive_s = (Instant::now().as_secs() as u32).saturating_sub(LAST_ACTIVITY_S.load(Ordering::Relaxed));
+        if standby || Duration::from_secs(inactive_s as u64) < delay {
+            continue;
+        }
+
+        log!(info, "No signal for {} min, entering standby", delay.as_secs() / 60);
+        AUTO_STANDBY.store(true, Ordering::Relaxed);
+        CONTROL.set_standby(true);
+        standby = true;
+    }
+}
@@ -0,0 +1,141 @@
+//! Backups of the persisted configuration: the settings, the presets, and the startup script.
+//!
+//! Backups are read and written by host tools on the bulk endpoints (see [`crate::bulk_transfer`]), in the format
+//! of [`protocol::backup`]. Restoring checks the complete backup first, such that an invalid one changes nothing.
+//! Then it applies and stores the settings, and replaces the stored presets and startup script. Note that erasing
+//! their storage sectors stalls playback for up to seconds.
+use protocol::backup::{self as backup_format, Section, Writer};
+use protocol::settings as settings_format;
+
+use crate::presets::{self, BANK_SIZE};
+use crate::settings;
+use crate::settings_store;
+use crate::startup_script::{self, MAX_SCRIPT_SIZE};
+use crate::storage;
+use crate::*;
+
+/// The maximum size of a backup.
+pub const MAX_BACKUP_SIZE: usize = backup_format::HEADER_SIZE
+    + 3 * backup_format::SECTION_HEADER_SIZE
+    + settings_format::MAX_ENCODED_SIZE
+    + BANK_SIZE
+    + MAX_SCRIPT_SIZE;
+
+/// An error of creating or restoring a backup.
+#[derive(Clone, Copy, PartialEq, Debug, defmt::Format)]
+pub enum Error {
+    /// The backup is malformed, or does not fit into the buffer.
+    Format(backup_format::Error),
+    /// The settings could not be encoded or decoded.
+    Settings(settings_format::Error),
+    /// The settings result in an invalid configuration.
+    InvalidConfig,
+    /// The presets could not be read or written, or are invalid.
+    Presets(presets::Error),
+    /// The startup script is too long, or not valid text.
+    InvalidScript,
+    /// A section is missing.
+    MissingSection(Section),
+    /// The settings could not be stored.
+    SettingsStore(settings_store::Error),
+    /// The storage reported an error.
+    Storage(storage::Error),
+}
+
+impl From<backup_format::Error> for Error {
+    fn from(error: backup_format::Error) -> Self {
+        Error::Format(error)
+    }
+}
+
+impl From<settings_format::Error> for Error {
+    fn from(error: settings_format::Error) -> Self {
+        Error::Settings(error)
+    }
+}
+
+impl From<presets::Error> for Error {
+    fn from(error: presets::Error) -> Self {
+        Error::Presets(error)
+    }
+}
+
+impl From<storage::Error> for Error {
+    fn from(error: storage::Error) -> Self {
+        Error::Storage(error)
+    }
+}
+
+impl From<settings_store::Error> for Error {
+    fn from(error: settings_store::Error) -> Self {
+        Error::SettingsStore(error)
+    }
+}
+
+/// Create a backup in a buffer. Returns its length.
+pub fn create(buffer: &mut [u8]) -> Result<usize, Error> {
+    let mut writer = Writer::new(buffer)?;
+
+    writer.section(Section::Settings, |space| -> Result<usize, Error> {
+        Ok(settings::encode(space)?)
+    })?;
+
+    writer.section(Section::Presets, |space| -> Result<usize, Error> {
+        let image = space
+            .first_chunk_mut::<BANK_SIZE>()
+            .ok_or(backup_format::Error::BufferTooSmall)?;
+        presets::backup(image)?;
+        Ok(BANK_SIZE)
+    })?;
+
+    writer.section(Section::StartupScript, |space| -> Result<usize, Error> {
+        let script = startup_script::load();
+        let space = space
+            .get_mut(..script.len())
+            .ok_or(backup_format::Error::BufferTooSmall)?;
+        space.copy_from_slice(script.as_bytes());
+        Ok(script.len())
+    })?;
+
+    let length = writer.finish();
+    log!(info, "Created a backup of {} byte", length);
+
+    Ok(length)
+}
+
+/// Restore a backup, which replaces and stores the settings, presets, and startup script.
+pub fn restore(backup: &[u8]) -> Result<(), Error> {
+    let mut settings_data = None;
+    let mut presets_data = None;
+    let mut script = None;
+
+    for (tag, data) in backup_format::sections(backup)? {
+        match Section::try_from(tag) {
+            Ok(Section::Settings) => settings_data = Some(data),
+            Ok(Section::Presets) => presets_data = Some(data),
+            Ok(Section::StartupScript) => script = Some(data),
+            Err(tag) => log!(debug, "Skipped unknown backup section {}", tag),
+        }
+    }
+
+    let config = settings::decode(settings_data.ok_or(Error::MissingSection(Section::Settings))?)?;
+    let presets_data = presets_data.ok_or(Error::MissingSection(Section::Presets))?;
+    let script = script.ok_or(Error::MissingSection(Section::StartupScript))?;
+
+    if !presets::is_valid_backup(presets_data) {
+        return Err(Error::Presets(presets::Error::InvalidBackup));
+    }
+
+    let script = core::str::from_utf8(script)
+        .ok()
+        .filter(|script| script.len() <= MAX_SCRIPT_SIZE)
+        .ok_or(Error::InvalidScript)?;
+
+    config.apply().map_err(|_| Error::InvalidConfig)?;
+    settings_store::save()?;
+    presets::restore_backup(presets_data)?;
+    startup_script::store(script)?;
+
+    log!(info, "Restored a backup");
+    Ok(())
+}
@@ -0,0 +1,217 @@
+//! Micro-benchmarks of the signal processing kernels on the target, by the cycle counter of the core (DWT).
+//!
+//! With the `benchmark` feature, the kernels run once at startup, before any task starts, and with interrupts
+//! disabled. Every kernel processes a block of [`FRAME_COUNT`] frames at the largest configuration (all filter stages,
+//! all FIR taps), with the code and data placed as for playback. The fastest of [`RUN_COUNT`] runs counts, since the
+//! first run also fills the caches.
+//!
+//! The cycles per block are reported over defmt, with the limit of every kernel. A kernel above its limit is reported
+//! as a regression, e.g. when hot code runs from flash instead of ITCM, or lost its inlining. The whole pipeline must
+//! leave [`MIN_HEADROOM_PERCENT`] of the block period.
+use core::hint::black_box;
+
+use audio::audio_filter::{sample_to_f32, sample_to_u32};
+use audio::biquad_bank::CYCLES_PER_STAGE;
+use audio::filter_config::{FilterConfig, LimiterConfig, StageConfig, MAX_STAGE_COUNT};
+use audio::fir::{Fir, MAX_FIR_LENGTH};
+use audio::{AudioFilter, BiquadType};
+use audio_pipeline::pipeline::Pipeline;
+use biquad::{Biquad, Coefficients, ToHertz, Type};
+use cortex_m::peripheral::DWT;
+use defmt::{info, unwrap, warn};
+use heapless::Vec;
+use static_cell::StaticCell;
+
+use crate::audio_routing::ROUTING;
+use crate::cpu_load::CORE_CLOCK_HZ;
+use crate::*;
+
+/// The number of frames per benchmarked block (1 ms).
+pub const FRAME_COUNT: usize = DEFAULT_SAMPLE_COUNT / INPUT_CHANNEL_COUNT;
+
+/// The number of runs of every kernel.
+pub const RUN_COUNT: usize = 8;
+
+/// The headroom that the whole pipeline must leave in the block period.
+pub const MIN_HEADROOM_PERCENT: u32 = 20;
+
+/// The limit of a biquad, in cycles per sample.
+const BIQUAD_CYCLES: u32 = 30;
+
+/// The limit of a FIR, in cycles per tap and sample.
+const FIR_TAP_CYCLES: u32 = 2;
+
+/// The limit of a conversion of a sample to float and back, in cycles.
+const CONVERSION_CYCLES: u32 = 10;
+
+/// The cycles of the block period.
+const BLOCK_CYCLES: u32 = (FRAME_COUNT as u64 * CORE_CLOCK_HZ as u64 / SAMPLE_RATE_HZ as u64) as u32;
+
+/// The benchmarked configuration of every output channel.
+struct Channel<'d> {
+    filter: AudioFilter<'d>,
+    fir: Fir,
+}
+
+/// Run a kernel, and count the cycles of its fastest run.
+fn measure(mut kernel: impl FnMut()) -> u32 {
+    critical_section::with(|_| {
+        (0..RUN_COUNT)
+            .map(|_| {
+                let start = DWT::cycle_count();
+                kernel();
+                DWT::cycle_count().wrapping_sub(start)
+            })
+            .min()
+            .unwrap_or(0)
+    })
+}
+
+/// Report the cycles of a kernel against its limit. Returns whether the kernel is within its limit.
+fn report(name: &str, cycles: u32, limit: u32) -> bool {
+    let share = cycles as f32 / BLOCK_CYCLES as f32 * 100.0;
+
+    if cycles > limit {
+        warn!(
+            "Benchmark {}: {} cycles per block ({}% of the period), limit {} exceeded",
+            name, cycles, share, limit
+        );
+        false
+    } else {
+        info!(
+            "Benchmark {}: {} cycles per block ({}% of the period), limit {}",
+            name, cycles, share, limit
+        );
+        true
+    }
+}
+
+/// Process a block through the pipeline, like the playback. Runs from ITCM, like the playback (see [`crate::tcm`]).
+#[inline(never)]
+#[link_section = ".itcm.benchmark_pipeline"]
+fn run_pipeline(
+    pipeline: &mut Pipeline<INPUT_CHANNEL_COUNT, OUTPUT_CHANNEL_COUNT>,
+    samples: &[u32],
+    output: &mut [u32],
+    chain: &mut Chain,
+) {
+    pipeline.process_frames(
+        samples,
+        output,
+        [1.0; INPUT_CHANNEL_COUNT],
+        [1.0; OUTPUT_CHANNEL_COUNT],
+        1.0,
+        |frame| chain.run(frame),
+    );
+}
+
+/// Run all benchmarks, and report them. Returns whether all kernels are within their limits.
+///
+/// Takes some milliseconds, during which interrupts are not served.
+pub fn run() -> bool {
+    let sample_rate = (SAMPLE_RATE_HZ as f32).hz();
+    let coefficients = Coefficients::<f32>::from_params(Type::PeakingEQ(3.0), sample_rate, 1_000.0.hz(), 0.7).unwrap();
+
+    let mut biquads = [BiquadType::new(coefficients); MAX_STAGE_COUNT];
+    let mut channel = Channel {
+        filter: AudioFilter::new(1.0, 0, &mut biquads),
+        fir: Fir::new(),
+    };
+    unwrap!(channel.filter.configure(0.5, 4, &[coefficients; MAX_STAGE_COUNT]));
+    let taps = [1.0 / MAX_FIR_LENGTH as f32; MAX_FIR_LENGTH];
+    channel.fir.set_taps(&taps);
+
+    let samples: [u32; DEFAULT_SAMPLE_COUNT] =
+        core::array::from_fn(|index| sample_to_u32(if index % 4 < 2 { 0.25 } else { -0.25 }));
+    let mut floats = [0.0f32; FRAME_COUNT];
+    for (float, sample) in floats.iter_mut().zip(samples.iter().step_by(INPUT_CHANNEL_COUNT)) {
+        *float = sample_to_f32(*sample);
+    }
+
+    info!(
+        "Benchmark with {} frames per block, {} cycles per block period",
+        FRAME_COUNT, BLOCK_CYCLES
+    );
+    let mut passed = true;
+
+    let cycles = measure(|| {
+        for sample in samples.iter() {
+            black_box(sample_to_u32(sample_to_f32(black_box(*sample))));
+        }
+    });
+    passed &= report("conversion", cycles, CONVERSION_CYCLES * DEFAULT_SAMPLE_COUNT as u32);
+
+    let mut biquad = BiquadType::new(coefficients);
+    let cycles = measure(|| {
+        for sample in floats.iter() {
+            black_box(biquad.run(*sample));
+        }
+    });
+    passed &= report("biquad", cycles, BIQUAD_CYCLES * FRAME_COUNT as u32);
+
+    let cycles = measure(|| {
+        for sample in floats.iter() {
+            black_box(channel.filter.run(*sample));
+        }
+    });
+    passed &= report("filter", cycles, BIQUAD_CYCLES * (MAX_STAGE_COUNT * FRAME_COUNT) as u32);
+
+    let cycles = measure(|| {
+        for sample in floats.iter() {
+            black_box(channel.fir.run(*sample));
+        }
+    });
+    passed &= report("fir", cycles, FIR_TAP_CYCLES * (MAX_FIR_LENGTH * FRAME_COUNT) as u32);
+
+    let mut bank = BiquadBank::new();
+    for channel in 0..OUTPUT_CHANNEL_COUNT {
+        bank.configure(channel, &[coefficients; MAX_STAGE_COUNT]);
+    }
+
+    let cycles = measure(|| {
+        for sample in floats.iter() {
+            let mut frame = [*sample; OUTPUT_CHANNEL_COUNT];
+            bank.run(&mut frame);
+            black_box(frame);
+        }
+    });
+    let limit = CYCLES_PER_STAGE * (OUTPUT_CHANNEL_COUNT * MAX_STAGE_COUNT * FRAME_COUNT) as u32;
+    passed &= report("bank", cycles, limit);
+
+    // The playback with all stages, gain and delay, all FIR taps, and a limiter on every output channel, placed like
+    // the chain of the playback (see [`crate::audio_routing`]).
+    #[link_section = ".dtcm.benchmark_chain"]
+    static CHAIN: StaticCell<Chain> = StaticCell::new();
+    let chain = CHAIN.init(Chain::new());
+
+    let Coefficients { a1, a2, b0, b1, b2 } = coefficients;
+    let config = FilterConfig {
+        gain_db: -6.0,
+        inverted: false,
+        delay: 4,
+        stages: Vec::from_slice(&[StageConfig::Coefficients { a1, a2, b0, b1, b2 }; MAX_STAGE_COUNT]).unwrap(),
+        crossover: None,
+        limiter: Some(LimiterConfig {
+            threshold_db: -1.0,
+            release_ms: 50.0,
+        }),
+    };
+    for channel in 0..OUTPUT_CHANNEL_COUNT {
+        unwrap!(chain.configure(channel, &config, SAMPLE_RATE_HZ));
+        chain.set_fir_taps(channel, &taps);
+    }
+
+    let mut pipeline = Pipeline::new(ROUTING, 1);
+    pipeline.reset_master_gain(1.0);
+    let mut output = [0u32; OUTPUT_CHANNEL_COUNT * FRAME_COUNT];
+    let cycles = measure(|| run_pipeline(&mut pipeline, &samples, &mut output, chain));
+    passed &= report("pipeline", cycles, BLOCK_CYCLES / 100 * (100 - MIN_HEADROOM_PERCENT));
+
+    if passed {
+        info!("Benchmark passed");
+    } else {
+        warn!("Benchmark failed: performance regression");
+    }
+
+    passed
+}
@@ -0,0 +1,359 @@
+//! Transfers of large data blocks (e.g. FIR coefficients) on a pair of vendor-specific bulk endpoints.
+//!
+//! See [`protocol::bulk`] for the transfer steps. On Windows, the interface binds to the WinUSB driver automatically.
+//!
+//! Backups (see [`crate::backup`]) are created and restored in the transfer buffer, which is sized to hold them.
+use defmt::{debug, info, panic};
+use embassy_stm32::{peripherals, usb};
+use embassy_time::{with_timeout, Duration};
+use embassy_usb::driver::{Driver, Endpoint, EndpointError, EndpointIn, EndpointOut};
+use embassy_usb::msos;
+use embassy_usb::Builder;
+use grounded::uninit::GroundedArrayCell;
+use protocol::bulk::{Begin, Command, Response, Status, Target};
+use protocol::crc::{crc32, Crc32};
+use static_assertions::const_assert;
+
+use crate::backup::{self, MAX_BACKUP_SIZE};
+use crate::config_slots;
+use crate::dsp;
+use crate::parameters::PARAMETERS;
+use crate::settings;
+use crate::thermal_log;
+use crate::*;
+
+/// The maximum packet size of the bulk endpoints.
+#[cfg(not(feature = "usb_high_speed"))]
+const BULK_MAX_PACKET_SIZE: usize = 64;
+
+/// The maximum packet size of the bulk endpoints.
+#[cfg(feature = "usb_high_speed")]
+const BULK_MAX_PACKET_SIZE: usize = 512;
+
+/// The maximum size of a transfer payload.
+const TRANSFER_BUFFER_SIZE: usize = 16384;
+
+const_assert!(MAX_BACKUP_SIZE <= TRANSFER_BUFFER_SIZE);
+
+/// A transfer is discarded, if the host does not send payload data for this long.
+const TRANSFER_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The device interface GUID, by which host tools find the interface on Windows.
+const DEVICE_INTERFACE_GUIDS: &[&str] = &["{A7D5A4C1-3B8E-4F2A-9C61-5E0B2D7F8A13}"];
+
+type UsbDriver = usb::Driver<'static, peripherals::USB_OTG_HS>;
+
+/// The endpoint size that is required for the bulk transfer interface.
+pub const BULK_ENDPOINT_BUFFER_SIZE: usize = BULK_MAX_PACKET_SIZE;
+
+struct Disconnected {}
+
+impl From<EndpointError> for Disconnected {
+    fn from(val: EndpointError) -> Self {
+        match val {
+            EndpointError::BufferOverflow => panic!("Buffer overflow"),
+            EndpointError::Disabled => Disconnected {},
+        }
+    }
+}
+
+/// The state of the current transfer.
+#[derive(Clone, Copy)]
+enum Transfer {
+    /// Waiting for a transfer to begin.
+    Idle,
+    /// Receiving payload data.
+    Receiving { begin: Begin, received: usize, crc: Crc32 },
+    /// The payload was received completely, and is waiting for commit.
+    Received { begin: Begin },
+}
+
+/// The vendor-specific bulk interface.
+pub struct BulkTransfer {
+    read_ep: <UsbDriver as Driver<'static>>::EndpointOut,
+    write_ep: <UsbDriver as Driver<'static>>::EndpointIn,
+}
+
+impl BulkTransfer {
+    /// Add the bulk transfer interface to a USB device.
+    pub fn new(builder: &mut Builder<'static, UsbDriver>) -> Self {
+        let mut function = builder.function(0xFF, 0x00, 0x00);
+        function.msos_feature(msos::CompatibleIdFeatureDescriptor::new("WINUSB", ""));
+        function.msos_feature(msos::RegistryPropertyFeatureDescriptor::new(
+            "DeviceInterfaceGUIDs",
+            msos::PropertyData::RegMultiSz(DEVICE_INTERFACE_GUIDS),
+        ));
+
+        let mut interface = function.interface();
+        let mut alt_setting = interface.alt_setting(0xFF, 0x00, 0x00, None);
+
+        let read_ep = alt_setting.endpoint_bulk_out(BULK_MAX_PACKET_SIZE as u16);
+        let write_ep = alt_setting.endpoint_bulk_in(BULK_MAX_PACKET_SIZE as u16);
+
+        BulkTransfer { read_ep, write_ep }
+    }
+}
+
+/// Check the announcement of a transfer.
+fn check_begin(begin: &Begin) -> Status {
+    let length = begin.length as usize;
+
+    match begin.target {
+        Target::FirCoefficients if begin.channel as usize >= OUTPUT_CHANNEL_COUNT => Status::InvalidTarget,
+        Target::FirCoefficients if length > audio::fir::MAX_FIR_LENGTH * size_of::<f32>() => Status::TooLarge,
+        Target::ThermalLog => Status::InvalidTarget,
+        _ if length > TRANSFER_BUFFER_SIZE => Status::TooLarge,
+        _ => Status::Ok,
+    }
+}
+
+/// Apply a received payload to its target.
+fn commit(begin: &Begin, payload: &[u8]) -> Status {
+    match begin.target {
+        Target::FirCoefficients => {
+            if payload.len() % size_of::<f32>() != 0 {
+                return Status::InvalidData;
+            }
+
+            let mut taps = dsp::FirTaps::new();
+            for bytes in payload.chunks_exact(size_of::<f32>()) {
+                let tap = f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+
+                if !tap.is_finite() || taps.push(tap).is_err() {
+                    return Status::InvalidData;
+                }
+            }
+
+            match dsp::set_fir_taps(begin.channel as usize, &taps) {
+                Ok(()) => Status::Ok,
+                Err(_) => Status::TooLarge,
+            }
+        }
+        Target::Parameters => match PARAMETERS.set_entries(payload) {
+            Ok(()) => Status::Ok,
+            Err(_) => Status::InvalidData,
+        },
+        Target::Settings => match settings::decode(payload).map(|config| config.apply()) {
+            Ok(Ok(())) => Status::Ok,
+            _ => Status::InvalidData,
+        },
+        Target::SlotB => match settings::decode(payload).map(config_slots::stage) {
+            Ok(Ok(())) => Status::Ok,
+            _ => Status::InvalidData,
+        },
+        Target::Backup => match backup::restore(payload) {
+            Ok(()) => Status::Ok,
+            Err(error @ (backup::Error::Storage(_) | backup::Error::SettingsStore(_))) => {
+                info!("Bulk transfer: Failed to restore the backup: {}", error);
+                Status::Failed
+            }
+            Err(error) => {
+                info!("Bulk transfer: Invalid backup: {}", error);
+                Status::InvalidData
+            }
+        },
+        Target::ThermalLog => Status::InvalidTarget,
+    }
+}
+
+/// Create the payload of a readable target in the buffer. Returns its length.
+fn read(begin: &Begin, buffer: &mut [u8]) -> Result<usize, Status> {
+    match begin.target {
+        Target::Backup => backup::create(buffer).map_err(|error| {
+            info!("Bulk transfer: Failed to create a backup: {}", error);
+            Status::Failed
+        }),
+        Target::ThermalLog => thermal_log::create(buffer).map_err(|error| {
+            info!("Bulk transfer: Failed to read the thermal log: {}", error);
+            Status::Failed
+        }),
+        _ => Err(Status::InvalidTarget),
+    }
+}
+
+async fn respond(
+    write_ep: &mut <UsbDriver as Driver<'static>>::EndpointIn,
+    command: u8,
+    status: Status,
+    transfer: &Transfer,
+) -> Result<(), Disconnected> {
+    let (received_length, received_crc) = match transfer {
+        Transfer::Receiving { received, crc, .. } => (*received as u32, crc.finalize()),
+        Transfer::Received { begin } => (begin.length, begin.crc),
+        Transfer::Idle => (0, 0),
+    };
+
+    let response = Response {
+        command,
+        status: status as u8,
+        received_length,
+        received_crc,
+    };
+
+    write_ep.write(&response.encode()).await?;
+    Ok(())
+}
+
+/// Send the response to a read message, followed by the payload.
+async fn send_payload(
+    write_ep: &mut <UsbDriver as Driver<'static>>::EndpointIn,
+    payload: &[u8],
+) -> Result<(), Disconnected> {
+    let response = Response {
+        command: Command::Read as u8,
+        status: Status::Ok as u8,
+        received_length: payload.len() as u32,
+        received_crc: crc32(payload),
+    };
+
+    write_ep.write(&response.encode()).await?;
+
+    for packet in payload.chunks(BULK_MAX_PACKET_SIZE) {
+        write_ep.write(packet).await?;
+    }
+
+    // A shorter packet ends the payload.
+    if payload.len() % BULK_MAX_PACKET_SIZE == 0 {
+        write_ep.write(&[]).await?;
+    }
+
+    Ok(())
+}
+
+async fn bulk_transfer_handler(bulk: &mut BulkTransfer, buffer: &mut [u8]) -> Result<(), Disconnected> {
+    let mut transfer = Transfer::Idle;
+    let mut packet = [0u8; BULK_MAX_PACKET_SIZE];
+
+    loop {
+        let length = if let Transfer::Receiving { .. } = transfer {
+            match with_timeout(TRANSFER_TIMEOUT, bulk.read_ep.read(&mut packet)).await {
+                Ok(length) => length?,
+                Err(_) => {
+                    debug!("Bulk transfer: Timeout");
+                    transfer = Transfer::Idle;
+                    continue;
+                }
+            }
+        } else {
+            bulk.read_ep.read(&mut packet).await?
+        };
+
+        let data = &packet[..length];
+
+        if let Transfer::Receiving {
+            begin,
+            mut received,
+            mut crc,
+        } = transfer
+        {
+            let chunk = &data[..data.len().min(begin.length as usize - received)];
+
+            buffer[received..received + chunk.len()].copy_from_slice(chunk);
+            crc.update(chunk);
+            received += chunk.len();
+
+            transfer = Transfer::Receiving { begin, received, crc };
+
+            if received == begin.length as usize {
+                let status = if crc.finalize() == begin.crc {
+                    Status::Ok
+                } else {
+                    Status::CrcMismatch
+                };
+
+                respond(&mut bulk.write_ep, Command::Data as u8, status, &transfer).await?;
+
+                transfer = if status == Status::Ok {
+                    Transfer::Received { begin }
+                } else {
+                    Transfer::Idle
+                };
+            }
+
+            continue;
+        }
+
+        match data.first().copied().map(Command::try_from) {
+            Some(Ok(Command::Begin)) => {
+                let status = match Begin::decode(data) {
+                    Ok(begin) => {
+                        let status = check_begin(&begin);
+
+                        if status == Status::Ok {
+                            transfer = Transfer::Receiving {
+                                begin,
+                                received: 0,
+                                crc: Crc32::new(),
+                            };
+                        }
+
+                        status
+                    }
+                    Err(status) => status,
+                };
+
+                respond(&mut bulk.write_ep, Command::Begin as u8, status, &transfer).await?;
+
+                // Empty payloads are complete right away.
+                if let Transfer::Receiving { begin, .. } = transfer {
+                    if begin.length == 0 {
+                        transfer = Transfer::Received { begin };
+                        respond(&mut bulk.write_ep, Command::Data as u8, Status::Ok, &transfer).await?;
+                    }
+                }
+            }
+            Some(Ok(Command::Commit)) => {
+                let status = match transfer {
+                    Transfer::Received { begin } => commit(&begin, &buffer[..begin.length as usize]),
+                    _ => Status::UnexpectedCommand,
+                };
+
+                info!("Bulk transfer: Commit, status {}", status);
+                respond(&mut bulk.write_ep, Command::Commit as u8, status, &transfer).await?;
+                transfer = Transfer::Idle;
+            }
+            Some(Ok(Command::Read)) => {
+                // Reading discards a transfer in progress, since it shares the buffer.
+                transfer = Transfer::Idle;
+
+                match Begin::decode(data).and_then(|begin| read(&begin, buffer)) {
+                    Ok(length) => send_payload(&mut bulk.write_ep, &buffer[..length]).await?,
+                    Err(status) => respond(&mut bulk.write_ep, Command::Read as u8, status, &transfer).await?,
+                }
+            }
+            Some(Ok(Command::Abort)) => {
+                transfer = Transfer::Idle;
+                respond(&mut bulk.write_ep, Command::Abort as u8, Status::Ok, &transfer).await?;
+            }
+            _ => {
+                let command = data.first().copied().unwrap_or_default();
+
+                debug!("Bulk transfer: Unexpected command {}", command);
+                respond(&mut bulk.write_ep, command, Status::UnexpectedCommand, &transfer).await?;
+            }
+        }
+    }
+}
+
+/// Receives data blocks from the host, and applies them.
+#[embassy_executor::task]
+pub async fn bulk_transfer_task(mut bulk: BulkTransfer) {
+    // Outside of the DTCM, which is too small for it.
+    #[link_section = ".axisram"]
+    static TRANSFER_BUFFER: GroundedArrayCell<u8, TRANSFER_BUFFER_SIZE> = GroundedArrayCell::uninit();
+
+    // SAFETY: The task is only spawned once, so nothing else uses the buffer.
+    let buffer: &mut [u8] = unsafe {
+        TRANSFER_BUFFER.initialize_all_copied(0);
+        let (ptr, len) = TRANSFER_BUFFER.get_ptr_len();
+        core::slice::from_raw_parts_mut(ptr, len)
+    };
+
+    loop {
+        bulk.read_ep.wait_enabled().await;
+        info!("Bulk transfer interface enabled");
+
+        _ = bulk_transfer_handler(&mut bulk, buffer).await;
+        info!("Bulk transfer interface disabled");
+    }
+}
@@ -0,0 +1,222 @@
+//! Board buttons, and buttons on the GPIO expander, with debouncing and detection of short, long, and double presses.
+//!
+//! Buttons 0 and 1 are on the board, the following ones on the inputs of the GPIO expander (see
+//! [`crate::gpio_expander`]), in order.
+//!
+//! Every press type of every button is mapped to a device action (see [`ButtonAction`]). The mapping is configured
+//! with the shell (`button map <button> <press> <action>`), and is part of the device settings. A power button maps
+//! its short press to standby, and its long press to an ordered shutdown (see [`crate::shutdown`]).
+use core::cell::Cell;
+
+use embassy_futures::join::join_array;
+use embassy_stm32::exti::ExtiInput;
+use embassy_stm32::gpio::Pull;
+use embassy_stm32::peripherals;
+use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_time::{with_timeout, Duration, Timer};
+use protocol::button::{ButtonAction, ButtonMap, Press, BOARD_BUTTON_COUNT, DEFAULT_BUTTON_MAP};
+
+use crate::control::CONTROL;
+use crate::gpio_expander;
+use crate::presets;
+use crate::shutdown;
+use crate::*;
+
+/// The time for a button to settle after an edge.
+const DEBOUNCE_TIME: Duration = Duration::from_millis(20);
+
+/// A press is long, if the button is held for this time.
+const LONG_PRESS_TIME: Duration = Duration::from_millis(800);
+
+/// A press is double, if the next press follows within this time after release.
+const DOUBLE_PRESS_TIME: Duration = Duration::from_millis(300);
+
+/// The actions of all buttons.
+static BUTTON_MAP: Mutex<ThreadModeRawMutex, Cell<ButtonMap>> = Mutex::new(Cell::new(DEFAULT_BUTTON_MAP));
+
+/// Get the actions of all buttons.
+pub fn button_map() -> ButtonMap {
+    BUTTON_MAP.lock(|map| map.get())
+}
+
+/// Replace the actions of all buttons.
+pub fn set_button_map(button_map: ButtonMap) {
+    BUTTON_MAP.lock(|map| map.set(button_map));
+}
+
+/// The name of a press type, as used by the text interfaces.
+pub fn press_name(press: Press) -> &'static str {
+    match press {
+        Press::Short => "short",
+        Press::Long => "long",
+        Press::Double => "double",
+    }
+}
+
+/// Parse the name of a press type.
+pub fn parse_press(name: &str) -> Option<Press> {
+    Press::ALL.into_iter().find(|press| press_name(*press) == name)
+}
+
+/// The name of an action, as used by the text interfaces.
+pub fn action_name(action: ButtonAction) -> &'static str {
+    match action {
+        ButtonAction::None => "none",
+        ButtonAction::Source => "source",
+        ButtonAction::Mute => "mute",
+        ButtonAction::Standby => "standby",
+        ButtonAction::NextPreset => "next-preset",
+        ButtonAction::Shutdown => "shutdown",
+    }
+}
+
+/// Parse the name of an action.
+pub fn parse_action(name: &str) -> Option<ButtonAction> {
+    ButtonAction::ALL
+        .into_iter()
+        .find(|action| action_name(*action) == name)
+}
+
+/// Resources that are required for the board buttons.
+#[allow(missing_docs)]
+pub struct ButtonResources {
+    pub pin_0: peripherals::PD5,
+    pub exti_0: peripherals::EXTI5,
+
+    pub pin_1: peripherals::PD6,
+    pub exti_1: peripherals::EXTI6,
+}
+
+/// The input of a button.
+pub enum ButtonInput<'d> {
+    /// An MCU pin with interrupt.
+    Pin(ExtiInput<'d>),
+    /// An input of the GPIO expander, which is polled.
+    Expander(u8),
+}
+
+impl ButtonInput<'_> {
+    /// Whether the input is high.
+    fn is_high(&self) -> bool {
+        match self {
+            ButtonInput::Pin(input) => input.is_high(),
+            ButtonInput::Expander(input) => gpio_expander::is_high(*input),
+        }
+    }
+
+    /// Wait until the input has the given level.
+    async fn wait_for_level(&mut self, high: bool) {
+        match self {
+            ButtonInput::Pin(input) if high => input.wait_for_high().await,
+            ButtonInput::Pin(input) => input.wait_for_low().await,
+            ButtonInput::Expander(input) => {
+                while gpio_expander::is_high(*input) != high {
+                    Timer::after(gpio_expander::POLL_INTERVAL).await;
+                }
+            }
+        }
+    }
+}
+
+/// A push button that connects its (pulled-up) input to ground.
+pub struct Button<'d> {
+    input: ButtonInput<'d>,
+}
+
+impl<'d> Button<'d> {
+    /// Create a button on an input.
+    pub fn new(input: ButtonInput<'d>) -> Self {
+        Button { input }
+    }
+
+    /// Wait until the button is pressed (low), or released (high), and has settled.
+    async fn wait_for_settled_level(&mut self, high: bool) {
+        loop {
+            self.input.wait_for_level(high).await;
+            Timer::after(DEBOUNCE_TIME).await;
+
+            if self.input.is_high() == high {
+                return;
+            }
+        }
+    }
+
+    /// Wait until the button is pressed, and has settled.
+    async fn wait_for_press(&mut self) {
+        self.wait_for_settled_level(false).await;
+    }
+
+    /// Wait until the button is released, and has settled.
+    async fn wait_for_release(&mut self) {
+        self.wait_for_settled_level(true).await;
+    }
+
+    /// Wait for the next press.
+    ///
+    /// Long presses are reported while the button is still held. Short presses are reported with a delay, which
+    /// allows for telling them from double presses.
+    pub async fn press(&mut self) -> Press {
+        // The button may still be held after a long press.
+        self.wait_for_release().await;
+        self.wait_for_press().await;
+
+        if with_timeout(LONG_PRESS_TIME, self.wait_for_release()).await.is_err() {
+            return Press::Long;
+        }
+
+        match with_timeout(DOUBLE_PRESS_TIME, self.wait_for_press()).await {
+            Ok(()) => Press::Double,
+            Err(_) => Press::Short,
+        }
+    }
+}
+
+/// Perform an action.
+fn perform(action: ButtonAction) {
+    match action {
+        ButtonAction::None => (),
+        ButtonAction::Source => CONTROL.select_next_source(),
+        ButtonAction::Mute => CONTROL.set_muted(!CONTROL.muted()),
+        ButtonAction::Standby => CONTROL.set_standby(!CONTROL.standby()),
+        ButtonAction::NextPreset => presets::load_next(),
+        ButtonAction::Shutdown => shutdown::request(),
+    }
+}
+
+/// Perform the mapped actions of a button's presses.
+async fn run(index: usize, mut button: Button<'_>) {
+    loop {
+        let press = button.press().await;
+        let action = button_map()[index][press as usize];
+
+        log!(debug, "Button {} press {:?}: {:?}", index, press, action);
+        perform(action);
+    }
+}
+
+/// Handles the board buttons, and the buttons on the GPIO expander.
+#[embassy_executor::task]
+pub async fn button_task(resources: ButtonResources) {
+    let button_0 = Button::new(ButtonInput::Pin(ExtiInput::new(
+        resources.pin_0,
+        resources.exti_0,
+        Pull::Up,
+    )));
+    let button_1 = Button::new(ButtonInput::Pin(ExtiInput::new(
+        resources.pin_1,
+        resources.exti_1,
+        Pull::Up,
+    )));
+    let expander_button = |input: u8| Button::new(ButtonInput::Expander(input));
+
+    join_array([
+        run(0, button_0),
+        run(1, button_1),
+        run(BOARD_BUTTON_COUNT, expander_button(0)),
+        run(BOARD_BUTTON_COUNT + 1, expander_button(1)),
+        run(BOARD_BUTTON_COUNT + 2, expander_button(2)),
+        run(BOARD_BUTTON_COUNT + 3, expander_button(3)),
+    ])
+    .await;
+}
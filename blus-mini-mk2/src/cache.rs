@@ -0,0 +1,112 @@
+//! The caches of the core, and the coherence of DMA buffers with the data cache.
+//!
+//! With the data cache enabled, the core and the DMA controllers may see different contents of a buffer: the core
+//! writes into cache lines, which DMA does not read, and DMA writes to memory, which the core does not see through
+//! stale cache lines. There are two kinds of DMA buffers:
+//!
+//! - Buffers that drivers access continuously (e.g. the ring buffers of SAI and S/PDIF), where the firmware cannot
+//!   maintain the cache around transfers. They live in the AHB SRAMs (SRAM1 and SRAM4), which the MPU maps as
+//!   non-cacheable. This also keeps the values in SRAM4, which persist through resets, out of the cache.
+//! - Buffers for single transfers (e.g. the frame buffer of the addressable LEDs). They may live in cached memory (AXI
+//!   SRAM), and must be cleaned with [`clean`] before DMA reads them, or invalidated with [`invalidate`] after DMA has
+//!   written them.
+//!
+//! Both are taken from the pool of [`crate::dma_buffers`]. Every [`DmaBuffer`] occupies whole cache lines, such that
+//! maintenance never affects neighbouring data. The TCMs are not cached, and not reachable by the DMA controllers of
+//! the audio interfaces.
+use cortex_m::peripheral::{CPUID, MPU, SCB};
+use grounded::uninit::GroundedArrayCell;
+
+/// The size of a line of the data cache in bytes.
+pub const LINE_SIZE: usize = 32;
+
+/// The non-cacheable regions of the MPU, as base address and size (a power of two, aligned).
+const NON_CACHEABLE_REGIONS: [(u32, u32); 2] = [(0x3000_0000, 16 * 1024), (0x3800_0000, 16 * 1024)];
+
+/// Region attributes: not executable, full access, normal memory that is shareable and not cacheable (TEX 1, C 0,
+/// B 0), and enabled. The size is added.
+const NON_CACHEABLE_ATTRIBUTES: u32 = (1 << 28) | (0b011 << 24) | (0b001 << 19) | (1 << 18) | 1;
+
+/// The MPU is enabled, with the default memory map outside of its regions.
+const MPU_ENABLE: u32 = (1 << 2) | 1;
+
+/// A statically allocated DMA buffer of `N` elements, which starts and ends at cache line boundaries.
+#[repr(C, align(32))]
+pub struct DmaBuffer<T, const N: usize>(GroundedArrayCell<T, N>);
+
+impl<T: Copy, const N: usize> DmaBuffer<T, N> {
+    /// An uninitialized buffer, to be placed in a memory section that DMA can access.
+    pub const fn uninit() -> Self {
+        Self(GroundedArrayCell::uninit())
+    }
+
+    /// Initialize all elements with a value, and take the buffer.
+    ///
+    /// # Safety
+    ///
+    /// Must only be called once.
+    #[allow(clippy::mut_from_ref)]
+    pub unsafe fn take(&'static self, value: T) -> &'static mut [T] {
+        self.0.initialize_all_copied(value);
+        let (ptr, len) = self.0.get_ptr_len();
+        core::slice::from_raw_parts_mut(ptr, len)
+    }
+}
+
+/// Map the DMA buffers in the AHB SRAMs as non-cacheable, and enable the instruction and data caches.
+///
+/// Must be called before any DMA transfer starts.
+pub fn init(mpu: &mut MPU, scb: &mut SCB, cpuid: &mut CPUID) {
+    cortex_m::asm::dmb();
+
+    // SAFETY: The MPU is reconfigured while disabled, before any access to the regions is cached.
+    unsafe {
+        mpu.ctrl.write(0);
+
+        for (index, (base, size)) in NON_CACHEABLE_REGIONS.into_iter().enumerate() {
+            mpu.rnr.write(index as u32);
+            mpu.rbar.write(base);
+            mpu.rasr
+                .write(NON_CACHEABLE_ATTRIBUTES | ((size.trailing_zeros() - 1) << 1));
+        }
+
+        mpu.ctrl.write(MPU_ENABLE);
+    }
+
+    cortex_m::asm::dsb();
+    cortex_m::asm::isb();
+
+    scb.enable_icache();
+    scb.enable_dcache(cpuid);
+}
+
+/// The system control block, for cache maintenance by address.
+fn scb() -> SCB {
+    // SAFETY: Maintenance by address takes single register writes, which do not interfere with other users of the SCB.
+    unsafe { cortex_m::Peripherals::steal() }.SCB
+}
+
+/// Write cached data of a buffer back to memory, before DMA reads it.
+pub fn clean<T>(buffer: &[T]) {
+    scb().clean_dcache_by_slice(buffer);
+}
+
+/// Discard cached data of a buffer, after DMA has written it, such that the core reads the new contents.
+///
+/// Panics, if the buffer does not cover whole cache lines (e.g. a [`DmaBuffer`] of a multiple of [`LINE_SIZE`] bytes),
+/// which would also discard neighbouring data.
+pub fn invalidate<T>(buffer: &mut [T]) {
+    let (address, size) = (buffer.as_ptr() as usize, core::mem::size_of_val(buffer));
+    if address % LINE_SIZE != 0 || size % LINE_SIZE != 0 {
+        panic!("Invalidated buffer is not aligned to cache lines.");
+    }
+
+    // SAFETY: The buffer is borrowed exclusively, and covers whole cache lines.
+    unsafe { scb().invalidate_dcache_by_slice(buffer) };
+}
+
+/// Discard cached data of the flash memory, after it was erased or written.
+pub fn invalidate_flash(address: usize, size: usize) {
+    // SAFETY: Cached flash memory is never dirty, such that also partially covered cache lines can be discarded.
+    unsafe { scb().invalidate_dcache_by_address(address, size) };
+}
@@ -0,0 +1,128 @@
+//! Calibration data of the device, which is written during production test, and applied automatically at runtime.
+//!
+//! The data (see [`protocol::calibration`]) holds the taper of the volume potentiometer, a gain trim for every output
+//! channel, and offsets of the temperature sensors. It is restored from its storage region at startup (see
+//! [`crate::storage`]), and is not affected by factory resets.
+//!
+//! The test station sets the values with the shell (`calibration` commands), which take effect immediately, and stores
+//! them with `calibration store`.
+use core::cell::Cell;
+
+use embassy_sync::blocking_mutex::raw::{CriticalSectionRawMutex, ThreadModeRawMutex};
+use embassy_sync::blocking_mutex::Mutex;
+use protocol::calibration::{self as calibration_format, Calibration, ENCODED_SIZE};
+use protocol::event_log::StoredData;
+use static_assertions::const_assert;
+
+use crate::event_log;
+use crate::storage::{self, CALIBRATION_REGION, WRITE_BLOCK_SIZE};
+use crate::*;
+
+/// The size of the stored calibration data, in whole write blocks.
+const STORED_SIZE: usize = ENCODED_SIZE.div_ceil(WRITE_BLOCK_SIZE) * WRITE_BLOCK_SIZE;
+
+const_assert!(STORED_SIZE as u32 <= CALIBRATION_REGION.size());
+
+/// An error of storing or restoring calibration data.
+#[derive(Clone, Copy, PartialEq, Debug, defmt::Format)]
+pub enum Error {
+    /// The calibration data is invalid, or could not be decoded.
+    Format(calibration_format::Error),
+    /// The storage reported an error.
+    Storage(storage::Error),
+}
+
+impl From<storage::Error> for Error {
+    fn from(error: storage::Error) -> Self {
+        Error::Storage(error)
+    }
+}
+
+impl From<calibration_format::Error> for Error {
+    fn from(error: calibration_format::Error) -> Self {
+        Error::Format(error)
+    }
+}
+
+/// The calibration data in effect.
+static CALIBRATION: Mutex<ThreadModeRawMutex, Cell<Calibration>> = Mutex::new(Cell::new(Calibration::new()));
+
+/// The output trims in effect, as linear gains.
+static OUTPUT_TRIMS: Mutex<CriticalSectionRawMutex, Cell<[f32; OUTPUT_CHANNEL_COUNT]>> =
+    Mutex::new(Cell::new([1.0; OUTPUT_CHANNEL_COUNT]));
+
+/// Get the calibration data in effect.
+pub fn calibration() -> Calibration {
+    CALIBRATION.lock(|calibration| calibration.get())
+}
+
+/// Replace the calibration data in effect, if it is valid. Does not store it.
+pub fn set_calibration(calibration: Calibration) -> Result<(), calibration_format::Error> {
+    calibration.validate()?;
+
+    let trims = calibration.output_trim_db.map(|trim_db| 10.0f32.powf(trim_db / 20.0));
+
+    CALIBRATION.lock(|current| current.set(calibration));
+    OUTPUT_TRIMS.lock(|current| current.set(trims));
+
+    Ok(())
+}
+
+/// Modify the calibration data in effect. The modified data only takes effect, if it is valid.
+pub fn update_calibration(modify: impl FnOnce(&mut Calibration)) -> Result<(), calibration_format::Error> {
+    let mut calibration = calibration();
+    modify(&mut calibration);
+    set_calibration(calibration)
+}
+
+/// The gain trims of all output channels, as linear gains.
+pub fn output_trims() -> [f32; OUTPUT_CHANNEL_COUNT] {
+    OUTPUT_TRIMS.lock(|trims| trims.get())
+}
+
+/// Correct the reading of a temperature sensor in °C (see
+/// [`TEMPERATURE_SENSOR_COUNT`](calibration_format::TEMPERATURE_SENSOR_COUNT) for the sensors).
+pub fn corrected_temperature(sensor: usize, temperature: f32) -> f32 {
+    temperature
+        + calibration()
+            .temperature_offsets
+            .get(sensor)
+            .copied()
+            .unwrap_or_default()
+}
+
+/// Store the calibration data in effect.
+pub fn store() -> Result<(), Error> {
+    let mut data = [0xFFu8; STORED_SIZE];
+    data[..ENCODED_SIZE].copy_from_slice(&calibration().encode());
+
+    CALIBRATION_REGION.erase()?;
+    CALIBRATION_REGION.write(0, &data)?;
+
+    log!(info, "Stored calibration");
+    Ok(())
+}
+
+/// Restore the stored calibration data. Damaged data is not restored, and recorded in the event log.
+pub fn restore() -> Result<(), Error> {
+    let mut data = [0u8; ENCODED_SIZE];
+    CALIBRATION_REGION.read(0, &mut data)?;
+
+    let calibration = Calibration::decode(&data).inspect_err(|error| {
+        // Erased storage holds no calibration data, instead of damaged data.
+        if !matches!(
+            error,
+            calibration_format::Error::InvalidMagic | calibration_format::Error::IncompatibleVersion
+        ) {
+            event_log::record_corruption(StoredData::Calibration);
+        }
+    })?;
+
+    Ok(set_calibration(calibration)?)
+}
+
+/// Erase the stored calibration data. The data in effect is reset as well.
+pub fn erase() -> Result<(), Error> {
+    CALIBRATION_REGION.erase()?;
+    Ok(set_calibration(Calibration::new())?)
+}
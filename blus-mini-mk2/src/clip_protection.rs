@@ -0,0 +1,79 @@
+//! Protection of speakers and amplifiers against a master volume that is turned up into sustained clipping.
+//!
+//! The audio routing counts sample blocks with clipped output samples (see [`Control::count_clipped_block`]). Once
+//! blocks clip in every check for [`SUSTAINED_TIME`], the master volume is reduced by the configured step (shell
+//! `clip-protection`), as its writer [`VolumeWriter::ClipProtection`]. Further reductions follow while clipping
+//! continues. Short peaks, which clip a single block now and then, are not acted upon.
+//!
+//! A reduced volume is indicated by the status flag [`status::CLIP_PROTECTION`] (which is notified to control
+//! clients, see [`crate::notifications`]) and the clip LED (see [`crate::led`]), until another writer sets the
+//! volume. The step is not stored with the settings; a startup script can configure it (see
+//! [`crate::startup_script`]).
+//!
+//! [`Control::count_clipped_block`]: crate::control::Control::count_clipped_block
+//! [`status::CLIP_PROTECTION`]: crate::control::status::CLIP_PROTECTION
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use embassy_time::{Duration, Ticker};
+
+use crate::control::{VolumeWriter, CONTROL, MUTED_ATTENUATION};
+use crate::*;
+
+/// The interval between checks for clipped sample blocks.
+const CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The time of clipping in every check, after which the master volume is reduced.
+pub const SUSTAINED_TIME: Duration = Duration::from_millis(500);
+
+/// The default reduction of the master volume in steps of 0.5 dB (3 dB).
+pub const DEFAULT_STEP_HALF_DB: u8 = 6;
+
+/// The maximum reduction of the master volume in steps of 0.5 dB (12 dB).
+pub const MAX_STEP_HALF_DB: u8 = 24;
+
+/// The reduction of the master volume in steps of 0.5 dB, or `0`, if the protection is disabled.
+static STEP_HALF_DB: AtomicU8 = AtomicU8::new(DEFAULT_STEP_HALF_DB);
+
+/// The reduction of the master volume in steps of 0.5 dB, or `0`, if the protection is disabled.
+pub fn step_half_db() -> u8 {
+    STEP_HALF_DB.load(Ordering::Relaxed)
+}
+
+/// Set the reduction of the master volume in steps of 0.5 dB (up to [`MAX_STEP_HALF_DB`]), or disable the
+/// protection with `0`.
+pub fn set_step_half_db(step_half_db: u8) {
+    STEP_HALF_DB.store(step_half_db.min(MAX_STEP_HALF_DB), Ordering::Relaxed);
+}
+
+/// Reduces the master volume on sustained clipping.
+#[embassy_executor::task]
+pub async fn clip_protection_task() {
+    const SUSTAINED_CHECK_COUNT: u32 = (SUSTAINED_TIME.as_ticks() / CHECK_INTERVAL.as_ticks()) as u32;
+
+    let mut ticker = Ticker::every(CHECK_INTERVAL);
+    let mut last_count = CONTROL.clipped_block_count();
+    let mut clipping_checks = 0u32;
+
+    loop {
+        ticker.next().await;
+
+        let count = CONTROL.clipped_block_count();
+        clipping_checks = if count != last_count { clipping_checks + 1 } else { 0 };
+        last_count = count;
+
+        let step = step_half_db();
+        if step == 0 || clipping_checks < SUSTAINED_CHECK_COUNT {
+            continue;
+        }
+        clipping_checks = 0;
+
+        let attenuation = CONTROL.attenuation().saturating_add(step).min(MUTED_ATTENUATION - 1);
+        log!(
+            warn,
+            "Sustained clipping, reduced the volume to -{} dB",
+            attenuation as f32 / 2.0
+        );
+
+        CONTROL.set_attenuation_by(VolumeWriter::ClipProtection, attenuation);
+    }
+}
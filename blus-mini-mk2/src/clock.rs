@@ -0,0 +1,91 @@
+//! The wall-clock time, as set by a host.
+//!
+//! The device has no battery-backed clock. Hosts set the time after connecting (with the shell `time` command, or
+//! `SYSTem:TIME`), and it is kept from then on by the time since startup, until the next reset. It marks persistent
+//! records, such as the creation of presets (see [`crate::presets`]).
+use core::fmt;
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use embassy_time::Instant;
+
+/// Marks that the time was not set since startup.
+const NOT_SET: u32 = u32::MAX;
+
+/// The Unix time in seconds at startup, or [`NOT_SET`].
+static STARTUP_TIME_S: AtomicU32 = AtomicU32::new(NOT_SET);
+
+/// The time since startup in seconds.
+fn uptime_s() -> u32 {
+    Instant::now().as_secs().min(u32::MAX as u64) as u32
+}
+
+/// The Unix time in seconds, unless it was not set since startup.
+pub fn unix_time() -> Option<u32> {
+    let startup_time_s = STARTUP_TIME_S.load(Ordering::Relaxed);
+    (startup_time_s != NOT_SET).then(|| startup_time_s.saturating_add(uptime_s()))
+}
+
+/// Set the Unix time in seconds.
+pub fn set_unix_time(time_s: u32) {
+    let startup_time_s = time_s.saturating_sub(uptime_s());
+    STARTUP_TIME_S.store(startup_time_s.min(NOT_SET - 1), Ordering::Relaxed);
+}
+
+/// A date and time in UTC, which displays in ISO 8601 format (e.g. `2024-05-01T12:30:00Z`).
+#[derive(Clone, Copy, PartialEq, Debug, defmt::Format)]
+pub struct DateTime {
+    /// The year.
+    pub year: u32,
+    /// The month, from 1.
+    pub month: u32,
+    /// The day of the month, from 1.
+    pub day: u32,
+    /// The hour.
+    pub hour: u32,
+    /// The minute.
+    pub minute: u32,
+    /// The second.
+    pub second: u32,
+}
+
+impl DateTime {
+    /// Convert a Unix time in seconds.
+    pub fn from_unix_time(time_s: u32) -> Self {
+        let days = time_s / 86400;
+        let seconds = time_s % 86400;
+
+        // Civil date from days since 1970-01-01, counting in eras of 400 years from 0000-03-01.
+        let days = days + 719468;
+        let era = days / 146097;
+        let day_of_era = days % 146097;
+        let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let month_index = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+        let month = if month_index < 10 {
+            month_index + 3
+        } else {
+            month_index - 9
+        };
+        let year = year_of_era + era * 400 + (month <= 2) as u32;
+
+        DateTime {
+            year,
+            month,
+            day,
+            hour: seconds / 3600,
+            minute: (seconds / 60) % 60,
+            second: seconds % 60,
+        }
+    }
+}
+
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
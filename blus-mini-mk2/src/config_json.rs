@@ -0,0 +1,446 @@
+//! Export and import of the device configuration as JSON documents.
+//!
+//! The document holds the master volume and mute, the source selection, and the filter configuration of all
+//! output channels. FIR taps are not included. On import, fields that are missing keep their current values,
+//! whereas configured stages are always replaced. Errors are reported per field, with its path in the document.
+use core::fmt::{self, Write};
+
+use audio::filter_config::{ConfigError, FilterConfig, StageConfig, StageKind, MAX_GAIN_DB, MIN_GAIN_DB};
+use heapless::{String, Vec};
+use protocol::json::{self, Parser};
+
+use crate::control;
+use crate::dsp::DspConfig;
+use crate::settings::DeviceConfig;
+use crate::*;
+
+/// The version of the document format.
+pub const CONFIG_VERSION: u32 = 1;
+
+/// The maximum number of errors that are reported for an import.
+pub const MAX_ERROR_COUNT: usize = 8;
+
+/// The name of stages that are given by coefficients.
+const COEFFICIENTS_TYPE: &str = "coefficients";
+
+/// An error in an imported document.
+pub struct FieldError {
+    /// The path of the field, for example `channels[1].stages[0].q`.
+    pub path: String<48>,
+    /// A description of the error.
+    pub message: &'static str,
+}
+
+/// The errors of an import.
+pub struct ImportErrors {
+    /// The first errors that were found.
+    pub errors: Vec<FieldError, MAX_ERROR_COUNT>,
+    /// The number of further errors that are not listed.
+    pub omitted: usize,
+}
+
+/// Write the configuration as a JSON document, with line breaks for terminals (`\r\n`).
+pub fn export(config: &DeviceConfig, out: &mut impl Write) -> fmt::Result {
+    let volume_db = -(config.attenuation as f32) / 2.0;
+
+    write!(out, "{{\r\n")?;
+    write!(out, "  \"version\": {},\r\n", CONFIG_VERSION)?;
+    write!(out, "  \"volume_db\": {},\r\n", volume_db)?;
+    write!(out, "  \"mute\": {},\r\n", config.muted)?;
+    write!(
+        out,
+        "  \"source\": \"{}\",\r\n",
+        control::source_selection_name(config.source_selection)
+    )?;
+    write!(out, "  \"channels\": [\r\n")?;
+
+    for (channel, filter) in config.dsp.iter().enumerate() {
+        write!(out, "    {{\r\n")?;
+        write!(out, "      \"gain_db\": {},\r\n", filter.gain_db)?;
+        write!(out, "      \"inverted\": {},\r\n", filter.inverted)?;
+        write!(out, "      \"delay\": {},\r\n", filter.delay)?;
+        write!(out, "      \"stages\": [")?;
+
+        for (index, stage) in filter.stages.iter().enumerate() {
+            let separator = if index + 1 < filter.stages.len() { "," } else { "" };
+
+            match *stage {
+                StageConfig::Parametric {
+                    kind,
+                    frequency_hz,
+                    q,
+                    gain_db,
+                } => {
+                    write!(
+                        out,
+                        "\r\n        {{\"type\": \"{}\", \"frequency_hz\": {}, \"q\": {}",
+                        kind.name(),
+                        frequency_hz,
+                        q
+                    )?;
+
+                    if kind.has_gain() {
+                        write!(out, ", \"gain_db\": {}", gain_db)?;
+                    }
+
+                    write!(out, "}}{}", separator)?;
+                }
+                StageConfig::Coefficients { a1, a2, b0, b1, b2 } => write!(
+                    out,
+                    "\r\n        {{\"type\": \"{}\", \"b0\": {}, \"b1\": {}, \"b2\": {}, \"a1\": {}, \"a2\": {}}}{}",
+                    COEFFICIENTS_TYPE, b0, b1, b2, a1, a2, separator
+                )?,
+            }
+        }
+
+        if !filter.stages.is_empty() {
+            write!(out, "\r\n      ")?;
+        }
+
+        let separator = if channel + 1 < OUTPUT_CHANNEL_COUNT { "," } else { "" };
+        write!(out, "]\r\n    }}{}\r\n", separator)?;
+    }
+
+    write!(out, "  ]\r\n}}\r\n")
+}
+
+/// The fields of a stage, which are collected before the stage is assembled.
+#[derive(Default)]
+struct StageFields {
+    kind: Option<Option<StageKind>>,
+    frequency_hz: Option<f32>,
+    q: Option<f32>,
+    gain_db: Option<f32>,
+    coefficients: [Option<f32>; 5],
+}
+
+/// Walks through an imported document, and collects errors.
+struct Importer<'a> {
+    parser: Parser<'a>,
+    errors: ImportErrors,
+}
+
+/// A path of a field in the document.
+type Path = String<48>;
+
+fn path(args: fmt::Arguments) -> Path {
+    let mut path = Path::new();
+
+    // Overlong paths are truncated.
+    _ = path.write_fmt(args);
+    path
+}
+
+impl Importer<'_> {
+    fn error(&mut self, path: Path, message: &'static str) {
+        if self.errors.errors.push(FieldError { path, message }).is_err() {
+            self.errors.omitted += 1;
+        }
+    }
+
+    /// Handle the result of reading a field. Recoverable errors are recorded, and give `None`.
+    fn field<T>(
+        &mut self,
+        result: Result<T, json::Error>,
+        path: Path,
+        expected: &'static str,
+    ) -> Result<Option<T>, json::Error> {
+        match result {
+            Ok(value) => Ok(Some(value)),
+            Err(error) if error.is_recoverable() => {
+                self.error(path, expected);
+                Ok(None)
+            }
+            Err(error) => Err(error),
+        }
+    }
+
+    /// Skip the value of an unknown field.
+    fn unknown(&mut self, path: Path) -> Result<(), json::Error> {
+        self.error(path, "unknown field");
+        self.parser.skip_value()
+    }
+
+    fn number(&mut self, path: Path) -> Result<Option<f32>, json::Error> {
+        let result = self.parser.number::<f32>();
+        self.field(result, path, "expected a number")
+    }
+
+    fn boolean(&mut self, path: Path) -> Result<Option<bool>, json::Error> {
+        let result = self.parser.boolean();
+        self.field(result, path, "expected true or false")
+    }
+
+    fn document(&mut self, config: &mut DeviceConfig) -> Result<(), json::Error> {
+        self.parser.begin_object()?;
+
+        while let Some(key) = self.parser.next_key()? {
+            match key {
+                "version" => {
+                    let result = self.parser.number::<u32>();
+
+                    match self.field(result, path(format_args!("version")), "expected an integer")? {
+                        Some(CONFIG_VERSION) | None => (),
+                        Some(_) => self.error(path(format_args!("version")), "unsupported version"),
+                    }
+                }
+                "volume_db" => {
+                    if let Some(volume_db) = self.number(path(format_args!("volume_db")))? {
+                        if (-127.5..=0.0).contains(&volume_db) {
+                            config.attenuation = (-volume_db * 2.0) as u8;
+                        } else {
+                            self.error(path(format_args!("volume_db")), "must be between -127.5 and 0");
+                        }
+                    }
+                }
+                "mute" => {
+                    if let Some(muted) = self.boolean(path(format_args!("mute")))? {
+                        config.muted = muted;
+                    }
+                }
+                "source" => {
+                    let result = self.parser.string();
+
+                    if let Some(name) = self.field(result, path(format_args!("source")), "expected a string")? {
+                        match control::parse_source_selection(name) {
+                            Some(source) => config.source_selection = source,
+                            None => self.error(path(format_args!("source")), "must be auto, usb, spdif, or rpi"),
+                        }
+                    }
+                }
+                "channels" => self.channels(&mut config.dsp)?,
+                key => self.unknown(path(format_args!("{}", key)))?,
+            }
+        }
+
+        self.parser.end()
+    }
+
+    fn channels(&mut self, dsp_config: &mut DspConfig) -> Result<(), json::Error> {
+        let result = self.parser.begin_array();
+        if self
+            .field(result, path(format_args!("channels")), "expected an array")?
+            .is_none()
+        {
+            return Ok(());
+        }
+
+        let mut channel = 0;
+        while self.parser.next_element()? {
+            if channel < OUTPUT_CHANNEL_COUNT {
+                self.channel(channel, &mut dsp_config[channel])?;
+            } else {
+                self.error(path(format_args!("channels[{}]", channel)), "too many channels");
+                self.parser.skip_value()?;
+            }
+
+            channel += 1;
+        }
+
+        Ok(())
+    }
+
+    fn channel(&mut self, channel: usize, config: &mut FilterConfig) -> Result<(), json::Error> {
+        let result = self.parser.begin_object();
+        if self
+            .field(
+                result,
+                path(format_args!("channels[{}]", channel)),
+                "expected an object",
+            )?
+            .is_none()
+        {
+            return Ok(());
+        }
+
+        while let Some(key) = self.parser.next_key()? {
+            let field_path = path(format_args!("channels[{}].{}", channel, key));
+
+            match key {
+                "gain_db" => {
+                    if let Some(gain_db) = self.number(field_path)? {
+                        config.gain_db = gain_db;
+                    }
+                }
+                "inverted" => {
+                    if let Some(inverted) = self.boolean(field_path)? {
+                        config.inverted = inverted;
+                    }
+                }
+                "delay" => {
+                    let result = self.parser.number::<usize>();
+
+                    if let Some(delay) = self.field(result, field_path, "expected an integer")? {
+                        config.delay = delay;
+                    }
+                }
+                "stages" => self.stages(channel, config)?,
+                _ => self.unknown(field_path)?,
+            }
+        }
+
+        Ok(())
+    }
+
+    fn stages(&mut self, channel: usize, config: &mut FilterConfig) -> Result<(), json::Error> {
+        let result = self.parser.begin_array();
+        if self
+            .field(
+                result,
+                path(format_args!("channels[{}].stages", channel)),
+                "expected an array",
+            )?
+            .is_none()
+        {
+            return Ok(());
+        }
+
+        config.stages.clear();
+
+        let mut index = 0;
+        while self.parser.next_element()? {
+            let stage_path = path(format_args!("channels[{}].stages[{}]", channel, index));
+
+            if let Some(stage) = self.stage(&stage_path)? {
+                if config.stages.push(stage).is_err() {
+                    self.error(stage_path, "too many stages");
+                }
+            }
+
+            index += 1;
+        }
+
+        Ok(())
+    }
+
+    fn stage(&mut self, stage_path: &str) -> Result<Option<StageConfig>, json::Error> {
+        let result = self.parser.begin_object();
+        if self
+            .field(result, path(format_args!("{}", stage_path)), "expected an object")?
+            .is_none()
+        {
+            return Ok(None);
+        }
+
+        let mut fields = StageFields::default();
+
+        while let Some(key) = self.parser.next_key()? {
+            let field_path = path(format_args!("{}.{}", stage_path, key));
+
+            match key {
+                "type" => {
+                    let result = self.parser.string();
+
+                    if let Some(name) = self.field(result, field_path.clone(), "expected a string")? {
+                        match (name, StageKind::from_name(name)) {
+                            (COEFFICIENTS_TYPE, _) => fields.kind = Some(None),
+                            (_, Some(kind)) => fields.kind = Some(Some(kind)),
+                            (_, None) => self.error(field_path, "unknown stage type"),
+                        }
+                    }
+                }
+                "frequency_hz" => fields.frequency_hz = self.number(field_path)?,
+                "q" => fields.q = self.number(field_path)?,
+                "gain_db" => fields.gain_db = self.number(field_path)?,
+                "b0" => fields.coefficients[0] = self.number(field_path)?,
+                "b1" => fields.coefficients[1] = self.number(field_path)?,
+                "b2" => fields.coefficients[2] = self.number(field_path)?,
+                "a1" => fields.coefficients[3] = self.number(field_path)?,
+                "a2" => fields.coefficients[4] = self.number(field_path)?,
+                _ => self.unknown(field_path)?,
+            }
+        }
+
+        let stage = match fields.kind {
+            Some(Some(kind)) => match (fields.frequency_hz, fields.q) {
+                (Some(frequency_hz), Some(q)) => Some(StageConfig::Parametric {
+                    kind,
+                    frequency_hz,
+                    q,
+                    gain_db: fields.gain_db.unwrap_or(0.0),
+                }),
+                _ => {
+                    self.error(path(format_args!("{}", stage_path)), "requires frequency_hz and q");
+                    None
+                }
+            },
+            Some(None) => match fields.coefficients {
+                [Some(b0), Some(b1), Some(b2), Some(a1), Some(a2)] => {
+                    Some(StageConfig::Coefficients { a1, a2, b0, b1, b2 })
+                }
+                _ => {
+                    self.error(path(format_args!("{}", stage_path)), "requires b0, b1, b2, a1, and a2");
+                    None
+                }
+            },
+            None => {
+                self.error(path(format_args!("{}", stage_path)), "requires a type");
+                None
+            }
+        };
+
+        Ok(stage)
+    }
+
+    /// Check the values of the imported configuration.
+    fn validate(&mut self, config: &DeviceConfig) {
+        for (channel, filter) in config.dsp.iter().enumerate() {
+            if filter.delay > audio::audio_filter::MAX_DELAY_LENGTH {
+                self.error(
+                    path(format_args!("channels[{}].delay", channel)),
+                    "exceeds the maximum delay",
+                );
+            }
+
+            if !(MIN_GAIN_DB..=MAX_GAIN_DB).contains(&filter.gain_db) {
+                self.error(path(format_args!("channels[{}].gain_db", channel)), "invalid gain");
+            }
+
+            for (index, stage) in filter.stages.iter().enumerate() {
+                let message = match stage.coefficients(SAMPLE_RATE_HZ) {
+                    Ok(_) => continue,
+                    Err(ConfigError::InvalidFrequency) => "invalid frequency",
+                    Err(ConfigError::InvalidQ) => "invalid quality factor",
+                    Err(ConfigError::InvalidGain) => "invalid gain",
+                    Err(ConfigError::UnstableCoefficients) => "unstable coefficients",
+                    Err(_) => "invalid stage",
+                };
+
+                self.error(path(format_args!("channels[{}].stages[{}]", channel, index)), message);
+            }
+        }
+    }
+}
+
+/// Parse a JSON document into a device configuration, based on the current configuration.
+#[allow(clippy::result_large_err)]
+pub fn import(document: &str) -> Result<DeviceConfig, ImportErrors> {
+    let mut config = DeviceConfig::current();
+    let mut importer = Importer {
+        parser: Parser::new(document),
+        errors: ImportErrors {
+            errors: Vec::new(),
+            omitted: 0,
+        },
+    };
+
+    if let Err(error) = importer.document(&mut config) {
+        let message = match error {
+            json::Error::UnexpectedEnd => "unexpected end of document",
+            json::Error::UnsupportedEscape => "escape sequences are not supported",
+            json::Error::TooDeep => "nested too deeply",
+            _ => "malformed document",
+        };
+
+        let position = importer.parser.position();
+        importer.error(path(format_args!("at byte {}", position)), message);
+    } else {
+        importer.validate(&config);
+    }
+
+    if importer.errors.errors.is_empty() {
+        Ok(config)
+    } else {
+        Err(importer.errors)
+    }
+}
@@ -0,0 +1,142 @@
+//! Two complete configuration slots, for auditioning an experimental configuration against the current one.
+//!
+//! Slot A holds the configuration in effect. A host tool stages an experimental configuration in slot B (on the bulk
+//! endpoints, see [`protocol::bulk::Target::SlotB`]), and then switches between the slots with [`SlotAction`]s (HID
+//! requests, or the shell `slot` commands):
+//! - [`SlotAction::Audition`] applies slot B, and keeps slot A for reverting.
+//! - [`SlotAction::Revert`] applies slot A again. Slot B stays staged, for auditioning it again.
+//! - [`SlotAction::Commit`] makes slot B the configuration in effect, which ends the audition.
+//! - [`SlotAction::Discard`] reverts, and empties slot B.
+//!
+//! Each switch applies a complete configuration at once (see [`DeviceConfig::apply`]), or nothing, if it is invalid.
+//! Staging a configuration while auditioning applies it immediately. During an audition, automatic saving is
+//! suspended (see [`crate::settings_store`]), such that slot A remains stored until the commit.
+use core::cell::RefCell;
+
+use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use protocol::hid::SlotAction;
+
+use crate::settings::DeviceConfig;
+use crate::*;
+
+/// A configuration slot.
+#[derive(Clone, Copy, PartialEq, Debug, defmt::Format)]
+pub enum Slot {
+    /// The configuration in effect.
+    A,
+    /// The experimental configuration.
+    B,
+}
+
+/// An error of switching configuration slots.
+#[derive(Clone, Copy, PartialEq, Debug, defmt::Format)]
+pub enum Error {
+    /// No configuration is staged in slot B.
+    NotStaged,
+    /// Slot B is not being auditioned.
+    NotAuditioning,
+    /// The configuration is invalid.
+    InvalidConfig,
+}
+
+/// The configurations that are held by the slots, other than the one in effect.
+struct Slots {
+    /// The configuration of slot B, if staged.
+    staged: Option<DeviceConfig>,
+    /// The configuration of slot A during an audition of slot B.
+    reverted: Option<DeviceConfig>,
+}
+
+/// The contents of the slots.
+static SLOTS: Mutex<ThreadModeRawMutex, RefCell<Slots>> = Mutex::new(RefCell::new(Slots {
+    staged: None,
+    reverted: None,
+}));
+
+/// The slot in effect.
+pub fn active_slot() -> Slot {
+    match SLOTS.lock(|slots| slots.borrow().reverted.is_some()) {
+        true => Slot::B,
+        false => Slot::A,
+    }
+}
+
+/// Whether a configuration is staged in slot B.
+pub fn staged() -> bool {
+    SLOTS.lock(|slots| slots.borrow().staged.is_some())
+}
+
+/// Stage a configuration in slot B, if it is valid. While auditioning, it is applied immediately.
+pub fn stage(config: DeviceConfig) -> Result<(), Error> {
+    if config
+        .dsp
+        .iter()
+        .any(|filter_config| filter_config.validate(SAMPLE_RATE_HZ).is_err())
+    {
+        return Err(Error::InvalidConfig);
+    }
+
+    if active_slot() == Slot::B {
+        config.clone().apply().map_err(|_| Error::InvalidConfig)?;
+    }
+
+    SLOTS.lock(|slots| slots.borrow_mut().staged = Some(config));
+
+    log!(info, "Staged a configuration in slot B");
+    Ok(())
+}
+
+/// Switch between the slots.
+pub fn perform(action: SlotAction) -> Result<(), Error> {
+    let (staged, reverted) = SLOTS.lock(|slots| {
+        let slots = slots.borrow();
+        (slots.staged.clone(), slots.reverted.clone())
+    });
+
+    match action {
+        SlotAction::Audition => {
+            let staged = staged.ok_or(Error::NotStaged)?;
+            let current = DeviceConfig::current();
+
+            staged.apply().map_err(|_| Error::InvalidConfig)?;
+            SLOTS.lock(|slots| {
+                slots.borrow_mut().reverted.get_or_insert(current);
+            });
+        }
+        SlotAction::Revert => {
+            reverted
+                .ok_or(Error::NotAuditioning)?
+                .apply()
+                .map_err(|_| Error::InvalidConfig)?;
+            SLOTS.lock(|slots| slots.borrow_mut().reverted = None);
+        }
+        SlotAction::Commit => {
+            let staged = staged.ok_or(Error::NotStaged)?;
+
+            if reverted.is_none() {
+                staged.apply().map_err(|_| Error::InvalidConfig)?;
+            }
+
+            SLOTS.lock(|slots| {
+                let mut slots = slots.borrow_mut();
+                slots.staged = None;
+                slots.reverted = None;
+            });
+        }
+        SlotAction::Discard => {
+            if let Some(reverted) = reverted {
+                reverted.apply().map_err(|_| Error::InvalidConfig)?;
+            }
+
+            SLOTS.lock(|slots| {
+                let mut slots = slots.borrow_mut();
+                slots.staged = None;
+                slots.reverted = None;
+            });
+        }
+    }
+
+    log!(info, "Configuration slot action: {:?}", action);
+    Ok(())
+}
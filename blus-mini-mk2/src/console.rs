@@ -0,0 +1,317 @@
+//! Text consoles on the USB CDC-ACM interface and the UART, which run the [`Shell`].
+//!
+//! Log events that are emitted with [`log!`](crate::log) go to defmt, and are additionally rendered as text
+//! into a buffer that is drained by the [`console_task`]. This allows capturing logs without a debug probe.
+use core::fmt::{self, Write as _};
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use defmt::debug;
+use embassy_futures::select::{select3, select4, Either3, Either4};
+use embassy_stm32::usart::BufferedUart;
+use embassy_stm32::{peripherals, usb};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::pipe::Pipe;
+use embassy_time::Instant;
+use embassy_usb::class::cdc_acm;
+use embassy_usb::driver::EndpointError;
+use embedded_io_async::{Read as _, Write as _};
+use grounded::uninit::GroundedCell;
+use heapless::String;
+
+use crate::notifications::{self, ChangeSubscriber};
+use crate::shell::{Shell, ShellBuffer};
+use crate::telemetry::{self, UART_TELEMETRY_SIGNAL, USB_TELEMETRY_SIGNAL};
+
+/// The maximum packet size of the CDC-ACM endpoints.
+pub const CONSOLE_MAX_PACKET_SIZE: usize = 64;
+
+/// The size of the log buffer. Logs are dropped, when it runs full (e.g. while no terminal is connected).
+const LOG_BUFFER_SIZE: usize = 2048;
+
+/// The maximum length of a single rendered log line. Longer lines are truncated.
+const MAX_LINE_LENGTH: usize = 128;
+
+/// The maximum length of a telemetry record.
+const MAX_TELEMETRY_LENGTH: usize = 320;
+
+/// Rendered log lines, waiting for transmission.
+static LOG_PIPE: Pipe<CriticalSectionRawMutex, LOG_BUFFER_SIZE> = Pipe::new();
+
+/// The minimum level of log events that are rendered to the console.
+static LOG_LEVEL: AtomicU8 = AtomicU8::new(Level::Info as u8);
+
+/// The severity of a log event.
+#[repr(u8)]
+#[derive(Clone, Copy, PartialEq, PartialOrd, Debug, defmt::Format)]
+#[allow(missing_docs)]
+pub enum Level {
+    Trace = 0,
+    Debug = 1,
+    Info = 2,
+    Warn = 3,
+    Error = 4,
+}
+
+impl Level {
+    /// The short name for display in the console.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Level::Trace => "TRACE",
+            Level::Debug => "DEBUG",
+            Level::Info => "INFO",
+            Level::Warn => "WARN",
+            Level::Error => "ERROR",
+        }
+    }
+}
+
+/// Set the minimum level of log events that are rendered to the console.
+pub fn set_log_level(level: Level) {
+    LOG_LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+/// Whether log events of a given level are rendered to the console.
+pub fn log_enabled(level: Level) -> bool {
+    level as u8 >= LOG_LEVEL.load(Ordering::Relaxed)
+}
+
+/// Render a log event into the console buffer.
+///
+/// Can be called from any context. The event is dropped, if the buffer is full.
+pub fn log(level: Level, args: fmt::Arguments) {
+    if !log_enabled(level) {
+        return;
+    }
+
+    let mut line: String<MAX_LINE_LENGTH> = String::new();
+    let timestamp_ms = Instant::now().as_millis();
+
+    // Formatting errors only occur for truncated lines, which are still emitted.
+    _ = write!(
+        line,
+        "[{:>6}.{:03}] {:<5} ",
+        timestamp_ms / 1000,
+        timestamp_ms % 1000,
+        level.name()
+    );
+    _ = line.write_fmt(args);
+
+    if line.len() >= MAX_LINE_LENGTH - 1 {
+        line.truncate(MAX_LINE_LENGTH - 2);
+    }
+    _ = line.push_str("\r\n");
+
+    // Only emit complete lines.
+    if LOG_PIPE.free_capacity() >= line.len() {
+        _ = LOG_PIPE.try_write(line.as_bytes());
+    }
+}
+
+/// Emit a log event to defmt and the console.
+///
+/// The first argument is the level (`trace`, `debug`, `info`, `warn`, or `error`), followed by a format string
+/// and its arguments. Arguments must implement both `defmt::Format` and the matching `core::fmt` trait.
+#[macro_export]
+macro_rules! log {
+    (trace, $($arg:tt)+) => {
+        $crate::log!(@emit trace, Trace, $($arg)+)
+    };
+    (debug, $($arg:tt)+) => {
+        $crate::log!(@emit debug, Debug, $($arg)+)
+    };
+    (info, $($arg:tt)+) => {
+        $crate::log!(@emit info, Info, $($arg)+)
+    };
+    (warn, $($arg:tt)+) => {
+        $crate::log!(@emit warn, Warn, $($arg)+)
+    };
+    (error, $($arg:tt)+) => {
+        $crate::log!(@emit error, Error, $($arg)+)
+    };
+    (@emit $defmt_level:ident, $level:ident, $($arg:tt)+) => {{
+        defmt::$defmt_level!($($arg)+);
+        $crate::console::log($crate::console::Level::$level, format_args!($($arg)+));
+    }};
+}
+
+#[derive(Debug)]
+struct Disconnected {}
+
+impl From<EndpointError> for Disconnected {
+    fn from(val: EndpointError) -> Self {
+        match val {
+            EndpointError::BufferOverflow => defmt::panic!("Buffer overflow"),
+            EndpointError::Disabled => Disconnected {},
+        }
+    }
+}
+
+impl embedded_io_async::Error for Disconnected {
+    fn kind(&self) -> embedded_io_async::ErrorKind {
+        embedded_io_async::ErrorKind::NotConnected
+    }
+}
+
+/// Writes to the CDC-ACM interface, split into packets.
+struct CdcWriter<'a, 'd, T: usb::Instance> {
+    sender: &'a mut cdc_acm::Sender<'d, usb::Driver<'d, T>>,
+    /// Whether the last packet was full-size, and must be followed by a zero-length packet.
+    terminate: bool,
+}
+
+impl<T: usb::Instance> embedded_io_async::ErrorType for CdcWriter<'_, '_, T> {
+    type Error = Disconnected;
+}
+
+impl<T: usb::Instance> embedded_io_async::Write for CdcWriter<'_, '_, T> {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        let length = buf.len().min(CONSOLE_MAX_PACKET_SIZE);
+
+        self.sender.write_packet(&buf[..length]).await?;
+        self.terminate = length == CONSOLE_MAX_PACKET_SIZE;
+
+        Ok(length)
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        // Terminate transfers that end on a full-size packet.
+        if self.terminate {
+            self.sender.write_packet(&[]).await?;
+            self.terminate = false;
+        }
+
+        Ok(())
+    }
+}
+
+/// Print a telemetry record above the command line.
+async fn write_telemetry<W: embedded_io_async::Write>(shell: &Shell, out: &mut W) -> Result<(), W::Error> {
+    let mut record: String<MAX_TELEMETRY_LENGTH> = String::new();
+
+    // Records are short enough for the buffer.
+    _ = telemetry::render(&mut record);
+
+    shell.clear(out).await?;
+    out.write_all(record.as_bytes()).await?;
+    out.write_all(b"\r\n").await?;
+    shell.redraw(out).await?;
+    out.flush().await
+}
+
+async fn console_handler<'d, T: usb::Instance + 'd>(
+    sender: &mut cdc_acm::Sender<'d, usb::Driver<'d, T>>,
+    receiver: &mut cdc_acm::Receiver<'d, usb::Driver<'d, T>>,
+    shell: &mut Shell,
+    changes: &mut ChangeSubscriber,
+) -> Result<(), Disconnected> {
+    let mut writer = CdcWriter {
+        sender,
+        terminate: false,
+    };
+
+    let mut input = [0u8; CONSOLE_MAX_PACKET_SIZE];
+    let mut log = [0u8; CONSOLE_MAX_PACKET_SIZE];
+
+    shell.redraw(&mut writer).await?;
+    writer.flush().await?;
+
+    loop {
+        match select4(
+            receiver.read_packet(&mut input),
+            LOG_PIPE.read(&mut log),
+            USB_TELEMETRY_SIGNAL.wait(),
+            changes.next_message_pure(),
+        )
+        .await
+        {
+            Either4::First(length) => {
+                let length = length?;
+                shell.receive(&input[..length], &mut writer).await?;
+            }
+            Either4::Second(length) => {
+                // Print pending logs above the command line.
+                shell.clear(&mut writer).await?;
+                writer.write_all(&log[..length]).await?;
+
+                // Lines are written completely, so an empty pipe ends on a line break.
+                while let Ok(length) = LOG_PIPE.try_read(&mut log) {
+                    writer.write_all(&log[..length]).await?;
+                }
+
+                shell.redraw(&mut writer).await?;
+                writer.flush().await?;
+            }
+            Either4::Third(()) => write_telemetry(shell, &mut writer).await?,
+            Either4::Fourth(change) => shell.notify(change, &mut writer).await?,
+        }
+    }
+}
+
+/// Run the shell on the USB console, and transmit log events to the host.
+#[embassy_executor::task]
+pub async fn console_task(
+    mut sender: cdc_acm::Sender<'static, usb::Driver<'static, peripherals::USB_OTG_HS>>,
+    mut receiver: cdc_acm::Receiver<'static, usb::Driver<'static, peripherals::USB_OTG_HS>>,
+) {
+    // Outside of the DTCM, which is too small for it.
+    #[link_section = ".axisram"]
+    static SHELL_BUFFER: GroundedCell<ShellBuffer> = GroundedCell::uninit();
+
+    // SAFETY: The task is only spawned once, so nothing else uses the buffer.
+    let buffer = unsafe {
+        SHELL_BUFFER.get().write(String::new());
+        &mut *SHELL_BUFFER.get()
+    };
+    let mut shell = Shell::new(buffer);
+    let mut changes = notifications::subscribe();
+
+    loop {
+        receiver.wait_connection().await;
+        debug!("Console connected");
+
+        _ = console_handler(&mut sender, &mut receiver, &mut shell, &mut changes).await;
+        debug!("Console disconnected");
+    }
+}
+
+/// Run the shell on the UART console.
+///
+/// Log events are only available on the USB console. Telemetry records are available on both.
+#[embassy_executor::task]
+pub async fn uart_console_task(uart: BufferedUart<'static>) {
+    // Outside of the DTCM, which is too small for it.
+    #[link_section = ".axisram"]
+    static SHELL_BUFFER: GroundedCell<ShellBuffer> = GroundedCell::uninit();
+
+    let (mut tx, mut rx) = uart.split();
+    // SAFETY: The task is only spawned once, so nothing else uses the buffer.
+    let buffer = unsafe {
+        SHELL_BUFFER.get().write(String::new());
+        &mut *SHELL_BUFFER.get()
+    };
+    let mut shell = Shell::new(buffer);
+    let mut changes = notifications::subscribe();
+    let mut input = [0u8; 32];
+
+    loop {
+        let result = match select3(
+            rx.read(&mut input),
+            UART_TELEMETRY_SIGNAL.wait(),
+            changes.next_message_pure(),
+        )
+        .await
+        {
+            Either3::First(Ok(length)) => shell.receive(&input[..length], &mut tx).await,
+            Either3::First(Err(error)) => {
+                debug!("UART console: Receive error {}", error);
+                continue;
+            }
+            Either3::Second(()) => write_telemetry(&shell, &mut tx).await,
+            Either3::Third(change) => shell.notify(change, &mut tx).await,
+        };
+
+        if let Err(error) = result {
+            debug!("UART console: Transmit error {}", error);
+        }
+    }
+}
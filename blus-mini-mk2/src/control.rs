@@ -0,0 +1,488 @@
+//! Control state that is shared between the audio routing task and the control interfaces.
+//!
+//! All values are stored in atomics, such that they can be read from interrupt handlers
+//! (e.g. the I2C slave) and from the audio routing task without locking. Control frontends read and write it
+//! through the parameter store (see [`crate::parameters`]).
+//!
+//! # Volume arbitration
+//!
+//! The master volume is the only volume of the device. Several writers change it, and the last writer wins:
+//! - Control frontends (shell, SCPI, HID, registers), the remote control, and restored settings.
+//! - The USB host, when it changes its volume (e.g. the volume slider of the operating system, or restoring it
+//!   after connecting). The louder channel sets the master volume, while the balance between left and right channel
+//!   only applies to USB audio (see [`Control::usb_gains`]).
+//! - The volume input: the potentiometer, when it is moved noticeably (soft takeover, such that resting pots never
+//!   override other writers), or the rotary encoder, which changes the master volume in steps.
+//! - The clip protection, which reduces the master volume on sustained clipping (see [`crate::clip_protection`]).
+//!
+//! Every write is published as change of the volume parameter (see [`crate::notifications`]). The last writer is
+//! available from [`Control::volume_writer`].
+//!
+//! With volumes per source ([`Control::set_source_volume`]), the master volume is remembered for the active source
+//! on every write. When another source becomes active, its remembered volume is restored, on behalf of the control
+//! frontends. Lacking a remembered volume, a source starts with the current one.
+//!
+//! # Master mute
+//!
+//! The master mute is toggled by a button (the second board button, by default), the remote control, and the control
+//! frontends, and shown on its LED (see [`crate::led`]). It persists across source changes, volume changes, and
+//! standby, until it is released. The audio routing ramps the gain down and up (see
+//! [`crate::audio_routing::SOFT_MUTE_TIME`]), such that muting does not click.
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU8, Ordering};
+
+use audio::AudioSource;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+use protocol::settings::{NO_SOURCE_ATTENUATION, SOURCE_COUNT};
+
+use crate::power_fail;
+use crate::supply;
+use crate::*;
+
+/// The volume attenuation at which the output is muted, in steps of 0.5 dB.
+pub const MUTED_ATTENUATION: u8 = 0xFF;
+
+/// The order in which sources are selected by [`Control::select_next_source`].
+pub const SOURCE_CYCLE: [AudioSource; 4] = [
+    AudioSource::None,
+    AudioSource::Usb,
+    AudioSource::Spdif,
+    AudioSource::Rpi,
+];
+
+/// A writer of the master volume.
+#[repr(u8)]
+#[derive(Clone, Copy, PartialEq, Debug, defmt::Format)]
+pub enum VolumeWriter {
+    /// Control frontends, the remote control, and restored settings.
+    Control = 0,
+    /// The USB host.
+    UsbHost = 1,
+    /// The potentiometer, or the rotary encoder.
+    VolumeInput = 2,
+    /// The clip protection.
+    ClipProtection = 3,
+}
+
+impl TryFrom<u8> for VolumeWriter {
+    type Error = u8;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(VolumeWriter::Control),
+            1 => Ok(VolumeWriter::UsbHost),
+            2 => Ok(VolumeWriter::VolumeInput),
+            3 => Ok(VolumeWriter::ClipProtection),
+            _ => Err(value),
+        }
+    }
+}
+
+/// Device control state.
+pub struct Control {
+    /// Master volume attenuation in steps of 0.5 dB.
+    attenuation_half_db: AtomicU8,
+    /// The last writer of the master volume.
+    volume_writer: AtomicU8,
+    /// Whether the master volume is remembered per source.
+    source_volume: AtomicBool,
+    /// The remembered attenuation of every source in steps of 0.5 dB, or [`NO_SOURCE_ATTENUATION`].
+    source_attenuations: [AtomicU8; SOURCE_COUNT],
+    /// Master mute.
+    muted: AtomicBool,
+    /// Standby, in which no source plays.
+    standby: AtomicBool,
+    /// The selected source. `AudioSource::None` selects the source automatically.
+    source_selection: AtomicU8,
+    /// The source that is currently playing.
+    active_source: AtomicU8,
+    /// Whether the amplifiers are set up and running.
+    amplifier_ready: AtomicBool,
+    /// The mask of amplifiers that are muted or shut down by their fault protection.
+    protected_amplifiers: AtomicU8,
+    /// Peak output levels of the last sample block, as attenuation below full-scale in steps of 0.5 dB.
+    meter_levels: [AtomicU8; OUTPUT_CHANNEL_COUNT],
+    /// The attenuation of the volume input (potentiometer or rotary encoder) in steps of 0.5 dB.
+    input_attenuation: AtomicU8,
+    /// The relative linear gains of the USB audio channels, as `f32` bits.
+    usb_gains: [AtomicU32; INPUT_CHANNEL_COUNT],
+    /// The number of sample blocks that were waiting for processing, when the last one was received.
+    buffer_fill: AtomicU8,
+    /// The number of sample blocks with clipped output samples since startup.
+    clipped_block_count: AtomicU32,
+}
+
+/// Bits of the device status byte.
+pub mod status {
+    /// The amplifiers are set up and running.
+    pub const AMPLIFIER_READY: u8 = 1 << 0;
+    /// A source is active and playing.
+    pub const PLAYING: u8 = 1 << 1;
+    /// The master output is muted.
+    pub const MUTED: u8 = 1 << 2;
+    /// The device is in standby.
+    pub const STANDBY: u8 = 1 << 3;
+    /// An amplifier is muted or shut down after faults.
+    pub const AMPLIFIER_FAULT: u8 = 1 << 4;
+    /// The amplifiers are shut down, as the supply voltage is too low.
+    pub const SUPPLY_UNDERVOLTAGE: u8 = 1 << 5;
+    /// The master volume was reduced by the clip protection, and not changed since.
+    pub const CLIP_PROTECTION: u8 = 1 << 6;
+}
+
+impl Control {
+    const fn new() -> Self {
+        Control {
+            attenuation_half_db: AtomicU8::new(0),
+            volume_writer: AtomicU8::new(VolumeWriter::Control as u8),
+            source_volume: AtomicBool::new(false),
+            source_attenuations: [const { AtomicU8::new(NO_SOURCE_ATTENUATION) }; SOURCE_COUNT],
+            muted: AtomicBool::new(false),
+            standby: AtomicBool::new(false),
+            source_selection: AtomicU8::new(AudioSource::None as u8),
+            active_source: AtomicU8::new(AudioSource::None as u8),
+            amplifier_ready: AtomicBool::new(false),
+            protected_amplifiers: AtomicU8::new(0),
+            meter_levels: [const { AtomicU8::new(MUTED_ATTENUATION) }; OUTPUT_CHANNEL_COUNT],
+            input_attenuation: AtomicU8::new(MUTED_ATTENUATION),
+            usb_gains: [const { AtomicU32::new(1.0f32.to_bits()) }; INPUT_CHANNEL_COUNT],
+            buffer_fill: AtomicU8::new(0),
+            clipped_block_count: AtomicU32::new(0),
+        }
+    }
+
+    /// The master volume attenuation in steps of 0.5 dB.
+    pub fn attenuation(&self) -> u8 {
+        self.attenuation_half_db.load(Ordering::Relaxed)
+    }
+
+    /// Set the master volume attenuation in steps of 0.5 dB, on behalf of the control frontends.
+    /// For example, an input of 0 gives an attenuation of 0 dB. An input of 100 gives -50 dB.
+    pub fn set_attenuation(&self, attenuation_half_db: u8) {
+        self.set_attenuation_by(VolumeWriter::Control, attenuation_half_db);
+    }
+
+    /// Set the master volume attenuation in steps of 0.5 dB, on behalf of a writer.
+    ///
+    /// With volumes per source, it is remembered for the active source.
+    pub fn set_attenuation_by(&self, writer: VolumeWriter, attenuation_half_db: u8) {
+        self.attenuation_half_db.store(attenuation_half_db, Ordering::Relaxed);
+        self.volume_writer.store(writer as u8, Ordering::Relaxed);
+        self.remember_attenuation(self.active_source());
+    }
+
+    /// Whether the master volume is remembered per source.
+    pub fn source_volume(&self) -> bool {
+        self.source_volume.load(Ordering::Relaxed)
+    }
+
+    /// Enable or disable volumes per source. Enabling starts with the current volume for the active source.
+    pub fn set_source_volume(&self, enabled: bool) {
+        self.source_volume.store(enabled, Ordering::Relaxed);
+        self.remember_attenuation(self.active_source());
+    }
+
+    /// The remembered attenuation of every source (by its identifier) in steps of 0.5 dB, or
+    /// [`NO_SOURCE_ATTENUATION`].
+    pub fn source_attenuations(&self) -> [u8; SOURCE_COUNT] {
+        self.source_attenuations
+            .each_ref()
+            .map(|attenuation| attenuation.load(Ordering::Relaxed))
+    }
+
+    /// Replace the remembered attenuations of all sources, e.g. from restored settings.
+    pub fn set_source_attenuations(&self, attenuations: [u8; SOURCE_COUNT]) {
+        for (remembered, attenuation) in self.source_attenuations.iter().zip(attenuations) {
+            remembered.store(attenuation, Ordering::Relaxed);
+        }
+    }
+
+    /// Remember the master volume for a source, if volumes per source are enabled.
+    fn remember_attenuation(&self, source: AudioSource) {
+        if !self.source_volume() || source == AudioSource::None {
+            return;
+        }
+
+        if let Some(remembered) = self.source_attenuations.get(source as usize) {
+            remembered.store(self.attenuation(), Ordering::Relaxed);
+        }
+    }
+
+    /// The last writer of the master volume.
+    pub fn volume_writer(&self) -> VolumeWriter {
+        VolumeWriter::try_from(self.volume_writer.load(Ordering::Relaxed)).unwrap_or(VolumeWriter::Control)
+    }
+
+    /// Whether the master output is muted.
+    pub fn muted(&self) -> bool {
+        self.muted.load(Ordering::Relaxed)
+    }
+
+    /// Mute or unmute the master output. The mute stays engaged, until it is released by another call.
+    pub fn set_muted(&self, muted: bool) {
+        if self.muted.swap(muted, Ordering::Relaxed) != muted {
+            STATUS_CHANGED_SIGNAL.signal(());
+        }
+    }
+
+    /// Whether the device is in standby.
+    pub fn standby(&self) -> bool {
+        self.standby.load(Ordering::Relaxed)
+    }
+
+    /// Enter or leave standby. In standby, the output is silent, and no source is allowed to play.
+    pub fn set_standby(&self, standby: bool) {
+        if self.standby.swap(standby, Ordering::Relaxed) != standby {
+            STATUS_CHANGED_SIGNAL.signal(());
+        }
+    }
+
+    /// Whether the output is silent for any reason: mute, muted attenuation, standby, amplifiers that are not
+    /// running (e.g. while switching sources), supply undervoltage (see [`supply`]), an imminent power loss (see
+    /// [`power_fail`]), or DC at the output (see [`dc_protection`]). The mute relay follows it (see
+    /// [`crate::mute_relay`]).
+    pub fn silent(&self) -> bool {
+        self.muted()
+            || self.attenuation() == MUTED_ATTENUATION
+            || self.standby()
+            || !self.amplifier_ready()
+            || supply::undervoltage()
+            || power_fail::power_failing()
+            || dc_protection::tripped() != 0
+    }
+
+    /// The linear master gain, derived from the attenuation, or `0.0`, while the output is silent (see
+    /// [`Control::silent`]).
+    pub fn gain(&self) -> f32 {
+        if self.silent() {
+            0.0
+        } else {
+            db_to_linear(-(self.attenuation() as f32) / 2.0)
+        }
+    }
+
+    /// The selected source. `AudioSource::None` means automatic selection.
+    pub fn source_selection(&self) -> AudioSource {
+        AudioSource::try_from(self.source_selection.load(Ordering::Relaxed)).unwrap_or(AudioSource::None)
+    }
+
+    /// Select a source. `AudioSource::None` selects automatically: the first source that delivers samples wins.
+    pub fn set_source_selection(&self, source: AudioSource) {
+        self.source_selection.store(source as u8, Ordering::Relaxed);
+    }
+
+    /// Select the next source, in the order automatic, USB, S/PDIF, and Raspberry Pi.
+    pub fn select_next_source(&self) {
+        let selection = self.source_selection();
+        let index = SOURCE_CYCLE
+            .iter()
+            .position(|source| *source == selection)
+            .unwrap_or_default();
+
+        self.set_source_selection(SOURCE_CYCLE[(index + 1) % SOURCE_CYCLE.len()]);
+    }
+
+    /// Whether a source may play, given the current source selection. No source may play in standby, or during
+    /// supply undervoltage.
+    pub fn source_allowed(&self, source: AudioSource) -> bool {
+        if self.standby() || supply::undervoltage() {
+            return false;
+        }
+
+        let selection = self.source_selection();
+        selection == AudioSource::None || selection == source
+    }
+
+    /// The source that is currently playing.
+    pub fn active_source(&self) -> AudioSource {
+        AudioSource::try_from(self.active_source.load(Ordering::Relaxed)).unwrap_or(AudioSource::None)
+    }
+
+    /// Update the source that is currently playing. With volumes per source, a newly active source gets its
+    /// remembered volume.
+    pub fn set_active_source(&self, source: AudioSource) {
+        if self.active_source.swap(source as u8, Ordering::Relaxed) == source as u8 {
+            return;
+        }
+
+        if self.source_volume() {
+            match self
+                .source_attenuations
+                .get(source as usize)
+                .map(|remembered| remembered.load(Ordering::Relaxed))
+            {
+                Some(attenuation) if attenuation != NO_SOURCE_ATTENUATION => {
+                    self.set_attenuation_by(VolumeWriter::Control, attenuation);
+                }
+                _ => self.remember_attenuation(source),
+            }
+        }
+
+        STATUS_CHANGED_SIGNAL.signal(());
+    }
+
+    /// Whether the amplifiers are set up and running.
+    pub fn amplifier_ready(&self) -> bool {
+        self.amplifier_ready.load(Ordering::Relaxed)
+    }
+
+    /// Update the amplifier state.
+    pub fn set_amplifier_ready(&self, ready: bool) {
+        if self.amplifier_ready.swap(ready, Ordering::Relaxed) != ready {
+            STATUS_CHANGED_SIGNAL.signal(());
+        }
+    }
+
+    /// The mask of amplifiers that are muted or shut down by their fault protection, by output channel.
+    pub fn protected_amplifiers(&self) -> u8 {
+        self.protected_amplifiers.load(Ordering::Relaxed)
+    }
+
+    /// Update the mask of protected amplifiers.
+    pub fn set_protected_amplifiers(&self, mask: u8) {
+        if self.protected_amplifiers.swap(mask, Ordering::Relaxed) != mask {
+            STATUS_CHANGED_SIGNAL.signal(());
+        }
+    }
+
+    /// The peak output level of a channel, as attenuation below full-scale in steps of 0.5 dB.
+    pub fn meter_level(&self, channel: usize) -> u8 {
+        self.meter_levels[channel].load(Ordering::Relaxed)
+    }
+
+    /// Update the peak output levels from linear sample magnitudes.
+    pub fn set_meter_levels(&self, peak_levels: &[f32; OUTPUT_CHANNEL_COUNT]) {
+        for (meter_level, peak_level) in self.meter_levels.iter().zip(peak_levels) {
+            meter_level.store(level_to_attenuation(*peak_level), Ordering::Relaxed);
+        }
+    }
+
+    /// The attenuation of the volume input (potentiometer or rotary encoder) in steps of 0.5 dB.
+    pub fn input_attenuation(&self) -> u8 {
+        self.input_attenuation.load(Ordering::Relaxed)
+    }
+
+    /// Update the attenuation of the volume input. Does not change the master volume by itself.
+    pub fn set_input_attenuation(&self, attenuation_half_db: u8) {
+        self.input_attenuation.store(attenuation_half_db, Ordering::Relaxed);
+    }
+
+    /// The relative linear gains of the USB audio channels, from the host's channel volumes.
+    /// The loudest channel has unity gain, since its volume is applied as master volume.
+    pub fn usb_gains(&self) -> [f32; INPUT_CHANNEL_COUNT] {
+        core::array::from_fn(|channel| f32::from_bits(self.usb_gains[channel].load(Ordering::Relaxed)))
+    }
+
+    /// Update the relative linear gains of the USB audio channels.
+    pub fn set_usb_gains(&self, gains: &[f32; INPUT_CHANNEL_COUNT]) {
+        for (stored, gain) in self.usb_gains.iter().zip(gains) {
+            stored.store(gain.to_bits(), Ordering::Relaxed);
+        }
+    }
+
+    /// The number of sample blocks that were waiting for processing, out of [`SAMPLE_BLOCK_COUNT`].
+    pub fn buffer_fill(&self) -> u8 {
+        self.buffer_fill.load(Ordering::Relaxed)
+    }
+
+    /// Update the number of sample blocks that are waiting for processing.
+    pub fn set_buffer_fill(&self, block_count: usize) {
+        self.buffer_fill
+            .store(block_count.min(u8::MAX as usize) as u8, Ordering::Relaxed);
+    }
+
+    /// The number of sample blocks with clipped output samples since startup.
+    pub fn clipped_block_count(&self) -> u32 {
+        self.clipped_block_count.load(Ordering::Relaxed)
+    }
+
+    /// Count a sample block with clipped output samples.
+    pub fn count_clipped_block(&self) {
+        self.clipped_block_count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// The device status byte, composed of the bits in [`status`].
+    pub fn status(&self) -> u8 {
+        let mut value = 0;
+
+        if self.amplifier_ready() {
+            value |= status::AMPLIFIER_READY;
+        }
+        if self.active_source() != AudioSource::None {
+            value |= status::PLAYING;
+        }
+        if self.muted() {
+            value |= status::MUTED;
+        }
+        if self.standby() {
+            value |= status::STANDBY;
+        }
+        if self.protected_amplifiers() != 0 {
+            value |= status::AMPLIFIER_FAULT;
+        }
+        if supply::undervoltage() {
+            value |= status::SUPPLY_UNDERVOLTAGE;
+        }
+        if self.volume_writer() == VolumeWriter::ClipProtection {
+            value |= status::CLIP_PROTECTION;
+        }
+
+        value
+    }
+}
+
+/// Convert a linear level to an attenuation below full-scale in steps of 0.5 dB.
+pub fn level_to_attenuation(level: f32) -> u8 {
+    if level <= 0.0 {
+        return MUTED_ATTENUATION;
+    }
+
+    let attenuation = -40.0 * level.log10();
+    attenuation.clamp(0.0, MUTED_ATTENUATION as f32) as u8
+}
+
+/// The name of a source, as used by the text interfaces.
+pub fn source_name(source: AudioSource) -> &'static str {
+    match source {
+        AudioSource::None => "none",
+        AudioSource::Usb => "usb",
+        AudioSource::Spdif => "spdif",
+        AudioSource::Ext => "ext",
+        AudioSource::Rpi => "rpi",
+    }
+}
+
+/// The name of a source selection. Automatic selection is called `auto`.
+pub fn source_selection_name(selection: AudioSource) -> &'static str {
+    match selection {
+        AudioSource::None => "auto",
+        source => source_name(source),
+    }
+}
+
+/// The name of a volume writer, as used by the text interfaces.
+pub fn volume_writer_name(writer: VolumeWriter) -> &'static str {
+    match writer {
+        VolumeWriter::Control => "control",
+        VolumeWriter::UsbHost => "usb-host",
+        VolumeWriter::VolumeInput => "volume-input",
+        VolumeWriter::ClipProtection => "clip-protection",
+    }
+}
+
+/// Find a source selection by its name (see [`source_selection_name`]).
+pub fn parse_source_selection(name: &str) -> Option<AudioSource> {
+    match name {
+        "auto" => Some(AudioSource::None),
+        "usb" => Some(AudioSource::Usb),
+        "spdif" => Some(AudioSource::Spdif),
+        "rpi" => Some(AudioSource::Rpi),
+        _ => None,
+    }
+}
+
+/// The global control state.
+pub static CONTROL: Control = Control::new();
+
+/// Signal that is emitted when the device status (see [`Control::status`]) changes.
+pub static STATUS_CHANGED_SIGNAL: Signal<CriticalSectionRawMutex, ()> = Signal::new();
@@ -0,0 +1,141 @@
+//! Measurement of the CPU load, in total and by sections of work, such as the signal processing.
+//!
+//! The load is counted by the cycle counter of the core (DWT), which halts while the core sleeps, i.e. while the
+//! executor waits for events. Over every [`WINDOW`], the total load is the share of counted cycles in the cycles of
+//! the core clock ([`CORE_CLOCK_HZ`]). With a debugger attached, the core clock may keep running in sleep, such that
+//! the total load reads close to 100 %.
+//!
+//! Sections of work (see [`Section`]) are measured around their synchronous code with [`measure`]. Sections on the
+//! thread executor include the time of the audio executor, when it preempts them (see [`crate::audio_routing`]). The
+//! signal processing is also measured per sample block: its headroom is the share of the block period that remained
+//! after the slowest block of the window. Added EQ stages or FIR taps fit the budget, as long as headroom remains.
+use core::sync::atomic::{AtomicU16, AtomicU32, Ordering};
+
+use cortex_m::peripheral::{DCB, DWT};
+use embassy_time::{Duration, Instant, Ticker};
+
+use crate::*;
+
+/// The frequency of the core clock (PLL1 P).
+pub const CORE_CLOCK_HZ: u32 = 245_760_000;
+
+/// The window, over which loads are measured.
+pub const WINDOW: Duration = Duration::from_secs(1);
+
+/// The number of measured sections.
+pub const SECTION_COUNT: usize = 3;
+
+/// A measured section of work.
+#[derive(Clone, Copy, PartialEq, Debug, defmt::Format)]
+pub enum Section {
+    /// The signal processing of sample blocks (filters, FIR, and gain).
+    Dsp = 0,
+    /// The measurements of the touch pads by their discharge time.
+    Touch = 1,
+    /// The rendering of the status display.
+    Display = 2,
+}
+
+impl Section {
+    /// All sections.
+    pub const ALL: [Section; SECTION_COUNT] = [Section::Dsp, Section::Touch, Section::Display];
+}
+
+/// The cycles of every section in the running window.
+static CYCLES: [AtomicU32; SECTION_COUNT] = [const { AtomicU32::new(0) }; SECTION_COUNT];
+
+/// The highest share of the block period that the signal processing of a block took in the running window, in
+/// per mille.
+static DSP_PEAK: AtomicU32 = AtomicU32::new(0);
+
+/// The load of every section in the last window, in per mille.
+static LOADS: [AtomicU16; SECTION_COUNT] = [const { AtomicU16::new(0) }; SECTION_COUNT];
+
+/// The total load in the last window, in per mille.
+static TOTAL_LOAD: AtomicU16 = AtomicU16::new(0);
+
+/// The headroom of the signal processing in the last window, in per mille.
+static DSP_HEADROOM: AtomicU16 = AtomicU16::new(1000);
+
+/// The name of a section, as used by the text interfaces.
+pub fn section_name(section: Section) -> &'static str {
+    match section {
+        Section::Dsp => "dsp",
+        Section::Touch => "touch",
+        Section::Display => "display",
+    }
+}
+
+/// Start the cycle counter.
+pub fn init(dcb: &mut DCB, dwt: &mut DWT) {
+    dcb.enable_trace();
+    DWT::unlock();
+    dwt.enable_cycle_counter();
+}
+
+/// Run the code of a section, and count its cycles.
+pub fn measure<R>(section: Section, f: impl FnOnce() -> R) -> R {
+    let start = DWT::cycle_count();
+    let result = f();
+
+    CYCLES[section as usize].fetch_add(DWT::cycle_count().wrapping_sub(start), Ordering::Relaxed);
+    result
+}
+
+/// Run the signal processing of a sample block with a number of frames, and count its cycles.
+pub fn measure_dsp<R>(frame_count: usize, f: impl FnOnce() -> R) -> R {
+    let start = DWT::cycle_count();
+    let result = f();
+    let cycles = DWT::cycle_count().wrapping_sub(start);
+
+    CYCLES[Section::Dsp as usize].fetch_add(cycles, Ordering::Relaxed);
+
+    // The cycles of the block period.
+    let budget = frame_count as u64 * CORE_CLOCK_HZ as u64 / SAMPLE_RATE_HZ as u64;
+    if budget > 0 {
+        DSP_PEAK.fetch_max((cycles as u64 * 1000 / budget) as u32, Ordering::Relaxed);
+    }
+
+    result
+}
+
+/// The total load in percent.
+pub fn total_load_percent() -> f32 {
+    TOTAL_LOAD.load(Ordering::Relaxed) as f32 / 10.0
+}
+
+/// The load of a section in percent.
+pub fn load_percent(section: Section) -> f32 {
+    LOADS[section as usize].load(Ordering::Relaxed) as f32 / 10.0
+}
+
+/// The headroom of the signal processing in percent of the block period.
+pub fn dsp_headroom_percent() -> f32 {
+    DSP_HEADROOM.load(Ordering::Relaxed) as f32 / 10.0
+}
+
+/// Evaluates the loads of every window.
+#[embassy_executor::task]
+pub async fn cpu_load_task() {
+    let mut ticker = Ticker::every(WINDOW);
+    let mut start = (Instant::now(), DWT::cycle_count());
+
+    loop {
+        ticker.next().await;
+
+        let now = (Instant::now(), DWT::cycle_count());
+        let elapsed_cycles = (now.0 - start.0).as_micros() * CORE_CLOCK_HZ as u64 / 1_000_000;
+        let per_mille = |cycles: u32| (cycles as u64 * 1000 / elapsed_cycles.max(1)).min(1000) as u16;
+
+        TOTAL_LOAD.store(per_mille(now.1.wrapping_sub(start.1)), Ordering::Relaxed);
+
+        for (load, cycles) in LOADS.iter().zip(CYCLES.iter()) {
+            load.store(per_mille(cycles.swap(0, Ordering::Relaxed)), Ordering::Relaxed);
+        }
+
+        let peak = DSP_PEAK.swap(0, Ordering::Relaxed).min(1000);
+        DSP_HEADROOM.store(1000 - peak as u16, Ordering::Relaxed);
+
+        start = now;
+    }
+}
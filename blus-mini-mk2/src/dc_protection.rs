@@ -0,0 +1,74 @@
+//! Protection of the speakers against DC at the output, e.g. from a stuck filter state or bad coefficients.
+//!
+//! The audio routing passes the mean of every processed sample block (see [`update`]), which is averaged over
+//! [`AVERAGING_TIME`] per output channel. Music averages to almost zero over that time, so an average beyond
+//! [`THRESHOLD`] is a sustained DC offset. It trips an emergency mute of the output (see
+//! [`crate::control::Control::gain`]), records an event, and is reported as a fault (see [`crate::faults`]).
+//!
+//! The mute lasts until the source changes, which also resets the filter states, or the faults are cleared (shell
+//! `faults clear`, see [`reset`]).
+use core::cell::Cell;
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_time::Duration;
+use protocol::event_log::EventKind;
+
+use crate::event_log;
+use crate::*;
+
+/// The averaging time of the output offsets.
+pub const AVERAGING_TIME: Duration = Duration::from_millis(500);
+
+/// The output offset relative to full-scale, beyond which the output is muted (-20 dBFS).
+pub const THRESHOLD: f32 = 0.1;
+
+/// The averaged output offsets relative to full-scale, by output channel.
+static OFFSETS: Mutex<CriticalSectionRawMutex, Cell<[f32; OUTPUT_CHANNEL_COUNT]>> =
+    Mutex::new(Cell::new([0.0; OUTPUT_CHANNEL_COUNT]));
+
+/// The mask of output channels that tripped the protection.
+static TRIPPED: AtomicU8 = AtomicU8::new(0);
+
+/// The mask of output channels that tripped the protection, while the output is muted. `0`, if not tripped.
+pub fn tripped() -> u8 {
+    TRIPPED.load(Ordering::Relaxed)
+}
+
+/// Update the output offsets by the means of the output channels in a sample block, with its number of samples per
+/// channel, and trip the protection on a sustained offset.
+pub fn update(means: &[f32; OUTPUT_CHANNEL_COUNT], sample_count: usize) {
+    let block_time_s = sample_count as f32 / SAMPLE_RATE_HZ as f32;
+    let weight = (block_time_s / (AVERAGING_TIME.as_millis() as f32 / 1000.0)).min(1.0);
+
+    let offset_channels = OFFSETS.lock(|offsets| {
+        let mut state = offsets.get();
+        let mut mask = 0u8;
+
+        for (channel, mean) in means.iter().enumerate() {
+            state[channel] += (mean - state[channel]) * weight;
+
+            if state[channel].abs() >= THRESHOLD {
+                mask |= 1 << channel;
+            }
+        }
+
+        offsets.set(state);
+        mask
+    });
+
+    if offset_channels != 0 && TRIPPED.fetch_or(offset_channels, Ordering::Relaxed) == 0 {
+        log!(error, "DC at the output (channels {:#x}), muting", offset_channels);
+        event_log::record(EventKind::OutputDc, offset_channels);
+    }
+}
+
+/// Forget the output offsets, and release the mute.
+pub fn reset() {
+    OFFSETS.lock(|offsets| offsets.set([0.0; OUTPUT_CHANNEL_COUNT]));
+
+    if TRIPPED.swap(0, Ordering::Relaxed) != 0 {
+        log!(info, "DC protection released");
+    }
+}
@@ -0,0 +1,118 @@
+//! Identification of the device and its firmware (see [`protocol::device_info`]), and the handshake with host tools
+//! (see [`protocol::handshake`]).
+//!
+//! The git commit hash and the build date are provided by the build script.
+use embassy_stm32::uid;
+use embassy_time::Instant;
+use protocol::device_info::{DeviceInfo, BUILD_DATE_LENGTH, GIT_HASH_LENGTH};
+use protocol::handshake::{self, Feature, Handshake};
+use protocol::parameter::PARAMETER_COUNT;
+use protocol::settings;
+
+use crate::config_json::CONFIG_VERSION;
+use crate::gpio_expander;
+use crate::provisioning::provisioning;
+use crate::registers::REGISTER_MAP_VERSION;
+
+/// The hardware revision of the board, unless provisioned otherwise (see [`crate::provisioning`]).
+pub const HARDWARE_REVISION: u8 = 2;
+
+/// The firmware version, as major, minor, and patch version.
+const VERSION: [u8; 3] = [
+    parse_u8(env!("CARGO_PKG_VERSION_MAJOR")),
+    parse_u8(env!("CARGO_PKG_VERSION_MINOR")),
+    parse_u8(env!("CARGO_PKG_VERSION_PATCH")),
+];
+
+/// The features of every build.
+const FEATURES: u32 = Feature::Notifications.mask()
+    | Feature::Reboot.mask()
+    | Feature::BulkTransfer.mask()
+    | Feature::Telemetry.mask()
+    | Feature::StartupScript.mask()
+    | Feature::EventLog.mask()
+    | Feature::Backup.mask()
+    | Feature::ConfigSlots.mask()
+    | Feature::ThermalLog.mask();
+
+const GIT_HASH: [u8; GIT_HASH_LENGTH] = ascii(env!("GIT_HASH"));
+const BUILD_DATE: [u8; BUILD_DATE_LENGTH] = ascii(env!("BUILD_DATE"));
+
+/// Parse a decimal number at compile time.
+const fn parse_u8(text: &str) -> u8 {
+    let bytes = text.as_bytes();
+    let mut value: u8 = 0;
+    let mut index = 0;
+
+    while index < bytes.len() {
+        value = value * 10 + (bytes[index] - b'0');
+        index += 1;
+    }
+
+    value
+}
+
+/// Copy text into a fixed-size array at compile time, truncated or padded with zeros.
+const fn ascii<const N: usize>(text: &str) -> [u8; N] {
+    let bytes = text.as_bytes();
+    let mut array = [0u8; N];
+    let mut index = 0;
+
+    while index < N && index < bytes.len() {
+        array[index] = bytes[index];
+        index += 1;
+    }
+
+    array
+}
+
+/// Get the current device information.
+pub fn device_info() -> DeviceInfo {
+    DeviceInfo {
+        version: VERSION,
+        hardware_revision: provisioning().map_or(HARDWARE_REVISION, |provisioning| provisioning.hardware_revision),
+        unique_id: *uid::uid(),
+        uptime_s: Instant::now().as_secs().min(u32::MAX as u64) as u32,
+        git_hash: GIT_HASH,
+        build_date: BUILD_DATE,
+    }
+}
+
+/// Get the protocol versions and the available features.
+pub fn handshake() -> Handshake {
+    let mut features = FEATURES;
+
+    if cfg!(feature = "encoder") {
+        features |= Feature::Encoder.mask();
+    }
+
+    if gpio_expander::present() {
+        features |= Feature::GpioExpander.mask();
+    }
+
+    Handshake {
+        protocol_version: [handshake::MAJOR_VERSION, handshake::MINOR_VERSION],
+        settings_version: [settings::MAJOR_VERSION, settings::MINOR_VERSION],
+        register_map_version: REGISTER_MAP_VERSION,
+        config_version: CONFIG_VERSION as u8,
+        parameter_count: PARAMETER_COUNT as u16,
+        features,
+    }
+}
+
+/// The name of a feature, as used by the text interfaces.
+pub fn feature_name(feature: Feature) -> &'static str {
+    match feature {
+        Feature::Notifications => "notifications",
+        Feature::Reboot => "reboot",
+        Feature::BulkTransfer => "bulk-transfer",
+        Feature::Telemetry => "telemetry",
+        Feature::StartupScript => "startup-script",
+        Feature::Encoder => "encoder",
+        Feature::GpioExpander => "gpio-expander",
+        Feature::EventLog => "event-log",
+        Feature::Backup => "backup",
+        Feature::ConfigSlots => "config-slots",
+        Feature::ThermalLog => "thermal-log",
+    }
+}
@@ -0,0 +1,470 @@
+//! An optional SSD1306 OLED status display (128x64) on the amplifier I2C bus.
+//!
+//! The display shows, from the top:
+//! - The active source, or `STANDBY`.
+//! - The master volume in dB, or `MUTE`.
+//! - The sample rate, while a source is active.
+//! - An icon for every active fault (see [`crate::faults`]), in the order of [`Fault::ALL`].
+//!
+//! While the menu is open, it replaces the device state (see [`crate::menu`]). After startup, the display shows the
+//! firmware version, git commit hash, and build date for [`SPLASH_TIME`], such that it can be told without a host.
+//!
+//! The [`display_task`] redraws on parameter changes (see [`crate::notifications`]), and every [`REFRESH_INTERVAL`]
+//! for faults, which are not parameters. Only the changed pages of the display are written. The contrast follows the
+//! other LEDs, also in night mode (see [`crate::led::current_brightness`]). Without a display, the task retries
+//! every [`RETRY_INTERVAL`].
+//!
+//! Against burn-in, the display blanks (or dims) after a time without control activity (see [`set_timeout`]). Any
+//! change of the watched parameters wakes it, e.g. by the volume, mute, source, or standby controls, and so does any
+//! menu input. The timeout is not stored with the settings; a startup script can configure it (shell `display`, see
+//! [`crate::startup_script`]).
+//!
+//! The SSD1306 is specified for I2C Fast-mode, but common modules also work with the Fast-mode Plus of the bus.
+use core::fmt::Write as _;
+use core::sync::atomic::{AtomicBool, AtomicU16, Ordering};
+
+use embassy_embedded_hal::shared_bus::blocking::i2c::I2cDevice;
+use embassy_futures::select::{select3, Either3};
+use embassy_stm32::i2c::I2c;
+use embassy_stm32::mode::Async;
+use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
+use embassy_time::{Duration, Instant, Timer};
+use embedded_hal::i2c::I2c as _;
+use heapless::String;
+
+use crate::control::{CONTROL, MUTED_ATTENUATION};
+use crate::cpu_load::{self, Section};
+use crate::device_info::device_info;
+use crate::faults::{self, Fault};
+use crate::gpio_expander::I2cBus;
+use crate::led;
+use crate::menu::{self, Line};
+use crate::notifications;
+use crate::*;
+
+/// The interval between redraws without parameter changes.
+pub const REFRESH_INTERVAL: Duration = Duration::from_millis(500);
+
+/// The time that the firmware version is shown after startup.
+pub const SPLASH_TIME: Duration = Duration::from_secs(3);
+
+/// The interval between attempts to reach an absent display.
+pub const RETRY_INTERVAL: Duration = Duration::from_secs(5);
+
+/// The default time without control activity, after which the screensaver starts.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+/// The maximum time without control activity, after which the screensaver starts.
+pub const MAX_TIMEOUT: Duration = Duration::from_secs(60 * 60);
+
+/// The contrast of a dimmed display.
+const DIM_CONTRAST: u8 = 1;
+
+/// The I2C address (SA0 pin low).
+const ADDRESS: u8 = 0x3C;
+
+/// The width of the display in pixels.
+const WIDTH: usize = 128;
+
+/// The number of pages of the display, with eight rows of pixels each.
+const PAGE_COUNT: usize = 8;
+
+/// The control byte before commands.
+const CONTROL_COMMAND: u8 = 0x00;
+
+/// The control byte before display data.
+const CONTROL_DATA: u8 = 0x40;
+
+/// The initialization, for a 128x64 display with the internal charge pump, and horizontal addressing.
+const INIT_SEQUENCE: [u8; 25] = [
+    0xAE, // Display off
+    0xD5, 0x80, // Clock divider
+    0xA8, 0x3F, // Multiplex ratio (64 rows)
+    0xD3, 0x00, // No display offset
+    0x40, // Start line 0
+    0x8D, 0x14, // Charge pump on
+    0x20, 0x00, // Horizontal addressing
+    0xA1, // Segments remapped
+    0xC8, // Rows scanned in reverse
+    0xDA, 0x12, // Alternative row configuration
+    0x81, 0xCF, // Contrast
+    0xD9, 0xF1, // Pre-charge period
+    0xDB, 0x40, // VCOMH level
+    0xA4, // Show the RAM contents
+    0xA6, // Not inverted
+    0xAF, // Display on
+];
+
+/// The command that sets the contrast.
+const SET_CONTRAST: u8 = 0x81;
+
+/// The command that switches the display off (sleep mode).
+const DISPLAY_OFF: u8 = 0xAE;
+
+/// The command that switches the display on.
+const DISPLAY_ON: u8 = 0xAF;
+
+/// The width of a character, with its spacing.
+const CHARACTER_WIDTH: usize = 6;
+
+/// The width of a fault icon, with its spacing.
+const ICON_WIDTH: usize = 10;
+
+/// Whether the display responds.
+static PRESENT: AtomicBool = AtomicBool::new(false);
+
+/// The time without control activity in seconds, after which the screensaver starts, or `0`, if disabled.
+static TIMEOUT_S: AtomicU16 = AtomicU16::new(DEFAULT_TIMEOUT.as_secs() as u16);
+
+/// Whether the screensaver dims the display, instead of blanking it.
+static DIM: AtomicBool = AtomicBool::new(false);
+
+/// Whether the screensaver is active.
+static IDLE: AtomicBool = AtomicBool::new(false);
+
+/// The contents of the display: one byte per column and page, with the top row in the least significant bit.
+type Frame = [[u8; WIDTH]; PAGE_COUNT];
+
+/// Whether the display responds.
+pub fn present() -> bool {
+    PRESENT.load(Ordering::Relaxed)
+}
+
+/// The time without control activity, after which the screensaver starts, or `None`, if disabled.
+pub fn timeout() -> Option<Duration> {
+    match TIMEOUT_S.load(Ordering::Relaxed) {
+        0 => None,
+        timeout_s => Some(Duration::from_secs(timeout_s as u64)),
+    }
+}
+
+/// Set the time without control activity (whole seconds, up to [`MAX_TIMEOUT`]), after which the screensaver starts,
+/// or disable it with `None`.
+pub fn set_timeout(timeout: Option<Duration>) {
+    let timeout_s = timeout.map_or(0, |timeout| timeout.min(MAX_TIMEOUT).as_secs().max(1));
+    TIMEOUT_S.store(timeout_s as u16, Ordering::Relaxed);
+}
+
+/// Whether the screensaver dims the display, instead of blanking it.
+pub fn dim() -> bool {
+    DIM.load(Ordering::Relaxed)
+}
+
+/// Set whether the screensaver dims the display, instead of blanking it.
+pub fn set_dim(dim: bool) {
+    DIM.store(dim, Ordering::Relaxed);
+}
+
+/// Whether the screensaver is active.
+pub fn idle() -> bool {
+    IDLE.load(Ordering::Relaxed)
+}
+
+/// An SSD1306 on a shared bus.
+struct Ssd1306<'a> {
+    i2c: I2cDevice<'a, ThreadModeRawMutex, I2c<'static, Async>>,
+}
+
+impl Ssd1306<'_> {
+    /// Write commands.
+    fn command(&mut self, commands: &[u8]) -> Result<(), ()> {
+        for command in commands {
+            self.i2c.write(ADDRESS, &[CONTROL_COMMAND, *command]).map_err(|_| ())?;
+        }
+
+        Ok(())
+    }
+
+    /// Write the contents of a page.
+    fn write_page(&mut self, page: usize, data: &[u8; WIDTH]) -> Result<(), ()> {
+        self.command(&[0x21, 0, (WIDTH - 1) as u8, 0x22, page as u8, page as u8])?;
+
+        let mut buffer = [0u8; 1 + WIDTH];
+        buffer[0] = CONTROL_DATA;
+        buffer[1..].copy_from_slice(data);
+
+        self.i2c.write(ADDRESS, &buffer).map_err(|_| ())
+    }
+}
+
+/// The columns of a character in the 5x7 font. Unknown characters show as `?`.
+fn glyph(character: char) -> [u8; 5] {
+    match character {
+        ' ' => [0x00, 0x00, 0x00, 0x00, 0x00],
+        '%' => [0x23, 0x13, 0x08, 0x64, 0x62],
+        '+' => [0x08, 0x08, 0x3E, 0x08, 0x08],
+        '-' => [0x08, 0x08, 0x08, 0x08, 0x08],
+        '.' => [0x00, 0x60, 0x60, 0x00, 0x00],
+        '/' => [0x20, 0x10, 0x08, 0x04, 0x02],
+        '0' => [0x3E, 0x51, 0x49, 0x45, 0x3E],
+        '1' => [0x00, 0x42, 0x7F, 0x40, 0x00],
+        '2' => [0x42, 0x61, 0x51, 0x49, 0x46],
+        '3' => [0x21, 0x41, 0x45, 0x4B, 0x31],
+        '4' => [0x18, 0x14, 0x12, 0x7F, 0x10],
+        '5' => [0x27, 0x45, 0x45, 0x45, 0x39],
+        '6' => [0x3C, 0x4A, 0x49, 0x49, 0x30],
+        '7' => [0x01, 0x71, 0x09, 0x05, 0x03],
+        '8' => [0x36, 0x49, 0x49, 0x49, 0x36],
+        '9' => [0x06, 0x49, 0x49, 0x29, 0x1E],
+        ':' => [0x00, 0x36, 0x36, 0x00, 0x00],
+        '>' => [0x41, 0x22, 0x14, 0x08, 0x00],
+        'A' => [0x7E, 0x11, 0x11, 0x11, 0x7E],
+        'B' => [0x7F, 0x49, 0x49, 0x49, 0x36],
+        'C' => [0x3E, 0x41, 0x41, 0x41, 0x22],
+        'D' => [0x7F, 0x41, 0x41, 0x22, 0x1C],
+        'E' => [0x7F, 0x49, 0x49, 0x49, 0x41],
+        'F' => [0x7F, 0x09, 0x09, 0x09, 0x01],
+        'G' => [0x3E, 0x41, 0x49, 0x49, 0x7A],
+        'H' => [0x7F, 0x08, 0x08, 0x08, 0x7F],
+        'I' => [0x00, 0x41, 0x7F, 0x41, 0x00],
+        'J' => [0x20, 0x40, 0x41, 0x3F, 0x01],
+        'K' => [0x7F, 0x08, 0x14, 0x22, 0x41],
+        'L' => [0x7F, 0x40, 0x40, 0x40, 0x40],
+        'M' => [0x7F, 0x02, 0x0C, 0x02, 0x7F],
+        'N' => [0x7F, 0x04, 0x08, 0x10, 0x7F],
+        'O' => [0x3E, 0x41, 0x41, 0x41, 0x3E],
+        'P' => [0x7F, 0x09, 0x09, 0x09, 0x06],
+        'Q' => [0x3E, 0x41, 0x51, 0x21, 0x5E],
+        'R' => [0x7F, 0x09, 0x19, 0x29, 0x46],
+        'S' => [0x46, 0x49, 0x49, 0x49, 0x31],
+        'T' => [0x01, 0x01, 0x7F, 0x01, 0x01],
+        'U' => [0x3F, 0x40, 0x40, 0x40, 0x3F],
+        'V' => [0x1F, 0x20, 0x40, 0x20, 0x1F],
+        'W' => [0x3F, 0x40, 0x38, 0x40, 0x3F],
+        'X' => [0x63, 0x14, 0x08, 0x14, 0x63],
+        'Y' => [0x07, 0x08, 0x70, 0x08, 0x07],
+        'Z' => [0x61, 0x51, 0x49, 0x45, 0x43],
+        'd' => [0x38, 0x44, 0x44, 0x48, 0x7F],
+        'k' => [0x7F, 0x10, 0x28, 0x44, 0x00],
+        'z' => [0x44, 0x64, 0x54, 0x4C, 0x44],
+        _ => [0x02, 0x01, 0x51, 0x09, 0x06],
+    }
+}
+
+/// The columns of the icon of a fault.
+fn icon(fault: Fault) -> [u8; 8] {
+    match fault {
+        // A speaker.
+        Fault::Amplifier => [0x1C, 0x1C, 0x3E, 0x7F, 0x00, 0x22, 0x1C, 0x00],
+        // A thermometer.
+        Fault::OverTemperature => [0x00, 0x60, 0xF0, 0x9F, 0xF0, 0x60, 0x00, 0x00],
+        // A clock.
+        Fault::ClockLoss => [0x3C, 0x42, 0x81, 0x9D, 0x91, 0x42, 0x3C, 0x00],
+        // A memory chip.
+        Fault::StorageCorruption => [0x14, 0x7F, 0x41, 0x41, 0x41, 0x7F, 0x14, 0x00],
+        // The symbol of direct current.
+        Fault::OutputDc => [0x24, 0x24, 0x04, 0x24, 0x24, 0x04, 0x24, 0x24],
+    }
+}
+
+/// Draw text at a column and page, at single or double size. Text beyond the right edge is cut off.
+fn draw_text(frame: &mut Frame, column: usize, page: usize, text: &str, double: bool) {
+    let scale = if double { 2 } else { 1 };
+
+    for (index, character) in text.chars().enumerate() {
+        let x = column + index * CHARACTER_WIDTH * scale;
+
+        for (offset, bits) in glyph(character).into_iter().enumerate() {
+            if !double {
+                if let Some(byte) = frame[page].get_mut(x + offset) {
+                    *byte = bits;
+                }
+                continue;
+            }
+
+            // Every row doubles into two, over two pages.
+            let stretched = (0..8).fold(0u16, |stretched, row| {
+                stretched | ((((bits >> row) & 1) as u16 * 0b11) << (2 * row))
+            });
+
+            for dx in 0..2 {
+                if let Some(byte) = frame[page].get_mut(x + 2 * offset + dx) {
+                    *byte = stretched as u8;
+                }
+                if let Some(byte) = frame[page + 1].get_mut(x + 2 * offset + dx) {
+                    *byte = (stretched >> 8) as u8;
+                }
+            }
+        }
+    }
+}
+
+/// The name of the active source on the display.
+fn source_text(source: AudioSource) -> &'static str {
+    match source {
+        AudioSource::None => "NO SOURCE",
+        AudioSource::Usb => "USB",
+        AudioSource::Spdif => "S/PDIF",
+        AudioSource::Ext => "EXT",
+        AudioSource::Rpi => "RPI",
+    }
+}
+
+/// Draw the open menu, one line per page.
+fn render_menu(frame: &mut Frame) {
+    let mut lines: [Line; menu::LINE_COUNT] = Default::default();
+    menu::render(&mut lines);
+
+    for (page, line) in lines.iter().enumerate().take(PAGE_COUNT) {
+        draw_text(frame, 0, page, line, false);
+    }
+}
+
+/// Draw the firmware version. Text is in upper case, since the font lacks most lower case letters.
+fn render_splash(frame: &mut Frame) {
+    let info = device_info();
+    let [major, minor, patch] = info.version;
+
+    draw_text(frame, 0, 0, "BLUS MINI", true);
+
+    let mut line: String<32> = String::new();
+    _ = write!(line, "FW {}.{}.{}  HW {}", major, minor, patch, info.hardware_revision);
+    draw_text(frame, 0, 3, &line, false);
+
+    line.clear();
+    _ = write!(line, "GIT {}", info.git_hash_str());
+    line.make_ascii_uppercase();
+    draw_text(frame, 0, 4, &line, false);
+
+    line.clear();
+    _ = write!(line, "BUILD {}", info.build_date_str());
+    draw_text(frame, 0, 5, &line, false);
+}
+
+/// Draw the device state, the open menu, or the firmware version after startup.
+fn render(frame: &mut Frame, splash: bool) {
+    *frame = [[0; WIDTH]; PAGE_COUNT];
+
+    if splash {
+        render_splash(frame);
+        return;
+    }
+
+    if menu::is_open() {
+        render_menu(frame);
+        return;
+    }
+
+    if CONTROL.standby() {
+        draw_text(frame, 0, 0, "STANDBY", true);
+    } else {
+        let source = CONTROL.active_source();
+        draw_text(frame, 0, 0, source_text(source), true);
+
+        let mut volume: String<16> = String::new();
+        match CONTROL.attenuation() {
+            attenuation if CONTROL.muted() || attenuation == MUTED_ATTENUATION => _ = volume.push_str("MUTE"),
+            attenuation => _ = write!(volume, "{:.1} dB", -(attenuation as f32) / 2.0),
+        }
+        draw_text(frame, 0, 3, &volume, true);
+
+        if source != AudioSource::None {
+            let mut rate: String<16> = String::new();
+            _ = write!(rate, "{} kHz", SAMPLE_RATE_HZ / 1000);
+            draw_text(frame, 0, 6, &rate, false);
+        }
+    }
+
+    let active = faults::active();
+    let icons = Fault::ALL.into_iter().filter(|fault| active & fault.mask() != 0);
+
+    for (index, fault) in icons.enumerate() {
+        let x = index * ICON_WIDTH;
+        frame[PAGE_COUNT - 1][x..x + 8].copy_from_slice(&icon(fault));
+    }
+}
+
+/// The contrast of the display, by the brightness of the LEDs, and the screensaver.
+fn contrast() -> u8 {
+    if idle() {
+        return DIM_CONTRAST;
+    }
+
+    (led::current_brightness().min(100) as u32 * u8::MAX as u32 / 100) as u8
+}
+
+/// Shows the device state on the display.
+#[embassy_executor::task]
+pub async fn display_task(i2c_bus: &'static I2cBus) {
+    let mut display = Ssd1306 {
+        i2c: I2cDevice::new(i2c_bus),
+    };
+
+    let mut changes = notifications::subscribe();
+    let mut reported_absent = false;
+
+    loop {
+        if display.command(&INIT_SEQUENCE).is_err() {
+            if !reported_absent {
+                log!(info, "No display");
+                reported_absent = true;
+            }
+
+            Timer::after(RETRY_INTERVAL).await;
+            continue;
+        }
+
+        log!(info, "Display found");
+        PRESENT.store(true, Ordering::Relaxed);
+
+        // The display RAM is undefined after power-up, so that all pages are written first.
+        let mut shown: Option<Frame> = None;
+        let mut shown_contrast = None;
+        let mut frame: Frame = [[0; WIDTH]; PAGE_COUNT];
+        let mut blank = false;
+        let mut last_activity = Instant::now();
+        let splash_end = Instant::from_millis(SPLASH_TIME.as_millis());
+
+        'connected: loop {
+            let idle = timeout().is_some_and(|timeout| last_activity.elapsed() >= timeout);
+            if IDLE.swap(idle, Ordering::Relaxed) != idle {
+                log!(debug, "Display screensaver: {}", if idle { "on" } else { "off" });
+            }
+
+            let blanked = idle && !dim();
+            if blanked != blank {
+                let command = if blanked { DISPLAY_OFF } else { DISPLAY_ON };
+                if display.command(&[command]).is_err() {
+                    break;
+                }
+
+                blank = blanked;
+            }
+
+            let contrast = contrast();
+            if shown_contrast != Some(contrast) {
+                if display.command(&[SET_CONTRAST, contrast]).is_err() {
+                    break;
+                }
+
+                shown_contrast = Some(contrast);
+            }
+
+            cpu_load::measure(Section::Display, || render(&mut frame, Instant::now() < splash_end));
+
+            for page in 0..PAGE_COUNT {
+                let changed = shown.is_none_or(|shown| shown[page] != frame[page]);
+                if changed && display.write_page(page, &frame[page]).is_err() {
+                    break 'connected;
+                }
+            }
+            shown = Some(frame);
+
+            match select3(
+                changes.next_message_pure(),
+                menu::changed(),
+                Timer::after(REFRESH_INTERVAL),
+            )
+            .await
+            {
+                Either3::First(_) | Either3::Second(()) => last_activity = Instant::now(),
+                Either3::Third(()) => (),
+            }
+        }
+
+        log!(warn, "Display lost");
+        PRESENT.store(false, Ordering::Relaxed);
+        IDLE.store(false, Ordering::Relaxed);
+        reported_absent = true;
+    }
+}
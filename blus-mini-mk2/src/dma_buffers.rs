@@ -0,0 +1,87 @@
+//! The pool of static DMA buffers, placed in memories that their DMA controllers can access.
+//!
+//! - The SAI4 buffers are in SRAM4 (zone D3), which is the only memory that the BDMA can access.
+//! - The S/PDIF and ADC buffers are in SRAM1 (zone D2), which is accessible by most system masters.
+//! - The frame buffer of the addressable LEDs is in the AXI SRAM (zone D1), and cached (see [`crate::cache`]).
+//!
+//! Every buffer is handed out once, as a mutable slice that is initialized to zero. Taking a buffer twice panics.
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use defmt::panic;
+
+use crate::audio_routing::SAI_AMP_SAMPLE_COUNT;
+use crate::cache::DmaBuffer;
+use crate::rgb_led::FRAME_BUFFER_SIZE;
+use crate::*;
+
+/// A buffer of the pool.
+#[derive(Clone, Copy, PartialEq, Debug, defmt::Format)]
+enum Buffer {
+    SaiAmpWrite = 0,
+    SaiRpiRead = 1,
+    Spdifrx = 2,
+    #[cfg(not(feature = "encoder"))]
+    Adc1Measurement = 3,
+    RgbLedFrame = 4,
+}
+
+#[link_section = ".sram4"]
+static SAI_AMP_WRITE: DmaBuffer<u32, SAI_AMP_SAMPLE_COUNT> = DmaBuffer::uninit();
+
+#[link_section = ".sram4"]
+static SAI_RPI_READ: DmaBuffer<u32, DEFAULT_SAMPLE_COUNT> = DmaBuffer::uninit();
+
+// Reserve twice the SPDIF sample count, since the DMA will transfer at
+// half-full interrupt (so, at SPDIF_SAMPLE_COUNT * 2 / 2).
+#[link_section = ".sram1"]
+static SPDIFRX: DmaBuffer<u32, { DEFAULT_SAMPLE_COUNT * 2 }> = DmaBuffer::uninit();
+
+#[cfg(not(feature = "encoder"))]
+#[link_section = ".sram1"]
+static ADC1_MEASUREMENT: DmaBuffer<u16, 2> = DmaBuffer::uninit();
+
+#[link_section = ".axisram"]
+static RGB_LED_FRAME: DmaBuffer<u8, FRAME_BUFFER_SIZE> = DmaBuffer::uninit();
+
+/// The buffers that were taken, by bit.
+static TAKEN: AtomicU8 = AtomicU8::new(0);
+
+/// Take a buffer of the pool, and initialize it to zero.
+///
+/// The mutable slice from a shared reference is sound, since [`TAKEN`] hands out every buffer once.
+#[allow(clippy::mut_from_ref)]
+fn take<T: Copy + Default, const N: usize>(buffer: Buffer, cell: &'static DmaBuffer<T, N>) -> &'static mut [T] {
+    let mask = 1 << buffer as u8;
+    if TAKEN.fetch_or(mask, Ordering::Relaxed) & mask != 0 {
+        panic!("DMA buffer {:?} taken twice.", buffer);
+    }
+
+    // SAFETY: Every buffer is only taken once.
+    unsafe { cell.take(T::default()) }
+}
+
+/// The write buffer of the amplifier SAI (SAI4 A).
+pub fn sai_amp_write() -> &'static mut [u32] {
+    take(Buffer::SaiAmpWrite, &SAI_AMP_WRITE)
+}
+
+/// The read buffer of the Raspberry Pi SAI (SAI4 B).
+pub fn sai_rpi_read() -> &'static mut [u32] {
+    take(Buffer::SaiRpiRead, &SAI_RPI_READ)
+}
+
+/// The read buffer of the S/PDIF receiver.
+pub fn spdifrx() -> &'static mut [u32] {
+    take(Buffer::Spdifrx, &SPDIFRX)
+}
+
+/// The measurement buffer of ADC1, for the potentiometer.
+#[cfg(not(feature = "encoder"))]
+pub fn adc1_measurement() -> &'static mut [u16] {
+    take(Buffer::Adc1Measurement, &ADC1_MEASUREMENT)
+}
+
+/// The frame buffer of the addressable LEDs.
+pub fn rgb_led_frame() -> &'static mut [u8] {
+    take(Buffer::RgbLedFrame, &RGB_LED_FRAME)
+}
@@ -0,0 +1,79 @@
+//! The signal processing configuration, shared between the audio routing task and the control interfaces.
+use core::cell::RefCell;
+
+use audio::filter_config::ConfigError;
+use audio::filter_graph::FilterGraph;
+use audio::fir::MAX_FIR_LENGTH;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::signal::Signal;
+use heapless::Vec;
+
+use crate::*;
+
+/// The filter configuration of all output channels: a chain of stages, crossover, gain, delay, and limiter each.
+pub type DspConfig = FilterGraph<OUTPUT_CHANNEL_COUNT>;
+
+/// The currently active signal processing configuration.
+static DSP_CONFIG: Mutex<CriticalSectionRawMutex, RefCell<DspConfig>> = Mutex::new(RefCell::new(FilterGraph::new()));
+
+/// Signal that is emitted when the signal processing configuration changes.
+pub static DSP_CONFIG_CHANGED_SIGNAL: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// FIR taps of a single output channel.
+pub type FirTaps = Vec<f32, MAX_FIR_LENGTH>;
+
+/// The currently active FIR taps of all output channels.
+static FIR_TAPS: Mutex<CriticalSectionRawMutex, RefCell<[FirTaps; OUTPUT_CHANNEL_COUNT]>> =
+    Mutex::new(RefCell::new([const { Vec::new() }; OUTPUT_CHANNEL_COUNT]));
+
+/// Signal that is emitted when the FIR taps of any output channel change.
+pub static FIR_TAPS_CHANGED_SIGNAL: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// Get a copy of the signal processing configuration.
+pub fn dsp_config() -> DspConfig {
+    DSP_CONFIG.lock(|config| config.borrow().clone())
+}
+
+/// Modify the signal processing configuration.
+///
+/// The modified configuration is validated, and only takes effect, if it is valid.
+pub fn update_dsp_config(modify: impl FnOnce(&mut DspConfig)) -> Result<(), ConfigError> {
+    let mut config = dsp_config();
+    modify(&mut config);
+    set_dsp_config(config)
+}
+
+/// Replace the signal processing configuration, if it is valid.
+pub fn set_dsp_config(config: DspConfig) -> Result<(), ConfigError> {
+    config.validate(SAMPLE_RATE_HZ).map_err(|(_, error)| error)?;
+
+    DSP_CONFIG.lock(|current| *current.borrow_mut() = config);
+    DSP_CONFIG_CHANGED_SIGNAL.signal(());
+
+    Ok(())
+}
+
+/// Access the FIR taps of an output channel.
+pub fn with_fir_taps<R>(channel: usize, f: impl FnOnce(&[f32]) -> R) -> R {
+    FIR_TAPS.lock(|taps| f(&taps.borrow()[channel]))
+}
+
+/// Replace the FIR taps of an output channel. An empty slice disables the FIR filter.
+pub fn set_fir_taps(channel: usize, taps: &[f32]) -> Result<(), ConfigError> {
+    if taps.len() > MAX_FIR_LENGTH {
+        return Err(ConfigError::TooManyStages);
+    }
+
+    if !taps.iter().all(|tap| tap.is_finite()) {
+        return Err(ConfigError::InvalidTap);
+    }
+
+    FIR_TAPS.lock(|current| {
+        // Cannot fail, the length was checked.
+        current.borrow_mut()[channel] = Vec::from_slice(taps).unwrap();
+    });
+    FIR_TAPS_CHANGED_SIGNAL.signal(());
+
+    Ok(())
+}
@@ -0,0 +1,100 @@
+//! Volume input by a rotary encoder with push button, as an alternative to the analog potentiometer.
+//!
+//! The quadrature signals are counted by a timer in encoder mode. The encoder changes the master volume relative to
+//! its current value, no matter which writer set it last (see [`crate::control`]). Fast rotation changes the volume
+//! in larger steps. Pushing the button toggles the master mute.
+//!
+//! A long press opens (or closes) the menu on the status display (see [`crate::menu`]). While it is open, rotation and
+//! pushing navigate the menu instead.
+use embassy_futures::select::{select, Either};
+use embassy_stm32::exti::ExtiInput;
+use embassy_stm32::gpio::Pull;
+use embassy_stm32::peripherals;
+use embassy_stm32::timer::qei::{Qei, QeiPin};
+use embassy_time::{with_timeout, Duration, Ticker, Timer};
+
+use crate::control::{self, VolumeWriter, CONTROL};
+use crate::menu::{self, Input};
+
+/// The rate at which the encoder count is read.
+const POLL_RATE_HZ: u64 = 100;
+
+/// The number of counts per detent. The timer counts all four edges of the quadrature signals.
+const COUNTS_PER_DETENT: i16 = 4;
+
+/// The attenuation change per detent at slow rotation, in steps of 0.5 dB.
+const STEP: i16 = 2;
+
+/// The maximum factor, by which fast rotation enlarges steps.
+const MAX_ACCELERATION: i16 = 8;
+
+/// The time for the push button to settle.
+const DEBOUNCE_TIME: Duration = Duration::from_millis(20);
+
+/// The time that the push button is held for a long press.
+const LONG_PRESS_TIME: Duration = Duration::from_millis(800);
+
+/// Resources that are required for the rotary encoder.
+#[allow(missing_docs)]
+pub struct EncoderResources {
+    pub timer: peripherals::TIM3,
+    pub pin_a: peripherals::PB4,
+    pub pin_b: peripherals::PA7,
+
+    pub button: peripherals::PD4,
+    pub button_exti: peripherals::EXTI4,
+}
+
+/// Reads the rotary encoder and its push button.
+#[embassy_executor::task]
+pub async fn encoder_task(resources: EncoderResources) {
+    let qei = Qei::new(
+        resources.timer,
+        QeiPin::new_ch1(resources.pin_a),
+        QeiPin::new_ch2(resources.pin_b),
+    );
+    let mut button = ExtiInput::new(resources.button, resources.button_exti, Pull::Up);
+    let mut ticker = Ticker::every(Duration::from_hz(POLL_RATE_HZ));
+
+    let mut last_count = qei.count();
+    // Counts that do not amount to a full detent yet.
+    let mut counts: i16 = 0;
+
+    loop {
+        match select(ticker.next(), button.wait_for_falling_edge()).await {
+            Either::First(()) => {
+                let count = qei.count();
+                counts += count.wrapping_sub(last_count) as i16;
+                last_count = count;
+
+                let detents = counts / COUNTS_PER_DETENT;
+                counts %= COUNTS_PER_DETENT;
+
+                if detents != 0 && menu::is_open() {
+                    menu::input(Input::Rotate(detents));
+                } else if detents != 0 {
+                    // More detents per poll interval mean faster rotation.
+                    let acceleration = detents.abs().min(MAX_ACCELERATION);
+                    let attenuation = (CONTROL.attenuation() as i16 - detents * acceleration * STEP)
+                        .clamp(0, control::MUTED_ATTENUATION as i16) as u8;
+
+                    CONTROL.set_input_attenuation(attenuation);
+                    CONTROL.set_attenuation_by(VolumeWriter::VolumeInput, attenuation);
+                }
+            }
+            Either::Second(()) => {
+                Timer::after(DEBOUNCE_TIME).await;
+
+                if button.is_low() {
+                    let long_press = with_timeout(LONG_PRESS_TIME, button.wait_for_high()).await.is_err();
+
+                    match (long_press, menu::is_open()) {
+                        (true, _) => menu::toggle(),
+                        (false, true) => menu::input(Input::Press),
+                        (false, false) => CONTROL.set_muted(!CONTROL.muted()),
+                    }
+                }
+            }
+        }
+    }
+}
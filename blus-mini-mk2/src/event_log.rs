@@ -0,0 +1,379 @@
+//! A log of faults and other significant events in flash, for post-mortem debugging of devices in the field.
+//!
+//! Events (resets and their cause, output underruns, amplifier faults, source errors, and damaged stored data) are
+//! recorded with the boot number and the time since startup, in the format of [`protocol::event_log`]. The log is
+//! shown and cleared with the shell (`events` commands), and survives factory resets.
+//!
+//! Recording never blocks, such that events can be recorded from anywhere. The [`event_log_task`] writes them to
+//! the log's storage region (see [`crate::storage`]). Events that repeat within [`COALESCE_TIME`] are written as one
+//! entry with a count (for up to [`MAX_ENTRY_TIME`]), such that bursts (e.g. of underruns) do not fill the log.
+//!
+//! Before a power loss, all pending events are written at once (see [`flush`], and [`crate::power_fail`]).
+//!
+//! Entries are appended to the region in slots of whole write blocks. Once the region is nearly full, it is erased
+//! while playback is stopped, and the newest [`KEEP_COUNT`] entries are written back, such that the log acts as a
+//! ring buffer. Events that find the region full during playback are dropped, and counted.
+use core::fmt::Write as _;
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use embassy_futures::select::{select, Either};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::channel::Channel;
+use embassy_sync::signal::Signal;
+use embassy_time::{with_timeout, Duration, Instant};
+use heapless::String;
+use protocol::event_log::{Entry, EventKind, ResetCause, StoredData, AMPLIFIER_FAULTS, ENTRY_SIZE};
+
+use crate::control;
+use crate::faults::{self, Fault};
+use crate::startup::{self, Step};
+use crate::storage::{self, EVENT_LOG_REGION, WRITE_BLOCK_SIZE};
+use crate::watchdog::{self, Task};
+use crate::*;
+
+/// The time within which a repeated event is written in the same entry as the previous one.
+pub const COALESCE_TIME: Duration = Duration::from_secs(1);
+
+/// The maximum time that is covered by one entry of repeated events.
+pub const MAX_ENTRY_TIME: Duration = Duration::from_secs(60);
+
+/// The number of newest entries that are kept, when the region is erased.
+pub const KEEP_COUNT: usize = 64;
+
+/// The interval between checks, whether the region should be erased.
+const CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// The number of events that can wait for being written.
+const QUEUE_SIZE: usize = 16;
+
+/// The size of a slot, in whole write blocks.
+const SLOT_SIZE: usize = ENTRY_SIZE.div_ceil(WRITE_BLOCK_SIZE) * WRITE_BLOCK_SIZE;
+
+/// The number of slots in the region.
+const SLOT_COUNT: u32 = EVENT_LOG_REGION.size() / SLOT_SIZE as u32;
+
+/// The number of used slots, from which on the region is erased once playback stops.
+const ERASE_THRESHOLD: u32 = SLOT_COUNT - 4 * KEEP_COUNT as u32;
+
+/// An event that waits for being written, with its time since startup in milliseconds.
+struct Event {
+    kind: EventKind,
+    argument: u8,
+    time_ms: u64,
+}
+
+/// Events that wait for being written.
+static EVENTS: Channel<CriticalSectionRawMutex, Event, QUEUE_SIZE> = Channel::new();
+
+/// Signal that is emitted when the recorded events should be written now.
+static FLUSH_SIGNAL: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// The number of used slots, once the region is scanned.
+static USED_SLOTS: AtomicU32 = AtomicU32::new(0);
+
+/// The number of the current startup.
+static BOOT: AtomicU32 = AtomicU32::new(0);
+
+/// The number of events that were dropped since startup.
+static DROPPED_COUNT: AtomicU32 = AtomicU32::new(0);
+
+/// Record an event.
+pub fn record(kind: EventKind, argument: u8) {
+    let event = Event {
+        kind,
+        argument,
+        time_ms: Instant::now().as_millis(),
+    };
+
+    if EVENTS.try_send(event).is_err() {
+        DROPPED_COUNT.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Write all recorded events now, without coalescing, e.g. before a power loss.
+pub fn flush() {
+    FLUSH_SIGNAL.signal(());
+}
+
+/// The number of entries in the log, including damaged ones.
+pub fn entry_count() -> usize {
+    USED_SLOTS.load(Ordering::Relaxed) as usize
+}
+
+/// An entry of the log, counting from the oldest. Returns `None` for damaged entries.
+pub fn entry(index: usize) -> Option<Entry> {
+    if index >= entry_count() {
+        return None;
+    }
+
+    let mut data = [0u8; ENTRY_SIZE];
+    EVENT_LOG_REGION.read((index * SLOT_SIZE) as u32, &mut data).ok()?;
+    Entry::decode(&data)
+}
+
+/// The number of the current startup, which is counted in the log.
+pub fn boot() -> u32 {
+    BOOT.load(Ordering::Relaxed)
+}
+
+/// The number of events that were dropped since startup, since they came too fast, or found the log full.
+pub fn dropped_count() -> u32 {
+    DROPPED_COUNT.load(Ordering::Relaxed)
+}
+
+/// Erase all entries.
+pub fn clear() -> Result<(), storage::Error> {
+    USED_SLOTS.store(0, Ordering::Relaxed);
+    EVENT_LOG_REGION.erase()
+}
+
+/// The name of an event kind, as used by the text interfaces.
+pub fn kind_name(kind: EventKind) -> &'static str {
+    match kind {
+        EventKind::Reset => "reset",
+        EventKind::Underrun => "underrun",
+        EventKind::AmplifierFault => "amplifier-fault",
+        EventKind::SourceError => "source-error",
+        EventKind::StorageCorruption => "storage-corruption",
+        EventKind::BootLoop => "boot-loop",
+        EventKind::Undervoltage => "undervoltage",
+        EventKind::TaskStall => "task-stall",
+        EventKind::PowerFail => "power-fail",
+        EventKind::OutputDc => "output-dc",
+        EventKind::Shutdown => "shutdown",
+        EventKind::Panic => "panic",
+        EventKind::StartupFailure => "startup-failure",
+    }
+}
+
+/// The name of a reset cause, as used by the text interfaces.
+pub fn reset_cause_name(cause: ResetCause) -> &'static str {
+    match cause {
+        ResetCause::PowerOn => "power-on",
+        ResetCause::Pin => "pin",
+        ResetCause::Software => "software",
+        ResetCause::Watchdog => "watchdog",
+        ResetCause::WindowWatchdog => "window-watchdog",
+        ResetCause::Brownout => "brownout",
+        ResetCause::LowPower => "low-power",
+        ResetCause::Unknown => "unknown",
+    }
+}
+
+/// The name of a kind of stored data, as used by the text interfaces.
+pub fn stored_data_name(data: StoredData) -> &'static str {
+    match data {
+        StoredData::Settings => "settings",
+        StoredData::Presets => "presets",
+        StoredData::StartupScript => "startup-script",
+        StoredData::Calibration => "calibration",
+        StoredData::Provisioning => "provisioning",
+    }
+}
+
+/// Record that stored data was found damaged, log it, and raise the fault.
+pub fn record_corruption(data: StoredData) {
+    log!(warn, "Damaged stored data: {}", stored_data_name(data));
+    record(EventKind::StorageCorruption, data as u8);
+    faults::raise(Fault::StorageCorruption);
+}
+
+/// A description of the argument of an entry, e.g. the source of an underrun.
+pub fn argument_text(entry: &Entry) -> String<64> {
+    let mut text = String::new();
+
+    match entry.kind {
+        EventKind::Reset => match ResetCause::try_from(entry.argument) {
+            Ok(cause) => _ = text.push_str(reset_cause_name(cause)),
+            Err(value) => _ = write!(text, "cause {}", value),
+        },
+        EventKind::Underrun | EventKind::SourceError => match AudioSource::try_from(entry.argument) {
+            Ok(source) => _ = text.push_str(control::source_name(source)),
+            Err(value) => _ = write!(text, "source {}", value),
+        },
+        EventKind::StorageCorruption => match StoredData::try_from(entry.argument) {
+            Ok(data) => _ = text.push_str(stored_data_name(data)),
+            Err(value) => _ = write!(text, "data {}", value),
+        },
+        EventKind::BootLoop => _ = write!(text, "{} resets", entry.argument),
+        EventKind::StartupFailure => match Step::ALL.get(entry.argument as usize) {
+            Some(&step) => _ = text.push_str(startup::step_name(step)),
+            None => _ = write!(text, "step {}", entry.argument),
+        },
+        EventKind::PowerFail | EventKind::Shutdown | EventKind::Panic => (),
+        EventKind::OutputDc => _ = write!(text, "channels {:#x}", entry.argument),
+        EventKind::Undervoltage => _ = write!(text, "{:.1} V", entry.argument as f32 / 10.0),
+        EventKind::TaskStall => {
+            let mut tasks = Task::ALL.into_iter().filter(|task| entry.argument & task.mask() != 0);
+
+            if let Some(task) = tasks.next() {
+                _ = text.push_str(watchdog::task_name(task));
+            }
+
+            for task in tasks {
+                _ = write!(text, ", {}", watchdog::task_name(task));
+            }
+        }
+        EventKind::AmplifierFault => {
+            _ = write!(text, "amplifier {}", entry.argument >> 4);
+
+            for (_, name) in AMPLIFIER_FAULTS.iter().filter(|(mask, _)| entry.argument & mask != 0) {
+                _ = write!(text, ", {}", name);
+            }
+        }
+    }
+
+    text
+}
+
+/// Find the number of used slots, and the highest boot number in the log.
+///
+/// The log ends at the first erased slot.
+fn scan() -> Result<(u32, Option<u32>), storage::Error> {
+    let mut data = [0u8; ENTRY_SIZE];
+    let mut last_boot = None;
+
+    for slot in 0..SLOT_COUNT {
+        EVENT_LOG_REGION.read(slot * SLOT_SIZE as u32, &mut data)?;
+
+        if data.iter().all(|byte| *byte == 0xFF) {
+            return Ok((slot, last_boot));
+        }
+
+        if let Some(entry) = Entry::decode(&data) {
+            last_boot = last_boot.max(Some(entry.boot));
+        }
+    }
+
+    Ok((SLOT_COUNT, last_boot))
+}
+
+/// Erase the region, and write back the newest [`KEEP_COUNT`] entries.
+fn compact() -> Result<(), storage::Error> {
+    let used = USED_SLOTS.load(Ordering::Relaxed);
+    let kept = used.min(KEEP_COUNT as u32);
+
+    let mut buffer = [0xFFu8; KEEP_COUNT * SLOT_SIZE];
+    let buffer = &mut buffer[..kept as usize * SLOT_SIZE];
+    EVENT_LOG_REGION.read((used - kept) * SLOT_SIZE as u32, buffer)?;
+
+    // The entries are lost, if writing them back fails.
+    USED_SLOTS.store(0, Ordering::Relaxed);
+    EVENT_LOG_REGION.erase()?;
+    EVENT_LOG_REGION.write(0, buffer)?;
+    USED_SLOTS.store(kept, Ordering::Relaxed);
+
+    log!(info, "Compacted the event log");
+    Ok(())
+}
+
+/// Append an entry to the log, unless it is full.
+fn append(entry: &Entry) -> Result<(), storage::Error> {
+    if USED_SLOTS.load(Ordering::Relaxed) >= SLOT_COUNT {
+        if !storage::erase_allowed() {
+            DROPPED_COUNT.fetch_add(entry.count as u32, Ordering::Relaxed);
+            return Ok(());
+        }
+
+        compact()?;
+    }
+
+    let used = USED_SLOTS.load(Ordering::Relaxed);
+    let mut slot = [0u8; SLOT_SIZE];
+    slot[..ENTRY_SIZE].copy_from_slice(&entry.encode());
+
+    // A failed write may leave a damaged slot, which is skipped.
+    USED_SLOTS.store(used + 1, Ordering::Relaxed);
+    EVENT_LOG_REGION.write(used * SLOT_SIZE as u32, &slot)
+}
+
+/// A new entry for an event, which occurred once so far.
+fn new_entry(event: &Event) -> Entry {
+    Entry {
+        kind: event.kind,
+        argument: event.argument,
+        count: 1,
+        boot: boot(),
+        time_ms: event.time_ms,
+    }
+}
+
+/// Write an entry, and log failures.
+fn write(entry: &Entry) {
+    log!(info, "Event: {:?}", entry);
+
+    if let Err(error) = append(entry) {
+        log!(warn, "Failed to write the event log: {:?}", error);
+    }
+}
+
+/// Writes recorded events to the log.
+#[embassy_executor::task]
+pub async fn event_log_task() {
+    match scan() {
+        Ok((used, last_boot)) => {
+            USED_SLOTS.store(used, Ordering::Relaxed);
+            BOOT.store(last_boot.map_or(0, |boot| boot.wrapping_add(1)), Ordering::Relaxed);
+        }
+        Err(error) => {
+            log!(warn, "Failed to read the event log: {:?}", error);
+            USED_SLOTS.store(SLOT_COUNT, Ordering::Relaxed);
+        }
+    }
+
+    let mut pending: Option<Entry> = None;
+    let mut last_time_ms = 0;
+
+    loop {
+        let timeout = if pending.is_some() {
+            COALESCE_TIME
+        } else {
+            CHECK_INTERVAL
+        };
+
+        let event = match with_timeout(timeout, select(EVENTS.receive(), FLUSH_SIGNAL.wait())).await {
+            Ok(Either::First(event)) => event,
+            Ok(Either::Second(())) => {
+                if let Some(entry) = pending.take() {
+                    write(&entry);
+                }
+
+                while let Ok(event) = EVENTS.try_receive() {
+                    write(&new_entry(&event));
+                }
+                continue;
+            }
+            Err(_) => {
+                if let Some(entry) = pending.take() {
+                    write(&entry);
+                }
+
+                if USED_SLOTS.load(Ordering::Relaxed) >= ERASE_THRESHOLD && storage::erase_allowed() {
+                    if let Err(error) = compact() {
+                        log!(warn, "Failed to compact the event log: {:?}", error);
+                    }
+                }
+                continue;
+            }
+        };
+
+        match pending.as_mut() {
+            Some(entry)
+                if entry.matches(event.kind, event.argument)
+                    && event.time_ms < last_time_ms + COALESCE_TIME.as_millis()
+                    && event.time_ms < entry.time_ms + MAX_ENTRY_TIME.as_millis() =>
+            {
+                entry.count = entry.count.saturating_add(1);
+            }
+            _ => {
+                if let Some(entry) = pending.take() {
+                    write(&entry);
+                }
+
+                pending = Some(new_entry(&event));
+            }
+        }
+
+        last_time_ms = event.time_ms;
+    }
+}
@@ -0,0 +1,58 @@
+//! The factory reset, which erases the stored settings, presets, and startup script, such that the compiled-in
+//! defaults apply.
+//!
+//! A factory reset is requested with the shell (`factory-reset`), which reboots the device, or by holding both board
+//! buttons at power-up. It runs early at startup, before the stored settings would be restored. All LEDs flash
+//! [`CONFIRMATION_FLASH_COUNT`] times to confirm the reset. If erasing fails, the LEDs stay lit for a while instead.
+//!
+//! The calibration and provisioning data (see [`crate::calibration`] and [`crate::provisioning`]) and the event log
+//! (see [`crate::event_log`]) are kept.
+use embassy_stm32::gpio::Output;
+use embassy_time::{Duration, Timer};
+
+use crate::presets;
+use crate::settings_store;
+use crate::startup_script;
+use crate::storage;
+use crate::*;
+
+/// The number of LED flashes that confirm a successful reset.
+pub const CONFIRMATION_FLASH_COUNT: usize = 3;
+
+/// The time of each LED flash, and of each pause in between.
+const FLASH_TIME: Duration = Duration::from_millis(150);
+
+/// The time for which the LEDs stay lit after a failed reset.
+const FAILURE_TIME: Duration = Duration::from_secs(3);
+
+/// Erase all stored data.
+fn erase() -> Result<(), storage::Error> {
+    settings_store::erase()?;
+    presets::erase()?;
+    startup_script::store("")
+}
+
+/// Perform a factory reset, and confirm it on the LEDs.
+///
+/// The reset takes effect on the configuration that is restored afterwards, so it must run before.
+pub async fn perform(leds: &mut [&mut Output<'_>]) {
+    log!(warn, "Factory reset");
+
+    match erase() {
+        Ok(()) => {
+            for _ in 0..CONFIRMATION_FLASH_COUNT {
+                leds.iter_mut().for_each(|led| led.set_high());
+                Timer::after(FLASH_TIME).await;
+                leds.iter_mut().for_each(|led| led.set_low());
+                Timer::after(FLASH_TIME).await;
+            }
+        }
+        Err(error) => {
+            log!(error, "Factory reset failed: {:?}", error);
+
+            leds.iter_mut().for_each(|led| led.set_high());
+            Timer::after(FAILURE_TIME).await;
+            leds.iter_mut().for_each(|led| led.set_low());
+        }
+    }
+}
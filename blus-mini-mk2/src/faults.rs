@@ -0,0 +1,128 @@
+//! The central fault manager, which collects the fault classes of the device for diagnosis without a console.
+//!
+//! Some faults are conditions, which are active as long as they last (e.g. a protected amplifier, see
+//! [`Fault::Amplifier`]). Others are raised by their source (see [`raise`]), and stay active until they are cleared
+//! (shell `faults clear`), or a hold time passed.
+//!
+//! The status LED blinks the codes of all active faults in turn (see [`crate::led::status_led_task`]): each code is
+//! a number of short pulses (see [`Fault::blink_count`]), followed by a pause.
+use core::sync::atomic::{AtomicU32, AtomicU8, Ordering};
+
+use embassy_time::{Duration, Instant};
+
+use crate::amplifiers::{self, AmplifierError, AmplifierState};
+use crate::control::CONTROL;
+use crate::dc_protection;
+use crate::panic_log;
+use crate::thermal;
+use crate::*;
+
+/// The time for which a clock loss stays active, after it was last raised.
+pub const CLOCK_LOSS_HOLD_TIME: Duration = Duration::from_secs(10);
+
+/// A fault class, in order of its blink code.
+#[repr(u8)]
+#[derive(Clone, Copy, PartialEq, Debug, defmt::Format)]
+pub enum Fault {
+    /// An amplifier is muted or shut down after faults, or did not power up.
+    Amplifier = 0,
+    /// The output gain is throttled, as an amplifier is too hot (see [`crate::thermal`]).
+    OverTemperature = 1,
+    /// An amplifier lost its clocks while running.
+    ClockLoss = 2,
+    /// Stored data was found damaged since startup.
+    StorageCorruption = 3,
+    /// The output is muted after DC at the output (see [`crate::dc_protection`]).
+    OutputDc = 4,
+}
+
+impl Fault {
+    /// All fault classes, in order of their blink codes.
+    pub const ALL: [Fault; 5] = [
+        Fault::Amplifier,
+        Fault::OverTemperature,
+        Fault::ClockLoss,
+        Fault::StorageCorruption,
+        Fault::OutputDc,
+    ];
+
+    /// The number of pulses of the blink code.
+    pub fn blink_count(self) -> u8 {
+        self as u8 + 1
+    }
+
+    /// The bit of the fault in the mask of active faults.
+    pub fn mask(self) -> u8 {
+        1 << self as u8
+    }
+}
+
+/// The name of a fault class, as used by the text interfaces.
+pub fn fault_name(fault: Fault) -> &'static str {
+    match fault {
+        Fault::Amplifier => "amplifier",
+        Fault::OverTemperature => "over-temperature",
+        Fault::ClockLoss => "clock-loss",
+        Fault::StorageCorruption => "storage-corruption",
+        Fault::OutputDc => "output-dc",
+    }
+}
+
+/// The mask of raised faults, which are active until they are cleared.
+static RAISED: AtomicU8 = AtomicU8::new(0);
+
+/// The uptime in seconds, at which a clock loss was last raised.
+static CLOCK_LOSS_S: AtomicU32 = AtomicU32::new(0);
+
+/// Raise a fault.
+pub fn raise(fault: Fault) {
+    if fault == Fault::ClockLoss {
+        CLOCK_LOSS_S.store(Instant::now().as_secs() as u32, Ordering::Relaxed);
+    }
+
+    if RAISED.fetch_or(fault.mask(), Ordering::Relaxed) & fault.mask() == 0 {
+        log!(warn, "Fault: {}", fault_name(fault));
+    }
+}
+
+/// Clear all raised faults, release the mute after DC at the output, and clear the message of the last panic (see
+/// [`panic_log`]). Other conditions stay active while they last.
+pub fn clear() {
+    RAISED.store(0, Ordering::Relaxed);
+    dc_protection::reset();
+    panic_log::clear();
+}
+
+/// Whether a condition that is a fault currently lasts.
+fn condition(fault: Fault) -> bool {
+    match fault {
+        Fault::Amplifier => {
+            CONTROL.protected_amplifiers() != 0
+                || matches!(
+                    amplifiers::state(),
+                    AmplifierState::Error(AmplifierError::NotResponding(_) | AmplifierError::ConfigurationTimeout(_))
+                )
+        }
+        Fault::OverTemperature => (0..OUTPUT_CHANNEL_COUNT).any(|channel| thermal::throttle_db(channel) > 0.0),
+        Fault::OutputDc => dc_protection::tripped() != 0,
+        Fault::ClockLoss | Fault::StorageCorruption => false,
+    }
+}
+
+/// The mask of active faults (see [`Fault::mask`]).
+pub fn active() -> u8 {
+    let raised_s = CLOCK_LOSS_S.load(Ordering::Relaxed);
+    if (Instant::now().as_secs() as u32).saturating_sub(raised_s) >= CLOCK_LOSS_HOLD_TIME.as_secs() as u32 {
+        RAISED.fetch_and(!Fault::ClockLoss.mask(), Ordering::Relaxed);
+    }
+
+    Fault::ALL
+        .iter()
+        .filter(|fault| condition(**fault))
+        .fold(RAISED.load(Ordering::Relaxed), |mask, fault| mask | fault.mask())
+}
+
+/// Whether a fault is active.
+pub fn is_active(fault: Fault) -> bool {
+    active() & fault.mask() != 0
+}
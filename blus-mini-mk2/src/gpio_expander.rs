@@ -0,0 +1,159 @@
+//! An optional PCAL6416A GPIO expander on the amplifier I2C bus, for more buttons and LEDs than the MCU has free pins.
+//!
+//! Port 0 holds inputs with pull-ups (buttons), port 1 holds push-pull outputs (LEDs). The [`gpio_expander_task`]
+//! polls the expander: it mirrors the inputs, and writes changed outputs. Other modules use the pins like MCU pins
+//! (see [`crate::button`] and [`crate::led`]). Without an expander, its inputs read high (released buttons), and its
+//! outputs are ignored.
+//!
+//! The PCAL6416A supports I2C Fast-mode Plus, which the amplifiers use.
+use core::cell::RefCell;
+use core::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+
+use embassy_embedded_hal::shared_bus::blocking::i2c::I2cDevice;
+use embassy_stm32::i2c::I2c;
+use embassy_stm32::mode::Async;
+use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_time::{Duration, Ticker, Timer};
+use embedded_hal::i2c::I2c as _;
+
+use crate::*;
+
+/// The I2C bus of the amplifiers and the GPIO expander.
+pub type I2cBus = Mutex<ThreadModeRawMutex, RefCell<I2c<'static, Async>>>;
+
+/// The number of inputs.
+pub const INPUT_COUNT: u8 = 8;
+
+/// The number of outputs.
+pub const OUTPUT_COUNT: u8 = 8;
+
+/// The interval between reads of the inputs, and writes of the outputs.
+pub const POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+/// The interval between attempts to reach an absent expander.
+const RETRY_INTERVAL: Duration = Duration::from_secs(5);
+
+/// The I2C address (ADDR pin low).
+const ADDRESS: u8 = 0x20;
+
+const INPUT_PORT_0: u8 = 0x00;
+const OUTPUT_PORT_1: u8 = 0x03;
+const CONFIGURATION_PORT_0: u8 = 0x06;
+const CONFIGURATION_PORT_1: u8 = 0x07;
+const PULL_ENABLE_PORT_0: u8 = 0x46;
+const PULL_SELECTION_PORT_0: u8 = 0x48;
+
+/// The levels of the inputs, one bit each.
+static INPUTS: AtomicU8 = AtomicU8::new(u8::MAX);
+
+/// The requested levels of the outputs, one bit each.
+static OUTPUTS: AtomicU8 = AtomicU8::new(0);
+
+/// Whether the expander responds.
+static PRESENT: AtomicBool = AtomicBool::new(false);
+
+/// Whether the expander responds.
+pub fn present() -> bool {
+    PRESENT.load(Ordering::Relaxed)
+}
+
+/// Whether an input is high. Inputs out of range, and all inputs of an absent expander, are high.
+pub fn is_high(input: u8) -> bool {
+    input >= INPUT_COUNT || INPUTS.load(Ordering::Relaxed) & (1 << input) != 0
+}
+
+/// Set the level of an output. Outputs out of range are ignored.
+pub fn set_output(output: u8, high: bool) {
+    if output >= OUTPUT_COUNT {
+        return;
+    }
+
+    if high {
+        OUTPUTS.fetch_or(1 << output, Ordering::Relaxed);
+    } else {
+        OUTPUTS.fetch_and(!(1 << output), Ordering::Relaxed);
+    }
+}
+
+/// A PCAL6416A on a shared bus.
+struct Pcal6416a<'a> {
+    i2c: I2cDevice<'a, ThreadModeRawMutex, I2c<'static, Async>>,
+}
+
+impl Pcal6416a<'_> {
+    /// Write a register.
+    fn write(&mut self, register: u8, value: u8) -> Result<(), ()> {
+        self.i2c.write(ADDRESS, &[register, value]).map_err(|_| ())
+    }
+
+    /// Read a register.
+    fn read(&mut self, register: u8) -> Result<u8, ()> {
+        let mut value = [0];
+        self.i2c.write_read(ADDRESS, &[register], &mut value).map_err(|_| ())?;
+
+        Ok(value[0])
+    }
+
+    /// Configure port 0 as pulled-up inputs, and port 1 as outputs with the requested levels.
+    fn configure(&mut self, outputs: u8) -> Result<(), ()> {
+        self.write(PULL_SELECTION_PORT_0, u8::MAX)?;
+        self.write(PULL_ENABLE_PORT_0, u8::MAX)?;
+        self.write(CONFIGURATION_PORT_0, u8::MAX)?;
+        self.write(OUTPUT_PORT_1, outputs)?;
+        self.write(CONFIGURATION_PORT_1, 0)
+    }
+}
+
+/// Mirrors the expander's inputs, and writes its outputs.
+#[embassy_executor::task]
+pub async fn gpio_expander_task(i2c_bus: &'static I2cBus) {
+    let mut expander = Pcal6416a {
+        i2c: I2cDevice::new(i2c_bus),
+    };
+
+    let mut reported_absent = false;
+
+    loop {
+        let mut outputs = OUTPUTS.load(Ordering::Relaxed);
+
+        if expander.configure(outputs).is_err() {
+            if !reported_absent {
+                log!(info, "No GPIO expander");
+                reported_absent = true;
+            }
+
+            Timer::after(RETRY_INTERVAL).await;
+            continue;
+        }
+
+        log!(info, "GPIO expander found");
+        PRESENT.store(true, Ordering::Relaxed);
+
+        let mut ticker = Ticker::every(POLL_INTERVAL);
+
+        loop {
+            ticker.next().await;
+
+            match expander.read(INPUT_PORT_0) {
+                Ok(inputs) => INPUTS.store(inputs, Ordering::Relaxed),
+                Err(()) => break,
+            }
+
+            let requested = OUTPUTS.load(Ordering::Relaxed);
+
+            if requested != outputs {
+                if expander.write(OUTPUT_PORT_1, requested).is_err() {
+                    break;
+                }
+
+                outputs = requested;
+            }
+        }
+
+        log!(warn, "GPIO expander lost");
+        PRESENT.store(false, Ordering::Relaxed);
+        INPUTS.store(u8::MAX, Ordering::Relaxed);
+        reported_absent = true;
+    }
+}
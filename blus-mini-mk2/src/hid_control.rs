@@ -0,0 +1,149 @@
+//! Parameter access for host tools (e.g. a configuration GUI), by means of the HID protocol.
+//!
+//! See [`protocol::hid`] for the report layout, and [`protocol::parameter`] for the available parameters.
+//! Subscribed hosts receive notifications of parameter changes (see [`crate::notifications`]).
+use defmt::{debug, info};
+use embassy_futures::select::{select, Either};
+use embassy_stm32::{peripherals, usb};
+use embassy_usb::class::hid::HidReaderWriter;
+use protocol::hid::{Command, Request, Response, Status, END_OF_PARAMETERS, REPORT_SIZE};
+use protocol::parameter::{Parameter, Value, PARAMETER_COUNT};
+
+use crate::config_slots::{self, Slot};
+use crate::device_info::{device_info, handshake};
+use crate::notifications::{self, Change};
+use crate::parameters::PARAMETERS;
+use crate::system::{self, RebootTarget};
+
+/// The HID interface that carries the parameter protocol.
+pub type HidControl = HidReaderWriter<'static, usb::Driver<'static, peripherals::USB_OTG_HS>, REPORT_SIZE, REPORT_SIZE>;
+
+/// Handle a request report, and assemble the response. Subscribe requests update `subscribed`.
+fn handle_request(report: &[u8], subscribed: &mut bool) -> Response {
+    let (sequence, request) = match Request::decode(report) {
+        Ok(request) => request,
+        Err(status) => {
+            let mut response = Response::new(
+                report.first().copied().unwrap_or_default(),
+                report.get(1).copied().unwrap_or_default(),
+            );
+            response.set_status(status);
+            return response;
+        }
+    };
+
+    let mut response = Response::new(request.command() as u8, sequence);
+
+    let parameter = |id: u16| Parameter::from_id(id).ok_or(Status::UnknownParameter);
+
+    let result = match request {
+        Request::Get { id } => parameter(id).and_then(|parameter| {
+            response.push(parameter, PARAMETERS.get(parameter)?.to_raw());
+            Ok(())
+        }),
+        Request::Set { id, value } => parameter(id).and_then(|parameter| {
+            let value = Value::from_raw(value, parameter.value_type()).ok_or(Status::InvalidValue)?;
+
+            PARAMETERS.set(parameter, value)?;
+            response.push(parameter, PARAMETERS.get(parameter)?.to_raw());
+            Ok(())
+        }),
+        Request::ReadAll { start_index } => {
+            let mut index = start_index as usize;
+
+            while let Some(parameter) = Parameter::from_index(index) {
+                // Parameters of unused stages are skipped.
+                if let Ok(value) = PARAMETERS.get(parameter) {
+                    if !response.push(parameter, value.to_raw()) {
+                        break;
+                    }
+                }
+
+                index += 1;
+            }
+
+            response.set_next_index(if index >= PARAMETER_COUNT {
+                END_OF_PARAMETERS
+            } else {
+                index as u16
+            });
+
+            Ok(())
+        }
+        Request::DeviceInfo => {
+            response.set_data(&device_info().encode());
+            Ok(())
+        }
+        Request::Handshake => {
+            response.set_data(&handshake().encode());
+            Ok(())
+        }
+        Request::Subscribe { enabled } => {
+            *subscribed = enabled;
+            Ok(())
+        }
+        Request::Slot { action } => action
+            .map_or(Ok(()), config_slots::perform)
+            .map_err(|_| Status::InvalidState)
+            .map(|()| {
+                response.set_data(&[
+                    (config_slots::active_slot() == Slot::B) as u8,
+                    config_slots::staged() as u8,
+                ]);
+            }),
+        Request::Reboot { bootloader } => {
+            system::request_reboot(if bootloader {
+                RebootTarget::Bootloader
+            } else {
+                RebootTarget::Firmware
+            });
+            Ok(())
+        }
+    };
+
+    if let Err(status) = result {
+        response.set_status(status);
+    }
+
+    response
+}
+
+/// Answers parameter requests from the host.
+#[embassy_executor::task]
+pub async fn hid_control_task(hid: HidControl) {
+    let (mut reader, mut writer) = hid.split();
+    let mut report = [0u8; REPORT_SIZE];
+    let mut changes = notifications::subscribe();
+
+    loop {
+        reader.ready().await;
+        info!("HID control ready");
+
+        // Hosts subscribe anew after reconnecting.
+        let mut subscribed = false;
+
+        loop {
+            let response = match select(reader.read(&mut report), changes.next_message_pure()).await {
+                Either::First(Ok(length)) => handle_request(&report[..length], &mut subscribed),
+                Either::First(Err(error)) => {
+                    debug!("HID control: Read error {}", error);
+                    break;
+                }
+                Either::Second(Change { parameter, value }) => {
+                    if !subscribed {
+                        continue;
+                    }
+
+                    let mut response = Response::new(Command::Notification as u8, 0);
+                    response.push(parameter, value.to_raw());
+                    response
+                }
+            };
+
+            if let Err(error) = writer.write(response.report()).await {
+                debug!("HID control: Write error {}", error);
+                break;
+            }
+        }
+    }
+}
@@ -0,0 +1,147 @@
+//! I2C slave interface that exposes the control register map to external controllers (e.g. the Raspberry Pi).
+//!
+//! A write transaction starts with the register address, followed by any number of values to write.
+//! A read transaction returns register values, starting at the last written register address.
+//! The register address auto-increments with every value that is read or written.
+use core::cell::Cell;
+
+use defmt::{debug, info};
+use embassy_stm32::i2c::{self, I2c};
+use embassy_stm32::mode::Blocking;
+use embassy_stm32::pac::i2c::vals;
+use embassy_stm32::time::Hertz;
+use embassy_stm32::{interrupt, pac, peripherals};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use static_cell::StaticCell;
+
+use crate::registers::{self, RegisterAddress};
+
+/// The seven-bit address of the device on the I2C bus.
+pub const I2C_SLAVE_ADDRESS: u8 = 0x28;
+
+/// The I2C driver, which must stay alive, since dropping it disables the peripheral.
+static I2C_SLAVE: StaticCell<I2c<'static, Blocking>> = StaticCell::new();
+
+/// Resources that are required for the I2C slave interface.
+#[allow(missing_docs)]
+pub struct I2cSlaveResources {
+    pub i2c: peripherals::I2C4,
+    pub scl: peripherals::PB8,
+    pub sda: peripherals::PB9,
+}
+
+/// Set up the I2C slave interface.
+///
+/// Register writes are applied by the [`registers::register_write_task`].
+pub fn init(resources: I2cSlaveResources) {
+    // The driver sets up clocks, pins and bus timings. It does not support slave operation,
+    // so the peripheral is reconfigured on register level below.
+    I2C_SLAVE.init(I2c::new_blocking(
+        resources.i2c,
+        resources.scl,
+        resources.sda,
+        Hertz(400_000),
+        i2c::Config::default(),
+    ));
+
+    let regs = pac::I2C4;
+
+    regs.cr1().modify(|w| w.set_pe(false));
+    regs.oar1().write(|w| {
+        w.set_oa1((I2C_SLAVE_ADDRESS as u16) << 1);
+        w.set_oa1mode(vals::Addmode::BIT7);
+        w.set_oa1en(true);
+    });
+    regs.cr1().modify(|w| {
+        w.set_addrie(true);
+        w.set_rxie(true);
+        w.set_txie(true);
+        w.set_stopie(true);
+        w.set_nackie(true);
+        w.set_errie(true);
+        w.set_pe(true);
+    });
+
+    unsafe {
+        cortex_m::peripheral::NVIC::unmask(interrupt::I2C4_EV);
+        cortex_m::peripheral::NVIC::unmask(interrupt::I2C4_ER);
+    }
+
+    info!("I2C slave listening at address {:#x}", I2C_SLAVE_ADDRESS);
+}
+
+#[interrupt]
+fn I2C4_EV() {
+    /// The register address for the next read or write.
+    static REGISTER_ADDRESS: Mutex<CriticalSectionRawMutex, Cell<RegisterAddress>> = Mutex::new(Cell::new(0));
+
+    /// Whether the next received byte is a register address.
+    static EXPECT_ADDRESS: Mutex<CriticalSectionRawMutex, Cell<bool>> = Mutex::new(Cell::new(false));
+
+    critical_section::with(|cs| {
+        let regs = pac::I2C4;
+        let status = regs.isr().read();
+
+        let register_address = REGISTER_ADDRESS.borrow(cs);
+        let expect_address = EXPECT_ADDRESS.borrow(cs);
+
+        if status.addr() {
+            match status.dir() {
+                vals::Dir::WRITE => expect_address.set(true),
+                vals::Dir::READ => {
+                    // Flush stale transmit data.
+                    regs.isr().write(|w| w.set_txe(true));
+                }
+            }
+
+            regs.icr().write(|w| w.set_addrcf(true));
+        }
+
+        if status.rxne() {
+            let value = regs.rxdr().read().rxdata();
+
+            if expect_address.get() {
+                expect_address.set(false);
+                register_address.set(value);
+            } else {
+                registers::queue_write(register_address.get(), value);
+                register_address.set(register_address.get().wrapping_add(1));
+            }
+        }
+
+        if status.txis() {
+            regs.txdr()
+                .write(|w| w.set_txdata(registers::read(register_address.get())));
+            register_address.set(register_address.get().wrapping_add(1));
+        }
+
+        if status.nackf() {
+            regs.icr().write(|w| w.set_nackcf(true));
+        }
+
+        if status.stopf() {
+            expect_address.set(false);
+            regs.icr().write(|w| w.set_stopcf(true));
+        }
+    });
+}
+
+#[interrupt]
+fn I2C4_ER() {
+    let regs = pac::I2C4;
+    let status = regs.isr().read();
+
+    debug!(
+        "I2C slave: Bus error (berr {}, arlo {}, ovr {})",
+        status.berr(),
+        status.arlo(),
+        status.ovr()
+    );
+
+    regs.icr().write(|w| {
+        w.set_berrcf(true);
+        w.set_arlocf(true);
+        w.set_ovrcf(true);
+    });
+}
@@ -0,0 +1,388 @@
+//! Receiver for infrared remote controls, with the NEC and RC5 protocols.
+//!
+//! The demodulated output of the receiver module (active low, e.g. a TSOP38238) is timed on every edge, by means of
+//! EXTI and the system timer. Received codes are mapped to device actions (see [`IrAction`]). The codes of the
+//! actions are learned with the shell (`ir learn <action>`), and are part of the device settings.
+use core::cell::Cell;
+
+use embassy_stm32::exti::ExtiInput;
+use embassy_stm32::gpio::Pull;
+use embassy_stm32::peripherals;
+use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_time::{Duration, Instant};
+use protocol::ir::{IrAction, IrCode, IrCodes, IrProtocol, ACTION_COUNT};
+
+use crate::control::{self, CONTROL};
+use crate::presets;
+use crate::*;
+
+/// The volume change per button press or repetition, in steps of 0.5 dB.
+const VOLUME_STEP: u8 = 2;
+
+/// A frame is a repetition of the previous one, if it follows within this time.
+const REPEAT_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// The remote codes of all actions.
+static IR_CODES: Mutex<ThreadModeRawMutex, Cell<IrCodes>> = Mutex::new(Cell::new([None; ACTION_COUNT]));
+
+/// The action that the next received code is assigned to.
+static LEARN_ACTION: Mutex<ThreadModeRawMutex, Cell<Option<IrAction>>> = Mutex::new(Cell::new(None));
+
+/// Get the remote codes of all actions.
+pub fn ir_codes() -> IrCodes {
+    IR_CODES.lock(|codes| codes.get())
+}
+
+/// Replace the remote codes of all actions.
+pub fn set_ir_codes(ir_codes: IrCodes) {
+    IR_CODES.lock(|codes| codes.set(ir_codes));
+}
+
+/// Assign the next received code to an action.
+pub fn learn(action: IrAction) {
+    LEARN_ACTION.lock(|learn_action| learn_action.set(Some(action)));
+}
+
+/// The name of an action, as used by the text interfaces.
+pub fn action_name(action: IrAction) -> &'static str {
+    match action {
+        IrAction::VolumeUp => "volume-up",
+        IrAction::VolumeDown => "volume-down",
+        IrAction::Mute => "mute",
+        IrAction::Source => "source",
+        IrAction::Standby => "standby",
+        IrAction::NextPreset => "next-preset",
+    }
+}
+
+/// Parse the name of an action.
+pub fn parse_action(name: &str) -> Option<IrAction> {
+    IrAction::ALL.into_iter().find(|action| action_name(*action) == name)
+}
+
+/// The name of a protocol, as used by the text interfaces.
+pub fn protocol_name(protocol: IrProtocol) -> &'static str {
+    match protocol {
+        IrProtocol::Nec => "nec",
+        IrProtocol::Rc5 => "rc5",
+    }
+}
+
+/// Resources that are required for the remote control receiver.
+#[allow(missing_docs)]
+pub struct IrRemoteResources {
+    pub pin: peripherals::PE3,
+    pub exti: peripherals::EXTI3,
+}
+
+/// A period of constant level of the receiver output.
+#[derive(Clone, Copy)]
+struct Pulse {
+    /// Whether the carrier was present (the receiver output was low).
+    mark: bool,
+    duration_us: u32,
+}
+
+/// A decoded frame.
+#[derive(Clone, Copy)]
+enum Frame {
+    /// A code, with the toggle bit of protocols that have one.
+    Code { code: IrCode, toggle: bool },
+    /// A repetition of the previous code, while the button is held.
+    Repeat,
+}
+
+/// Whether a duration matches a nominal duration, with tolerance for the receiver and for task latency.
+fn near(duration_us: u32, nominal_us: u32) -> bool {
+    duration_us.abs_diff(nominal_us) <= nominal_us / 4 + 100
+}
+
+/// The state of the NEC decoder.
+#[derive(Clone, Copy)]
+enum Nec {
+    Idle,
+    /// The 9 ms leader mark was received.
+    Leader,
+    /// A repeat code is complete, after its final mark.
+    RepeatMark,
+    /// Waiting for the mark of the next bit, or the final mark.
+    BitMark {
+        data: u32,
+        count: u8,
+    },
+    /// Waiting for the space of a bit, which holds its value.
+    BitSpace {
+        data: u32,
+        count: u8,
+    },
+}
+
+/// Decoder for the NEC protocol: a 9 ms leader, and 32 bits of address, inverted address, command, and inverted
+/// command, least significant bit first. Bits are coded in the length of the space after a 562 µs mark.
+struct NecDecoder {
+    state: Nec,
+}
+
+impl NecDecoder {
+    const fn new() -> Self {
+        NecDecoder { state: Nec::Idle }
+    }
+
+    fn pulse(&mut self, pulse: Pulse) -> Option<Frame> {
+        let Pulse { mark, duration_us } = pulse;
+
+        let (state, frame) = match self.state {
+            Nec::Leader if !mark && near(duration_us, 4500) => (Nec::BitMark { data: 0, count: 0 }, None),
+            Nec::Leader if !mark && near(duration_us, 2250) => (Nec::RepeatMark, None),
+            Nec::RepeatMark if mark && near(duration_us, 562) => (Nec::Idle, Some(Frame::Repeat)),
+            Nec::BitMark { data, count } if mark && near(duration_us, 562) => {
+                if count == 32 {
+                    (Nec::Idle, decode_nec(data))
+                } else {
+                    (Nec::BitSpace { data, count }, None)
+                }
+            }
+            Nec::BitSpace { data, count } if !mark && near(duration_us, 562) => {
+                (Nec::BitMark { data, count: count + 1 }, None)
+            }
+            Nec::BitSpace { data, count } if !mark && near(duration_us, 1687) => (
+                Nec::BitMark {
+                    data: data | (1 << count),
+                    count: count + 1,
+                },
+                None,
+            ),
+            // Anything else starts over, possibly with a new leader.
+            _ if mark && near(duration_us, 9000) => (Nec::Leader, None),
+            _ => (Nec::Idle, None),
+        };
+
+        self.state = state;
+        frame
+    }
+}
+
+fn decode_nec(data: u32) -> Option<Frame> {
+    let [address_low, address_high, command, inverted_command] = data.to_le_bytes();
+
+    if command != !inverted_command {
+        return None;
+    }
+
+    // Extended NEC uses the inverted address byte for a 16 bit address.
+    let address = if address_high == !address_low {
+        address_low as u16
+    } else {
+        u16::from_le_bytes([address_low, address_high])
+    };
+
+    Some(Frame::Code {
+        code: IrCode {
+            protocol: IrProtocol::Nec,
+            address,
+            command,
+        },
+        toggle: false,
+    })
+}
+
+/// The number of half-bits of an RC5 frame.
+const RC5_HALF_BIT_COUNT: u8 = 28;
+
+/// Decoder for the RC5 protocol: 14 Manchester coded bits of 1.778 ms (two start bits, toggle, 5 bit address,
+/// 6 bit command), most significant bit first. A one is a space followed by a mark.
+struct Rc5Decoder {
+    /// The levels of the received half-bits, with a set bit for a mark.
+    levels: u32,
+    count: u8,
+}
+
+impl Rc5Decoder {
+    const fn new() -> Self {
+        Rc5Decoder { levels: 0, count: 0 }
+    }
+
+    fn pulse(&mut self, pulse: Pulse) -> Option<Frame> {
+        if self.count == 0 {
+            // Idle, until the first mark. The first half of the start bit is a space, which cannot be distinguished
+            // from idle.
+            if !pulse.mark {
+                return None;
+            }
+
+            self.levels = 0;
+            self.count = 1;
+        }
+
+        let half_bits = if near(pulse.duration_us, 889) {
+            1
+        } else if near(pulse.duration_us, 1778) {
+            2
+        } else {
+            self.count = 0;
+            return None;
+        };
+
+        for _ in 0..half_bits {
+            if self.count < RC5_HALF_BIT_COUNT {
+                if pulse.mark {
+                    self.levels |= 1 << self.count;
+                }
+                self.count += 1;
+            }
+        }
+
+        // The second half of a final zero is a space, which merges with idle.
+        if self.count == RC5_HALF_BIT_COUNT - 1 && pulse.mark {
+            self.count = RC5_HALF_BIT_COUNT;
+        }
+
+        if self.count < RC5_HALF_BIT_COUNT {
+            return None;
+        }
+
+        self.count = 0;
+        self.decode()
+    }
+
+    fn decode(&self) -> Option<Frame> {
+        let mut bits = 0u16;
+
+        for bit in 0..RC5_HALF_BIT_COUNT / 2 {
+            let first = self.levels >> (2 * bit) & 1;
+            let second = self.levels >> (2 * bit + 1) & 1;
+
+            if first == second {
+                return None;
+            }
+
+            bits = bits << 1 | second as u16;
+        }
+
+        // Extended RC5 uses the inverted second start bit as the seventh command bit.
+        let field = (bits >> 12) & 1;
+        let command = (bits & 0x3F) | ((field ^ 1) << 6);
+
+        Some(Frame::Code {
+            code: IrCode {
+                protocol: IrProtocol::Rc5,
+                address: (bits >> 6) & 0x1F,
+                command: command as u8,
+            },
+            toggle: (bits >> 11) & 1 != 0,
+        })
+    }
+}
+
+/// Perform the action of a code.
+fn perform(action: IrAction) {
+    match action {
+        IrAction::VolumeUp => CONTROL.set_attenuation(CONTROL.attenuation().saturating_sub(VOLUME_STEP)),
+        IrAction::VolumeDown => {
+            let attenuation = CONTROL.attenuation().saturating_add(VOLUME_STEP);
+            CONTROL.set_attenuation(attenuation.min(control::MUTED_ATTENUATION - 1));
+        }
+        IrAction::Mute => CONTROL.set_muted(!CONTROL.muted()),
+        IrAction::Source => CONTROL.select_next_source(),
+        IrAction::Standby => CONTROL.set_standby(!CONTROL.standby()),
+        IrAction::NextPreset => presets::load_next(),
+    }
+}
+
+/// Learn or perform a received code.
+fn handle_code(code: IrCode, repeat: bool) {
+    if repeat {
+        // Only volume changes repeat, while the button is held.
+        if let Some(action @ (IrAction::VolumeUp | IrAction::VolumeDown)) = find_action(code) {
+            perform(action);
+        }
+        return;
+    }
+
+    if let Some(action) = LEARN_ACTION.lock(|learn_action| learn_action.take()) {
+        IR_CODES.lock(|codes| {
+            let mut ir_codes = codes.get();
+
+            // A code triggers a single action.
+            for assigned in ir_codes.iter_mut().filter(|assigned| **assigned == Some(code)) {
+                *assigned = None;
+            }
+
+            ir_codes[action as usize] = Some(code);
+            codes.set(ir_codes);
+        });
+
+        log!(
+            info,
+            "IR: Learned code {}/{:#x}/{:#x} for {}",
+            protocol_name(code.protocol),
+            code.address,
+            code.command,
+            action_name(action)
+        );
+        return;
+    }
+
+    match find_action(code) {
+        Some(action) => perform(action),
+        None => log!(
+            debug,
+            "IR: Unassigned code {}/{:#x}/{:#x}",
+            protocol_name(code.protocol),
+            code.address,
+            code.command
+        ),
+    }
+}
+
+fn find_action(code: IrCode) -> Option<IrAction> {
+    let index = ir_codes().iter().position(|assigned| *assigned == Some(code))?;
+    IrAction::try_from(index as u8).ok()
+}
+
+/// Decodes remote control codes, and performs their actions.
+#[embassy_executor::task]
+pub async fn ir_remote_task(resources: IrRemoteResources) {
+    let mut input = ExtiInput::new(resources.pin, resources.exti, Pull::Up);
+
+    let mut nec = NecDecoder::new();
+    let mut rc5 = Rc5Decoder::new();
+
+    let mut last_edge = Instant::now();
+    // The last code, its toggle bit, and the time of its last frame.
+    let mut last_frame: Option<(IrCode, bool, Instant)> = None;
+
+    loop {
+        input.wait_for_any_edge().await;
+
+        let now = Instant::now();
+        let pulse = Pulse {
+            // The output is low during marks, so a high level follows a mark.
+            mark: input.is_high(),
+            duration_us: (now - last_edge).as_micros().min(u32::MAX as u64) as u32,
+        };
+        last_edge = now;
+
+        for frame in [nec.pulse(pulse), rc5.pulse(pulse)].into_iter().flatten() {
+            let recent = |(_, _, time): (IrCode, bool, Instant)| now - time < REPEAT_TIMEOUT;
+
+            match frame {
+                Frame::Repeat => {
+                    if let Some((code, toggle, _)) = last_frame.filter(|last| recent(*last)) {
+                        last_frame = Some((code, toggle, now));
+                        handle_code(code, true);
+                    }
+                }
+                Frame::Code { code, toggle } => {
+                    // RC5 repeats full frames with an unchanged toggle bit, while the button is held.
+                    let repeat = code.protocol == IrProtocol::Rc5
+                        && last_frame.is_some_and(|last| recent(last) && last.0 == code && last.1 == toggle);
+
+                    last_frame = Some((code, toggle, now));
+                    handle_code(code, repeat);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,83 @@
+//! The block size of the signal processing, and a low-latency mode for the Raspberry Pi input.
+//!
+//! Samples are read, processed, and played in blocks. The latency from input to output is about three blocks: one for
+//! filling the read buffer, one for processing, and one in the write buffer of the amplifier SAI. The block size is
+//! therefore a tradeoff between latency and CPU load:
+//!
+//! - [`LatencyMode::Normal`] processes blocks of 1 ms (48 frames), for a latency of about 3 ms.
+//! - [`LatencyMode::Low`] processes blocks of 0.25 ms (12 frames), for a latency of about 0.75 ms, e.g. for live
+//!   instruments that are fed through the Raspberry Pi. Every block carries a fixed overhead (waking the task, the DMA
+//!   interrupts, gains and levels), which is spent four times as often. The load is highest with all filter stages and
+//!   FIR taps in use; the shell `cpu` shows the remaining DSP headroom of the shorter block period.
+//!
+//! Only the Raspberry Pi input follows the mode. The block sizes of USB and S/PDIF are given by their interfaces, and
+//! stay at 1 ms. A change of the mode restarts the playback, like a change of the source.
+//!
+//! The default mode is [`LatencyMode::Low`] with the `low_latency` feature, and [`LatencyMode::Normal`] otherwise. The
+//! mode is not stored with the settings; a startup script can configure it (shell `latency`, see
+//! [`crate::startup_script`]).
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use crate::*;
+
+/// The number of frames per block in [`LatencyMode::Normal`] (1 ms).
+pub const NORMAL_FRAME_COUNT: usize = DEFAULT_SAMPLE_COUNT / INPUT_CHANNEL_COUNT;
+
+/// The number of frames per block in [`LatencyMode::Low`] (0.25 ms).
+pub const LOW_FRAME_COUNT: usize = NORMAL_FRAME_COUNT / 4;
+
+/// The latency mode of the signal processing.
+#[derive(Clone, Copy, PartialEq, Debug, defmt::Format)]
+pub enum LatencyMode {
+    /// Blocks of 1 ms.
+    Normal,
+    /// Blocks of 0.25 ms.
+    Low,
+}
+
+/// Whether the low-latency mode is selected.
+static LOW_LATENCY: AtomicBool = AtomicBool::new(cfg!(feature = "low_latency"));
+
+/// The selected latency mode.
+pub fn mode() -> LatencyMode {
+    if LOW_LATENCY.load(Ordering::Relaxed) {
+        LatencyMode::Low
+    } else {
+        LatencyMode::Normal
+    }
+}
+
+/// Select the latency mode. Takes effect with the next block.
+pub fn set_mode(mode: LatencyMode) {
+    LOW_LATENCY.store(mode == LatencyMode::Low, Ordering::Relaxed);
+}
+
+/// The name of a latency mode.
+pub fn mode_name(mode: LatencyMode) -> &'static str {
+    match mode {
+        LatencyMode::Normal => "normal",
+        LatencyMode::Low => "low",
+    }
+}
+
+/// The latency mode of a name.
+pub fn mode_from_name(name: &str) -> Option<LatencyMode> {
+    match name {
+        "normal" => Some(LatencyMode::Normal),
+        "low" => Some(LatencyMode::Low),
+        _ => None,
+    }
+}
+
+/// The number of frames per block of the Raspberry Pi input in a latency mode.
+pub fn frame_count(mode: LatencyMode) -> usize {
+    match mode {
+        LatencyMode::Normal => NORMAL_FRAME_COUNT,
+        LatencyMode::Low => LOW_FRAME_COUNT,
+    }
+}
+
+/// The approximate latency from input to output in a latency mode, in microseconds.
+pub fn latency_us(mode: LatencyMode) -> u32 {
+    (3 * frame_count(mode) as u64 * 1_000_000 / SAMPLE_RATE_HZ as u64) as u32
+}
@@ -0,0 +1,99 @@
+//! Instrumentation of the latency from USB to the amplifiers, with the `latency_probe` feature.
+//!
+//! The streaming task marks the first sample of a packet with the time of its reception, at its position in the USB
+//! ring (see [`UsbRing`]). The audio routing finds the mark in the block that it reads from the ring, and once the
+//! block was submitted to the DMA of the amplifier SAI, the time since the reception is the measured latency. It is
+//! reported in telemetry (see [`crate::telemetry`]). Only one mark is in flight at a time; a mark whose samples are
+//! dropped (e.g. when the ring is cleared for a new source) is discarded with the next read.
+//!
+//! The probe output toggles on every measurement, such that a scope can relate it to the analog output (e.g. of a
+//! step at the input). The samples still spend the write buffer of the SAI before they play, which the measurement
+//! does not include.
+use core::cell::{Cell, RefCell};
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use embassy_stm32::gpio::{Level, Output, Speed};
+use embassy_stm32::peripherals;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_time::Instant;
+
+use crate::*;
+
+/// Resources that are required for the latency probe.
+#[allow(missing_docs)]
+pub struct LatencyProbeResources {
+    pub output: peripherals::PD11,
+}
+
+/// A sample in the USB ring, and the time of its reception.
+#[derive(Clone, Copy)]
+struct Mark {
+    position: usize,
+    received: Instant,
+}
+
+/// The mark that waits for the audio routing.
+static MARK: Mutex<CriticalSectionRawMutex, Cell<Option<Mark>>> = Mutex::new(Cell::new(None));
+
+/// The last measured latency in microseconds, or [`u32::MAX`] before the first measurement.
+static LATENCY_US: AtomicU32 = AtomicU32::new(u32::MAX);
+
+/// The output that toggles on every measurement.
+static OUTPUT: Mutex<CriticalSectionRawMutex, RefCell<Option<Output<'static>>>> = Mutex::new(RefCell::new(None));
+
+/// Set up the probe output.
+pub fn init(resources: LatencyProbeResources) {
+    let output = Output::new(resources.output, Level::Low, Speed::Low);
+    OUTPUT.lock(|cell| *cell.borrow_mut() = Some(output));
+}
+
+/// Mark the sample at a position of the USB ring as received now, unless a mark is in flight.
+pub fn usb_received(position: usize) {
+    MARK.lock(|mark| {
+        if mark.get().is_none() {
+            mark.set(Some(Mark {
+                position,
+                received: Instant::now(),
+            }));
+        }
+    });
+}
+
+/// Take the mark, if it is among the `count` samples that were read from a position of the USB ring. Returns the
+/// time of its reception.
+pub fn usb_read(position: usize, count: usize) -> Option<Instant> {
+    MARK.lock(|mark| {
+        let offset = mark.get()?.position.wrapping_sub(position);
+
+        if offset < count {
+            mark.take().map(|mark| mark.received)
+        } else if offset > USB_RING_SIZE {
+            // The marked sample was read before, or dropped.
+            mark.set(None);
+            None
+        } else {
+            None
+        }
+    })
+}
+
+/// Measure the latency of a marked sample, which was just submitted to the amplifier SAI.
+pub fn submitted(received: Instant) {
+    let latency_us = received.elapsed().as_micros().min(u32::MAX as u64 - 1) as u32;
+    LATENCY_US.store(latency_us, Ordering::Relaxed);
+
+    OUTPUT.lock(|cell| {
+        if let Some(output) = cell.borrow_mut().as_mut() {
+            output.toggle();
+        }
+    });
+}
+
+/// The last measured latency from USB to the amplifier SAI in microseconds, if any.
+pub fn latency_us() -> Option<u32> {
+    match LATENCY_US.load(Ordering::Relaxed) {
+        u32::MAX => None,
+        latency_us => Some(latency_us),
+    }
+}
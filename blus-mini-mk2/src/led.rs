@@ -0,0 +1,659 @@
+//! Indicator LEDs, on MCU pins or on the GPIO expander.
+//!
+//! The LEDs show functions (see [`LedFunction`]): the active source, mute, standby, and a master volume that was
+//! reduced by the clip protection (see [`led_task`]). The status LED blinks the codes of active faults (see
+//! [`status_led_task`]), and the heartbeat LED shows the health of the tasks (see [`heartbeat_task`]). Which LED shows
+//! which function is stored with the settings (see [`set_led_map`], and [`DEFAULT_LED_MAP`]).
+//!
+//! The LEDs on MCU pins are driven by PWM (TIM8, see [`init`]), with a configurable brightness. In night mode, they
+//! dim to the night brightness: after a time without operation (no change of volume, mute, standby, or source), and
+//! during the night hours of the wall clock (see [`crate::clock`], in UTC). Operating the device restores the
+//! brightness, until the next time without operation, or for [`NIGHT_WAKE_TIME`] during the night hours. The LEDs on
+//! the GPIO expander only switch on and off.
+//!
+//! In meter mode, the four LEDs on MCU pins show the peak output level as a bar (see [`METER_THRESHOLDS_DB`])
+//! instead of the active source. The bar follows rising levels immediately, and falls by [`METER_DECAY_DB_PER_S`].
+//!
+//! During boot, the LEDs on MCU pins show its progress instead (see [`BootStage`]): the LED of every reached stage
+//! lights up, and the LED of the next stage blinks. A boot that gets stuck keeps its LEDs this way. Once all stages
+//! are reached, and after [`BOOT_DISPLAY_TIMEOUT`] at the latest (e.g. without a USB host), the LEDs return to normal.
+//! Without a status display (see [`crate::display`]), which shows the firmware version at boot, the LEDs on MCU pins
+//! blink it next: the major, minor, and patch version as numbers of pulses.
+//!
+//! The brightness and the mode are not stored with the settings; a startup script can configure them (shell `led`,
+//! see [`crate::startup_script`]).
+use core::cell::{Cell, RefCell};
+use core::sync::atomic::{AtomicBool, AtomicU16, AtomicU8, Ordering};
+
+use embassy_stm32::gpio::Output;
+use embassy_stm32::peripherals;
+use embassy_stm32::timer::simple_pwm::SimplePwm;
+use embassy_stm32::timer::Channel;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_time::{Duration, Instant, Ticker, Timer};
+use protocol::led::{LedFunction, LedMap, LedOutput, DEFAULT_LED_MAP};
+
+use crate::clock;
+use crate::control::{VolumeWriter, CONTROL, MUTED_ATTENUATION};
+use crate::device_info::device_info;
+use crate::display;
+use crate::faults::{self, Fault};
+use crate::gpio_expander;
+use crate::watchdog::{self, Health};
+use crate::*;
+
+/// The default brightness in percent.
+pub const DEFAULT_BRIGHTNESS: u8 = 100;
+
+/// The default brightness in night mode in percent.
+pub const DEFAULT_NIGHT_BRIGHTNESS: u8 = 10;
+
+/// The maximum time without operation, after which night mode starts.
+pub const MAX_NIGHT_DELAY: Duration = Duration::from_secs(240 * 60);
+
+/// The time that operating the device restores the brightness during the night hours.
+pub const NIGHT_WAKE_TIME: Duration = Duration::from_secs(10);
+
+/// The peak output levels in dBFS, from which on the LEDs of the bar light up in meter mode, from the first PWM
+/// channel on.
+pub const METER_THRESHOLDS_DB: [f32; 4] = [-36.0, -24.0, -12.0, -3.0];
+
+/// The rate at which the bar falls in meter mode in dB/s.
+pub const METER_DECAY_DB_PER_S: f32 = 30.0;
+
+/// The time since startup, after which the LEDs stop showing the boot progress, even if stages are missing.
+pub const BOOT_DISPLAY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The time that the LEDs show a complete boot.
+pub const BOOT_COMPLETE_TIME: Duration = Duration::from_millis(500);
+
+/// The PWM frequency of the LEDs, which keeps clear of visible flicker.
+pub const PWM_FREQUENCY_HZ: u32 = 1_000;
+
+/// The on and off time of a pulse of the version code.
+const VERSION_PULSE_TIME: Duration = Duration::from_millis(300);
+
+/// The pause between the numbers of the version code.
+const VERSION_PAUSE_TIME: Duration = Duration::from_millis(1200);
+
+/// The period of blinking of the next stage, during boot.
+const BOOT_BLINK_PERIOD: Duration = Duration::from_millis(200);
+
+/// The on time of a heartbeat pulse.
+const HEARTBEAT_PULSE_TIME: Duration = Duration::from_millis(50);
+
+/// The period of heartbeats, while all tasks are alive.
+const HEARTBEAT_PERIOD: Duration = Duration::from_millis(1500);
+
+/// The on and off time of blinking, while a task is late.
+const LATE_BLINK_TIME: Duration = Duration::from_millis(500);
+
+/// The on and off time of blinking, while a task stalled.
+const STALLED_BLINK_TIME: Duration = Duration::from_millis(100);
+
+/// The on and off time of a pulse of a blink code.
+const PULSE_TIME: Duration = Duration::from_millis(250);
+
+/// The pause after a blink code.
+const PAUSE_TIME: Duration = Duration::from_millis(1500);
+
+/// The rate at which the indicator LEDs are updated.
+const UPDATE_RATE_HZ: u64 = 20;
+
+/// Marks night hours that are disabled.
+const NO_NIGHT_HOURS: u16 = u16::MAX;
+
+/// The PWM channels of the LEDs on MCU pins, by index (blue, green, yellow, and red).
+const CHANNELS: [Channel; 4] = [Channel::Ch1, Channel::Ch2, Channel::Ch3, Channel::Ch4];
+
+/// The PWM of the LEDs on MCU pins, once set up by [`init`].
+static PWM: Mutex<CriticalSectionRawMutex, RefCell<Option<SimplePwm<'static, peripherals::TIM8>>>> =
+    Mutex::new(RefCell::new(None));
+
+/// The mask of PWM channels (by index) whose LEDs are on.
+static PWM_ON: AtomicU8 = AtomicU8::new(0);
+
+/// The LEDs of the functions.
+static LED_MAP: Mutex<CriticalSectionRawMutex, Cell<LedMap>> = Mutex::new(Cell::new(DEFAULT_LED_MAP));
+
+/// The mask of functions (by identifier) that are on.
+static FUNCTIONS_ON: AtomicU8 = AtomicU8::new(0);
+
+/// The brightness in percent.
+static BRIGHTNESS: AtomicU8 = AtomicU8::new(DEFAULT_BRIGHTNESS);
+
+/// The brightness in night mode in percent.
+static NIGHT_BRIGHTNESS: AtomicU8 = AtomicU8::new(DEFAULT_NIGHT_BRIGHTNESS);
+
+/// The time without operation in minutes, after which night mode starts, or `0`, if disabled.
+static NIGHT_DELAY_MIN: AtomicU16 = AtomicU16::new(0);
+
+/// The night hours as start hour (high byte) and end hour (low byte), or [`NO_NIGHT_HOURS`].
+static NIGHT_HOURS: AtomicU16 = AtomicU16::new(NO_NIGHT_HOURS);
+
+/// Whether night mode is active.
+static NIGHT: AtomicBool = AtomicBool::new(false);
+
+/// Whether the LEDs on MCU pins show the output level, instead of the active source.
+static METER_MODE: AtomicBool = AtomicBool::new(false);
+
+/// The mask of PWM channels (by index) whose LEDs are on in meter mode.
+static METER_ON: AtomicU8 = AtomicU8::new(0);
+
+/// The mask of reached boot stages (by index).
+static BOOT_PROGRESS: AtomicU8 = AtomicU8::new(0);
+
+/// Whether the LEDs on MCU pins show the boot progress.
+static BOOT_DISPLAY: AtomicBool = AtomicBool::new(true);
+
+/// Whether the LEDs on MCU pins show the firmware version.
+static VERSION_DISPLAY: AtomicBool = AtomicBool::new(false);
+
+/// The mask of PWM channels (by index) whose LEDs are on in the version code.
+static VERSION_ON: AtomicU8 = AtomicU8::new(0);
+
+/// A stage of the boot, which is shown on the LED of the PWM channel with the same index (blue, green, and yellow).
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum BootStage {
+    /// The clocks are configured, and the LEDs started.
+    Clocks = 0,
+    /// The amplifier control is set up, with the amplifiers shut down until a source is active.
+    Amplifiers = 1,
+    /// The USB host configured the device.
+    Usb = 2,
+}
+
+impl BootStage {
+    /// All stages, in order.
+    pub const ALL: [BootStage; 3] = [BootStage::Clocks, BootStage::Amplifiers, BootStage::Usb];
+
+    /// The bit of the stage in masks of stages.
+    pub const fn mask(self) -> u8 {
+        1 << self as u8
+    }
+}
+
+/// An LED that lights up with a high output level.
+pub enum Led<'d> {
+    /// An LED on an MCU pin.
+    Pin(Output<'d>),
+    /// An LED on an output of the GPIO expander.
+    Expander(u8),
+    /// An LED on an MCU pin, on a channel of the LED PWM (see [`init`]).
+    Pwm(Channel),
+    /// The LED of a function, by the LED map (see [`set_led_map`]). It is lit while any function on it is on.
+    Function(LedFunction),
+}
+
+impl Led<'_> {
+    /// Switch the LED on or off.
+    pub fn set(&mut self, on: bool) {
+        match self {
+            Led::Pin(output) => output.set_level(on.into()),
+            Led::Expander(output) => gpio_expander::set_output(*output, on),
+            Led::Pwm(channel) => {
+                let mask = 1 << channel.index();
+
+                if on {
+                    PWM_ON.fetch_or(mask, Ordering::Relaxed);
+                } else {
+                    PWM_ON.fetch_and(!mask, Ordering::Relaxed);
+                }
+
+                apply(*channel);
+            }
+            Led::Function(function) => {
+                let mask = 1 << *function as u8;
+
+                if on {
+                    FUNCTIONS_ON.fetch_or(mask, Ordering::Relaxed);
+                } else {
+                    FUNCTIONS_ON.fetch_and(!mask, Ordering::Relaxed);
+                }
+
+                refresh(led_map()[*function as usize]);
+            }
+        }
+    }
+
+    /// Switch the LED on.
+    pub fn set_high(&mut self) {
+        self.set(true);
+    }
+
+    /// Switch the LED off.
+    pub fn set_low(&mut self) {
+        self.set(false);
+    }
+}
+
+/// The name of a function, as used by the text interfaces.
+pub fn function_name(function: LedFunction) -> &'static str {
+    match function {
+        LedFunction::UsbSource => "usb",
+        LedFunction::SpdifSource => "spdif",
+        LedFunction::RpiSource => "rpi",
+        LedFunction::Mute => "mute",
+        LedFunction::Standby => "standby",
+        LedFunction::Clip => "clip",
+        LedFunction::Fault => "fault",
+        LedFunction::Heartbeat => "heartbeat",
+    }
+}
+
+/// Parse the name of a function.
+pub fn parse_function(name: &str) -> Option<LedFunction> {
+    LedFunction::ALL
+        .into_iter()
+        .find(|function| function_name(*function) == name)
+}
+
+/// Set up the PWM of the LEDs on MCU pins, with all LEDs off.
+pub fn init(mut pwm: SimplePwm<'static, peripherals::TIM8>) {
+    for channel in CHANNELS {
+        let mut channel = pwm.channel(channel);
+        channel.set_duty_cycle_fully_off();
+        channel.enable();
+    }
+
+    PWM.lock(|current| current.replace(Some(pwm)));
+}
+
+/// The LEDs of the functions, indexed by function.
+pub fn led_map() -> LedMap {
+    LED_MAP.lock(|map| map.get())
+}
+
+/// Set the LEDs of the functions. LEDs that are no longer mapped switch off.
+pub fn set_led_map(map: LedMap) {
+    let previous = LED_MAP.lock(|current| current.replace(map));
+
+    for output in previous.into_iter().chain(map) {
+        refresh(output);
+    }
+}
+
+/// Switch an LED on or off, by whether any function on it is on.
+fn refresh(output: LedOutput) {
+    let map = led_map();
+    let functions_on = FUNCTIONS_ON.load(Ordering::Relaxed);
+    let on = LedFunction::ALL
+        .into_iter()
+        .any(|function| map[function as usize] == output && functions_on & (1 << function as u8) != 0);
+
+    match output {
+        LedOutput::None => {}
+        LedOutput::Mcu(index) => {
+            let Some(&channel) = CHANNELS.get(index as usize) else {
+                return;
+            };
+
+            Led::Pwm(channel).set(on);
+        }
+        LedOutput::Expander(output) => gpio_expander::set_output(output, on),
+    }
+}
+
+/// Mark a boot stage as reached. Stages after the boot are ignored.
+pub fn boot_progress(stage: BootStage) {
+    if BOOT_PROGRESS.fetch_or(stage.mask(), Ordering::Relaxed) & stage.mask() == 0 {
+        log!(debug, "Boot stage reached: {:?}", stage);
+    }
+
+    if boot_display() {
+        apply_all();
+    }
+}
+
+/// Whether the LEDs on MCU pins show the boot progress.
+pub fn boot_display() -> bool {
+    BOOT_DISPLAY.load(Ordering::Relaxed)
+}
+
+/// The LEDs of the boot progress, as a mask of PWM channels: the reached stages, and the next stage while it blinks.
+fn boot_mask() -> u8 {
+    let progress = BOOT_PROGRESS.load(Ordering::Relaxed);
+    let next = BootStage::ALL.into_iter().find(|stage| progress & stage.mask() == 0);
+    let blink_on = Instant::now().as_millis() % BOOT_BLINK_PERIOD.as_millis() < BOOT_BLINK_PERIOD.as_millis() / 2;
+
+    match next {
+        Some(stage) if blink_on => progress | stage.mask(),
+        _ => progress,
+    }
+}
+
+/// The LEDs of the version code at a time since its start, as a mask of PWM channels, or `None` after its end.
+///
+/// The major, minor, and patch version blink in turn as a number of pulses, on the blue, green, and yellow LED. A
+/// zero shows as a single long pulse.
+fn version_mask(elapsed: Duration) -> Option<u8> {
+    let elapsed_ms = elapsed.as_millis();
+    let pulse_ms = VERSION_PULSE_TIME.as_millis();
+    let mut start_ms = 0;
+
+    for (index, number) in device_info().version.into_iter().enumerate() {
+        let (count, on_ms) = match number {
+            0 => (1, 3 * pulse_ms),
+            number => (number as u64, pulse_ms),
+        };
+        let length_ms = count * (on_ms + pulse_ms);
+
+        if elapsed_ms < start_ms + length_ms {
+            let on = (elapsed_ms - start_ms) % (on_ms + pulse_ms) < on_ms;
+            return Some(if on { 1 << index } else { 0 });
+        }
+
+        start_ms += length_ms + VERSION_PAUSE_TIME.as_millis();
+        if elapsed_ms < start_ms {
+            return Some(0);
+        }
+    }
+
+    None
+}
+
+/// The brightness in percent.
+pub fn brightness() -> u8 {
+    BRIGHTNESS.load(Ordering::Relaxed)
+}
+
+/// Set the brightness in percent.
+pub fn set_brightness(percent: u8) {
+    BRIGHTNESS.store(percent.min(100), Ordering::Relaxed);
+    apply_all();
+}
+
+/// The brightness in night mode in percent.
+pub fn night_brightness() -> u8 {
+    NIGHT_BRIGHTNESS.load(Ordering::Relaxed)
+}
+
+/// Set the brightness in night mode in percent.
+pub fn set_night_brightness(percent: u8) {
+    NIGHT_BRIGHTNESS.store(percent.min(100), Ordering::Relaxed);
+    apply_all();
+}
+
+/// The time without operation, after which night mode starts, or `None`, if disabled.
+pub fn night_delay() -> Option<Duration> {
+    match NIGHT_DELAY_MIN.load(Ordering::Relaxed) {
+        0 => None,
+        delay_min => Some(Duration::from_secs(delay_min as u64 * 60)),
+    }
+}
+
+/// Set the time without operation (whole minutes, up to [`MAX_NIGHT_DELAY`]), after which night mode starts, or
+/// disable it with `None`.
+pub fn set_night_delay(delay: Option<Duration>) {
+    let delay_min = delay.map_or(0, |delay| (delay.min(MAX_NIGHT_DELAY).as_secs() / 60).max(1));
+    NIGHT_DELAY_MIN.store(delay_min as u16, Ordering::Relaxed);
+}
+
+/// The night hours in UTC as start and end hour, or `None`, if disabled.
+pub fn night_hours() -> Option<(u8, u8)> {
+    match NIGHT_HOURS.load(Ordering::Relaxed) {
+        NO_NIGHT_HOURS => None,
+        hours => Some(((hours >> 8) as u8, hours as u8)),
+    }
+}
+
+/// Set the night hours in UTC as start and end hour (each below 24), or disable them with `None`. Night mode is
+/// active from the start of the start hour to the start of the end hour.
+pub fn set_night_hours(hours: Option<(u8, u8)>) {
+    let hours = hours.map_or(NO_NIGHT_HOURS, |(start, end)| {
+        ((start.min(23) as u16) << 8) | end.min(23) as u16
+    });
+    NIGHT_HOURS.store(hours, Ordering::Relaxed);
+}
+
+/// Whether night mode is active.
+pub fn night() -> bool {
+    NIGHT.load(Ordering::Relaxed)
+}
+
+/// Whether the LEDs on MCU pins show the output level, instead of the active source.
+pub fn meter_mode() -> bool {
+    METER_MODE.load(Ordering::Relaxed)
+}
+
+/// Switch the LEDs on MCU pins between showing the output level, and the active source.
+pub fn set_meter_mode(enabled: bool) {
+    METER_MODE.store(enabled, Ordering::Relaxed);
+    apply_all();
+}
+
+/// The current brightness in percent, by night mode. Also dims the addressable LEDs (see [`crate::rgb_led`]).
+pub fn current_brightness() -> u8 {
+    if night() {
+        night_brightness()
+    } else {
+        brightness()
+    }
+}
+
+/// Set the duty cycle of a PWM channel, by whether its LED is on in the current mode, and the current brightness.
+fn apply(channel: Channel) {
+    let mask = if boot_display() {
+        boot_mask()
+    } else if VERSION_DISPLAY.load(Ordering::Relaxed) {
+        VERSION_ON.load(Ordering::Relaxed)
+    } else if meter_mode() {
+        METER_ON.load(Ordering::Relaxed)
+    } else {
+        PWM_ON.load(Ordering::Relaxed)
+    };
+    let on = mask & (1 << channel.index()) != 0;
+    let brightness = if on { current_brightness() as u32 } else { 0 };
+
+    PWM.lock(|pwm| {
+        if let Some(pwm) = pwm.borrow_mut().as_mut() {
+            let mut channel = pwm.channel(channel);
+
+            // The perceived brightness follows the duty cycle roughly by its square root.
+            let duty = channel.max_duty_cycle() as u32 * brightness * brightness / (100 * 100);
+            channel.set_duty_cycle(duty as u16);
+        }
+    });
+}
+
+/// Set the duty cycles of all PWM channels.
+fn apply_all() {
+    for channel in CHANNELS {
+        apply(channel);
+    }
+}
+
+/// Whether the wall clock is within the night hours.
+fn in_night_hours() -> bool {
+    let (Some((start, end)), Some(time_s)) = (night_hours(), clock::unix_time()) else {
+        return false;
+    };
+
+    let hour = (time_s / 3600 % 24) as u8;
+
+    if start <= end {
+        (start..end).contains(&hour)
+    } else {
+        hour >= start || hour < end
+    }
+}
+
+/// The controls, whose changes count as operation of the device.
+fn controls() -> (u8, bool, bool, AudioSource) {
+    (
+        CONTROL.attenuation(),
+        CONTROL.muted(),
+        CONTROL.standby(),
+        CONTROL.source_selection(),
+    )
+}
+
+/// The bar of LEDs for a peak output level in dBFS, as a mask of PWM channels.
+fn meter_mask(level_db: f32) -> u8 {
+    METER_THRESHOLDS_DB
+        .iter()
+        .enumerate()
+        .filter(|(_, threshold_db)| level_db >= **threshold_db)
+        .fold(0, |mask, (index, _)| mask | (1 << index))
+}
+
+/// Indicates mute, standby, and the clip protection on their LEDs, switches night mode, shows the
+/// output level in meter mode, the boot progress, and the firmware version.
+#[embassy_executor::task]
+pub async fn led_task() {
+    let mut mute_led = Led::Function(LedFunction::Mute);
+    let mut standby_led = Led::Function(LedFunction::Standby);
+    let mut clip_led = Led::Function(LedFunction::Clip);
+
+    let mut ticker = Ticker::every(Duration::from_hz(UPDATE_RATE_HZ));
+    let mut last_controls = controls();
+    let mut last_operation = Instant::now();
+    let mut meter_level_db = f32::NEG_INFINITY;
+    let mut boot_complete: Option<Instant> = None;
+    let mut version_start: Option<Instant> = None;
+
+    loop {
+        if boot_display() {
+            let progress = BOOT_PROGRESS.load(Ordering::Relaxed);
+            let complete = BootStage::ALL.iter().all(|stage| progress & stage.mask() != 0);
+
+            if complete && boot_complete.is_none() {
+                boot_complete = Some(Instant::now());
+            }
+
+            let shown = boot_complete.is_some_and(|complete| complete.elapsed() >= BOOT_COMPLETE_TIME);
+            let timed_out = Instant::now().as_millis() >= BOOT_DISPLAY_TIMEOUT.as_millis();
+
+            if shown || timed_out {
+                if let Some(stage) = BootStage::ALL.into_iter().find(|stage| progress & stage.mask() == 0) {
+                    log!(info, "Boot progress incomplete, stage {:?} not reached", stage);
+                }
+
+                BOOT_DISPLAY.store(false, Ordering::Relaxed);
+
+                if !display::present() {
+                    version_start = Some(Instant::now());
+                    VERSION_DISPLAY.store(true, Ordering::Relaxed);
+                }
+            }
+
+            // Blinks the next stage, or returns to normal.
+            apply_all();
+        }
+
+        if let Some(start) = version_start {
+            match version_mask(start.elapsed()) {
+                Some(mask) => VERSION_ON.store(mask, Ordering::Relaxed),
+                None => {
+                    version_start = None;
+                    VERSION_DISPLAY.store(false, Ordering::Relaxed);
+                }
+            }
+
+            apply_all();
+        }
+
+        mute_led.set(CONTROL.muted());
+        standby_led.set(CONTROL.standby());
+        clip_led.set(CONTROL.volume_writer() == VolumeWriter::ClipProtection);
+
+        if controls() != last_controls {
+            last_controls = controls();
+            last_operation = Instant::now();
+        }
+
+        let inactive = night_delay().is_some_and(|delay| last_operation.elapsed() >= delay);
+        let night = inactive || (in_night_hours() && last_operation.elapsed() >= NIGHT_WAKE_TIME);
+
+        if NIGHT.swap(night, Ordering::Relaxed) != night {
+            log!(debug, "LED night mode: {}", if night { "on" } else { "off" });
+            apply_all();
+        }
+
+        // The loudest channel, of which the meter levels count in steps of 0.5 dB below full-scale.
+        let peak_level = (0..OUTPUT_CHANNEL_COUNT)
+            .map(|channel| CONTROL.meter_level(channel))
+            .min();
+        let level_db = match peak_level {
+            Some(level) if level != MUTED_ATTENUATION => -(level as f32) / 2.0,
+            _ => f32::NEG_INFINITY,
+        };
+        meter_level_db = level_db.max(meter_level_db - METER_DECAY_DB_PER_S / UPDATE_RATE_HZ as f32);
+
+        let mask = meter_mask(meter_level_db);
+        if METER_ON.swap(mask, Ordering::Relaxed) != mask && meter_mode() {
+            apply_all();
+        }
+
+        ticker.next().await;
+    }
+}
+
+/// Blinks the codes of all active faults in turn on the status LED, which is off without faults.
+#[embassy_executor::task]
+pub async fn status_led_task() {
+    let mut status_led = Led::Function(LedFunction::Fault);
+    status_led.set_low();
+
+    loop {
+        let active = faults::active();
+
+        if active == 0 {
+            Timer::after(PULSE_TIME).await;
+            continue;
+        }
+
+        for fault in Fault::ALL.into_iter().filter(|fault| active & fault.mask() != 0) {
+            for _ in 0..fault.blink_count() {
+                status_led.set_high();
+                Timer::after(PULSE_TIME).await;
+                status_led.set_low();
+                Timer::after(PULSE_TIME).await;
+            }
+
+            Timer::after(PAUSE_TIME).await;
+        }
+    }
+}
+
+/// Shows the health of the supervised tasks (see [`crate::watchdog`]) on the heartbeat LED:
+/// - All tasks alive: a short double pulse every [`HEARTBEAT_PERIOD`].
+/// - A task is late: slow blinking.
+/// - A task stalled, before the watchdog resets the microcontroller: fast blinking.
+///
+/// An LED that stays on or off shows that the executor hangs, e.g. in a busy loop of a task.
+#[embassy_executor::task]
+pub async fn heartbeat_task() {
+    let mut heartbeat_led = Led::Function(LedFunction::Heartbeat);
+    heartbeat_led.set_low();
+
+    loop {
+        let health = watchdog::health();
+
+        match health {
+            Health::Alive => {
+                for _ in 0..2 {
+                    heartbeat_led.set_high();
+                    Timer::after(HEARTBEAT_PULSE_TIME).await;
+                    heartbeat_led.set_low();
+                    Timer::after(2 * HEARTBEAT_PULSE_TIME).await;
+                }
+
+                Timer::after(HEARTBEAT_PERIOD - 6 * HEARTBEAT_PULSE_TIME).await;
+            }
+            Health::Late | Health::Stalled => {
+                let blink_time = if health == Health::Late {
+                    LATE_BLINK_TIME
+                } else {
+                    STALLED_BLINK_TIME
+                };
+
+                heartbeat_led.set_high();
+                Timer::after(blink_time).await;
+                heartbeat_led.set_low();
+                Timer::after(blink_time).await;
+            }
+        }
+    }
+}
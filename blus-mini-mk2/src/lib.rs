@@ -2,13 +2,79 @@
 #![no_std]
 #![warn(missing_docs)]
 
+pub mod amplifiers;
 pub mod audio_routing;
+pub mod auto_standby;
+pub mod backup;
+pub mod benchmark;
+pub mod bulk_transfer;
+pub mod button;
+pub mod cache;
+pub mod calibration;
+pub mod clip_protection;
+pub mod clock;
+pub mod config_json;
+pub mod config_slots;
+pub mod console;
+pub mod control;
+pub mod cpu_load;
+pub mod dc_protection;
+pub mod device_info;
+pub mod display;
+pub mod dma_buffers;
+pub mod dsp;
+pub mod encoder;
+pub mod event_log;
+pub mod factory_reset;
+pub mod faults;
+pub mod gpio_expander;
+pub mod hid_control;
+pub mod i2c_slave;
+pub mod ir_remote;
+pub mod latency;
+pub mod latency_probe;
+pub mod led;
+pub mod low_power;
+pub mod memory_usage;
+pub mod menu;
+pub mod mute_relay;
+pub mod notifications;
+pub mod output_power;
+pub mod panic_log;
+pub mod parameters;
+pub mod potentiometer;
+pub mod power_fail;
+pub mod presets;
+pub mod provisioning;
+pub mod registers;
+pub mod rgb_led;
+pub mod scpi;
+pub mod self_test;
+pub mod settings;
+pub mod settings_store;
+pub mod shell;
+pub mod shutdown;
+pub mod spi_slave;
+pub mod stack_usage;
+pub mod startup;
+pub mod startup_script;
+pub mod storage;
+pub mod supply;
+pub mod system;
+pub mod tcm;
+pub mod telemetry;
+pub mod thermal;
+pub mod thermal_log;
+pub mod touch;
+pub mod trigger;
 pub mod usb_audio;
+pub mod watchdog;
 
 use micromath::F32Ext;
 
 use audio::AudioSource;
-use embassy_sync::blocking_mutex::raw::{CriticalSectionRawMutex, ThreadModeRawMutex};
+use audio_pipeline::source::AudioInput;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
 use embassy_sync::signal::Signal;
 use embassy_usb::class::uac1;
 use heapless::Vec;
@@ -19,6 +85,9 @@ pub const INPUT_CHANNEL_COUNT: usize = 2;
 /// Two two-way speakers.
 pub const OUTPUT_CHANNEL_COUNT: usize = 4;
 
+// The settings, parameters, and calibration formats hold values for every output channel.
+static_assertions::const_assert_eq!(protocol::CHANNEL_COUNT, OUTPUT_CHANNEL_COUNT);
+
 /// Fixed sample rate.
 pub const SAMPLE_RATE_HZ: u32 = 48_000;
 
@@ -78,21 +147,18 @@ pub const MAX_SAMPLE_COUNT: usize = if USB_MAX_SAMPLE_COUNT > DEFAULT_SAMPLE_COU
     DEFAULT_SAMPLE_COUNT
 };
 
+/// The maximum number of frames (one sample per input channel) per block of samples.
+pub const MAX_FRAME_COUNT: usize = MAX_SAMPLE_COUNT / INPUT_CHANNEL_COUNT;
+
+/// The maximum number of output samples per processed block, for all output channels.
+pub const MAX_OUTPUT_SAMPLE_COUNT: usize = MAX_FRAME_COUNT * OUTPUT_CHANNEL_COUNT;
+
 // Thread synchronization
 /// Signal for a new feedback value, sent by the feedback interrupt handler.
 pub static FEEDBACK_SIGNAL: Signal<CriticalSectionRawMutex, u32> = Signal::new();
 
-/// Signal that is emitted when the playback SAI becomes active. Carries the new source within.
-pub static SAI_ACTIVE_SIGNAL: Signal<ThreadModeRawMutex, AudioSource> = Signal::new();
-
-/// Signal that is emitted when amplifier setup is complete.
-pub static AMP_SETUP_SIGNAL: Signal<ThreadModeRawMutex, bool> = Signal::new();
-
-/// Signal that is emitted when there is a new gain setting for the USB input.
-pub static USB_GAIN_SIGNAL: Signal<ThreadModeRawMutex, (f32, f32)> = Signal::new();
-
-/// Signal that is emitted when there is a new gain setting for the USB input.
-pub static POT_GAIN_SIGNAL: Signal<ThreadModeRawMutex, f32> = Signal::new();
+/// Signal for new samples in the USB ring, sent by the streaming task (see [`usb_audio`]).
+pub static USB_SAMPLES_SIGNAL: Signal<CriticalSectionRawMutex, ()> = Signal::new();
 
 // Type definitions
 /// A sample block, originating from different sources.
@@ -107,21 +173,103 @@ pub enum SampleBlock {
     Rpi(RpiSampleBlock),
 }
 
+impl AudioInput for SampleBlock {
+    fn source(&self) -> AudioSource {
+        match self {
+            SampleBlock::Usb(_) => AudioSource::Usb,
+            SampleBlock::Spdif(_) => AudioSource::Spdif,
+            SampleBlock::Rpi(_) => AudioSource::Rpi,
+        }
+    }
+
+    fn samples(&self) -> &[u32] {
+        match self {
+            SampleBlock::Usb(samples) => samples.as_slice(),
+            SampleBlock::Spdif(samples) => samples.as_slice(),
+            SampleBlock::Rpi(samples) => samples.as_slice(),
+        }
+    }
+}
+
 /// The number of sample blocks that exist.
 pub const SAMPLE_BLOCK_COUNT: usize = 5;
 
-/// The type of data that the USB input generates.
-pub type UsbSampleBlock = Vec<u32, USB_MAX_SAMPLE_COUNT>;
+/// The type of data that the audio routing reads from the USB ring, in blocks of 1 ms.
+pub type UsbSampleBlock = Vec<u32, DEFAULT_SAMPLE_COUNT>;
+
+/// The number of samples in the USB ring, which holds at least as many as [`SAMPLE_BLOCK_COUNT`] sample blocks.
+pub const USB_RING_SIZE: usize = (SAMPLE_BLOCK_COUNT * MAX_SAMPLE_COUNT).next_power_of_two();
+
+/// The ring buffer that hands samples from the USB streaming task to the audio routing.
+pub type UsbRing = audio_pipeline::ring::SampleRing<USB_RING_SIZE>;
 
 /// The type of data that the S/PDIF input generates.
 pub type SpdifSampleBlock = [u32; DEFAULT_SAMPLE_COUNT];
 
-/// The type of data that the Raspberry Pi input generates.
-pub type RpiSampleBlock = [u32; DEFAULT_SAMPLE_COUNT];
+/// The type of data that the Raspberry Pi input generates, of a block size by the latency mode (see [`latency`]).
+pub type RpiSampleBlock = Vec<u32, DEFAULT_SAMPLE_COUNT>;
 
 /// The type of biquad filter that is used for processing.
 pub type BiquadType = biquad::DirectForm2Transposed<f32>;
 
+/// The biquad cascades of all output channels, which run interleaved (see [`audio::biquad_bank`]).
+pub type BiquadBank = audio::biquad_bank::BiquadBank<OUTPUT_CHANNEL_COUNT, { audio::filter_config::MAX_STAGE_COUNT }>;
+
+/// The processing of all output channels (see [`audio::chain`]).
+pub type Chain = audio::chain::Chain<OUTPUT_CHANNEL_COUNT>;
+
+/// The configuration of the peripherals and clocks, shared by the firmware and the on-target tests.
+pub fn peripheral_config() -> embassy_stm32::Config {
+    use embassy_stm32::rcc::*;
+    use embassy_stm32::time::Hertz;
+
+    let mut peripheral_config = embassy_stm32::Config::default();
+
+    // Uses a 24.576 MHz external oscillator.
+    peripheral_config.rcc.hse = Some(Hse {
+        freq: Hertz(24_576_000),
+        mode: HseMode::Bypass,
+    });
+    peripheral_config.rcc.hsi = None;
+    peripheral_config.rcc.csi = true;
+    peripheral_config.rcc.hsi48 = None;
+    peripheral_config.rcc.pll1 = Some(Pll {
+        source: PllSource::HSE,
+        prediv: PllPreDiv::DIV4,
+        mul: PllMul::MUL80,
+        divp: Some(PllDiv::DIV2),  // 245.76 MHz
+        divq: Some(PllDiv::DIV20), // 24.576 MHz for SAI4
+        divr: Some(PllDiv::DIV2),  // 245.76 MHz
+    });
+    peripheral_config.rcc.pll3 = Some(Pll {
+        source: PllSource::HSE,
+        prediv: PllPreDiv::DIV8,
+        mul: PllMul::MUL125,
+        divp: Some(PllDiv::DIV2), // 192 MHz
+        divq: Some(PllDiv::DIV8), // 48 MHz for USB
+        divr: Some(PllDiv::DIV4), // 96 MHz for SPDIFRX (good for up to 136 kHz audio sample rate)
+    });
+    peripheral_config.rcc.sys = Sysclk::PLL1_P;
+    peripheral_config.rcc.ahb_pre = AHBPrescaler::DIV2;
+    peripheral_config.rcc.apb1_pre = APBPrescaler::DIV2;
+    peripheral_config.rcc.apb2_pre = APBPrescaler::DIV2;
+    peripheral_config.rcc.apb3_pre = APBPrescaler::DIV2;
+    peripheral_config.rcc.apb4_pre = APBPrescaler::DIV2;
+
+    // Voltage scaling
+    // 0 (<= 520 MHz)
+    // 1 (<= 400 MHz)
+    // 2 (<= 300 MHz)
+    // 3 (<= 170 MHz)
+    peripheral_config.rcc.voltage_scale = VoltageScale::Scale2;
+    peripheral_config.rcc.mux.usbsel = mux::Usbsel::PLL3_Q;
+    peripheral_config.rcc.mux.sai1sel = mux::Saisel::PLL1_Q;
+    peripheral_config.rcc.mux.adcsel = mux::Adcsel::PLL3_R;
+    peripheral_config.rcc.mux.spdifrxsel = mux::Spdifrxsel::PLL3_R;
+
+    peripheral_config
+}
+
 /// Convert a gain in dB to linear scale.
 pub fn db_to_linear(db: f32) -> f32 {
     10.0_f32.powf(db / 20.0)
@@ -0,0 +1,273 @@
+//! Low-power STOP mode of the microcontroller during standby.
+//!
+//! The device stops, while it is in standby with the amplifiers off, the USB host is absent or suspended (see
+//! [`crate::usb_audio::usb_active`]), and it has been awake for [`AWAKE_TIME`]. In STOP mode, all clocks but the
+//! low-speed oscillator halt, and the core sleeps until one of these wakes it:
+//! - USB resume (the OTG wakeup line).
+//! - Edges on the S/PDIF input, which an active transmitter produces.
+//! - The board buttons (e.g. a power button, see [`crate::shutdown`]), the IR receiver, and the trigger input, while
+//!   their tasks wait for edges.
+//!
+//! After waking up, the clocks are brought up again as configured at startup, and the tasks resume. Time does not
+//! advance while stopped, except for the independent watchdog: the RTC wakes the core every [`WATCHDOG_INTERVAL`] to
+//! feed it (see [`crate::watchdog::hold_off`]), and stops again right away. The buttons on the GPIO expander and the
+//! UART console do not wake the device.
+//!
+//! Low power is not stored with the settings; a startup script can disable it (shell `low-power`, see
+//! [`crate::startup_script`]).
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+use embassy_stm32::pac::rcc::regs::Cr;
+use embassy_stm32::pac::rcc::vals::{Rtcsel, Sw};
+use embassy_stm32::pac::rtc::vals::Wucksel;
+use embassy_stm32::{interrupt, pac};
+use embassy_time::{Duration, Instant, Ticker};
+
+use crate::amplifiers::{self, AmplifierState};
+use crate::control::CONTROL;
+use crate::power_fail;
+use crate::usb_audio;
+use crate::watchdog;
+use crate::*;
+
+/// The time that the device stays awake after startup, or waking up, before it stops again.
+pub const AWAKE_TIME: Duration = Duration::from_secs(5);
+
+/// The interval of RTC wakeups, which feed the watchdog while stopped. Below [`watchdog::WATCHDOG_TIMEOUT`].
+pub const WATCHDOG_INTERVAL: Duration = Duration::from_secs(2);
+
+/// The interval between checks whether the device may stop.
+const CHECK_INTERVAL: Duration = Duration::from_millis(500);
+
+/// The frequency of the RTC wakeup timer: the LSI (32 kHz), divided by 16.
+const WAKEUP_TIMER_HZ: u32 = 2_000;
+
+/// The EXTI line of the RTC wakeup timer.
+const RTC_WAKEUP_EXTI_LINE: usize = 19;
+
+/// The EXTI line of the S/PDIF input (PD7).
+const SPDIF_EXTI_LINE: usize = 7;
+
+/// The EXTI port selection of GPIO port D.
+const SPDIF_EXTI_PORT: u8 = 3;
+
+/// The EXTI line of the USB OTG HS wakeup.
+const USB_WAKEUP_EXTI_LINE: usize = 43;
+
+/// Whether the device stops in standby.
+static ENABLED: AtomicBool = AtomicBool::new(true);
+
+/// Whether the last wakeup was by the RTC, for feeding the watchdog.
+static WATCHDOG_WAKEUP: AtomicBool = AtomicBool::new(false);
+
+/// The number of times that the device stopped since startup.
+static STOP_COUNT: AtomicU32 = AtomicU32::new(0);
+
+/// Whether the device stops in standby.
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Enable or disable stopping in standby.
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+/// The number of times that the device stopped since startup.
+pub fn stop_count() -> u32 {
+    STOP_COUNT.load(Ordering::Relaxed)
+}
+
+/// Resources that are required for the STOP mode.
+#[allow(missing_docs)]
+pub struct LowPowerResources {
+    pub scb: cortex_m::peripheral::SCB,
+}
+
+/// The oscillators, PLLs, and system clock in use before stopping, which STOP mode turns off.
+///
+/// The configuration of the PLLs, prescalers, and flash wait states is retained while stopped, so turning them on
+/// again and selecting the system clock restores the clocks as configured at startup.
+struct Clocks {
+    cr: Cr,
+    sys: Sw,
+}
+
+impl Clocks {
+    fn save() -> Self {
+        Self {
+            cr: pac::RCC.cr().read(),
+            sys: pac::RCC.cfgr().read().sw(),
+        }
+    }
+
+    /// Restore the clocks, while running from the HSI after waking up.
+    fn restore(&self) {
+        let rcc = pac::RCC;
+
+        rcc.cr().modify(|w| {
+            w.set_hseon(self.cr.hseon());
+            w.set_csion(self.cr.csion());
+            w.set_hsi48on(self.cr.hsi48on());
+        });
+        while self.cr.hseon() && !rcc.cr().read().hserdy() {}
+        while self.cr.csion() && !rcc.cr().read().csirdy() {}
+        while self.cr.hsi48on() && !rcc.cr().read().hsi48rdy() {}
+
+        // The regulator returns to the voltage scale of before, which the clocks of the PLLs require.
+        while !pac::PWR.d3cr().read().vosrdy() {}
+
+        for pll in 0..3 {
+            if self.cr.pllon(pll) {
+                rcc.cr().modify(|w| w.set_pllon(pll, true));
+                while !rcc.cr().read().pllrdy(pll) {}
+            }
+        }
+
+        rcc.cfgr().modify(|w| w.set_sw(self.sys));
+        while rcc.cfgr().read().sws() != self.sys {}
+
+        rcc.cr().modify(|w| w.set_hsion(self.cr.hsion()));
+    }
+}
+
+/// Whether the device may stop.
+fn may_stop() -> bool {
+    enabled()
+        && CONTROL.standby()
+        && !usb_audio::usb_active()
+        && amplifiers::state() == AmplifierState::Off
+        && !power_fail::power_failing()
+}
+
+/// Set up the RTC wakeup timer on the LSI, and its interrupt.
+fn init_wakeup_timer() {
+    pac::RCC.csr().modify(|w| w.set_lsion(true));
+    while !pac::RCC.csr().read().lsirdy() {}
+
+    pac::PWR.cr1().modify(|w| w.set_dbp(true));
+    pac::RCC.bdcr().modify(|w| {
+        w.set_rtcsel(Rtcsel::LSI);
+        w.set_rtcen(true);
+    });
+
+    let rtc = pac::RTC;
+    rtc.wpr().write(|w| w.set_key(0xCA));
+    rtc.wpr().write(|w| w.set_key(0x53));
+
+    rtc.cr().modify(|w| w.set_wute(false));
+    while !rtc.isr().read().wutwf() {}
+
+    let wakeup_ticks = WATCHDOG_INTERVAL.as_millis() as u32 * WAKEUP_TIMER_HZ / 1000;
+    rtc.wutr().write(|w| w.set_wut(wakeup_ticks as u16 - 1));
+    rtc.cr().modify(|w| {
+        w.set_wucksel(Wucksel::DIV16);
+        w.set_wutie(true);
+    });
+
+    rtc.wpr().write(|w| w.set_key(0xFF));
+
+    let exti = pac::EXTI;
+    exti.rtsr(0).modify(|w| w.set_line(RTC_WAKEUP_EXTI_LINE, true));
+    exti.imr(0).modify(|w| w.set_line(RTC_WAKEUP_EXTI_LINE, true));
+
+    unsafe {
+        cortex_m::peripheral::NVIC::unmask(interrupt::RTC_WKUP);
+    }
+}
+
+/// Start or stop the RTC wakeup timer.
+fn set_wakeup_timer(enabled: bool) {
+    let rtc = pac::RTC;
+    rtc.wpr().write(|w| w.set_key(0xCA));
+    rtc.wpr().write(|w| w.set_key(0x53));
+    rtc.cr().modify(|w| w.set_wute(enabled));
+    rtc.wpr().write(|w| w.set_key(0xFF));
+}
+
+#[interrupt]
+fn RTC_WKUP() {
+    pac::RTC.isr().modify(|w| w.set_wutf(false));
+    pac::EXTI.pr(0).write(|w| w.set_line(RTC_WAKEUP_EXTI_LINE, true));
+
+    WATCHDOG_WAKEUP.store(true, Ordering::Relaxed);
+}
+
+/// Enable or disable the wakeups by the S/PDIF input and USB resume, which are only needed while stopped.
+fn set_wakeup_lines(enabled: bool) {
+    let exti = pac::EXTI;
+
+    if enabled {
+        pac::SYSCFG
+            .exticr(SPDIF_EXTI_LINE / 4)
+            .modify(|w| w.set_exti(SPDIF_EXTI_LINE % 4, SPDIF_EXTI_PORT));
+        exti.rtsr(0).modify(|w| w.set_line(SPDIF_EXTI_LINE, true));
+        exti.ftsr(0).modify(|w| w.set_line(SPDIF_EXTI_LINE, true));
+    }
+
+    // Pending S/PDIF edges are handled (and masked again) by the EXTI interrupt of the HAL.
+    exti.imr(0).modify(|w| w.set_line(SPDIF_EXTI_LINE, enabled));
+    exti.imr(1).modify(|w| w.set_line(USB_WAKEUP_EXTI_LINE - 32, enabled));
+}
+
+/// Stop until a wakeup other than for feeding the watchdog.
+fn stop(scb: &mut cortex_m::peripheral::SCB) {
+    // STOP instead of STANDBY for all domains, with the low-power regulator.
+    pac::PWR.cpucr().modify(|w| {
+        w.set_pdds_d1(false);
+        w.set_pdds_d2(false);
+        w.set_pdds_d3(false);
+        w.set_run_d3(false);
+    });
+    pac::PWR.cr1().modify(|w| w.set_lpds(true));
+
+    set_wakeup_lines(true);
+    set_wakeup_timer(true);
+    scb.set_sleepdeep();
+
+    loop {
+        watchdog::hold_off();
+        WATCHDOG_WAKEUP.store(false, Ordering::Relaxed);
+
+        cortex_m::asm::dsb();
+        cortex_m::asm::wfi();
+
+        if !WATCHDOG_WAKEUP.load(Ordering::Relaxed) {
+            break;
+        }
+    }
+
+    scb.clear_sleepdeep();
+    set_wakeup_timer(false);
+    set_wakeup_lines(false);
+}
+
+/// Stops the microcontroller in standby, and restores the clocks after waking up.
+#[embassy_executor::task]
+pub async fn low_power_task(resources: LowPowerResources) {
+    let mut scb = resources.scb;
+    let mut ticker = Ticker::every(CHECK_INTERVAL);
+    let mut awake_since = Instant::now();
+
+    init_wakeup_timer();
+
+    loop {
+        ticker.next().await;
+
+        if !may_stop() || awake_since.elapsed() < AWAKE_TIME {
+            continue;
+        }
+
+        log!(debug, "Entering STOP mode");
+        STOP_COUNT.fetch_add(1, Ordering::Relaxed);
+
+        // Blocks the executor, until the core wakes up. The clocks run from the HSI afterwards.
+        let clocks = Clocks::save();
+        stop(&mut scb);
+        clocks.restore();
+
+        watchdog::hold_off();
+        awake_since = Instant::now();
+        log!(debug, "Left STOP mode");
+    }
+}
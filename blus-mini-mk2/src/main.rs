@@ -5,60 +5,58 @@
 #![allow(clippy::excessive_precision)]
 
 use core::cell::{Cell, RefCell};
+use core::sync::atomic::{AtomicBool, Ordering};
 
-use audio::{self, AudioFilter, AudioSource};
+use audio::{self, AudioSource};
+use blus_mini_mk2::audio_routing::AudioPipeline;
+use blus_mini_mk2::startup::Step;
 use blus_mini_mk2::*;
 use defmt::{debug, info, unwrap};
-use embassy_embedded_hal::shared_bus::blocking::i2c::I2cDevice;
+use defmt_rtt as _;
 use embassy_executor::Spawner;
+#[cfg(not(feature = "encoder"))]
 use embassy_stm32::adc::{self, AdcChannel};
-use embassy_stm32::gpio::{Input, Level, Output, Pull, Speed};
-use embassy_stm32::mode::Async;
+use embassy_stm32::gpio::{Input, Level, Output, OutputType, Pull, Speed};
 use embassy_stm32::spdifrx::{self, Spdifrx};
 use embassy_stm32::time::Hertz;
-use embassy_stm32::{bind_interrupts, i2c, interrupt, peripherals, timer, usb};
-use embassy_sync::blocking_mutex::raw::{CriticalSectionRawMutex, NoopRawMutex};
-use embassy_sync::blocking_mutex::{Mutex, NoopMutex};
+use embassy_stm32::timer::low_level::CountingMode;
+use embassy_stm32::timer::simple_pwm::{PwmPin, SimplePwm};
+use embassy_stm32::{bind_interrupts, i2c, interrupt, peripherals, timer, usart, usb};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
 use embassy_sync::channel;
-use embassy_time::{Duration, Ticker, Timer};
+use embassy_time::Timer;
+#[cfg(not(feature = "encoder"))]
+use embassy_time::{Duration, Ticker};
+use embassy_usb::class::cdc_acm::{self, CdcAcmClass};
+use embassy_usb::class::hid::{self, HidReaderWriter};
 use embassy_usb::class::uac1;
 use embassy_usb::class::uac1::speaker::{self, Speaker};
-use grounded::uninit::GroundedArrayCell;
+use embassy_usb::msos;
+#[cfg(not(feature = "encoder"))]
 use micromath::F32Ext;
+use protocol::event_log::{EventKind, ResetCause};
+use protocol::provisioning::Provisioning;
 use static_cell::StaticCell;
-use {defmt_rtt as _, panic_probe as _};
 
 bind_interrupts!(struct Irqs {
     OTG_HS => usb::InterruptHandler<peripherals::USB_OTG_HS>;
     I2C1_EV => i2c::EventInterruptHandler<peripherals::I2C1>;
     I2C1_ER => i2c::ErrorInterruptHandler<peripherals::I2C1>;
     SPDIF_RX => spdifrx::GlobalInterruptHandler<peripherals::SPDIFRX1>;
+    USART1 => usart::BufferedInterruptHandler<peripherals::USART1>;
 });
 
 static TIMER: Mutex<CriticalSectionRawMutex, RefCell<Option<timer::low_level::Timer<peripherals::TIM2>>>> =
     Mutex::new(RefCell::new(None));
-static I2C_BUS: StaticCell<NoopMutex<RefCell<i2c::I2c<'static, Async>>>> = StaticCell::new();
-
-// Accessible by most system masters (Zone D2)
-#[link_section = ".sram1"]
-static ADC1_MEASUREMENT_BUFFER: GroundedArrayCell<u16, 1> = GroundedArrayCell::uninit();
-
-// Reserve twice the SPDIF sample count, since the DMA will transfer at
-// half-full interrupt (so, at SPDIF_SAMPLE_COUNT * 2 / 2).
-#[link_section = ".sram1"]
-static SPDIFRX_BUFFER: GroundedArrayCell<u32, { DEFAULT_SAMPLE_COUNT * 2 }> = GroundedArrayCell::uninit();
-
-#[allow(unused)]
-struct AmplifierResources {
-    i2c: i2c::I2c<'static, Async>,
-    pin_nsd: Output<'static>,
-    pin_irqz: Input<'static>,
-}
+static I2C_BUS: StaticCell<gpio_expander::I2cBus> = StaticCell::new();
 
+#[cfg(not(feature = "encoder"))]
 #[allow(unused)]
 struct AdcResources<T: adc::Instance> {
     adc: adc::Adc<'static, T>,
     pin: adc::AnyAdcChannel<T>,
+    second_pin: adc::AnyAdcChannel<T>,
     dma: peripherals::DMA1_CH0,
 }
 
@@ -69,197 +67,98 @@ struct SpdifResources {
     dma: peripherals::DMA1_CH1,
 }
 
-/// Get audio filters for a given sample rate.
+/// Get the default signal processing configuration.
 ///
 /// The filters depend on the connected speakers.
-pub fn get_filters(sample_rate_hz: u32) -> [AudioFilter<'static>; OUTPUT_CHANNEL_COUNT] {
-    use biquad::*;
-
-    type B = BiquadType;
-    type C = Coefficients<f32>;
+pub fn default_dsp_config() -> dsp::DspConfig {
+    use audio::filter_config::{FilterConfig, StageConfig, StageKind};
+    use biquad::Q_BUTTERWORTH_F32;
+    use heapless::Vec;
 
     // Crossover frequency
-    let f_co = 1800.hz();
-
-    let fs = sample_rate_hz.hz();
-
-    static BIQUADS_A: StaticCell<[B; 9]> = StaticCell::new();
-    static BIQUADS_C: StaticCell<[B; 9]> = StaticCell::new();
-    static BIQUADS_B: StaticCell<[B; 6]> = StaticCell::new();
-    static BIQUADS_D: StaticCell<[B; 6]> = StaticCell::new();
-
-    let biquads_a = BIQUADS_A.init([
-        B::new(C::from_params(Type::AllPass, fs, f_co, 0.6).unwrap()),
-        B::new(C {
-            a1: -1.9925941047116,
-            a2: 0.992621419175639,
-            b0: 1.00200843380849,
-            b1: -1.99256829254308,
-            b2: 0.990638797535668,
-        }),
-        B::new(C::from_params(Type::PeakingEQ(-2.5), fs, 660.hz(), 2.5).unwrap()),
-        B::new(C::from_params(Type::PeakingEQ(1.0), fs, 880.hz(), 2.0).unwrap()),
-        B::new(C::from_params(Type::HighShelf(-8.0), fs, 1200.hz(), 0.35).unwrap()),
-        B::new(C::from_params(Type::PeakingEQ(-2.5), fs, 1300.hz(), 2.0).unwrap()),
-        B::new(C::from_params(Type::PeakingEQ(-3.0), fs, 3450.hz(), 2.0).unwrap()),
-        B::new(C::from_params(Type::LowPass, fs, f_co, Q_BUTTERWORTH_F32).unwrap()),
-        B::new(C::from_params(Type::LowPass, fs, f_co, Q_BUTTERWORTH_F32).unwrap()),
-    ]);
-
-    let biquads_c = BIQUADS_C.init([
-        B::new(C::from_params(Type::AllPass, fs, f_co, 0.6).unwrap()),
-        B::new(C {
-            a1: -1.9925941047116,
-            a2: 0.992621419175639,
-            b0: 1.00200843380849,
-            b1: -1.99256829254308,
-            b2: 0.990638797535668,
-        }),
-        B::new(C::from_params(Type::PeakingEQ(-2.5), fs, 660.hz(), 2.5).unwrap()),
-        B::new(C::from_params(Type::PeakingEQ(1.0), fs, 880.hz(), 2.0).unwrap()),
-        B::new(C::from_params(Type::HighShelf(-8.0), fs, 1200.hz(), 0.35).unwrap()),
-        B::new(C::from_params(Type::PeakingEQ(-2.5), fs, 1300.hz(), 2.0).unwrap()),
-        B::new(C::from_params(Type::PeakingEQ(-3.0), fs, 3450.hz(), 2.0).unwrap()),
-        B::new(C::from_params(Type::LowPass, fs, f_co, Q_BUTTERWORTH_F32).unwrap()),
-        B::new(C::from_params(Type::LowPass, fs, f_co, Q_BUTTERWORTH_F32).unwrap()),
-    ]);
-
-    let biquads_b = BIQUADS_B.init([
-        B::new(C::from_params(Type::PeakingEQ(-9.0), fs, 1700.hz(), 0.3).unwrap()),
-        B::new(C::from_params(Type::PeakingEQ(1.0), fs, 7700.hz(), 2.0).unwrap()),
-        B::new(C::from_params(Type::PeakingEQ(-1.0), fs, 12000.hz(), 2.0).unwrap()),
-        B::new(C::from_params(Type::PeakingEQ(6.0), fs, 18000.hz(), 0.6).unwrap()),
-        B::new(C::from_params(Type::HighPass, fs, f_co, Q_BUTTERWORTH_F32).unwrap()),
-        B::new(C::from_params(Type::HighPass, fs, f_co, Q_BUTTERWORTH_F32).unwrap()),
-    ]);
-
-    let biquads_d = BIQUADS_D.init([
-        B::new(C::from_params(Type::PeakingEQ(-9.0), fs, 1700.hz(), 0.3).unwrap()),
-        B::new(C::from_params(Type::PeakingEQ(1.0), fs, 7700.hz(), 2.0).unwrap()),
-        B::new(C::from_params(Type::PeakingEQ(-1.0), fs, 12000.hz(), 2.0).unwrap()),
-        B::new(C::from_params(Type::PeakingEQ(6.0), fs, 18000.hz(), 0.6).unwrap()),
-        B::new(C::from_params(Type::HighPass, fs, f_co, Q_BUTTERWORTH_F32).unwrap()),
-        B::new(C::from_params(Type::HighPass, fs, f_co, Q_BUTTERWORTH_F32).unwrap()),
-    ]);
-
-    // Negative gain inverts a channel.
-    let gain_a = -db_to_linear(-10.0);
-    let gain_b = db_to_linear(-11.5);
-    let gain_c = -db_to_linear(-10.0);
-    let gain_d = db_to_linear(-11.5);
-
-    let delay_a: usize = 0;
-    let delay_b: usize = 6;
-    let delay_c: usize = 0;
-    let delay_d: usize = 6;
-
-    let f_a = AudioFilter::new(gain_a, delay_a, biquads_a);
-    let f_b = AudioFilter::new(gain_b, delay_b, biquads_b);
-    let f_c = AudioFilter::new(gain_c, delay_c, biquads_c);
-    let f_d = AudioFilter::new(gain_d, delay_d, biquads_d);
-
-    [f_a, f_b, f_c, f_d]
-}
+    let f_co = 1800.0;
+
+    fn parametric(kind: StageKind, frequency_hz: f32, q: f32, gain_db: f32) -> StageConfig {
+        StageConfig::Parametric {
+            kind,
+            frequency_hz,
+            q,
+            gain_db,
+        }
+    }
 
-#[embassy_executor::task]
-async fn amplifier_task(amplifier_resources: AmplifierResources) {
-    use tas2780::tas2780::*;
-
-    let mut pin_nsd = amplifier_resources.pin_nsd;
-
-    let i2c_bus = NoopMutex::new(RefCell::new(amplifier_resources.i2c));
-    let i2c_bus = I2C_BUS.init(i2c_bus);
-
-    let mut ic2_device_a = I2cDevice::new(i2c_bus);
-    let mut tas2780_a = Tas2780::new(&mut ic2_device_a, 0x39);
-
-    let mut ic2_device_b = I2cDevice::new(i2c_bus);
-    let mut tas2780_b = Tas2780::new(&mut ic2_device_b, 0x3a);
-
-    let mut ic2_device_c = I2cDevice::new(i2c_bus);
-    let mut tas2780_c = Tas2780::new(&mut ic2_device_c, 0x3d);
-
-    let mut ic2_device_d = I2cDevice::new(i2c_bus);
-    let mut tas2780_d = Tas2780::new(&mut ic2_device_d, 0x3e);
-
-    debug!("Reset amplifiers.");
-    pin_nsd.set_low();
-    Timer::after_millis(10).await;
-    pin_nsd.set_high();
-
-    // Wait for reset
-    Timer::after_millis(10).await;
-
-    tas2780_a
-        .init(Config {
-            tdm_slot: 0,
-            ..Default::default()
-        })
-        .await;
-    tas2780_b
-        .init(Config {
-            tdm_slot: 1,
-            ..Default::default()
-        })
-        .await;
-    tas2780_c
-        .init(Config {
-            tdm_slot: 2,
-            ..Default::default()
-        })
-        .await;
-    tas2780_d
-        .init(Config {
-            tdm_slot: 3,
-            ..Default::default()
-        })
-        .await;
+    let woofer = FilterConfig {
+        gain_db: -10.0,
+        inverted: true,
+        delay: 0,
+        stages: Vec::from_slice(&[
+            parametric(StageKind::AllPass, f_co, 0.6, 0.0),
+            StageConfig::Coefficients {
+                a1: -1.9925941047116,
+                a2: 0.992621419175639,
+                b0: 1.00200843380849,
+                b1: -1.99256829254308,
+                b2: 0.990638797535668,
+            },
+            parametric(StageKind::PeakingEq, 660.0, 2.5, -2.5),
+            parametric(StageKind::PeakingEq, 880.0, 2.0, 1.0),
+            parametric(StageKind::HighShelf, 1200.0, 0.35, -8.0),
+            parametric(StageKind::PeakingEq, 1300.0, 2.0, -2.5),
+            parametric(StageKind::PeakingEq, 3450.0, 2.0, -3.0),
+            parametric(StageKind::LowPass, f_co, Q_BUTTERWORTH_F32, 0.0),
+            parametric(StageKind::LowPass, f_co, Q_BUTTERWORTH_F32, 0.0),
+        ])
+        .unwrap(),
+        ..FilterConfig::new()
+    };
 
-    loop {
-        let source = SAI_ACTIVE_SIGNAL.wait().await;
-
-        if !matches!(source, AudioSource::None) {
-            debug!("Initialize TAS2780");
-
-            for amplifier in [&mut tas2780_a, &mut tas2780_b, &mut tas2780_c, &mut tas2780_d] {
-                let mut config = amplifier.config();
-
-                match source {
-                    AudioSource::Spdif => {
-                        config.tdm_word_length = TdmWordLength::Word16Bit;
-                        config.tdm_time_slot_length = TdmTimeSlotLength::Slot16Bit;
-                    }
-                    _ => {
-                        config.tdm_word_length = TdmWordLength::Word32Bit;
-                        config.tdm_time_slot_length = TdmTimeSlotLength::Slot32Bit;
-                    }
-                };
-
-                amplifier.init(config).await;
-                amplifier.enable();
-            }
+    let tweeter = FilterConfig {
+        gain_db: -11.5,
+        inverted: false,
+        delay: 6,
+        stages: Vec::from_slice(&[
+            parametric(StageKind::PeakingEq, 1700.0, 0.3, -9.0),
+            parametric(StageKind::PeakingEq, 7700.0, 2.0, 1.0),
+            parametric(StageKind::PeakingEq, 12000.0, 2.0, -1.0),
+            parametric(StageKind::PeakingEq, 18000.0, 0.6, 6.0),
+            parametric(StageKind::HighPass, f_co, Q_BUTTERWORTH_F32, 0.0),
+            parametric(StageKind::HighPass, f_co, Q_BUTTERWORTH_F32, 0.0),
+        ])
+        .unwrap(),
+        ..FilterConfig::new()
+    };
 
-            AMP_SETUP_SIGNAL.signal(true);
-        } else {
-            AMP_SETUP_SIGNAL.signal(false);
-        }
+    dsp::DspConfig::from([woofer.clone(), tweeter.clone(), woofer, tweeter])
+}
+
+/// Get the processing chain of all output channels for a given configuration and sample rate.
+///
+/// The chain lives in DTCM, and can be reconfigured at runtime (see [`audio::chain`]).
+pub fn get_chain(config: &dsp::DspConfig, sample_rate_hz: u32) -> &'static mut Chain {
+    #[link_section = ".dtcm"]
+    static CHAIN: StaticCell<Chain> = StaticCell::new();
+    let chain = CHAIN.init(Chain::new());
+
+    for (channel, filter_config) in config.iter().enumerate() {
+        unwrap!(chain.configure(channel, filter_config, sample_rate_hz));
     }
+
+    chain
 }
 
+#[cfg(not(feature = "encoder"))]
 #[embassy_executor::task]
 async fn potentiometer_task(mut adc_resources: AdcResources<peripherals::ADC1>) {
     use biquad::*;
 
     const POT_SAMPLE_RATE_HZ: u64 = 25;
     const POT_CUT_OFF_HZ: f32 = 5.0;
+    // The position change (out of 1) that counts as moving the potentiometer.
+    const POT_HYSTERESIS: f32 = 0.01;
 
     let mut ticker = Ticker::every(Duration::from_hz(POT_SAMPLE_RATE_HZ));
 
-    let buffer: &mut [u16] = unsafe {
-        ADC1_MEASUREMENT_BUFFER.initialize_all_copied(0);
-        let (ptr, len) = ADC1_MEASUREMENT_BUFFER.get_ptr_len();
-        core::slice::from_raw_parts_mut(ptr, len)
-    };
+    let buffer = dma_buffers::adc1_measurement();
 
     let coefficients = Coefficients::<f32>::from_params(
         Type::LowPass,
@@ -270,37 +169,62 @@ async fn potentiometer_task(mut adc_resources: AdcResources<peripherals::ADC1>)
     .unwrap();
 
     let mut filter = DirectForm2Transposed::<f32>::new(coefficients);
+    let mut second_filter = DirectForm2Transposed::<f32>::new(coefficients);
+    let mut knob = potentiometer::Knob::default();
+    let mut second_knob = potentiometer::Knob::default();
+    // The position that was last applied to the master volume.
+    let mut applied_position = None;
 
     loop {
         ticker.next().await;
 
-        adc_resources
-            .adc
-            .read(
-                &mut adc_resources.dma,
-                [(&mut adc_resources.pin, adc::SampleTime::CYCLES810_5)].into_iter(),
-                buffer,
-            )
-            .await;
+        let mut sums = [0u32; 2];
+        for _ in 0..potentiometer::OVERSAMPLING_COUNT {
+            adc_resources
+                .adc
+                .read(
+                    &mut adc_resources.dma,
+                    [
+                        (&mut adc_resources.pin, adc::SampleTime::CYCLES810_5),
+                        (&mut adc_resources.second_pin, adc::SampleTime::CYCLES810_5),
+                    ]
+                    .into_iter(),
+                    buffer,
+                )
+                .await;
+
+            for (sum, value) in sums.iter_mut().zip(buffer.iter()) {
+                *sum += *value as u32;
+            }
+        }
 
-        let gain = filter.run((buffer[0] as f32) / 65535f32);
+        let [mean, second_mean] = sums.map(|sum| sum as f32 / potentiometer::OVERSAMPLING_COUNT as f32);
 
-        // Clamp, and make gain exponential
-        let exp_gain = gain.clamp(0.0, 1.0).powf(2.0);
-        POT_GAIN_SIGNAL.signal(exp_gain);
+        // The second potentiometer has a linear taper, without calibration.
+        let second_position = second_filter.run(second_mean / 65535f32).clamp(0.0, 1.0);
+        potentiometer::update_second(second_knob.update(potentiometer::apply_dead_band(second_position)));
+
+        let position = filter.run(mean / 65535f32).clamp(0.0, 1.0);
+        let position = calibration::calibration().apply_taper(position);
+        let position = knob.update(potentiometer::apply_dead_band(position));
+
+        let attenuation = potentiometer::attenuation(position);
+        control::CONTROL.set_input_attenuation(attenuation);
+
+        // Soft takeover: only a moved potentiometer overrides the master volume, which other writers may have changed.
+        if applied_position.is_none_or(|applied: f32| (position - applied).abs() >= POT_HYSTERESIS) {
+            applied_position = Some(position);
+            control::CONTROL.set_attenuation_by(control::VolumeWriter::VolumeInput, attenuation);
+        }
     }
 }
 
 #[embassy_executor::task]
 async fn spdif_task(
     mut resources: SpdifResources,
-    audio_channel: channel::Sender<'static, NoopRawMutex, SampleBlock, SAMPLE_BLOCK_COUNT>,
+    audio_channel: channel::Sender<'static, CriticalSectionRawMutex, SampleBlock, SAMPLE_BLOCK_COUNT>,
 ) {
-    let buffer: &mut [u32] = unsafe {
-        SPDIFRX_BUFFER.initialize_all_copied(0);
-        let (ptr, len) = SPDIFRX_BUFFER.get_ptr_len();
-        core::slice::from_raw_parts_mut(ptr, len)
-    };
+    let buffer = dma_buffers::spdifrx();
 
     fn new_spdif<'d>(resources: &'d mut SpdifResources, buffer: &'d mut [u32]) -> Spdifrx<'d, peripherals::SPDIFRX1> {
         Spdifrx::new(
@@ -325,11 +249,13 @@ async fn spdif_task(
         match result {
             Ok(_) => {
                 if audio_channel.try_send(SampleBlock::Spdif(data)).is_err() {
-                    debug!("SPDIF: Failed to send to channel")
+                    audio_routing::STATS.count_overrun();
+                    log!(debug, "SPDIF: Failed to send to channel")
                 }
             }
             Err(spdifrx::Error::RingbufferError(_)) => {
-                debug!("SPDIF ringbuffer error");
+                log!(debug, "SPDIF ringbuffer error");
+                event_log::record(EventKind::SourceError, AudioSource::Spdif as u8);
                 drop(spdif);
                 spdif = new_spdif(&mut resources, buffer);
                 spdif.start();
@@ -341,88 +267,131 @@ async fn spdif_task(
 
 #[embassy_executor::main]
 async fn main(spawner: Spawner) {
+    // SAFETY: Runs first, before any code or data in the TCMs is used.
+    unsafe { tcm::init() };
+
+    // Paint the free stack, for its high-water mark.
+    #[cfg(feature = "stack_usage")]
+    stack_usage::paint();
+
+    // Leaves the firmware, if a reboot into the bootloader was requested.
+    system::enter_bootloader_if_requested();
+
     info!("Hi.");
 
-    let mut peripheral_config = embassy_stm32::Config::default();
-    {
-        // Uses a 24.576 MHz external oscillator.
-        use embassy_stm32::rcc::*;
-        peripheral_config.rcc.hse = Some(Hse {
-            freq: Hertz(24_576_000),
-            mode: HseMode::Bypass,
-        });
-        peripheral_config.rcc.hsi = None;
-        peripheral_config.rcc.csi = true;
-        peripheral_config.rcc.hsi48 = None;
-        peripheral_config.rcc.pll1 = Some(Pll {
-            source: PllSource::HSE,
-            prediv: PllPreDiv::DIV4,
-            mul: PllMul::MUL80,
-            divp: Some(PllDiv::DIV2),  // 245.76 MHz
-            divq: Some(PllDiv::DIV20), // 24.576 MHz for SAI4
-            divr: Some(PllDiv::DIV2),  // 245.76 MHz
-        });
-        peripheral_config.rcc.pll3 = Some(Pll {
-            source: PllSource::HSE,
-            prediv: PllPreDiv::DIV8,
-            mul: PllMul::MUL125,
-            divp: Some(PllDiv::DIV2), // 192 MHz
-            divq: Some(PllDiv::DIV8), // 48 MHz for USB
-            divr: Some(PllDiv::DIV4), // 96 MHz for SPDIFRX (good for up to 136 kHz audio sample rate)
-        });
-        peripheral_config.rcc.sys = Sysclk::PLL1_P;
-        peripheral_config.rcc.ahb_pre = AHBPrescaler::DIV2;
-        peripheral_config.rcc.apb1_pre = APBPrescaler::DIV2;
-        peripheral_config.rcc.apb2_pre = APBPrescaler::DIV2;
-        peripheral_config.rcc.apb3_pre = APBPrescaler::DIV2;
-        peripheral_config.rcc.apb4_pre = APBPrescaler::DIV2;
-
-        // Voltage scaling
-        // 0 (<= 520 MHz)
-        // 1 (<= 400 MHz)
-        // 2 (<= 300 MHz)
-        // 3 (<= 170 MHz)
-        peripheral_config.rcc.voltage_scale = VoltageScale::Scale2;
-        peripheral_config.rcc.mux.usbsel = mux::Usbsel::PLL3_Q;
-        peripheral_config.rcc.mux.sai1sel = mux::Saisel::PLL1_Q;
-        peripheral_config.rcc.mux.adcsel = mux::Adcsel::PLL3_R;
-        peripheral_config.rcc.mux.spdifrxsel = mux::Spdifrxsel::PLL3_R;
-    }
-    let p = embassy_stm32::init(peripheral_config);
+    let peripheral_config = peripheral_config();
+    let mut p = embassy_stm32::init(peripheral_config);
 
     let mut core_peri = cortex_m::Peripherals::take().unwrap();
 
-    // Enable instruction cache.
-    core_peri.SCB.enable_icache();
+    // Enable the caches, with the DMA buffers in non-cacheable memory.
+    cache::init(&mut core_peri.MPU, &mut core_peri.SCB, &mut core_peri.CPUID);
+
+    // Count cycles for measuring the CPU load.
+    cpu_load::init(&mut core_peri.DCB, &mut core_peri.DWT);
+
+    // What the static data takes of every memory region, and what is left.
+    memory_usage::report();
 
-    let mut led_blue = Output::new(p.PC6, Level::Low, Speed::Low);
-    let mut led_green = Output::new(p.PC7, Level::Low, Speed::Low);
-    let mut led_yellow = Output::new(p.PC8, Level::Low, Speed::Low);
-    let mut led_red = Output::new(p.PC9, Level::Low, Speed::Low);
+    // Benchmark the signal processing kernels, before any task runs.
+    #[cfg(feature = "benchmark")]
+    benchmark::run();
+
+    let mut led_blue = Output::new(&mut p.PC6, Level::Low, Speed::Low);
+    let mut led_green = Output::new(&mut p.PC7, Level::Low, Speed::Low);
+    let mut led_yellow = Output::new(&mut p.PC8, Level::Low, Speed::Low);
+    let mut led_red = Output::new(&mut p.PC9, Level::Low, Speed::Low);
 
     for led in [&mut led_blue, &mut led_green, &mut led_yellow, &mut led_red] {
         led.set_low();
     }
 
+    // Holding both board buttons at power-up requests a factory reset.
+    let factory_reset_held = {
+        let button_0 = Input::new(&mut p.PD5, Pull::Up);
+        let button_1 = Input::new(&mut p.PD6, Pull::Up);
+
+        // Let the pull-ups settle.
+        Timer::after_millis(1).await;
+        button_0.is_low() && button_1.is_low()
+    };
+
+    // Persistent storage in flash, erased by a factory reset.
+    storage::init(p.FLASH);
+
+    // Early warning of a power loss, for saving the state.
+    power_fail::init();
+
+    // The startup is the first entry of the event log for this boot.
+    let reset_cause = system::take_reset_cause();
+    event_log::record(EventKind::Reset, reset_cause as u8);
+
+    let boot_loop = system::detect_boot_loop(reset_cause);
+    if let Some(count) = boot_loop {
+        log!(
+            warn,
+            "Boot loop after {} resets, using the default signal processing",
+            count
+        );
+        event_log::record(EventKind::BootLoop, count.min(u8::MAX as u16) as u8);
+    }
+
+    if let Some(tasks) = watchdog::take_stalled_tasks() {
+        if reset_cause == ResetCause::Watchdog {
+            event_log::record(EventKind::TaskStall, tasks);
+        }
+    }
+
+    if panic_log::take() {
+        if let Some(message) = panic_log::last_panic() {
+            log!(warn, "Panic before the reset: {}", message.as_str());
+        }
+        event_log::record(EventKind::Panic, 0);
+    }
+
+    // Provisioning data, which the USB device descriptor holds.
+    static PROVISIONING: StaticCell<Provisioning> = StaticCell::new();
+    let provisioning: Option<&'static Provisioning> = match provisioning::restore() {
+        Ok(()) => provisioning::provisioning().map(|provisioning| &*PROVISIONING.init(provisioning)),
+        Err(error) => {
+            info!("Provisioning not restored: {}", error);
+            None
+        }
+    };
+
     debug!("USB packet size is {} byte", USB_MAX_PACKET_SIZE);
-    static CONFIG_DESCRIPTOR: StaticCell<[u8; 256]> = StaticCell::new();
-    let config_descriptor = CONFIG_DESCRIPTOR.init([0; 256]);
+    static CONFIG_DESCRIPTOR: StaticCell<[u8; 512]> = StaticCell::new();
+    let config_descriptor = CONFIG_DESCRIPTOR.init([0; 512]);
 
     static BOS_DESCRIPTOR: StaticCell<[u8; 32]> = StaticCell::new();
     let bos_descriptor = BOS_DESCRIPTOR.init([0; 32]);
 
+    static MSOS_DESCRIPTOR: StaticCell<[u8; 256]> = StaticCell::new();
+    let msos_descriptor = MSOS_DESCRIPTOR.init([0; 256]);
+
     const CONTROL_BUF_SIZE: usize = 64;
     static CONTROL_BUF: StaticCell<[u8; CONTROL_BUF_SIZE]> = StaticCell::new();
     let control_buf = CONTROL_BUF.init([0; CONTROL_BUF_SIZE]);
 
     const FEEDBACK_BUF_SIZE: usize = 4;
-    static EP_OUT_BUFFER: StaticCell<[u8; FEEDBACK_BUF_SIZE + CONTROL_BUF_SIZE + USB_MAX_PACKET_SIZE]> =
-        StaticCell::new();
-    let ep_out_buffer = EP_OUT_BUFFER.init([0u8; FEEDBACK_BUF_SIZE + CONTROL_BUF_SIZE + USB_MAX_PACKET_SIZE]);
+    const EP_OUT_BUFFER_SIZE: usize = FEEDBACK_BUF_SIZE
+        + CONTROL_BUF_SIZE
+        + USB_MAX_PACKET_SIZE
+        + console::CONSOLE_MAX_PACKET_SIZE
+        + protocol::hid::REPORT_SIZE
+        + bulk_transfer::BULK_ENDPOINT_BUFFER_SIZE;
+    static EP_OUT_BUFFER: StaticCell<[u8; EP_OUT_BUFFER_SIZE]> = StaticCell::new();
+    let ep_out_buffer = EP_OUT_BUFFER.init([0u8; EP_OUT_BUFFER_SIZE]);
 
     static STATE: StaticCell<speaker::State> = StaticCell::new();
     let state = STATE.init(speaker::State::new());
 
+    static CONSOLE_STATE: StaticCell<cdc_acm::State> = StaticCell::new();
+    let console_state = CONSOLE_STATE.init(cdc_acm::State::new());
+
+    static HID_STATE: StaticCell<hid::State> = StaticCell::new();
+    let hid_state = HID_STATE.init(hid::State::new());
+
     // Create the driver, from the HAL.
     let mut usb_config = usb::Config::default();
 
@@ -477,6 +446,7 @@ async fn main(spawner: Spawner) {
     let mut config = embassy_usb::Config::new(0x1209, 0xaf03);
     config.manufacturer = Some("elagil");
     config.product = Some("blus-mini mk2");
+    config.serial_number = provisioning.map(|provisioning| provisioning.serial_number());
     config.self_powered = true;
     config.max_power = 0;
 
@@ -492,10 +462,17 @@ async fn main(spawner: Spawner) {
         config,
         config_descriptor,
         bos_descriptor,
-        &mut [], // no msos descriptors
+        msos_descriptor,
         control_buf,
     );
 
+    // The USB state decides, whether the device may stop in standby.
+    static USB_STATE_HANDLER: StaticCell<usb_audio::UsbStateHandler> = StaticCell::new();
+    builder.handler(USB_STATE_HANDLER.init(usb_audio::UsbStateHandler::new()));
+
+    // Microsoft OS descriptors let Windows bind the bulk transfer interface to WinUSB.
+    builder.msos_descriptor(msos::windows_version::WIN8_1, 0);
+
     // Create the UAC1 Speaker class components
     let (stream, feedback, control_changed) = Speaker::new(
         &mut builder,
@@ -507,6 +484,25 @@ async fn main(spawner: Spawner) {
         FEEDBACK_REFRESH_PERIOD,
     );
 
+    // Create the CDC-ACM console
+    let console_class = CdcAcmClass::new(&mut builder, console_state, console::CONSOLE_MAX_PACKET_SIZE as u16);
+    let (console_sender, console_receiver) = console_class.split();
+
+    // Create the HID parameter interface for host tools
+    let hid_control = HidReaderWriter::new(
+        &mut builder,
+        hid_state,
+        hid::Config {
+            report_descriptor: protocol::hid::REPORT_DESCRIPTOR,
+            request_handler: None,
+            poll_ms: 1,
+            max_packet_size: protocol::hid::REPORT_SIZE as u16,
+        },
+    );
+
+    // Create the bulk transfer interface for large data blocks
+    let bulk_transfer = bulk_transfer::BulkTransfer::new(&mut builder);
+
     // Build and run the USB device
     let usb_device = builder.build();
 
@@ -525,64 +521,273 @@ async fn main(spawner: Spawner) {
         dma_b: p.BDMA_CH1,
     };
 
-    let amplifier_resources = AmplifierResources {
-        i2c: i2c::I2c::new(
-            p.I2C1,
-            p.PB6,
-            p.PB7,
-            Irqs,
-            p.DMA2_CH0,
-            p.DMA2_CH1,
-            Hertz(1_000_000),
-            Default::default(),
-        ),
+    // The amplifiers and the GPIO expander share the bus.
+    let i2c_bus = I2C_BUS.init(Mutex::new(RefCell::new(i2c::I2c::new(
+        p.I2C1,
+        p.PB6,
+        p.PB7,
+        Irqs,
+        p.DMA2_CH0,
+        p.DMA2_CH1,
+        Hertz(1_000_000),
+        Default::default(),
+    ))));
+
+    let amplifier_resources = amplifiers::AmplifierResources {
+        i2c_bus,
         pin_nsd: Output::new(p.PC13, Level::Low, Speed::Low),
         pin_irqz: Input::new(p.PC14, Pull::None),
     };
 
+    #[cfg(not(feature = "encoder"))]
     let adc_resources = AdcResources {
         adc: adc::Adc::new(p.ADC1),
         pin: p.PA6.degrade_adc(),
+        second_pin: p.PC5.degrade_adc(),
         dma: p.DMA1_CH0,
     };
 
+    #[cfg(feature = "encoder")]
+    let encoder_resources = encoder::EncoderResources {
+        timer: p.TIM3,
+        pin_a: p.PB4,
+        pin_b: p.PA7,
+        button: p.PD4,
+        button_exti: p.EXTI4,
+    };
+
     let spdif_resources = SpdifResources {
         spdifrx: p.SPDIFRX1,
         in_pin: p.PD7,
         dma: p.DMA1_CH1,
     };
 
-    // Establish a channel for transferring received audio samples.
-    static AUDIO_CHANNEL: StaticCell<channel::Channel<NoopRawMutex, SampleBlock, SAMPLE_BLOCK_COUNT>> =
+    let i2c_slave_resources = i2c_slave::I2cSlaveResources {
+        i2c: p.I2C4,
+        scl: p.PB8,
+        sda: p.PB9,
+    };
+
+    let spi_slave_resources = spi_slave::SpiSlaveResources {
+        spi: p.SPI3,
+        sck: p.PC10,
+        miso: p.PC11,
+        mosi: p.PC12,
+        nss: p.PA15,
+        nss_exti: p.EXTI15,
+        irq: p.PD1,
+    };
+
+    let ir_remote_resources = ir_remote::IrRemoteResources {
+        pin: p.PE3,
+        exti: p.EXTI3,
+    };
+
+    let button_resources = button::ButtonResources {
+        pin_0: p.PD5,
+        exti_0: p.EXTI5,
+        pin_1: p.PD6,
+        exti_1: p.EXTI6,
+    };
+
+    let touch_resources = touch::TouchResources {
+        volume_up: p.PE7,
+        volume_down: p.PE8,
+        mute: p.PE9,
+        source: p.PE10,
+    };
+
+    let supply_resources = supply::SupplyResources {
+        adc: p.ADC2,
+        pin: p.PC4,
+    };
+
+    let thermal_resources = thermal::ThermalResources { adc: p.ADC3 };
+
+    let watchdog_resources = watchdog::WatchdogResources { iwdg: p.IWDG1 };
+
+    let trigger_resources = trigger::TriggerResources {
+        input: p.PD8,
+        input_exti: p.EXTI8,
+        output: p.PD9,
+    };
+
+    let mute_relay_resources = mute_relay::MuteRelayResources { output: p.PD10 };
+
+    #[cfg(feature = "latency_probe")]
+    latency_probe::init(latency_probe::LatencyProbeResources { output: p.PD11 });
+
+    let rgb_led_resources = rgb_led::RgbLedResources {
+        spi: p.SPI2,
+        mosi: p.PB15,
+        dma: p.DMA1_CH2,
+    };
+
+    let low_power_resources = low_power::LowPowerResources { scb: core_peri.SCB };
+
+    static UART_TX_BUFFER: StaticCell<[u8; 256]> = StaticCell::new();
+    static UART_RX_BUFFER: StaticCell<[u8; 64]> = StaticCell::new();
+    let uart = unwrap!(usart::BufferedUart::new(
+        p.USART1,
+        Irqs,
+        p.PA10,
+        p.PA9,
+        UART_TX_BUFFER.init([0; 256]),
+        UART_RX_BUFFER.init([0; 64]),
+        usart::Config::default(),
+    ));
+
+    // Establish a channel for transferring received S/PDIF sample blocks to the audio executor.
+    static AUDIO_CHANNEL: StaticCell<channel::Channel<CriticalSectionRawMutex, SampleBlock, SAMPLE_BLOCK_COUNT>> =
         StaticCell::new();
     let audio_channel = AUDIO_CHANNEL.init(channel::Channel::new());
 
+    // Establish a ring buffer for transferring the samples from USB.
+    static USB_RING: StaticCell<UsbRing> = StaticCell::new();
+    let (usb_producer, usb_consumer) = USB_RING.init(UsbRing::new()).split();
+
     setup_sof_timer(timer::low_level::Timer::new(p.TIM2));
 
-    // Launch audio routing.
-    unwrap!(spawner.spawn(audio_routing::audio_routing_task(
-        get_filters(SAMPLE_RATE_HZ),
-        sai4_resources,
-        audio_channel.receiver(),
-        led_blue,
-        led_red,
-        led_yellow,
-    )));
+    if system::take_factory_reset_request() || factory_reset_held {
+        factory_reset::perform(&mut [&mut led_blue, &mut led_green, &mut led_yellow, &mut led_red]).await;
+    }
+
+    // From now on, the LEDs on MCU pins are dimmed by PWM.
+    drop([led_blue, led_green, led_yellow, led_red]);
+    led::init(SimplePwm::new(
+        p.TIM8,
+        Some(PwmPin::new_ch1(p.PC6, OutputType::PushPull)),
+        Some(PwmPin::new_ch2(p.PC7, OutputType::PushPull)),
+        Some(PwmPin::new_ch3(p.PC8, OutputType::PushPull)),
+        Some(PwmPin::new_ch4(p.PC9, OutputType::PushPull)),
+        Hertz(led::PWM_FREQUENCY_HZ),
+        CountingMode::EdgeAlignedUp,
+    ));
+    startup::bring_up(Step::Clocks).await;
+    led::boot_progress(led::BootStage::Clocks);
+
+    // Calibration data, which a factory reset keeps.
+    match calibration::restore() {
+        Ok(()) => info!("Restored calibration"),
+        Err(error) => info!("Calibration not restored: {}", error),
+    }
+
+    // Default signal processing, and the stored settings and the default preset over the defaults.
+    unwrap!(dsp::set_dsp_config(default_dsp_config()));
+
+    match settings_store::restore() {
+        Ok(()) => info!("Restored settings"),
+        Err(error) => info!("Settings not restored: {}", error),
+    }
+
+    // In a boot loop, the stored signal processing is suspected, and replaced by the defaults.
+    if boot_loop.is_some() {
+        unwrap!(dsp::set_dsp_config(default_dsp_config()));
+    } else {
+        match presets::load_default() {
+            Ok(Some(index)) => info!("Loaded default preset {}", index),
+            Ok(None) => (),
+            Err(error) => info!("Default preset not loaded: {}", error),
+        }
+    }
+
+    // The event log records failed startup steps.
+    unwrap!(spawner.spawn(event_log::event_log_task()));
+
+    // Amplifier power sequencing and protection, with the amplifiers shut down until a source is active.
+    unwrap!(spawner.spawn(amplifiers::amplifier_task(amplifier_resources)));
+    startup::bring_up(Step::Amplifiers).await;
 
     // Launch USB audio tasks.
     unwrap!(spawner.spawn(usb_audio::control_task(control_changed)));
-    unwrap!(spawner.spawn(usb_audio::streaming_task(stream, audio_channel.sender())));
+    unwrap!(spawner.spawn(usb_audio::streaming_task(stream, usb_producer)));
     unwrap!(spawner.spawn(usb_audio::feedback_task(feedback)));
     unwrap!(spawner.spawn(usb_audio::usb_task(usb_device)));
+    startup::bring_up(Step::Usb).await;
+
+    // Launch audio routing on its own executor, which preempts all other tasks.
+    unwrap!(AudioPipeline::new()
+        .input(audio_routing::Input::Usb(usb_consumer))
+        .input(audio_routing::Input::Spdif(audio_channel.receiver()))
+        .input(audio_routing::Input::Rpi)
+        .dsp(get_chain(&dsp::dsp_config(), SAMPLE_RATE_HZ))
+        .output(sai4_resources)
+        .spawn(audio_routing::start_executor()));
+    startup::bring_up(Step::Pipeline).await;
+
+    // Command shell and log output on the USB console, command shell on the UART, and telemetry on either.
+    unwrap!(spawner.spawn(console::console_task(console_sender, console_receiver)));
+    unwrap!(spawner.spawn(console::uart_console_task(uart)));
+    unwrap!(spawner.spawn(telemetry::telemetry_task()));
+    unwrap!(spawner.spawn(cpu_load::cpu_load_task()));
+
+    // Parameter access and change notifications for host tools.
+    unwrap!(spawner.spawn(hid_control::hid_control_task(hid_control)));
+    unwrap!(spawner.spawn(bulk_transfer::bulk_transfer_task(bulk_transfer)));
+    unwrap!(spawner.spawn(notifications::notification_task()));
+
+    // Automatic saving of changed settings, and saving the state before a power loss.
+    unwrap!(spawner.spawn(settings_store::settings_task()));
+    unwrap!(spawner.spawn(power_fail::power_fail_task()));
+
+    // Reboots on request, also into the bootloader, and the detection of boot loops.
+    unwrap!(spawner.spawn(system::reboot_task()));
+    unwrap!(spawner.spawn(system::stable_uptime_task()));
+
+    // Supervision of the audio, USB, and control tasks, which resets the firmware when one of them stalls.
+    unwrap!(spawner.spawn(watchdog::watchdog_task(watchdog_resources)));
 
     // Volume control.
+    #[cfg(not(feature = "encoder"))]
     unwrap!(spawner.spawn(potentiometer_task(adc_resources)));
+    #[cfg(feature = "encoder")]
+    unwrap!(spawner.spawn(encoder::encoder_task(encoder_resources)));
+
+    // Remote control.
+    unwrap!(spawner.spawn(ir_remote::ir_remote_task(ir_remote_resources)));
+
+    // Board buttons, and more buttons and LEDs on the GPIO expander, touch pads, the ordered shutdown by a power
+    // button, the addressable LEDs, and the status display.
+    unwrap!(spawner.spawn(gpio_expander::gpio_expander_task(i2c_bus)));
+    unwrap!(spawner.spawn(button::button_task(button_resources)));
+    unwrap!(spawner.spawn(touch::touch_task(touch_resources)));
+    unwrap!(spawner.spawn(shutdown::shutdown_task()));
+    unwrap!(spawner.spawn(led::led_task()));
+    unwrap!(spawner.spawn(led::status_led_task()));
+    unwrap!(spawner.spawn(led::heartbeat_task()));
+    unwrap!(spawner.spawn(rgb_led::rgb_led_task(rgb_led_resources)));
+    unwrap!(spawner.spawn(display::display_task(i2c_bus)));
+
+    // Power control by upstream equipment, and of downstream equipment.
+    unwrap!(spawner.spawn(trigger::trigger_task(trigger_resources)));
 
-    // Amplifier setup and control.
-    unwrap!(spawner.spawn(amplifier_task(amplifier_resources)));
+    // External mute relay, which opens the analog path for every mute reason.
+    unwrap!(spawner.spawn(mute_relay::mute_relay_task(mute_relay_resources)));
+
+    // Supply monitoring, which locks out the outputs on undervoltage.
+    unwrap!(spawner.spawn(supply::supply_task(supply_resources)));
+
+    // Temperature of the microcontroller, the temperature history, and the thermal log.
+    unwrap!(spawner.spawn(thermal::thermal_task(thermal_resources)));
+    unwrap!(spawner.spawn(thermal_log::thermal_log_task()));
+
+    // Standby after inactivity, and the STOP mode in standby.
+    unwrap!(spawner.spawn(auto_standby::auto_standby_task()));
+    unwrap!(spawner.spawn(low_power::low_power_task(low_power_resources)));
+
+    // Volume reduction on sustained clipping.
+    unwrap!(spawner.spawn(clip_protection::clip_protection_task()));
 
     // S/PDIF data reception.
     unwrap!(spawner.spawn(spdif_task(spdif_resources, audio_channel.sender())));
+
+    // Control register map access via I2C and SPI.
+    i2c_slave::init(i2c_slave_resources);
+    unwrap!(spawner.spawn(spi_slave::spi_slave_task(spi_slave_resources)));
+    unwrap!(spawner.spawn(registers::register_write_task()));
+
+    // Commands that bring headless installations into a known state.
+    unwrap!(spawner.spawn(startup_script::startup_script_task()));
 }
 
 fn setup_sof_timer(mut tim2: timer::low_level::Timer<'static, peripherals::TIM2>) {
@@ -640,3 +845,26 @@ fn TIM2() {
         timer.sr().modify(|r| r.set_tif(false));
     });
 }
+
+/// Panics leave their message for after the reset (see [`panic_log`]), are logged, and reset the microcontroller, like
+/// faults.
+#[panic_handler]
+fn panic(info: &core::panic::PanicInfo) -> ! {
+    static PANICKED: AtomicBool = AtomicBool::new(false);
+
+    cortex_m::interrupt::disable();
+
+    // A panic while handling a panic only resets.
+    if !PANICKED.swap(true, Ordering::Relaxed) {
+        panic_log::store(info);
+        defmt::error!("{}", defmt::Display2Format(info));
+    }
+
+    cortex_m::peripheral::SCB::sys_reset()
+}
+
+/// Faults, including panics, reset the microcontroller, such that boot loops are detected (see [`system`]).
+#[cortex_m_rt::exception]
+unsafe fn HardFault(_frame: &cortex_m_rt::ExceptionFrame) -> ! {
+    cortex_m::peripheral::SCB::sys_reset()
+}
@@ -0,0 +1,160 @@
+//! The report of the static memory usage per memory region, at startup.
+//!
+//! The usage is taken from the bounds of the sections in `memory.x`, and reported to defmt and the console, before
+//! any task runs. It shows what still fits, e.g. FIR taps in DTCM (see [`crate::tcm`]), or bigger DMA buffers in
+//! SRAM4 (see [`crate::dma_buffers`]).
+//!
+//! - The DTCM is the RAM of the core: it holds `.data`, `.bss` (with the state of all tasks), `.uninit`, and the hot
+//!   data of the signal processing (`.dtcm`). The stack takes what remains, downwards from its end.
+//! - The other regions only hold their own sections, from their start.
+//!
+//! A region with less than [`MIN_FREE_PERCENT`] free is reported as a warning.
+use core::ptr::addr_of;
+
+use crate::*;
+
+/// The share of a region that should stay free, below which its usage is a warning.
+pub const MIN_FREE_PERCENT: usize = 10;
+
+extern "C" {
+    /// The start of `.data`, the first section in RAM (see `cortex-m-rt`).
+    static __sdata: u32;
+    /// The end of all sections in RAM, where the heap would start (see `cortex-m-rt`).
+    static __sheap: u32;
+    /// The start of the stack, at the end of RAM.
+    static _stack_start: u32;
+    static __sdtcm: u32;
+    static __edtcm: u32;
+    static __sitcm: u32;
+    static __eitcm: u32;
+    static __saxisram: u32;
+    static __eaxisram: u32;
+    static __ssram1: u32;
+    static __esram1: u32;
+    static __ssram2: u32;
+    static __esram2: u32;
+    static __ssram4: u32;
+    static __esram4: u32;
+    static __sbsram: u32;
+    static __ebsram: u32;
+    // The sizes of the regions, as the addresses of the symbols.
+    static __itcm_size: u32;
+    static __dtcm_size: u32;
+    static __axisram_size: u32;
+    static __sram1_size: u32;
+    static __sram2_size: u32;
+    static __sram4_size: u32;
+    static __bsram_size: u32;
+}
+
+/// The usage of a memory region in byte.
+#[derive(Clone, Copy, PartialEq, Debug, defmt::Format)]
+pub struct Usage {
+    /// The name of the region, as in `memory.x`.
+    pub name: &'static str,
+    /// The bytes used by sections.
+    pub used: usize,
+    /// The size of the region.
+    pub size: usize,
+}
+
+impl Usage {
+    /// The bytes that are left.
+    pub fn free(&self) -> usize {
+        self.size.saturating_sub(self.used)
+    }
+
+    /// Whether less than [`MIN_FREE_PERCENT`] of the region is left.
+    pub fn is_tight(&self) -> bool {
+        self.free() * 100 < self.size * MIN_FREE_PERCENT
+    }
+}
+
+/// The distance between two linker symbols.
+fn span(start: *const u32, end: *const u32) -> usize {
+    (end as usize).saturating_sub(start as usize)
+}
+
+/// The value of a linker symbol that is defined as a number, not an address.
+fn value(symbol: *const u32) -> usize {
+    symbol as usize
+}
+
+/// The usage of all memory regions. The DTCM counts the static data, without the stack.
+pub fn usage() -> [Usage; 7] {
+    [
+        Usage {
+            name: "DTCM",
+            used: span(addr_of!(__sdata), addr_of!(__sheap)),
+            size: value(addr_of!(__dtcm_size)),
+        },
+        Usage {
+            name: "ITCM",
+            used: span(addr_of!(__sitcm), addr_of!(__eitcm)),
+            size: value(addr_of!(__itcm_size)),
+        },
+        Usage {
+            name: "AXISRAM",
+            used: span(addr_of!(__saxisram), addr_of!(__eaxisram)),
+            size: value(addr_of!(__axisram_size)),
+        },
+        Usage {
+            name: "SRAM1",
+            used: span(addr_of!(__ssram1), addr_of!(__esram1)),
+            size: value(addr_of!(__sram1_size)),
+        },
+        Usage {
+            name: "SRAM2",
+            used: span(addr_of!(__ssram2), addr_of!(__esram2)),
+            size: value(addr_of!(__sram2_size)),
+        },
+        Usage {
+            name: "SRAM4",
+            used: span(addr_of!(__ssram4), addr_of!(__esram4)),
+            size: value(addr_of!(__sram4_size)),
+        },
+        Usage {
+            name: "BSRAM",
+            used: span(addr_of!(__sbsram), addr_of!(__ebsram)),
+            size: value(addr_of!(__bsram_size)),
+        },
+    ]
+}
+
+/// Report the usage of all memory regions.
+pub fn report() {
+    for region in usage() {
+        let percent = region.used * 100 / region.size.max(1);
+
+        if region.is_tight() {
+            log!(
+                warn,
+                "Memory {}: {} of {} byte used ({}%), only {} byte free",
+                region.name,
+                region.used,
+                region.size,
+                percent,
+                region.free()
+            );
+        } else {
+            log!(
+                info,
+                "Memory {}: {} of {} byte used ({}%), {} byte free",
+                region.name,
+                region.used,
+                region.size,
+                percent,
+                region.free()
+            );
+        }
+    }
+
+    let tcm_data = span(addr_of!(__sdtcm), addr_of!(__edtcm));
+    let stack = span(addr_of!(__sheap), addr_of!(_stack_start));
+    log!(
+        info,
+        "Memory DTCM: {} byte of signal processing data, {} byte left for the stack",
+        tcm_data,
+        stack
+    );
+}
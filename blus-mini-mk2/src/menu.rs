@@ -0,0 +1,386 @@
+//! A menu on the status display (see [`crate::display`]), navigated with the rotary encoder (feature `encoder`).
+//!
+//! A long press of the encoder button opens the menu, and another one closes it, as does [`TIMEOUT`] without input.
+//! The menu lists its [`Item`]s: rotating selects an item, and pressing edits it. While an item is edited, rotating
+//! changes its value, and pressing returns to the list. The info pages are scrolled by rotating instead.
+//!
+//! Changes of the volume, the source selection, and the channel trims (the gains of the output channels) are written
+//! through the parameter store (see [`crate::parameters`]), like those of any other control frontend. Presets are
+//! loaded on the press that ends editing (see [`crate::presets`]).
+use core::cell::Cell;
+use core::fmt::Write as _;
+
+use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, Instant};
+use heapless::String;
+use protocol::parameter::{Parameter, Value};
+
+use crate::control::{self, CONTROL, MUTED_ATTENUATION};
+use crate::device_info::device_info;
+use crate::parameters::PARAMETERS;
+use crate::presets::{self, MAX_PRESET_COUNT};
+use crate::thermal;
+use crate::*;
+
+/// The time without input, after which the menu closes.
+pub const TIMEOUT: Duration = Duration::from_secs(15);
+
+/// The number of lines of text, including the title.
+pub const LINE_COUNT: usize = 8;
+
+/// The number of characters per line.
+pub const LINE_LENGTH: usize = 21;
+
+/// The highest channel trim in either direction in dB.
+pub const MAX_TRIM_DB: f32 = 20.0;
+
+/// The volume change per detent, in steps of 0.5 dB.
+const VOLUME_STEP: i32 = 2;
+
+/// The trim change per detent in dB.
+const TRIM_STEP_DB: f32 = 0.5;
+
+/// The number of info pages.
+const INFO_PAGE_COUNT: u8 = 2;
+
+/// A line of text.
+pub type Line = String<LINE_LENGTH>;
+
+/// An entry of the menu.
+#[derive(Clone, Copy, PartialEq, Debug, defmt::Format)]
+pub enum Item {
+    /// The master volume.
+    Volume,
+    /// The source selection.
+    Source,
+    /// The preset, which is loaded after editing.
+    Preset,
+    /// The gain of an output channel.
+    Trim(u8),
+    /// Pages of device information.
+    Info,
+    /// Closes the menu.
+    Exit,
+}
+
+/// All items, in order.
+const ITEMS: [Item; 4 + OUTPUT_CHANNEL_COUNT] = {
+    let mut items = [Item::Exit; 4 + OUTPUT_CHANNEL_COUNT];
+    items[0] = Item::Volume;
+    items[1] = Item::Source;
+    items[2] = Item::Preset;
+
+    let mut channel = 0;
+    while channel < OUTPUT_CHANNEL_COUNT {
+        items[3 + channel] = Item::Trim(channel as u8);
+        channel += 1;
+    }
+
+    items[3 + OUTPUT_CHANNEL_COUNT] = Item::Info;
+    items
+};
+
+/// An input from the rotary encoder.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Input {
+    /// A rotation by detents, positive for clockwise.
+    Rotate(i16),
+    /// A short press of the button.
+    Press,
+}
+
+/// What the menu shows.
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Mode {
+    /// The list of items.
+    List,
+    /// The value of the selected item, while it is edited.
+    Edit,
+    /// The chosen preset, which is loaded by the next press.
+    Preset(u8),
+    /// An info page.
+    Info(u8),
+}
+
+/// The state of the menu.
+#[derive(Clone, Copy)]
+struct State {
+    open: bool,
+    selected: u8,
+    mode: Mode,
+    last_input: Instant,
+}
+
+/// The state of the menu.
+static STATE: Mutex<ThreadModeRawMutex, Cell<State>> = Mutex::new(Cell::new(State {
+    open: false,
+    selected: 0,
+    mode: Mode::List,
+    last_input: Instant::from_ticks(0),
+}));
+
+/// Signals changes of the menu, which the display redraws.
+static CHANGED: Signal<ThreadModeRawMutex, ()> = Signal::new();
+
+/// Whether the menu is open. Closes it after [`TIMEOUT`] without input.
+pub fn is_open() -> bool {
+    let mut state = STATE.lock(|state| state.get());
+
+    if state.open && state.last_input.elapsed() >= TIMEOUT {
+        state.open = false;
+        STATE.lock(|current| current.set(state));
+        log!(debug, "Menu closed after timeout");
+    }
+
+    state.open
+}
+
+/// Open the menu at its first item, or close it.
+pub fn toggle() {
+    let open = !is_open();
+
+    STATE.lock(|state| {
+        state.set(State {
+            open,
+            selected: 0,
+            mode: Mode::List,
+            last_input: Instant::now(),
+        })
+    });
+
+    CHANGED.signal(());
+}
+
+/// Wait for a change of the menu.
+pub async fn changed() {
+    CHANGED.wait().await;
+}
+
+/// Handle an input, while the menu is open.
+pub fn input(input: Input) {
+    let mut state = STATE.lock(|state| state.get());
+
+    if !state.open {
+        return;
+    }
+
+    let item = ITEMS[state.selected as usize];
+    state.last_input = Instant::now();
+
+    match (state.mode, input) {
+        (Mode::List, Input::Rotate(detents)) => {
+            let selected = (state.selected as i16 + detents).clamp(0, ITEMS.len() as i16 - 1);
+            state.selected = selected as u8;
+        }
+        (Mode::List, Input::Press) => match item {
+            Item::Exit => state.open = false,
+            Item::Info => state.mode = Mode::Info(0),
+            Item::Preset => state.mode = Mode::Preset(presets::active().unwrap_or_default() as u8),
+            _ => state.mode = Mode::Edit,
+        },
+        (Mode::Edit, Input::Rotate(detents)) => adjust(item, detents),
+        (Mode::Preset(index), Input::Rotate(detents)) => {
+            state.mode = Mode::Preset(next_preset(index, detents));
+        }
+        (Mode::Preset(index), Input::Press) => {
+            if let Err(error) = presets::load(index as usize) {
+                log!(warn, "Failed to load preset {}: {:?}", index, error);
+            }
+            state.mode = Mode::List;
+        }
+        (Mode::Info(page), Input::Rotate(detents)) => {
+            state.mode = Mode::Info((page as i16 + detents).rem_euclid(INFO_PAGE_COUNT as i16) as u8);
+        }
+        (Mode::Edit | Mode::Info(_), Input::Press) => state.mode = Mode::List,
+    }
+
+    STATE.lock(|current| current.set(state));
+    CHANGED.signal(());
+}
+
+/// The stored preset, that is a number of detents away from another one. Stays at the index without presets.
+fn next_preset(index: u8, detents: i16) -> u8 {
+    let step = if detents > 0 { 1 } else { MAX_PRESET_COUNT - 1 };
+    let mut next = index as usize;
+
+    for _ in 0..detents.unsigned_abs() {
+        let found = (1..=MAX_PRESET_COUNT)
+            .map(|offset| (next + offset * step) % MAX_PRESET_COUNT)
+            .find(|index| presets::name(*index).is_some());
+
+        match found {
+            Some(index) => next = index,
+            None => break,
+        }
+    }
+
+    next as u8
+}
+
+/// Change the value of an item, through the parameter store.
+fn adjust(item: Item, detents: i16) {
+    let result = match item {
+        Item::Volume => {
+            // Turning up from the muted attenuation starts at the lowest volume.
+            let attenuation = CONTROL.attenuation().min(MUTED_ATTENUATION - 1) as i32 - detents as i32 * VOLUME_STEP;
+            let attenuation = attenuation.clamp(0, MUTED_ATTENUATION as i32 - 1);
+            PARAMETERS.set(Parameter::Volume, Value::Integer(attenuation))
+        }
+        Item::Source => {
+            let cycle = control::SOURCE_CYCLE;
+            let selection = CONTROL.source_selection();
+            let index = cycle.iter().position(|source| *source == selection).unwrap_or_default() as i16;
+            let source = cycle[(index + detents).rem_euclid(cycle.len() as i16) as usize];
+            PARAMETERS.set(Parameter::SourceSelect, Value::Integer(source as i32))
+        }
+        Item::Trim(channel) => match PARAMETERS.get(Parameter::ChannelGain { channel }) {
+            Ok(Value::Float(gain_db)) => {
+                let gain_db = (gain_db + detents as f32 * TRIM_STEP_DB).clamp(-MAX_TRIM_DB, MAX_TRIM_DB);
+                PARAMETERS.set(Parameter::ChannelGain { channel }, Value::Float(gain_db))
+            }
+            Ok(_) => return,
+            Err(status) => Err(status),
+        },
+        Item::Preset | Item::Info | Item::Exit => return,
+    };
+
+    if let Err(status) = result {
+        log!(debug, "Menu: Failed to change {:?}: {:?}", item, status);
+    }
+}
+
+/// The label of an item.
+fn label(item: Item) -> Line {
+    let mut line = Line::new();
+
+    match item {
+        Item::Volume => _ = line.push_str("VOLUME"),
+        Item::Source => _ = line.push_str("SOURCE"),
+        Item::Preset => _ = line.push_str("PRESET"),
+        Item::Trim(channel) => _ = write!(line, "TRIM {}", channel + 1),
+        Item::Info => _ = line.push_str("INFO"),
+        Item::Exit => _ = line.push_str("EXIT"),
+    }
+
+    line
+}
+
+/// The value of an item, if it has one.
+fn value(item: Item) -> Line {
+    let mut line = Line::new();
+
+    match item {
+        Item::Volume => match CONTROL.attenuation() {
+            MUTED_ATTENUATION => _ = line.push_str("MUTE"),
+            attenuation => _ = write!(line, "{:.1} dB", -(attenuation as f32) / 2.0),
+        },
+        Item::Source => _ = line.push_str(control::source_selection_name(CONTROL.source_selection())),
+        Item::Preset => match presets::active().and_then(presets::name) {
+            Some(name) => _ = line.push_str(&name),
+            None => _ = line.push('-'),
+        },
+        Item::Trim(channel) => {
+            if let Ok(Value::Float(gain_db)) = PARAMETERS.get(Parameter::ChannelGain { channel }) {
+                _ = write!(line, "{:+.1} dB", gain_db);
+            }
+        }
+        Item::Info | Item::Exit => (),
+    }
+
+    line
+}
+
+/// Write the lines of an info page.
+fn info_page(page: u8, lines: &mut [Line]) {
+    let mut lines = lines.iter_mut();
+
+    match page {
+        0 => {
+            let info = device_info();
+            let [major, minor, patch] = info.version;
+
+            if let Some(line) = lines.next() {
+                _ = write!(line, "FW {}.{}.{} HW {}", major, minor, patch, info.hardware_revision);
+            }
+            if let Some(line) = lines.next() {
+                _ = write!(line, "BUILD {}", info.build_date_str());
+            }
+            if let Some(line) = lines.next() {
+                _ = write!(line, "GIT {}", info.git_hash_str());
+            }
+            if let Some(line) = lines.next() {
+                let uptime_min = info.uptime_s / 60;
+                _ = write!(line, "UPTIME {}:{:02} H", uptime_min / 60, uptime_min % 60);
+            }
+        }
+        _ => {
+            if let (Some(line), Some(temperature)) = (lines.next(), thermal::microcontroller_temperature()) {
+                _ = write!(line, "MCU {:.0} C", temperature);
+            }
+
+            for channel in 0..OUTPUT_CHANNEL_COUNT {
+                if let (Some(line), Some(temperature)) = (lines.next(), thermal::temperature(channel)) {
+                    _ = write!(line, "AMP {} {:.0} C", channel + 1, temperature);
+                }
+            }
+        }
+    }
+}
+
+/// Write the lines of the menu: the title, and the items around the selected one, or an info page. Text is in upper
+/// case, except for units.
+pub fn render(lines: &mut [Line; LINE_COUNT]) {
+    let state = STATE.lock(|state| state.get());
+    lines.iter_mut().for_each(|line| line.clear());
+
+    let item = ITEMS[state.selected as usize];
+
+    match state.mode {
+        Mode::Info(page) => {
+            _ = write!(lines[0], "INFO {}/{}", page + 1, INFO_PAGE_COUNT);
+            info_page(page, &mut lines[1..]);
+        }
+        Mode::Edit | Mode::Preset(_) => {
+            lines[0] = label(item);
+
+            let text = match state.mode {
+                Mode::Preset(index) => {
+                    let mut line = Line::new();
+                    match presets::name(index as usize) {
+                        Some(name) => _ = write!(line, "{} {}", index, name.as_str()),
+                        None => _ = line.push_str("NO PRESETS"),
+                    }
+                    line
+                }
+                _ => value(item),
+            };
+
+            lines[3] = text;
+        }
+        Mode::List => {
+            _ = lines[0].push_str("MENU");
+
+            // The visible items scroll with the selection.
+            let visible = LINE_COUNT - 1;
+            let first = (state.selected as usize + 1).saturating_sub(visible);
+
+            for (line, (index, item)) in lines[1..].iter_mut().zip(ITEMS.iter().enumerate().skip(first)) {
+                let marker = if index == state.selected as usize { '>' } else { ' ' };
+                _ = write!(line, "{}{:<9}{}", marker, label(*item).as_str(), value(*item).as_str());
+            }
+        }
+    }
+
+    for line in lines.iter_mut() {
+        let upper: Line = line
+            .chars()
+            .map(|character| match character {
+                'd' | 'k' | 'z' => character,
+                _ => character.to_ascii_uppercase(),
+            })
+            .collect();
+        *line = upper;
+    }
+}
@@ -0,0 +1,64 @@
+//! External mute relay (or mute switch) in the analog output path, which opens for every reason of a silent output.
+//!
+//! The output drives the relay coil (active high: closed, the output connects to the speakers). It follows
+//! [`Control::silent`], which combines all reasons for muting: mute and standby, source switching and amplifier
+//! faults (the amplifiers are not running), supply undervoltage, an imminent power loss, and DC at the output.
+//!
+//! The relay opens within one [`CHECK_INTERVAL`] of the output turning silent, and closes only after the output
+//! played for [`CLOSE_DELAY`], such that switching transients of the amplifiers do not reach the speakers.
+//!
+//! [`Control::silent`]: crate::control::Control::silent
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use embassy_stm32::gpio::{Level, Output, Speed};
+use embassy_stm32::peripherals;
+use embassy_time::{Duration, Instant, Ticker};
+
+use crate::control::CONTROL;
+use crate::*;
+
+/// The interval between checks of the output state.
+pub const CHECK_INTERVAL: Duration = Duration::from_millis(5);
+
+/// The time that the output must play, before the relay closes.
+pub const CLOSE_DELAY: Duration = Duration::from_millis(200);
+
+/// Whether the relay is closed.
+static CLOSED: AtomicBool = AtomicBool::new(false);
+
+/// Whether the relay is closed, such that the output connects to the speakers.
+pub fn closed() -> bool {
+    CLOSED.load(Ordering::Relaxed)
+}
+
+/// Resources that are required for the mute relay.
+#[allow(missing_docs)]
+pub struct MuteRelayResources {
+    pub output: peripherals::PD10,
+}
+
+/// Drives the mute relay by the output state.
+#[embassy_executor::task]
+pub async fn mute_relay_task(resources: MuteRelayResources) {
+    let mut output = Output::new(resources.output, Level::Low, Speed::Low);
+    let mut ticker = Ticker::every(CHECK_INTERVAL);
+    let mut playing_since: Option<Instant> = None;
+
+    loop {
+        ticker.next().await;
+
+        if CONTROL.silent() {
+            playing_since = None;
+        } else if playing_since.is_none() {
+            playing_since = Some(Instant::now());
+        }
+
+        let close = playing_since.is_some_and(|since| since.elapsed() >= CLOSE_DELAY);
+
+        if close != output.is_set_high() {
+            log!(debug, "Mute relay: {}", if close { "closed" } else { "open" });
+            output.set_level(Level::from(close));
+            CLOSED.store(close, Ordering::Relaxed);
+        }
+    }
+}
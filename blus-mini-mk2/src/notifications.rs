@@ -0,0 +1,84 @@
+//! Notifications of parameter changes, for control clients that would otherwise poll.
+//!
+//! Global parameters change from many places: the potentiometer, automatic source selection, buttons, the remote
+//! control, and all control frontends. Instead of hooking each of them, the [`notification_task`] compares the
+//! watched parameters periodically, and publishes every change. The consoles and the HID interface subscribe, and
+//! forward the changes to clients that enabled notifications. The status display redraws on changes (see
+//! [`crate::display`]).
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::pubsub::{PubSubChannel, Subscriber};
+use embassy_time::{Duration, Ticker};
+use protocol::parameter::{Parameter, Value};
+
+use crate::parameters::PARAMETERS;
+
+/// The rate at which parameters are compared.
+const POLL_RATE_HZ: u64 = 20;
+
+/// The number of changes that are queued per subscriber. Older changes are dropped for slow subscribers.
+const QUEUE_SIZE: usize = 8;
+
+/// The maximum number of subscribers: the USB console, the UART console, the HID interface, and the status display.
+const MAX_SUBSCRIBER_COUNT: usize = 4;
+
+/// The parameters that are watched for changes.
+const WATCHED_PARAMETERS: [Parameter; 7] = [
+    Parameter::Volume,
+    Parameter::Mute,
+    Parameter::SourceSelect,
+    Parameter::ActiveSource,
+    Parameter::Standby,
+    Parameter::InputVolume,
+    Parameter::Status,
+];
+
+/// A change of a parameter, with its new value.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Change {
+    /// The parameter that changed.
+    pub parameter: Parameter,
+    /// The new value.
+    pub value: Value,
+}
+
+/// Changes of parameters, for all subscribers.
+static CHANGES: PubSubChannel<CriticalSectionRawMutex, Change, QUEUE_SIZE, MAX_SUBSCRIBER_COUNT, 0> =
+    PubSubChannel::new();
+
+/// A receiver of parameter changes.
+pub type ChangeSubscriber = Subscriber<'static, CriticalSectionRawMutex, Change, QUEUE_SIZE, MAX_SUBSCRIBER_COUNT, 0>;
+
+/// Subscribe to parameter changes.
+///
+/// Panics, if there are more than [`MAX_SUBSCRIBER_COUNT`] subscribers.
+pub fn subscribe() -> ChangeSubscriber {
+    defmt::unwrap!(CHANGES.subscriber())
+}
+
+/// Publishes changes of the watched parameters.
+#[embassy_executor::task]
+pub async fn notification_task() {
+    let publisher = CHANGES.immediate_publisher();
+    let mut ticker = Ticker::every(Duration::from_hz(POLL_RATE_HZ));
+
+    let mut values = WATCHED_PARAMETERS.map(|parameter| PARAMETERS.get(parameter).ok());
+
+    loop {
+        ticker.next().await;
+
+        for (parameter, last_value) in WATCHED_PARAMETERS.iter().zip(values.iter_mut()) {
+            let value = PARAMETERS.get(*parameter).ok();
+
+            if value != *last_value {
+                *last_value = value;
+
+                if let Some(value) = value {
+                    publisher.publish_immediate(Change {
+                        parameter: *parameter,
+                        value,
+                    });
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,100 @@
+//! Estimation of the output power per channel, from the output signal, the amplifier gain, and the speaker load.
+//!
+//! The audio routing passes the mean square of every processed sample block (see [`update`]). At full-scale, a sine
+//! drives the amplifier output at its gain in dBV (see [`crate::amplifiers::gain`]), so the power into the load is
+//! `2 * mean_square * 10^(gain / 10) / load`. The power is averaged over [`AVERAGING_TIME`] for metering (shell
+//! `stats`, and telemetry), and over [`LONG_TERM_AVERAGING_TIME`], which follows the heating of the amplifier dies.
+//! While the short-term power exceeds the long-term power, the dies are still heating up, which the thermal
+//! protection anticipates (see [`crate::thermal`]).
+//!
+//! The estimate assumes a resistive load at its nominal impedance. The loads are not stored with the settings; a
+//! startup script can configure them (shell `load`, see [`crate::startup_script`]).
+use core::cell::Cell;
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_time::Duration;
+
+use crate::amplifiers;
+use crate::*;
+
+/// The default speaker load in Ω.
+pub const DEFAULT_LOAD_OHM: u8 = 4;
+
+/// The range of speaker loads in Ω.
+pub const LOAD_RANGE_OHM: core::ops::RangeInclusive<u8> = 2..=32;
+
+/// The averaging time of the output power for metering.
+pub const AVERAGING_TIME: Duration = Duration::from_secs(1);
+
+/// The averaging time of the output power, which follows the heating of the amplifier dies.
+pub const LONG_TERM_AVERAGING_TIME: Duration = Duration::from_secs(30);
+
+/// The speaker loads in Ω, by output channel.
+static LOADS_OHM: [AtomicU8; OUTPUT_CHANNEL_COUNT] = [const { AtomicU8::new(DEFAULT_LOAD_OHM) }; OUTPUT_CHANNEL_COUNT];
+
+/// The averaged output powers in W.
+#[derive(Clone, Copy)]
+struct Power {
+    short_term_w: [f32; OUTPUT_CHANNEL_COUNT],
+    long_term_w: [f32; OUTPUT_CHANNEL_COUNT],
+}
+
+/// The output powers without playback.
+const SILENT: Power = Power {
+    short_term_w: [0.0; OUTPUT_CHANNEL_COUNT],
+    long_term_w: [0.0; OUTPUT_CHANNEL_COUNT],
+};
+
+static POWER: Mutex<CriticalSectionRawMutex, Cell<Power>> = Mutex::new(Cell::new(SILENT));
+
+/// The speaker load of an output channel in Ω.
+pub fn load_ohm(channel: usize) -> u8 {
+    LOADS_OHM[channel].load(Ordering::Relaxed)
+}
+
+/// Set the speaker load of an output channel in Ω, within [`LOAD_RANGE_OHM`].
+pub fn set_load_ohm(channel: usize, load_ohm: u8) {
+    let load_ohm = load_ohm.clamp(*LOAD_RANGE_OHM.start(), *LOAD_RANGE_OHM.end());
+    LOADS_OHM[channel].store(load_ohm, Ordering::Relaxed);
+}
+
+/// The output power of an output channel in W, averaged over [`AVERAGING_TIME`].
+pub fn power_w(channel: usize) -> f32 {
+    POWER.lock(|power| power.get().short_term_w[channel])
+}
+
+/// The output power of an output channel in W, averaged over [`LONG_TERM_AVERAGING_TIME`].
+pub fn long_term_power_w(channel: usize) -> f32 {
+    POWER.lock(|power| power.get().long_term_w[channel])
+}
+
+/// Update the output powers by the mean squares of the output channels in a sample block, with its number of
+/// samples per channel.
+pub fn update(mean_squares: &[f32; OUTPUT_CHANNEL_COUNT], sample_count: usize) {
+    let block_time_s = sample_count as f32 / SAMPLE_RATE_HZ as f32;
+    let short_term_weight = (block_time_s / (AVERAGING_TIME.as_millis() as f32 / 1000.0)).min(1.0);
+    let long_term_weight = (block_time_s / (LONG_TERM_AVERAGING_TIME.as_millis() as f32 / 1000.0)).min(1.0);
+
+    // The power of a full-scale square wave into 1 Ω.
+    let full_scale_w = 2.0 * 10.0f32.powf(amplifiers::gain().dbv() / 10.0);
+
+    POWER.lock(|power| {
+        let mut state = power.get();
+
+        for (channel, mean_square) in mean_squares.iter().enumerate() {
+            let power_w = mean_square * full_scale_w / load_ohm(channel) as f32;
+
+            state.short_term_w[channel] += (power_w - state.short_term_w[channel]) * short_term_weight;
+            state.long_term_w[channel] += (power_w - state.long_term_w[channel]) * long_term_weight;
+        }
+
+        power.set(state);
+    });
+}
+
+/// Forget the output powers, when playback stops.
+pub fn reset() {
+    POWER.lock(|power| power.set(SILENT));
+}
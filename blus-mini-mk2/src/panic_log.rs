@@ -0,0 +1,120 @@
+//! The message of the last panic, kept across the reset that follows it.
+//!
+//! The panic handler of the firmware (see `src/main.rs`) leaves the location and message of a panic in SRAM4, which
+//! keeps its content across resets (see [`store`]), and then resets the microcontroller. At startup, [`take`] finds
+//! the record: the panic is recorded in the event log, and its message stays available on the control interfaces
+//! (shell `faults`) until the faults are cleared.
+//!
+//! Messages are truncated to [`MAX_MESSAGE_SIZE`]. Panics of `defmt::panic!` and `defmt::unwrap!` carry their message
+//! on the defmt log only, since it is encoded for the host; they are kept with their location.
+use core::cell::RefCell;
+use core::fmt::{self, Write};
+use core::mem::MaybeUninit;
+use core::panic::PanicInfo;
+use core::ptr::addr_of_mut;
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use heapless::String;
+
+/// The maximum size of a kept message in byte, including its location.
+pub const MAX_MESSAGE_SIZE: usize = 192;
+
+/// Marks a valid record in [`RECORD`].
+const PANIC_MAGIC: u32 = 0x9A41_C0DE;
+
+/// The record of a panic, as left for after the reset.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Record {
+    /// [`PANIC_MAGIC`], if the record is valid. Comes first, such that it is cleared on its own.
+    magic: u32,
+    length: u32,
+    message: [u8; MAX_MESSAGE_SIZE],
+}
+
+/// Holds the record of a panic during the reset. Not initialized at startup.
+#[link_section = ".sram4"]
+static mut RECORD: MaybeUninit<Record> = MaybeUninit::uninit();
+
+/// The message of the panic before the last reset, until it is cleared.
+static LAST_PANIC: Mutex<CriticalSectionRawMutex, RefCell<Option<String<MAX_MESSAGE_SIZE>>>> =
+    Mutex::new(RefCell::new(None));
+
+/// Formats into a buffer, and drops what exceeds it.
+struct Truncating<'a> {
+    buffer: &'a mut [u8],
+    length: usize,
+}
+
+impl Write for Truncating<'_> {
+    fn write_str(&mut self, text: &str) -> fmt::Result {
+        let length = text.len().min(self.buffer.len() - self.length);
+        self.buffer[self.length..self.length + length].copy_from_slice(&text.as_bytes()[..length]);
+        self.length += length;
+        Ok(())
+    }
+}
+
+/// Leave the location and message of a panic for after the reset.
+///
+/// Only called by the panic handler, which does not return.
+pub fn store(info: &PanicInfo) {
+    let mut message = [0; MAX_MESSAGE_SIZE];
+    let mut writer = Truncating {
+        buffer: &mut message,
+        length: 0,
+    };
+
+    if let Some(location) = info.location() {
+        _ = write!(writer, "{}:{}: ", location.file(), location.line());
+    }
+    _ = write!(writer, "{}", info.message());
+
+    let record = Record {
+        magic: PANIC_MAGIC,
+        length: writer.length as u32,
+        message,
+    };
+
+    // SAFETY: Only accessed by the panic handler, and once at startup, before any task runs.
+    unsafe {
+        addr_of_mut!(RECORD).cast::<Record>().write_volatile(record);
+    }
+}
+
+/// Find the record of a panic before the last reset. Returns whether there was one, and clears the record.
+///
+/// Must be called once at startup.
+pub fn take() -> bool {
+    // SAFETY: See `store`. The memory may hold any value after power-up, which is valid for `Record`.
+    let record = unsafe { addr_of_mut!(RECORD).cast::<Record>().read_volatile() };
+    // SAFETY: See `store`.
+    unsafe {
+        addr_of_mut!(RECORD).cast::<u32>().write_volatile(0);
+    }
+
+    if record.magic != PANIC_MAGIC {
+        return false;
+    }
+
+    // A truncated message may end within a character.
+    let bytes = &record.message[..(record.length as usize).min(MAX_MESSAGE_SIZE)];
+    let text = match core::str::from_utf8(bytes) {
+        Ok(text) => text,
+        Err(error) => core::str::from_utf8(&bytes[..error.valid_up_to()]).unwrap_or_default(),
+    };
+
+    LAST_PANIC.lock(|last_panic| *last_panic.borrow_mut() = String::try_from(text).ok());
+    true
+}
+
+/// The message of the panic before the last reset, unless it was cleared.
+pub fn last_panic() -> Option<String<MAX_MESSAGE_SIZE>> {
+    LAST_PANIC.lock(|last_panic| last_panic.borrow().clone())
+}
+
+/// Clear the message of the last panic.
+pub fn clear() {
+    LAST_PANIC.lock(|last_panic| *last_panic.borrow_mut() = None);
+}
@@ -0,0 +1,345 @@
+//! The parameter store, through which all control frontends read and write device parameters.
+//!
+//! The host protocols (HID and bulk transfers), the command shell, SCPI, and the control register map access
+//! parameters by their typed identifiers (see [`protocol::parameter`]). Writes are checked against the parameter's
+//! [`Descriptor`] (type, range, and writability) in one place, before they are applied to the control state or the
+//! signal processing configuration.
+//!
+//! [`Descriptor`]: protocol::parameter::Descriptor
+use core::f32::consts::FRAC_1_SQRT_2;
+
+use audio::filter_config::{FilterConfig, StageConfig, StageKind};
+use audio::AudioSource;
+use protocol::bulk::PARAMETER_ENTRY_SIZE;
+use protocol::hid::Status;
+use protocol::parameter::{Parameter, StageType, Value};
+
+use crate::control::CONTROL;
+use crate::dsp;
+use crate::*;
+
+static_assertions::const_assert_eq!(protocol::CHANNEL_COUNT, OUTPUT_CHANNEL_COUNT);
+static_assertions::const_assert_eq!(protocol::MAX_STAGE_COUNT, audio::filter_config::MAX_STAGE_COUNT);
+static_assertions::const_assert_eq!(protocol::MAX_DELAY, audio::audio_filter::MAX_DELAY_LENGTH);
+
+/// A stage that passes samples unchanged.
+const IDENTITY_STAGE: StageConfig = StageConfig::Coefficients {
+    a1: 0.0,
+    a2: 0.0,
+    b0: 1.0,
+    b1: 0.0,
+    b2: 0.0,
+};
+
+/// The stage type of a stage configuration.
+pub fn stage_type(stage: &StageConfig) -> StageType {
+    match stage {
+        StageConfig::Parametric { kind, .. } => match kind {
+            StageKind::LowPass => StageType::LowPass,
+            StageKind::HighPass => StageType::HighPass,
+            StageKind::BandPass => StageType::BandPass,
+            StageKind::Notch => StageType::Notch,
+            StageKind::AllPass => StageType::AllPass,
+            StageKind::PeakingEq => StageType::PeakingEq,
+            StageKind::LowShelf => StageType::LowShelf,
+            StageKind::HighShelf => StageType::HighShelf,
+        },
+        StageConfig::Coefficients { .. } => StageType::Coefficients,
+    }
+}
+
+/// The kind of parametric stages of a stage type, or `None` for coefficient stages.
+pub fn stage_kind(stage_type: StageType) -> Option<StageKind> {
+    match stage_type {
+        StageType::LowPass => Some(StageKind::LowPass),
+        StageType::HighPass => Some(StageKind::HighPass),
+        StageType::BandPass => Some(StageKind::BandPass),
+        StageType::Notch => Some(StageKind::Notch),
+        StageType::AllPass => Some(StageKind::AllPass),
+        StageType::PeakingEq => Some(StageKind::PeakingEq),
+        StageType::LowShelf => Some(StageKind::LowShelf),
+        StageType::HighShelf => Some(StageKind::HighShelf),
+        StageType::Coefficients => None,
+    }
+}
+
+/// Get a mutable reference to a value of a stage, by its index. See [`StageType`] for the meaning of the indices.
+fn stage_value_mut(stage: &mut StageConfig, index: u8) -> Option<&mut f32> {
+    match (stage, index) {
+        (StageConfig::Parametric { frequency_hz, .. }, 0) => Some(frequency_hz),
+        (StageConfig::Parametric { q, .. }, 1) => Some(q),
+        (StageConfig::Parametric { gain_db, .. }, 2) => Some(gain_db),
+        (StageConfig::Coefficients { b0, .. }, 0) => Some(b0),
+        (StageConfig::Coefficients { b1, .. }, 1) => Some(b1),
+        (StageConfig::Coefficients { b2, .. }, 2) => Some(b2),
+        (StageConfig::Coefficients { a1, .. }, 3) => Some(a1),
+        (StageConfig::Coefficients { a2, .. }, 4) => Some(a2),
+        _ => None,
+    }
+}
+
+/// Change the type of a stage, keeping its parameters or response where possible.
+fn set_stage_type(stage: &mut StageConfig, stage_type: StageType) -> Result<(), Status> {
+    *stage = match (*stage, stage_kind(stage_type)) {
+        (
+            StageConfig::Parametric {
+                frequency_hz,
+                q,
+                gain_db,
+                ..
+            },
+            Some(kind),
+        ) => StageConfig::Parametric {
+            kind,
+            frequency_hz,
+            q,
+            gain_db,
+        },
+        (StageConfig::Coefficients { .. }, Some(kind)) => StageConfig::Parametric {
+            kind,
+            frequency_hz: 1000.0,
+            q: FRAC_1_SQRT_2,
+            gain_db: 0.0,
+        },
+        (StageConfig::Parametric { .. }, None) => {
+            let coefficients = stage.coefficients(SAMPLE_RATE_HZ).map_err(|_| Status::InvalidValue)?;
+
+            StageConfig::Coefficients {
+                a1: coefficients.a1,
+                a2: coefficients.a2,
+                b0: coefficients.b0,
+                b1: coefficients.b1,
+                b2: coefficients.b2,
+            }
+        }
+        (StageConfig::Coefficients { .. }, None) => *stage,
+    };
+
+    Ok(())
+}
+
+/// The output channel of a channel parameter, or `None` for global parameters.
+fn parameter_channel(parameter: Parameter) -> Option<usize> {
+    match parameter {
+        Parameter::ChannelGain { channel }
+        | Parameter::ChannelInverted { channel }
+        | Parameter::ChannelDelay { channel }
+        | Parameter::StageCount { channel }
+        | Parameter::StageType { channel, .. }
+        | Parameter::StageValue { channel, .. } => Some(channel as usize),
+        _ => None,
+    }
+}
+
+/// The integer of a value, after checking against the parameter's range (see [`check_writable`]).
+fn integer(value: Value) -> Result<i32, Status> {
+    match value {
+        Value::Integer(value) => Ok(value),
+        _ => Err(Status::InvalidValue),
+    }
+}
+
+/// Get a channel parameter from a filter configuration.
+fn get_channel_parameter(config: &FilterConfig, parameter: Parameter) -> Result<Value, Status> {
+    let stage = |stage: u8| config.stages.get(stage as usize).ok_or(Status::UnknownParameter);
+
+    let value = match parameter {
+        Parameter::ChannelGain { .. } => Value::Float(config.gain_db),
+        Parameter::ChannelInverted { .. } => Value::Boolean(config.inverted),
+        Parameter::ChannelDelay { .. } => Value::Integer(config.delay as i32),
+        Parameter::StageCount { .. } => Value::Integer(config.stages.len() as i32),
+        Parameter::StageType { stage: index, .. } => Value::Integer(stage_type(stage(index)?) as i32),
+        Parameter::StageValue {
+            stage: stage_index,
+            index,
+            ..
+        } => {
+            let mut stage = *stage(stage_index)?;
+            Value::Float(*stage_value_mut(&mut stage, index).ok_or(Status::UnknownParameter)?)
+        }
+        _ => return Err(Status::UnknownParameter),
+    };
+
+    Ok(value)
+}
+
+/// Set a channel parameter in a filter configuration, without validating the result.
+fn set_channel_parameter(config: &mut FilterConfig, parameter: Parameter, value: Value) -> Result<(), Status> {
+    match (parameter, value) {
+        (Parameter::ChannelGain { .. }, Value::Float(gain_db)) => config.gain_db = gain_db,
+        (Parameter::ChannelInverted { .. }, Value::Boolean(inverted)) => config.inverted = inverted,
+        (Parameter::ChannelDelay { .. }, value) => config.delay = integer(value)? as usize,
+        (Parameter::StageCount { .. }, value) => {
+            let stage_count = integer(value)? as usize;
+
+            config.stages.truncate(stage_count);
+            while config.stages.len() < stage_count {
+                // Cannot fail, the stage count is within the capacity.
+                config.stages.push(IDENTITY_STAGE).unwrap();
+            }
+        }
+        (Parameter::StageType { stage, .. }, value) => {
+            let stage_type = StageType::try_from(integer(value)? as u8).map_err(|_| Status::InvalidValue)?;
+            let stage = config.stages.get_mut(stage as usize).ok_or(Status::UnknownParameter)?;
+
+            set_stage_type(stage, stage_type)?;
+        }
+        (Parameter::StageValue { stage, index, .. }, Value::Float(value)) => {
+            let stage = config.stages.get_mut(stage as usize).ok_or(Status::UnknownParameter)?;
+
+            *stage_value_mut(stage, index).ok_or(Status::UnknownParameter)? = value;
+        }
+        _ => return Err(Status::InvalidValue),
+    }
+
+    Ok(())
+}
+
+/// A checked change of a global parameter.
+enum GlobalChange {
+    Attenuation(u8),
+    Muted(bool),
+    SourceSelection(AudioSource),
+    Standby(bool),
+}
+
+impl GlobalChange {
+    fn new(parameter: Parameter, value: Value) -> Result<Self, Status> {
+        let change = match (parameter, value) {
+            (Parameter::Volume, value) => GlobalChange::Attenuation(integer(value)? as u8),
+            (Parameter::Mute, Value::Boolean(muted)) => GlobalChange::Muted(muted),
+            (Parameter::Standby, Value::Boolean(standby)) => GlobalChange::Standby(standby),
+            (Parameter::SourceSelect, value) => GlobalChange::SourceSelection(
+                AudioSource::try_from(integer(value)? as u8).map_err(|_| Status::InvalidValue)?,
+            ),
+            _ => return Err(Status::InvalidValue),
+        };
+
+        Ok(change)
+    }
+
+    fn apply(self) {
+        match self {
+            GlobalChange::Attenuation(attenuation) => CONTROL.set_attenuation(attenuation),
+            GlobalChange::Muted(muted) => CONTROL.set_muted(muted),
+            GlobalChange::SourceSelection(source) => CONTROL.set_source_selection(source),
+            GlobalChange::Standby(standby) => CONTROL.set_standby(standby),
+        }
+    }
+}
+
+/// Check that a value can be written to a parameter: the parameter is writable, and the value has its type and range.
+fn check_writable(parameter: Parameter, value: Value) -> Result<(), Status> {
+    let descriptor = parameter.descriptor();
+
+    if !descriptor.writable {
+        return Err(Status::ReadOnly);
+    }
+
+    if !descriptor.accepts(value) {
+        return Err(Status::InvalidValue);
+    }
+
+    Ok(())
+}
+
+/// Decode a list of parameter entries of identifier (`u16`) and raw value (`u32`).
+fn decode_entries(entries: &[u8]) -> impl Iterator<Item = Result<(Parameter, Value), Status>> + '_ {
+    entries.chunks_exact(PARAMETER_ENTRY_SIZE).map(|entry| {
+        let id = u16::from_le_bytes([entry[0], entry[1]]);
+        let raw = u32::from_le_bytes([entry[2], entry[3], entry[4], entry[5]]);
+
+        let parameter = Parameter::from_id(id).ok_or(Status::UnknownParameter)?;
+        let value = Value::from_raw(raw, parameter.value_type()).ok_or(Status::InvalidValue)?;
+
+        check_writable(parameter, value)?;
+        Ok((parameter, value))
+    })
+}
+
+/// Typed access to all device parameters.
+///
+/// Global parameters are backed by the [`CONTROL`] state, channel parameters by the signal processing
+/// configuration (see [`dsp::dsp_config`]).
+pub struct ParameterStore {
+    _private: (),
+}
+
+/// The parameter store, shared by all control frontends.
+pub static PARAMETERS: ParameterStore = ParameterStore { _private: () };
+
+impl ParameterStore {
+    /// Get the value of a parameter.
+    pub fn get(&self, parameter: Parameter) -> Result<Value, Status> {
+        if let Some(channel) = parameter_channel(parameter) {
+            return get_channel_parameter(&dsp::dsp_config()[channel], parameter);
+        }
+
+        let value = match parameter {
+            Parameter::Volume => Value::Integer(CONTROL.attenuation() as i32),
+            Parameter::Mute => Value::Boolean(CONTROL.muted()),
+            Parameter::SourceSelect => Value::Integer(CONTROL.source_selection() as i32),
+            Parameter::ActiveSource => Value::Integer(CONTROL.active_source() as i32),
+            Parameter::Status => Value::Integer(CONTROL.status() as i32),
+            Parameter::Standby => Value::Boolean(CONTROL.standby()),
+            Parameter::InputVolume => Value::Integer(CONTROL.input_attenuation() as i32),
+            _ => return Err(Status::UnknownParameter),
+        };
+
+        Ok(value)
+    }
+
+    /// Set the value of a parameter.
+    ///
+    /// Changes to the signal processing configuration only take effect, if the resulting configuration is valid.
+    pub fn set(&self, parameter: Parameter, value: Value) -> Result<(), Status> {
+        check_writable(parameter, value)?;
+
+        match parameter_channel(parameter) {
+            Some(channel) => {
+                let mut config = dsp::dsp_config();
+                set_channel_parameter(&mut config[channel], parameter, value)?;
+
+                dsp::set_dsp_config(config).map_err(|_| Status::InvalidValue)
+            }
+            None => {
+                GlobalChange::new(parameter, value)?.apply();
+                Ok(())
+            }
+        }
+    }
+
+    /// Set multiple parameters from a list of entries of identifier (`u16`) and raw value (`u32`).
+    ///
+    /// Entries are applied in order. Either all parameters are set, or none, if any of them is invalid,
+    /// or the resulting signal processing configuration is invalid.
+    pub fn set_entries(&self, entries: &[u8]) -> Result<(), Status> {
+        if entries.len() % PARAMETER_ENTRY_SIZE != 0 {
+            return Err(Status::InvalidValue);
+        }
+
+        let mut config = dsp::dsp_config();
+
+        for entry in decode_entries(entries) {
+            let (parameter, value) = entry?;
+
+            match parameter_channel(parameter) {
+                Some(channel) => set_channel_parameter(&mut config[channel], parameter, value)?,
+                // Checked here, but applied below.
+                None => _ = GlobalChange::new(parameter, value)?,
+            }
+        }
+
+        dsp::set_dsp_config(config).map_err(|_| Status::InvalidValue)?;
+
+        for entry in decode_entries(entries) {
+            let (parameter, value) = entry?;
+
+            if parameter_channel(parameter).is_none() {
+                GlobalChange::new(parameter, value)?.apply();
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,177 @@
+//! The mapping of the volume potentiometer to the volume input (see [`crate::control`]).
+//!
+//! The potentiometer task reads the wiper [`OVERSAMPLING_COUNT`] times per sample, and low-pass filters the mean.
+//! After the calibrated taper of the potentiometer (see [`crate::calibration`]), the position passes:
+//! - A dead band at both ends ([`DEAD_BAND`]), such that the end stops reliably reach mute and full volume.
+//! - A hysteresis ([`Knob`]), such that noise around a resting position does not toggle between volume steps.
+//! - The audio taper: the position maps linearly to dB, over a configurable range below full-scale (see
+//!   [`set_range_db`]). The lower end stop mutes.
+//!
+//! A second potentiometer (on PC5, read along with the first one) adjusts the gains of the output channels, by its
+//! [`SecondTarget`]: the balance, or the level of some channels, e.g. of a subwoofer, or of the tweeters for treble.
+//! Its position passes the same dead band and hysteresis, and a dead band around the center ([`CENTER_DEAD_BAND`]),
+//! which leaves the gains unchanged. The adjustments apply on top of the signal processing configuration, and are not
+//! stored with it.
+//!
+//! The range and the target are not stored with the settings; a startup script can configure them (shell `pot`, see
+//! [`crate::startup_script`]). With the rotary encoder (feature `encoder`), there are no potentiometers.
+use core::cell::Cell;
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use embassy_sync::blocking_mutex::raw::{CriticalSectionRawMutex, ThreadModeRawMutex};
+use embassy_sync::blocking_mutex::Mutex;
+
+use crate::control::MUTED_ATTENUATION;
+use crate::*;
+
+/// The number of conversions, whose mean is a sample of the wiper.
+pub const OVERSAMPLING_COUNT: usize = 16;
+
+/// The width of the dead band at either end of the position range (out of 1).
+pub const DEAD_BAND: f32 = 0.02;
+
+/// The position change (out of 1) that changes the held position.
+pub const HYSTERESIS: f32 = 0.004;
+
+/// The default volume range in dB.
+pub const DEFAULT_RANGE_DB: u8 = 60;
+
+/// The lowest volume range in dB.
+pub const MIN_RANGE_DB: u8 = 20;
+
+/// The highest volume range in dB, which keeps the attenuation below [`MUTED_ATTENUATION`].
+pub const MAX_RANGE_DB: u8 = 120;
+
+/// The width of the dead band around the center of the second potentiometer (out of 1, on either side).
+pub const CENTER_DEAD_BAND: f32 = 0.02;
+
+/// The gain change of a level target at either end stop in dB.
+pub const LEVEL_RANGE_DB: f32 = 12.0;
+
+/// The attenuation of the opposite side by a balance target at either end stop in dB.
+pub const BALANCE_RANGE_DB: f32 = 40.0;
+
+/// The output channels of the left input channel. The others belong to the right one.
+const LEFT_CHANNELS: u8 = 0b0011;
+
+/// The parameter that the second potentiometer adjusts.
+#[derive(Clone, Copy, PartialEq, Debug, defmt::Format)]
+pub enum SecondTarget {
+    /// Nothing, e.g. without a second potentiometer.
+    Off,
+    /// The balance between the output channels of the left input channel (0 and 1), and the right one (2 and 3).
+    Balance,
+    /// The level of the output channels in a mask (by index), by up to [`LEVEL_RANGE_DB`] in either direction.
+    Level(u8),
+}
+
+/// The volume range in dB.
+static RANGE_DB: AtomicU8 = AtomicU8::new(DEFAULT_RANGE_DB);
+
+/// The parameter that the second potentiometer adjusts.
+static SECOND_TARGET: Mutex<ThreadModeRawMutex, Cell<SecondTarget>> = Mutex::new(Cell::new(SecondTarget::Off));
+
+/// The gain adjustments of the output channels by the second potentiometer in dB.
+static ADJUSTMENTS_DB: Mutex<CriticalSectionRawMutex, Cell<[f32; OUTPUT_CHANNEL_COUNT]>> =
+    Mutex::new(Cell::new([0.0; OUTPUT_CHANNEL_COUNT]));
+
+/// The volume range in dB, from the upper end stop (full-scale) to just above the lower end stop.
+pub fn range_db() -> u8 {
+    RANGE_DB.load(Ordering::Relaxed)
+}
+
+/// Set the volume range in dB (from [`MIN_RANGE_DB`] to [`MAX_RANGE_DB`]).
+pub fn set_range_db(range_db: u8) {
+    RANGE_DB.store(range_db.clamp(MIN_RANGE_DB, MAX_RANGE_DB), Ordering::Relaxed);
+}
+
+/// The parameter that the second potentiometer adjusts.
+pub fn second_target() -> SecondTarget {
+    SECOND_TARGET.lock(|target| target.get())
+}
+
+/// Set the parameter that the second potentiometer adjusts. Takes effect with its next sample, except for
+/// [`SecondTarget::Off`], which removes the adjustments right away.
+pub fn set_second_target(target: SecondTarget) {
+    SECOND_TARGET.lock(|current| current.set(target));
+
+    if target == SecondTarget::Off {
+        ADJUSTMENTS_DB.lock(|adjustments| adjustments.set([0.0; OUTPUT_CHANNEL_COUNT]));
+    }
+}
+
+/// The gain adjustment of an output channel by the second potentiometer in dB.
+pub fn adjustment_db(channel: usize) -> f32 {
+    ADJUSTMENTS_DB.lock(|adjustments| adjustments.get().get(channel).copied().unwrap_or_default())
+}
+
+/// The linear gain adjustments of all output channels by the second potentiometer.
+pub fn adjustment_gains() -> [f32; OUTPUT_CHANNEL_COUNT] {
+    ADJUSTMENTS_DB.lock(|adjustments| adjustments.get()).map(db_to_linear)
+}
+
+/// Update the gain adjustments with a position of the second potentiometer (out of 1, after the dead band).
+pub fn update_second(position: f32) {
+    // From -1 at the lower end stop to 1 at the upper one, and 0 within the center dead band.
+    let offset = 2.0 * position - 1.0;
+    let offset = if offset.abs() <= 2.0 * CENTER_DEAD_BAND {
+        0.0
+    } else {
+        (offset - 2.0 * CENTER_DEAD_BAND * offset.signum()) / (1.0 - 2.0 * CENTER_DEAD_BAND)
+    };
+
+    let adjustments_db = match second_target() {
+        SecondTarget::Off => return,
+        SecondTarget::Balance => core::array::from_fn(|channel| {
+            // Turning towards one side attenuates the other.
+            let left = LEFT_CHANNELS & (1 << channel) != 0;
+            let attenuation = if left { offset.max(0.0) } else { (-offset).max(0.0) };
+            -BALANCE_RANGE_DB * attenuation
+        }),
+        SecondTarget::Level(mask) => core::array::from_fn(|channel| {
+            if mask & (1 << channel) != 0 {
+                LEVEL_RANGE_DB * offset
+            } else {
+                0.0
+            }
+        }),
+    };
+
+    ADJUSTMENTS_DB.lock(|adjustments| adjustments.set(adjustments_db));
+}
+
+/// Apply the dead band to a position (out of 1), and stretch the rest to the full range.
+pub fn apply_dead_band(position: f32) -> f32 {
+    ((position - DEAD_BAND) / (1.0 - 2.0 * DEAD_BAND)).clamp(0.0, 1.0)
+}
+
+/// The attenuation of a position (out of 1) by the audio taper, in steps of 0.5 dB.
+pub fn attenuation(position: f32) -> u8 {
+    if position <= 0.0 {
+        return MUTED_ATTENUATION;
+    }
+
+    let attenuation_half_db = 2.0 * range_db() as f32 * (1.0 - position.min(1.0));
+    (attenuation_half_db + 0.5) as u8
+}
+
+/// A held position, which only follows changes beyond the hysteresis, and always the end stops.
+#[derive(Default)]
+pub struct Knob {
+    position: Option<f32>,
+}
+
+impl Knob {
+    /// Update the held position with a new position (out of 1), and return it.
+    pub fn update(&mut self, position: f32) -> f32 {
+        let end_stop = position <= 0.0 || position >= 1.0;
+
+        match self.position {
+            Some(held) if !end_stop && (position - held).abs() < HYSTERESIS => held,
+            _ => {
+                self.position = Some(position);
+                position
+            }
+        }
+    }
+}
@@ -0,0 +1,89 @@
+//! Early warning of a power loss by the programmable voltage detector (PVD), which saves the state in time.
+//!
+//! The PVD compares the microcontroller supply with [`PVD_LEVEL`], which lies below the regulated 3.3 V, but above
+//! the brown-out reset. When the supply falls below it, the PVD interrupt mutes the output (see
+//! [`crate::control::Control::gain`]), and wakes the [`power_fail_task`]. Since the rail collapses within
+//! milliseconds, the task only does what needs no erase (see [`crate::storage::erase_allowed`]):
+//! - It saves a changed configuration into a free settings slot, if there is one (see [`crate::settings_store`]).
+//! - It records the power failure, and writes all pending events (see [`crate::event_log`]).
+//! - It writes the current record of the thermal log (see [`crate::thermal_log`]).
+//!
+//! If the supply recovers instead (e.g. a short dip), the output plays again.
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use embassy_stm32::{interrupt, pac};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+use protocol::event_log::EventKind;
+
+use crate::event_log;
+use crate::settings_store;
+use crate::thermal_log;
+use crate::*;
+
+/// The PVD level selection (2.85 V).
+const PVD_LEVEL: u8 = 6;
+
+/// The EXTI line of the PVD output.
+const PVD_EXTI_LINE: usize = 16;
+
+/// Whether the supply is below the PVD level.
+static POWER_FAILING: AtomicBool = AtomicBool::new(false);
+
+/// Signal that is emitted when the supply falls below the PVD level (`true`), or recovers (`false`).
+static POWER_FAIL_SIGNAL: Signal<CriticalSectionRawMutex, bool> = Signal::new();
+
+/// Whether the supply is below the PVD level, such that a power loss is imminent.
+pub fn power_failing() -> bool {
+    POWER_FAILING.load(Ordering::Relaxed)
+}
+
+/// Set up the PVD, and its interrupt on both edges of its output.
+pub fn init() {
+    pac::PWR.cr1().modify(|w| {
+        w.set_pls(PVD_LEVEL);
+        w.set_pvde(true);
+    });
+
+    let exti = pac::EXTI;
+    exti.rtsr(0).modify(|w| w.set_line(PVD_EXTI_LINE, true));
+    exti.ftsr(0).modify(|w| w.set_line(PVD_EXTI_LINE, true));
+    exti.imr(0).modify(|w| w.set_line(PVD_EXTI_LINE, true));
+
+    unsafe {
+        cortex_m::peripheral::NVIC::unmask(interrupt::PVD_AVD);
+    }
+}
+
+#[interrupt]
+fn PVD_AVD() {
+    pac::EXTI.pr(0).write(|w| w.set_line(PVD_EXTI_LINE, true));
+
+    // The PVD output is set while the supply is below the level.
+    let failing = pac::PWR.csr1().read().pvdo();
+
+    if POWER_FAILING.swap(failing, Ordering::Relaxed) != failing {
+        POWER_FAIL_SIGNAL.signal(failing);
+    }
+}
+
+/// Saves the state on an imminent power loss.
+#[embassy_executor::task]
+pub async fn power_fail_task() {
+    loop {
+        if !POWER_FAIL_SIGNAL.wait().await {
+            log!(info, "Supply recovered from a power failure");
+            continue;
+        }
+
+        log!(warn, "Power failure, saving the state");
+
+        if let Err(error) = settings_store::save_without_erase() {
+            log!(warn, "Failed to save settings: {:?}", error);
+        }
+
+        event_log::record(EventKind::PowerFail, 0);
+        event_log::flush();
+        thermal_log::flush();
+    }
+}
@@ -0,0 +1,512 @@
+//! Named presets of the signal processing configuration, stored in flash, and selectable at runtime.
+//!
+//! The bank holds up to [`MAX_PRESET_COUNT`] presets, each in the format of [`protocol::settings`]. Loading a preset
+//! only replaces the signal processing configuration (see [`crate::dsp`]). Presets are saved, loaded, renamed, and
+//! deleted with the shell (`preset` commands) and SCPI (`PRESet` commands), and loaded in turn by buttons and remote
+//! codes that are mapped to the next preset.
+//!
+//! Besides its name, a preset holds notes (e.g. on the speakers it was tuned for), and its creation time, if the
+//! wall-clock time was set (see [`crate::clock`]).
+//!
+//! One preset may be marked as the boot default, which is loaded at startup, over the stored settings.
+//!
+//! The bank is kept in RAM, and written to its storage region as a whole on every modification (see
+//! [`crate::storage`]). Erasing the sector stalls playback for up to seconds.
+//!
+//! Layout of the bank: a header block with the index of the boot default (`0xFF` for none) and the bank version,
+//! followed by a slot per preset. A slot holds the settings length (`u16`, `0` or `0xFFFF` when empty), the name
+//! length (`u8`), the notes length (`u8`), the CRC-32 of all following fields (`u32`), the creation time as Unix time
+//! in seconds (`u32`, `0` when unknown), the name (padded to [`MAX_NAME_LENGTH`]), the notes (padded to
+//! [`MAX_NOTES_LENGTH`]), and the settings. Presets with a damaged slot are not loaded, and recorded in the event log
+//! (see [`crate::event_log`]). Banks of an older version hold no notes, and are discarded.
+use core::cell::RefCell;
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use grounded::uninit::GroundedArrayCell;
+use heapless::String;
+use protocol::crc::Crc32;
+use protocol::event_log::StoredData;
+use protocol::settings::{self as settings_format, MAX_ENCODED_SIZE};
+use static_assertions::const_assert;
+
+use crate::clock;
+use crate::dsp;
+use crate::event_log;
+use crate::settings;
+use crate::storage::{self, PRESETS_REGION, WRITE_BLOCK_SIZE};
+use crate::*;
+
+/// The maximum number of presets.
+pub const MAX_PRESET_COUNT: usize = 8;
+
+/// The maximum length of a preset name.
+pub const MAX_NAME_LENGTH: usize = 16;
+
+/// The maximum length of the notes of a preset.
+pub const MAX_NOTES_LENGTH: usize = 48;
+
+/// The name of a preset.
+pub type PresetName = String<MAX_NAME_LENGTH>;
+
+/// The notes of a preset.
+pub type PresetNotes = String<MAX_NOTES_LENGTH>;
+
+/// The name, notes, and creation time of a stored preset.
+#[derive(Clone, PartialEq, Debug)]
+pub struct PresetInfo {
+    /// The name.
+    pub name: PresetName,
+    /// The notes, e.g. on the speakers that the preset was tuned for.
+    pub notes: PresetNotes,
+    /// The creation time as Unix time in seconds, if the wall-clock time was set.
+    pub created: Option<u32>,
+}
+
+/// Marks that no preset is selected.
+const NO_PRESET: u8 = 0xFF;
+
+/// The version of the bank layout.
+const BANK_VERSION: u8 = 1;
+
+/// The size of the bank header.
+const BANK_HEADER_SIZE: usize = WRITE_BLOCK_SIZE;
+
+/// The offset of the name in a slot.
+const NAME_OFFSET: usize = 12;
+
+/// The offset of the notes in a slot.
+const NOTES_OFFSET: usize = NAME_OFFSET + MAX_NAME_LENGTH;
+
+/// The size of the slot header, before the settings.
+const SLOT_HEADER_SIZE: usize = NOTES_OFFSET + MAX_NOTES_LENGTH;
+
+/// The size of a slot, in whole write blocks.
+const SLOT_SIZE: usize = (SLOT_HEADER_SIZE + MAX_ENCODED_SIZE).div_ceil(WRITE_BLOCK_SIZE) * WRITE_BLOCK_SIZE;
+
+/// The size of the bank, as in backups.
+pub const BANK_SIZE: usize = BANK_HEADER_SIZE + MAX_PRESET_COUNT * SLOT_SIZE;
+
+const_assert!(BANK_SIZE as u32 <= PRESETS_REGION.size());
+
+/// An error of a preset operation.
+#[derive(Clone, Copy, PartialEq, Debug, defmt::Format)]
+pub enum Error {
+    /// The preset index is out of range.
+    InvalidIndex,
+    /// The name is empty, too long, or contains other than printable ASCII characters.
+    InvalidName,
+    /// The notes are too long, or contain other than printable ASCII characters and spaces.
+    InvalidNotes,
+    /// No preset is stored at the index.
+    NotFound,
+    /// The settings could not be encoded or decoded.
+    Format(settings_format::Error),
+    /// The signal processing configuration of the preset is invalid.
+    InvalidConfig,
+    /// A bank from a backup has another size or version.
+    InvalidBackup,
+    /// The storage reported an error.
+    Storage(storage::Error),
+}
+
+impl From<storage::Error> for Error {
+    fn from(error: storage::Error) -> Self {
+        Error::Storage(error)
+    }
+}
+
+impl From<settings_format::Error> for Error {
+    fn from(error: settings_format::Error) -> Self {
+        Error::Format(error)
+    }
+}
+
+/// The preset that was loaded last, or [`NO_PRESET`].
+static ACTIVE_PRESET: AtomicU8 = AtomicU8::new(NO_PRESET);
+
+/// The fields of a valid slot.
+struct Slot<'a> {
+    name: &'a str,
+    notes: &'a str,
+    created: u32,
+    settings: &'a [u8],
+}
+
+/// A copy of the stored bank.
+struct Bank {
+    /// Whether the image was read from flash.
+    loaded: bool,
+}
+
+static BANK: Mutex<ThreadModeRawMutex, RefCell<Bank>> = Mutex::new(RefCell::new(Bank { loaded: false }));
+
+/// The image of the bank, outside of the DTCM, which is too small for it. Only accessed through the [`BANK`].
+#[link_section = ".axisram"]
+static IMAGE: GroundedArrayCell<u8, BANK_SIZE> = GroundedArrayCell::uninit();
+
+impl Bank {
+    fn image(&self) -> &[u8] {
+        let (pointer, length) = IMAGE.get_ptr_len();
+        // SAFETY: There is only one bank, so its borrow rules apply to the image. The image is read from flash before
+        // it is used (see `load`).
+        unsafe { core::slice::from_raw_parts(pointer, length) }
+    }
+
+    fn image_mut(&mut self) -> &mut [u8] {
+        let (pointer, length) = IMAGE.get_ptr_len();
+        // SAFETY: See `image`.
+        unsafe { core::slice::from_raw_parts_mut(pointer, length) }
+    }
+
+    /// Read the image from flash, unless that happened before.
+    fn load(&mut self) -> Result<(), Error> {
+        if !self.loaded {
+            PRESETS_REGION.read(0, self.image_mut())?;
+            self.loaded = true;
+
+            if self.image()[1] != BANK_VERSION {
+                if self.image().iter().any(|byte| *byte != 0xFF) {
+                    log!(info, "Discarded presets of an older version");
+                }
+
+                self.image_mut().fill(0xFF);
+            }
+
+            if (0..MAX_PRESET_COUNT).any(|index| self.is_damaged(index)) {
+                event_log::record_corruption(StoredData::Presets);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Erase the region, and write the image.
+    fn store(&mut self) -> Result<(), Error> {
+        // The stored bank is unknown after a failure.
+        self.loaded = false;
+        self.image_mut()[1] = BANK_VERSION;
+
+        PRESETS_REGION.erase()?;
+        PRESETS_REGION.write(0, self.image())?;
+
+        self.loaded = true;
+        Ok(())
+    }
+
+    fn slot(&self, index: usize) -> &[u8] {
+        let offset = BANK_HEADER_SIZE + index * SLOT_SIZE;
+        &self.image()[offset..offset + SLOT_SIZE]
+    }
+
+    fn slot_mut(&mut self, index: usize) -> &mut [u8] {
+        let offset = BANK_HEADER_SIZE + index * SLOT_SIZE;
+        &mut self.image_mut()[offset..offset + SLOT_SIZE]
+    }
+
+    /// The fields of a valid preset.
+    fn preset(&self, index: usize) -> Option<Slot<'_>> {
+        let slot = self.slot(index);
+
+        let length = u16::from_le_bytes([slot[0], slot[1]]) as usize;
+        let name_length = slot[2] as usize;
+        let notes_length = slot[3] as usize;
+        let crc = u32::from_le_bytes([slot[4], slot[5], slot[6], slot[7]]);
+
+        if length == 0 || length > MAX_ENCODED_SIZE || name_length > MAX_NAME_LENGTH || notes_length > MAX_NOTES_LENGTH
+        {
+            return None;
+        }
+
+        if slot_crc(slot, length) != crc {
+            return None;
+        }
+
+        Some(Slot {
+            name: core::str::from_utf8(&slot[NAME_OFFSET..NAME_OFFSET + name_length]).ok()?,
+            notes: core::str::from_utf8(&slot[NOTES_OFFSET..NOTES_OFFSET + notes_length]).ok()?,
+            created: u32::from_le_bytes([slot[8], slot[9], slot[10], slot[11]]),
+            settings: &slot[SLOT_HEADER_SIZE..SLOT_HEADER_SIZE + length],
+        })
+    }
+
+    /// Write the header of a slot, whose settings of the given length are in place.
+    fn write_header(&mut self, index: usize, length: usize, created: u32, name: &str, notes: &str) {
+        let slot = self.slot_mut(index);
+
+        slot[NAME_OFFSET..SLOT_HEADER_SIZE].fill(0);
+        slot[NAME_OFFSET..NAME_OFFSET + name.len()].copy_from_slice(name.as_bytes());
+        slot[NOTES_OFFSET..NOTES_OFFSET + notes.len()].copy_from_slice(notes.as_bytes());
+
+        slot[0..2].copy_from_slice(&(length as u16).to_le_bytes());
+        slot[2] = name.len() as u8;
+        slot[3] = notes.len() as u8;
+        slot[8..12].copy_from_slice(&created.to_le_bytes());
+
+        let crc = slot_crc(slot, length);
+        slot[4..8].copy_from_slice(&crc.to_le_bytes());
+    }
+
+    /// Replace the name and notes of a stored preset, keeping its settings and creation time.
+    fn modify(&mut self, index: usize, modify: impl FnOnce(&mut PresetName, &mut PresetNotes)) -> Result<(), Error> {
+        let preset = self.preset(index).ok_or(Error::NotFound)?;
+
+        let length = preset.settings.len();
+        let created = preset.created;
+        let mut name = PresetName::try_from(preset.name).map_err(|_| Error::InvalidName)?;
+        let mut notes = PresetNotes::try_from(preset.notes).map_err(|_| Error::InvalidNotes)?;
+
+        modify(&mut name, &mut notes);
+        self.write_header(index, length, created, &name, &notes);
+        self.store()
+    }
+
+    /// Whether a slot is used, but does not hold a valid preset.
+    fn is_damaged(&self, index: usize) -> bool {
+        let slot = self.slot(index);
+        let length = u16::from_le_bytes([slot[0], slot[1]]);
+
+        length != 0 && length != 0xFFFF && self.preset(index).is_none()
+    }
+
+    /// The boot default preset.
+    fn default_preset(&self) -> Option<usize> {
+        Some(self.image()[0] as usize).filter(|index| *index < MAX_PRESET_COUNT)
+    }
+}
+
+/// The CRC-32 of a slot, from the creation time to the end of the settings.
+fn slot_crc(slot: &[u8], length: usize) -> u32 {
+    let mut crc = Crc32::new();
+    crc.update(&slot[8..SLOT_HEADER_SIZE + length]);
+    crc.finalize()
+}
+
+/// Run a function with the bank, once it is read from flash.
+fn with_bank<T>(function: impl FnOnce(&mut Bank) -> Result<T, Error>) -> Result<T, Error> {
+    BANK.lock(|bank| {
+        let mut bank = bank.borrow_mut();
+        bank.load()?;
+        function(&mut bank)
+    })
+}
+
+/// Check the index of a preset.
+fn check_index(index: usize) -> Result<(), Error> {
+    if index < MAX_PRESET_COUNT {
+        Ok(())
+    } else {
+        Err(Error::InvalidIndex)
+    }
+}
+
+/// Whether a name is valid for a preset.
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && name.len() <= MAX_NAME_LENGTH && name.bytes().all(|byte| byte.is_ascii_graphic())
+}
+
+/// Whether notes are valid for a preset.
+pub fn is_valid_notes(notes: &str) -> bool {
+    notes.len() <= MAX_NOTES_LENGTH && notes.bytes().all(|byte| byte.is_ascii_graphic() || byte == b' ')
+}
+
+/// The name of a stored preset.
+pub fn name(index: usize) -> Option<PresetName> {
+    info(index).map(|info| info.name)
+}
+
+/// The name, notes, and creation time of a stored preset.
+pub fn info(index: usize) -> Option<PresetInfo> {
+    check_index(index).ok()?;
+    with_bank(|bank| {
+        Ok(bank.preset(index).and_then(|preset| {
+            Some(PresetInfo {
+                name: PresetName::try_from(preset.name).ok()?,
+                notes: PresetNotes::try_from(preset.notes).ok()?,
+                created: Some(preset.created).filter(|created| *created != 0),
+            })
+        }))
+    })
+    .unwrap_or(None)
+}
+
+/// The preset that was loaded last.
+pub fn active() -> Option<usize> {
+    Some(ACTIVE_PRESET.load(Ordering::Relaxed) as usize).filter(|index| *index < MAX_PRESET_COUNT)
+}
+
+/// The boot default preset.
+pub fn default_preset() -> Option<usize> {
+    with_bank(|bank| Ok(bank.default_preset())).unwrap_or(None)
+}
+
+/// Mark a preset as the boot default, or none.
+pub fn set_default_preset(index: Option<usize>) -> Result<(), Error> {
+    with_bank(|bank| {
+        if let Some(index) = index {
+            check_index(index)?;
+            bank.preset(index).ok_or(Error::NotFound)?;
+        }
+
+        bank.image_mut()[0] = index.map_or(NO_PRESET, |index| index as u8);
+        bank.store()
+    })
+}
+
+/// Save the current signal processing configuration as a preset, replacing the one at the index.
+///
+/// The creation time is the wall-clock time, if it was set.
+pub fn save(index: usize, name: &str, notes: &str) -> Result<(), Error> {
+    check_index(index)?;
+
+    if !is_valid_name(name) {
+        return Err(Error::InvalidName);
+    }
+
+    if !is_valid_notes(notes) {
+        return Err(Error::InvalidNotes);
+    }
+
+    with_bank(|bank| {
+        let slot = bank.slot_mut(index);
+        slot.fill(0xFF);
+
+        let length = settings::encode(&mut slot[SLOT_HEADER_SIZE..SLOT_HEADER_SIZE + MAX_ENCODED_SIZE])?;
+
+        bank.write_header(index, length, clock::unix_time().unwrap_or(0), name, notes);
+        bank.store()
+    })?;
+
+    ACTIVE_PRESET.store(index as u8, Ordering::Relaxed);
+    log!(info, "Saved preset {}", index);
+
+    Ok(())
+}
+
+/// Rename a stored preset.
+pub fn rename(index: usize, name: &str) -> Result<(), Error> {
+    check_index(index)?;
+
+    let new_name = PresetName::try_from(name)
+        .ok()
+        .filter(|name| is_valid_name(name))
+        .ok_or(Error::InvalidName)?;
+    with_bank(|bank| bank.modify(index, |name, _| *name = new_name))
+}
+
+/// Replace the notes of a stored preset.
+pub fn set_notes(index: usize, notes: &str) -> Result<(), Error> {
+    check_index(index)?;
+
+    let new_notes = PresetNotes::try_from(notes)
+        .ok()
+        .filter(|notes| is_valid_notes(notes))
+        .ok_or(Error::InvalidNotes)?;
+    with_bank(|bank| bank.modify(index, |_, notes| *notes = new_notes))
+}
+
+/// Delete a preset. A deleted boot default is no longer loaded at startup.
+pub fn delete(index: usize) -> Result<(), Error> {
+    check_index(index)?;
+
+    with_bank(|bank| {
+        bank.preset(index).ok_or(Error::NotFound)?;
+        bank.slot_mut(index).fill(0xFF);
+
+        if bank.default_preset() == Some(index) {
+            bank.image_mut()[0] = NO_PRESET;
+        }
+
+        bank.store()
+    })?;
+
+    if active() == Some(index) {
+        ACTIVE_PRESET.store(NO_PRESET, Ordering::Relaxed);
+    }
+
+    Ok(())
+}
+
+/// Erase all presets, and the boot default.
+pub fn erase() -> Result<(), storage::Error> {
+    ACTIVE_PRESET.store(NO_PRESET, Ordering::Relaxed);
+
+    BANK.lock(|bank| {
+        bank.borrow_mut().loaded = false;
+        PRESETS_REGION.erase()
+    })
+}
+
+/// Load a preset, which replaces the signal processing configuration.
+pub fn load(index: usize) -> Result<(), Error> {
+    check_index(index)?;
+
+    let config = with_bank(|bank| {
+        let preset = bank.preset(index).ok_or(Error::NotFound)?;
+        Ok(settings::decode(preset.settings)?)
+    })?;
+
+    dsp::set_dsp_config(config.dsp).map_err(|_| Error::InvalidConfig)?;
+    ACTIVE_PRESET.store(index as u8, Ordering::Relaxed);
+
+    log!(info, "Loaded preset {}", index);
+    Ok(())
+}
+
+/// Load the stored preset that follows the active one, wrapping around.
+pub fn load_next() {
+    let start = active().map_or(0, |index| index + 1);
+    let next = with_bank(|bank| {
+        Ok((0..MAX_PRESET_COUNT)
+            .map(|offset| (start + offset) % MAX_PRESET_COUNT)
+            .find(|index| bank.preset(*index).is_some()))
+    });
+
+    match next {
+        Ok(Some(index)) => {
+            if let Err(error) = load(index) {
+                log!(warn, "Failed to load preset {}: {:?}", index, error);
+            }
+        }
+        Ok(None) => log!(info, "No presets stored"),
+        Err(error) => log!(warn, "Failed to read presets: {:?}", error),
+    }
+}
+
+/// Load the boot default preset, if any.
+pub fn load_default() -> Result<Option<usize>, Error> {
+    match with_bank(|bank| Ok(bank.default_preset()))? {
+        Some(index) => load(index).map(|()| Some(index)),
+        None => Ok(None),
+    }
+}
+
+/// Whether a bank from a backup can be restored.
+pub fn is_valid_backup(image: &[u8]) -> bool {
+    image.len() == BANK_SIZE && image[1] == BANK_VERSION
+}
+
+/// Copy the bank, for a backup.
+pub fn backup(image: &mut [u8; BANK_SIZE]) -> Result<(), Error> {
+    with_bank(|bank| {
+        image.copy_from_slice(bank.image());
+        image[1] = BANK_VERSION;
+        Ok(())
+    })
+}
+
+/// Replace the bank with one from a backup, and store it.
+pub fn restore_backup(image: &[u8]) -> Result<(), Error> {
+    if !is_valid_backup(image) {
+        return Err(Error::InvalidBackup);
+    }
+
+    ACTIVE_PRESET.store(NO_PRESET, Ordering::Relaxed);
+
+    BANK.lock(|bank| {
+        let mut bank = bank.borrow_mut();
+        bank.image_mut().copy_from_slice(image);
+        bank.store()
+    })
+}
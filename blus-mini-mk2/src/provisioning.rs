@@ -0,0 +1,106 @@
+//! Provisioning data of the device (see [`protocol::provisioning`]): the serial number, the hardware revision, and
+//! a device ID, which are written once during manufacturing.
+//!
+//! The data is restored at startup, before USB is set up, such that the serial number appears in the USB device
+//! descriptor. It also identifies the device in the device information, telemetry records, and `*IDN?`.
+//!
+//! The test station writes the data with the shell (`provision` command), which must be confirmed with the unique ID
+//! of the microcontroller. Once written, it can never be changed: writing is refused while data is stored, even
+//! damaged data.
+//!
+//! The data is kept in the OTP area of the flash memory (see [`crate::storage::PROVISIONING_OTP_REGION`]), which
+//! cannot be erased, such that neither erasing stored data, nor a power loss meanwhile can lose it.
+use core::cell::Cell;
+
+use embassy_stm32::uid;
+use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use protocol::device_info::UNIQUE_ID_SIZE;
+use protocol::event_log::StoredData;
+use protocol::provisioning::{self as provisioning_format, Provisioning, ENCODED_SIZE};
+use static_assertions::const_assert;
+
+use crate::event_log;
+use crate::storage::{self, OTP_WRITE_SIZE, PROVISIONING_OTP_REGION};
+use crate::*;
+
+/// The size of the stored provisioning data, in whole flash words of the OTP area.
+const STORED_SIZE: usize = ENCODED_SIZE.div_ceil(OTP_WRITE_SIZE) * OTP_WRITE_SIZE;
+
+const_assert!(STORED_SIZE as u32 <= PROVISIONING_OTP_REGION.size());
+
+/// An error of provisioning the device.
+#[derive(Clone, Copy, PartialEq, Debug, defmt::Format)]
+pub enum Error {
+    /// The provisioning data is invalid, or could not be decoded.
+    Format(provisioning_format::Error),
+    /// The confirmation does not match the unique ID of the microcontroller.
+    NotConfirmed,
+    /// The device was provisioned before.
+    AlreadyProvisioned,
+    /// The storage reported an error.
+    Storage(storage::Error),
+}
+
+impl From<storage::Error> for Error {
+    fn from(error: storage::Error) -> Self {
+        Error::Storage(error)
+    }
+}
+
+impl From<provisioning_format::Error> for Error {
+    fn from(error: provisioning_format::Error) -> Self {
+        Error::Format(error)
+    }
+}
+
+/// The restored provisioning data, unless the device is not provisioned.
+static PROVISIONING: Mutex<ThreadModeRawMutex, Cell<Option<Provisioning>>> = Mutex::new(Cell::new(None));
+
+/// Get the provisioning data, unless the device is not provisioned.
+pub fn provisioning() -> Option<Provisioning> {
+    PROVISIONING.lock(|provisioning| provisioning.get())
+}
+
+/// Read the stored provisioning data, as written.
+fn read_stored() -> Result<[u8; STORED_SIZE], storage::Error> {
+    let mut data = [0u8; STORED_SIZE];
+    PROVISIONING_OTP_REGION.read(0, &mut data)?;
+    Ok(data)
+}
+
+/// Restore the stored provisioning data. Damaged data is not restored, and recorded in the event log.
+pub fn restore() -> Result<(), Error> {
+    let data = read_stored()?;
+
+    let provisioning = Provisioning::decode(&data).inspect_err(|error| {
+        // An OTP area that was never programmed holds no provisioning data, instead of damaged data.
+        if !matches!(error, provisioning_format::Error::InvalidMagic) {
+            event_log::record_corruption(StoredData::Provisioning);
+        }
+    })?;
+
+    PROVISIONING.lock(|current| current.set(Some(provisioning)));
+    Ok(())
+}
+
+/// Write the provisioning data, confirmed by the unique ID of the microcontroller. Takes effect after a reboot for
+/// the USB device descriptor, and immediately everywhere else.
+pub fn provision(provisioning: Provisioning, confirmation: &[u8; UNIQUE_ID_SIZE]) -> Result<(), Error> {
+    if confirmation != uid::uid() {
+        return Err(Error::NotConfirmed);
+    }
+
+    if read_stored()?.iter().any(|byte| *byte != 0xFF) {
+        return Err(Error::AlreadyProvisioned);
+    }
+
+    let mut data = [0xFFu8; STORED_SIZE];
+    data[..ENCODED_SIZE].copy_from_slice(&provisioning.encode());
+    PROVISIONING_OTP_REGION.program(0, &data)?;
+
+    PROVISIONING.lock(|current| current.set(Some(provisioning)));
+
+    log!(info, "Provisioned serial number {}", provisioning.serial_number());
+    Ok(())
+}
@@ -0,0 +1,134 @@
+//! The control register map, as exposed by the I2C and SPI slave interfaces.
+//!
+//! Registers are one byte wide. Multi-byte accesses auto-increment the register address. Counters take four
+//! registers, little-endian, and may change between the reads of their bytes; hosts read them twice, until they match.
+use defmt::debug;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::channel::Channel;
+use protocol::parameter::{Parameter, Value};
+
+use crate::audio_routing::STATS;
+use crate::control::CONTROL;
+use crate::parameters::PARAMETERS;
+use crate::*;
+
+/// A register address.
+pub type RegisterAddress = u8;
+
+/// Device identification (read-only).
+pub const DEVICE_ID_REGISTER: RegisterAddress = 0x00;
+
+/// Version of the register map (read-only).
+pub const REGISTER_MAP_VERSION_REGISTER: RegisterAddress = 0x01;
+
+/// Device status, see [`control::status`] (read-only).
+pub const STATUS_REGISTER: RegisterAddress = 0x02;
+
+/// The currently playing source (read-only).
+pub const ACTIVE_SOURCE_REGISTER: RegisterAddress = 0x03;
+
+/// The selected source, where `AudioSource::None` selects automatically (read-write).
+pub const SOURCE_SELECT_REGISTER: RegisterAddress = 0x04;
+
+/// Master volume attenuation in steps of 0.5 dB (read-write).
+pub const VOLUME_REGISTER: RegisterAddress = 0x05;
+
+/// Master mute, non-zero values mute (read-write).
+pub const MUTE_REGISTER: RegisterAddress = 0x06;
+
+/// The first of the output meter level registers, one per output channel (read-only).
+///
+/// Levels are given as peak attenuation below full-scale in steps of 0.5 dB.
+pub const METER_LEVEL_REGISTER: RegisterAddress = 0x10;
+
+/// The first register of the number of amplifier SAI underruns, see [`audio_routing::Stats`] (read-only).
+pub const UNDERRUN_COUNT_REGISTER: RegisterAddress = 0x20;
+
+/// The first register of the number of overruns (read-only).
+pub const OVERRUN_COUNT_REGISTER: RegisterAddress = 0x24;
+
+/// The first register of the number of dropped sample blocks (read-only).
+pub const DROPPED_BLOCK_COUNT_REGISTER: RegisterAddress = 0x28;
+
+/// The first register of the number of SAI restarts (read-only).
+pub const SAI_RESTART_COUNT_REGISTER: RegisterAddress = 0x2C;
+
+/// The value of the device identification register.
+pub const DEVICE_ID: u8 = 0xB2;
+
+/// The version of the register map:
+/// - 1: Initial version.
+/// - 2: Audio routing counters.
+pub const REGISTER_MAP_VERSION: u8 = 2;
+
+/// The number of register writes that can be queued, before further writes are dropped.
+const REGISTER_WRITE_QUEUE_SIZE: usize = 16;
+
+/// Register writes, received by the slave interfaces' interrupt handlers.
+static REGISTER_WRITE_CHANNEL: Channel<CriticalSectionRawMutex, (RegisterAddress, u8), REGISTER_WRITE_QUEUE_SIZE> =
+    Channel::new();
+
+/// Read a register. Unknown registers read as zero.
+///
+/// Can be called from interrupt context.
+pub fn read(address: RegisterAddress) -> u8 {
+    const METER_LEVEL_END: RegisterAddress = METER_LEVEL_REGISTER + OUTPUT_CHANNEL_COUNT as u8;
+    const COUNTER_END: RegisterAddress = SAI_RESTART_COUNT_REGISTER + 4;
+
+    match address {
+        DEVICE_ID_REGISTER => DEVICE_ID,
+        REGISTER_MAP_VERSION_REGISTER => REGISTER_MAP_VERSION,
+        STATUS_REGISTER => CONTROL.status(),
+        ACTIVE_SOURCE_REGISTER => CONTROL.active_source() as u8,
+        SOURCE_SELECT_REGISTER => CONTROL.source_selection() as u8,
+        VOLUME_REGISTER => CONTROL.attenuation(),
+        MUTE_REGISTER => CONTROL.muted() as u8,
+        METER_LEVEL_REGISTER..METER_LEVEL_END => CONTROL.meter_level((address - METER_LEVEL_REGISTER) as usize),
+        UNDERRUN_COUNT_REGISTER..COUNTER_END => {
+            let count = match address & !0x03 {
+                UNDERRUN_COUNT_REGISTER => STATS.underrun_count(),
+                OVERRUN_COUNT_REGISTER => STATS.overrun_count(),
+                DROPPED_BLOCK_COUNT_REGISTER => STATS.dropped_block_count(),
+                _ => STATS.sai_restart_count(),
+            };
+
+            count.to_le_bytes()[(address & 0x03) as usize]
+        }
+        _ => 0,
+    }
+}
+
+/// Write a register. Writes to read-only, or unknown registers, and invalid values are ignored.
+pub fn write(address: RegisterAddress, value: u8) {
+    let (parameter, value) = match address {
+        SOURCE_SELECT_REGISTER => (Parameter::SourceSelect, Value::Integer(value as i32)),
+        VOLUME_REGISTER => (Parameter::Volume, Value::Integer(value as i32)),
+        MUTE_REGISTER => (Parameter::Mute, Value::Boolean(value != 0)),
+        _ => {
+            debug!("Registers: Ignore write to {:#x}", address);
+            return;
+        }
+    };
+
+    if PARAMETERS.set(parameter, value).is_err() {
+        debug!("Registers: Invalid value {} for {:#x}", value, address);
+    }
+}
+
+/// Queue a register write, to be applied by the [`register_write_task`].
+///
+/// Can be called from interrupt context.
+pub fn queue_write(address: RegisterAddress, value: u8) {
+    if REGISTER_WRITE_CHANNEL.try_send((address, value)).is_err() {
+        debug!("Registers: Write queue full, drop write to {:#x}", address);
+    }
+}
+
+/// Applies register writes that were received by the slave interfaces.
+#[embassy_executor::task]
+pub async fn register_write_task() {
+    loop {
+        let (address, value) = REGISTER_WRITE_CHANNEL.receive().await;
+        write(address, value);
+    }
+}
@@ -0,0 +1,243 @@
+//! Addressable RGB status LEDs (WS2812, or SK6812), as a chain on the SPI2 output (PB15).
+//!
+//! The LEDs show the device state, by priority:
+//! - Active faults (see [`crate::faults`]): red, blinking.
+//! - Standby: amber, breathing slowly.
+//! - A change of the master volume: the volume for [`VOLUME_DISPLAY_TIME`], as a white bar over the chain (or as the
+//!   brightness of a single LED).
+//! - Mute: the source color, dimmed.
+//! - Otherwise, the color of the active source: blue for USB, yellow for S/PDIF, and magenta for the Raspberry Pi.
+//!   White, while no source is active.
+//!
+//! The colors are dimmed with the other LEDs, also in night mode (see [`crate::led::current_brightness`]).
+//!
+//! The LEDs are driven by SPI with DMA, at [`SPI_FREQUENCY_HZ`]. Every data bit takes four SPI bits: `1000` for a
+//! zero (326 ns high), and `1100` for a one (651 ns high), which is within the timing of both kinds. Every frame ends
+//! with a low level of at least 300 µs, which latches the colors.
+//!
+//! The number of LEDs, and whether they have a white channel (SK6812 RGBW), are not stored with the settings; a
+//! startup script can configure them (shell `rgb`, see [`crate::startup_script`]). Without LEDs, the output stays low.
+use core::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+
+use embassy_stm32::mode::Async;
+use embassy_stm32::peripherals;
+use embassy_stm32::spi::{self, Spi};
+use embassy_stm32::time::Hertz;
+use embassy_time::{Duration, Instant, Ticker};
+
+use crate::cache;
+use crate::control::{CONTROL, MUTED_ATTENUATION};
+use crate::dma_buffers;
+use crate::faults;
+use crate::led;
+use crate::*;
+
+/// The maximum number of LEDs in the chain.
+pub const MAX_LED_COUNT: usize = 16;
+
+/// The SPI bit rate: the SPI kernel clock (24.576 MHz), divided by 8.
+pub const SPI_FREQUENCY_HZ: u32 = 3_072_000;
+
+/// The time that a change of the master volume is shown.
+pub const VOLUME_DISPLAY_TIME: Duration = Duration::from_secs(2);
+
+/// The rate of frames, which animates the LEDs.
+const FRAME_RATE_HZ: u64 = 50;
+
+/// The period of blinking on faults.
+const BLINK_PERIOD: Duration = Duration::from_millis(500);
+
+/// The period of breathing in standby.
+const BREATHING_PERIOD: Duration = Duration::from_secs(4);
+
+/// The master volume attenuation in steps of 0.5 dB, that shows as an empty bar (-60 dB).
+const BAR_ATTENUATION: u8 = 120;
+
+/// The number of color channels per LED, with a white channel.
+const MAX_CHANNEL_COUNT: usize = 4;
+
+/// The number of SPI bytes per color byte.
+const SPI_BYTES_PER_BYTE: usize = 4;
+
+/// The number of low SPI bytes that latch the colors (312 µs).
+const LATCH_SIZE: usize = 120;
+
+/// The size of the frame buffer.
+pub const FRAME_BUFFER_SIZE: usize = MAX_LED_COUNT * MAX_CHANNEL_COUNT * SPI_BYTES_PER_BYTE + LATCH_SIZE;
+
+/// The number of LEDs in the chain.
+static LED_COUNT: AtomicU8 = AtomicU8::new(0);
+
+/// Whether the LEDs have a white channel.
+static WHITE_CHANNEL: AtomicBool = AtomicBool::new(false);
+
+/// A color, as red, green, and blue intensities.
+#[derive(Clone, Copy, PartialEq, Debug)]
+struct Color(u8, u8, u8);
+
+impl Color {
+    const OFF: Color = Color(0, 0, 0);
+    const RED: Color = Color(255, 0, 0);
+    const AMBER: Color = Color(255, 100, 0);
+    const BLUE: Color = Color(0, 0, 255);
+    const YELLOW: Color = Color(255, 180, 0);
+    const MAGENTA: Color = Color(255, 0, 160);
+    const WHITE: Color = Color(255, 255, 255);
+
+    /// The color, scaled by a fraction (out of 1).
+    fn scaled(self, fraction: f32) -> Color {
+        let scale = |value: u8| (value as f32 * fraction.clamp(0.0, 1.0)) as u8;
+        Color(scale(self.0), scale(self.1), scale(self.2))
+    }
+}
+
+/// Resources that are required for the addressable LEDs.
+#[allow(missing_docs)]
+pub struct RgbLedResources {
+    pub spi: peripherals::SPI2,
+    pub mosi: peripherals::PB15,
+    pub dma: peripherals::DMA1_CH2,
+}
+
+/// The number of LEDs in the chain.
+pub fn led_count() -> usize {
+    LED_COUNT.load(Ordering::Relaxed) as usize
+}
+
+/// Whether the LEDs have a white channel.
+pub fn white_channel() -> bool {
+    WHITE_CHANNEL.load(Ordering::Relaxed)
+}
+
+/// Set the number of LEDs in the chain (up to [`MAX_LED_COUNT`], `0` without LEDs), and whether they have a white
+/// channel.
+pub fn set_leds(count: usize, white_channel: bool) {
+    LED_COUNT.store(count.min(MAX_LED_COUNT) as u8, Ordering::Relaxed);
+    WHITE_CHANNEL.store(white_channel, Ordering::Relaxed);
+}
+
+/// The position within a period of an animation, from 0 to 1.
+fn phase(period: Duration) -> f32 {
+    (Instant::now().as_millis() % period.as_millis()) as f32 / period.as_millis() as f32
+}
+
+/// The color of the active source.
+fn source_color() -> Color {
+    match CONTROL.active_source() {
+        AudioSource::Usb => Color::BLUE,
+        AudioSource::Spdif => Color::YELLOW,
+        AudioSource::Rpi => Color::MAGENTA,
+        _ => Color::WHITE,
+    }
+}
+
+/// The color of an LED in the chain, by the device state.
+fn color(index: usize, count: usize, volume_display: bool) -> Color {
+    if faults::active() != 0 {
+        return if phase(BLINK_PERIOD) < 0.5 {
+            Color::RED
+        } else {
+            Color::OFF
+        };
+    }
+
+    if CONTROL.standby() {
+        let level = 1.0 - (2.0 * phase(BREATHING_PERIOD) - 1.0).abs();
+        return Color::AMBER.scaled(0.1 + 0.9 * level);
+    }
+
+    if volume_display {
+        let attenuation = match CONTROL.attenuation() {
+            MUTED_ATTENUATION => BAR_ATTENUATION,
+            attenuation => attenuation.min(BAR_ATTENUATION),
+        };
+        let volume = 1.0 - attenuation as f32 / BAR_ATTENUATION as f32;
+
+        // A single LED shows the volume as its brightness.
+        if count == 1 {
+            return Color::WHITE.scaled(volume);
+        }
+
+        return if (index as f32) < (volume * count as f32).round() {
+            Color::WHITE
+        } else {
+            Color::OFF
+        };
+    }
+
+    if CONTROL.muted() {
+        return source_color().scaled(0.2);
+    }
+
+    source_color()
+}
+
+/// Encode a color byte into SPI bytes, two data bits per byte, most significant bit first.
+fn encode_byte(value: u8, output: &mut [u8]) {
+    for (index, byte) in output.iter_mut().enumerate().take(SPI_BYTES_PER_BYTE) {
+        let bits = value >> (6 - 2 * index);
+        let symbol = |bit: u8| if bit & 1 != 0 { 0b1100 } else { 0b1000 };
+        *byte = (symbol(bits >> 1) << 4) | symbol(bits);
+    }
+}
+
+/// Encode the colors of the chain into the frame buffer. Returns the length of the frame.
+fn encode_frame(buffer: &mut [u8], volume_display: bool) -> usize {
+    let count = led_count();
+    let channel_count = if white_channel() { 4 } else { 3 };
+
+    // The perceived brightness follows the intensity roughly by its square root.
+    let brightness = led::current_brightness() as f32 / 100.0;
+    let brightness = brightness * brightness;
+
+    let mut length = 0;
+    for index in 0..count {
+        let Color(red, green, blue) = color(index, count, volume_display).scaled(brightness);
+
+        // The LEDs take green first.
+        for value in [green, red, blue, 0].into_iter().take(channel_count) {
+            encode_byte(value, &mut buffer[length..length + SPI_BYTES_PER_BYTE]);
+            length += SPI_BYTES_PER_BYTE;
+        }
+    }
+
+    buffer[length..length + LATCH_SIZE].fill(0);
+    length + LATCH_SIZE
+}
+
+/// Shows the device state on the addressable LEDs.
+#[embassy_executor::task]
+pub async fn rgb_led_task(resources: RgbLedResources) {
+    let mut config = spi::Config::default();
+    config.frequency = Hertz(SPI_FREQUENCY_HZ);
+
+    let mut spi: Spi<'static, Async> = Spi::new_txonly_nosck(resources.spi, resources.mosi, resources.dma, config);
+
+    let buffer = dma_buffers::rgb_led_frame();
+
+    let mut ticker = Ticker::every(Duration::from_hz(FRAME_RATE_HZ));
+    let mut last_attenuation = CONTROL.attenuation();
+    let mut volume_changed: Option<Instant> = None;
+
+    loop {
+        ticker.next().await;
+
+        if led_count() == 0 {
+            continue;
+        }
+
+        let attenuation = CONTROL.attenuation();
+        if attenuation != last_attenuation {
+            last_attenuation = attenuation;
+            volume_changed = Some(Instant::now());
+        }
+
+        let volume_display = volume_changed.is_some_and(|changed| changed.elapsed() < VOLUME_DISPLAY_TIME);
+        let length = encode_frame(buffer, volume_display);
+        cache::clean(&buffer[..length]);
+
+        if let Err(error) = spi.write(&buffer[..length]).await {
+            log!(warn, "RGB LED: Failed to write a frame: {:?}", error);
+        }
+    }
+}
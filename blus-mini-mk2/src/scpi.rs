@@ -0,0 +1,493 @@
+//! An SCPI-like command set on the serial consoles, for scripting from lab tools and automated tests.
+//!
+//! Headers consist of mnemonics that are separated by colons, in their short (`SYST`) or long form (`SYSTem`),
+//! regardless of case. Queries end with `?`. Parameters follow the header after a space, separated by commas.
+//! Several commands on one line are separated by semicolons. Only queries respond, with their results on one line,
+//! separated by semicolons. Errors are queued, and read with `SYST:ERR?`.
+//!
+//! Commands:
+//! - `*IDN?`: The device identification, with the provisioned serial number (see [`crate::provisioning`]), or else
+//!   the unique ID.
+//! - `*CLS`: Clear the error queue.
+//! - `SYSTem:ERRor[:NEXT]?`: The oldest error, as code and description.
+//! - `SYSTem:VOLume <dB>`, `SYSTem:VOLume?`: The master volume, from -127 to 0 dB.
+//! - `SYSTem:MUTE ON|OFF`, `SYSTem:MUTE?`: The master mute.
+//! - `SYSTem:REBoot`: Restart the device.
+//! - `SYSTem:BOOTloader`: Restart into the system bootloader, for a firmware update.
+//! - `SYSTem:TIME <s>`, `SYSTem:TIME?`: The wall-clock time as Unix time in seconds, `0` when not set (see
+//!   [`crate::clock`]).
+//! - `SYSTem:HANDshake?`: The protocol version, settings format version, register map version, configuration
+//!   version, number of parameters, and feature bitmap (see [`protocol::handshake`]), e.g. `1.0,1.3,1,1,100,31`.
+//! - `INPut:SELect AUTO|USB|SPDIF|RPI`, `INPut:SELect?`: The source selection.
+//! - `INPut:ACTive?`: The source that is playing.
+//! - `MEASure:LEVel? [<channel>]`: The peak output level in dB of one channel, or of all channels.
+//! - `CHANnel<n>:GAIN <dB>`, `CHANnel<n>:GAIN?`: The gain of output channel `n`, counting from 1.
+//! - `CHANnel<n>:DELay <samples>`, `CHANnel<n>:DELay?`: The delay of output channel `n`.
+//! - `CHANnel<n>:INVert ON|OFF`, `CHANnel<n>:INVert?`: The polarity inversion of output channel `n`.
+//! - `PRESet:CATalog?`: The numbers of the stored presets, e.g. `1,3`.
+//! - `PRESet<n>:NAME "<name>"`, `PRESet<n>:NAME?`: The name of preset `n`, counting from 1.
+//! - `PRESet<n>:NOTes "<notes>"`, `PRESet<n>:NOTes?`: The notes of preset `n`, which may not contain commas.
+//! - `PRESet<n>:CREated?`: The creation time of preset `n` as Unix time in seconds, `0` when unknown.
+//! - `PRESet<n>:DELete`: Delete preset `n`.
+//!
+//! Levels without signal, and the volume when muted by attenuation, are reported as `-9.9E37` (negative infinity).
+use core::fmt::Write;
+
+use heapless::{Deque, String, Vec};
+use protocol::parameter::{Parameter, Value};
+
+use crate::clock;
+use crate::control::{self, CONTROL};
+use crate::device_info::{self, device_info};
+use crate::dsp;
+use crate::parameters::PARAMETERS;
+use crate::presets;
+use crate::provisioning;
+use crate::system::{self, RebootTarget};
+use crate::*;
+
+/// The maximum number of queued errors.
+const ERROR_QUEUE_LENGTH: usize = 8;
+
+/// The maximum number of mnemonics in a header.
+const MAX_MNEMONIC_COUNT: usize = 4;
+
+/// The maximum number of parameters of a command.
+const MAX_PARAMETER_COUNT: usize = 4;
+
+/// The SCPI representation of negative infinity.
+const NEGATIVE_INFINITY: &str = "-9.9E37";
+
+/// Errors, with their SCPI error codes.
+#[derive(Clone, Copy, PartialEq)]
+enum Error {
+    DataType = -104,
+    ParameterNotAllowed = -108,
+    MissingParameter = -109,
+    UndefinedHeader = -113,
+    HeaderSuffixOutOfRange = -114,
+    DataOutOfRange = -222,
+    IllegalParameterValue = -224,
+    Execution = -200,
+    QueueOverflow = -350,
+}
+
+impl Error {
+    fn description(&self) -> &'static str {
+        match self {
+            Error::DataType => "Data type error",
+            Error::ParameterNotAllowed => "Parameter not allowed",
+            Error::MissingParameter => "Missing parameter",
+            Error::UndefinedHeader => "Undefined header",
+            Error::HeaderSuffixOutOfRange => "Header suffix out of range",
+            Error::DataOutOfRange => "Data out of range",
+            Error::IllegalParameterValue => "Illegal parameter value",
+            Error::Execution => "Execution error",
+            Error::QueueOverflow => "Queue overflow",
+        }
+    }
+}
+
+/// A resolved command header.
+#[derive(Clone, Copy)]
+enum Header {
+    Identify,
+    ClearStatus,
+    Error,
+    Volume,
+    Mute,
+    Reboot,
+    Bootloader,
+    Handshake,
+    Select,
+    Active,
+    Level,
+    Gain(usize),
+    Delay(usize),
+    Invert(usize),
+    Time,
+    PresetCatalog,
+    PresetName(usize),
+    PresetNotes(usize),
+    PresetCreated(usize),
+    PresetDelete(usize),
+}
+
+/// Whether a line is meant as SCPI command, rather than a shell command.
+pub fn is_command(line: &str) -> bool {
+    let header = line.split_whitespace().next().unwrap_or_default();
+    header.starts_with('*') || header.contains(':') || header.ends_with('?')
+}
+
+/// Whether a mnemonic matches the short form (the uppercase part), or the long form of `long`.
+fn mnemonic_matches(mnemonic: &str, long: &str) -> bool {
+    let short_length = long.chars().take_while(|c| c.is_ascii_uppercase()).count();
+    mnemonic.eq_ignore_ascii_case(long) || mnemonic.eq_ignore_ascii_case(&long[..short_length])
+}
+
+/// Split a numeric suffix from a mnemonic (e.g. `CHAN2`).
+fn split_suffix(mnemonic: &str) -> (&str, Option<&str>) {
+    let split = mnemonic.trim_end_matches(|c: char| c.is_ascii_digit()).len();
+
+    match mnemonic.split_at(split) {
+        (name, "") => (name, None),
+        (name, suffix) => (name, Some(suffix)),
+    }
+}
+
+fn resolve(header: &str) -> Result<Header, Error> {
+    if header.starts_with('*') {
+        return match uppercase::<8>(header).as_deref() {
+            Some("*IDN") => Ok(Header::Identify),
+            Some("*CLS") => Ok(Header::ClearStatus),
+            _ => Err(Error::UndefinedHeader),
+        };
+    }
+
+    let mnemonics: Vec<&str, MAX_MNEMONIC_COUNT> =
+        split(header.trim_start_matches(':'), ':').ok_or(Error::UndefinedHeader)?;
+
+    let m = mnemonic_matches;
+
+    let header = match mnemonics.as_slice() {
+        [a, b] if m(a, "SYSTem") && m(b, "ERRor") => Header::Error,
+        [a, b, c] if m(a, "SYSTem") && m(b, "ERRor") && m(c, "NEXT") => Header::Error,
+        [a, b] if m(a, "SYSTem") && m(b, "VOLume") => Header::Volume,
+        [a, b] if m(a, "SYSTem") && m(b, "MUTE") => Header::Mute,
+        [a, b] if m(a, "SYSTem") && m(b, "REBoot") => Header::Reboot,
+        [a, b] if m(a, "SYSTem") && m(b, "BOOTloader") => Header::Bootloader,
+        [a, b] if m(a, "SYSTem") && m(b, "HANDshake") => Header::Handshake,
+        [a, b] if m(a, "SYSTem") && m(b, "TIME") => Header::Time,
+        [a, b] if m(a, "INPut") && m(b, "SELect") => Header::Select,
+        [a, b] if m(a, "INPut") && m(b, "ACTive") => Header::Active,
+        [a, b] if m(a, "MEASure") && m(b, "LEVel") => Header::Level,
+        [a, b] if m(split_suffix(a).0, "CHANnel") => {
+            let channel = match split_suffix(a).1 {
+                Some(suffix) => suffix.parse::<usize>().map_err(|_| Error::HeaderSuffixOutOfRange)?,
+                None => 1,
+            };
+
+            if !(1..=OUTPUT_CHANNEL_COUNT).contains(&channel) {
+                return Err(Error::HeaderSuffixOutOfRange);
+            }
+
+            let channel = channel - 1;
+
+            if m(b, "GAIN") {
+                Header::Gain(channel)
+            } else if m(b, "DELay") {
+                Header::Delay(channel)
+            } else if m(b, "INVert") {
+                Header::Invert(channel)
+            } else {
+                return Err(Error::UndefinedHeader);
+            }
+        }
+        [a, b] if m(a, "PRESet") && m(b, "CATalog") => Header::PresetCatalog,
+        [a, b] if m(split_suffix(a).0, "PRESet") => {
+            let index = match split_suffix(a).1 {
+                Some(suffix) => suffix.parse::<usize>().map_err(|_| Error::HeaderSuffixOutOfRange)?,
+                None => 1,
+            };
+
+            if !(1..=presets::MAX_PRESET_COUNT).contains(&index) {
+                return Err(Error::HeaderSuffixOutOfRange);
+            }
+
+            let index = index - 1;
+
+            if m(b, "NAME") {
+                Header::PresetName(index)
+            } else if m(b, "NOTes") {
+                Header::PresetNotes(index)
+            } else if m(b, "CREated") {
+                Header::PresetCreated(index)
+            } else if m(b, "DELete") {
+                Header::PresetDelete(index)
+            } else {
+                return Err(Error::UndefinedHeader);
+            }
+        }
+        _ => return Err(Error::UndefinedHeader),
+    };
+
+    Ok(header)
+}
+
+/// Convert text to uppercase, for matching keywords. Returns `None`, if the text is longer than `N`.
+fn uppercase<const N: usize>(text: &str) -> Option<String<N>> {
+    let mut uppercase = String::new();
+
+    for c in text.chars() {
+        uppercase.push(c.to_ascii_uppercase()).ok()?;
+    }
+
+    Some(uppercase)
+}
+
+/// Split text at a separator into trimmed parts. Returns `None`, if there are more than `N` parts.
+fn split<const N: usize>(text: &str, separator: char) -> Option<Vec<&str, N>> {
+    let mut parts = Vec::new();
+
+    for part in text.split(separator) {
+        parts.push(part.trim()).ok()?;
+    }
+
+    Some(parts)
+}
+
+fn parse_bool(parameter: &str) -> Result<bool, Error> {
+    match uppercase::<4>(parameter).as_deref() {
+        Some("ON" | "1") => Ok(true),
+        Some("OFF" | "0") => Ok(false),
+        _ => Err(Error::IllegalParameterValue),
+    }
+}
+
+/// Parse a string parameter, which is enclosed in double quotes.
+fn parse_string(parameter: &str) -> Result<&str, Error> {
+    parameter
+        .strip_prefix('"')
+        .and_then(|parameter| parameter.strip_suffix('"'))
+        .ok_or(Error::DataType)
+}
+
+fn parse_number<T: core::str::FromStr>(parameter: &str) -> Result<T, Error> {
+    parameter.parse().map_err(|_| Error::DataType)
+}
+
+/// Write a level in steps of 0.5 dB below full-scale, in dB.
+fn write_level(response: &mut impl Write, attenuation_half_db: u8) {
+    if attenuation_half_db == control::MUTED_ATTENUATION {
+        _ = response.write_str(NEGATIVE_INFINITY);
+    } else {
+        _ = write!(response, "{:.1}", -(attenuation_half_db as f32) / 2.0);
+    }
+}
+
+/// The SCPI command interpreter, with its error queue.
+#[derive(Default)]
+pub struct Scpi {
+    errors: Deque<Error, ERROR_QUEUE_LENGTH>,
+}
+
+impl Scpi {
+    /// Create an interpreter with an empty error queue.
+    pub const fn new() -> Self {
+        Scpi { errors: Deque::new() }
+    }
+
+    fn push_error(&mut self, error: Error) {
+        if self.errors.is_full() {
+            // The newest error is replaced by the overflow.
+            self.errors.pop_back();
+            _ = self.errors.push_back(Error::QueueOverflow);
+        } else {
+            _ = self.errors.push_back(error);
+        }
+    }
+
+    /// Execute a line of commands, and write the responses of queries.
+    ///
+    /// Processing of the line ends at the first failing command.
+    pub fn execute(&mut self, line: &str, response: &mut impl Write) {
+        let mut first = true;
+
+        for command in line.split(';').map(str::trim).filter(|command| !command.is_empty()) {
+            let mut command_response: String<64> = String::new();
+
+            if let Err(error) = self.execute_command(command, &mut command_response) {
+                self.push_error(error);
+                break;
+            }
+
+            if !command_response.is_empty() {
+                if !first {
+                    _ = response.write_char(';');
+                }
+
+                _ = response.write_str(&command_response);
+                first = false;
+            }
+        }
+    }
+
+    fn execute_command(&mut self, command: &str, response: &mut impl Write) -> Result<(), Error> {
+        let (header, parameters) = command.split_once(char::is_whitespace).unwrap_or((command, ""));
+        let (header, query) = match header.strip_suffix('?') {
+            Some(header) => (header, true),
+            None => (header, false),
+        };
+
+        let parameters: Vec<&str, MAX_PARAMETER_COUNT> = match parameters.trim() {
+            "" => Vec::new(),
+            parameters => split(parameters, ',').ok_or(Error::ParameterNotAllowed)?,
+        };
+
+        // The single parameter of a setting.
+        let value = || match parameters.as_slice() {
+            [value] => Ok(*value),
+            [] => Err(Error::MissingParameter),
+            _ => Err(Error::ParameterNotAllowed),
+        };
+
+        let header = resolve(header)?;
+
+        if query && !matches!(header, Header::Level) && !parameters.is_empty() {
+            return Err(Error::ParameterNotAllowed);
+        }
+
+        match (header, query) {
+            (Header::Identify, true) => {
+                _ = write!(response, "elagil,blus-mini mk2,");
+                match provisioning::provisioning() {
+                    Some(provisioning) => _ = write!(response, "{}", provisioning.serial_number()),
+                    None => {
+                        for byte in device_info().unique_id {
+                            _ = write!(response, "{:02x}", byte);
+                        }
+                    }
+                }
+                _ = write!(response, ",{}", env!("CARGO_PKG_VERSION"));
+            }
+            (Header::ClearStatus, false) => self.errors.clear(),
+            (Header::Error, true) => match self.errors.pop_front() {
+                Some(error) => _ = write!(response, "{},\"{}\"", error as i16, error.description()),
+                None => _ = write!(response, "0,\"No error\""),
+            },
+            (Header::Volume, true) => write_level(response, CONTROL.attenuation()),
+            (Header::Volume, false) => {
+                let volume_db: f32 = parse_number(value()?)?;
+
+                if !(-127.0..=0.0).contains(&volume_db) {
+                    return Err(Error::DataOutOfRange);
+                }
+
+                set(Parameter::Volume, Value::Integer((-volume_db * 2.0) as i32))?;
+            }
+            (Header::Mute, true) => _ = write!(response, "{}", CONTROL.muted() as u8),
+            (Header::Mute, false) => set(Parameter::Mute, Value::Boolean(parse_bool(value()?)?))?,
+            (Header::Reboot, false) => system::request_reboot(RebootTarget::Firmware),
+            (Header::Bootloader, false) => system::request_reboot(RebootTarget::Bootloader),
+            (Header::Handshake, true) => {
+                let handshake = device_info::handshake();
+                let [protocol_major, protocol_minor] = handshake.protocol_version;
+                let [settings_major, settings_minor] = handshake.settings_version;
+
+                _ = write!(
+                    response,
+                    "{}.{},{}.{},{},{},{},{}",
+                    protocol_major,
+                    protocol_minor,
+                    settings_major,
+                    settings_minor,
+                    handshake.register_map_version,
+                    handshake.config_version,
+                    handshake.parameter_count,
+                    handshake.features
+                );
+            }
+            (Header::Select, true) => {
+                let name = control::source_selection_name(CONTROL.source_selection());
+                _ = write!(response, "{}", uppercase::<8>(name).unwrap_or_default());
+            }
+            (Header::Select, false) => {
+                let mut name: String<8> = String::try_from(value()?).map_err(|_| Error::IllegalParameterValue)?;
+                name.as_mut_str().make_ascii_lowercase();
+
+                let selection = control::parse_source_selection(&name).ok_or(Error::IllegalParameterValue)?;
+
+                set(Parameter::SourceSelect, Value::Integer(selection as i32))?;
+            }
+            (Header::Active, true) => {
+                let name = control::source_name(CONTROL.active_source());
+                _ = write!(response, "{}", uppercase::<8>(name).unwrap_or_default());
+            }
+            (Header::Level, true) => match parameters.as_slice() {
+                [] => {
+                    for channel in 0..OUTPUT_CHANNEL_COUNT {
+                        if channel > 0 {
+                            _ = response.write_char(',');
+                        }
+
+                        write_level(response, CONTROL.meter_level(channel));
+                    }
+                }
+                [channel] => {
+                    let channel: usize = parse_number(channel)?;
+
+                    if !(1..=OUTPUT_CHANNEL_COUNT).contains(&channel) {
+                        return Err(Error::DataOutOfRange);
+                    }
+
+                    write_level(response, CONTROL.meter_level(channel - 1));
+                }
+                _ => return Err(Error::ParameterNotAllowed),
+            },
+            (Header::Gain(channel), true) => _ = write!(response, "{:.2}", dsp::dsp_config()[channel].gain_db),
+            (Header::Gain(channel), false) => {
+                let gain_db: f32 = parse_number(value()?)?;
+                set(Parameter::ChannelGain { channel: channel as u8 }, Value::Float(gain_db))?;
+            }
+            (Header::Delay(channel), true) => _ = write!(response, "{}", dsp::dsp_config()[channel].delay),
+            (Header::Delay(channel), false) => {
+                let delay: i32 = parse_number(value()?)?;
+                set(
+                    Parameter::ChannelDelay { channel: channel as u8 },
+                    Value::Integer(delay),
+                )?;
+            }
+            (Header::Invert(channel), true) => _ = write!(response, "{}", dsp::dsp_config()[channel].inverted as u8),
+            (Header::Invert(channel), false) => {
+                let inverted = parse_bool(value()?)?;
+                set(
+                    Parameter::ChannelInverted { channel: channel as u8 },
+                    Value::Boolean(inverted),
+                )?;
+            }
+            (Header::Time, true) => _ = write!(response, "{}", clock::unix_time().unwrap_or(0)),
+            (Header::Time, false) => clock::set_unix_time(parse_number(value()?)?),
+            (Header::PresetCatalog, true) => {
+                let mut first = true;
+
+                for index in (0..presets::MAX_PRESET_COUNT).filter(|index| presets::name(*index).is_some()) {
+                    _ = write!(response, "{}{}", if first { "" } else { "," }, index + 1);
+                    first = false;
+                }
+            }
+            (Header::PresetName(index), true) => {
+                let info = presets::info(index).ok_or(Error::Execution)?;
+                _ = write!(response, "\"{}\"", info.name);
+            }
+            (Header::PresetName(index), false) => preset_result(presets::rename(index, parse_string(value()?)?))?,
+            (Header::PresetNotes(index), true) => {
+                let info = presets::info(index).ok_or(Error::Execution)?;
+                _ = write!(response, "\"{}\"", info.notes);
+            }
+            (Header::PresetNotes(index), false) => {
+                preset_result(presets::set_notes(index, parse_string(value()?)?))?;
+            }
+            (Header::PresetCreated(index), true) => {
+                let info = presets::info(index).ok_or(Error::Execution)?;
+                _ = write!(response, "{}", info.created.unwrap_or(0));
+            }
+            (Header::PresetDelete(index), false) => preset_result(presets::delete(index))?,
+            _ => return Err(Error::UndefinedHeader),
+        }
+
+        Ok(())
+    }
+}
+
+/// Set a parameter in the parameter store. Invalid values are rejected as out of range.
+fn set(parameter: Parameter, value: Value) -> Result<(), Error> {
+    PARAMETERS.set(parameter, value).map_err(|_| Error::DataOutOfRange)
+}
+
+/// Map the result of a preset operation. Invalid names and notes are illegal values, other errors fail the execution.
+fn preset_result(result: Result<(), presets::Error>) -> Result<(), Error> {
+    result.map_err(|error| match error {
+        presets::Error::InvalidName | presets::Error::InvalidNotes => Error::IllegalParameterValue,
+        _ => Error::Execution,
+    })
+}
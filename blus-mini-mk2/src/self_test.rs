@@ -0,0 +1,348 @@
+//! The factory self-test, which plays a tone on every output channel in turn, and measures it through a loopback.
+//!
+//! The test fixture digitizes the amplifier outputs, and feeds them back into the Raspberry Pi header: the left
+//! speaker (channels 0 and 1) in the left, the right speaker (channels 2 and 3) in the right slot. Every channel plays
+//! a sine of [`TONE_FREQUENCY_HZ`] at [`TONE_LEVEL_DB`], without signal processing or volume. After [`SETTLE_TIME`],
+//! [`ANALYSIS_FRAME_COUNT`] frames are analyzed (by Goertzel filters at the harmonics of the tone), and checked for:
+//! - channel assignment: the tone must be in the slot of the channel, and at most [`MAX_CROSSTALK_DB`] in the other.
+//! - level: the tone must be within the level limits in dBFS (the fixture determines the gain of the loopback).
+//! - distortion: the THD (up to the [`HARMONIC_COUNT`]th harmonic) must be below its limit.
+//!
+//! The test is started with the shell (`self-test start`), which reports the result per channel (`self-test`). It
+//! takes over the audio routing, which plays no source meanwhile, and requires the master mute and standby to be off.
+//! The limits are not stored with the settings; a startup script can configure them (shell `self-test limits`, see
+//! [`crate::startup_script`]).
+use core::cell::Cell;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use audio::audio_filter::{sample_to_f32, sample_to_u32};
+use embassy_futures::join::join;
+use embassy_stm32::{peripherals, sai};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_time::Duration;
+
+use crate::amplifiers;
+use crate::control::CONTROL;
+use crate::watchdog::{self, Task};
+use crate::*;
+
+/// The frequency of the test tone, a whole number of cycles over the analysis.
+pub const TONE_FREQUENCY_HZ: u32 = 1_000;
+
+/// The peak level of the test tone in dBFS.
+pub const TONE_LEVEL_DB: f32 = -12.0;
+
+/// The time that every channel plays, before the analysis starts.
+pub const SETTLE_TIME: Duration = Duration::from_millis(300);
+
+/// The number of analyzed frames per channel (100 ms).
+pub const ANALYSIS_FRAME_COUNT: usize = 4_800;
+
+/// The number of analyzed harmonics, including the fundamental.
+pub const HARMONIC_COUNT: usize = 5;
+
+/// The maximum level of the tone in the slot of the other speaker, relative to the slot of the channel.
+pub const MAX_CROSSTALK_DB: f32 = -30.0;
+
+/// The loopback slot of every output channel.
+const LOOPBACK_SLOTS: [usize; OUTPUT_CHANNEL_COUNT] = [0, 0, 1, 1];
+
+/// The number of frames per block.
+const FRAME_COUNT: usize = DEFAULT_SAMPLE_COUNT / INPUT_CHANNEL_COUNT;
+
+/// Whether a test is requested.
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// The limits of the checks.
+static LIMITS: Mutex<CriticalSectionRawMutex, Cell<Limits>> = Mutex::new(Cell::new(Limits::new()));
+
+/// The state of the test.
+static STATE: Mutex<CriticalSectionRawMutex, Cell<State>> = Mutex::new(Cell::new(State::Idle));
+
+/// The results of the last test.
+static RESULTS: Mutex<CriticalSectionRawMutex, Cell<[Option<ChannelResult>; OUTPUT_CHANNEL_COUNT]>> =
+    Mutex::new(Cell::new([None; OUTPUT_CHANNEL_COUNT]));
+
+/// The limits of the checks.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Limits {
+    /// The minimum level of the tone in the loopback, in dBFS.
+    pub min_level_db: f32,
+    /// The maximum level of the tone in the loopback, in dBFS.
+    pub max_level_db: f32,
+    /// The maximum THD in percent.
+    pub max_thd_percent: f32,
+}
+
+impl Limits {
+    /// The default limits: a loopback within 12 dB of the tone level, and at most 1 % THD.
+    pub const fn new() -> Self {
+        Limits {
+            min_level_db: TONE_LEVEL_DB - 12.0,
+            max_level_db: TONE_LEVEL_DB + 12.0,
+            max_thd_percent: 1.0,
+        }
+    }
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The state of the test.
+#[derive(Clone, Copy, PartialEq, Debug, defmt::Format)]
+pub enum State {
+    /// No test ran since startup.
+    Idle,
+    /// The test is requested, or runs on a channel.
+    Running(u8),
+    /// The test completed, see [`results`].
+    Done,
+    /// The test was aborted by an error of the audio interfaces.
+    Aborted,
+}
+
+/// The verdict of a channel, by the first failed check.
+#[derive(Clone, Copy, PartialEq, Debug, defmt::Format)]
+pub enum Verdict {
+    /// All checks passed.
+    Pass,
+    /// The tone is not in the slot of the channel, or also in the other.
+    Assignment,
+    /// The level of the tone is out of limits.
+    Level,
+    /// The THD exceeds its limit.
+    Distortion,
+}
+
+/// The measurements and verdict of a channel.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct ChannelResult {
+    /// The level of the tone in the slot of the channel, in dBFS.
+    pub level_db: f32,
+    /// The level of the tone in the other slot, relative to the slot of the channel.
+    pub crosstalk_db: f32,
+    /// The THD in percent.
+    pub thd_percent: f32,
+    /// The verdict.
+    pub verdict: Verdict,
+}
+
+/// An error of starting the test.
+#[derive(Clone, Copy, PartialEq, Debug, defmt::Format)]
+pub enum Error {
+    /// A test is running.
+    Busy,
+    /// The output is muted or in standby.
+    Silent,
+}
+
+/// The name of a verdict, as used by the text interfaces.
+pub fn verdict_name(verdict: Verdict) -> &'static str {
+    match verdict {
+        Verdict::Pass => "pass",
+        Verdict::Assignment => "fail (assignment)",
+        Verdict::Level => "fail (level)",
+        Verdict::Distortion => "fail (distortion)",
+    }
+}
+
+/// The limits of the checks.
+pub fn limits() -> Limits {
+    LIMITS.lock(|limits| limits.get())
+}
+
+/// Set the limits of the checks.
+pub fn set_limits(limits: Limits) {
+    LIMITS.lock(|current| current.set(limits));
+}
+
+/// The state of the test.
+pub fn state() -> State {
+    STATE.lock(|state| state.get())
+}
+
+/// The results of the last test, by channel (`None` for channels that were not measured).
+pub fn results() -> [Option<ChannelResult>; OUTPUT_CHANNEL_COUNT] {
+    RESULTS.lock(|results| results.get())
+}
+
+/// Request a test, which the audio routing runs, once it is idle.
+pub fn start() -> Result<(), Error> {
+    if matches!(state(), State::Running(_)) {
+        return Err(Error::Busy);
+    }
+
+    if CONTROL.muted() || CONTROL.standby() {
+        return Err(Error::Silent);
+    }
+
+    RESULTS.lock(|results| results.set([None; OUTPUT_CHANNEL_COUNT]));
+    STATE.lock(|state| state.set(State::Running(0)));
+    REQUESTED.store(true, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Whether a test is requested. The audio routing then releases its source, and runs it (see [`run`]).
+pub fn requested() -> bool {
+    REQUESTED.load(Ordering::Relaxed)
+}
+
+/// A Goertzel filter, which measures the amplitude of a single frequency.
+struct Goertzel {
+    coefficient: f32,
+    s1: f32,
+    s2: f32,
+}
+
+impl Goertzel {
+    fn new(frequency_hz: u32) -> Self {
+        let omega = 2.0 * core::f32::consts::PI * frequency_hz as f32 / SAMPLE_RATE_HZ as f32;
+
+        Goertzel {
+            coefficient: 2.0 * omega.cos(),
+            s1: 0.0,
+            s2: 0.0,
+        }
+    }
+
+    fn run(&mut self, sample: f32) {
+        let s0 = sample + self.coefficient * self.s1 - self.s2;
+        self.s2 = self.s1;
+        self.s1 = s0;
+    }
+
+    /// The peak amplitude after `count` samples.
+    fn amplitude(&self, count: usize) -> f32 {
+        let power = self.s1 * self.s1 + self.s2 * self.s2 - self.coefficient * self.s1 * self.s2;
+        2.0 * power.max(0.0).sqrt() / count as f32
+    }
+}
+
+/// Evaluate the measurements of a channel.
+fn evaluate(harmonics: &[Goertzel; HARMONIC_COUNT], other: &Goertzel, limits: &Limits) -> ChannelResult {
+    // Avoids infinite levels in silence.
+    let fundamental = harmonics[0].amplitude(ANALYSIS_FRAME_COUNT).max(1e-9);
+    let distortion = harmonics[1..]
+        .iter()
+        .map(|harmonic| {
+            let amplitude = harmonic.amplitude(ANALYSIS_FRAME_COUNT);
+            amplitude * amplitude
+        })
+        .sum::<f32>()
+        .sqrt();
+
+    let level_db = 20.0 * fundamental.log10();
+    let crosstalk_db = 20.0 * (other.amplitude(ANALYSIS_FRAME_COUNT).max(1e-9) / fundamental).log10();
+    let thd_percent = 100.0 * distortion / fundamental;
+
+    let verdict = if crosstalk_db > MAX_CROSSTALK_DB {
+        Verdict::Assignment
+    } else if level_db < limits.min_level_db || level_db > limits.max_level_db {
+        Verdict::Level
+    } else if thd_percent > limits.max_thd_percent {
+        Verdict::Distortion
+    } else {
+        Verdict::Pass
+    };
+
+    ChannelResult {
+        level_db,
+        crosstalk_db,
+        thd_percent,
+        verdict,
+    }
+}
+
+/// Run the requested test on the audio interfaces, which must be configured for a 32 bit source, and are to be reset
+/// afterwards.
+pub async fn run(
+    sai_amp: &mut sai::Sai<'_, peripherals::SAI4, u32>,
+    sai_rpi: &mut sai::Sai<'_, peripherals::SAI4, u32>,
+) {
+    REQUESTED.store(false, Ordering::Relaxed);
+    log!(info, "Self-test started");
+
+    amplifiers::request(AudioSource::Rpi);
+    amplifiers::settled().await;
+
+    let limits = limits();
+    let amplitude = db_to_linear(TONE_LEVEL_DB);
+    let omega = 2.0 * core::f32::consts::PI * TONE_FREQUENCY_HZ as f32 / SAMPLE_RATE_HZ as f32;
+    let (step_cos, step_sin) = (omega.cos(), omega.sin());
+    let settle_frame_count = (SETTLE_TIME.as_millis() * SAMPLE_RATE_HZ as u64 / 1000) as usize;
+    let mut aborted = false;
+
+    'channels: for channel in 0..OUTPUT_CHANNEL_COUNT {
+        STATE.lock(|state| state.set(State::Running(channel as u8)));
+
+        let slot = LOOPBACK_SLOTS[channel];
+        let mut harmonics: [Goertzel; HARMONIC_COUNT] =
+            core::array::from_fn(|index| Goertzel::new(TONE_FREQUENCY_HZ * (index as u32 + 1)));
+        let mut other = Goertzel::new(TONE_FREQUENCY_HZ);
+
+        // The tone as a rotating phasor, which is free of the approximation errors of the sine function.
+        let (mut cos, mut sin) = (1.0f32, 0.0f32);
+        let mut frame_index = 0;
+
+        while frame_index < settle_frame_count + ANALYSIS_FRAME_COUNT {
+            watchdog::check_in(Task::Audio);
+
+            let mut tone: Vec<u32, MAX_OUTPUT_SAMPLE_COUNT> = Vec::new();
+            for _ in 0..FRAME_COUNT {
+                (cos, sin) = (cos * step_cos - sin * step_sin, sin * step_cos + cos * step_sin);
+
+                for output in 0..OUTPUT_CHANNEL_COUNT {
+                    let sample = if output == channel { amplitude * sin } else { 0.0 };
+                    tone.push(sample_to_u32(sample)).unwrap();
+                }
+            }
+
+            // Keeps the amplitude of the phasor from drifting.
+            let magnitude = (cos * cos + sin * sin).sqrt();
+            (cos, sin) = (cos / magnitude, sin / magnitude);
+
+            let mut loopback = [0u32; DEFAULT_SAMPLE_COUNT];
+            let (write_result, read_result) = join(sai_amp.write(&tone), sai_rpi.read(&mut loopback)).await;
+
+            if write_result.is_err() || read_result.is_err() {
+                log!(warn, "Self-test aborted by an audio interface error");
+                aborted = true;
+                break 'channels;
+            }
+
+            for frame in loopback.chunks_exact(INPUT_CHANNEL_COUNT) {
+                if (settle_frame_count..settle_frame_count + ANALYSIS_FRAME_COUNT).contains(&frame_index) {
+                    let sample = sample_to_f32(frame[slot]);
+                    harmonics.iter_mut().for_each(|harmonic| harmonic.run(sample));
+                    other.run(sample_to_f32(frame[1 - slot]));
+                }
+
+                frame_index += 1;
+            }
+        }
+
+        let result = evaluate(&harmonics, &other, &limits);
+        log!(
+            info,
+            "Self-test channel {}: {:?}, {} dBFS, {} dB crosstalk, {} % THD",
+            channel,
+            result.verdict,
+            result.level_db,
+            result.crosstalk_db,
+            result.thd_percent
+        );
+
+        RESULTS.lock(|results| {
+            let mut current = results.get();
+            current[channel] = Some(result);
+            results.set(current);
+        });
+    }
+
+    amplifiers::request(AudioSource::None);
+    STATE.lock(|state| state.set(if aborted { State::Aborted } else { State::Done }));
+}
@@ -0,0 +1,249 @@
+//! The device configuration, and its conversion to the binary settings format of [`protocol::settings`].
+use audio::filter_config::{
+    ConfigError, Crossover, CrossoverKind, CrossoverOrder, FilterConfig, LimiterConfig, StageConfig,
+};
+use protocol::button::ButtonMap;
+use protocol::ir::IrCodes;
+use protocol::led::LedMap;
+use protocol::settings::{
+    self, ChannelSettings, CrossoverSettings, LimiterSettings, Settings, StageSettings, SOURCE_COUNT,
+};
+use tas2780::tas2780::Gain;
+
+use crate::amplifiers;
+use crate::button;
+use crate::control::CONTROL;
+use crate::dsp::{self, DspConfig};
+use crate::ir_remote;
+use crate::led;
+use crate::parameters::{stage_kind, stage_type};
+use crate::supply;
+use crate::trigger::{self, TriggerMode};
+use crate::*;
+
+/// The device configuration, as represented by settings documents.
+#[derive(Clone)]
+pub struct DeviceConfig {
+    /// The master attenuation in steps of 0.5 dB.
+    pub attenuation: u8,
+    /// The master mute.
+    pub muted: bool,
+    /// The source selection.
+    pub source_selection: AudioSource,
+    /// The mode of the trigger output.
+    pub trigger_mode: TriggerMode,
+    /// The filter configuration of all output channels.
+    pub dsp: DspConfig,
+    /// The remote codes of all actions.
+    pub ir_codes: IrCodes,
+    /// The actions of all buttons.
+    pub buttons: ButtonMap,
+    /// Whether the master volume is remembered per source.
+    pub source_volume: bool,
+    /// The remembered attenuation of every source.
+    pub source_attenuations: [u8; SOURCE_COUNT],
+    /// The supply voltage in mV, below which the amplifiers are shut down, or `0` for no threshold.
+    pub supply_threshold_mv: u16,
+    /// The analog gain of the amplifiers.
+    pub amplifier_gain: Gain,
+    /// The LEDs of all functions.
+    pub leds: LedMap,
+}
+
+impl DeviceConfig {
+    /// Get the currently active configuration.
+    pub fn current() -> Self {
+        DeviceConfig {
+            attenuation: CONTROL.attenuation(),
+            muted: CONTROL.muted(),
+            source_selection: CONTROL.source_selection(),
+            trigger_mode: trigger::trigger_mode(),
+            dsp: dsp::dsp_config(),
+            ir_codes: ir_remote::ir_codes(),
+            buttons: button::button_map(),
+            source_volume: CONTROL.source_volume(),
+            source_attenuations: CONTROL.source_attenuations(),
+            supply_threshold_mv: supply::threshold_mv(),
+            amplifier_gain: amplifiers::gain(),
+            leds: led::led_map(),
+        }
+    }
+
+    /// Make the configuration active.
+    pub fn apply(self) -> Result<(), ConfigError> {
+        dsp::set_dsp_config(self.dsp)?;
+
+        CONTROL.set_attenuation(self.attenuation);
+        CONTROL.set_muted(self.muted);
+        CONTROL.set_source_selection(self.source_selection);
+        trigger::set_trigger_mode(self.trigger_mode);
+        ir_remote::set_ir_codes(self.ir_codes);
+        button::set_button_map(self.buttons);
+        supply::set_threshold_mv(self.supply_threshold_mv);
+        amplifiers::set_gain(self.amplifier_gain);
+        led::set_led_map(self.leds);
+
+        // After the master volume, such that setting it does not overwrite the remembered volumes.
+        CONTROL.set_source_attenuations(self.source_attenuations);
+        CONTROL.set_source_volume(self.source_volume);
+
+        Ok(())
+    }
+
+    /// Convert the configuration to binary settings.
+    pub fn to_settings(&self) -> Settings {
+        let mut settings = Settings::new();
+
+        settings.attenuation = self.attenuation;
+        settings.muted = self.muted;
+        settings.source_selection = self.source_selection as u8;
+        settings.trigger_mode = self.trigger_mode as u8;
+        settings.ir_codes = self.ir_codes;
+        settings.buttons = self.buttons;
+        settings.source_volume = self.source_volume;
+        settings.source_attenuations = self.source_attenuations;
+        settings.supply_threshold_mv = self.supply_threshold_mv;
+        settings.amplifier_gain = self.amplifier_gain as u8;
+        settings.leds = self.leds;
+
+        for (channel, config) in settings.channels.iter_mut().zip(self.dsp.iter()) {
+            *channel = channel_settings(config);
+        }
+
+        settings
+    }
+
+    /// Convert binary settings to a configuration, without validating it.
+    ///
+    /// Unknown sources fall back to automatic source selection, unknown trigger modes to following standby, and unknown
+    /// amplifier gains to the default gain.
+    pub fn from_settings(settings: &Settings) -> Self {
+        let mut dsp = DspConfig::new();
+
+        for (config, channel) in dsp.iter_mut().zip(settings.channels.iter()) {
+            *config = filter_config(channel);
+        }
+
+        DeviceConfig {
+            attenuation: settings.attenuation,
+            muted: settings.muted,
+            source_selection: AudioSource::try_from(settings.source_selection).unwrap_or(AudioSource::None),
+            trigger_mode: TriggerMode::try_from(settings.trigger_mode).unwrap_or(TriggerMode::Standby),
+            dsp,
+            ir_codes: settings.ir_codes,
+            buttons: settings.buttons,
+            source_volume: settings.source_volume,
+            source_attenuations: settings.source_attenuations,
+            supply_threshold_mv: settings.supply_threshold_mv,
+            amplifier_gain: Gain::try_from(settings.amplifier_gain).unwrap_or(amplifiers::DEFAULT_GAIN),
+            leds: settings.leds,
+        }
+    }
+}
+
+fn channel_settings(config: &FilterConfig) -> ChannelSettings {
+    let mut channel = ChannelSettings::new();
+
+    channel.gain_db = config.gain_db;
+    channel.inverted = config.inverted;
+    channel.delay = config.delay as u32;
+
+    for stage in config.stages.iter() {
+        let values = match *stage {
+            StageConfig::Parametric {
+                frequency_hz,
+                q,
+                gain_db,
+                ..
+            } => [frequency_hz, q, gain_db, 0.0, 0.0],
+            StageConfig::Coefficients { b0, b1, b2, a1, a2 } => [b0, b1, b2, a1, a2],
+        };
+
+        // Cannot fail, both have the same stage capacity.
+        _ = channel.push_stage(StageSettings {
+            stage_type: stage_type(stage),
+            values,
+        });
+    }
+
+    channel.crossover = config.crossover.map(|crossover| CrossoverSettings {
+        high_pass: crossover.kind == CrossoverKind::HighPass,
+        order: match crossover.order {
+            CrossoverOrder::Lr2 => 2,
+            CrossoverOrder::Lr4 => 4,
+        },
+        frequency_hz: crossover.frequency_hz,
+    });
+    channel.limiter = config.limiter.map(|limiter| LimiterSettings {
+        threshold_db: limiter.threshold_db,
+        release_ms: limiter.release_ms,
+    });
+
+    channel
+}
+
+fn filter_config(channel: &ChannelSettings) -> FilterConfig {
+    let mut config = FilterConfig::new();
+
+    config.gain_db = channel.gain_db;
+    config.inverted = channel.inverted;
+    config.delay = channel.delay as usize;
+
+    for stage in channel.stages() {
+        let [v0, v1, v2, v3, v4] = stage.values;
+
+        let stage_config = match stage_kind(stage.stage_type) {
+            Some(kind) => StageConfig::Parametric {
+                kind,
+                frequency_hz: v0,
+                q: v1,
+                gain_db: v2,
+            },
+            None => StageConfig::Coefficients {
+                b0: v0,
+                b1: v1,
+                b2: v2,
+                a1: v3,
+                a2: v4,
+            },
+        };
+
+        // Cannot fail, both have the same stage capacity.
+        _ = config.stages.push(stage_config);
+    }
+
+    config.crossover = channel.crossover.map(|crossover| Crossover {
+        kind: if crossover.high_pass {
+            CrossoverKind::HighPass
+        } else {
+            CrossoverKind::LowPass
+        },
+        // Other orders are not supported, and fall back to the steepest.
+        order: match crossover.order {
+            2 => CrossoverOrder::Lr2,
+            _ => CrossoverOrder::Lr4,
+        },
+        frequency_hz: crossover.frequency_hz,
+    });
+    config.limiter = channel.limiter.map(|limiter| LimiterConfig {
+        threshold_db: limiter.threshold_db,
+        release_ms: limiter.release_ms,
+    });
+
+    config
+}
+
+/// Encode the current configuration, and return the encoded length.
+pub fn encode(buffer: &mut [u8]) -> Result<usize, settings::Error> {
+    DeviceConfig::current().to_settings().encode(buffer)
+}
+
+/// Decode binary settings on top of the current configuration.
+///
+/// The result is not validated, which happens when it is applied.
+pub fn decode(data: &[u8]) -> Result<DeviceConfig, settings::Error> {
+    let mut settings = DeviceConfig::current().to_settings();
+    settings.decode(data)?;
+
+    Ok(DeviceConfig::from_settings(&settings))
+}
@@ -0,0 +1,397 @@
+//! Persistent device settings in flash, which are restored at startup.
+//!
+//! The device configuration (volume, mute, source selection, signal processing, remote codes, buttons, trigger mode,
+//! supply threshold, and LED functions) is stored in the format of [`protocol::settings`], in one of two storage
+//! regions (see [`crate::storage`]). Every save appends a slot to the active region, such that a region is only erased
+//! after many saves. Once the active region is full, the other region is erased, and becomes the active one. Slots
+//! carry a sequence number, and at startup, the valid slot with the highest number is restored. A save that is
+//! interrupted by a reset leaves the previous slot intact.
+//!
+//! Every slot is protected by a CRC. Damaged slots (e.g. by a power loss while writing) are never interpreted: the
+//! newest intact slot is restored instead, or the defaults stay in effect, if there is none. Damage of the newest save
+//! is logged, and recorded in the event log (see [`crate::event_log`]).
+//!
+//! Settings are saved on request (shell `save`), and automatically, once the configuration is unchanged for the
+//! autosave delay ([`DEFAULT_AUTOSAVE_DELAY`], shell `autosave`). Waiting for a quiet period saves flash wear, since
+//! e.g. turning the volume knob changes the configuration many times in a row. A configuration that keeps changing is
+//! saved after [`MAX_AUTOSAVE_DEFERRAL`] at the latest, such that little is lost on power-off. Erasing stalls playback
+//! for up to seconds, so automatic saves that require an erase wait until playback stops (standby, or no active
+//! source). Automatic saves also wait during the audition of a configuration slot (see [`crate::config_slots`]).
+use core::cell::RefCell;
+use core::sync::atomic::{AtomicU16, Ordering};
+
+use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_time::{Duration, Instant, Ticker};
+use protocol::crc::crc32;
+use protocol::event_log::StoredData;
+use protocol::settings::{self as settings_format, MAX_ENCODED_SIZE};
+
+use crate::config_slots::{self, Slot};
+use crate::event_log;
+use crate::settings::{self, DeviceConfig};
+use crate::storage::{self, SETTINGS_REGIONS, WRITE_BLOCK_SIZE};
+use crate::*;
+
+/// The default time without configuration changes, after which the configuration is saved.
+pub const DEFAULT_AUTOSAVE_DELAY: Duration = Duration::from_secs(5);
+
+/// The maximum autosave delay.
+pub const MAX_AUTOSAVE_DELAY: Duration = Duration::from_secs(600);
+
+/// The maximum time, for which a changed configuration stays unsaved, while it keeps changing.
+pub const MAX_AUTOSAVE_DEFERRAL: Duration = Duration::from_secs(60);
+
+/// The interval between checks for configuration changes.
+const CHECK_INTERVAL: Duration = Duration::from_millis(500);
+
+/// The autosave delay in seconds. Zero disables automatic saving.
+static AUTOSAVE_DELAY_S: AtomicU16 = AtomicU16::new(DEFAULT_AUTOSAVE_DELAY.as_secs() as u16);
+
+/// Marks a slot.
+const MAGIC: u32 = 0x5345_5453;
+
+/// The size of the slot header: magic number (`u32`), sequence number (`u32`), settings length (`u32`),
+/// and the CRC-32 of the settings (`u32`).
+const HEADER_SIZE: usize = 16;
+
+/// The maximum size of a slot, in whole write blocks.
+const MAX_SLOT_SIZE: usize = (HEADER_SIZE + MAX_ENCODED_SIZE).div_ceil(WRITE_BLOCK_SIZE) * WRITE_BLOCK_SIZE;
+
+/// An error of saving or restoring settings.
+#[derive(Clone, Copy, PartialEq, Debug, defmt::Format)]
+pub enum Error {
+    /// No valid settings are stored.
+    NotFound,
+    /// The settings could not be encoded or decoded.
+    Format(settings_format::Error),
+    /// The stored configuration is invalid.
+    InvalidConfig,
+    /// Saving requires erasing a region, which was not allowed.
+    EraseRequired,
+    /// The storage reported an error.
+    Storage(storage::Error),
+}
+
+impl From<storage::Error> for Error {
+    fn from(error: storage::Error) -> Self {
+        Error::Storage(error)
+    }
+}
+
+impl From<settings_format::Error> for Error {
+    fn from(error: settings_format::Error) -> Self {
+        Error::Format(error)
+    }
+}
+
+/// A valid slot.
+#[derive(Clone, Copy)]
+struct StoreSlot {
+    /// The index of the region in [`SETTINGS_REGIONS`].
+    region: usize,
+    /// The offset within the region.
+    offset: u32,
+    /// The sequence number.
+    sequence: u32,
+    /// The length of the settings.
+    length: usize,
+    /// The CRC-32 of the settings.
+    crc: u32,
+}
+
+/// The state of both regions.
+#[derive(Clone, Copy)]
+struct Scan {
+    /// The newest valid slot.
+    latest: Option<StoreSlot>,
+    /// The used size of each region.
+    used: [u32; SETTINGS_REGIONS.len()],
+    /// Whether a slot that is newer than [`Scan::latest`] is damaged, such that the newest save was lost.
+    damaged: bool,
+}
+
+/// The state of the store, with a buffer for a slot.
+struct Store {
+    /// The state of both regions, once scanned.
+    scan: Option<Scan>,
+    buffer: [u8; MAX_SLOT_SIZE],
+}
+
+/// The only instance of the store.
+static STORE: Mutex<ThreadModeRawMutex, RefCell<Store>> = Mutex::new(RefCell::new(Store {
+    scan: None,
+    buffer: [0; MAX_SLOT_SIZE],
+}));
+
+/// The size of a slot in flash, for settings of a given length.
+fn slot_size(length: usize) -> u32 {
+    ((HEADER_SIZE + length).div_ceil(WRITE_BLOCK_SIZE) * WRITE_BLOCK_SIZE) as u32
+}
+
+impl Store {
+    /// Find the newest slot, and the used size of both regions, unless that happened before.
+    ///
+    /// A region ends at the first erased slot header. A damaged header ends it as well, such that it counts as full.
+    /// A slot with a damaged payload is skipped.
+    fn scan(&mut self) -> Result<Scan, Error> {
+        if let Some(scan) = self.scan {
+            return Ok(scan);
+        }
+
+        let mut scan = Scan {
+            latest: None,
+            used: [0; SETTINGS_REGIONS.len()],
+            damaged: false,
+        };
+
+        // The highest sequence number of a slot with a damaged payload, and the regions that end in a damaged header.
+        let mut damaged_sequence = None;
+        let mut damaged_header = [false; SETTINGS_REGIONS.len()];
+
+        for (index, region) in SETTINGS_REGIONS.iter().enumerate() {
+            let mut offset = 0;
+
+            while offset + HEADER_SIZE as u32 <= region.size() {
+                let header = &mut self.buffer[..HEADER_SIZE];
+                region.read(offset, header)?;
+
+                if header.iter().all(|byte| *byte == 0xFF) {
+                    break;
+                }
+
+                let field = |index: usize| u32::from_le_bytes(header[4 * index..4 * index + 4].try_into().unwrap());
+                let (magic, sequence, length, crc) = (field(0), field(1), field(2) as usize, field(3));
+
+                if magic != MAGIC || length > MAX_ENCODED_SIZE || offset + slot_size(length) > region.size() {
+                    damaged_header[index] = true;
+                    offset = region.size();
+                    break;
+                }
+
+                let payload = &mut self.buffer[HEADER_SIZE..HEADER_SIZE + length];
+                region.read(offset + HEADER_SIZE as u32, payload)?;
+
+                if crc32(payload) != crc {
+                    damaged_sequence = damaged_sequence.max(Some(sequence));
+                } else if scan.latest.is_none_or(|latest| sequence > latest.sequence) {
+                    scan.latest = Some(StoreSlot {
+                        region: index,
+                        offset,
+                        sequence,
+                        length,
+                        crc,
+                    });
+                }
+
+                offset += slot_size(length);
+            }
+
+            scan.used[index] = offset;
+        }
+
+        // A damaged header ends the region, so it is newer than all slots before it.
+        scan.damaged = match scan.latest {
+            Some(latest) => damaged_sequence > Some(latest.sequence) || damaged_header[latest.region],
+            None => damaged_sequence.is_some() || damaged_header.contains(&true),
+        };
+
+        self.scan = Some(scan);
+        Ok(scan)
+    }
+
+    /// Encode the current configuration into the payload of the buffer. Returns its length, and its CRC-32.
+    fn encode_current(&mut self) -> Result<(usize, u32), Error> {
+        let length = settings::encode(&mut self.buffer[HEADER_SIZE..])?;
+        Ok((length, crc32(&self.buffer[HEADER_SIZE..HEADER_SIZE + length])))
+    }
+
+    /// Save the current configuration, unless it equals the stored one.
+    ///
+    /// Without `allow_erase`, fails with [`Error::EraseRequired`] instead of erasing a region.
+    fn save(&mut self, allow_erase: bool) -> Result<(), Error> {
+        let mut scan = self.scan()?;
+        let (length, crc) = self.encode_current()?;
+
+        if scan
+            .latest
+            .is_some_and(|latest| latest.length == length && latest.crc == crc)
+        {
+            return Ok(());
+        }
+
+        let size = slot_size(length);
+        let (mut region, sequence) = match scan.latest {
+            Some(latest) => (latest.region, latest.sequence.wrapping_add(1)),
+            None => (0, 0),
+        };
+
+        if scan.used[region] + size > SETTINGS_REGIONS[region].size() {
+            if !allow_erase {
+                return Err(Error::EraseRequired);
+            }
+
+            region = (region + 1) % SETTINGS_REGIONS.len();
+
+            // The region is only used again after a successful erase.
+            scan.used[region] = SETTINGS_REGIONS[region].size();
+            self.scan = Some(scan);
+
+            SETTINGS_REGIONS[region].erase()?;
+            scan.used[region] = 0;
+        }
+
+        let offset = scan.used[region];
+
+        self.buffer[0..4].copy_from_slice(&MAGIC.to_le_bytes());
+        self.buffer[4..8].copy_from_slice(&sequence.to_le_bytes());
+        self.buffer[8..12].copy_from_slice(&(length as u32).to_le_bytes());
+        self.buffer[12..16].copy_from_slice(&crc.to_le_bytes());
+        self.buffer[HEADER_SIZE + length..size as usize].fill(0xFF);
+
+        // A failed write may leave a damaged slot, so the region counts as used up to its end.
+        scan.used[region] = offset + size;
+        self.scan = Some(scan);
+
+        SETTINGS_REGIONS[region].write(offset, &self.buffer[..size as usize])?;
+
+        scan.latest = Some(StoreSlot {
+            region,
+            offset,
+            sequence,
+            length,
+            crc,
+        });
+        self.scan = Some(scan);
+
+        log!(info, "Saved settings ({} byte)", length);
+        Ok(())
+    }
+
+    /// Load the stored configuration.
+    fn load(&mut self) -> Result<DeviceConfig, Error> {
+        let slot = self.scan()?.latest.ok_or(Error::NotFound)?;
+        let payload = &mut self.buffer[HEADER_SIZE..HEADER_SIZE + slot.length];
+
+        SETTINGS_REGIONS[slot.region].read(slot.offset + HEADER_SIZE as u32, payload)?;
+        Ok(settings::decode(payload)?)
+    }
+}
+
+/// Save the current configuration.
+pub fn save() -> Result<(), Error> {
+    STORE.lock(|store| store.borrow_mut().save(true))
+}
+
+/// Save the current configuration, if that does not require erasing a sector (e.g. before a power loss).
+pub fn save_without_erase() -> Result<(), Error> {
+    STORE.lock(|store| store.borrow_mut().save(false))
+}
+
+/// Erase the stored settings.
+pub fn erase() -> Result<(), storage::Error> {
+    STORE.lock(|store| {
+        let mut store = store.borrow_mut();
+        store.scan = None;
+
+        SETTINGS_REGIONS.iter().try_for_each(|region| region.erase())
+    })
+}
+
+/// Restore the stored configuration. Missing parts keep their current values.
+///
+/// Damaged settings are not restored, and recorded in the event log.
+pub fn restore() -> Result<(), Error> {
+    let (config, damaged) = STORE.lock(|store| {
+        let mut store = store.borrow_mut();
+        let config = store.load();
+        (config, store.scan.is_some_and(|scan| scan.damaged))
+    });
+
+    // Intact slots hold intact settings, unless written by incompatible firmware.
+    let corrupted = matches!(config, Err(Error::Format(error)) if error != settings_format::Error::IncompatibleVersion);
+
+    if damaged || corrupted {
+        event_log::record_corruption(StoredData::Settings);
+    }
+
+    config?.apply().map_err(|_| Error::InvalidConfig)
+}
+
+/// The CRC-32 of the current configuration, as it would be saved.
+fn current_crc() -> Result<u32, Error> {
+    STORE.lock(|store| store.borrow_mut().encode_current().map(|(_, crc)| crc))
+}
+
+/// The autosave delay, or `None`, if automatic saving is disabled.
+pub fn autosave_delay() -> Option<Duration> {
+    match AUTOSAVE_DELAY_S.load(Ordering::Relaxed) {
+        0 => None,
+        delay_s => Some(Duration::from_secs(delay_s as u64)),
+    }
+}
+
+/// Set the autosave delay (up to [`MAX_AUTOSAVE_DELAY`]), or disable automatic saving with `None`.
+pub fn set_autosave_delay(delay: Option<Duration>) {
+    let delay_s = delay.map_or(0, |delay| delay.min(MAX_AUTOSAVE_DELAY).as_secs().max(1));
+    AUTOSAVE_DELAY_S.store(delay_s as u16, Ordering::Relaxed);
+}
+
+/// Saves the configuration automatically, once it is unchanged for the autosave delay.
+#[embassy_executor::task]
+pub async fn settings_task() {
+    let mut ticker = Ticker::every(CHECK_INTERVAL);
+
+    let mut saved_crc = current_crc().ok();
+    let mut last_crc = saved_crc;
+    let mut changed_at = Instant::now();
+    // The time of the first change since the last save.
+    let mut unsaved_since = None;
+
+    loop {
+        ticker.next().await;
+
+        let crc = current_crc().ok();
+
+        if crc != last_crc {
+            last_crc = crc;
+            changed_at = Instant::now();
+            unsaved_since.get_or_insert(changed_at);
+        }
+
+        if crc == saved_crc {
+            unsaved_since = None;
+            continue;
+        }
+
+        let Some(delay) = autosave_delay() else {
+            continue;
+        };
+
+        // Slot A stays stored during an audition of slot B.
+        if config_slots::active_slot() == Slot::B {
+            continue;
+        }
+
+        let deferred = unsaved_since.is_some_and(|since: Instant| since.elapsed() >= MAX_AUTOSAVE_DEFERRAL);
+
+        if changed_at.elapsed() < delay && !deferred {
+            continue;
+        }
+
+        match STORE.lock(|store| store.borrow_mut().save(storage::erase_allowed())) {
+            Ok(()) => {
+                saved_crc = crc;
+                unsaved_since = None;
+            }
+            Err(Error::EraseRequired) => (),
+            Err(error) => {
+                log!(warn, "Failed to save settings: {:?}", error);
+
+                // Retry after the next delay.
+                changed_at = Instant::now();
+                unsaved_since = None;
+            }
+        }
+    }
+}
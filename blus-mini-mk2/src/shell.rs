@@ -0,0 +1,2293 @@
+//! An interactive command shell, for configuring the device from any terminal program.
+//!
+//! Supports line editing with backspace, `Ctrl-C` (discard line), `Ctrl-U` (erase line),
+//! and recalling the previous command with the up-arrow key.
+//!
+//! The device configuration can be exported and imported as a JSON document (see [`crate::config_json`]).
+//! For importing, the document is pasted after the `config import` command, and terminated by an empty line
+//! or `Ctrl-D`.
+//!
+//! Lines that look like SCPI commands (e.g. `SYST:VOL -20.5`) are handled by [`crate::scpi`]. For scripting,
+//! `echo off` disables the echo of input and the prompt.
+//!
+//! After `notify on`, changes of global parameters are printed as they happen (see [`crate::notifications`]),
+//! as lines of `EVENT <name> <value>` (e.g. `EVENT volume -20.5`).
+//!
+//! Host tools start with `handshake`, which prints the protocol versions and features as a single line of JSON, e.g.:
+//!
+//! ```text
+//! {"protocol":[1,0],"settings":[1,4],"registers":1,"config":1,"parameters":100,"features":["reboot","telemetry"]}
+//! ```
+use core::fmt::{self, Write as _};
+
+use audio::filter_config::{StageConfig, StageKind};
+use embassy_time::{Duration, Instant};
+use embedded_io_async::Write;
+use heapless::{String, Vec};
+use protocol::button::{Press, BUTTON_COUNT};
+use protocol::calibration::{TAPER_POINT_COUNT, TEMPERATURE_SENSOR_COUNT};
+use protocol::device_info::UNIQUE_ID_SIZE;
+use protocol::hid::SlotAction;
+use protocol::ir::IrAction;
+use protocol::led::{LedFunction, LedOutput, EXPANDER_LED_COUNT, MCU_LED_COUNT};
+use protocol::parameter::{Parameter, Value};
+use protocol::provisioning::Provisioning;
+use protocol::settings::NO_SOURCE_ATTENUATION;
+
+use crate::amplifiers;
+use crate::audio_routing::STATS;
+use crate::auto_standby;
+use crate::button;
+use crate::calibration;
+use crate::clip_protection;
+use crate::clock;
+use crate::config_json;
+use crate::config_slots;
+use crate::console::{self, Level};
+use crate::control::{self, CONTROL};
+use crate::cpu_load;
+use crate::device_info::{self, device_info};
+use crate::display;
+use crate::dsp;
+use crate::event_log;
+use crate::faults::{self, Fault};
+use crate::ir_remote;
+use crate::latency;
+use crate::led;
+use crate::low_power;
+use crate::mute_relay;
+use crate::notifications::Change;
+use crate::output_power;
+use crate::parameters::PARAMETERS;
+use crate::potentiometer::{self, SecondTarget};
+use crate::presets;
+use crate::provisioning;
+use crate::rgb_led;
+use crate::scpi::{self, Scpi};
+use crate::self_test;
+use crate::settings::DeviceConfig;
+use crate::settings_store;
+use crate::shutdown;
+use crate::startup_script::{self, Script};
+use crate::supply;
+use crate::system::{self, RebootTarget};
+use crate::telemetry::{self, TelemetryChannel};
+use crate::thermal;
+use crate::thermal_log;
+use crate::touch::{self, TouchPad};
+use crate::trigger;
+use crate::*;
+
+/// The maximum length of a command line.
+const MAX_LINE_LENGTH: usize = 96;
+
+/// The maximum number of words on a command line.
+const MAX_WORD_COUNT: usize = 8;
+
+/// The number of event log entries that are shown by default.
+const DEFAULT_EVENT_COUNT: usize = 20;
+
+/// The maximum length of a single line of output.
+const MAX_OUTPUT_LENGTH: usize = 160;
+
+/// The size of the buffer for exported and imported documents.
+pub const SHELL_BUFFER_SIZE: usize = 8192;
+
+/// The buffer for exported and imported documents.
+pub type ShellBuffer = String<SHELL_BUFFER_SIZE>;
+
+const PROMPT: &str = "> ";
+
+/// Clears the current terminal line.
+const CLEAR_LINE: &str = "\r\x1b[K";
+
+const HELP: &[(&str, &str)] = &[
+    ("help", "Show this help"),
+    ("volume [<dB>]", "Show or set the master volume (0 to -127)"),
+    ("mute [on|off]", "Show or set the master mute"),
+    (
+        "pot [<dB>]",
+        "Show the potentiometer volume, or set its range below full-scale",
+    ),
+    (
+        "pot second off|balance|level <channel>...",
+        "Set what the second potentiometer adjusts",
+    ),
+    ("source [auto|usb|spdif|rpi]", "Show or select the source"),
+    (
+        "source-volume [on|off]",
+        "Show or set whether the volume is remembered per source",
+    ),
+    ("eq show [<channel>]", "Show the filter configuration"),
+    (
+        "eq set <channel> <stage> <kind> <Hz> <Q> [<dB>]",
+        "Set a filter stage (lp, hp, bp, notch, ap, peak, ls, hs)",
+    ),
+    ("eq remove <channel> <stage>", "Remove a filter stage"),
+    ("eq gain <channel> <dB>", "Set a channel's gain"),
+    ("eq delay <channel> <samples>", "Set a channel's delay"),
+    ("eq invert <channel> on|off", "Invert a channel"),
+    ("stats", "Show the device status"),
+    (
+        "cpu",
+        "Show the CPU load in total and by section, and the headroom of the signal processing",
+    ),
+    (
+        "latency [normal|low]",
+        "Show or set the latency mode (blocks of 1 ms or 0.25 ms for the Raspberry Pi)",
+    ),
+    ("info", "Show the firmware and hardware identification"),
+    (
+        "events [<count>]",
+        "Show the newest entries of the event log (20 by default)",
+    ),
+    ("events clear", "Erase the event log"),
+    (
+        "faults [clear]",
+        "Show the active faults and the last panic, or clear the raised faults, DC mute, and panic",
+    ),
+    (
+        "handshake",
+        "Show the protocol versions and features as JSON, for host tools",
+    ),
+    (
+        "telemetry off|usb|uart [<Hz>]",
+        "Stream telemetry records to a console (1 Hz by default)",
+    ),
+    ("standby [on|off]", "Show or set standby"),
+    (
+        "auto-standby [off|<min>]",
+        "Show or set the time without signal, after which standby is entered",
+    ),
+    (
+        "low-power [on|off]",
+        "Show or set whether the microcontroller stops in standby",
+    ),
+    (
+        "led [<percent>]",
+        "Show the LED brightness and night mode, or set the brightness",
+    ),
+    ("led night <percent>", "Set the LED brightness in night mode"),
+    (
+        "led meter on|off",
+        "Show the output level on the LEDs, instead of the source",
+    ),
+    (
+        "led night delay off|<min>",
+        "Set the time without operation, after which night mode starts",
+    ),
+    (
+        "led night hours off|<from> <to>",
+        "Set the hours of night mode (UTC, by the wall clock)",
+    ),
+    ("led map", "Show the LEDs of all functions"),
+    (
+        "led map <function> none|mcu <n>|expander <n>",
+        "Set the LED of a function (stored with the settings)",
+    ),
+    (
+        "rgb [off|<count> [rgbw]]",
+        "Show or set the number of addressable LEDs, and whether they have a white channel",
+    ),
+    ("display", "Show the status display and its screensaver"),
+    (
+        "display timeout off|<s>",
+        "Set the time without control activity, after which the screensaver starts",
+    ),
+    (
+        "display saver blank|dim",
+        "Set whether the screensaver blanks or dims the display",
+    ),
+    (
+        "trigger [standby|source]",
+        "Show or set the condition for the trigger output",
+    ),
+    ("thermal", "Show the temperatures, and their history (once per minute)"),
+    (
+        "thermal log",
+        "Show the lowest and highest temperatures over the operating time",
+    ),
+    ("thermal log clear", "Erase the thermal log"),
+    (
+        "load [<channel> <ohm>]",
+        "Show the output powers, or set the speaker load of an output channel",
+    ),
+    (
+        "amp-gain [<dBV>]",
+        "Show or set the analog gain of the amplifiers (11 to 21 dBV, 0.5 dB steps)",
+    ),
+    (
+        "supply [off|<V>]",
+        "Show the supply voltage, or set the threshold for shutting down the amplifiers",
+    ),
+    ("ir show", "Show the remote codes of all actions"),
+    ("ir learn <action>", "Assign the next received remote code to an action"),
+    ("ir clear <action>", "Remove the remote code of an action"),
+    ("button show", "Show the actions of all buttons"),
+    (
+        "button map <button> <press> <action>",
+        "Map a short, long, or double press to an action",
+    ),
+    ("touch", "Show the counts and baselines of the touch pads"),
+    (
+        "touch threshold <percent>",
+        "Set the count change over the baseline, which makes a touch",
+    ),
+    (
+        "touch calibrate",
+        "Calibrate the touch pads, which must not be touched meanwhile",
+    ),
+    ("config export", "Print the configuration as JSON"),
+    ("config import", "Read a configuration as JSON, end with an empty line"),
+    ("echo on|off", "Echo input and show the prompt"),
+    (
+        "notify on|off",
+        "Print changes of volume, mute, source, standby, and status",
+    ),
+    ("save", "Store the settings"),
+    (
+        "clip-protection [off|<dB>]",
+        "Show or set the volume reduction on sustained clipping (3 dB by default)",
+    ),
+    (
+        "autosave [off|<s>]",
+        "Show or set the time without changes, after which the settings are stored",
+    ),
+    (
+        "time [<unix time>]",
+        "Show or set the wall-clock time, in seconds since 1970 (UTC)",
+    ),
+    (
+        "slot",
+        "Show the active configuration slot, and whether slot B is staged",
+    ),
+    (
+        "slot audition|revert|commit|discard",
+        "Switch between configuration slot A and the staged slot B",
+    ),
+    ("preset list", "Show the stored presets"),
+    (
+        "preset show <preset>",
+        "Show the name, creation time, and notes of a preset",
+    ),
+    (
+        "preset save <preset> <name> [<notes>]",
+        "Store the signal processing configuration as a preset",
+    ),
+    (
+        "preset load <preset>",
+        "Load the signal processing configuration of a preset",
+    ),
+    ("preset rename <preset> <name>", "Rename a preset"),
+    (
+        "preset notes <preset> [<notes>]",
+        "Replace the notes of a preset, e.g. on the target speakers",
+    ),
+    ("preset delete <preset>", "Delete a preset"),
+    (
+        "preset default <preset>|none",
+        "Select the preset that is loaded at startup",
+    ),
+    ("startup show", "Show the commands that run at startup"),
+    ("startup add <command>", "Append a command to run at startup"),
+    ("startup remove <line>", "Remove a command from the startup commands"),
+    ("startup clear", "Remove all startup commands"),
+    ("calibration show", "Show the calibration data"),
+    (
+        "calibration taper <point> <position>",
+        "Set a point of the potentiometer taper (0 to 1)",
+    ),
+    (
+        "calibration trim <channel> <dB>",
+        "Set the gain trim of an output channel",
+    ),
+    (
+        "calibration temperature <sensor> <offset>",
+        "Set the offset of a temperature sensor in degrees Celsius",
+    ),
+    ("calibration store", "Store the calibration data"),
+    ("calibration erase", "Erase the stored calibration data"),
+    (
+        "provision <serial> <revision> <device ID> <unique ID>",
+        "Store the serial number, hardware revision, and device ID once, confirmed by the unique ID",
+    ),
+    (
+        "self-test",
+        "Show the state, limits, and results of the self-test by channel",
+    ),
+    (
+        "self-test start",
+        "Play a tone on every channel in turn, and check it through the loopback",
+    ),
+    (
+        "self-test limits <min dB> <max dB> <THD %>",
+        "Set the level limits (in dBFS) and the THD limit of the self-test",
+    ),
+    (
+        "shutdown",
+        "Enter standby, and save the state, such that the device can be switched off",
+    ),
+    (
+        "reboot [bootloader]",
+        "Restart the device, or start the bootloader for a firmware update",
+    ),
+    (
+        "factory-reset confirm",
+        "Erase settings, presets, and startup commands, and restart with the defaults",
+    ),
+    (
+        "log <level>",
+        "Set the console log level (trace, debug, info, warn, error)",
+    ),
+];
+
+/// Write a line of output to the terminal.
+macro_rules! reply {
+    ($out:expr, $($arg:tt)*) => {
+        write_line($out, format_args!($($arg)*)).await
+    };
+}
+
+async fn write_line<W: Write>(out: &mut W, args: fmt::Arguments<'_>) -> Result<(), W::Error> {
+    let mut text: String<MAX_OUTPUT_LENGTH> = String::new();
+
+    // Overlong output is truncated.
+    _ = text.write_fmt(args);
+    out.write_all(text.as_bytes()).await?;
+    out.write_all(b"\r\n").await
+}
+
+/// The state of escape sequence parsing.
+#[derive(Clone, Copy)]
+enum Escape {
+    /// No escape sequence.
+    None,
+    /// Received the escape character.
+    Started,
+    /// Inside a control sequence.
+    ControlSequence,
+}
+
+/// The state of a document import.
+#[derive(Clone, Copy, PartialEq)]
+enum Import {
+    /// Not importing, input is interpreted as commands.
+    Inactive,
+    /// Receiving a document. Holds whether the current document line is empty.
+    Receiving { line_empty: bool },
+    /// The document does not fit into the buffer. Input is discarded until its end.
+    Overflow,
+}
+
+/// The line editor and command interpreter.
+pub struct Shell {
+    line: String<MAX_LINE_LENGTH>,
+    history: String<MAX_LINE_LENGTH>,
+    escape: Escape,
+    last_byte: u8,
+    import: Import,
+    buffer: &'static mut ShellBuffer,
+    scpi: Scpi,
+    echo: bool,
+    notify: bool,
+}
+
+impl Shell {
+    /// Create a new shell with an empty command line.
+    pub fn new(buffer: &'static mut ShellBuffer) -> Self {
+        Shell {
+            line: String::new(),
+            history: String::new(),
+            escape: Escape::None,
+            last_byte: 0,
+            import: Import::Inactive,
+            buffer,
+            scpi: Scpi::new(),
+            echo: true,
+            notify: false,
+        }
+    }
+
+    /// Print a parameter change above the command line, if notifications are enabled.
+    pub async fn notify<W: Write>(&self, change: Change, out: &mut W) -> Result<(), W::Error> {
+        if !self.notify {
+            return Ok(());
+        }
+
+        let on_off = |value: bool| if value { "on" } else { "off" };
+        let source = |value: i32| AudioSource::try_from(value as u8).unwrap_or(AudioSource::None);
+
+        self.clear(out).await?;
+
+        match (change.parameter, change.value) {
+            (Parameter::Volume, Value::Integer(attenuation)) => {
+                reply!(out, "EVENT volume {:.1}", attenuation_db(attenuation as u8))?
+            }
+            (Parameter::InputVolume, Value::Integer(attenuation)) => {
+                reply!(out, "EVENT input-volume {:.1}", attenuation_db(attenuation as u8))?
+            }
+            (Parameter::Mute, Value::Boolean(muted)) => reply!(out, "EVENT mute {}", on_off(muted))?,
+            (Parameter::Standby, Value::Boolean(standby)) => reply!(out, "EVENT standby {}", on_off(standby))?,
+            (Parameter::Status, Value::Integer(status)) => reply!(out, "EVENT status {:#04x}", status)?,
+            (Parameter::SourceSelect, Value::Integer(selection)) => reply!(
+                out,
+                "EVENT selection {}",
+                control::source_selection_name(source(selection))
+            )?,
+            (Parameter::ActiveSource, Value::Integer(active)) => {
+                reply!(out, "EVENT source {}", control::source_name(source(active)))?
+            }
+            _ => (),
+        }
+
+        self.redraw(out).await?;
+        out.flush().await
+    }
+
+    /// Clear the current terminal line, for printing other output (e.g. logs).
+    pub async fn clear<W: Write>(&self, out: &mut W) -> Result<(), W::Error> {
+        if !self.echo {
+            return Ok(());
+        }
+
+        out.write_all(CLEAR_LINE.as_bytes()).await
+    }
+
+    /// Print the prompt and the current command line.
+    pub async fn redraw<W: Write>(&self, out: &mut W) -> Result<(), W::Error> {
+        // The document is not repeated while importing.
+        if self.import != Import::Inactive || !self.echo {
+            return Ok(());
+        }
+
+        out.write_all(CLEAR_LINE.as_bytes()).await?;
+        out.write_all(PROMPT.as_bytes()).await?;
+        out.write_all(self.line.as_bytes()).await
+    }
+
+    /// Write echoed input, if echo is enabled.
+    async fn echo<W: Write>(&self, data: &[u8], out: &mut W) -> Result<(), W::Error> {
+        if !self.echo {
+            return Ok(());
+        }
+
+        out.write_all(data).await
+    }
+
+    /// Process received characters. Echoes input, and executes complete command lines.
+    pub async fn receive<W: Write>(&mut self, data: &[u8], out: &mut W) -> Result<(), W::Error> {
+        for byte in data.iter().copied() {
+            self.receive_byte(byte, out).await?;
+            self.last_byte = byte;
+        }
+
+        out.flush().await
+    }
+
+    async fn receive_byte<W: Write>(&mut self, byte: u8, out: &mut W) -> Result<(), W::Error> {
+        if self.import != Import::Inactive {
+            return self.receive_document_byte(byte, out).await;
+        }
+
+        match self.escape {
+            Escape::Started => {
+                self.escape = if byte == b'[' {
+                    Escape::ControlSequence
+                } else {
+                    Escape::None
+                };
+                return Ok(());
+            }
+            Escape::ControlSequence => {
+                // Parameter and intermediate bytes are followed by a final byte.
+                if (0x40..=0x7E).contains(&byte) {
+                    self.escape = Escape::None;
+
+                    // Cursor up recalls the last command.
+                    if byte == b'A' {
+                        self.line = self.history.clone();
+                        self.redraw(out).await?;
+                    }
+                }
+                return Ok(());
+            }
+            Escape::None => (),
+        }
+
+        match byte {
+            b'\r' | b'\n' => {
+                // Terminals may send both characters for a single line break.
+                if byte == b'\n' && self.last_byte == b'\r' {
+                    return Ok(());
+                }
+
+                self.echo(b"\r\n", out).await?;
+
+                let line = core::mem::take(&mut self.line);
+
+                if !line.trim().is_empty() {
+                    self.history = line.clone();
+                }
+
+                // Commands that use the document buffer.
+                match line.trim() {
+                    "" => (),
+                    "config export" => self.export_config(out).await?,
+                    "config import" => {
+                        self.buffer.clear();
+                        self.import = Import::Receiving { line_empty: true };
+                        return reply!(out, "Paste the configuration, and end with an empty line or Ctrl-D.");
+                    }
+                    "echo on" => self.echo = true,
+                    "echo off" => self.echo = false,
+                    "notify on" => self.notify = true,
+                    "notify off" => self.notify = false,
+                    line if scpi::is_command(line) => {
+                        let mut response: String<MAX_OUTPUT_LENGTH> = String::new();
+                        self.scpi.execute(line, &mut response);
+
+                        if !response.is_empty() {
+                            reply!(out, "{}", response)?;
+                        }
+                    }
+                    line => execute(line, out).await?,
+                }
+
+                self.echo(PROMPT.as_bytes(), out).await?;
+            }
+            // Backspace or delete
+            0x08 | 0x7F => {
+                if self.line.pop().is_some() {
+                    self.echo(b"\x08 \x08", out).await?;
+                }
+            }
+            // Ctrl-C
+            0x03 => {
+                self.line.clear();
+                self.echo(b"^C\r\n", out).await?;
+                self.echo(PROMPT.as_bytes(), out).await?;
+            }
+            // Ctrl-U
+            0x15 => {
+                self.line.clear();
+                self.redraw(out).await?;
+            }
+            0x1B => self.escape = Escape::Started,
+            0x20..=0x7E => {
+                if self.line.push(byte as char).is_ok() {
+                    self.echo(&[byte], out).await?;
+                }
+            }
+            _ => (),
+        }
+
+        Ok(())
+    }
+
+    async fn receive_document_byte<W: Write>(&mut self, byte: u8, out: &mut W) -> Result<(), W::Error> {
+        let line_empty = match self.import {
+            Import::Receiving { line_empty } => line_empty,
+            _ => false,
+        };
+
+        match byte {
+            // Ctrl-C
+            0x03 => {
+                self.import = Import::Inactive;
+                self.buffer.clear();
+                self.echo(b"^C\r\n", out).await?;
+                return self.echo(PROMPT.as_bytes(), out).await;
+            }
+            // Ctrl-D
+            0x04 => return self.finish_import(out).await,
+            b'\r' | b'\n' => {
+                // Terminals may send both characters for a single line break.
+                if byte == b'\n' && self.last_byte == b'\r' {
+                    return Ok(());
+                }
+
+                if line_empty && !self.buffer.is_empty() {
+                    return self.finish_import(out).await;
+                }
+
+                self.echo(b"\r\n", out).await?;
+            }
+            _ => self.echo(&[byte], out).await?,
+        }
+
+        if let Import::Receiving { .. } = self.import {
+            self.import = if self.buffer.push(byte as char).is_ok() {
+                Import::Receiving {
+                    line_empty: matches!(byte, b'\r' | b'\n'),
+                }
+            } else {
+                Import::Overflow
+            };
+        }
+
+        Ok(())
+    }
+
+    async fn finish_import<W: Write>(&mut self, out: &mut W) -> Result<(), W::Error> {
+        self.echo(b"\r\n", out).await?;
+
+        if self.import == Import::Overflow {
+            reply!(out, "The configuration exceeds {} bytes", SHELL_BUFFER_SIZE)?;
+        } else {
+            match config_json::import(self.buffer.as_str()) {
+                Ok(config) => match config.apply() {
+                    Ok(()) => reply!(out, "Configuration imported")?,
+                    Err(error) => reply!(out, "Invalid configuration: {:?}", error)?,
+                },
+                Err(errors) => {
+                    for error in errors.errors.iter() {
+                        reply!(out, "Error in {}: {}", error.path, error.message)?;
+                    }
+
+                    if errors.omitted > 0 {
+                        reply!(out, "{} more errors", errors.omitted)?;
+                    }
+
+                    reply!(out, "Configuration was not imported")?;
+                }
+            }
+        }
+
+        self.import = Import::Inactive;
+        self.buffer.clear();
+        self.echo(PROMPT.as_bytes(), out).await
+    }
+
+    async fn export_config<W: Write>(&mut self, out: &mut W) -> Result<(), W::Error> {
+        self.buffer.clear();
+
+        if config_json::export(&DeviceConfig::current(), &mut *self.buffer).is_err() {
+            return reply!(out, "The configuration exceeds {} bytes", SHELL_BUFFER_SIZE);
+        }
+
+        out.write_all(self.buffer.as_bytes()).await?;
+        self.buffer.clear();
+
+        Ok(())
+    }
+}
+
+fn parse_on_off(word: &str) -> Option<bool> {
+    match word {
+        "on" | "1" => Some(true),
+        "off" | "0" => Some(false),
+        _ => None,
+    }
+}
+
+fn parse_level(word: &str) -> Option<Level> {
+    match word {
+        "trace" => Some(Level::Trace),
+        "debug" => Some(Level::Debug),
+        "info" => Some(Level::Info),
+        "warn" => Some(Level::Warn),
+        "error" => Some(Level::Error),
+        _ => None,
+    }
+}
+
+fn parse_channel(word: &str) -> Option<usize> {
+    word.parse::<usize>()
+        .ok()
+        .filter(|channel| *channel < OUTPUT_CHANNEL_COUNT)
+}
+
+fn parse_percent(word: &str) -> Option<u8> {
+    word.parse::<u8>().ok().filter(|percent| *percent <= 100)
+}
+
+fn parse_hour(word: &str) -> Option<u8> {
+    word.parse::<u8>().ok().filter(|hour| *hour < 24)
+}
+
+fn parse_preset(word: &str) -> Option<usize> {
+    word.parse::<usize>()
+        .ok()
+        .filter(|index| *index < presets::MAX_PRESET_COUNT)
+}
+
+fn parse_slot_action(word: &str) -> Option<SlotAction> {
+    match word {
+        "audition" => Some(SlotAction::Audition),
+        "revert" => Some(SlotAction::Revert),
+        "commit" => Some(SlotAction::Commit),
+        "discard" => Some(SlotAction::Discard),
+        _ => None,
+    }
+}
+
+/// Parse bytes in hexadecimal, optionally separated by colons (e.g. `02:00:5e:10:00:01`).
+fn parse_hex<const N: usize>(word: &str) -> Option<[u8; N]> {
+    let mut digits = word.chars().filter(|c| *c != ':');
+    let mut bytes = [0u8; N];
+
+    for byte in bytes.iter_mut() {
+        let high = digits.next()?.to_digit(16)?;
+        let low = digits.next()?.to_digit(16)?;
+        *byte = (high * 16 + low) as u8;
+    }
+
+    digits.next().is_none().then_some(bytes)
+}
+
+/// The rest of a line after a number of words, for text that may consist of more words than are split.
+fn rest_of_line(line: &str, word_count: usize) -> &str {
+    let mut rest = line.trim();
+
+    for _ in 0..word_count {
+        rest = rest.trim_start_matches(|c: char| !c.is_whitespace()).trim_start();
+    }
+
+    rest
+}
+
+/// Format an attenuation in steps of 0.5 dB as a level in dB.
+fn attenuation_db(attenuation_half_db: u8) -> f32 {
+    -(attenuation_half_db as f32) / 2.0
+}
+
+/// Execute a command line, other than SCPI commands and those that need the shell state (e.g. `config import`).
+pub async fn execute<W: Write>(line: &str, out: &mut W) -> Result<(), W::Error> {
+    let words: Vec<&str, MAX_WORD_COUNT> = line.split_whitespace().take(MAX_WORD_COUNT).collect();
+
+    match words.as_slice() {
+        ["help"] => {
+            for (command, description) in HELP {
+                reply!(out, "{:<48} {}", command, description)?;
+            }
+        }
+        ["volume"] => volume(out).await?,
+        ["volume", volume_db] => match volume_db.parse::<f32>() {
+            Ok(volume_db) if (-127.0..=0.0).contains(&volume_db) => {
+                _ = PARAMETERS.set(Parameter::Volume, Value::Integer((-volume_db * 2.0) as i32));
+                volume(out).await?;
+            }
+            _ => reply!(out, "Invalid volume")?,
+        },
+        ["mute"] => reply!(out, "Mute: {}", if CONTROL.muted() { "on" } else { "off" })?,
+        ["mute", state] => match parse_on_off(state) {
+            Some(muted) => {
+                _ = PARAMETERS.set(Parameter::Mute, Value::Boolean(muted));
+                reply!(out, "Mute: {}", state)?;
+            }
+            None => reply!(out, "Invalid mute state")?,
+        },
+        ["pot"] => pot(out).await?,
+        ["pot", "second", "off"] => {
+            potentiometer::set_second_target(SecondTarget::Off);
+            pot(out).await?;
+        }
+        ["pot", "second", "balance"] => {
+            potentiometer::set_second_target(SecondTarget::Balance);
+            pot(out).await?;
+        }
+        ["pot", "second", "level", channels @ ..] if !channels.is_empty() => {
+            let mask = channels
+                .iter()
+                .map(|channel| parse_channel(channel))
+                .try_fold(0u8, |mask, channel| Some(mask | (1 << channel?)));
+
+            match mask {
+                Some(mask) => {
+                    potentiometer::set_second_target(SecondTarget::Level(mask));
+                    pot(out).await?;
+                }
+                None => reply!(out, "Invalid channel")?,
+            }
+        }
+        ["pot", range_db] => match range_db.parse::<u8>() {
+            Ok(range_db) if (potentiometer::MIN_RANGE_DB..=potentiometer::MAX_RANGE_DB).contains(&range_db) => {
+                potentiometer::set_range_db(range_db);
+                pot(out).await?;
+            }
+            _ => reply!(
+                out,
+                "Invalid range ({} to {} dB)",
+                potentiometer::MIN_RANGE_DB,
+                potentiometer::MAX_RANGE_DB
+            )?,
+        },
+        ["source"] => source(out).await?,
+        ["source", name] => match control::parse_source_selection(name) {
+            Some(selection) => {
+                _ = PARAMETERS.set(Parameter::SourceSelect, Value::Integer(selection as i32));
+                source(out).await?;
+            }
+            None => reply!(out, "Invalid source")?,
+        },
+        ["source-volume"] => source_volume(out).await?,
+        ["source-volume", state] => match parse_on_off(state) {
+            Some(enabled) => {
+                CONTROL.set_source_volume(enabled);
+                source_volume(out).await?;
+            }
+            None => reply!(out, "Invalid state")?,
+        },
+        ["eq", "show"] => {
+            for channel in 0..OUTPUT_CHANNEL_COUNT {
+                eq_show(channel, out).await?;
+            }
+        }
+        ["eq", "show", channel] => match parse_channel(channel) {
+            Some(channel) => eq_show(channel, out).await?,
+            None => reply!(out, "Invalid channel")?,
+        },
+        ["eq", "set", arguments @ ..] => eq_set(arguments, out).await?,
+        ["eq", "remove", channel, stage] => match (parse_channel(channel), stage.parse::<usize>().ok()) {
+            (Some(channel), Some(stage)) => {
+                let result = dsp::update_dsp_config(|config| {
+                    let stages = &mut config[channel].stages;
+                    if stage < stages.len() {
+                        stages.remove(stage);
+                    }
+                });
+                config_result(result, out).await?;
+            }
+            _ => reply!(out, "Invalid channel or stage")?,
+        },
+        ["eq", "gain", channel, gain_db] => match (parse_channel(channel), gain_db.parse::<f32>()) {
+            (Some(channel), Ok(gain_db)) => {
+                let result = dsp::update_dsp_config(|config| config[channel].gain_db = gain_db);
+                config_result(result, out).await?;
+            }
+            _ => reply!(out, "Invalid channel or gain")?,
+        },
+        ["eq", "delay", channel, delay] => match (parse_channel(channel), delay.parse::<usize>()) {
+            (Some(channel), Ok(delay)) => {
+                let result = dsp::update_dsp_config(|config| config[channel].delay = delay);
+                config_result(result, out).await?;
+            }
+            _ => reply!(out, "Invalid channel or delay")?,
+        },
+        ["eq", "invert", channel, state] => match (parse_channel(channel), parse_on_off(state)) {
+            (Some(channel), Some(inverted)) => {
+                let result = dsp::update_dsp_config(|config| config[channel].inverted = inverted);
+                config_result(result, out).await?;
+            }
+            _ => reply!(out, "Invalid channel or state")?,
+        },
+        ["stats"] => stats(out).await?,
+        ["cpu"] => cpu(out).await?,
+        ["latency"] => latency(out).await?,
+        ["latency", mode] => match latency::mode_from_name(mode) {
+            Some(mode) => {
+                latency::set_mode(mode);
+                latency(out).await?;
+            }
+            None => reply!(out, "Invalid latency mode")?,
+        },
+        ["info"] => info(out).await?,
+        ["handshake"] => handshake(out).await?,
+        ["events", "clear"] => match event_log::clear() {
+            Ok(()) => reply!(out, "Event log cleared")?,
+            Err(error) => reply!(out, "Failed to clear the event log: {:?}", error)?,
+        },
+        ["events", count @ ..] if count.len() <= 1 => match count {
+            [count] => match count.parse::<usize>() {
+                Ok(count) => events(count, out).await?,
+                Err(_) => reply!(out, "Invalid count")?,
+            },
+            _ => events(DEFAULT_EVENT_COUNT, out).await?,
+        },
+        ["telemetry"] => match telemetry::channel() {
+            TelemetryChannel::Off => reply!(out, "Telemetry: off")?,
+            channel => reply!(
+                out,
+                "Telemetry: {} at {} Hz",
+                telemetry::channel_name(channel),
+                telemetry::rate_hz()
+            )?,
+        },
+        ["telemetry", channel, rate @ ..] if rate.len() <= 1 => {
+            let rate_hz = match rate {
+                [rate] => rate
+                    .parse::<u8>()
+                    .ok()
+                    .filter(|rate| (1..=telemetry::MAX_RATE_HZ).contains(rate)),
+                _ => Some(1),
+            };
+
+            match (telemetry::parse_channel(channel), rate_hz) {
+                (Some(channel), Some(rate_hz)) => {
+                    telemetry::configure(channel, rate_hz);
+                    reply!(out, "Telemetry: {}", telemetry::channel_name(channel))?;
+                }
+                (None, _) => reply!(out, "Invalid telemetry channel (off, usb, uart)")?,
+                (_, None) => reply!(out, "Invalid rate (1 to {} Hz)", telemetry::MAX_RATE_HZ)?,
+            }
+        }
+        ["standby"] => reply!(out, "Standby: {}", if CONTROL.standby() { "on" } else { "off" })?,
+        ["standby", state] => match parse_on_off(state) {
+            Some(standby) => {
+                _ = PARAMETERS.set(Parameter::Standby, Value::Boolean(standby));
+                reply!(out, "Standby: {}", state)?;
+            }
+            None => reply!(out, "Invalid standby state")?,
+        },
+        ["ir", "show"] => {
+            for (action, code) in IrAction::ALL.iter().zip(ir_remote::ir_codes()) {
+                match code {
+                    Some(code) => reply!(
+                        out,
+                        "{:<12} {} address {:#x}, command {:#x}",
+                        ir_remote::action_name(*action),
+                        ir_remote::protocol_name(code.protocol),
+                        code.address,
+                        code.command
+                    )?,
+                    None => reply!(out, "{:<12} unassigned", ir_remote::action_name(*action))?,
+                }
+            }
+        }
+        ["ir", "learn", action] => match ir_remote::parse_action(action) {
+            Some(action) => {
+                ir_remote::learn(action);
+                reply!(out, "Press the remote button for {}", ir_remote::action_name(action))?;
+            }
+            None => reply!(
+                out,
+                "Invalid action (volume-up, volume-down, mute, source, standby, next-preset)"
+            )?,
+        },
+        ["ir", "clear", action] => match ir_remote::parse_action(action) {
+            Some(action) => {
+                let mut ir_codes = ir_remote::ir_codes();
+                ir_codes[action as usize] = None;
+                ir_remote::set_ir_codes(ir_codes);
+                reply!(out, "Cleared {}", ir_remote::action_name(action))?;
+            }
+            None => reply!(
+                out,
+                "Invalid action (volume-up, volume-down, mute, source, standby, next-preset)"
+            )?,
+        },
+        ["faults"] => faults(out).await?,
+        ["faults", "clear"] => {
+            faults::clear();
+            faults(out).await?;
+        }
+        ["thermal"] => thermal(out).await?,
+        ["thermal", "log"] => thermal_log(out).await?,
+        ["thermal", "log", "clear"] => match thermal_log::clear() {
+            Ok(()) => reply!(out, "Thermal log cleared")?,
+            Err(error) => reply!(out, "Failed to clear the thermal log: {:?}", error)?,
+        },
+        ["load"] => output_power(out).await?,
+        ["load", channel, load_ohm] => match (channel.parse::<usize>(), load_ohm.parse::<u8>()) {
+            (Ok(channel), Ok(load_ohm))
+                if channel < OUTPUT_CHANNEL_COUNT && output_power::LOAD_RANGE_OHM.contains(&load_ohm) =>
+            {
+                output_power::set_load_ohm(channel, load_ohm);
+                output_power(out).await?;
+            }
+            _ => reply!(out, "Invalid channel or load (2 to 32 ohm)")?,
+        },
+        ["amp-gain"] => amplifier_gain(out).await?,
+        ["amp-gain", dbv] => match dbv.parse::<f32>().ok().and_then(amplifiers::gain_from_dbv) {
+            Some(gain) => {
+                amplifiers::set_gain(gain);
+                amplifier_gain(out).await?;
+            }
+            _ => reply!(out, "Invalid gain (11 to 21 dBV, in steps of 0.5 dB)")?,
+        },
+        ["supply"] => supply(out).await?,
+        ["supply", "off"] => {
+            supply::set_threshold_mv(0);
+            supply(out).await?;
+        }
+        ["supply", threshold] => match threshold.parse::<f32>() {
+            Ok(threshold) if (0.0..=60.0).contains(&threshold) => {
+                supply::set_threshold_mv((threshold * 1000.0) as u16);
+                supply(out).await?;
+            }
+            _ => reply!(out, "Invalid threshold")?,
+        },
+        ["trigger"] => reply!(
+            out,
+            "Trigger mode: {}",
+            trigger::trigger_mode_name(trigger::trigger_mode())
+        )?,
+        ["trigger", mode] => match trigger::parse_trigger_mode(mode) {
+            Some(mode) => {
+                trigger::set_trigger_mode(mode);
+                reply!(out, "Trigger mode: {}", trigger::trigger_mode_name(mode))?;
+            }
+            None => reply!(out, "Invalid trigger mode (standby, source)")?,
+        },
+        ["button", "show"] => {
+            for (index, actions) in button::button_map().iter().enumerate() {
+                for (press, action) in Press::ALL.iter().zip(actions) {
+                    reply!(
+                        out,
+                        "Button {} {:<6} {}",
+                        index,
+                        button::press_name(*press),
+                        button::action_name(*action)
+                    )?;
+                }
+            }
+        }
+        ["button", "map", index, press, action] => {
+            let index = index.parse::<usize>().ok().filter(|index| *index < BUTTON_COUNT);
+
+            match (index, button::parse_press(press), button::parse_action(action)) {
+                (Some(index), Some(press), Some(action)) => {
+                    let mut button_map = button::button_map();
+                    button_map[index][press as usize] = action;
+                    button::set_button_map(button_map);
+                    reply!(
+                        out,
+                        "Button {} {}: {}",
+                        index,
+                        button::press_name(press),
+                        button::action_name(action)
+                    )?;
+                }
+                (None, _, _) => reply!(out, "Invalid button")?,
+                (_, None, _) => reply!(out, "Invalid press (short, long, double)")?,
+                (_, _, None) => reply!(
+                    out,
+                    "Invalid action (none, source, mute, standby, next-preset, shutdown)"
+                )?,
+            }
+        }
+        ["touch"] => touch(out).await?,
+        ["touch", "threshold", percent] => match parse_percent(percent).filter(|percent| *percent >= 1) {
+            Some(percent) => {
+                touch::set_threshold_percent(percent);
+                touch(out).await?;
+            }
+            None => reply!(out, "Invalid threshold (1 to 100 %)")?,
+        },
+        ["touch", "calibrate"] => {
+            touch::calibrate();
+            reply!(out, "Calibrating the touch pads, do not touch them")?;
+        }
+        ["save"] => match settings_store::save() {
+            Ok(()) => reply!(out, "Settings saved")?,
+            Err(error) => reply!(out, "Failed to save settings: {:?}", error)?,
+        },
+        ["slot"] => slot(out).await?,
+        ["slot", action] => match parse_slot_action(action) {
+            Some(action) => match config_slots::perform(action) {
+                Ok(()) => slot(out).await?,
+                Err(error) => reply!(out, "Failed to switch slots: {:?}", error)?,
+            },
+            None => reply!(out, "Invalid action")?,
+        },
+        ["low-power"] => low_power(out).await?,
+        ["low-power", state] => match parse_on_off(state) {
+            Some(enabled) => {
+                low_power::set_enabled(enabled);
+                low_power(out).await?;
+            }
+            None => reply!(out, "Invalid low-power state")?,
+        },
+        ["led"] => led(out).await?,
+        ["led", "map"] => led_map(out).await?,
+        ["led", "map", function, led @ ..] => {
+            let output = match led {
+                ["none"] => Some(LedOutput::None),
+                ["mcu", index] => index
+                    .parse()
+                    .ok()
+                    .filter(|index| *index < MCU_LED_COUNT)
+                    .map(LedOutput::Mcu),
+                ["expander", output] => output
+                    .parse()
+                    .ok()
+                    .filter(|output| *output < EXPANDER_LED_COUNT)
+                    .map(LedOutput::Expander),
+                _ => None,
+            };
+
+            match (led::parse_function(function), output) {
+                (Some(function), Some(output)) => {
+                    let mut map = led::led_map();
+                    map[function as usize] = output;
+                    led::set_led_map(map);
+                    led_map(out).await?;
+                }
+                (None, _) => reply!(
+                    out,
+                    "Invalid function (usb, spdif, rpi, mute, standby, clip, fault, heartbeat)"
+                )?,
+                (_, None) => reply!(
+                    out,
+                    "Invalid LED (none, mcu 0 to {}, expander 0 to {})",
+                    MCU_LED_COUNT - 1,
+                    EXPANDER_LED_COUNT - 1
+                )?,
+            }
+        }
+        ["led", "meter", state] => match parse_on_off(state) {
+            Some(enabled) => {
+                led::set_meter_mode(enabled);
+                led(out).await?;
+            }
+            None => reply!(out, "Invalid meter state")?,
+        },
+        ["led", "night", "delay", "off"] => {
+            led::set_night_delay(None);
+            led(out).await?;
+        }
+        ["led", "night", "delay", delay_min] => {
+            let max_delay_min = led::MAX_NIGHT_DELAY.as_secs() / 60;
+
+            match delay_min
+                .parse::<u64>()
+                .ok()
+                .filter(|delay_min| (1..=max_delay_min).contains(delay_min))
+            {
+                Some(delay_min) => {
+                    led::set_night_delay(Some(Duration::from_secs(delay_min * 60)));
+                    led(out).await?;
+                }
+                None => reply!(out, "Invalid delay (1 to {} min)", max_delay_min)?,
+            }
+        }
+        ["led", "night", "hours", "off"] => {
+            led::set_night_hours(None);
+            led(out).await?;
+        }
+        ["led", "night", "hours", start, end] => match (parse_hour(start), parse_hour(end)) {
+            (Some(start), Some(end)) if start != end => {
+                led::set_night_hours(Some((start, end)));
+                led(out).await?;
+            }
+            _ => reply!(out, "Invalid hours (0 to 23, different)")?,
+        },
+        ["led", "night", percent] => match parse_percent(percent) {
+            Some(percent) => {
+                led::set_night_brightness(percent);
+                led(out).await?;
+            }
+            None => reply!(out, "Invalid brightness (0 to 100 %)")?,
+        },
+        ["led", percent] => match parse_percent(percent) {
+            Some(percent) => {
+                led::set_brightness(percent);
+                led(out).await?;
+            }
+            None => reply!(out, "Invalid brightness (0 to 100 %)")?,
+        },
+        ["rgb"] => rgb(out).await?,
+        ["rgb", "off"] => {
+            rgb_led::set_leds(0, false);
+            rgb(out).await?;
+        }
+        ["rgb", count, kind @ ..] if matches!(kind, [] | ["rgbw"]) => {
+            match count
+                .parse::<usize>()
+                .ok()
+                .filter(|count| (1..=rgb_led::MAX_LED_COUNT).contains(count))
+            {
+                Some(count) => {
+                    rgb_led::set_leds(count, !kind.is_empty());
+                    rgb(out).await?;
+                }
+                None => reply!(out, "Invalid count (1 to {})", rgb_led::MAX_LED_COUNT)?,
+            }
+        }
+        ["display"] => display(out).await?,
+        ["display", "timeout", "off"] => {
+            display::set_timeout(None);
+            display(out).await?;
+        }
+        ["display", "timeout", timeout_s] => {
+            let max_timeout_s = display::MAX_TIMEOUT.as_secs();
+
+            match timeout_s
+                .parse::<u64>()
+                .ok()
+                .filter(|timeout_s| (1..=max_timeout_s).contains(timeout_s))
+            {
+                Some(timeout_s) => {
+                    display::set_timeout(Some(Duration::from_secs(timeout_s)));
+                    display(out).await?;
+                }
+                None => reply!(out, "Invalid timeout (1 to {} s)", max_timeout_s)?,
+            }
+        }
+        ["display", "saver", saver @ ("blank" | "dim")] => {
+            display::set_dim(*saver == "dim");
+            display(out).await?;
+        }
+        ["auto-standby"] => auto_standby(out).await?,
+        ["auto-standby", "off"] => {
+            auto_standby::set_delay(None);
+            auto_standby(out).await?;
+        }
+        ["auto-standby", delay_min] => {
+            let max_delay_min = auto_standby::MAX_DELAY.as_secs() / 60;
+
+            match delay_min
+                .parse::<u64>()
+                .ok()
+                .filter(|delay_min| (1..=max_delay_min).contains(delay_min))
+            {
+                Some(delay_min) => {
+                    auto_standby::set_delay(Some(Duration::from_secs(delay_min * 60)));
+                    auto_standby(out).await?;
+                }
+                None => reply!(out, "Invalid delay (1 to {} min)", max_delay_min)?,
+            }
+        }
+        ["clip-protection"] => clip_protection(out).await?,
+        ["clip-protection", "off"] => {
+            clip_protection::set_step_half_db(0);
+            clip_protection(out).await?;
+        }
+        ["clip-protection", step_db] => {
+            let max_step_db = clip_protection::MAX_STEP_HALF_DB as f32 / 2.0;
+
+            match step_db
+                .parse::<f32>()
+                .ok()
+                .filter(|step_db| (0.5..=max_step_db).contains(step_db))
+            {
+                Some(step_db) => {
+                    clip_protection::set_step_half_db((step_db * 2.0).round() as u8);
+                    clip_protection(out).await?;
+                }
+                None => reply!(out, "Invalid step (0.5 to {} dB)", max_step_db)?,
+            }
+        }
+        ["autosave"] => match settings_store::autosave_delay() {
+            Some(delay) => reply!(out, "Autosave: after {} s", delay.as_secs())?,
+            None => reply!(out, "Autosave: off")?,
+        },
+        ["autosave", "off"] => {
+            settings_store::set_autosave_delay(None);
+            reply!(out, "Autosave: off")?;
+        }
+        ["autosave", delay_s] => {
+            let max_delay_s = settings_store::MAX_AUTOSAVE_DELAY.as_secs();
+
+            match delay_s
+                .parse::<u64>()
+                .ok()
+                .filter(|delay_s| (1..=max_delay_s).contains(delay_s))
+            {
+                Some(delay_s) => {
+                    settings_store::set_autosave_delay(Some(Duration::from_secs(delay_s)));
+                    reply!(out, "Autosave: after {} s", delay_s)?;
+                }
+                None => reply!(out, "Invalid delay (1 to {} s)", max_delay_s)?,
+            }
+        }
+        ["time"] => match clock::unix_time() {
+            Some(time_s) => reply!(out, "Time: {} ({})", clock::DateTime::from_unix_time(time_s), time_s)?,
+            None => reply!(out, "Time: not set")?,
+        },
+        ["time", time_s] => match time_s.parse::<u32>() {
+            Ok(time_s) => {
+                clock::set_unix_time(time_s);
+                reply!(out, "Time: {}", clock::DateTime::from_unix_time(time_s))?;
+            }
+            Err(_) => reply!(out, "Invalid time (seconds since 1970)")?,
+        },
+        ["preset", "list"] => {
+            let default_preset = presets::default_preset();
+            let mut found = false;
+
+            for index in 0..presets::MAX_PRESET_COUNT {
+                if let Some(name) = presets::name(index) {
+                    found = true;
+                    reply!(
+                        out,
+                        "{}: {}{}{}",
+                        index,
+                        name,
+                        if default_preset == Some(index) {
+                            " (default)"
+                        } else {
+                            ""
+                        },
+                        if presets::active() == Some(index) {
+                            " (active)"
+                        } else {
+                            ""
+                        }
+                    )?;
+                }
+            }
+
+            if !found {
+                reply!(out, "No presets")?;
+            }
+        }
+        ["preset", "show", index] => match parse_preset(index) {
+            Some(index) => match presets::info(index) {
+                Some(info) => {
+                    reply!(out, "Name: {}", info.name)?;
+
+                    match info.created {
+                        Some(created) => reply!(out, "Created: {}", clock::DateTime::from_unix_time(created))?,
+                        None => reply!(out, "Created: unknown")?,
+                    }
+
+                    reply!(out, "Notes: {}", info.notes)?;
+                }
+                None => reply!(out, "No such preset")?,
+            },
+            None => reply!(out, "Invalid preset (0 to {})", presets::MAX_PRESET_COUNT - 1)?,
+        },
+        ["preset", "save", index, name, ..] => match parse_preset(index) {
+            Some(index) => match presets::save(index, name, rest_of_line(line, 4)) {
+                Ok(()) => reply!(out, "Saved preset {}: {}", index, name)?,
+                Err(error) => preset_error(error, out).await?,
+            },
+            None => reply!(out, "Invalid preset (0 to {})", presets::MAX_PRESET_COUNT - 1)?,
+        },
+        ["preset", "rename", index, name] => match parse_preset(index) {
+            Some(index) => match presets::rename(index, name) {
+                Ok(()) => reply!(out, "Renamed preset {}: {}", index, name)?,
+                Err(error) => preset_error(error, out).await?,
+            },
+            None => reply!(out, "Invalid preset (0 to {})", presets::MAX_PRESET_COUNT - 1)?,
+        },
+        ["preset", "notes", index, ..] => match parse_preset(index) {
+            Some(index) => match presets::set_notes(index, rest_of_line(line, 3)) {
+                Ok(()) => reply!(out, "Replaced the notes of preset {}", index)?,
+                Err(error) => preset_error(error, out).await?,
+            },
+            None => reply!(out, "Invalid preset (0 to {})", presets::MAX_PRESET_COUNT - 1)?,
+        },
+        ["preset", "load", index] => match parse_preset(index).map(presets::load) {
+            Some(Ok(())) => reply!(out, "Loaded preset {}", index)?,
+            Some(Err(presets::Error::NotFound)) => reply!(out, "No such preset")?,
+            Some(Err(error)) => reply!(out, "Failed to load the preset: {:?}", error)?,
+            None => reply!(out, "Invalid preset (0 to {})", presets::MAX_PRESET_COUNT - 1)?,
+        },
+        ["preset", "delete", index] => match parse_preset(index).map(presets::delete) {
+            Some(Ok(())) => reply!(out, "Deleted preset {}", index)?,
+            Some(Err(presets::Error::NotFound)) => reply!(out, "No such preset")?,
+            Some(Err(error)) => reply!(out, "Failed to delete the preset: {:?}", error)?,
+            None => reply!(out, "Invalid preset (0 to {})", presets::MAX_PRESET_COUNT - 1)?,
+        },
+        ["preset", "default", index] => {
+            let index = match *index {
+                "none" => Some(None),
+                index => parse_preset(index).map(Some),
+            };
+
+            match index.map(|index| (index, presets::set_default_preset(index))) {
+                Some((Some(index), Ok(()))) => reply!(out, "Default preset: {}", index)?,
+                Some((None, Ok(()))) => reply!(out, "Default preset: none")?,
+                Some((_, Err(presets::Error::NotFound))) => reply!(out, "No such preset")?,
+                Some((_, Err(error))) => reply!(out, "Failed to select the default preset: {:?}", error)?,
+                None => reply!(out, "Invalid preset (0 to {})", presets::MAX_PRESET_COUNT - 1)?,
+            }
+        }
+        ["startup", "show"] => {
+            let script = startup_script::load();
+
+            if startup_script::lines(&script).next().is_none() {
+                reply!(out, "No startup commands")?;
+            }
+            for (index, line) in startup_script::lines(&script).enumerate() {
+                reply!(out, "{}: {}", index, line)?;
+            }
+        }
+        ["startup", "add", _, ..] => {
+            // The command is taken from the line, since it may consist of more words than are split.
+            let command = rest_of_line(line, 2);
+            let mut script = startup_script::load();
+
+            if matches!(
+                command.split_whitespace().next(),
+                Some("startup" | "reboot" | "factory-reset")
+            ) {
+                reply!(out, "Not allowed at startup")?;
+            } else if script.push_str(command).and_then(|_| script.push('\n')).is_err() {
+                reply!(
+                    out,
+                    "The startup commands exceed {} bytes",
+                    startup_script::MAX_SCRIPT_SIZE
+                )?;
+            } else {
+                store_script(&script, out).await?;
+            }
+        }
+        ["startup", "remove", index] => {
+            let script = startup_script::load();
+
+            match index
+                .parse::<usize>()
+                .ok()
+                .filter(|index| *index < startup_script::lines(&script).count())
+            {
+                Some(index) => {
+                    let mut remaining = Script::new();
+
+                    for (_, line) in startup_script::lines(&script).enumerate().filter(|(i, _)| *i != index) {
+                        // Cannot fail, the remaining script is shorter.
+                        _ = remaining.push_str(line);
+                        _ = remaining.push('\n');
+                    }
+
+                    store_script(&remaining, out).await?;
+                }
+                None => reply!(out, "Invalid line")?,
+            }
+        }
+        ["startup", "clear"] => store_script("", out).await?,
+        ["calibration", "show"] => calibration_show(out).await?,
+        ["calibration", "taper", point, position] => {
+            let point = point.parse::<usize>().ok().filter(|point| *point < TAPER_POINT_COUNT);
+
+            match (point, position.parse::<f32>()) {
+                (Some(point), Ok(position)) => {
+                    let result = calibration::update_calibration(|calibration| calibration.taper[point] = position);
+                    calibration_result(result, out).await?;
+                }
+                _ => reply!(out, "Invalid point (0 to {}) or position", TAPER_POINT_COUNT - 1)?,
+            }
+        }
+        ["calibration", "trim", channel, trim_db] => match (parse_channel(channel), trim_db.parse::<f32>()) {
+            (Some(channel), Ok(trim_db)) => {
+                let result =
+                    calibration::update_calibration(|calibration| calibration.output_trim_db[channel] = trim_db);
+                calibration_result(result, out).await?;
+            }
+            _ => reply!(out, "Invalid channel or trim")?,
+        },
+        ["calibration", "temperature", sensor, offset] => {
+            let sensor = sensor
+                .parse::<usize>()
+                .ok()
+                .filter(|sensor| *sensor < TEMPERATURE_SENSOR_COUNT);
+
+            match (sensor, offset.parse::<f32>()) {
+                (Some(sensor), Ok(offset)) => {
+                    let result =
+                        calibration::update_calibration(|calibration| calibration.temperature_offsets[sensor] = offset);
+                    calibration_result(result, out).await?;
+                }
+                _ => reply!(out, "Invalid sensor (0 to {}) or offset", TEMPERATURE_SENSOR_COUNT - 1)?,
+            }
+        }
+        ["calibration", "store"] => match calibration::store() {
+            Ok(()) => reply!(out, "Calibration stored")?,
+            Err(error) => reply!(out, "Failed to store the calibration: {:?}", error)?,
+        },
+        ["calibration", "erase"] => match calibration::erase() {
+            Ok(()) => reply!(out, "Calibration erased")?,
+            Err(error) => reply!(out, "Failed to erase the calibration: {:?}", error)?,
+        },
+        ["provision", serial_number, revision, device_id, unique_id] => {
+            match (revision.parse::<u8>(), parse_hex(device_id), parse_hex(unique_id)) {
+                (Ok(revision), Some(device_id), Some(unique_id)) => {
+                    match Provisioning::new(revision, device_id, serial_number)
+                        .map_err(provisioning::Error::from)
+                        .and_then(|data| provisioning::provision(data, &unique_id))
+                    {
+                        Ok(()) => reply!(out, "Provisioned, the USB serial number applies after a reboot")?,
+                        Err(error) => reply!(out, "Failed to provision: {:?}", error)?,
+                    }
+                }
+                _ => reply!(
+                    out,
+                    "Invalid revision, device ID (e.g. 02:00:00:00:00:01), or unique ID"
+                )?,
+            }
+        }
+        ["self-test"] => self_test(out).await?,
+        ["self-test", "start"] => match self_test::start() {
+            Ok(()) => reply!(out, "Self-test started")?,
+            Err(self_test::Error::Busy) => reply!(out, "The self-test is running")?,
+            Err(self_test::Error::Silent) => reply!(out, "The output is muted or in standby")?,
+        },
+        ["self-test", "limits", min_level_db, max_level_db, max_thd_percent] => {
+            match (
+                min_level_db.parse::<f32>(),
+                max_level_db.parse::<f32>(),
+                max_thd_percent.parse::<f32>(),
+            ) {
+                (Ok(min_level_db), Ok(max_level_db), Ok(max_thd_percent))
+                    if min_level_db <= max_level_db && max_thd_percent > 0.0 =>
+                {
+                    self_test::set_limits(self_test::Limits {
+                        min_level_db,
+                        max_level_db,
+                        max_thd_percent,
+                    });
+                    self_test(out).await?;
+                }
+                _ => reply!(out, "Invalid limits")?,
+            }
+        }
+        ["shutdown"] => {
+            reply!(out, "Shutting down")?;
+            shutdown::request();
+        }
+        ["reboot"] => {
+            reply!(out, "Rebooting")?;
+            system::request_reboot(RebootTarget::Firmware);
+        }
+        ["reboot", "bootloader"] => {
+            reply!(out, "Entering the bootloader")?;
+            system::request_reboot(RebootTarget::Bootloader);
+        }
+        ["factory-reset"] => reply!(out, "Erases all stored data, confirm with: factory-reset confirm")?,
+        ["factory-reset", "confirm"] => {
+            reply!(out, "Factory reset, rebooting")?;
+            system::request_reboot(RebootTarget::FactoryReset);
+        }
+        ["log", level] => match parse_level(level) {
+            Some(level) => {
+                console::set_log_level(level);
+                reply!(out, "Log level: {}", level.name())?;
+            }
+            None => reply!(out, "Invalid log level")?,
+        },
+        _ => reply!(out, "Unknown command, type 'help' for a list of commands")?,
+    }
+
+    Ok(())
+}
+
+async fn volume<W: Write>(out: &mut W) -> Result<(), W::Error> {
+    let attenuation = CONTROL.attenuation();
+
+    if attenuation == control::MUTED_ATTENUATION {
+        reply!(out, "Volume: muted")
+    } else {
+        reply!(out, "Volume: {:.1} dB", attenuation_db(attenuation))
+    }
+}
+
+async fn pot<W: Write>(out: &mut W) -> Result<(), W::Error> {
+    let mut line: String<MAX_OUTPUT_LENGTH> = String::new();
+
+    match CONTROL.input_attenuation() {
+        control::MUTED_ATTENUATION => _ = line.push_str("muted"),
+        attenuation => _ = write!(line, "{:.1} dB", attenuation_db(attenuation)),
+    }
+
+    reply!(out, "Potentiometer: {}, range {} dB", line, potentiometer::range_db())?;
+
+    line.clear();
+    match potentiometer::second_target() {
+        SecondTarget::Off => return reply!(out, "Second potentiometer: off"),
+        SecondTarget::Balance => _ = line.push_str("balance"),
+        SecondTarget::Level(mask) => {
+            _ = line.push_str("level of channels");
+            for channel in (0..OUTPUT_CHANNEL_COUNT).filter(|channel| mask & (1 << channel) != 0) {
+                _ = write!(line, " {}", channel);
+            }
+        }
+    }
+
+    _ = line.push_str(", adjustments");
+    for channel in 0..OUTPUT_CHANNEL_COUNT {
+        _ = write!(line, " {:.1}", potentiometer::adjustment_db(channel));
+    }
+
+    reply!(out, "Second potentiometer: {} dB", line)
+}
+
+async fn source<W: Write>(out: &mut W) -> Result<(), W::Error> {
+    reply!(
+        out,
+        "Source: {} (active: {})",
+        control::source_selection_name(CONTROL.source_selection()),
+        control::source_name(CONTROL.active_source())
+    )
+}
+
+/// Write temperatures in °C as a line, with `-` for missing ones.
+fn write_temperatures(line: &mut impl fmt::Write, microcontroller: Option<f32>, amplifiers: &[Option<f32>]) {
+    for temperature in core::iter::once(&microcontroller).chain(amplifiers) {
+        _ = match temperature {
+            Some(temperature) => write!(line, " {:6.1}", temperature),
+            None => write!(line, " {:>6}", "-"),
+        };
+    }
+}
+
+async fn thermal<W: Write>(out: &mut W) -> Result<(), W::Error> {
+    let amplifiers: [Option<f32>; OUTPUT_CHANNEL_COUNT] = core::array::from_fn(thermal::temperature);
+
+    reply!(out, "{:<15}    MCU  Amp 0  Amp 1  Amp 2  Amp 3", "Temperature C:")?;
+
+    let mut line: String<MAX_OUTPUT_LENGTH> = String::new();
+    _ = line.write_str("now:           ");
+    write_temperatures(&mut line, thermal::microcontroller_temperature(), &amplifiers);
+    reply!(out, "{}", line)?;
+
+    let now = Instant::now();
+    for index in (0..thermal::history_len()).rev() {
+        let Some(entry) = thermal::history_entry(index) else {
+            continue;
+        };
+
+        line.clear();
+        _ = write!(line, "{:>5} min ago: ", (now - entry.time).as_secs() / 60);
+        write_temperatures(&mut line, entry.microcontroller, &entry.amplifiers);
+        reply!(out, "{}", line)?;
+    }
+
+    Ok(())
+}
+
+async fn thermal_log<W: Write>(out: &mut W) -> Result<(), W::Error> {
+    let statistics = match thermal_log::statistics() {
+        Ok(statistics) => statistics,
+        Err(error) => return reply!(out, "Failed to read the thermal log: {:?}", error),
+    };
+
+    reply!(
+        out,
+        "Thermal log: {:.1} h of operation, {:.1} h throttled, {} records",
+        statistics.duration_s as f32 / 3600.0,
+        statistics.throttled_s as f32 / 3600.0,
+        thermal_log::record_count().unwrap_or_default()
+    )?;
+    reply!(out, "{:<15}    MCU  Amp 0  Amp 1  Amp 2  Amp 3", "Temperature C:")?;
+
+    let mut line: String<MAX_OUTPUT_LENGTH> = String::new();
+    for (label, temperatures) in [("lowest:", statistics.minimum_c), ("highest:", statistics.maximum_c)] {
+        let temperatures = temperatures.map(|temperature| temperature.map(f32::from));
+
+        line.clear();
+        _ = write!(line, "{:<15}", label);
+        write_temperatures(&mut line, temperatures[0], &temperatures[1..]);
+        reply!(out, "{}", line)?;
+    }
+
+    Ok(())
+}
+
+async fn auto_standby<W: Write>(out: &mut W) -> Result<(), W::Error> {
+    match auto_standby::delay() {
+        Some(delay) => reply!(out, "Auto-standby: after {} min without signal", delay.as_secs() / 60),
+        None => reply!(out, "Auto-standby: off"),
+    }
+}
+
+async fn clip_protection<W: Write>(out: &mut W) -> Result<(), W::Error> {
+    match clip_protection::step_half_db() {
+        0 => reply!(out, "Clip protection: off"),
+        step => reply!(out, "Clip protection: {:.1} dB", step as f32 / 2.0),
+    }
+}
+
+async fn faults<W: Write>(out: &mut W) -> Result<(), W::Error> {
+    let active = faults::active();
+
+    if active == 0 {
+        reply!(out, "Faults: none")?;
+    }
+
+    for fault in Fault::ALL.into_iter().filter(|fault| active & fault.mask() != 0) {
+        reply!(
+            out,
+            "Fault: {} (blink code {})",
+            faults::fault_name(fault),
+            fault.blink_count()
+        )?;
+    }
+
+    if let Some(message) = panic_log::last_panic() {
+        reply!(out, "Panic before the last reset: {}", message)?;
+    }
+
+    Ok(())
+}
+
+async fn output_power<W: Write>(out: &mut W) -> Result<(), W::Error> {
+    for channel in 0..OUTPUT_CHANNEL_COUNT {
+        reply!(
+            out,
+            "Power {}: {:.2} W into {} ohm",
+            channel,
+            output_power::power_w(channel),
+            output_power::load_ohm(channel)
+        )?;
+    }
+
+    Ok(())
+}
+
+async fn led<W: Write>(out: &mut W) -> Result<(), W::Error> {
+    let mut line: String<MAX_OUTPUT_LENGTH> = String::new();
+
+    if let Some(delay) = led::night_delay() {
+        _ = write!(line, ", after {} min without operation", delay.as_secs() / 60);
+    }
+
+    if let Some((start, end)) = led::night_hours() {
+        _ = write!(line, ", {:02}:00 to {:02}:00 UTC", start, end);
+    }
+
+    reply!(
+        out,
+        "LED brightness: {} %, night {} %{} (night mode {}), showing the {}",
+        led::brightness(),
+        led::night_brightness(),
+        line,
+        if led::night() { "on" } else { "off" },
+        if led::meter_mode() { "output level" } else { "source" }
+    )
+}
+
+async fn led_map<W: Write>(out: &mut W) -> Result<(), W::Error> {
+    for (function, output) in LedFunction::ALL.into_iter().zip(led::led_map()) {
+        match output {
+            LedOutput::None => reply!(out, "LED {:<9} none", led::function_name(function))?,
+            LedOutput::Mcu(index) => reply!(out, "LED {:<9} mcu {}", led::function_name(function), index)?,
+            LedOutput::Expander(output) => reply!(out, "LED {:<9} expander {}", led::function_name(function), output)?,
+        }
+    }
+
+    Ok(())
+}
+
+async fn touch<W: Write>(out: &mut W) -> Result<(), W::Error> {
+    reply!(out, "Touch threshold: {} %", touch::threshold_percent())?;
+
+    for (pad, status) in TouchPad::ALL.into_iter().zip(touch::status()) {
+        match status.baseline {
+            Some(baseline) => reply!(
+                out,
+                "Touch {:<11} count {}, baseline {:.0}{}",
+                touch::pad_name(pad),
+                status.count,
+                baseline,
+                if status.touched { ", touched" } else { "" }
+            )?,
+            None => reply!(out, "Touch {:<11} absent", touch::pad_name(pad))?,
+        }
+    }
+
+    Ok(())
+}
+
+async fn rgb<W: Write>(out: &mut W) -> Result<(), W::Error> {
+    match rgb_led::led_count() {
+        0 => reply!(out, "Addressable LEDs: off"),
+        count => reply!(
+            out,
+            "Addressable LEDs: {} ({})",
+            count,
+            if rgb_led::white_channel() { "RGBW" } else { "RGB" }
+        ),
+    }
+}
+
+async fn display<W: Write>(out: &mut W) -> Result<(), W::Error> {
+    if !display::present() {
+        return reply!(out, "Display: absent");
+    }
+
+    let saver = if display::dim() { "dims" } else { "blanks" };
+
+    match display::timeout() {
+        None => reply!(out, "Display: present, no screensaver"),
+        Some(timeout) => reply!(
+            out,
+            "Display: present, {} after {} s without control activity (screensaver {})",
+            saver,
+            timeout.as_secs(),
+            if display::idle() { "on" } else { "off" }
+        ),
+    }
+}
+
+async fn low_power<W: Write>(out: &mut W) -> Result<(), W::Error> {
+    reply!(
+        out,
+        "Low power: {}, stopped {} times",
+        if low_power::enabled() { "on" } else { "off" },
+        low_power::stop_count()
+    )
+}
+
+async fn self_test<W: Write>(out: &mut W) -> Result<(), W::Error> {
+    match self_test::state() {
+        self_test::State::Idle => reply!(out, "Self-test: not run")?,
+        self_test::State::Running(channel) => reply!(out, "Self-test: running on channel {}", channel)?,
+        self_test::State::Done => reply!(out, "Self-test: done")?,
+        self_test::State::Aborted => reply!(out, "Self-test: aborted")?,
+    }
+
+    let limits = self_test::limits();
+    reply!(
+        out,
+        "Limits: {:.1} to {:.1} dBFS, {:.2} % THD",
+        limits.min_level_db,
+        limits.max_level_db,
+        limits.max_thd_percent
+    )?;
+
+    for (channel, result) in self_test::results().iter().enumerate() {
+        match result {
+            Some(result) => reply!(
+                out,
+                "Channel {}: {}, {:.1} dBFS, {:.1} dB crosstalk, {:.3} % THD",
+                channel,
+                self_test::verdict_name(result.verdict),
+                result.level_db,
+                result.crosstalk_db,
+                result.thd_percent
+            )?,
+            None => reply!(out, "Channel {}: not measured", channel)?,
+        }
+    }
+
+    Ok(())
+}
+
+async fn amplifier_gain<W: Write>(out: &mut W) -> Result<(), W::Error> {
+    reply!(out, "Amplifier gain: {:.1} dBV", amplifiers::gain().dbv())
+}
+
+async fn supply<W: Write>(out: &mut W) -> Result<(), W::Error> {
+    let voltage = supply::voltage_mv() as f32 / 1000.0;
+
+    match supply::threshold_mv() {
+        0 => reply!(out, "Supply: {:.2} V, no threshold", voltage),
+        threshold_mv => reply!(
+            out,
+            "Supply: {:.2} V, threshold {:.2} V{}",
+            voltage,
+            threshold_mv as f32 / 1000.0,
+            if supply::undervoltage() { ", locked out" } else { "" }
+        ),
+    }
+}
+
+async fn source_volume<W: Write>(out: &mut W) -> Result<(), W::Error> {
+    let mut line: String<MAX_OUTPUT_LENGTH> = String::new();
+    _ = write!(
+        line,
+        "Volume per source: {}",
+        if CONTROL.source_volume() { "on" } else { "off" }
+    );
+
+    for (source, attenuation) in CONTROL.source_attenuations().into_iter().enumerate() {
+        let Ok(source) = AudioSource::try_from(source as u8) else {
+            continue;
+        };
+
+        if attenuation != NO_SOURCE_ATTENUATION {
+            _ = write!(
+                line,
+                ", {} {:.1} dB",
+                control::source_name(source),
+                attenuation_db(attenuation)
+            );
+        }
+    }
+
+    reply!(out, "{}", line)
+}
+
+async fn store_script<W: Write>(script: &str, out: &mut W) -> Result<(), W::Error> {
+    match startup_script::store(script) {
+        Ok(()) => reply!(out, "OK"),
+        Err(error) => reply!(out, "Failed to store the startup commands: {:?}", error),
+    }
+}
+
+async fn config_result<W: Write>(
+    result: Result<(), audio::filter_config::ConfigError>,
+    out: &mut W,
+) -> Result<(), W::Error> {
+    match result {
+        Ok(()) => reply!(out, "OK"),
+        Err(error) => reply!(out, "Invalid configuration: {:?}", error),
+    }
+}
+
+async fn calibration_result<W: Write>(
+    result: Result<(), protocol::calibration::Error>,
+    out: &mut W,
+) -> Result<(), W::Error> {
+    match result {
+        Ok(()) => reply!(out, "OK"),
+        Err(error) => reply!(out, "Invalid calibration: {:?}", error),
+    }
+}
+
+async fn preset_error<W: Write>(error: presets::Error, out: &mut W) -> Result<(), W::Error> {
+    match error {
+        presets::Error::InvalidName => reply!(
+            out,
+            "Invalid name (up to {} printable characters)",
+            presets::MAX_NAME_LENGTH
+        ),
+        presets::Error::InvalidNotes => reply!(
+            out,
+            "Invalid notes (up to {} printable characters)",
+            presets::MAX_NOTES_LENGTH
+        ),
+        presets::Error::NotFound => reply!(out, "No such preset"),
+        error => reply!(out, "Failed to modify the preset: {:?}", error),
+    }
+}
+
+async fn calibration_show<W: Write>(out: &mut W) -> Result<(), W::Error> {
+    /// Format values in a line, after a label.
+    fn values(label: &str, values: &[f32], precision: usize) -> String<MAX_OUTPUT_LENGTH> {
+        let mut line = String::new();
+        _ = line.push_str(label);
+
+        for value in values {
+            _ = write!(line, " {:.*}", precision, value);
+        }
+
+        line
+    }
+
+    let calibration = calibration::calibration();
+
+    reply!(out, "{}", values("Taper:", &calibration.taper, 3))?;
+    reply!(out, "{}", values("Trim (dB):", &calibration.output_trim_db, 2))?;
+    reply!(
+        out,
+        "{}",
+        values("Temperature offsets (C):", &calibration.temperature_offsets, 1)
+    )
+}
+
+async fn eq_show<W: Write>(channel: usize, out: &mut W) -> Result<(), W::Error> {
+    let config = dsp::dsp_config();
+    let filter = &config[channel];
+
+    reply!(
+        out,
+        "Channel {}: gain {:.1} dB{}, delay {} samples",
+        channel,
+        filter.gain_db,
+        if filter.inverted { ", inverted" } else { "" },
+        filter.delay
+    )?;
+
+    for (index, stage) in filter.stages.iter().enumerate() {
+        match *stage {
+            StageConfig::Parametric {
+                kind,
+                frequency_hz,
+                q,
+                gain_db,
+            } => {
+                if kind.has_gain() {
+                    reply!(
+                        out,
+                        "  {:>2}: {:<5} {:>8.1} Hz  Q {:.2}  {:.1} dB",
+                        index,
+                        kind.name(),
+                        frequency_hz,
+                        q,
+                        gain_db
+                    )?;
+                } else {
+                    reply!(
+                        out,
+                        "  {:>2}: {:<5} {:>8.1} Hz  Q {:.2}",
+                        index,
+                        kind.name(),
+                        frequency_hz,
+                        q
+                    )?;
+                }
+            }
+            StageConfig::Coefficients { a1, a2, b0, b1, b2 } => {
+                reply!(
+                    out,
+                    "  {:>2}: coefficients b0 {} b1 {} b2 {} a1 {} a2 {}",
+                    index,
+                    b0,
+                    b1,
+                    b2,
+                    a1,
+                    a2
+                )?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Set a filter stage from the arguments `<channel> <stage> <kind> <Hz> <Q> [<dB>]`.
+///
+/// A stage index at, or beyond the end of the filter chain appends a stage.
+async fn eq_set<W: Write>(words: &[&str], out: &mut W) -> Result<(), W::Error> {
+    let channel = words.first().and_then(|word| parse_channel(word));
+    let index = words.get(1).and_then(|word| word.parse::<usize>().ok());
+    let kind = words.get(2).and_then(|word| StageKind::from_name(word));
+    let frequency_hz = words.get(3).and_then(|word| word.parse::<f32>().ok());
+    let q = words.get(4).and_then(|word| word.parse::<f32>().ok());
+    let gain_db = match words.get(5) {
+        Some(word) => word.parse::<f32>().ok(),
+        None => Some(0.0),
+    };
+
+    let (Some(channel), Some(index), Some(kind), Some(frequency_hz), Some(q), Some(gain_db)) =
+        (channel, index, kind, frequency_hz, q, gain_db)
+    else {
+        return reply!(out, "Usage: eq set <channel> <stage> <kind> <Hz> <Q> [<dB>]");
+    };
+
+    let stage = StageConfig::Parametric {
+        kind,
+        frequency_hz,
+        q,
+        gain_db,
+    };
+
+    let mut full = false;
+    let result = dsp::update_dsp_config(|config| {
+        let stages = &mut config[channel].stages;
+
+        if index < stages.len() {
+            stages[index] = stage;
+        } else {
+            full = stages.push(stage).is_err();
+        }
+    });
+
+    if full {
+        reply!(out, "No free filter stages")
+    } else {
+        config_result(result, out).await
+    }
+}
+
+async fn slot<W: Write>(out: &mut W) -> Result<(), W::Error> {
+    let staged = if config_slots::staged() { "staged" } else { "empty" };
+    reply!(
+        out,
+        "Active slot: {:?}, slot B: {}",
+        config_slots::active_slot(),
+        staged
+    )
+}
+
+async fn info<W: Write>(out: &mut W) -> Result<(), W::Error> {
+    let info = device_info();
+    let [major, minor, patch] = info.version;
+
+    reply!(out, "Firmware: {}.{}.{} ({})", major, minor, patch, info.git_hash_str())?;
+    reply!(out, "Build date: {}", info.build_date_str())?;
+    reply!(out, "Hardware revision: {}", info.hardware_revision)?;
+
+    let mut unique_id: String<{ 2 * UNIQUE_ID_SIZE }> = String::new();
+    for byte in info.unique_id {
+        _ = write!(unique_id, "{:02x}", byte);
+    }
+    reply!(out, "Unique ID: {}", unique_id)?;
+
+    match provisioning::provisioning() {
+        Some(provisioning) => {
+            let [a, b, c, d, e, f] = provisioning.device_id;
+            reply!(out, "Serial number: {}", provisioning.serial_number())?;
+            reply!(
+                out,
+                "Device ID: {:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
+                a,
+                b,
+                c,
+                d,
+                e,
+                f
+            )?;
+        }
+        None => reply!(out, "Serial number: not provisioned")?,
+    }
+
+    if system::boot_loop_detected() {
+        reply!(out, "Boot loop detected, started with the default signal processing")?;
+    }
+
+    reply!(out, "Uptime: {} s", info.uptime_s)
+}
+
+/// Print the handshake, which exceeds the usual output length with all features.
+async fn handshake<W: Write>(out: &mut W) -> Result<(), W::Error> {
+    let handshake = device_info::handshake();
+    let [protocol_major, protocol_minor] = handshake.protocol_version;
+    let [settings_major, settings_minor] = handshake.settings_version;
+
+    let mut line: String<256> = String::new();
+    _ = write!(
+        line,
+        "{{\"protocol\":[{},{}],\"settings\":[{},{}],\"registers\":{},\"config\":{},\"parameters\":{},\"features\":[",
+        protocol_major,
+        protocol_minor,
+        settings_major,
+        settings_minor,
+        handshake.register_map_version,
+        handshake.config_version,
+        handshake.parameter_count,
+    );
+
+    for (index, feature) in handshake.supported_features().enumerate() {
+        let separator = if index > 0 { "," } else { "" };
+        _ = write!(line, "{}\"{}\"", separator, device_info::feature_name(feature));
+    }
+
+    _ = line.write_str("]}");
+    out.write_all(line.as_bytes()).await?;
+    out.write_all(b"\r\n").await
+}
+
+async fn events<W: Write>(count: usize, out: &mut W) -> Result<(), W::Error> {
+    let entry_count = event_log::entry_count();
+
+    reply!(
+        out,
+        "Event log: {} entries, boot {}, {} dropped",
+        entry_count,
+        event_log::boot(),
+        event_log::dropped_count()
+    )?;
+
+    for index in entry_count.saturating_sub(count)..entry_count {
+        match event_log::entry(index) {
+            Some(entry) => reply!(
+                out,
+                "{}: boot {}, {}.{:03} s, {} ({}), {}x",
+                index,
+                entry.boot,
+                entry.time_ms / 1000,
+                entry.time_ms % 1000,
+                event_log::kind_name(entry.kind),
+                event_log::argument_text(&entry),
+                entry.count
+            )?,
+            None => reply!(out, "{}: damaged", index)?,
+        }
+    }
+
+    Ok(())
+}
+
+async fn cpu<W: Write>(out: &mut W) -> Result<(), W::Error> {
+    let mut line: String<MAX_OUTPUT_LENGTH> = String::new();
+    _ = write!(line, "CPU: {:.1} %", cpu_load::total_load_percent());
+
+    for section in cpu_load::Section::ALL {
+        _ = write!(
+            line,
+            ", {} {:.1} %",
+            cpu_load::section_name(section),
+            cpu_load::load_percent(section)
+        );
+    }
+
+    reply!(out, "{}", line)?;
+    reply!(
+        out,
+        "DSP headroom: {:.1} % of the block period",
+        cpu_load::dsp_headroom_percent()
+    )
+}
+
+async fn latency<W: Write>(out: &mut W) -> Result<(), W::Error> {
+    let mode = latency::mode();
+
+    reply!(
+        out,
+        "Latency: {}, {} frames per block, about {} us",
+        latency::mode_name(mode),
+        latency::frame_count(mode),
+        latency::latency_us(mode)
+    )
+}
+
+async fn stats<W: Write>(out: &mut W) -> Result<(), W::Error> {
+    let uptime_s = Instant::now().as_secs();
+
+    reply!(
+        out,
+        "Uptime: {}:{:02}:{:02}",
+        uptime_s / 3600,
+        (uptime_s / 60) % 60,
+        uptime_s % 60
+    )?;
+    source(out).await?;
+    volume(out).await?;
+    reply!(
+        out,
+        "Volume set by: {}",
+        control::volume_writer_name(CONTROL.volume_writer())
+    )?;
+    reply!(out, "Mute: {}", if CONTROL.muted() { "on" } else { "off" })?;
+    reply!(
+        out,
+        "Standby: {}",
+        match (CONTROL.standby(), auto_standby::auto_standby()) {
+            (true, true) => "on (no signal)",
+            (true, false) => "on",
+            (false, _) => "off",
+        }
+    )?;
+    reply!(
+        out,
+        "Amplifiers: {}, gain {:.1} dBV",
+        amplifiers::state_name(amplifiers::state()),
+        amplifiers::gain().dbv()
+    )?;
+    reply!(
+        out,
+        "Mute relay: {}",
+        if mute_relay::closed() { "closed" } else { "open" }
+    )?;
+
+    let protected = CONTROL.protected_amplifiers();
+    for channel in (0..OUTPUT_CHANNEL_COUNT).filter(|channel| protected & 1 << channel != 0) {
+        reply!(out, "Amplifier {}: muted after faults", channel)?;
+    }
+
+    for channel in 0..OUTPUT_CHANNEL_COUNT {
+        if let Some(temperature) = thermal::temperature(channel) {
+            reply!(
+                out,
+                "Amplifier {}: {:.1} C, throttled by {:.1} dB",
+                channel,
+                temperature,
+                thermal::throttle_db(channel)
+            )?;
+        }
+    }
+
+    if let Some(temperature) = thermal::microcontroller_temperature() {
+        reply!(out, "Microcontroller: {:.1} C", temperature)?;
+    }
+
+    output_power(out).await?;
+    supply(out).await?;
+    faults(out).await?;
+    reply!(out, "Buffer: {}/{}", CONTROL.buffer_fill(), SAMPLE_BLOCK_COUNT)?;
+    reply!(
+        out,
+        "Underruns: {}, overruns: {}, dropped blocks: {}, SAI restarts: {}",
+        STATS.underrun_count(),
+        STATS.overrun_count(),
+        STATS.dropped_block_count(),
+        STATS.sai_restart_count()
+    )?;
+    cpu(out).await?;
+
+    for channel in 0..OUTPUT_CHANNEL_COUNT {
+        let level = CONTROL.meter_level(channel);
+
+        if level == control::MUTED_ATTENUATION {
+            reply!(out, "Level {}: silent", channel)?;
+        } else {
+            reply!(out, "Level {}: {:.1} dBFS", channel, attenuation_db(level))?;
+        }
+    }
+
+    Ok(())
+}
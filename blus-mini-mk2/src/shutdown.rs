@@ -0,0 +1,66 @@
+//! Ordered shutdown, after which the device can be switched off without losing its state.
+//!
+//! A power button maps its short press to [`ButtonAction::Standby`], and its long press to
+//! [`ButtonAction::Shutdown`] (shell `button map`). The shutdown goes through standby, in order:
+//! 1. Standby mutes the output (see [`crate::control::Control::silent`]), and the audio routing releases the
+//!    amplifiers, which power down within [`POWER_DOWN_TIMEOUT`] (see [`crate::amplifiers`]).
+//! 2. The configuration is saved (see [`crate::settings_store`]), and pending events and temperatures are written
+//!    (see [`crate::event_log`], and [`crate::thermal_log`]).
+//!
+//! The device stays in standby afterwards, until it is left by any means (e.g. a short press of the power button).
+//!
+//! [`ButtonAction::Standby`]: protocol::button::ButtonAction::Standby
+//! [`ButtonAction::Shutdown`]: protocol::button::ButtonAction::Shutdown
+use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{with_timeout, Duration, Timer};
+use protocol::event_log::EventKind;
+
+use crate::amplifiers::{self, AmplifierState};
+use crate::control::CONTROL;
+use crate::event_log;
+use crate::settings_store;
+use crate::thermal_log;
+use crate::*;
+
+/// The time for the amplifiers to power down, after entering standby.
+pub const POWER_DOWN_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Signal that is emitted when a shutdown is requested.
+static SHUTDOWN_SIGNAL: Signal<ThreadModeRawMutex, ()> = Signal::new();
+
+/// Request an ordered shutdown.
+pub fn request() {
+    SHUTDOWN_SIGNAL.signal(());
+}
+
+/// Performs the ordered shutdown on request.
+#[embassy_executor::task]
+pub async fn shutdown_task() {
+    loop {
+        SHUTDOWN_SIGNAL.wait().await;
+        log!(info, "Shutting down");
+
+        CONTROL.set_standby(true);
+
+        let powered_down = with_timeout(POWER_DOWN_TIMEOUT, async {
+            while !matches!(amplifiers::state(), AmplifierState::Off | AmplifierState::Error(_)) {
+                Timer::after_millis(10).await;
+            }
+        });
+
+        if powered_down.await.is_err() {
+            log!(warn, "Amplifiers did not power down");
+        }
+
+        if let Err(error) = settings_store::save() {
+            log!(warn, "Failed to save settings: {:?}", error);
+        }
+
+        event_log::record(EventKind::Shutdown, 0);
+        event_log::flush();
+        thermal_log::flush();
+
+        log!(info, "Shut down, the device can be switched off");
+    }
+}
@@ -0,0 +1,186 @@
+//! SPI slave interface that mirrors the control register map of the I2C slave interface.
+//!
+//! Every transaction starts with a command byte: bit 7 selects a read (1) or write (0),
+//! bits 6..0 hold the register address.
+//! - For writes, all following bytes are written to consecutive registers.
+//! - For reads, the second byte is a turnaround byte. Register values are returned from the third byte on.
+//!
+//! The device returns the status register in the first byte of every transaction. The host must
+//! wait at least 10 us after asserting NSS, before clocking out data.
+//!
+//! The (active low) interrupt line is asserted when the device status changes,
+//! and released at the start of the next transaction.
+use core::cell::Cell;
+
+use defmt::{info, trace};
+use embassy_futures::select::{select, Either};
+use embassy_stm32::exti::ExtiInput;
+use embassy_stm32::gpio::{Level, Output, Pull, Speed};
+use embassy_stm32::mode::Blocking;
+use embassy_stm32::pac::spi::vals;
+use embassy_stm32::spi::{self, Spi};
+use embassy_stm32::{interrupt, pac, peripherals};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+
+use crate::control::STATUS_CHANGED_SIGNAL;
+use crate::registers::{self, RegisterAddress};
+
+/// Marks a read command.
+const READ_COMMAND: u8 = 0x80;
+
+/// The state of the current SPI transaction.
+#[derive(Clone, Copy)]
+enum Transaction {
+    /// Waiting for the command byte.
+    Command,
+    /// Writing to consecutive registers.
+    Write(RegisterAddress),
+    /// Reading from consecutive registers.
+    Read(RegisterAddress),
+}
+
+static TRANSACTION: Mutex<CriticalSectionRawMutex, Cell<Transaction>> = Mutex::new(Cell::new(Transaction::Command));
+
+/// Resources that are required for the SPI slave interface.
+#[allow(missing_docs)]
+pub struct SpiSlaveResources {
+    pub spi: peripherals::SPI3,
+
+    pub sck: peripherals::PC10,
+    pub miso: peripherals::PC11,
+    pub mosi: peripherals::PC12,
+    pub nss: peripherals::PA15,
+    pub nss_exti: peripherals::EXTI15,
+
+    pub irq: peripherals::PD1,
+}
+
+/// Push a byte into the transmit FIFO.
+fn transmit(regs: pac::spi::Spi, value: u8) {
+    // Use byte access, since word access pushes four data frames.
+    regs.txdr8().write_value(value);
+}
+
+/// Prepare the peripheral for a new transaction.
+///
+/// Flushes stale FIFO contents and primes the transmit FIFO with the status and turnaround bytes.
+/// From then on, one byte is pushed for every byte that is received, such that the transmit FIFO
+/// never runs empty.
+fn begin_transaction(regs: pac::spi::Spi) {
+    critical_section::with(|cs| TRANSACTION.borrow(cs).set(Transaction::Command));
+
+    regs.cr1().modify(|w| w.set_spe(false));
+    regs.ifcr().write(|w| {
+        w.set_udrc(true);
+        w.set_ovrc(true);
+    });
+    regs.cr1().modify(|w| w.set_spe(true));
+
+    transmit(regs, registers::read(registers::STATUS_REGISTER));
+    transmit(regs, 0x00);
+
+    // Select the slave by software.
+    regs.cr1().modify(|w| w.set_ssi(false));
+}
+
+/// Finish the current transaction.
+fn end_transaction(regs: pac::spi::Spi) {
+    regs.cr1().modify(|w| w.set_ssi(true));
+}
+
+/// Runs the SPI slave interface, and drives its interrupt line.
+///
+/// Register writes are applied by the [`registers::register_write_task`].
+#[embassy_executor::task]
+pub async fn spi_slave_task(resources: SpiSlaveResources) {
+    // The driver sets up clocks and pins. It does not support slave operation,
+    // so the peripheral is reconfigured on register level below.
+    // The driver must stay alive, since dropping it disables the peripheral.
+    let _spi: Spi<'static, Blocking> = Spi::new_blocking(
+        resources.spi,
+        resources.sck,
+        resources.mosi,
+        resources.miso,
+        spi::Config::default(),
+    );
+
+    // Chip select is managed in software, by following the NSS pin.
+    let mut nss = ExtiInput::new(resources.nss, resources.nss_exti, Pull::Up);
+    let mut irq = Output::new(resources.irq, Level::High, Speed::Low);
+
+    let regs = pac::SPI3;
+
+    regs.cr1().modify(|w| w.set_spe(false));
+    regs.cfg1().modify(|w| {
+        w.set_dsize(8 - 1);
+        w.set_fthlv(vals::Fthlv::ONE_FRAME);
+    });
+    regs.cfg2().modify(|w| {
+        w.set_master(vals::Master::SLAVE);
+        w.set_ssm(true);
+        w.set_ssoe(false);
+    });
+    regs.cr1().modify(|w| w.set_ssi(true));
+    regs.ier().write(|w| w.set_rxpie(true));
+    regs.cr1().modify(|w| w.set_spe(true));
+
+    unsafe {
+        cortex_m::peripheral::NVIC::unmask(interrupt::SPI3);
+    }
+
+    info!("SPI slave ready");
+
+    loop {
+        match select(nss.wait_for_falling_edge(), STATUS_CHANGED_SIGNAL.wait()).await {
+            Either::First(_) => {
+                // The status is transmitted with the first byte of the transaction.
+                irq.set_high();
+
+                begin_transaction(regs);
+                nss.wait_for_rising_edge().await;
+                end_transaction(regs);
+            }
+            Either::Second(_) => irq.set_low(),
+        }
+    }
+}
+
+#[interrupt]
+fn SPI3() {
+    critical_section::with(|cs| {
+        let regs = pac::SPI3;
+        let transaction = TRANSACTION.borrow(cs);
+
+        while regs.sr().read().rxp() {
+            // Use byte access, since word access pops four data frames.
+            let value = regs.rxdr8().read();
+
+            let (next_transaction, response) = match transaction.get() {
+                Transaction::Command => {
+                    let address = value & !READ_COMMAND;
+
+                    if value & READ_COMMAND != 0 {
+                        // The first register value is transmitted with the third byte.
+                        (Transaction::Read(address.wrapping_add(1)), registers::read(address))
+                    } else {
+                        (Transaction::Write(address), 0x00)
+                    }
+                }
+                Transaction::Write(address) => {
+                    registers::queue_write(address, value);
+                    (Transaction::Write(address.wrapping_add(1)), 0x00)
+                }
+                Transaction::Read(address) => (Transaction::Read(address.wrapping_add(1)), registers::read(address)),
+            };
+
+            transaction.set(next_transaction);
+            transmit(regs, response);
+        }
+
+        if regs.sr().read().ovr() {
+            trace!("SPI slave: Overrun");
+            regs.ifcr().write(|w| w.set_ovrc(true));
+        }
+    });
+}
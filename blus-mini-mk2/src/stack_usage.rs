@@ -0,0 +1,88 @@
+//! Instrumentation of the stack usage, with the `stack_usage` feature.
+//!
+//! All tasks of all executors run on the one stack of the core: the thread executor polls its tasks on it, and the
+//! interrupt executors (e.g. of the audio routing) preempt it on the same stack. Its high-water mark is therefore the
+//! deepest poll of a thread task, plus the deepest nesting of interrupts. At startup, [`paint`] fills the free stack
+//! with a pattern; the lowest word that no longer holds the pattern is the high-water mark since. It is reported in
+//! telemetry (see [`crate::telemetry`]), together with the size of the stack (see [`crate::memory_usage`]).
+//!
+//! The state of the tasks is not on the stack: the executor allocates it from its arena at spawn time, which is sized
+//! by the `task-arena-size` feature of `embassy-executor` (see [`TASK_ARENA_SIZE`]). The linker script places the
+//! arena in its own section (see `memory.x`), such that [`task_arena_usage`] can read how far it is allocated. It is
+//! reported in telemetry next to the stack, for the headroom of tasks spawned later, or of a smaller arena.
+use core::ptr::addr_of;
+
+/// The size of the task arena in byte, as selected by the `task-arena-size-65536` feature of `embassy-executor`.
+pub const TASK_ARENA_SIZE: usize = 65536;
+
+/// The pattern of unused stack words.
+const PATTERN: u32 = 0xCCCC_CCCC;
+
+/// The bytes below the stack pointer that stay unpainted, for the frame of [`paint`] itself.
+const GUARD: usize = 256;
+
+extern "C" {
+    /// The end of all sections in RAM, the lowest address of the stack (see `cortex-m-rt`).
+    static __sheap: u32;
+    /// The start of the stack, at the end of RAM.
+    static _stack_start: u32;
+    /// The start of the task arena (see `memory.x`).
+    static __stask_arena: u32;
+    /// The end of the task arena.
+    static __etask_arena: u32;
+}
+
+/// The lowest address of the stack.
+fn bottom() -> usize {
+    addr_of!(__sheap) as usize
+}
+
+/// The highest address of the stack, where it starts.
+fn top() -> usize {
+    addr_of!(_stack_start) as usize
+}
+
+/// Paint the free stack below the current stack pointer with the pattern.
+///
+/// Must run early in `main`, before any task is spawned, and before interrupts that use deep stack are enabled.
+pub fn paint() {
+    let stack_pointer = cortex_m::register::msp::read() as usize;
+
+    for address in (bottom()..stack_pointer.saturating_sub(GUARD)).step_by(size_of::<u32>()) {
+        // SAFETY: The words below the stack pointer are not in use, and above the static data.
+        unsafe { (address as *mut u32).write_volatile(PATTERN) };
+    }
+}
+
+/// The size of the stack in byte.
+pub fn stack_size() -> usize {
+    top().saturating_sub(bottom())
+}
+
+/// The deepest stack usage in byte since the stack was painted.
+pub fn high_water_mark() -> usize {
+    let unused = (bottom()..top())
+        .step_by(size_of::<u32>())
+        // SAFETY: The words of the stack are always readable; a stale read only shifts the mark by a word.
+        .take_while(|&address| unsafe { (address as *const u32).read_volatile() } == PATTERN)
+        .count();
+
+    stack_size().saturating_sub(unused * size_of::<u32>())
+}
+
+/// The allocated bytes of the task arena.
+///
+/// The arena of `embassy-executor` is its buffer, followed by the pointer to the next free byte, which stays null
+/// until the first spawn.
+pub fn task_arena_usage() -> usize {
+    let start = addr_of!(__stask_arena) as usize;
+    let end = addr_of!(__etask_arena) as usize;
+    if end - start != TASK_ARENA_SIZE + size_of::<usize>() {
+        // The arena is not in its section, or its layout changed.
+        return 0;
+    }
+
+    // SAFETY: The pointer is a word in RAM, which the executor only writes in a critical section at spawn time.
+    let next = unsafe { ((start + TASK_ARENA_SIZE) as *const usize).read_volatile() };
+    next.saturating_sub(start)
+}
@@ -0,0 +1,146 @@
+//! The ordered startup of the clocks, the amplifier control, USB, and the audio pipeline.
+//!
+//! `main` brings up one [`Step`] after another: it starts the part of the step (e.g. spawns its task), and waits with
+//! [`bring_up`] until the part reports that it runs (see [`complete`]), before it starts the next. All parts run from
+//! the clocks, and the amplifiers power up on requests of the audio pipeline. USB comes up before the pipeline, but
+//! the pipeline does not depend on it: it also plays the S/PDIF and Raspberry Pi sources.
+//!
+//! A wait is bounded by the [`Step::timeout`] of the step, and repeated up to [`Step::attempts`] times, since a part
+//! that is slow to start (e.g. a USB PHY) may still come up. Every missed attempt is logged. A step that misses all
+//! attempts is recorded in the event log. Without the clocks, the amplifier control, or the audio pipeline, the device
+//! cannot play, so their failure panics, which resets the firmware and counts towards a boot loop (see
+//! [`crate::system`]). Without USB, the other sources still play, so the startup continues.
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use embassy_stm32::pac;
+use embassy_stm32::pac::rcc::vals::Sw;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{with_timeout, Duration, Timer};
+use protocol::event_log::EventKind;
+
+use crate::event_log;
+use crate::*;
+
+/// The interval at which the clocks are polled.
+const CLOCK_POLL_INTERVAL: Duration = Duration::from_micros(100);
+
+/// The time for the event log to write a startup failure, before the firmware panics.
+const FLUSH_TIME: Duration = Duration::from_millis(100);
+
+/// A step of the startup, in order.
+#[repr(u8)]
+#[derive(Clone, Copy, PartialEq, Debug, defmt::Format)]
+pub enum Step {
+    /// The oscillator and the PLLs of the configuration run, and clock the core (see [`crate::peripheral_config`]).
+    Clocks = 0,
+    /// The amplifier control runs, with the amplifiers shut down (see [`crate::amplifiers`]).
+    Amplifiers = 1,
+    /// The USB peripheral and its PHY are enabled (not yet configured by a host).
+    Usb = 2,
+    /// The audio routing runs its interfaces (see [`crate::audio_routing`]).
+    Pipeline = 3,
+}
+
+impl Step {
+    /// All steps, in order.
+    pub const ALL: [Step; 4] = [Step::Clocks, Step::Amplifiers, Step::Usb, Step::Pipeline];
+
+    /// The bit of the step in a mask of steps.
+    const fn mask(self) -> u8 {
+        1 << self as u8
+    }
+
+    /// The longest wait for the step, per attempt.
+    pub const fn timeout(self) -> Duration {
+        match self {
+            Step::Clocks => Duration::from_millis(10),
+            Step::Amplifiers | Step::Pipeline => Duration::from_millis(100),
+            Step::Usb => Duration::from_millis(500),
+        }
+    }
+
+    /// The number of attempts to wait for the step.
+    pub const fn attempts(self) -> u8 {
+        match self {
+            Step::Clocks | Step::Amplifiers | Step::Pipeline => 3,
+            Step::Usb => 2,
+        }
+    }
+
+    /// Whether the device cannot play without the step.
+    pub const fn required(self) -> bool {
+        !matches!(self, Step::Usb)
+    }
+}
+
+/// The name of a step, as used by the text interfaces.
+pub fn step_name(step: Step) -> &'static str {
+    match step {
+        Step::Clocks => "clocks",
+        Step::Amplifiers => "amplifiers",
+        Step::Usb => "usb",
+        Step::Pipeline => "pipeline",
+    }
+}
+
+/// The mask of completed steps.
+static COMPLETED: AtomicU8 = AtomicU8::new(0);
+
+/// Signals a completed step. The audio pipeline completes from its own executor.
+static COMPLETED_SIGNAL: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// Report that the part of a step runs.
+pub fn complete(step: Step) {
+    COMPLETED.fetch_or(step.mask(), Ordering::Relaxed);
+    COMPLETED_SIGNAL.signal(());
+}
+
+/// Whether the oscillator and the PLLs of the configuration are locked, and the core runs from PLL1 (P).
+fn clocks_running() -> bool {
+    let cr = pac::RCC.cr().read();
+    cr.hserdy() && cr.pllrdy(0) && cr.pllrdy(2) && pac::RCC.cfgr().read().sws() == Sw::PLL1_P
+}
+
+/// Wait until the part of a step runs.
+async fn completed(step: Step) {
+    if step == Step::Clocks {
+        while !clocks_running() {
+            Timer::after(CLOCK_POLL_INTERVAL).await;
+        }
+        return;
+    }
+
+    while COMPLETED.load(Ordering::Relaxed) & step.mask() == 0 {
+        COMPLETED_SIGNAL.wait().await;
+    }
+}
+
+/// Wait for a step, once its part was started, in up to [`Step::attempts`] of [`Step::timeout`]. Returns whether the
+/// step completed. Panics, if a [required](Step::required) step did not.
+pub async fn bring_up(step: Step) -> bool {
+    for attempt in 1..=step.attempts() {
+        if with_timeout(step.timeout(), completed(step)).await.is_ok() {
+            log!(debug, "Startup step {} complete", step_name(step));
+            return true;
+        }
+
+        log!(
+            warn,
+            "Startup step {} not complete (attempt {} of {})",
+            step_name(step),
+            attempt,
+            step.attempts()
+        );
+    }
+
+    event_log::record(EventKind::StartupFailure, step as u8);
+
+    if step.required() {
+        event_log::flush();
+        Timer::after(FLUSH_TIME).await;
+        panic!("Startup step {} failed", step_name(step));
+    }
+
+    false
+}
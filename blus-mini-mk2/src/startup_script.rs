@@ -0,0 +1,143 @@
+//! A script of shell commands that runs at startup, such that headless installations come up in a known state.
+//!
+//! The script is edited with the `startup` shell commands, and stored in flash (see [`crate::storage`]). Every line
+//! is a shell command, for example:
+//!
+//! ```text
+//! source spdif
+//! volume -30
+//! ```
+//!
+//! The script runs once the volume input settled after startup, such that a potentiometer does not override it.
+//! Output of the commands goes to the log.
+use core::convert::Infallible;
+
+use embassy_time::{Duration, Timer};
+use embedded_io_async::{ErrorType, Write};
+use heapless::String;
+use protocol::crc::crc32;
+use protocol::event_log::StoredData;
+
+use crate::event_log;
+use crate::shell;
+use crate::storage::{self, STARTUP_SCRIPT_REGION, WRITE_BLOCK_SIZE};
+use crate::thermal_log;
+use crate::*;
+
+/// The maximum size of the script, including line breaks.
+pub const MAX_SCRIPT_SIZE: usize = 512;
+
+/// The maximum length of a logged line of command output.
+const MAX_OUTPUT_LENGTH: usize = 96;
+
+/// The time after startup, after which the script runs.
+const SETTLE_TIME: Duration = Duration::from_secs(1);
+
+/// Marks a stored script.
+const MAGIC: u32 = 0x5343_5250;
+
+/// The size of the record header: magic number (`u32`), script length (`u16`), reserved (`u16`),
+/// and the CRC-32 of the script (`u32`).
+const HEADER_SIZE: usize = 12;
+
+/// The size of the stored record, in whole write blocks.
+pub const RECORD_SIZE: usize = (HEADER_SIZE + MAX_SCRIPT_SIZE).div_ceil(WRITE_BLOCK_SIZE) * WRITE_BLOCK_SIZE;
+
+/// A script of shell commands, one per line.
+pub type Script = String<MAX_SCRIPT_SIZE>;
+
+/// Load the stored script. Returns an empty script, if none is stored, or the stored one is damaged.
+pub fn load() -> Script {
+    let mut record = [0u8; RECORD_SIZE];
+
+    if let Err(error) = STARTUP_SCRIPT_REGION.read(0, &mut record) {
+        log!(warn, "Failed to read the startup script: {:?}", error);
+        return Script::new();
+    }
+
+    let magic = u32::from_le_bytes([record[0], record[1], record[2], record[3]]);
+    let length = u16::from_le_bytes([record[4], record[5]]) as usize;
+    let crc = u32::from_le_bytes([record[8], record[9], record[10], record[11]]);
+
+    if magic != MAGIC {
+        return Script::new();
+    }
+
+    let text = record
+        .get(HEADER_SIZE..HEADER_SIZE + length)
+        .filter(|text| crc32(text) == crc);
+
+    match text.and_then(|text| core::str::from_utf8(text).ok()) {
+        Some(text) => Script::try_from(text).unwrap_or_default(),
+        None => {
+            event_log::record_corruption(StoredData::StartupScript);
+            Script::new()
+        }
+    }
+}
+
+/// Store a script, replacing the stored one. An empty script is removed.
+pub fn store(script: &str) -> Result<(), storage::Error> {
+    thermal_log::erase_startup_script_region()?;
+
+    if script.is_empty() {
+        return Ok(());
+    }
+
+    let mut record = [0xFFu8; RECORD_SIZE];
+    let length = script.len().min(MAX_SCRIPT_SIZE);
+
+    record[0..4].copy_from_slice(&MAGIC.to_le_bytes());
+    record[4..6].copy_from_slice(&(length as u16).to_le_bytes());
+    record[6..8].copy_from_slice(&[0, 0]);
+    record[8..12].copy_from_slice(&crc32(&script.as_bytes()[..length]).to_le_bytes());
+    record[HEADER_SIZE..HEADER_SIZE + length].copy_from_slice(&script.as_bytes()[..length]);
+
+    STARTUP_SCRIPT_REGION.write(0, &record)
+}
+
+/// The commands of a script, without empty lines.
+pub fn lines(script: &str) -> impl Iterator<Item = &str> {
+    script.lines().map(str::trim).filter(|line| !line.is_empty())
+}
+
+/// Collects command output, and sends it to the log line by line.
+struct LogWriter {
+    line: String<MAX_OUTPUT_LENGTH>,
+}
+
+impl ErrorType for LogWriter {
+    type Error = Infallible;
+}
+
+impl Write for LogWriter {
+    async fn write(&mut self, data: &[u8]) -> Result<usize, Self::Error> {
+        for byte in data {
+            match byte {
+                b'\r' => (),
+                b'\n' => {
+                    log!(info, "Startup script: {}", self.line.as_str());
+                    self.line.clear();
+                }
+                // Overlong output is truncated.
+                _ => _ = self.line.push(*byte as char),
+            }
+        }
+
+        Ok(data.len())
+    }
+}
+
+/// Runs the stored script once, after startup.
+#[embassy_executor::task]
+pub async fn startup_script_task() {
+    Timer::after(SETTLE_TIME).await;
+
+    let script = load();
+    let mut out = LogWriter { line: String::new() };
+
+    for line in lines(&script) {
+        log!(info, "Startup script: > {}", line);
+        _ = shell::execute(line, &mut out).await;
+    }
+}
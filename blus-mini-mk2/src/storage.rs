@@ -0,0 +1,352 @@
+//! Persistent storage in the internal flash memory.
+//!
+//! The upper two sectors of the flash memory are reserved for storage, and excluded from the firmware image (see
+//! `memory.x`). The storage area is divided into regions, one per kind of stored data. Most kinds need far less than a
+//! sector, so regions share sectors: erasing a region erases its sector, and writes back the data of the others (see
+//! [`Region::erase`]).
+//!
+//! Data that must survive every erase, and a power loss meanwhile, is kept in the one-time programmable (OTP) area of
+//! the flash memory instead (see [`OtpRegion`]), which cannot be erased at all.
+//!
+//! The flash memory has a single bank, from which the firmware also executes. Erasing and writing therefore stalls
+//! the processor, including audio playback. Stored data should only be written rarely, and sectors should not be erased
+//! during playback.
+use core::cell::RefCell;
+
+use embassy_stm32::flash::{self, Blocking, Flash, FLASH_BASE, WRITE_SIZE};
+use embassy_stm32::{pac, peripherals};
+use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use grounded::uninit::GroundedArrayCell;
+
+use crate::cache;
+use crate::control::CONTROL;
+use crate::power_fail;
+use crate::watchdog;
+use crate::*;
+
+/// The size of a flash sector, the unit of erasing.
+pub const SECTOR_SIZE: u32 = 128 * 1024;
+
+/// The unit of writing. Writes must start at, and extend to a multiple of it.
+pub const WRITE_BLOCK_SIZE: usize = WRITE_SIZE;
+
+/// The offset of the storage area from the start of the flash memory.
+const STORAGE_OFFSET: u32 = 0xC_0000;
+
+/// The address of the OTP area, 1 KiB outside of the flash bank.
+const OTP_ADDRESS: u32 = 0x08FF_F000;
+
+/// The unit of programming the OTP area, a flash word of it. Each can be programmed once.
+pub const OTP_WRITE_SIZE: usize = 2;
+
+/// The flash driver, once set up by [`init`].
+///
+/// Not locked by a critical section, since erasing takes long enough to starve interrupt handlers (e.g. USB).
+static FLASH: Mutex<ThreadModeRawMutex, RefCell<Option<Flash<'static, Blocking>>>> = Mutex::new(RefCell::new(None));
+
+/// Holds the content of a sector, while it is erased (see [`Region::erase`]). Only used with the flash driver locked.
+#[link_section = ".axisram"]
+static SECTOR_BUFFER: GroundedArrayCell<u8, { SECTOR_SIZE as usize }> = GroundedArrayCell::uninit();
+
+/// A storage error.
+#[derive(Clone, Copy, PartialEq, Debug, defmt::Format)]
+pub enum Error {
+    /// The storage was not set up.
+    Unavailable,
+    /// The access is outside of the region, or not aligned to [`WRITE_BLOCK_SIZE`].
+    OutOfBounds,
+    /// The flash memory reported an error.
+    Flash(flash::Error),
+}
+
+/// A range in the storage area, either of whole sectors, or within a single sector.
+///
+/// Both the offset and the size are multiples of [`WRITE_BLOCK_SIZE`].
+pub struct Region {
+    /// The offset from the start of the storage area.
+    offset: u32,
+    /// The size in bytes.
+    size: u32,
+}
+
+/// The calibration data (see [`crate::calibration`]).
+pub const CALIBRATION_REGION: Region = Region {
+    offset: SECTOR_SIZE + 64 * 1024,
+    size: 8 * 1024,
+};
+
+/// The event log (see [`crate::event_log`]).
+pub const EVENT_LOG_REGION: Region = Region {
+    offset: 0,
+    size: 64 * 1024,
+};
+
+/// The device settings, alternating between two regions in different sectors (see [`crate::settings_store`]).
+pub const SETTINGS_REGIONS: [Region; 2] = [
+    Region {
+        offset: 64 * 1024,
+        size: 32 * 1024,
+    },
+    Region {
+        offset: SECTOR_SIZE,
+        size: 32 * 1024,
+    },
+];
+
+/// The presets of the signal processing configuration (see [`crate::presets`]).
+pub const PRESETS_REGION: Region = Region {
+    offset: 96 * 1024,
+    size: 32 * 1024,
+};
+
+/// The startup script (see [`crate::startup_script`]), and the thermal log (see [`crate::thermal_log`]).
+pub const STARTUP_SCRIPT_REGION: Region = Region {
+    offset: SECTOR_SIZE + 32 * 1024,
+    size: 32 * 1024,
+};
+
+/// A range in the OTP area. It reads as `0xFF` until programmed, and cannot be erased.
+///
+/// Both the offset and the size are multiples of [`OTP_WRITE_SIZE`].
+pub struct OtpRegion {
+    /// The offset from the start of the OTP area.
+    offset: u32,
+    /// The size in bytes.
+    size: u32,
+}
+
+/// The provisioning data (see [`crate::provisioning`]).
+pub const PROVISIONING_OTP_REGION: OtpRegion = OtpRegion { offset: 0, size: 128 };
+
+/// Set up the storage.
+pub fn init(flash: peripherals::FLASH) {
+    FLASH.lock(|f| f.replace(Some(Flash::new_blocking(flash))));
+}
+
+/// Whether a sector may be erased now, without interrupting playback (in standby, or without an active source). Never
+/// before a power loss, which would interrupt the erase (see [`crate::power_fail`]).
+pub fn erase_allowed() -> bool {
+    (CONTROL.standby() || CONTROL.active_source() == AudioSource::None) && !power_fail::power_failing()
+}
+
+/// Run a function with the flash driver.
+fn with_flash<T>(function: impl FnOnce(&mut Flash<'static, Blocking>) -> Result<T, flash::Error>) -> Result<T, Error> {
+    FLASH.lock(|f| match f.borrow_mut().as_mut() {
+        Some(flash) => function(flash).map_err(Error::Flash),
+        None => Err(Error::Unavailable),
+    })
+}
+
+impl Region {
+    /// The size of the region in bytes.
+    pub const fn size(&self) -> u32 {
+        self.size
+    }
+
+    /// The offset of a range within the region from the start of the flash memory, if it lies within the region.
+    fn offset(&self, offset: u32, length: usize) -> Result<u32, Error> {
+        match offset.checked_add(length as u32) {
+            Some(end) if end <= self.size() => Ok(STORAGE_OFFSET + self.offset + offset),
+            _ => Err(Error::OutOfBounds),
+        }
+    }
+
+    /// Read data, starting at an offset within the region.
+    pub fn read(&self, offset: u32, data: &mut [u8]) -> Result<(), Error> {
+        let offset = self.offset(offset, data.len())?;
+        with_flash(|flash| flash.blocking_read(offset, data))
+    }
+
+    /// Erase the whole region. Erased memory reads as `0xFF`.
+    ///
+    /// A region within a sector is erased together with the sector, after which the data of the other regions in it is
+    /// written back. That data is lost, if the power fails in between.
+    pub fn erase(&self) -> Result<(), Error> {
+        let from = self.offset(0, 0)?;
+
+        watchdog::hold_off();
+        let result = self.erase_from(from);
+        watchdog::hold_off();
+
+        // The whole sector, since the other regions in it may not be written back completely after a failure.
+        let sector = from - from % SECTOR_SIZE;
+        let end = (from + self.size).next_multiple_of(SECTOR_SIZE);
+        cache::invalidate_flash(FLASH_BASE + sector as usize, (end - sector) as usize);
+
+        result
+    }
+
+    /// Erase the region, which starts at an offset from the start of the flash memory.
+    fn erase_from(&self, from: u32) -> Result<(), Error> {
+        let to = from + self.size;
+
+        if from % SECTOR_SIZE == 0 && self.size % SECTOR_SIZE == 0 {
+            return with_flash(|flash| flash.blocking_erase(from, to));
+        }
+
+        let sector = from - from % SECTOR_SIZE;
+        if to > sector + SECTOR_SIZE {
+            return Err(Error::OutOfBounds);
+        }
+
+        with_flash(|flash| {
+            let (pointer, length) = SECTOR_BUFFER.get_ptr_len();
+            // SAFETY: The buffer is only used here, while the flash driver is borrowed, so never by more than one caller.
+            // Any content is a valid `u8`.
+            let buffer = unsafe { core::slice::from_raw_parts_mut(pointer, length) };
+
+            flash.blocking_read(sector, buffer)?;
+            flash.blocking_erase(sector, sector + SECTOR_SIZE)?;
+            watchdog::hold_off();
+
+            for (address, block) in (sector..)
+                .step_by(WRITE_BLOCK_SIZE)
+                .zip(buffer.chunks(WRITE_BLOCK_SIZE))
+            {
+                // Erased blocks are skipped, since each block can only be written once after an erase.
+                if (from..to).contains(&address) || block.iter().all(|&byte| byte == 0xFF) {
+                    continue;
+                }
+
+                flash.blocking_write(address, block)?;
+            }
+
+            Ok(())
+        })
+    }
+
+    /// Write data to erased memory, starting at an offset within the region.
+    ///
+    /// Both the offset and the length of the data must be multiples of [`WRITE_BLOCK_SIZE`].
+    pub fn write(&self, offset: u32, data: &[u8]) -> Result<(), Error> {
+        if offset as usize % WRITE_BLOCK_SIZE != 0 || data.len() % WRITE_BLOCK_SIZE != 0 {
+            return Err(Error::OutOfBounds);
+        }
+
+        let offset = self.offset(offset, data.len())?;
+        let result = with_flash(|flash| flash.blocking_write(offset, data));
+
+        cache::invalidate_flash(FLASH_BASE + offset as usize, data.len());
+        result
+    }
+}
+
+impl OtpRegion {
+    /// The size of the region in bytes.
+    pub const fn size(&self) -> u32 {
+        self.size
+    }
+
+    /// The address of a range within the region, if it lies within the region.
+    fn address(&self, offset: u32, length: usize) -> Result<u32, Error> {
+        match offset.checked_add(length as u32) {
+            Some(end) if end <= self.size => Ok(OTP_ADDRESS + self.offset + offset),
+            _ => Err(Error::OutOfBounds),
+        }
+    }
+
+    /// Read data, starting at an offset within the region.
+    pub fn read(&self, offset: u32, data: &mut [u8]) -> Result<(), Error> {
+        let address = self.address(offset, data.len())?;
+
+        // With the flash driver locked, such that the area is not programmed meanwhile.
+        with_flash(|_| {
+            for (address, byte) in (address..).zip(data.iter_mut()) {
+                // SAFETY: The address lies within the OTP area, which is always readable.
+                *byte = unsafe { (address as *const u8).read_volatile() };
+            }
+
+            Ok(())
+        })
+    }
+
+    /// Program data, starting at an offset within the region. Programmed flash words cannot be programmed again, so
+    /// the caller must ensure that the range reads as `0xFF` before. Flash words of `0xFF` are skipped.
+    ///
+    /// Both the offset and the length of the data must be multiples of [`OTP_WRITE_SIZE`].
+    pub fn program(&self, offset: u32, data: &[u8]) -> Result<(), Error> {
+        if offset as usize % OTP_WRITE_SIZE != 0 || data.len() % OTP_WRITE_SIZE != 0 {
+            return Err(Error::OutOfBounds);
+        }
+
+        let address = self.address(offset, data.len())?;
+        // The flash driver only programs its bank, so the registers are used directly.
+        // SAFETY: The flash driver is locked, so no other operation runs meanwhile.
+        let result = with_flash(|_| unsafe { program_otp(address, data) });
+
+        cache::invalidate_flash(address as usize, data.len());
+        result
+    }
+}
+
+/// Program flash words of the OTP area, with the flash registers. The driver of the flash memory must be locked.
+unsafe fn program_otp(address: u32, data: &[u8]) -> Result<(), flash::Error> {
+    let bank = pac::FLASH.bank(0);
+
+    if bank.cr().read().lock() {
+        bank.keyr().write_value(0x4567_0123);
+        bank.keyr().write_value(0xCDEF_89AB);
+    }
+
+    bank.cr().write(|w| w.set_pg(true));
+    cortex_m::asm::dsb();
+
+    let mut result = Ok(());
+    for (address, word) in (address..)
+        .step_by(OTP_WRITE_SIZE)
+        .zip(data.chunks_exact(OTP_WRITE_SIZE))
+    {
+        let word = u16::from_le_bytes([word[0], word[1]]);
+        if word == 0xFFFF {
+            continue;
+        }
+
+        (address as *mut u16).write_volatile(word);
+        cortex_m::asm::dsb();
+
+        // Programming starts once the flash word is complete. Force it, in case the buffer still holds the word.
+        if bank.sr().read().wbne() {
+            bank.cr().modify(|w| w.set_fw(true));
+        }
+
+        result = wait_otp_ready(bank);
+        if result.is_err() {
+            break;
+        }
+    }
+
+    bank.cr().write(|w| w.set_pg(false));
+    bank.cr().modify(|w| w.set_lock(true));
+
+    result
+}
+
+/// Wait for the programming of a flash word of the OTP area, and clear its flags.
+fn wait_otp_ready(bank: pac::flash::Bank) -> Result<(), flash::Error> {
+    let status = loop {
+        let status = bank.sr().read();
+        if !status.bsy() && !status.qw() {
+            break status;
+        }
+    };
+
+    bank.ccr().write(|w| {
+        w.set_clr_eop(true);
+        w.set_clr_wrperr(true);
+        w.set_clr_pgserr(true);
+        w.set_clr_strberr(true);
+        w.set_clr_incerr(true);
+        w.set_clr_operr(true);
+    });
+
+    if status.wrperr() {
+        Err(flash::Error::Protected)
+    } else if status.pgserr() || status.strberr() || status.incerr() {
+        Err(flash::Error::Seq)
+    } else if status.operr() {
+        Err(flash::Error::Prog)
+    } else {
+        Ok(())
+    }
+}
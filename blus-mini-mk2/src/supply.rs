@@ -0,0 +1,122 @@
+//! Monitoring of the main supply rail, which shuts the amplifiers down before the rail sags too far.
+//!
+//! The rail is sampled through a divider (see [`DIVIDER_RATIO`]) by ADC2, and averaged over [`SAMPLE_COUNT`]
+//! conversions per period. Without threshold, the voltage is only measured.
+//!
+//! Once the voltage drops below the threshold (a device setting, see
+//! [`protocol::settings::Settings::supply_threshold_mv`]), the device locks out, instead of letting the amplifiers
+//! misbehave, or run into their own undervoltage lockout with a pop:
+//! - The output is muted with the next sample block (see [`crate::control::Control::gain`]).
+//! - The amplifiers power down (see [`crate::amplifiers`]).
+//! - No source is allowed to play, such that the audio routing stops the SAIs.
+//! - The event is logged (see [`crate::event_log`]), and the status flag
+//!   [`crate::control::status::SUPPLY_UNDERVOLTAGE`] is set.
+//!
+//! The device stays locked out until the voltage exceeds the threshold by [`HYSTERESIS_MV`]. Then, sources may play
+//! again, and power up the amplifiers.
+use core::sync::atomic::{AtomicBool, AtomicU16, Ordering};
+
+use embassy_stm32::adc::{self, Adc, AdcChannel};
+use embassy_stm32::peripherals;
+use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, Ticker};
+use protocol::event_log::EventKind;
+
+use crate::control::STATUS_CHANGED_SIGNAL;
+use crate::event_log;
+use crate::*;
+
+/// The ratio of the supply voltage to the voltage at the ADC input (100 kΩ over 10 kΩ).
+const DIVIDER_RATIO: f32 = 11.0;
+
+/// The reference voltage of the ADC in mV.
+const REFERENCE_MV: f32 = 3300.0;
+
+/// The rate at which the supply voltage is measured.
+const SAMPLE_RATE_HZ: u64 = 100;
+
+/// The number of conversions, which are averaged per measurement.
+const SAMPLE_COUNT: u32 = 4;
+
+/// The voltage in mV above the threshold, at which the amplifiers start again.
+pub const HYSTERESIS_MV: u16 = 500;
+
+/// The last measured supply voltage in mV.
+static VOLTAGE_MV: AtomicU16 = AtomicU16::new(0);
+
+/// The supply voltage in mV, below which the amplifiers are shut down, or `0` for no threshold.
+static THRESHOLD_MV: AtomicU16 = AtomicU16::new(0);
+
+/// Whether the supply voltage is below the threshold.
+static UNDERVOLTAGE: AtomicBool = AtomicBool::new(false);
+
+/// Signal that is emitted when the supply voltage drops below the threshold (`true`), or recovers (`false`).
+pub static UNDERVOLTAGE_SIGNAL: Signal<ThreadModeRawMutex, bool> = Signal::new();
+
+/// Resources that are required for measuring the supply voltage.
+#[allow(missing_docs)]
+pub struct SupplyResources {
+    pub adc: peripherals::ADC2,
+    pub pin: peripherals::PC4,
+}
+
+/// The last measured supply voltage in mV.
+pub fn voltage_mv() -> u16 {
+    VOLTAGE_MV.load(Ordering::Relaxed)
+}
+
+/// The supply voltage in mV, below which the amplifiers are shut down, or `0` for no threshold.
+pub fn threshold_mv() -> u16 {
+    THRESHOLD_MV.load(Ordering::Relaxed)
+}
+
+/// Set the supply threshold in mV, or `0` for no threshold.
+pub fn set_threshold_mv(threshold_mv: u16) {
+    THRESHOLD_MV.store(threshold_mv, Ordering::Relaxed);
+}
+
+/// Whether the supply voltage is below the threshold, such that the amplifiers are shut down.
+pub fn undervoltage() -> bool {
+    UNDERVOLTAGE.load(Ordering::Relaxed)
+}
+
+/// Measures the supply voltage, and signals when it crosses the threshold.
+#[embassy_executor::task]
+pub async fn supply_task(resources: SupplyResources) {
+    let mut adc = Adc::new(resources.adc);
+    let mut pin = resources.pin.degrade_adc();
+    adc.set_sample_time(adc::SampleTime::CYCLES64_5);
+
+    let mut ticker = Ticker::every(Duration::from_hz(SAMPLE_RATE_HZ));
+
+    loop {
+        ticker.next().await;
+
+        let sum: u32 = (0..SAMPLE_COUNT).map(|_| adc.blocking_read(&mut pin) as u32).sum();
+        let input_mv = (sum / SAMPLE_COUNT) as f32 / 65535.0 * REFERENCE_MV;
+        let voltage_mv = (input_mv * DIVIDER_RATIO) as u16;
+        VOLTAGE_MV.store(voltage_mv, Ordering::Relaxed);
+
+        let threshold_mv = threshold_mv();
+        let was_undervoltage = undervoltage();
+        let is_undervoltage = match threshold_mv {
+            0 => false,
+            _ if was_undervoltage => voltage_mv < threshold_mv.saturating_add(HYSTERESIS_MV),
+            _ => voltage_mv < threshold_mv,
+        };
+
+        if is_undervoltage != was_undervoltage {
+            UNDERVOLTAGE.store(is_undervoltage, Ordering::Relaxed);
+            UNDERVOLTAGE_SIGNAL.signal(is_undervoltage);
+            STATUS_CHANGED_SIGNAL.signal(());
+
+            if is_undervoltage {
+                log!(warn, "Supply undervoltage: {} mV, locking out", voltage_mv);
+                event_log::record(EventKind::Undervoltage, (voltage_mv / 100).min(u8::MAX as u16) as u8);
+            } else {
+                log!(info, "Supply recovered: {} mV", voltage_mv);
+            }
+        }
+    }
+}
@@ -0,0 +1,205 @@
+//! Rebooting the microcontroller, either into the firmware, or into the system bootloader for updates.
+//!
+//! Reboots are requested by the control frontends, and performed by the [`reboot_task`] after a short delay,
+//! such that responses still reach the host. For entering the bootloader, a magic value is left in SRAM4, which
+//! keeps its content across resets. Early after the reset, [`enter_bootloader_if_requested`] finds the value,
+//! and jumps to the bootloader in system memory (USB DFU, or UART). Factory resets are requested the same way, and
+//! performed at startup (see [`crate::factory_reset`]).
+//!
+//! The cause of the last reset is taken from the reset flags at startup, and recorded in the event log.
+//!
+//! Faults (including panics) reset the microcontroller. Resets before the firmware ran for [`STABLE_UPTIME`] are
+//! counted in SRAM4 as well, other than power-on resets and requested reboots. After [`BOOT_LOOP_RESET_COUNT`] of
+//! them in a row (e.g. from a stored signal processing configuration that makes the firmware panic), a boot loop is
+//! detected: the firmware starts with the default signal processing configuration, and records the event. The stored
+//! settings are kept, until they are saved again.
+use core::mem::MaybeUninit;
+use core::ptr::addr_of_mut;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use embassy_stm32::pac;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, Instant, Timer};
+use protocol::event_log::ResetCause;
+
+use crate::*;
+
+/// The address of the system bootloader's vector table (see AN2606 for the STM32H72x/73x).
+const SYSTEM_MEMORY_ADDRESS: u32 = 0x1FF0_9800;
+
+/// Marks a request for entering the bootloader.
+const BOOTLOADER_MAGIC: u32 = 0xB007_10AD;
+
+/// Marks a request for a factory reset.
+const FACTORY_RESET_MAGIC: u32 = 0xFAC7_0123;
+
+/// Marks a valid count of quick resets in the upper half of [`QUICK_RESETS`].
+const QUICK_RESETS_MAGIC: u32 = 0xB007_0000;
+
+/// The time between a reboot request, and the reboot.
+const REBOOT_DELAY: Duration = Duration::from_millis(100);
+
+/// The number of quick resets in a row, from which a boot loop is detected.
+pub const BOOT_LOOP_RESET_COUNT: u16 = 3;
+
+/// The uptime, after which a reset does not count as quick anymore.
+pub const STABLE_UPTIME: Duration = Duration::from_secs(30);
+
+/// Holds [`BOOTLOADER_MAGIC`] or [`FACTORY_RESET_MAGIC`] during a reset. Not initialized at startup.
+#[link_section = ".sram4"]
+static mut BOOT_REQUEST: MaybeUninit<u32> = MaybeUninit::uninit();
+
+/// Holds [`QUICK_RESETS_MAGIC`] and the number of quick resets in a row during a reset. Not initialized at startup.
+#[link_section = ".sram4"]
+static mut QUICK_RESETS: MaybeUninit<u32> = MaybeUninit::uninit();
+
+/// Whether a boot loop was detected at startup.
+static BOOT_LOOP: AtomicBool = AtomicBool::new(false);
+
+/// What to start after a reboot.
+#[derive(Clone, Copy, PartialEq, Debug, defmt::Format)]
+pub enum RebootTarget {
+    /// Restart the firmware.
+    Firmware,
+    /// Start the system bootloader, for a firmware update.
+    Bootloader,
+    /// Erase all stored data, and restart the firmware with its defaults.
+    FactoryReset,
+}
+
+/// Signal that is emitted, when a reboot is requested.
+static REBOOT_SIGNAL: Signal<CriticalSectionRawMutex, RebootTarget> = Signal::new();
+
+/// Request a reboot, which happens shortly after.
+pub fn request_reboot(target: RebootTarget) {
+    REBOOT_SIGNAL.signal(target);
+}
+
+/// Jump to the system bootloader, if it was requested before the last reset.
+///
+/// Must be called first thing after startup, before any peripherals are configured.
+pub fn enter_bootloader_if_requested() {
+    // SAFETY: Only accessed here and by the reboot task, before and after running the executor respectively.
+    // The memory may hold any value after power-up, which is valid for `u32`.
+    unsafe {
+        let request = addr_of_mut!(BOOT_REQUEST).cast::<u32>();
+
+        if request.read_volatile() == BOOTLOADER_MAGIC {
+            request.write_volatile(0);
+
+            // SAFETY: The system memory holds a valid vector table, and the peripherals are in reset state.
+            cortex_m::asm::bootload(SYSTEM_MEMORY_ADDRESS as *const u32);
+        }
+    }
+}
+
+/// Whether a factory reset was requested before the last reset. Clears the request.
+///
+/// Must be called after [`enter_bootloader_if_requested`], before the stored settings are restored.
+pub fn take_factory_reset_request() -> bool {
+    // SAFETY: See `enter_bootloader_if_requested`.
+    unsafe {
+        let request = addr_of_mut!(BOOT_REQUEST).cast::<u32>();
+        let requested = request.read_volatile() == FACTORY_RESET_MAGIC;
+
+        request.write_volatile(0);
+        requested
+    }
+}
+
+/// The cause of the last reset. Clears the reset flags, such that the next reset is told apart.
+pub fn take_reset_cause() -> ResetCause {
+    let flags = pac::RCC.rsr().read();
+
+    // A power-on reset also sets the flags of the pin and brown-out resets, and every reset the pin flag.
+    let cause = if flags.porrstf() {
+        ResetCause::PowerOn
+    } else if flags.borrstf() {
+        ResetCause::Brownout
+    } else if flags.iwdg1rstf() {
+        ResetCause::Watchdog
+    } else if flags.wwdg1rstf() {
+        ResetCause::WindowWatchdog
+    } else if flags.lpwrrstf() {
+        ResetCause::LowPower
+    } else if flags.sftrstf() {
+        ResetCause::Software
+    } else if flags.pinrstf() {
+        ResetCause::Pin
+    } else {
+        ResetCause::Unknown
+    };
+
+    pac::RCC.rsr().modify(|w| w.set_rmvf(true));
+    cause
+}
+
+/// Set the number of quick resets in a row.
+fn set_quick_resets(count: u16) {
+    // SAFETY: Only accessed by the thread-mode executor, and by the reboot task before resetting.
+    unsafe {
+        addr_of_mut!(QUICK_RESETS)
+            .cast::<u32>()
+            .write_volatile(QUICK_RESETS_MAGIC | count as u32);
+    }
+}
+
+/// Count the startup after a reset, and detect a boot loop. Returns the number of quick resets in a row, if in one.
+///
+/// Must be called once at startup, with the cause of the last reset.
+pub fn detect_boot_loop(cause: ResetCause) -> Option<u16> {
+    // SAFETY: See `set_quick_resets`. The memory may hold any value after power-up, which is valid for `u32`.
+    let value = unsafe { addr_of_mut!(QUICK_RESETS).cast::<u32>().read_volatile() };
+
+    let count = match cause {
+        // Memory content is lost, and the reset is not a fault of the firmware.
+        ResetCause::PowerOn | ResetCause::Brownout => 0,
+        _ if value & 0xFFFF_0000 == QUICK_RESETS_MAGIC => (value as u16).saturating_add(1),
+        _ => 1,
+    };
+
+    set_quick_resets(count);
+
+    let boot_loop = count >= BOOT_LOOP_RESET_COUNT;
+    BOOT_LOOP.store(boot_loop, Ordering::Relaxed);
+
+    boot_loop.then_some(count)
+}
+
+/// Whether a boot loop was detected at startup.
+pub fn boot_loop_detected() -> bool {
+    BOOT_LOOP.load(Ordering::Relaxed)
+}
+
+/// Ends counting quick resets, once the firmware ran for [`STABLE_UPTIME`].
+#[embassy_executor::task]
+pub async fn stable_uptime_task() {
+    Timer::at(Instant::from_ticks(0) + STABLE_UPTIME).await;
+    set_quick_resets(0);
+}
+
+/// Performs requested reboots.
+#[embassy_executor::task]
+pub async fn reboot_task() {
+    let target = REBOOT_SIGNAL.wait().await;
+
+    log!(info, "Reboot into {:?}", target);
+    Timer::after(REBOOT_DELAY).await;
+
+    let magic = match target {
+        RebootTarget::Firmware => 0,
+        RebootTarget::Bootloader => BOOTLOADER_MAGIC,
+        RebootTarget::FactoryReset => FACTORY_RESET_MAGIC,
+    };
+
+    // SAFETY: See `enter_bootloader_if_requested`.
+    unsafe {
+        addr_of_mut!(BOOT_REQUEST).cast::<u32>().write_volatile(magic);
+    }
+
+    // Requested reboots are not quick resets.
+    set_quick_resets(0);
+
+    cortex_m::peripheral::SCB::sys_reset();
+}
@@ -0,0 +1,46 @@
+//! Placement of the hot code and data of the signal processing in the tightly-coupled memories (TCM) of the core.
+//!
+//! The TCMs have zero wait-states, and are not shared with the DMA of the audio interfaces. Code in ITCM does not
+//! depend on the instruction cache, and data in DTCM bypasses the data cache, such that the processing time of sample
+//! blocks is short and deterministic (see the DSP headroom of [`crate::cpu_load`]).
+//!
+//! - Functions are placed in ITCM with `#[link_section = ".itcm.<name>"]`, and should be `#[inline(never)]`, such
+//!   that they are not inlined into code in flash. Functions that they call should be inlined, or they run from flash.
+//! - Statics are placed in DTCM with `#[link_section = ".dtcm"]`. The section is zeroed at startup, such that
+//!   [`static_cell::StaticCell`] and other zero-initialized types work, but statics with other initial values do not.
+//!   Statics that are only used with a feature go to `.dtcm.<name>`, since the linker can only drop whole sections.
+//!
+//! Both sections are initialized by [`init`], which must run first in `main`. The remaining data (`.data`, `.bss`,
+//! and the state of tasks) is in DTCM as well, as long as it is the RAM of the core (see `memory.x`).
+
+extern "C" {
+    /// The start of the code in ITCM.
+    static mut __sitcm: u32;
+    /// The end of the code in ITCM.
+    static mut __eitcm: u32;
+    /// The start of the code for ITCM in flash.
+    static __siitcm: u32;
+    /// The start of the data in DTCM.
+    static mut __sdtcm: u32;
+    /// The end of the data in DTCM.
+    static mut __edtcm: u32;
+}
+
+/// Copy the code for ITCM from flash, and zero the data in DTCM.
+///
+/// # Safety
+///
+/// Must be called once, before any function in ITCM runs, or any static in DTCM is used.
+pub unsafe fn init() {
+    let itcm_start = core::ptr::addr_of_mut!(__sitcm);
+    let itcm_length = (core::ptr::addr_of!(__eitcm) as usize - itcm_start as usize) / size_of::<u32>();
+    core::ptr::copy_nonoverlapping(core::ptr::addr_of!(__siitcm), itcm_start, itcm_length);
+
+    let dtcm_start = core::ptr::addr_of_mut!(__sdtcm);
+    let dtcm_length = (core::ptr::addr_of!(__edtcm) as usize - dtcm_start as usize) / size_of::<u32>();
+    core::ptr::write_bytes(dtcm_start, 0, dtcm_length);
+
+    // Fetch the copied code, not the stale contents of the pipeline.
+    cortex_m::asm::dsb();
+    cortex_m::asm::isb();
+}
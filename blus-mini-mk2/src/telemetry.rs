@@ -0,0 +1,225 @@
+//! An opt-in stream of telemetry records, for dashboards (e.g. on the Raspberry Pi).
+//!
+//! When enabled, one record is emitted per period on the selected console: a single line with a JSON object of
+//! uptime in milliseconds, serial number (`null` if not provisioned, see [`crate::provisioning`]), active source,
+//! peak output levels in dBFS (`null` for silence), amplifier temperatures in °C (`null` while the amplifiers are
+//! off, see [`crate::thermal`]), estimated output powers in W (see [`crate::output_power`]), the microcontroller
+//! temperature in °C (`null` until measured), the supply voltage in V (see [`crate::supply`]), the fill of the sample
+//! block buffer, the number of amplifier output underruns, and the CPU load in percent: in total, of the signal
+//! processing, and the headroom of the signal processing (see [`crate::cpu_load`]). With the `latency_probe` feature,
+//! the last measured latency from USB to the amplifier SAI in µs follows (`null` until measured, see
+//! [`crate::latency_probe`]). With the `stack_usage` feature, the high-water mark and the size of the stack in byte
+//! follow, and the allocated bytes and the size of the task arena (see [`crate::stack_usage`]). For example:
+//!
+//! ```text
+//! {"t":12500,"serial":"B0123","source":"usb","levels":[-12.5,-13.0,-20.5,null],"temperatures":[61.5,62.0,58.5,59.0],
+//! "power":[1.52,1.41,0.18,0.00],"mcu":48.5,"supply":12.04,"fill":2,"capacity":5,"underruns":0,"cpu":24.5,
+//! "dsp":18.3,"headroom":79.0,"latency":2150,"stack":9412,"stack_size":41216,
+//! "arena":21504,"arena_size":65536}
+//! ```
+//!
+//! The example is wrapped here, records are single lines.
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, Instant, Timer};
+
+use crate::audio_routing::STATS;
+use crate::control::{self, CONTROL};
+use crate::cpu_load::{self, Section};
+use crate::output_power;
+use crate::provisioning::provisioning;
+use crate::supply;
+use crate::thermal;
+use crate::*;
+
+/// The highest rate of records.
+pub const MAX_RATE_HZ: u8 = 50;
+
+/// The console that receives telemetry records.
+#[repr(u8)]
+#[derive(Clone, Copy, PartialEq, Debug, defmt::Format)]
+pub enum TelemetryChannel {
+    /// Telemetry is disabled.
+    Off = 0,
+    /// The USB console.
+    Usb = 1,
+    /// The UART console.
+    Uart = 2,
+}
+
+impl TryFrom<u8> for TelemetryChannel {
+    type Error = u8;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(TelemetryChannel::Off),
+            1 => Ok(TelemetryChannel::Usb),
+            2 => Ok(TelemetryChannel::Uart),
+            _ => Err(value),
+        }
+    }
+}
+
+/// The console that receives telemetry records.
+static CHANNEL: AtomicU8 = AtomicU8::new(TelemetryChannel::Off as u8);
+
+/// The rate of records.
+static RATE_HZ: AtomicU8 = AtomicU8::new(1);
+
+/// Signal that is emitted when the telemetry configuration changes.
+static CONFIG_CHANGED_SIGNAL: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// Signal that is emitted when a record is due on the USB console.
+pub static USB_TELEMETRY_SIGNAL: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// Signal that is emitted when a record is due on the UART console.
+pub static UART_TELEMETRY_SIGNAL: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// The console that receives telemetry records.
+pub fn channel() -> TelemetryChannel {
+    TelemetryChannel::try_from(CHANNEL.load(Ordering::Relaxed)).unwrap_or(TelemetryChannel::Off)
+}
+
+/// The rate of records.
+pub fn rate_hz() -> u8 {
+    RATE_HZ.load(Ordering::Relaxed)
+}
+
+/// Select the console for telemetry records, and their rate (limited to [`MAX_RATE_HZ`]).
+pub fn configure(channel: TelemetryChannel, rate_hz: u8) {
+    CHANNEL.store(channel as u8, Ordering::Relaxed);
+    RATE_HZ.store(rate_hz.clamp(1, MAX_RATE_HZ), Ordering::Relaxed);
+
+    USB_TELEMETRY_SIGNAL.reset();
+    UART_TELEMETRY_SIGNAL.reset();
+    CONFIG_CHANGED_SIGNAL.signal(());
+}
+
+/// The name of a telemetry channel, as used by the text interfaces.
+pub fn channel_name(channel: TelemetryChannel) -> &'static str {
+    match channel {
+        TelemetryChannel::Off => "off",
+        TelemetryChannel::Usb => "usb",
+        TelemetryChannel::Uart => "uart",
+    }
+}
+
+/// Parse the name of a telemetry channel.
+pub fn parse_channel(name: &str) -> Option<TelemetryChannel> {
+    [TelemetryChannel::Off, TelemetryChannel::Usb, TelemetryChannel::Uart]
+        .into_iter()
+        .find(|channel| channel_name(*channel) == name)
+}
+
+/// Render a telemetry record of the current state, without line break.
+pub fn render(out: &mut impl Write) -> fmt::Result {
+    write!(out, "{{\"t\":{},\"serial\":", Instant::now().as_millis())?;
+
+    match provisioning() {
+        Some(provisioning) => write!(out, "\"{}\"", provisioning.serial_number())?,
+        None => out.write_str("null")?,
+    }
+
+    write!(
+        out,
+        ",\"source\":\"{}\",\"levels\":[",
+        control::source_name(CONTROL.active_source())
+    )?;
+
+    for channel in 0..OUTPUT_CHANNEL_COUNT {
+        if channel > 0 {
+            out.write_char(',')?;
+        }
+
+        match CONTROL.meter_level(channel) {
+            control::MUTED_ATTENUATION => out.write_str("null")?,
+            level => write!(out, "{:.1}", -(level as f32) / 2.0)?,
+        }
+    }
+
+    out.write_str("],\"temperatures\":[")?;
+
+    for channel in 0..OUTPUT_CHANNEL_COUNT {
+        if channel > 0 {
+            out.write_char(',')?;
+        }
+
+        match thermal::temperature(channel) {
+            Some(temperature) => write!(out, "{:.1}", temperature)?,
+            None => out.write_str("null")?,
+        }
+    }
+
+    out.write_str("],\"power\":[")?;
+
+    for channel in 0..OUTPUT_CHANNEL_COUNT {
+        if channel > 0 {
+            out.write_char(',')?;
+        }
+
+        write!(out, "{:.2}", output_power::power_w(channel))?;
+    }
+
+    out.write_str("],\"mcu\":")?;
+
+    match thermal::microcontroller_temperature() {
+        Some(temperature) => write!(out, "{:.1}", temperature)?,
+        None => out.write_str("null")?,
+    }
+
+    write!(
+        out,
+        ",\"supply\":{:.2},\"fill\":{},\"capacity\":{},\"underruns\":{}",
+        supply::voltage_mv() as f32 / 1000.0,
+        CONTROL.buffer_fill(),
+        SAMPLE_BLOCK_COUNT,
+        STATS.underrun_count()
+    )?;
+
+    write!(
+        out,
+        ",\"cpu\":{:.1},\"dsp\":{:.1},\"headroom\":{:.1}",
+        cpu_load::total_load_percent(),
+        cpu_load::load_percent(Section::Dsp),
+        cpu_load::dsp_headroom_percent()
+    )?;
+
+    #[cfg(feature = "latency_probe")]
+    match latency_probe::latency_us() {
+        Some(latency_us) => write!(out, ",\"latency\":{}", latency_us)?,
+        None => out.write_str(",\"latency\":null")?,
+    }
+
+    #[cfg(feature = "stack_usage")]
+    write!(
+        out,
+        ",\"stack\":{},\"stack_size\":{},\"arena\":{},\"arena_size\":{}",
+        stack_usage::high_water_mark(),
+        stack_usage::stack_size(),
+        stack_usage::task_arena_usage(),
+        stack_usage::TASK_ARENA_SIZE
+    )?;
+
+    out.write_char('}')
+}
+
+/// Paces the telemetry records on the selected console.
+#[embassy_executor::task]
+pub async fn telemetry_task() {
+    loop {
+        let signal = match channel() {
+            TelemetryChannel::Off => {
+                CONFIG_CHANGED_SIGNAL.wait().await;
+                continue;
+            }
+            TelemetryChannel::Usb => &USB_TELEMETRY_SIGNAL,
+            TelemetryChannel::Uart => &UART_TELEMETRY_SIGNAL,
+        };
+
+        Timer::after(Duration::from_hz(rate_hz() as u64)).await;
+        signal.signal(());
+    }
+}
@@ -0,0 +1,242 @@
+//! Temperature monitoring of the amplifiers, which throttles the output gain before they shut down.
+//!
+//! While the amplifiers run, their die temperatures are read with every fault check (see [`crate::amplifiers`]), and
+//! corrected by the calibrated sensor offsets (see [`crate::calibration`]). Above [`THROTTLE_START_C`], the gain of
+//! the amplifier's output channel is reduced progressively, by up to [`MAX_THROTTLE_DB`] at [`THROTTLE_FULL_C`].
+//! The reduction follows rising temperatures immediately, and recovers by [`RECOVERY_STEP_DB`] per reading as the
+//! amplifier cools, such that the gain does not oscillate around a threshold.
+//!
+//! The die temperature lags behind the output power. When the output power rises (see [`crate::output_power`]), the
+//! heating that is still to come is anticipated by [`ANTICIPATION_C_PER_W`] per W above the long-term power, such
+//! that throttling starts before the die reaches its temperature.
+//!
+//! The die temperature of the microcontroller is measured by its internal sensor (on ADC3, see [`thermal_task`]),
+//! and corrected by the calibrations of the sensor in the factory, and in [`crate::calibration`]. It does not
+//! throttle the gain, but is reported along with the amplifier temperatures, e.g. for correlating dropouts with
+//! thermal conditions.
+//!
+//! Every [`HISTORY_INTERVAL`], the current temperatures are appended to a history in RAM, which holds the last
+//! [`HISTORY_LENGTH`] entries (shell `thermal`). For the long term, the temperatures are summarized in flash (see
+//! [`crate::thermal_log`]).
+use core::cell::{Cell, RefCell};
+
+use embassy_stm32::adc::{self, Adc, Resolution};
+use embassy_stm32::peripherals;
+use embassy_sync::blocking_mutex::raw::{CriticalSectionRawMutex, ThreadModeRawMutex};
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_time::{Duration, Instant, Ticker};
+use heapless::Deque;
+
+use crate::calibration;
+use crate::output_power;
+use crate::*;
+
+/// The temperature in °C, above which the gain is reduced.
+pub const THROTTLE_START_C: f32 = 100.0;
+
+/// The temperature in °C, at which the gain is reduced by [`MAX_THROTTLE_DB`].
+pub const THROTTLE_FULL_C: f32 = 130.0;
+
+/// The largest gain reduction in dB.
+pub const MAX_THROTTLE_DB: f32 = 12.0;
+
+/// The anticipated heating of an amplifier die in °C per W of output power above its long-term power.
+pub const ANTICIPATION_C_PER_W: f32 = 10.0;
+
+/// The recovery of the gain reduction per temperature reading in dB.
+pub const RECOVERY_STEP_DB: f32 = 0.5;
+
+/// The interval between measurements of the microcontroller temperature.
+const MEASUREMENT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The interval between entries of the temperature history.
+pub const HISTORY_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The number of entries of the temperature history.
+pub const HISTORY_LENGTH: usize = 60;
+
+/// The address of the factory calibration of the temperature sensor at [`TS_CAL1_C`] (a 12-bit reading at 3.3 V).
+const TS_CAL1_ADDRESS: usize = 0x1FF1_E820;
+
+/// The address of the factory calibration of the temperature sensor at [`TS_CAL2_C`].
+const TS_CAL2_ADDRESS: usize = 0x1FF1_E840;
+
+/// The temperature of the first factory calibration in °C.
+const TS_CAL1_C: f32 = 30.0;
+
+/// The temperature of the second factory calibration in °C.
+const TS_CAL2_C: f32 = 110.0;
+
+/// The temperatures and gain reductions of all output channels.
+#[derive(Clone, Copy)]
+struct Thermal {
+    /// The last temperature reading in °C, unless the amplifier is off.
+    temperatures: [Option<f32>; OUTPUT_CHANNEL_COUNT],
+    /// The gain reduction in dB.
+    throttle_db: [f32; OUTPUT_CHANNEL_COUNT],
+    /// The gain reduction as linear gain.
+    throttle_gains: [f32; OUTPUT_CHANNEL_COUNT],
+}
+
+/// An entry of the temperature history.
+#[derive(Clone, Copy)]
+pub struct HistoryEntry {
+    /// The uptime at the time of the entry.
+    pub time: Instant,
+    /// The temperature of the microcontroller in °C, unless it was not measured yet.
+    pub microcontroller: Option<f32>,
+    /// The temperatures of the amplifiers in °C, unless they were off.
+    pub amplifiers: [Option<f32>; OUTPUT_CHANNEL_COUNT],
+}
+
+/// Resources that are required for measuring the temperature of the microcontroller.
+#[allow(missing_docs)]
+pub struct ThermalResources {
+    pub adc: peripherals::ADC3,
+}
+
+/// The state of amplifiers that are off.
+const OFF: Thermal = Thermal {
+    temperatures: [None; OUTPUT_CHANNEL_COUNT],
+    throttle_db: [0.0; OUTPUT_CHANNEL_COUNT],
+    throttle_gains: [1.0; OUTPUT_CHANNEL_COUNT],
+};
+
+static THERMAL: Mutex<CriticalSectionRawMutex, Cell<Thermal>> = Mutex::new(Cell::new(OFF));
+
+/// The last temperature of the microcontroller in °C, unless it was not measured yet.
+static MICROCONTROLLER_TEMPERATURE: Mutex<ThreadModeRawMutex, Cell<Option<f32>>> = Mutex::new(Cell::new(None));
+
+/// The temperature history, oldest entries first.
+static HISTORY: Mutex<ThreadModeRawMutex, RefCell<Deque<HistoryEntry, HISTORY_LENGTH>>> =
+    Mutex::new(RefCell::new(Deque::new()));
+
+/// The last temperature of the microcontroller in °C, unless it was not measured yet.
+pub fn microcontroller_temperature() -> Option<f32> {
+    MICROCONTROLLER_TEMPERATURE.lock(|temperature| temperature.get())
+}
+
+/// The number of entries of the temperature history.
+pub fn history_len() -> usize {
+    HISTORY.lock(|history| history.borrow().len())
+}
+
+/// An entry of the temperature history by index, oldest first.
+pub fn history_entry(index: usize) -> Option<HistoryEntry> {
+    HISTORY.lock(|history| history.borrow().iter().nth(index).copied())
+}
+
+/// The last temperature reading of the amplifier of an output channel in °C, unless it is off.
+pub fn temperature(channel: usize) -> Option<f32> {
+    THERMAL.lock(|thermal| thermal.get().temperatures.get(channel).copied().flatten())
+}
+
+/// The gain reduction of an output channel in dB.
+pub fn throttle_db(channel: usize) -> f32 {
+    THERMAL.lock(|thermal| thermal.get().throttle_db.get(channel).copied().unwrap_or_default())
+}
+
+/// The gain reductions of all output channels, as linear gains.
+pub fn throttle_gains() -> [f32; OUTPUT_CHANNEL_COUNT] {
+    THERMAL.lock(|thermal| thermal.get().throttle_gains)
+}
+
+/// The gain reduction in dB for a temperature in °C.
+fn target_throttle_db(temperature: f32) -> f32 {
+    let fraction = (temperature - THROTTLE_START_C) / (THROTTLE_FULL_C - THROTTLE_START_C);
+    fraction.clamp(0.0, 1.0) * MAX_THROTTLE_DB
+}
+
+/// Update the temperature of the amplifier of an output channel from a raw reading in °C, and adapt its gain.
+pub fn update(channel: usize, reading: f32) {
+    // The amplifiers follow the microcontroller in the order of the calibrated sensors.
+    let temperature = calibration::corrected_temperature(channel + 1, reading);
+    let rising_w = (output_power::power_w(channel) - output_power::long_term_power_w(channel)).max(0.0);
+    let target_db = target_throttle_db(temperature + rising_w * ANTICIPATION_C_PER_W);
+
+    let previous_db = throttle_db(channel);
+    let throttle_db = if target_db >= previous_db {
+        target_db
+    } else {
+        (previous_db - RECOVERY_STEP_DB).max(target_db)
+    };
+
+    if previous_db == 0.0 && throttle_db > 0.0 {
+        log!(warn, "Amplifier {} at {} C, throttling", channel, temperature as i32);
+    } else if previous_db > 0.0 && throttle_db == 0.0 {
+        log!(info, "Amplifier {} cooled down", channel);
+    }
+
+    THERMAL.lock(|thermal| {
+        let mut state = thermal.get();
+
+        state.temperatures[channel] = Some(temperature);
+        state.throttle_db[channel] = throttle_db;
+        state.throttle_gains[channel] = 10.0f32.powf(-throttle_db / 20.0);
+        thermal.set(state);
+    });
+}
+
+/// Forget the temperatures, and end throttling, when the amplifiers are off.
+pub fn reset() {
+    THERMAL.lock(|thermal| thermal.set(OFF));
+}
+
+/// Convert a 12-bit reading of the temperature sensor to °C, by the factory calibration.
+fn sensor_temperature(reading: u16) -> f32 {
+    // Safety: the factory calibration is in read-only system memory.
+    let (cal1, cal2) = unsafe {
+        (
+            core::ptr::read_volatile(TS_CAL1_ADDRESS as *const u16),
+            core::ptr::read_volatile(TS_CAL2_ADDRESS as *const u16),
+        )
+    };
+
+    let slope = (TS_CAL2_C - TS_CAL1_C) / (cal2 as f32 - cal1 as f32);
+    TS_CAL1_C + (reading as f32 - cal1 as f32) * slope
+}
+
+/// Append the current temperatures to the history, which drops the oldest entry when it is full.
+fn record_history() {
+    let entry = HistoryEntry {
+        time: Instant::now(),
+        microcontroller: microcontroller_temperature(),
+        amplifiers: THERMAL.lock(|thermal| thermal.get().temperatures),
+    };
+
+    HISTORY.lock(|history| {
+        let mut history = history.borrow_mut();
+
+        if history.is_full() {
+            history.pop_front();
+        }
+
+        // Cannot fail, there is space.
+        _ = history.push_back(entry);
+    });
+}
+
+/// Measures the temperature of the microcontroller, and records the temperature history.
+#[embassy_executor::task]
+pub async fn thermal_task(resources: ThermalResources) {
+    let mut adc = Adc::new(resources.adc);
+    adc.set_resolution(Resolution::BITS12);
+    adc.set_sample_time(adc::SampleTime::CYCLES810_5);
+
+    let mut sensor = adc.enable_temperature();
+    let mut ticker = Ticker::every(MEASUREMENT_INTERVAL);
+    let mut last_history_entry = Instant::now();
+
+    loop {
+        ticker.next().await;
+
+        // The microcontroller is the first calibrated sensor.
+        let temperature = calibration::corrected_temperature(0, sensor_temperature(adc.blocking_read(&mut sensor)));
+        MICROCONTROLLER_TEMPERATURE.lock(|current| current.set(Some(temperature)));
+
+        if last_history_entry.elapsed() >= HISTORY_INTERVAL {
+            last_history_entry = Instant::now();
+            record_history();
+        }
+    }
+}
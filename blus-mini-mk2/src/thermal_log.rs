@@ -0,0 +1,309 @@
+//! A log of the temperatures in flash, which summarizes them over the operating time of the device, e.g. for warranty
+//! claims, and feedback on enclosure designs.
+//!
+//! Every [`SAMPLE_INTERVAL`], the temperatures of the microcontroller and the running amplifiers (see
+//! [`crate::thermal`]) are added to a record in the format of [`protocol::thermal_log`], with their lowest and highest
+//! values, and the time during which the amplifiers throttled the output. Every [`RECORD_INTERVAL`] of operation,
+//! the record is appended to the log, and a new one starts. Before a power loss, and on shutdown, the current record
+//! is appended early (see [`flush`]).
+//!
+//! The statistics of the whole log are shown with the shell (`thermal log` commands), and read on the bulk endpoints
+//! (see [`create`], and [`protocol::bulk::Target::ThermalLog`]). The log survives factory resets.
+//!
+//! The log shares the storage region of the startup script (see [`crate::storage::STARTUP_SCRIPT_REGION`]), from
+//! [`OFFSET`]. Once the log is nearly full, the region is erased while playback is stopped: all but the newest
+//! [`KEEP_COUNT`] records are merged into one, and the startup script is written back. Storing the startup script
+//! compacts the log the same way (see [`erase_startup_script_region`]). A power loss in between loses the log.
+use core::cell::Cell;
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use embassy_sync::blocking_mutex::raw::{CriticalSectionRawMutex, ThreadModeRawMutex};
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{with_timeout, Duration, Instant};
+use protocol::thermal_log::{Record, ENTRY_SIZE, SENSOR_COUNT};
+use static_assertions::const_assert;
+
+use crate::startup_script;
+use crate::storage::{self, STARTUP_SCRIPT_REGION, WRITE_BLOCK_SIZE};
+use crate::thermal;
+use crate::*;
+
+/// The interval between temperature samples.
+pub const SAMPLE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// The operating time that is covered by one record.
+pub const RECORD_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// The number of newest records that are kept as they are, when the region is erased.
+pub const KEEP_COUNT: usize = 24;
+
+/// The offset of the log in the startup script region, behind the startup script.
+pub const OFFSET: u32 = 0x1000;
+
+/// The size of a slot, in whole write blocks.
+const SLOT_SIZE: usize = ENTRY_SIZE.div_ceil(WRITE_BLOCK_SIZE) * WRITE_BLOCK_SIZE;
+
+/// The number of slots behind the startup script.
+const SLOT_COUNT: u32 = (STARTUP_SCRIPT_REGION.size() - OFFSET) / SLOT_SIZE as u32;
+
+/// The number of used slots, from which on the region is erased once playback stops.
+const ERASE_THRESHOLD: u32 = SLOT_COUNT - KEEP_COUNT as u32;
+
+const_assert!(startup_script::RECORD_SIZE as u32 <= OFFSET);
+const_assert!(SENSOR_COUNT == 1 + OUTPUT_CHANNEL_COUNT);
+
+/// Marks that the region was not scanned yet.
+const NOT_SCANNED: u32 = u32::MAX;
+
+/// The number of used slots, or [`NOT_SCANNED`].
+static USED_SLOTS: AtomicU32 = AtomicU32::new(NOT_SCANNED);
+
+/// The record of the current period, which is not written yet.
+static CURRENT: Mutex<ThreadModeRawMutex, Cell<Record>> = Mutex::new(Cell::new(EMPTY));
+
+/// Signal that is emitted when the current record should be written now.
+static FLUSH_SIGNAL: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// A record without samples.
+const EMPTY: Record = Record {
+    duration_s: 0,
+    throttled_s: 0,
+    minimum_c: [None; SENSOR_COUNT],
+    maximum_c: [None; SENSOR_COUNT],
+};
+
+/// Append the current record now, e.g. before a power loss.
+pub fn flush() {
+    FLUSH_SIGNAL.signal(());
+}
+
+/// The statistics of the whole log, including the current record.
+pub fn statistics() -> Result<Record, storage::Error> {
+    let mut statistics = EMPTY;
+
+    for slot in 0..used_slots()? {
+        if let Some(record) = read_slot(slot)? {
+            statistics.merge(&record);
+        }
+    }
+
+    statistics.merge(&CURRENT.lock(|current| current.get()));
+    Ok(statistics)
+}
+
+/// The number of records in the log, including damaged ones.
+pub fn record_count() -> Result<u32, storage::Error> {
+    used_slots()
+}
+
+/// Erase the log, and keep the startup script.
+pub fn clear() -> Result<(), storage::Error> {
+    rewrite(true, false)?;
+    CURRENT.lock(|current| current.set(EMPTY));
+    Ok(())
+}
+
+/// Erase the startup script region, which keeps the log in compacted form (see the module documentation).
+pub fn erase_startup_script_region() -> Result<(), storage::Error> {
+    rewrite(false, true)
+}
+
+/// Create the payload of a read on the bulk endpoints in the buffer: the statistics of the whole log, followed by the
+/// newest records that fit, oldest first. Returns its length.
+pub fn create(buffer: &mut [u8]) -> Result<usize, storage::Error> {
+    if buffer.len() < ENTRY_SIZE {
+        return Ok(0);
+    }
+
+    buffer[..ENTRY_SIZE].copy_from_slice(&statistics()?.encode());
+
+    let used = used_slots()?;
+    let capacity = (buffer.len() / ENTRY_SIZE - 1) as u32;
+    let mut length = ENTRY_SIZE;
+
+    for slot in used.saturating_sub(capacity)..used {
+        if let Some(record) = read_slot(slot)? {
+            buffer[length..length + ENTRY_SIZE].copy_from_slice(&record.encode());
+            length += ENTRY_SIZE;
+        }
+    }
+
+    Ok(length)
+}
+
+/// The number of used slots, which scans the region once.
+///
+/// The log ends at the first erased slot.
+fn used_slots() -> Result<u32, storage::Error> {
+    let used = USED_SLOTS.load(Ordering::Relaxed);
+    if used != NOT_SCANNED {
+        return Ok(used);
+    }
+
+    let mut data = [0u8; ENTRY_SIZE];
+    let mut used = SLOT_COUNT;
+
+    for slot in 0..SLOT_COUNT {
+        STARTUP_SCRIPT_REGION.read(slot_offset(slot), &mut data)?;
+
+        if data.iter().all(|byte| *byte == 0xFF) {
+            used = slot;
+            break;
+        }
+    }
+
+    USED_SLOTS.store(used, Ordering::Relaxed);
+    Ok(used)
+}
+
+/// The offset of a slot in the startup script region.
+fn slot_offset(slot: u32) -> u32 {
+    OFFSET + slot * SLOT_SIZE as u32
+}
+
+/// Read the record in a slot. Returns `None` for damaged records.
+fn read_slot(slot: u32) -> Result<Option<Record>, storage::Error> {
+    let mut data = [0u8; ENTRY_SIZE];
+    STARTUP_SCRIPT_REGION.read(slot_offset(slot), &mut data)?;
+    Ok(Record::decode(&data))
+}
+
+/// Erase the region, and write back the startup script, and the log in compacted form, as requested.
+fn rewrite(keep_script: bool, keep_log: bool) -> Result<(), storage::Error> {
+    let used = if keep_log { used_slots()? } else { 0 };
+    let kept = used.min(KEEP_COUNT as u32);
+
+    let mut merged = EMPTY;
+    for slot in 0..used - kept {
+        if let Some(record) = read_slot(slot)? {
+            merged.merge(&record);
+        }
+    }
+
+    let mut buffer = [0xFFu8; KEEP_COUNT * SLOT_SIZE];
+    let buffer = &mut buffer[..kept as usize * SLOT_SIZE];
+    STARTUP_SCRIPT_REGION.read(slot_offset(used - kept), buffer)?;
+
+    let mut script = [0xFFu8; startup_script::RECORD_SIZE];
+    if keep_script {
+        STARTUP_SCRIPT_REGION.read(0, &mut script)?;
+    }
+
+    // The log is lost, if writing it back fails.
+    USED_SLOTS.store(0, Ordering::Relaxed);
+    STARTUP_SCRIPT_REGION.erase()?;
+
+    if script.iter().any(|byte| *byte != 0xFF) {
+        STARTUP_SCRIPT_REGION.write(0, &script)?;
+    }
+
+    let mut slot = 0;
+    if used > kept {
+        let mut data = [0u8; SLOT_SIZE];
+        data[..ENTRY_SIZE].copy_from_slice(&merged.encode());
+        STARTUP_SCRIPT_REGION.write(slot_offset(0), &data)?;
+        slot = 1;
+    }
+
+    if !buffer.is_empty() {
+        STARTUP_SCRIPT_REGION.write(slot_offset(slot), buffer)?;
+    }
+
+    USED_SLOTS.store(slot + kept, Ordering::Relaxed);
+
+    if keep_log {
+        log!(info, "Compacted the thermal log");
+    }
+
+    Ok(())
+}
+
+/// Append a record to the log. Returns `false`, if the log is full during playback.
+fn append(record: &Record) -> Result<bool, storage::Error> {
+    if used_slots()? >= SLOT_COUNT {
+        if !storage::erase_allowed() {
+            return Ok(false);
+        }
+
+        rewrite(true, true)?;
+    }
+
+    let used = used_slots()?;
+    let mut slot = [0u8; SLOT_SIZE];
+    slot[..ENTRY_SIZE].copy_from_slice(&record.encode());
+
+    // A failed write may leave a damaged slot, which is skipped.
+    USED_SLOTS.store(used + 1, Ordering::Relaxed);
+    STARTUP_SCRIPT_REGION.write(slot_offset(used), &slot)?;
+    Ok(true)
+}
+
+/// Add the current temperatures to the current record, for the time since the previous sample.
+fn sample(elapsed: Duration) {
+    let elapsed_s = elapsed.as_secs() as u32;
+    let throttled = (0..OUTPUT_CHANNEL_COUNT).any(|channel| thermal::throttle_db(channel) > 0.0);
+
+    CURRENT.lock(|current| {
+        let mut record = current.get();
+
+        record.duration_s = record.duration_s.saturating_add(elapsed_s);
+        if throttled {
+            record.throttled_s = record.throttled_s.saturating_add(elapsed_s);
+        }
+
+        // The microcontroller is the first sensor.
+        if let Some(temperature) = thermal::microcontroller_temperature() {
+            record.add_reading(0, temperature);
+        }
+
+        for channel in 0..OUTPUT_CHANNEL_COUNT {
+            if let Some(temperature) = thermal::temperature(channel) {
+                record.add_reading(1 + channel, temperature);
+            }
+        }
+
+        current.set(record);
+    });
+}
+
+/// Append the current record, and start a new one. A record that finds the log full during playback grows, until it
+/// can be written.
+fn write_current() {
+    let record = CURRENT.lock(|current| current.get());
+    if record.duration_s == 0 {
+        return;
+    }
+
+    match append(&record) {
+        Ok(true) => CURRENT.lock(|current| current.set(EMPTY)),
+        Ok(false) => (),
+        Err(error) => log!(warn, "Failed to write the thermal log: {:?}", error),
+    }
+}
+
+/// Samples the temperatures, and writes the records of the log.
+#[embassy_executor::task]
+pub async fn thermal_log_task() {
+    let mut last_sample = Instant::now();
+
+    loop {
+        let flushed = with_timeout(SAMPLE_INTERVAL, FLUSH_SIGNAL.wait()).await.is_ok();
+
+        let now = Instant::now();
+        sample(now - last_sample);
+        last_sample = now;
+
+        let complete = CURRENT.lock(|current| current.get().duration_s) as u64 >= RECORD_INTERVAL.as_secs();
+        if flushed || complete {
+            write_current();
+        }
+
+        let compact = used_slots().is_ok_and(|used| used >= ERASE_THRESHOLD);
+        if compact && storage::erase_allowed() {
+            if let Err(error) = rewrite(true, true) {
+                log!(warn, "Failed to compact the thermal log: {:?}", error);
+            }
+        }
+    }
+}
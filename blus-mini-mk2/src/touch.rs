@@ -0,0 +1,339 @@
+//! Capacitive touch pads (volume up and down, mute, and source), as an alternative front panel.
+//!
+//! The STM32H723 has no touch sensing controller (TSC), so the pads are measured in software, by their discharge time:
+//! every pad is on a GPIO (PE7 to PE10) with a bleed resistor to ground (about 1 MΩ). The pin charges the pad, then
+//! releases it, and counts the polls until the pad reads low. A finger adds capacitance, which lengthens the count.
+//! Interrupts can only lengthen a count as well, so the shortest of [`SAMPLE_COUNT`] counts is taken.
+//!
+//! Every pad is calibrated at startup (and on request, see [`calibrate`]): its baseline is the mean count over
+//! [`CALIBRATION_SCAN_COUNT`] scans, without touching the pads. A pad is touched, once its count exceeds the baseline
+//! by the threshold (see [`set_threshold_percent`]) for [`TOUCH_SCAN_COUNT`] scans, and released below half of it.
+//! Environmental drift (temperature, humidity) is compensated:
+//! - While a pad is released, its baseline slowly follows the count ([`DRIFT_TIME`]), and faster, if the count falls
+//!   below the baseline (touches only ever raise it).
+//! - A pad that stays touched for [`MAX_TOUCH_TIME`] (e.g. under a drop of water) is recalibrated to its count.
+//!
+//! A pad whose count never ends (without the bleed resistor, i.e. not fitted) is absent, and ignored. The threshold is
+//! not stored with the settings; a startup script can configure it (shell `touch`, see [`crate::startup_script`]).
+use core::cell::Cell;
+use core::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+
+use embassy_futures::yield_now;
+use embassy_stm32::gpio::{Flex, Pull, Speed};
+use embassy_stm32::peripherals;
+use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_time::{Duration, Instant, Ticker};
+
+use crate::control::{self, CONTROL};
+use crate::cpu_load::{self, Section};
+use crate::*;
+
+/// The number of touch pads.
+pub const PAD_COUNT: usize = 4;
+
+/// The number of counts per pad and scan, of which the shortest is taken.
+pub const SAMPLE_COUNT: usize = 4;
+
+/// The number of scans, whose mean count is the baseline of a calibrated pad.
+pub const CALIBRATION_SCAN_COUNT: u32 = 32;
+
+/// The number of consecutive scans above the threshold, which make a touch.
+pub const TOUCH_SCAN_COUNT: u8 = 2;
+
+/// The default threshold in percent of the baseline.
+pub const DEFAULT_THRESHOLD_PERCENT: u8 = 10;
+
+/// The time constant, by which the baseline of a released pad follows its count.
+pub const DRIFT_TIME: Duration = Duration::from_secs(10);
+
+/// The time after which a pad that stays touched is recalibrated.
+pub const MAX_TOUCH_TIME: Duration = Duration::from_secs(20);
+
+/// The rate of scans of all pads.
+const SCAN_RATE_HZ: u64 = 50;
+
+/// The number of cycles, for which the pin charges the pad (about 2 µs).
+const CHARGE_CYCLES: u32 = 1_000;
+
+/// The count after which a pad is absent.
+const MAX_COUNT: u32 = 20_000;
+
+/// The rate at which the baseline follows a count below it, per scan.
+const FALLING_DRIFT_RATE: f32 = 1.0 / 16.0;
+
+/// The delay after touching a volume pad, until the volume changes repeatedly.
+const REPEAT_DELAY: Duration = Duration::from_millis(500);
+
+/// The interval between volume changes, while a volume pad is held.
+const REPEAT_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The attenuation change per volume step, in steps of 0.5 dB.
+const VOLUME_STEP: u8 = 2;
+
+/// The threshold in percent of the baseline.
+static THRESHOLD_PERCENT: AtomicU8 = AtomicU8::new(DEFAULT_THRESHOLD_PERCENT);
+
+/// Whether a calibration of all pads is requested.
+static CALIBRATE: AtomicBool = AtomicBool::new(true);
+
+/// The states of all pads.
+static STATUS: Mutex<ThreadModeRawMutex, Cell<[PadStatus; PAD_COUNT]>> =
+    Mutex::new(Cell::new([PadStatus::new(); PAD_COUNT]));
+
+/// A touch pad, by its function.
+#[derive(Clone, Copy, PartialEq, Debug, defmt::Format)]
+pub enum TouchPad {
+    /// Raises the master volume, repeatedly while held.
+    VolumeUp = 0,
+    /// Lowers the master volume, repeatedly while held.
+    VolumeDown = 1,
+    /// Toggles the master mute.
+    Mute = 2,
+    /// Selects the next source.
+    Source = 3,
+}
+
+impl TouchPad {
+    /// All pads, in order of their pins.
+    pub const ALL: [TouchPad; PAD_COUNT] = [
+        TouchPad::VolumeUp,
+        TouchPad::VolumeDown,
+        TouchPad::Mute,
+        TouchPad::Source,
+    ];
+}
+
+/// The state of a touch pad.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct PadStatus {
+    /// The baseline count, or `None`, while the pad is absent or not calibrated.
+    pub baseline: Option<f32>,
+    /// The last count.
+    pub count: u32,
+    /// Whether the pad is touched.
+    pub touched: bool,
+}
+
+impl PadStatus {
+    const fn new() -> Self {
+        PadStatus {
+            baseline: None,
+            count: 0,
+            touched: false,
+        }
+    }
+}
+
+/// Resources that are required for the touch pads.
+#[allow(missing_docs)]
+pub struct TouchResources {
+    pub volume_up: peripherals::PE7,
+    pub volume_down: peripherals::PE8,
+    pub mute: peripherals::PE9,
+    pub source: peripherals::PE10,
+}
+
+/// The name of a pad, as used by the text interfaces.
+pub fn pad_name(pad: TouchPad) -> &'static str {
+    match pad {
+        TouchPad::VolumeUp => "volume-up",
+        TouchPad::VolumeDown => "volume-down",
+        TouchPad::Mute => "mute",
+        TouchPad::Source => "source",
+    }
+}
+
+/// The threshold in percent of the baseline.
+pub fn threshold_percent() -> u8 {
+    THRESHOLD_PERCENT.load(Ordering::Relaxed)
+}
+
+/// Set the threshold in percent of the baseline (1 to 100).
+pub fn set_threshold_percent(percent: u8) {
+    THRESHOLD_PERCENT.store(percent.clamp(1, 100), Ordering::Relaxed);
+}
+
+/// Request a calibration of all pads, which must not be touched during the next [`CALIBRATION_SCAN_COUNT`] scans.
+pub fn calibrate() {
+    CALIBRATE.store(true, Ordering::Relaxed);
+}
+
+/// The states of all pads.
+pub fn status() -> [PadStatus; PAD_COUNT] {
+    STATUS.lock(|status| status.get())
+}
+
+/// Measure the discharge time of a pad, as the shortest of [`SAMPLE_COUNT`] counts. Returns `None` for an absent pad.
+fn measure(pin: &mut Flex<'_>) -> Option<u32> {
+    let mut shortest = MAX_COUNT;
+
+    for _ in 0..SAMPLE_COUNT {
+        pin.set_high();
+        pin.set_as_output(Speed::Low);
+        cortex_m::asm::delay(CHARGE_CYCLES);
+        pin.set_as_input(Pull::None);
+
+        let mut count = 0;
+        while pin.is_high() && count < MAX_COUNT {
+            count += 1;
+        }
+
+        shortest = shortest.min(count);
+    }
+
+    // Leave the pad discharged.
+    pin.set_low();
+    pin.set_as_output(Speed::Low);
+
+    (shortest < MAX_COUNT).then_some(shortest)
+}
+
+/// Change the master volume by a step.
+fn step_volume(pad: TouchPad) {
+    let attenuation = match pad {
+        TouchPad::VolumeUp => CONTROL.attenuation().saturating_sub(VOLUME_STEP),
+        _ => CONTROL
+            .attenuation()
+            .saturating_add(VOLUME_STEP)
+            .min(control::MUTED_ATTENUATION - 1),
+    };
+
+    CONTROL.set_attenuation(attenuation);
+}
+
+/// Perform the action of a pad that was touched, or is held since a time.
+fn perform(pad: TouchPad, touched: Instant, repeated: &mut Option<Instant>) {
+    match pad {
+        TouchPad::VolumeUp | TouchPad::VolumeDown => {
+            let due = match *repeated {
+                None => true,
+                Some(last) if touched.elapsed() >= REPEAT_DELAY => last.elapsed() >= REPEAT_INTERVAL,
+                Some(_) => false,
+            };
+
+            if due {
+                step_volume(pad);
+                *repeated = Some(Instant::now());
+            }
+        }
+        TouchPad::Mute if repeated.is_none() => {
+            CONTROL.set_muted(!CONTROL.muted());
+            *repeated = Some(Instant::now());
+        }
+        TouchPad::Source if repeated.is_none() => {
+            CONTROL.select_next_source();
+            *repeated = Some(Instant::now());
+        }
+        _ => (),
+    }
+}
+
+/// Scans the touch pads, and performs their actions.
+#[embassy_executor::task]
+pub async fn touch_task(resources: TouchResources) {
+    let mut pins = [
+        Flex::new(resources.volume_up),
+        Flex::new(resources.volume_down),
+        Flex::new(resources.mute),
+        Flex::new(resources.source),
+    ];
+
+    let drift_rate = 1.0 / (DRIFT_TIME.as_millis() * SCAN_RATE_HZ / 1000) as f32;
+
+    let mut status = [PadStatus::new(); PAD_COUNT];
+    // The sum of counts, and the number of scans of a running calibration.
+    let mut calibration = [(0u32, 0u32); PAD_COUNT];
+    let mut calibrating = false;
+    // The number of consecutive scans above the threshold.
+    let mut above = [0u8; PAD_COUNT];
+    // The time of the touch, and of the last action.
+    let mut touches: [Option<(Instant, Option<Instant>)>; PAD_COUNT] = [None; PAD_COUNT];
+
+    let mut ticker = Ticker::every(Duration::from_hz(SCAN_RATE_HZ));
+
+    loop {
+        ticker.next().await;
+
+        if CALIBRATE.swap(false, Ordering::Relaxed) {
+            calibration = [(0, 0); PAD_COUNT];
+            calibrating = true;
+            touches = [None; PAD_COUNT];
+        }
+
+        let threshold = threshold_percent() as f32 / 100.0;
+
+        for (index, pin) in pins.iter_mut().enumerate() {
+            let pad = TouchPad::ALL[index];
+            let count = cpu_load::measure(Section::Touch, || measure(pin));
+            yield_now().await;
+
+            if calibrating {
+                let (sum, scans) = &mut calibration[index];
+
+                // A single absent count marks the pad as absent.
+                match count {
+                    Some(count) if *scans != u32::MAX => {
+                        *sum += count;
+                        *scans += 1;
+                    }
+                    _ => *scans = u32::MAX,
+                }
+
+                continue;
+            }
+
+            let (Some(count), Some(baseline)) = (count, status[index].baseline) else {
+                continue;
+            };
+
+            status[index].count = count;
+            let delta = (count as f32 - baseline) / baseline;
+
+            above[index] = if delta >= threshold {
+                above[index].saturating_add(1)
+            } else {
+                0
+            };
+
+            if touches[index].is_none() && above[index] >= TOUCH_SCAN_COUNT {
+                log!(debug, "Touch pad touched: {:?}", pad);
+                touches[index] = Some((Instant::now(), None));
+            } else if touches[index].is_some() && delta < threshold / 2.0 {
+                touches[index] = None;
+            }
+
+            let held_too_long = touches[index].is_some_and(|(touched, _)| touched.elapsed() >= MAX_TOUCH_TIME);
+
+            if held_too_long {
+                log!(warn, "Touch pad {:?} held for too long, recalibrated", pad);
+                status[index].baseline = Some(count as f32);
+                touches[index] = None;
+            } else if let Some((touched, repeated)) = &mut touches[index] {
+                perform(pad, *touched, repeated);
+            } else if delta < threshold / 2.0 {
+                let rate = if delta < 0.0 { FALLING_DRIFT_RATE } else { drift_rate };
+                status[index].baseline = Some(baseline + (count as f32 - baseline) * rate);
+            }
+
+            status[index].touched = touches[index].is_some();
+        }
+
+        if calibrating && calibration.iter().all(|(_, scans)| *scans >= CALIBRATION_SCAN_COUNT) {
+            calibrating = false;
+
+            for (index, (sum, scans)) in calibration.iter().enumerate() {
+                let baseline = (*scans != u32::MAX).then(|| *sum as f32 / *scans as f32);
+                status[index].baseline = baseline;
+                status[index].touched = false;
+
+                match baseline {
+                    Some(baseline) => log!(debug, "Touch pad {:?} calibrated: {}", TouchPad::ALL[index], baseline),
+                    None => log!(debug, "Touch pad {:?} absent", TouchPad::ALL[index]),
+                }
+            }
+        }
+
+        STATUS.lock(|current| current.set(status));
+    }
+}
@@ -0,0 +1,144 @@
+//! 12 V trigger input and output, for powering up and down together with other audio/video equipment.
+//!
+//! The trigger voltage is level-shifted to the input (active high, e.g. by an optocoupler or a divider). A rising
+//! trigger leaves standby, and a falling trigger enters standby (see [`Control::set_standby`]). Only changes of the
+//! trigger are acted upon, so that standby can still be controlled by other means, and an unconnected trigger input
+//! has no effect.
+//!
+//! The output drives a 12 V switch (active high) for downstream equipment, such as powered subwoofers. Depending on
+//! the [`TriggerMode`], it follows the standby state, or the playback of a source.
+//!
+//! [`Control::set_standby`]: crate::control::Control::set_standby
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use embassy_futures::join::join;
+use embassy_stm32::exti::ExtiInput;
+use embassy_stm32::gpio::{Level, Output, Pull, Speed};
+use embassy_stm32::peripherals;
+use embassy_time::{Duration, Instant, Ticker, Timer};
+
+use crate::control::CONTROL;
+use crate::*;
+
+/// The time for the trigger input to settle, which suppresses glitches from switching upstream equipment.
+const SETTLE_TIME: Duration = Duration::from_millis(50);
+
+/// The rate at which the state of the trigger output is updated.
+const OUTPUT_UPDATE_RATE_HZ: u64 = 10;
+
+/// In [`TriggerMode::Source`], the output stays on for this time after playback stopped, which bridges pauses
+/// between tracks.
+const SOURCE_HOLD_TIME: Duration = Duration::from_secs(30);
+
+/// The condition for the trigger output to switch on.
+#[repr(u8)]
+#[derive(Clone, Copy, PartialEq, Debug, defmt::Format)]
+pub enum TriggerMode {
+    /// On, while the device is not in standby.
+    Standby = 0,
+    /// On, while a source is playing.
+    Source = 1,
+}
+
+impl TryFrom<u8> for TriggerMode {
+    type Error = u8;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(TriggerMode::Standby),
+            1 => Ok(TriggerMode::Source),
+            _ => Err(value),
+        }
+    }
+}
+
+/// The mode of the trigger output.
+static TRIGGER_MODE: AtomicU8 = AtomicU8::new(TriggerMode::Standby as u8);
+
+/// Get the mode of the trigger output.
+pub fn trigger_mode() -> TriggerMode {
+    TriggerMode::try_from(TRIGGER_MODE.load(Ordering::Relaxed)).unwrap_or(TriggerMode::Standby)
+}
+
+/// Set the mode of the trigger output.
+pub fn set_trigger_mode(mode: TriggerMode) {
+    TRIGGER_MODE.store(mode as u8, Ordering::Relaxed);
+}
+
+/// The name of a trigger mode, as used by the text interfaces.
+pub fn trigger_mode_name(mode: TriggerMode) -> &'static str {
+    match mode {
+        TriggerMode::Standby => "standby",
+        TriggerMode::Source => "source",
+    }
+}
+
+/// Parse the name of a trigger mode.
+pub fn parse_trigger_mode(name: &str) -> Option<TriggerMode> {
+    [TriggerMode::Standby, TriggerMode::Source]
+        .into_iter()
+        .find(|mode| trigger_mode_name(*mode) == name)
+}
+
+/// Resources that are required for the trigger input and output.
+#[allow(missing_docs)]
+pub struct TriggerResources {
+    pub input: peripherals::PD8,
+    pub input_exti: peripherals::EXTI8,
+
+    pub output: peripherals::PD9,
+}
+
+/// Follows the trigger input with the standby state.
+async fn follow_input(mut input: ExtiInput<'_>) {
+    let mut active = input.is_high();
+
+    loop {
+        input.wait_for_any_edge().await;
+        Timer::after(SETTLE_TIME).await;
+
+        let level = input.is_high();
+        if level == active {
+            continue;
+        }
+        active = level;
+
+        log!(info, "Trigger input: {}", if active { "on" } else { "off" });
+        CONTROL.set_standby(!active);
+    }
+}
+
+/// Drives the trigger output, according to the trigger mode.
+async fn drive_output(mut output: Output<'_>) {
+    let mut ticker = Ticker::every(Duration::from_hz(OUTPUT_UPDATE_RATE_HZ));
+    let mut last_playback: Option<Instant> = None;
+
+    loop {
+        ticker.next().await;
+
+        let now = Instant::now();
+        if CONTROL.active_source() != AudioSource::None {
+            last_playback = Some(now);
+        }
+
+        let on = !CONTROL.standby()
+            && match trigger_mode() {
+                TriggerMode::Standby => true,
+                TriggerMode::Source => last_playback.is_some_and(|time| now - time < SOURCE_HOLD_TIME),
+            };
+
+        if on != output.is_set_high() {
+            log!(info, "Trigger output: {}", if on { "on" } else { "off" });
+            output.set_level(Level::from(on));
+        }
+    }
+}
+
+/// Follows the trigger input with the standby state, and drives the trigger output.
+#[embassy_executor::task]
+pub async fn trigger_task(resources: TriggerResources) {
+    let input = ExtiInput::new(resources.input, resources.input_exti, Pull::Down);
+    let output = Output::new(resources.output, Level::Low, Speed::Low);
+
+    join(follow_input(input), drive_output(output)).await;
+}
@@ -1,129 +1,173 @@
-//! Interfaces to the USB audio class and transports samples to the audio routing task.
-use defmt::{debug, panic};
+//! Interfaces to the USB audio class and transports samples to the audio routing task, through the USB ring (see
+//! [`UsbRing`]).
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use audio_pipeline::ring::Producer;
+use embassy_futures::select::select;
 use embassy_stm32::{peripherals, usb};
-use embassy_sync::blocking_mutex::raw::NoopRawMutex;
-use embassy_sync::channel;
+use embassy_time::{with_timeout, Timer};
 use embassy_usb::class::uac1::speaker;
-use embassy_usb::driver::EndpointError;
 use static_assertions;
-
+use usb_stream::handler::{self, SampleSink};
+use usb_stream::{FeedbackFormat, StreamConfig};
+
+use crate::audio_routing::STATS;
+use crate::control::{self, VolumeWriter, CONTROL};
+use crate::led;
+use crate::log;
+use crate::startup;
+use crate::watchdog::{self, Task};
 use crate::*;
 
-// Number of ticks of the feedback timer per audio sample period.
-const TICKS_PER_SAMPLE: u32 = FEEDBACK_COUNTER_TICK_RATE / SAMPLE_RATE_HZ;
-static_assertions::const_assert_eq!(TICKS_PER_SAMPLE * SAMPLE_RATE_HZ, FEEDBACK_COUNTER_TICK_RATE);
+// The stream carries 32 bit samples.
+static_assertions::const_assert_eq!(SAMPLE_SIZE, usb_stream::SAMPLE_SIZE);
 
 // Feedback is provided in 16.16 format for high-speed endpoints.
 #[cfg(feature = "usb_high_speed")]
-const FEEDBACK_SHIFT: usize = 16;
+const FEEDBACK_FORMAT: FeedbackFormat = FeedbackFormat::HighSpeed;
 
 // Feedback is provided in 10.14 format for full-speed endpoints.
 #[cfg(not(feature = "usb_high_speed"))]
-const FEEDBACK_SHIFT: usize = 14;
-
-const FEEDBACK_FACTOR: u32 = ((1 << FEEDBACK_SHIFT) / TICKS_PER_SAMPLE) / FEEDBACK_REFRESH_PERIOD.frame_count() as u32;
-static_assertions::const_assert_eq!(
-    (FEEDBACK_FACTOR << (FEEDBACK_REFRESH_PERIOD as usize)) * TICKS_PER_SAMPLE,
-    (1 << FEEDBACK_SHIFT)
-);
+const FEEDBACK_FORMAT: FeedbackFormat = FeedbackFormat::FullSpeed;
+
+/// The parameters of the stream from the host.
+const STREAM_CONFIG: StreamConfig = StreamConfig {
+    sample_rate_hz: SAMPLE_RATE_HZ,
+    feedback_counter_tick_rate_hz: FEEDBACK_COUNTER_TICK_RATE,
+    feedback_refresh_frame_count: FEEDBACK_REFRESH_PERIOD.frame_count() as u32,
+    feedback_format: FEEDBACK_FORMAT,
+};
+
+/// Whether the device is configured by the USB host, and not suspended.
+static USB_ACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// Whether the device is configured by the USB host, and not suspended.
+pub fn usb_active() -> bool {
+    USB_ACTIVE.load(Ordering::Relaxed)
+}
 
-struct Disconnected {}
+/// Follows the state of the USB device.
+pub struct UsbStateHandler {
+    configured: bool,
+    suspended: bool,
+}
 
-impl From<EndpointError> for Disconnected {
-    fn from(val: EndpointError) -> Self {
-        match val {
-            EndpointError::BufferOverflow => panic!("Buffer overflow"),
-            EndpointError::Disabled => Disconnected {},
+impl UsbStateHandler {
+    /// Create a handler for an unconfigured device.
+    pub const fn new() -> Self {
+        UsbStateHandler {
+            configured: false,
+            suspended: false,
         }
     }
-}
 
-async fn feedback_handler<'d, T: usb::Instance + 'd>(
-    feedback: &mut speaker::Feedback<'d, usb::Driver<'d, T>>,
-) -> Result<(), Disconnected> {
-    let mut packet: Vec<u8, 4> = Vec::new();
+    fn update(&self) {
+        USB_ACTIVE.store(self.configured && !self.suspended, Ordering::Relaxed);
+    }
+}
 
-    loop {
-        let counter = FEEDBACK_SIGNAL.wait().await;
+impl Default for UsbStateHandler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
-        packet.clear();
+impl embassy_usb::Handler for UsbStateHandler {
+    fn enabled(&mut self, enabled: bool) {
+        if enabled {
+            startup::complete(startup::Step::Usb);
+        } else {
+            self.configured = false;
+            self.update();
+        }
+    }
 
-        let value = counter * FEEDBACK_FACTOR;
+    fn reset(&mut self) {
+        self.configured = false;
+        self.suspended = false;
+        self.update();
+    }
 
-        #[cfg(feature = "usb_high_speed")]
-        {
-            packet.push(value as u8).unwrap();
-            packet.push((value >> 8) as u8).unwrap();
-            packet.push((value >> 16) as u8).unwrap();
-            packet.push((value >> 24) as u8).unwrap();
-        }
+    fn configured(&mut self, configured: bool) {
+        self.configured = configured;
+        self.update();
 
-        #[cfg(not(feature = "usb_high_speed"))]
-        {
-            packet.push(value as u8).unwrap();
-            packet.push((value >> 8) as u8).unwrap();
-            packet.push((value >> 16) as u8).unwrap();
+        if configured {
+            led::boot_progress(led::BootStage::Usb);
         }
+    }
 
-        feedback.write_packet(&packet).await?;
+    fn suspended(&mut self, suspended: bool) {
+        self.suspended = suspended;
+        self.update();
     }
 }
 
-async fn stream_handler<'d, T: usb::Instance + 'd>(
-    stream: &mut speaker::Stream<'d, usb::Driver<'d, T>>,
-    audio_channel_sender: &mut channel::Sender<'static, NoopRawMutex, SampleBlock, SAMPLE_BLOCK_COUNT>,
-) -> Result<(), Disconnected> {
-    loop {
-        let mut usb_data = [0u8; USB_MAX_PACKET_SIZE];
-        let data_size = stream.read_packet(&mut usb_data).await?;
-
-        let word_count = data_size / SAMPLE_SIZE;
+/// Writes the samples from the host into the USB ring, from which the audio routing reads them.
+struct RingSink {
+    producer: Producer<'static, USB_RING_SIZE>,
+}
 
-        if word_count * SAMPLE_SIZE == data_size {
-            let mut samples: UsbSampleBlock = Vec::new();
+impl SampleSink for RingSink {
+    async fn send(&mut self, samples: impl ExactSizeIterator<Item = u32>) {
+        // Only whole frames keep the channels of the ring in order.
+        if samples.len() % INPUT_CHANNEL_COUNT != 0 {
+            log!(
+                debug,
+                "USB: Packet of {} samples without whole frames, skipped",
+                samples.len()
+            );
+            return;
+        }
 
-            for w in 0..word_count {
-                let byte_offset = w * SAMPLE_SIZE;
-                let sample = u32::from_le_bytes(usb_data[byte_offset..byte_offset + SAMPLE_SIZE].try_into().unwrap());
+        #[cfg(feature = "latency_probe")]
+        let position = self.producer.position();
 
-                // Fill the sample buffer with data.
-                samples.push(sample).unwrap();
-            }
+        if self.producer.push(samples) {
+            #[cfg(feature = "latency_probe")]
+            latency_probe::usb_received(position);
 
-            if audio_channel_sender.try_send(SampleBlock::Usb(samples)).is_err() {
-                debug!("USB: Failed to send to channel")
-            }
+            USB_SAMPLES_SIGNAL.signal(());
         } else {
-            debug!("USB: Invalid USB buffer size of {}, skipped", data_size);
+            STATS.count_overrun();
+            log!(debug, "USB: Failed to write to ring")
         }
     }
+
+    fn skip(&mut self, size: usize) {
+        log!(debug, "USB: Invalid USB buffer size of {}, skipped", size);
+    }
 }
 
 /// Stream audio samples from the host.
 #[embassy_executor::task]
 pub async fn streaming_task(
     mut stream: speaker::Stream<'static, usb::Driver<'static, peripherals::USB_OTG_HS>>,
-    mut audio_channel: channel::Sender<'static, NoopRawMutex, SampleBlock, SAMPLE_BLOCK_COUNT>,
+    producer: Producer<'static, USB_RING_SIZE>,
 ) {
-    loop {
-        stream.wait_connection().await;
-        _ = stream_handler(&mut stream, &mut audio_channel).await;
-    }
+    let mut packet = [0u8; USB_MAX_PACKET_SIZE];
+    let mut sink = RingSink { producer };
+
+    handler::stream(&mut stream, &mut packet, &mut sink).await
 }
 
 /// Provide feedback information to the host.
 #[embassy_executor::task]
 pub async fn feedback_task(mut feedback: speaker::Feedback<'static, usb::Driver<'static, peripherals::USB_OTG_HS>>) {
-    loop {
-        feedback.wait_connection().await;
-        _ = feedback_handler(&mut feedback).await;
-    }
+    handler::feedback(&mut feedback, &FEEDBACK_SIGNAL, &STREAM_CONFIG).await
 }
 
-/// Run the USB device task.
+/// Run the USB device task, which checks in with the watchdog while it runs.
 #[embassy_executor::task]
 pub async fn usb_task(mut usb_device: embassy_usb::UsbDevice<'static, usb::Driver<'static, peripherals::USB_OTG_HS>>) {
-    usb_device.run().await;
+    let check_in = async {
+        loop {
+            watchdog::check_in(Task::Usb);
+            Timer::after(watchdog::CHECK_IN_INTERVAL).await;
+        }
+    };
+
+    select(usb_device.run(), check_in).await;
 }
 
 /// The USB control task.
@@ -132,39 +176,39 @@ pub async fn usb_task(mut usb_device: embassy_usb::UsbDevice<'static, usb::Drive
 /// - Volume adjustment
 /// - Sample rate adjustment (not used, is fixed)
 /// - Sample width adjustment (not used, is fixed)
+///
+/// The louder of the host's channel volumes is applied as master volume, whenever the host changes it (see
+/// [`crate::control`] for the volume arbitration). The balance between the channels only applies to USB audio.
 #[embassy_executor::task]
 pub async fn control_task(control_monitor: speaker::ControlMonitor<'static>) {
+    // The last volume of the host, as master volume attenuation.
+    let mut host_attenuation = None;
+
     loop {
-        control_monitor.changed().await;
-
-        let mut usb_gain_left = 0.0_f32;
-        let mut usb_gain_right = 0.0_f32;
-
-        for channel in USB_AUDIO_CHANNELS {
-            let volume = control_monitor.volume(channel).unwrap();
-
-            let gain = match volume {
-                speaker::Volume::Muted => 0.0,
-                speaker::Volume::DeciBel(volume_db) => {
-                    if volume_db > 0.0 {
-                        panic!("Volume must not be positive.")
-                    }
-
-                    db_to_linear(volume_db)
-                }
-            };
-
-            match channel {
-                uac1::Channel::LeftFront => {
-                    usb_gain_left = gain;
-                }
-                uac1::Channel::RightFront => {
-                    usb_gain_right = gain;
-                }
-                _ => (),
-            }
+        watchdog::check_in(Task::Control);
+
+        if with_timeout(watchdog::CHECK_IN_INTERVAL, control_monitor.changed())
+            .await
+            .is_err()
+        {
+            continue;
         }
 
-        USB_GAIN_SIGNAL.signal((usb_gain_left, usb_gain_right));
+        let usb_gains = handler::channel_gains(&control_monitor, USB_AUDIO_CHANNELS);
+        let loudest_gain = usb_gains.iter().copied().fold(0.0, f32::max);
+
+        if loudest_gain <= 0.0 {
+            // Muted by the host.
+            CONTROL.set_usb_gains(&[0.0; INPUT_CHANNEL_COUNT]);
+            continue;
+        }
+
+        CONTROL.set_usb_gains(&usb_gains.map(|gain| gain / loudest_gain));
+
+        let attenuation = control::level_to_attenuation(loudest_gain);
+        if host_attenuation != Some(attenuation) {
+            host_attenuation = Some(attenuation);
+            CONTROL.set_attenuation_by(VolumeWriter::UsbHost, attenuation);
+        }
     }
 }
@@ -0,0 +1,199 @@
+//! The independent watchdog, and a supervisor that only feeds it while all supervised tasks are alive.
+//!
+//! The audio routing, the USB device, and the USB control task check in regularly (see [`check_in`]), also while
+//! they idle. Once a task did not check in for [`CHECK_IN_TIMEOUT`], the [`watchdog_task`] stops feeding the
+//! watchdog, which resets the microcontroller after [`WATCHDOG_TIMEOUT`]. The stalled tasks are left in SRAM4, which
+//! keeps its content across resets, and recorded in the event log after the reset (see [`take_stalled_tasks`]),
+//! along with the watchdog reset itself (see [`crate::system`]).
+//!
+//! The health of the supervised tasks (see [`health`]) shows on a heartbeat LED (see [`crate::led::heartbeat_task`]).
+//!
+//! Erasing flash sectors stalls all tasks, and holds off the supervision (see [`hold_off`]). The watchdog keeps
+//! running while the core is halted by a debugger.
+use core::mem::MaybeUninit;
+use core::ptr::addr_of_mut;
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use embassy_stm32::pac::iwdg::vals::Key;
+use embassy_stm32::wdg::IndependentWatchdog;
+use embassy_stm32::{pac, peripherals};
+use embassy_time::{Duration, Instant, Ticker};
+
+use crate::*;
+
+/// The time after which the watchdog resets the microcontroller, unless it is fed. Exceeds the time for erasing a
+/// flash sector, which stalls all tasks (see [`hold_off`]).
+pub const WATCHDOG_TIMEOUT: Duration = Duration::from_secs(4);
+
+/// The time within which every supervised task must check in.
+pub const CHECK_IN_TIMEOUT: Duration = Duration::from_secs(4);
+
+/// The interval, at which idle tasks check in.
+pub const CHECK_IN_INTERVAL: Duration = Duration::from_millis(500);
+
+/// The time without check-in, after which a task counts as late. Idle tasks check in well within it.
+pub const LATE_TIME: Duration = Duration::from_millis(2 * CHECK_IN_INTERVAL.as_millis());
+
+/// The interval between checks of the supervised tasks.
+const SUPERVISION_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Marks a valid mask of stalled tasks in the upper half of [`STALLED_TASKS`].
+const STALLED_TASKS_MAGIC: u32 = 0x57A1_0000;
+
+/// A task that is supervised by the watchdog.
+#[repr(u8)]
+#[derive(Clone, Copy, PartialEq, Debug, defmt::Format)]
+pub enum Task {
+    /// The audio routing.
+    Audio = 0,
+    /// The USB device.
+    Usb = 1,
+    /// The USB control task (volume changes by the host).
+    Control = 2,
+}
+
+impl Task {
+    /// All supervised tasks.
+    pub const ALL: [Task; 3] = [Task::Audio, Task::Usb, Task::Control];
+
+    /// The bit of the task in a mask of tasks.
+    pub fn mask(self) -> u8 {
+        1 << self as u8
+    }
+}
+
+/// The health of the supervised tasks.
+#[derive(Clone, Copy, PartialEq, Debug, defmt::Format)]
+pub enum Health {
+    /// All tasks check in.
+    Alive,
+    /// A task did not check in for [`LATE_TIME`].
+    Late,
+    /// A task did not check in for [`CHECK_IN_TIMEOUT`], and the watchdog is about to reset the microcontroller.
+    Stalled,
+}
+
+/// The name of a supervised task, as used by the text interfaces.
+pub fn task_name(task: Task) -> &'static str {
+    match task {
+        Task::Audio => "audio",
+        Task::Usb => "usb",
+        Task::Control => "control",
+    }
+}
+
+/// The uptime in ms at the last check-in, by task.
+static CHECK_INS_MS: [AtomicU32; Task::ALL.len()] = [const { AtomicU32::new(0) }; Task::ALL.len()];
+
+/// Holds [`STALLED_TASKS_MAGIC`] and the mask of stalled tasks during a watchdog reset. Not initialized at startup.
+#[link_section = ".sram4"]
+static mut STALLED_TASKS: MaybeUninit<u32> = MaybeUninit::uninit();
+
+/// Resources that are required for the watchdog.
+#[allow(missing_docs)]
+pub struct WatchdogResources {
+    pub iwdg: peripherals::IWDG1,
+}
+
+/// The uptime in ms, which wraps after 49 days.
+fn uptime_ms() -> u32 {
+    Instant::now().as_millis() as u32
+}
+
+/// Check in a supervised task, which shows that it is alive.
+pub fn check_in(task: Task) {
+    CHECK_INS_MS[task as usize].store(uptime_ms(), Ordering::Relaxed);
+}
+
+/// Feed the watchdog, and restart the check-in timeouts, around a blocking operation that stalls all tasks (e.g.
+/// erasing a flash sector).
+pub fn hold_off() {
+    // Reloading the counter has no effect, before the watchdog is started.
+    pac::IWDG1.kr().write(|w| w.set_key(Key::RESET));
+
+    for task in Task::ALL {
+        check_in(task);
+    }
+}
+
+/// The mask of tasks, which did not check in for a time.
+fn silent_tasks(time: Duration) -> u8 {
+    let now_ms = uptime_ms();
+
+    Task::ALL
+        .iter()
+        .filter(|task| {
+            let check_in_ms = CHECK_INS_MS[**task as usize].load(Ordering::Relaxed);
+            now_ms.wrapping_sub(check_in_ms) >= time.as_millis() as u32
+        })
+        .fold(0, |mask, task| mask | task.mask())
+}
+
+/// The mask of tasks, which did not check in for [`CHECK_IN_TIMEOUT`].
+fn stalled_tasks() -> u8 {
+    silent_tasks(CHECK_IN_TIMEOUT)
+}
+
+/// The health of the supervised tasks.
+pub fn health() -> Health {
+    if stalled_tasks() != 0 {
+        Health::Stalled
+    } else if silent_tasks(LATE_TIME) != 0 {
+        Health::Late
+    } else {
+        Health::Alive
+    }
+}
+
+/// Leave the mask of stalled tasks for after the reset.
+fn set_stalled_tasks(mask: u8) {
+    // SAFETY: Only accessed by the thread-mode executor.
+    unsafe {
+        addr_of_mut!(STALLED_TASKS)
+            .cast::<u32>()
+            .write_volatile(STALLED_TASKS_MAGIC | mask as u32);
+    }
+}
+
+/// The mask of tasks that stalled before the last reset, if it was caused by the watchdog. Clears the mask.
+///
+/// Must be called once at startup, before the [`watchdog_task`] runs.
+pub fn take_stalled_tasks() -> Option<u8> {
+    // SAFETY: See `set_stalled_tasks`. The memory may hold any value after power-up, which is valid for `u32`.
+    let value = unsafe { addr_of_mut!(STALLED_TASKS).cast::<u32>().read_volatile() };
+    set_stalled_tasks(0);
+
+    (value & 0xFFFF_0000 == STALLED_TASKS_MAGIC && value as u8 != 0).then_some(value as u8)
+}
+
+/// Starts the watchdog, and feeds it while all supervised tasks check in.
+#[embassy_executor::task]
+pub async fn watchdog_task(resources: WatchdogResources) {
+    let mut watchdog = IndependentWatchdog::new(resources.iwdg, WATCHDOG_TIMEOUT.as_micros() as u32);
+    let mut ticker = Ticker::every(SUPERVISION_INTERVAL);
+
+    // The tasks start with a full timeout.
+    for task in Task::ALL {
+        check_in(task);
+    }
+
+    watchdog.unleash();
+
+    loop {
+        ticker.next().await;
+
+        let stalled = stalled_tasks();
+        if stalled == 0 {
+            watchdog.pet();
+            continue;
+        }
+
+        for task in Task::ALL.into_iter().filter(|task| stalled & task.mask() != 0) {
+            log!(error, "Task stalled: {}", task_name(task));
+        }
+
+        // The supervisor stops feeding the watchdog for good.
+        set_stalled_tasks(stalled);
+        core::future::pending::<()>().await;
+    }
+}
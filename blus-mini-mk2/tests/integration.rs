@@ -0,0 +1,198 @@
+//! On-target integration tests, which run on a device in a test rig (`cargo test --test integration`, with a probe).
+//!
+//! The tests bring up the SAIs without amplifiers or Raspberry Pi attached, and overwrite the stored settings of the
+//! device.
+#![no_std]
+#![no_main]
+
+use {defmt_rtt as _, panic_probe as _};
+
+#[defmt_test::tests]
+mod tests {
+    use audio::AudioSource;
+    use audio_pipeline::source::select_source;
+    use blus_mini_mk2::audio_routing::{self, Sai4Resources, SetupError};
+    use blus_mini_mk2::control::CONTROL;
+    use blus_mini_mk2::latency::{self, LatencyMode};
+    use blus_mini_mk2::*;
+    use defmt::{assert, assert_eq, unwrap};
+    use embassy_futures::block_on;
+    use embassy_time::{with_timeout, Duration};
+
+    /// The time, within which SAI transfers complete.
+    const TRANSFER_TIMEOUT: Duration = Duration::from_millis(100);
+
+    struct State {
+        sai4_resources: Sai4Resources,
+        sai_amp_write_buffer: &'static mut [u32],
+        sai_rpi_read_buffer: &'static mut [u32],
+    }
+
+    /// Whether a buffer lies within a memory region, and starts at a cache line.
+    fn placed_in<T>(buffer: &[T], base: usize, size: usize) -> bool {
+        let address = buffer.as_ptr() as usize;
+        (base..base + size).contains(&address) && address % cache::LINE_SIZE == 0
+    }
+
+    #[init]
+    fn init() -> State {
+        // SAFETY: Runs first, before any code or data in the TCMs is used.
+        unsafe { tcm::init() };
+
+        let p = embassy_stm32::init(peripheral_config());
+        let mut core_peri = unwrap!(cortex_m::Peripherals::take());
+        cache::init(&mut core_peri.MPU, &mut core_peri.SCB, &mut core_peri.CPUID);
+        storage::init(p.FLASH);
+
+        State {
+            sai4_resources: Sai4Resources {
+                sai: p.SAI4,
+
+                mclk_a: p.PE0,
+                sck_a: p.PD13,
+                sd_a: p.PC1,
+                fs_a: p.PD12,
+                dma_a: p.BDMA_CH0,
+
+                sck_b: p.PE12,
+                sd_b: p.PE11,
+                fs_b: p.PE13,
+                dma_b: p.BDMA_CH1,
+            },
+            sai_amp_write_buffer: dma_buffers::sai_amp_write(),
+            sai_rpi_read_buffer: dma_buffers::sai_rpi_read(),
+        }
+    }
+
+    #[test]
+    fn places_dma_buffers(state: &mut State) {
+        // SRAM4 for the BDMA of SAI4, SRAM1 for the DMA of S/PDIF, and the AXI SRAM for the LED frame buffer.
+        assert!(placed_in(state.sai_amp_write_buffer, 0x3800_0000, 16 * 1024));
+        assert!(placed_in(state.sai_rpi_read_buffer, 0x3800_0000, 16 * 1024));
+        assert_eq!(state.sai_amp_write_buffer.len(), audio_routing::SAI_AMP_SAMPLE_COUNT);
+        assert_eq!(state.sai_rpi_read_buffer.len(), DEFAULT_SAMPLE_COUNT);
+
+        let spdifrx = dma_buffers::spdifrx();
+        assert!(placed_in(spdifrx, 0x3000_0000, 16 * 1024));
+        assert!(spdifrx.iter().all(|sample| *sample == 0));
+
+        let rgb_led_frame = dma_buffers::rgb_led_frame();
+        assert!(placed_in(rgb_led_frame, 0x2400_0000, 320 * 1024));
+        assert_eq!(rgb_led_frame.len(), rgb_led::FRAME_BUFFER_SIZE);
+        assert!(rgb_led_frame.iter().all(|byte| *byte == 0));
+    }
+
+    #[test]
+    fn rejects_unsupported_sample_rate(state: &mut State) {
+        let result = audio_routing::new_sai_amp_rpi(
+            &mut state.sai4_resources,
+            state.sai_amp_write_buffer,
+            state.sai_rpi_read_buffer,
+            44_100,
+            AudioSource::Usb,
+            LatencyMode::Normal,
+        );
+
+        assert!(matches!(result, Err(SetupError::UnsupportedSampleRate(44_100))));
+    }
+
+    #[test]
+    fn brings_up_sais(state: &mut State) {
+        let (mut sai_amp, mut sai_rpi) = unwrap!(audio_routing::new_sai_amp_rpi(
+            &mut state.sai4_resources,
+            state.sai_amp_write_buffer,
+            state.sai_rpi_read_buffer,
+            SAMPLE_RATE_HZ,
+            AudioSource::None,
+            LatencyMode::Normal,
+        ));
+        unwrap!(sai_rpi.start());
+
+        let mut samples = [0u32; DEFAULT_SAMPLE_COUNT];
+        assert!(matches!(
+            block_on(with_timeout(TRANSFER_TIMEOUT, sai_rpi.read(&mut samples))),
+            Ok(Ok(_))
+        ));
+
+        let silence = [0u32; DEFAULT_SAMPLE_COUNT / INPUT_CHANNEL_COUNT * OUTPUT_CHANNEL_COUNT];
+        assert!(matches!(
+            block_on(with_timeout(TRANSFER_TIMEOUT, sai_amp.write(&silence))),
+            Ok(Ok(_))
+        ));
+    }
+
+    #[test]
+    fn brings_up_sais_with_low_latency(state: &mut State) {
+        let (_sai_amp, mut sai_rpi) = unwrap!(audio_routing::new_sai_amp_rpi(
+            &mut state.sai4_resources,
+            state.sai_amp_write_buffer,
+            state.sai_rpi_read_buffer,
+            SAMPLE_RATE_HZ,
+            AudioSource::Rpi,
+            LatencyMode::Low,
+        ));
+        unwrap!(sai_rpi.start());
+
+        let mut samples = [0u32; latency::LOW_FRAME_COUNT * INPUT_CHANNEL_COUNT];
+        for _ in 0..4 {
+            assert!(matches!(
+                block_on(with_timeout(TRANSFER_TIMEOUT, sai_rpi.read(&mut samples))),
+                Ok(Ok(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn persists_settings() {
+        CONTROL.set_attenuation(40);
+        unwrap!(settings_store::save());
+
+        CONTROL.set_attenuation(0);
+        unwrap!(settings_store::restore());
+        assert_eq!(CONTROL.attenuation(), 40);
+
+        unwrap!(settings_store::erase());
+        assert_eq!(settings_store::restore(), Err(settings_store::Error::NotFound));
+    }
+
+    #[test]
+    fn arbitrates_sources() {
+        let allowed = |source| CONTROL.source_allowed(source);
+        CONTROL.set_source_selection(AudioSource::None);
+
+        // The first block selects its source, which stays against other sources, until its blocks end.
+        assert_eq!(
+            select_source(AudioSource::None, Some(AudioSource::Usb), allowed),
+            AudioSource::Usb
+        );
+        assert_eq!(
+            select_source(AudioSource::Usb, Some(AudioSource::Spdif), allowed),
+            AudioSource::Usb
+        );
+        assert_eq!(select_source(AudioSource::Usb, None, allowed), AudioSource::None);
+
+        // A selected source releases others, and only it may play.
+        CONTROL.set_source_selection(AudioSource::Spdif);
+        assert_eq!(
+            select_source(AudioSource::Usb, Some(AudioSource::Usb), allowed),
+            AudioSource::None
+        );
+        assert_eq!(
+            select_source(AudioSource::None, Some(AudioSource::Usb), allowed),
+            AudioSource::None
+        );
+        assert_eq!(
+            select_source(AudioSource::None, Some(AudioSource::Spdif), allowed),
+            AudioSource::Spdif
+        );
+
+        // No source plays in standby.
+        CONTROL.set_source_selection(AudioSource::None);
+        CONTROL.set_standby(true);
+        assert_eq!(
+            select_source(AudioSource::Spdif, Some(AudioSource::Spdif), allowed),
+            AudioSource::None
+        );
+        CONTROL.set_standby(false);
+    }
+}
@@ -0,0 +1,44 @@
+//! Fuzzing of the control frames that the device decodes: HID requests (see [`protocol::hid`]), with the parameters
+//! and values they address, and bulk transfer messages (see [`protocol::bulk`]).
+//!
+//! ```text
+//! cargo +nightly fuzz run control_frames
+//! ```
+//!
+//! Decoding must never panic. Requests that decode must encode to a report, which decodes to the same request.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use protocol::bulk::{self, Begin};
+use protocol::hid::{self, Request};
+use protocol::parameter::{Parameter, Value};
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok((sequence, request)) = Request::decode(data) {
+        assert_eq!(Request::decode(&request.encode(sequence)), Ok((sequence, request)));
+
+        let (id, value) = match request {
+            Request::Get { id } => (id, 0),
+            Request::Set { id, value } => (id, value),
+            _ => return,
+        };
+
+        if let Some(parameter) = Parameter::from_id(id) {
+            assert_eq!(parameter.id(), id);
+
+            if let Some(value) = Value::from_raw(value, parameter.value_type()) {
+                _ = parameter.descriptor().accepts(value);
+            }
+        }
+    }
+
+    if let Ok(begin) = Begin::decode(data) {
+        assert_eq!(Begin::decode(&begin.encode()), Ok(begin));
+    }
+
+    _ = bulk::Response::decode(data);
+
+    if let Ok(report) = <&[u8; hid::REPORT_SIZE]>::try_from(data) {
+        hid::Response::entries(report).for_each(drop);
+    }
+});
@@ -0,0 +1,44 @@
+//! Fuzzing of the settings decoder (see [`protocol::settings`]), for settings that the device reads from its flash or
+//! receives over bulk transfers.
+//!
+//! ```text
+//! cargo +nightly fuzz run settings
+//! ```
+//!
+//! Random data rarely has a valid header and CRC, so the data is decoded twice: as is, and as the records of a
+//! document with a valid header, whose minor version is the first byte. Decoding must never panic, and decoded
+//! settings must always encode, to a document that decodes again.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use protocol::crc::crc32;
+use protocol::settings::{Settings, MAGIC, MAJOR_VERSION, MAX_ENCODED_SIZE};
+
+fuzz_target!(|data: &[u8]| {
+    _ = Settings::new().decode(data);
+
+    let Some((&minor_version, records)) = data.split_first() else {
+        return;
+    };
+    let Ok(length) = u16::try_from(records.len()) else {
+        return;
+    };
+
+    let mut document = MAGIC.to_vec();
+    document.extend_from_slice(&[MAJOR_VERSION, minor_version]);
+    document.extend_from_slice(&length.to_le_bytes());
+    document.extend_from_slice(&crc32(records).to_le_bytes());
+    document.extend_from_slice(records);
+
+    let mut settings = Settings::new();
+    if settings.decode(&document).is_ok() {
+        let mut buffer = [0u8; MAX_ENCODED_SIZE];
+        let length = settings
+            .encode(&mut buffer)
+            .expect("decoded settings fit the encoded size");
+
+        Settings::new()
+            .decode(&buffer[..length])
+            .expect("encoded settings decode");
+    }
+});
@@ -0,0 +1,170 @@
+//! Backups of the complete persisted configuration, as a blob that host tools keep without interpreting it.
+//!
+//! Users back up a device before firmware updates, and clone the configuration to other units, by reading a backup
+//! from one device, and writing it to another (see [`crate::bulk::Target::Backup`]). Calibration data and the event
+//! log describe a single unit, and are not part of backups.
+//!
+//! Layout:
+//! - Header ([`HEADER_SIZE`] byte): magic `BLBK`, version (1 byte), three reserved bytes, the length of the sections
+//!   (`u32`), and the CRC-32 of the sections (`u32`, see [`crate::crc`]).
+//! - Sections, each with a [`Section`] tag (1 byte), a reserved byte, the data length (`u16`), and the data.
+//!
+//! Multi-byte fields are little-endian. Sections of unknown tags are skipped, such that backups of newer firmware
+//! restore what older firmware knows.
+use crate::crc::crc32;
+
+/// Identifies a backup.
+pub const MAGIC: [u8; 4] = *b"BLBK";
+
+/// The version of the format.
+pub const VERSION: u8 = 1;
+
+/// The size of the backup header.
+pub const HEADER_SIZE: usize = 16;
+
+/// The size of a section header.
+pub const SECTION_HEADER_SIZE: usize = 4;
+
+/// The content of a section.
+#[repr(u8)]
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Section {
+    /// The device settings, in the format of [`crate::settings`].
+    Settings = 1,
+    /// The bank of presets, in the storage format of the firmware.
+    Presets = 2,
+    /// The startup script, as text.
+    StartupScript = 3,
+}
+
+impl TryFrom<u8> for Section {
+    type Error = u8;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            1 => Ok(Section::Settings),
+            2 => Ok(Section::Presets),
+            3 => Ok(Section::StartupScript),
+            _ => Err(value),
+        }
+    }
+}
+
+/// Errors of encoding and decoding backups.
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// The backup does not fit into the buffer.
+    BufferTooSmall,
+    /// The data is shorter than its header tells.
+    TooShort,
+    /// The data does not start with the magic.
+    InvalidMagic,
+    /// The backup has another version.
+    IncompatibleVersion,
+    /// The checksum does not match.
+    CrcMismatch,
+    /// A section is longer than the remaining data, or than a section can be.
+    InvalidSection,
+}
+
+/// Builds a backup in a buffer.
+pub struct Writer<'a> {
+    buffer: &'a mut [u8],
+    length: usize,
+}
+
+impl<'a> Writer<'a> {
+    /// Start a backup in a buffer.
+    pub fn new(buffer: &'a mut [u8]) -> Result<Self, Error> {
+        if buffer.len() < HEADER_SIZE {
+            return Err(Error::BufferTooSmall);
+        }
+
+        Ok(Writer {
+            buffer,
+            length: HEADER_SIZE,
+        })
+    }
+
+    /// Append a section, whose data is written by a function into the given space. The function returns the length
+    /// of the data.
+    pub fn section<E>(&mut self, section: Section, write: impl FnOnce(&mut [u8]) -> Result<usize, E>) -> Result<(), E>
+    where
+        E: From<Error>,
+    {
+        let start = self.length + SECTION_HEADER_SIZE;
+        let space = self.buffer.get_mut(start..).ok_or(Error::BufferTooSmall)?;
+        let space_length = space.len().min(u16::MAX as usize);
+
+        let length = write(&mut space[..space_length])?;
+
+        if length > space_length {
+            return Err(Error::BufferTooSmall.into());
+        }
+
+        self.buffer[self.length] = section as u8;
+        self.buffer[self.length + 1] = 0;
+        self.buffer[self.length + 2..start].copy_from_slice(&(length as u16).to_le_bytes());
+
+        self.length = start + length;
+        Ok(())
+    }
+
+    /// Write the header, and return the length of the backup.
+    pub fn finish(self) -> usize {
+        let sections = &self.buffer[HEADER_SIZE..self.length];
+        let crc = crc32(sections);
+        let sections_length = sections.len() as u32;
+
+        let header = &mut self.buffer[..HEADER_SIZE];
+        header.fill(0);
+        header[0..4].copy_from_slice(&MAGIC);
+        header[4] = VERSION;
+        header[8..12].copy_from_slice(&sections_length.to_le_bytes());
+        header[12..16].copy_from_slice(&crc.to_le_bytes());
+
+        self.length
+    }
+}
+
+/// Check a backup, and iterate over its sections, as tag and data. Sections of unknown tags are included.
+pub fn sections(backup: &[u8]) -> Result<impl Iterator<Item = (u8, &[u8])>, Error> {
+    let header = backup.get(..HEADER_SIZE).ok_or(Error::TooShort)?;
+
+    if header[0..4] != MAGIC {
+        return Err(Error::InvalidMagic);
+    }
+
+    if header[4] != VERSION {
+        return Err(Error::IncompatibleVersion);
+    }
+
+    let length = u32::from_le_bytes([header[8], header[9], header[10], header[11]]) as usize;
+    let crc = u32::from_le_bytes([header[12], header[13], header[14], header[15]]);
+    let mut sections = backup.get(HEADER_SIZE..HEADER_SIZE + length).ok_or(Error::TooShort)?;
+
+    if crc32(sections) != crc {
+        return Err(Error::CrcMismatch);
+    }
+
+    // Check the section lengths, such that iterating cannot fail.
+    let mut remaining = sections;
+    while !remaining.is_empty() {
+        let header = remaining.get(..SECTION_HEADER_SIZE).ok_or(Error::InvalidSection)?;
+        let length = u16::from_le_bytes([header[2], header[3]]) as usize;
+        remaining = remaining
+            .get(SECTION_HEADER_SIZE + length..)
+            .ok_or(Error::InvalidSection)?;
+    }
+
+    Ok(core::iter::from_fn(move || {
+        let tag = *sections.first()?;
+        let length = u16::from_le_bytes([sections[2], sections[3]]) as usize;
+        let (data, rest) = sections[SECTION_HEADER_SIZE..].split_at(length);
+
+        sections = rest;
+        Some((tag, data))
+    }))
+}
@@ -0,0 +1,215 @@
+//! A transfer protocol on a pair of vendor-specific bulk endpoints, for data that exceeds the size of HID reports
+//! (e.g. FIR coefficients, or complete signal processing configurations).
+//!
+//! A transfer consists of three steps, each of which is answered by the device with a [`Response`]:
+//! 1. The host sends a [`Command::Begin`] message, which announces the [`Target`], the payload length,
+//!    and the CRC-32 of the payload (see [`crate::crc`]).
+//! 2. The host sends the payload as raw data, split into packets of any size. After the last byte, the device
+//!    verifies the CRC, and responds with [`Command::Data`].
+//! 3. The host sends [`Command::Commit`], and the device applies the payload.
+//!
+//! A transfer is discarded by [`Command::Abort`] (outside of the payload), or after a second of inactivity.
+//!
+//! Readable targets are read with a [`Command::Read`] message, which has the layout of a begin message, without
+//! length and CRC. The device responds with the length and CRC-32 of the payload in the [`Response`], and sends the
+//! payload right after it, in packets of the maximum size. A shorter packet (possibly empty) ends the payload.
+//!
+//! Multi-byte fields are little-endian.
+//!
+//! Payload formats:
+//! - [`Target::FirCoefficients`]: Taps of a single channel, as `f32` values.
+//! - [`Target::Parameters`]: A list of parameter identifiers (`u16`) and raw values (`u32`), as in [`crate::hid`].
+//!   All parameters are applied at once, or not at all.
+//! - [`Target::Settings`]: Complete device settings, in the format of [`crate::settings`].
+//! - [`Target::Backup`]: The complete persisted configuration, in the format of [`crate::backup`]. Readable, and
+//!   writing it replaces and stores the configuration.
+//! - [`Target::SlotB`]: Complete device settings as for [`Target::Settings`], which are staged in configuration slot
+//!   B instead of being applied (see [`crate::hid::SlotAction`]).
+//! - [`Target::ThermalLog`]: The thermal log, as records in the format of [`crate::thermal_log`]: the summary of
+//!   the whole log, followed by the newest records, oldest first, as many as fit. Only readable.
+
+/// The size of a begin message.
+pub const BEGIN_SIZE: usize = 12;
+
+/// The size of a response.
+pub const RESPONSE_SIZE: usize = 12;
+
+/// The size of a parameter entry in a [`Target::Parameters`] payload.
+pub const PARAMETER_ENTRY_SIZE: usize = 6;
+
+/// A transfer command.
+#[repr(u8)]
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Command {
+    /// Announce a transfer.
+    Begin = 0x01,
+    /// Apply the received payload.
+    Commit = 0x02,
+    /// Discard the current transfer.
+    Abort = 0x03,
+    /// Sent by the device, after the payload was received.
+    Data = 0x04,
+    /// Read the payload of a readable target.
+    Read = 0x05,
+}
+
+impl TryFrom<u8> for Command {
+    type Error = u8;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0x01 => Ok(Command::Begin),
+            0x02 => Ok(Command::Commit),
+            0x03 => Ok(Command::Abort),
+            0x04 => Ok(Command::Data),
+            0x05 => Ok(Command::Read),
+            _ => Err(value),
+        }
+    }
+}
+
+/// The destination of a transfer.
+#[repr(u8)]
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Target {
+    /// FIR coefficients for one output channel.
+    FirCoefficients = 0x01,
+    /// A set of parameters.
+    Parameters = 0x02,
+    /// The device settings.
+    Settings = 0x03,
+    /// A backup of the persisted configuration.
+    Backup = 0x04,
+    /// The experimental configuration slot.
+    SlotB = 0x05,
+    /// The thermal log.
+    ThermalLog = 0x06,
+}
+
+impl TryFrom<u8> for Target {
+    type Error = u8;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0x01 => Ok(Target::FirCoefficients),
+            0x02 => Ok(Target::Parameters),
+            0x03 => Ok(Target::Settings),
+            0x04 => Ok(Target::Backup),
+            0x05 => Ok(Target::SlotB),
+            0x06 => Ok(Target::ThermalLog),
+            _ => Err(value),
+        }
+    }
+}
+
+/// The result of a transfer step.
+#[repr(u8)]
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Status {
+    /// The step succeeded.
+    Ok = 0x00,
+    /// The command is not known, or not expected in the current state.
+    UnexpectedCommand = 0x01,
+    /// The target or channel is not known, or the target is not readable.
+    InvalidTarget = 0x02,
+    /// The payload does not fit into the device's buffer, or the target.
+    TooLarge = 0x03,
+    /// The checksum of the received payload does not match.
+    CrcMismatch = 0x04,
+    /// The payload is malformed, or results in an invalid configuration.
+    InvalidData = 0x05,
+    /// The device failed to read or store the payload, e.g. by a storage error.
+    Failed = 0x06,
+}
+
+/// The announcement of a transfer.
+///
+/// Layout: command (1 byte), target (1 byte), channel (1 byte), reserved (1 byte), length (4 byte), CRC (4 byte).
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Begin {
+    /// The destination of the payload.
+    pub target: Target,
+    /// The output channel, for channel-specific targets.
+    pub channel: u8,
+    /// The payload length in byte.
+    pub length: u32,
+    /// The CRC-32 of the payload.
+    pub crc: u32,
+}
+
+impl Begin {
+    /// Decode a begin message, or a read message.
+    pub fn decode(message: &[u8]) -> Result<Self, Status> {
+        if message.len() < BEGIN_SIZE || !matches!(Command::try_from(message[0]), Ok(Command::Begin | Command::Read)) {
+            return Err(Status::UnexpectedCommand);
+        }
+
+        Ok(Begin {
+            target: Target::try_from(message[1]).map_err(|_| Status::InvalidTarget)?,
+            channel: message[2],
+            length: u32::from_le_bytes([message[4], message[5], message[6], message[7]]),
+            crc: u32::from_le_bytes([message[8], message[9], message[10], message[11]]),
+        })
+    }
+
+    /// Encode the begin message.
+    pub fn encode(&self) -> [u8; BEGIN_SIZE] {
+        let mut message = [0u8; BEGIN_SIZE];
+
+        message[0] = Command::Begin as u8;
+        message[1] = self.target as u8;
+        message[2] = self.channel;
+        message[4..8].copy_from_slice(&self.length.to_le_bytes());
+        message[8..12].copy_from_slice(&self.crc.to_le_bytes());
+
+        message
+    }
+}
+
+/// The answer to a transfer step.
+///
+/// Layout: command (1 byte), status (1 byte), reserved (2 byte), received length (4 byte), received CRC (4 byte).
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Response {
+    /// The command that is answered.
+    pub command: u8,
+    /// The result.
+    pub status: u8,
+    /// The number of payload bytes that were received so far, or the length of the payload that is read.
+    pub received_length: u32,
+    /// The CRC-32 of the received payload, or of the payload that is read.
+    pub received_crc: u32,
+}
+
+impl Response {
+    /// Decode a response.
+    pub fn decode(message: &[u8]) -> Option<Self> {
+        if message.len() < RESPONSE_SIZE {
+            return None;
+        }
+
+        Some(Response {
+            command: message[0],
+            status: message[1],
+            received_length: u32::from_le_bytes([message[4], message[5], message[6], message[7]]),
+            received_crc: u32::from_le_bytes([message[8], message[9], message[10], message[11]]),
+        })
+    }
+
+    /// Encode the response.
+    pub fn encode(&self) -> [u8; RESPONSE_SIZE] {
+        let mut message = [0u8; RESPONSE_SIZE];
+
+        message[0] = self.command;
+        message[1] = self.status;
+        message[4..8].copy_from_slice(&self.received_length.to_le_bytes());
+        message[8..12].copy_from_slice(&self.received_crc.to_le_bytes());
+
+        message
+    }
+}
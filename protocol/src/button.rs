@@ -0,0 +1,85 @@
+//! Presses of the buttons, and the device actions that they are mapped to.
+//!
+//! The first buttons are on the board, the others on an optional GPIO expander.
+
+/// The number of buttons on the board.
+pub const BOARD_BUTTON_COUNT: usize = 2;
+
+/// The number of buttons on the GPIO expander.
+pub const EXPANDER_BUTTON_COUNT: usize = 4;
+
+/// The number of buttons.
+pub const BUTTON_COUNT: usize = BOARD_BUTTON_COUNT + EXPANDER_BUTTON_COUNT;
+
+/// The number of press types.
+pub const PRESS_COUNT: usize = 3;
+
+/// The type of a button press.
+#[repr(u8)]
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Press {
+    /// A short press.
+    Short = 0,
+    /// A press that is held for a while.
+    Long = 1,
+    /// Two short presses in quick succession.
+    Double = 2,
+}
+
+impl Press {
+    /// All press types, in order of their identifiers.
+    pub const ALL: [Press; PRESS_COUNT] = [Press::Short, Press::Long, Press::Double];
+}
+
+/// A device action that is triggered by a button press.
+#[repr(u8)]
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ButtonAction {
+    /// No action.
+    None = 0,
+    /// Select the next source.
+    Source = 1,
+    /// Toggle the master mute.
+    Mute = 2,
+    /// Toggle standby.
+    Standby = 3,
+    /// Select the next preset.
+    NextPreset = 4,
+    /// Shut down in order (standby, with the state saved), such that the device can be switched off.
+    Shutdown = 5,
+}
+
+impl ButtonAction {
+    /// All actions, in order of their identifiers.
+    pub const ALL: [ButtonAction; 6] = [
+        ButtonAction::None,
+        ButtonAction::Source,
+        ButtonAction::Mute,
+        ButtonAction::Standby,
+        ButtonAction::NextPreset,
+        ButtonAction::Shutdown,
+    ];
+}
+
+impl TryFrom<u8> for ButtonAction {
+    type Error = u8;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        ButtonAction::ALL.get(value as usize).copied().ok_or(value)
+    }
+}
+
+/// The actions of all buttons, indexed by button and press type.
+pub type ButtonMap = [[ButtonAction; PRESS_COUNT]; BUTTON_COUNT];
+
+/// The mapping of buttons to actions, unless configured otherwise.
+pub const DEFAULT_BUTTON_MAP: ButtonMap = [
+    [ButtonAction::Source, ButtonAction::Standby, ButtonAction::NextPreset],
+    [ButtonAction::Mute, ButtonAction::None, ButtonAction::None],
+    [ButtonAction::None; PRESS_COUNT],
+    [ButtonAction::None; PRESS_COUNT],
+    [ButtonAction::None; PRESS_COUNT],
+    [ButtonAction::None; PRESS_COUNT],
+];
@@ -0,0 +1,187 @@
+//! Calibration data of a single device, which is measured and written during production test.
+//!
+//! Unlike the settings, calibration data describes the hardware, not the user's configuration. It is stored apart
+//! from the settings, and survives factory resets.
+//!
+//! Layout (see [`Calibration::encode`]):
+//! - Header (8 byte): magic `CALB`, version (1 byte), and three reserved bytes.
+//! - The potentiometer taper: [`TAPER_POINT_COUNT`] values (`f32`).
+//! - The output trims in dB: [`CHANNEL_COUNT`] values (`f32`).
+//! - The temperature sensor offsets in °C: [`TEMPERATURE_SENSOR_COUNT`] values (`f32`).
+//! - The CRC-32 of all preceding bytes (`u32`, see [`crate::crc`]).
+//!
+//! Multi-byte fields are little-endian.
+use crate::crc::crc32;
+use crate::CHANNEL_COUNT;
+
+/// Identifies encoded calibration data.
+pub const MAGIC: [u8; 4] = *b"CALB";
+
+/// The version of the format.
+pub const VERSION: u8 = 1;
+
+/// The number of points of the potentiometer taper curve.
+pub const TAPER_POINT_COUNT: usize = 9;
+
+/// The number of temperature sensors: the microcontroller, followed by the amplifiers.
+pub const TEMPERATURE_SENSOR_COUNT: usize = 1 + CHANNEL_COUNT;
+
+/// The maximum magnitude of an output trim in dB.
+pub const MAX_TRIM_DB: f32 = 6.0;
+
+/// The maximum magnitude of a temperature sensor offset in °C.
+pub const MAX_TEMPERATURE_OFFSET: f32 = 20.0;
+
+const HEADER_SIZE: usize = 8;
+const VALUE_COUNT: usize = TAPER_POINT_COUNT + CHANNEL_COUNT + TEMPERATURE_SENSOR_COUNT;
+
+/// The size of encoded calibration data.
+pub const ENCODED_SIZE: usize = HEADER_SIZE + VALUE_COUNT * size_of::<f32>() + size_of::<u32>();
+
+/// Errors of decoding and validating calibration data.
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// The data is shorter than [`ENCODED_SIZE`].
+    TooShort,
+    /// The data does not start with the magic.
+    InvalidMagic,
+    /// The data has another version.
+    IncompatibleVersion,
+    /// The checksum does not match.
+    CrcMismatch,
+    /// The taper is not rising from 0 to 1.
+    InvalidTaper,
+    /// An output trim exceeds [`MAX_TRIM_DB`].
+    InvalidTrim,
+    /// A temperature sensor offset exceeds [`MAX_TEMPERATURE_OFFSET`].
+    InvalidTemperatureOffset,
+}
+
+/// The calibration data.
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Calibration {
+    /// The taper of the volume potentiometer: calibrated positions (0 to 1) at equally spaced measured positions,
+    /// from fully counter-clockwise to fully clockwise. Positions in between are interpolated linearly.
+    pub taper: [f32; TAPER_POINT_COUNT],
+    /// The gain trim of every output channel in dB, which evens out differences between the amplifiers.
+    pub output_trim_db: [f32; CHANNEL_COUNT],
+    /// The offset of every temperature sensor in °C, which is added to its readings.
+    pub temperature_offsets: [f32; TEMPERATURE_SENSOR_COUNT],
+}
+
+impl Default for Calibration {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Calibration {
+    /// Calibration data that changes nothing: a linear taper, no trims, and no offsets.
+    pub const fn new() -> Self {
+        let mut taper = [0.0; TAPER_POINT_COUNT];
+        let mut index = 0;
+
+        while index < TAPER_POINT_COUNT {
+            taper[index] = index as f32 / (TAPER_POINT_COUNT - 1) as f32;
+            index += 1;
+        }
+
+        Calibration {
+            taper,
+            output_trim_db: [0.0; CHANNEL_COUNT],
+            temperature_offsets: [0.0; TEMPERATURE_SENSOR_COUNT],
+        }
+    }
+
+    /// Check that all values are within their limits.
+    pub fn validate(&self) -> Result<(), Error> {
+        let taper_rises = self.taper.windows(2).all(|pair| pair[0] <= pair[1]);
+
+        if !taper_rises || !self.taper.iter().all(|point| (0.0..=1.0).contains(point)) {
+            return Err(Error::InvalidTaper);
+        }
+
+        if !self
+            .output_trim_db
+            .iter()
+            .all(|trim| (-MAX_TRIM_DB..=MAX_TRIM_DB).contains(trim))
+        {
+            return Err(Error::InvalidTrim);
+        }
+
+        if !self
+            .temperature_offsets
+            .iter()
+            .all(|offset| (-MAX_TEMPERATURE_OFFSET..=MAX_TEMPERATURE_OFFSET).contains(offset))
+        {
+            return Err(Error::InvalidTemperatureOffset);
+        }
+
+        Ok(())
+    }
+
+    /// The calibrated position for a measured position of the potentiometer (both from 0 to 1).
+    pub fn apply_taper(&self, position: f32) -> f32 {
+        let scaled = position.clamp(0.0, 1.0) * (TAPER_POINT_COUNT - 1) as f32;
+        let index = (scaled as usize).min(TAPER_POINT_COUNT - 2);
+        let fraction = scaled - index as f32;
+
+        self.taper[index] + (self.taper[index + 1] - self.taper[index]) * fraction
+    }
+
+    /// Encode the calibration data.
+    pub fn encode(&self) -> [u8; ENCODED_SIZE] {
+        let mut data = [0u8; ENCODED_SIZE];
+
+        data[0..4].copy_from_slice(&MAGIC);
+        data[4] = VERSION;
+
+        let values = self
+            .taper
+            .iter()
+            .chain(&self.output_trim_db)
+            .chain(&self.temperature_offsets);
+
+        for (chunk, value) in data[HEADER_SIZE..].chunks_exact_mut(size_of::<f32>()).zip(values) {
+            chunk.copy_from_slice(&value.to_le_bytes());
+        }
+
+        let crc = crc32(&data[..ENCODED_SIZE - 4]);
+        data[ENCODED_SIZE - 4..].copy_from_slice(&crc.to_le_bytes());
+
+        data
+    }
+
+    /// Decode and validate calibration data.
+    pub fn decode(data: &[u8]) -> Result<Self, Error> {
+        let data = data.get(..ENCODED_SIZE).ok_or(Error::TooShort)?;
+
+        if data[0..4] != MAGIC {
+            return Err(Error::InvalidMagic);
+        }
+
+        if data[4] != VERSION {
+            return Err(Error::IncompatibleVersion);
+        }
+
+        if crc32(&data[..ENCODED_SIZE - 4]).to_le_bytes() != data[ENCODED_SIZE - 4..] {
+            return Err(Error::CrcMismatch);
+        }
+
+        let mut calibration = Calibration::new();
+        let values = calibration
+            .taper
+            .iter_mut()
+            .chain(&mut calibration.output_trim_db)
+            .chain(&mut calibration.temperature_offsets);
+
+        for (value, chunk) in values.zip(data[HEADER_SIZE..].chunks_exact(size_of::<f32>())) {
+            *value = f32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        }
+
+        calibration.validate()?;
+        Ok(calibration)
+    }
+}
@@ -0,0 +1,67 @@
+//! CRC-32 (IEEE 802.3), as used by zlib and most host libraries, for verifying transferred data.
+
+/// The reflected CRC-32 polynomial.
+const POLYNOMIAL: u32 = 0xEDB8_8320;
+
+/// Lookup table for processing one byte per step.
+const TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut index = 0;
+
+    while index < 256 {
+        let mut value = index as u32;
+        let mut bit = 0;
+
+        while bit < 8 {
+            value = if value & 1 != 0 {
+                (value >> 1) ^ POLYNOMIAL
+            } else {
+                value >> 1
+            };
+            bit += 1;
+        }
+
+        table[index] = value;
+        index += 1;
+    }
+
+    table
+};
+
+/// An incremental CRC-32 calculation.
+#[derive(Clone, Copy, Debug)]
+pub struct Crc32 {
+    state: u32,
+}
+
+impl Default for Crc32 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Crc32 {
+    /// Start a new calculation.
+    pub const fn new() -> Self {
+        Crc32 { state: 0xFFFF_FFFF }
+    }
+
+    /// Add data to the calculation.
+    pub fn update(&mut self, data: &[u8]) {
+        for byte in data {
+            self.state = TABLE[((self.state ^ *byte as u32) & 0xFF) as usize] ^ (self.state >> 8);
+        }
+    }
+
+    /// The checksum of all data so far.
+    pub fn finalize(&self) -> u32 {
+        !self.state
+    }
+}
+
+/// Calculate the checksum of a block of data.
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = Crc32::new();
+    crc.update(data);
+    crc.finalize()
+}
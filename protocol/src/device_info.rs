@@ -0,0 +1,84 @@
+//! Identification of the device and its firmware, as queried by host tools.
+//!
+//! Layout (see [`DeviceInfo::encode`]):
+//!
+//! | Offset | Size | Content                                                  |
+//! |--------|------|----------------------------------------------------------|
+//! | 0      | 3    | Firmware version (major, minor, patch)                   |
+//! | 3      | 1    | Hardware revision                                        |
+//! | 4      | 12   | Unique ID of the microcontroller                         |
+//! | 16     | 4    | Uptime in seconds (`u32`, little-endian)                 |
+//! | 20     | 8    | Abbreviated git commit hash of the firmware (ASCII)      |
+//! | 28     | 10   | Build date of the firmware, as `YYYY-MM-DD` (ASCII)      |
+
+/// The size of encoded device information.
+pub const DEVICE_INFO_SIZE: usize = 38;
+
+/// The size of the unique ID.
+pub const UNIQUE_ID_SIZE: usize = 12;
+
+/// The length of the abbreviated git commit hash.
+pub const GIT_HASH_LENGTH: usize = 8;
+
+/// The length of the build date.
+pub const BUILD_DATE_LENGTH: usize = 10;
+
+/// Identification of the device and its firmware.
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct DeviceInfo {
+    /// The firmware version, as major, minor, and patch version.
+    pub version: [u8; 3],
+    /// The hardware revision of the board.
+    pub hardware_revision: u8,
+    /// The unique ID of the microcontroller.
+    pub unique_id: [u8; UNIQUE_ID_SIZE],
+    /// The time since startup in seconds.
+    pub uptime_s: u32,
+    /// The abbreviated git commit hash of the firmware, in ASCII. Shorter text is padded with zeros.
+    pub git_hash: [u8; GIT_HASH_LENGTH],
+    /// The build date of the firmware, as `YYYY-MM-DD` in ASCII.
+    pub build_date: [u8; BUILD_DATE_LENGTH],
+}
+
+impl DeviceInfo {
+    /// Encode the device information.
+    pub fn encode(&self) -> [u8; DEVICE_INFO_SIZE] {
+        let mut data = [0u8; DEVICE_INFO_SIZE];
+
+        data[0..3].copy_from_slice(&self.version);
+        data[3] = self.hardware_revision;
+        data[4..16].copy_from_slice(&self.unique_id);
+        data[16..20].copy_from_slice(&self.uptime_s.to_le_bytes());
+        data[20..28].copy_from_slice(&self.git_hash);
+        data[28..38].copy_from_slice(&self.build_date);
+
+        data
+    }
+
+    /// Decode device information. Returns `None`, if the data is too short.
+    pub fn decode(data: &[u8]) -> Option<Self> {
+        let data: &[u8; DEVICE_INFO_SIZE] = data.get(..DEVICE_INFO_SIZE)?.try_into().ok()?;
+
+        Some(DeviceInfo {
+            version: [data[0], data[1], data[2]],
+            hardware_revision: data[3],
+            unique_id: data[4..16].try_into().ok()?,
+            uptime_s: u32::from_le_bytes([data[16], data[17], data[18], data[19]]),
+            git_hash: data[20..28].try_into().ok()?,
+            build_date: data[28..38].try_into().ok()?,
+        })
+    }
+
+    /// The git commit hash as text, or an empty string for invalid ASCII.
+    pub fn git_hash_str(&self) -> &str {
+        core::str::from_utf8(&self.git_hash)
+            .unwrap_or_default()
+            .trim_end_matches('\0')
+    }
+
+    /// The build date as text, or an empty string for invalid ASCII.
+    pub fn build_date_str(&self) -> &str {
+        core::str::from_utf8(&self.build_date).unwrap_or_default()
+    }
+}
@@ -0,0 +1,231 @@
+//! Entries of the event log, which records faults and other significant events for post-mortem debugging.
+//!
+//! Layout of an entry (see [`Entry::encode`]):
+//!
+//! | Offset | Size | Content                                                              |
+//! |--------|------|----------------------------------------------------------------------|
+//! | 0      | 1    | [`EventKind`]                                                        |
+//! | 1      | 1    | Argument, depending on the kind                                      |
+//! | 2      | 2    | Number of occurrences, which are recorded as one (`u16`)             |
+//! | 4      | 4    | Boot number, counting the startups since the log was cleared (`u32`) |
+//! | 8      | 8    | Time of the first occurrence since startup in milliseconds (`u64`)   |
+//! | 16     | 4    | CRC-32 of the preceding bytes (`u32`)                                |
+//!
+//! All values are little-endian.
+use crate::crc::crc32;
+
+/// The size of an encoded entry.
+pub const ENTRY_SIZE: usize = 20;
+
+/// The kind of an event.
+#[repr(u8)]
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum EventKind {
+    /// The firmware started. The argument is the [`ResetCause`].
+    Reset = 0,
+    /// The amplifier output ran out of samples. The argument is the active source.
+    Underrun = 1,
+    /// An amplifier reported a fault. The argument holds the index of the amplifier in the upper four bits,
+    /// and the [`AMPLIFIER_FAULTS`] in the lower four bits.
+    AmplifierFault = 2,
+    /// A source failed to deliver samples. The argument is the source.
+    SourceError = 3,
+    /// Stored data was found damaged (e.g. by a power loss while writing), and was not used. The argument is the
+    /// [`StoredData`].
+    StorageCorruption = 4,
+    /// The firmware reset repeatedly shortly after startup, and started with the default signal processing
+    /// configuration. The argument is the number of resets in a row.
+    BootLoop = 5,
+    /// The supply voltage dropped below its threshold, and the outputs were shut down until it recovered. The
+    /// argument is the measured voltage in steps of 100 mV.
+    Undervoltage = 6,
+    /// Supervised tasks stopped checking in, and the watchdog reset the firmware. Recorded after the reset. The
+    /// argument is the mask of the stalled tasks (bit 0: audio routing, bit 1: USB device, bit 2: USB control).
+    TaskStall = 7,
+    /// The supply of the microcontroller dropped, and a power loss was imminent (see the PVD). The argument is
+    /// unused.
+    PowerFail = 8,
+    /// A sustained DC offset at the output of the signal processing muted the output. The argument is the mask of
+    /// the output channels with the offset.
+    OutputDc = 9,
+    /// The device was shut down in order (e.g. by a power button). The argument is unused.
+    Shutdown = 10,
+    /// The firmware panicked, and reset. Recorded after the reset. The argument is unused.
+    Panic = 11,
+    /// A step of the startup did not complete in time. The argument is the step (0: clocks, 1: amplifiers, 2: USB,
+    /// 3: audio pipeline).
+    StartupFailure = 12,
+}
+
+impl EventKind {
+    /// All kinds, in order of their identifiers.
+    pub const ALL: [EventKind; 13] = [
+        EventKind::Reset,
+        EventKind::Underrun,
+        EventKind::AmplifierFault,
+        EventKind::SourceError,
+        EventKind::StorageCorruption,
+        EventKind::BootLoop,
+        EventKind::Undervoltage,
+        EventKind::TaskStall,
+        EventKind::PowerFail,
+        EventKind::OutputDc,
+        EventKind::Shutdown,
+        EventKind::Panic,
+        EventKind::StartupFailure,
+    ];
+}
+
+impl TryFrom<u8> for EventKind {
+    type Error = u8;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        EventKind::ALL.get(value as usize).copied().ok_or(value)
+    }
+}
+
+/// The cause of a reset, as argument of [`EventKind::Reset`].
+#[repr(u8)]
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ResetCause {
+    /// The supply was switched on.
+    PowerOn = 0,
+    /// The reset pin was pulled low.
+    Pin = 1,
+    /// The firmware requested the reset, e.g. for a reboot.
+    Software = 2,
+    /// The independent watchdog expired.
+    Watchdog = 3,
+    /// The window watchdog expired.
+    WindowWatchdog = 4,
+    /// The supply dropped below the brown-out threshold.
+    Brownout = 5,
+    /// A low-power mode was entered illegally.
+    LowPower = 6,
+    /// No cause was recorded.
+    Unknown = 7,
+}
+
+impl ResetCause {
+    /// All causes, in order of their identifiers.
+    pub const ALL: [ResetCause; 8] = [
+        ResetCause::PowerOn,
+        ResetCause::Pin,
+        ResetCause::Software,
+        ResetCause::Watchdog,
+        ResetCause::WindowWatchdog,
+        ResetCause::Brownout,
+        ResetCause::LowPower,
+        ResetCause::Unknown,
+    ];
+}
+
+impl TryFrom<u8> for ResetCause {
+    type Error = u8;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        ResetCause::ALL.get(value as usize).copied().ok_or(value)
+    }
+}
+
+/// The kind of stored data, as argument of [`EventKind::StorageCorruption`].
+#[repr(u8)]
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum StoredData {
+    /// The device settings.
+    Settings = 0,
+    /// The presets of the signal processing configuration.
+    Presets = 1,
+    /// The startup script.
+    StartupScript = 2,
+    /// The calibration data.
+    Calibration = 3,
+    /// The provisioning data.
+    Provisioning = 4,
+}
+
+impl StoredData {
+    /// All kinds, in order of their identifiers.
+    pub const ALL: [StoredData; 5] = [
+        StoredData::Settings,
+        StoredData::Presets,
+        StoredData::StartupScript,
+        StoredData::Calibration,
+        StoredData::Provisioning,
+    ];
+}
+
+impl TryFrom<u8> for StoredData {
+    type Error = u8;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        StoredData::ALL.get(value as usize).copied().ok_or(value)
+    }
+}
+
+/// The faults of an amplifier, as bits of the argument of [`EventKind::AmplifierFault`], with their names.
+pub const AMPLIFIER_FAULTS: [(u8, &str); 4] = [
+    (1 << 0, "over-temperature"),
+    (1 << 1, "over-current"),
+    (1 << 2, "clock-error"),
+    (1 << 3, "dc-detection"),
+];
+
+/// An event, as recorded in the log.
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Entry {
+    /// The kind of the event.
+    pub kind: EventKind,
+    /// The argument, depending on the kind.
+    pub argument: u8,
+    /// The number of occurrences, which are recorded as one.
+    pub count: u16,
+    /// The number of the startup, during which the event occurred.
+    pub boot: u32,
+    /// The time of the first occurrence since startup in milliseconds.
+    pub time_ms: u64,
+}
+
+impl Entry {
+    /// Whether another occurrence of an event is recorded by this entry.
+    pub fn matches(&self, kind: EventKind, argument: u8) -> bool {
+        self.kind == kind && self.argument == argument
+    }
+
+    /// Encode the entry.
+    pub fn encode(&self) -> [u8; ENTRY_SIZE] {
+        let mut data = [0u8; ENTRY_SIZE];
+
+        data[0] = self.kind as u8;
+        data[1] = self.argument;
+        data[2..4].copy_from_slice(&self.count.to_le_bytes());
+        data[4..8].copy_from_slice(&self.boot.to_le_bytes());
+        data[8..16].copy_from_slice(&self.time_ms.to_le_bytes());
+
+        let crc = crc32(&data[..16]);
+        data[16..20].copy_from_slice(&crc.to_le_bytes());
+
+        data
+    }
+
+    /// Decode an entry. Returns `None` for damaged entries, and entries of unknown kinds.
+    pub fn decode(data: &[u8]) -> Option<Self> {
+        let data: &[u8; ENTRY_SIZE] = data.get(..ENTRY_SIZE)?.try_into().ok()?;
+
+        if crc32(&data[..16]).to_le_bytes() != data[16..20] {
+            return None;
+        }
+
+        Some(Entry {
+            kind: EventKind::try_from(data[0]).ok()?,
+            argument: data[1],
+            count: u16::from_le_bytes([data[2], data[3]]),
+            boot: u32::from_le_bytes([data[4], data[5], data[6], data[7]]),
+            time_ms: u64::from_le_bytes(data[8..16].try_into().ok()?),
+        })
+    }
+}
@@ -0,0 +1,149 @@
+//! A handshake, by which host tools learn the protocol and format versions, and the features of the firmware.
+//!
+//! Host tools request the handshake first (HID [`Command::Handshake`], shell `handshake`, SCPI
+//! `SYSTem:HANDshake?`), and adapt to the firmware instead of breaking when it evolves. A new minor version only adds
+//! definitions (e.g. commands, parameters, settings records, feature bits), which hosts ignore if unknown. A new
+//! major version changes existing definitions; hosts must not use a protocol of an unknown major version. Unknown
+//! feature bits are ignored as well.
+//!
+//! Layout (see [`Handshake::encode`]):
+//!
+//! | Offset | Size | Content                                                        |
+//! |--------|------|----------------------------------------------------------------|
+//! | 0      | 2    | Protocol version (major, minor)                                |
+//! | 2      | 2    | Settings format version (major, minor), see [`crate::settings`] |
+//! | 4      | 1    | Version of the control register map                            |
+//! | 5      | 1    | Version of the JSON configuration document                     |
+//! | 6      | 2    | Number of parameters (`u16`, little-endian)                    |
+//! | 8      | 4    | Bitmap of [`Feature`]s (`u32`, little-endian)                  |
+//!
+//! The protocol version covers the HID and bulk protocols, as well as the command sets of the consoles.
+//!
+//! [`Command::Handshake`]: crate::hid::Command::Handshake
+
+/// The major protocol version. Changes with incompatible protocol changes.
+pub const MAJOR_VERSION: u8 = 1;
+
+/// The minor protocol version. Changes with compatible additions:
+/// - 0: Initial version, with handshake.
+/// - 1: Event log.
+/// - 2: Backups of the configuration.
+/// - 3: Configuration slots.
+pub const MINOR_VERSION: u8 = 3;
+
+/// The size of an encoded handshake.
+pub const HANDSHAKE_SIZE: usize = 12;
+
+/// An optional feature of the firmware, or of the hardware it runs on.
+#[repr(u8)]
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Feature {
+    /// Notifications of parameter changes.
+    Notifications = 0,
+    /// Reboots, also into the system bootloader.
+    Reboot = 1,
+    /// Transfers on the bulk endpoints (see [`crate::bulk`]).
+    BulkTransfer = 2,
+    /// Telemetry records on a console.
+    Telemetry = 3,
+    /// A startup command script.
+    StartupScript = 4,
+    /// A rotary encoder for the volume, instead of a potentiometer.
+    Encoder = 5,
+    /// A GPIO expander with more buttons and LEDs.
+    GpioExpander = 6,
+    /// A log of faults and other significant events (see [`crate::event_log`]).
+    EventLog = 7,
+    /// Backups of the persisted configuration (see [`crate::backup`]).
+    Backup = 8,
+    /// Two configuration slots, for auditioning an experimental configuration (see [`crate::hid::SlotAction`]).
+    ConfigSlots = 9,
+    /// A log of the temperatures over the operating time (see [`crate::thermal_log`]).
+    ThermalLog = 10,
+}
+
+impl Feature {
+    /// All features, in order of their bits.
+    pub const ALL: [Feature; 11] = [
+        Feature::Notifications,
+        Feature::Reboot,
+        Feature::BulkTransfer,
+        Feature::Telemetry,
+        Feature::StartupScript,
+        Feature::Encoder,
+        Feature::GpioExpander,
+        Feature::EventLog,
+        Feature::Backup,
+        Feature::ConfigSlots,
+        Feature::ThermalLog,
+    ];
+
+    /// The bit of the feature in the bitmap.
+    pub const fn mask(self) -> u32 {
+        1 << self as u8
+    }
+}
+
+/// Versions and features of the firmware.
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Handshake {
+    /// The protocol version, as major and minor version.
+    pub protocol_version: [u8; 2],
+    /// The settings format version, as major and minor version.
+    pub settings_version: [u8; 2],
+    /// The version of the control register map.
+    pub register_map_version: u8,
+    /// The version of the JSON configuration document.
+    pub config_version: u8,
+    /// The number of parameters.
+    pub parameter_count: u16,
+    /// The bitmap of available features.
+    pub features: u32,
+}
+
+impl Handshake {
+    /// Whether a feature is available.
+    pub fn supports(&self, feature: Feature) -> bool {
+        self.features & feature.mask() != 0
+    }
+
+    /// The available features, in order of their bits. Unknown features are skipped.
+    pub fn supported_features(&self) -> impl Iterator<Item = Feature> + '_ {
+        Feature::ALL.into_iter().filter(|feature| self.supports(*feature))
+    }
+
+    /// Whether a host of the current protocol version can talk to the firmware.
+    pub fn is_compatible(&self) -> bool {
+        self.protocol_version[0] == MAJOR_VERSION
+    }
+
+    /// Encode the handshake.
+    pub fn encode(&self) -> [u8; HANDSHAKE_SIZE] {
+        let mut data = [0u8; HANDSHAKE_SIZE];
+
+        data[0..2].copy_from_slice(&self.protocol_version);
+        data[2..4].copy_from_slice(&self.settings_version);
+        data[4] = self.register_map_version;
+        data[5] = self.config_version;
+        data[6..8].copy_from_slice(&self.parameter_count.to_le_bytes());
+        data[8..12].copy_from_slice(&self.features.to_le_bytes());
+
+        data
+    }
+
+    /// Decode a handshake. Returns `None`, if the data is too short.
+    pub fn decode(data: &[u8]) -> Option<Self> {
+        let data: &[u8; HANDSHAKE_SIZE] = data.get(..HANDSHAKE_SIZE)?.try_into().ok()?;
+
+        Some(Handshake {
+            protocol_version: [data[0], data[1]],
+            settings_version: [data[2], data[3]],
+            register_map_version: data[4],
+            config_version: data[5],
+            parameter_count: u16::from_le_bytes([data[6], data[7]]),
+            features: u32::from_le_bytes([data[8], data[9], data[10], data[11]]),
+        })
+    }
+}
@@ -0,0 +1,361 @@
+//! A parameter protocol on a vendor-defined HID interface, which works without custom drivers on all host platforms.
+//!
+//! The host sends output reports with requests, and the device answers every request with a single input report.
+//! Both use [`REPORT_SIZE`] byte, without report IDs. Multi-byte fields are little-endian.
+//!
+//! Request layout:
+//!
+//! | Offset | Size | Content                                                             |
+//! |--------|------|---------------------------------------------------------------------|
+//! | 0      | 1    | [`Command`]                                                         |
+//! | 1      | 1    | Sequence number, returned with the response                         |
+//! | 2      | 2    | Parameter identifier (get, set), or first parameter index (read all) |
+//! | 4      | 4    | Value (set only)                                                    |
+//!
+//! Response layout:
+//!
+//! | Offset | Size | Content                                                                  |
+//! |--------|------|--------------------------------------------------------------------------|
+//! | 0      | 1    | [`Command`] of the request                                                |
+//! | 1      | 1    | Sequence number of the request                                            |
+//! | 2      | 1    | [`Status`]                                                                |
+//! | 3      | 1    | Number of entries                                                         |
+//! | 4      | 2    | Index of the next parameter to read (read all), or [`END_OF_PARAMETERS`] |
+//! | 6      | 6·n  | Entries of parameter identifier (2 byte) and value (4 byte)               |
+//!
+//! Get and set requests return the (new) value of the parameter in a single entry. Read all requests return
+//! up to [`MAX_ENTRY_COUNT`] parameters; the host continues with the next index, until the end is reached.
+//!
+//! Device info requests return no entries. Instead, the encoded [`DeviceInfo`] follows the response header.
+//! Handshake requests do the same with the encoded [`Handshake`], and should be the first request of a host.
+//!
+//! After a subscribe request with a non-zero argument, the device additionally sends unsolicited
+//! [`Command::Notification`] reports with sequence number 0, whenever global parameters change (e.g. the volume by
+//! the potentiometer, or the active source). Their entries hold the new values. A subscribe request with argument 0
+//! ends notifications.
+//!
+//! Reboot requests are answered first, then the device resets shortly after. With argument 1, it starts the
+//! system bootloader for a firmware update, otherwise the firmware.
+//!
+//! Slot requests switch between the configuration slots with the [`SlotAction`] in the argument, or only query them
+//! with argument 0. Instead of entries, the response holds the active slot (0 for A, 1 for B), and whether a
+//! configuration is staged in slot B (0 or 1). Actions that are not possible in the current state (e.g. reverting
+//! without an audition) fail with [`Status::InvalidState`].
+//!
+//! [`DeviceInfo`]: crate::device_info::DeviceInfo
+//! [`Handshake`]: crate::handshake::Handshake
+use crate::device_info::DEVICE_INFO_SIZE;
+use crate::handshake::HANDSHAKE_SIZE;
+use crate::parameter::Parameter;
+
+/// The size of input and output reports.
+pub const REPORT_SIZE: usize = 64;
+
+/// The vendor-defined usage page, by which hosts identify the interface.
+pub const USAGE_PAGE: u16 = 0xFF00;
+
+/// The maximum number of parameter entries in a response.
+pub const MAX_ENTRY_COUNT: usize = (REPORT_SIZE - RESPONSE_HEADER_SIZE) / ENTRY_SIZE;
+
+/// Marks the end of the parameter list in read all responses.
+pub const END_OF_PARAMETERS: u16 = 0xFFFF;
+
+const RESPONSE_HEADER_SIZE: usize = 6;
+const ENTRY_SIZE: usize = 6;
+
+const _: () = assert!(RESPONSE_HEADER_SIZE + DEVICE_INFO_SIZE <= REPORT_SIZE);
+const _: () = assert!(RESPONSE_HEADER_SIZE + HANDSHAKE_SIZE <= REPORT_SIZE);
+
+/// The HID report descriptor: one vendor-defined input and output report of [`REPORT_SIZE`] byte.
+#[rustfmt::skip]
+pub const REPORT_DESCRIPTOR: &[u8] = &[
+    0x06, 0x00, 0xFF,       // Usage page (vendor-defined 0xFF00)
+    0x09, 0x01,             // Usage (0x01)
+    0xA1, 0x01,             // Collection (application)
+    0x15, 0x00,             //   Logical minimum (0)
+    0x26, 0xFF, 0x00,       //   Logical maximum (255)
+    0x75, 0x08,             //   Report size (8 bit)
+    0x95, REPORT_SIZE as u8, //   Report count
+    0x09, 0x02,             //   Usage (0x02)
+    0x81, 0x02,             //   Input (data, variable, absolute)
+    0x95, REPORT_SIZE as u8, //   Report count
+    0x09, 0x03,             //   Usage (0x03)
+    0x91, 0x02,             //   Output (data, variable, absolute)
+    0xC0,                   // End collection
+];
+
+/// A request command.
+#[repr(u8)]
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Command {
+    /// Read a single parameter.
+    GetParameter = 0x01,
+    /// Write a single parameter.
+    SetParameter = 0x02,
+    /// Read consecutive parameters from the list of all parameters.
+    ReadAll = 0x03,
+    /// Read the device information.
+    DeviceInfo = 0x04,
+    /// Enable or disable notifications of parameter changes.
+    Subscribe = 0x05,
+    /// Reboot the device, or enter the bootloader.
+    Reboot = 0x06,
+    /// Read the protocol versions and features.
+    Handshake = 0x07,
+    /// Switch between the configuration slots, or query them.
+    Slot = 0x08,
+    /// Sent by the device, when parameters changed.
+    Notification = 0x80,
+}
+
+impl TryFrom<u8> for Command {
+    type Error = u8;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0x01 => Ok(Command::GetParameter),
+            0x02 => Ok(Command::SetParameter),
+            0x03 => Ok(Command::ReadAll),
+            0x04 => Ok(Command::DeviceInfo),
+            0x05 => Ok(Command::Subscribe),
+            0x06 => Ok(Command::Reboot),
+            0x07 => Ok(Command::Handshake),
+            0x08 => Ok(Command::Slot),
+            0x80 => Ok(Command::Notification),
+            _ => Err(value),
+        }
+    }
+}
+
+/// The result of a request.
+#[repr(u8)]
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Status {
+    /// The request succeeded.
+    Ok = 0x00,
+    /// The command is not known.
+    UnknownCommand = 0x01,
+    /// The parameter identifier is not known, or refers to an unused stage.
+    UnknownParameter = 0x02,
+    /// The value is out of range, or results in an invalid configuration.
+    InvalidValue = 0x03,
+    /// The parameter cannot be written.
+    ReadOnly = 0x04,
+    /// The request is not possible in the current state.
+    InvalidState = 0x05,
+}
+
+/// A switch between the configuration slots: slot A holds the configuration in effect, slot B an experimental one.
+#[repr(u8)]
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SlotAction {
+    /// Apply the configuration of slot B, and keep slot A for reverting.
+    Audition = 1,
+    /// Apply the configuration of slot A again.
+    Revert = 2,
+    /// Keep the configuration of slot B in effect, which replaces slot A.
+    Commit = 3,
+    /// Revert, and empty slot B.
+    Discard = 4,
+}
+
+impl TryFrom<u8> for SlotAction {
+    type Error = u8;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            1 => Ok(SlotAction::Audition),
+            2 => Ok(SlotAction::Revert),
+            3 => Ok(SlotAction::Commit),
+            4 => Ok(SlotAction::Discard),
+            _ => Err(value),
+        }
+    }
+}
+
+/// A decoded request.
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Request {
+    /// Read a parameter.
+    Get { id: u16 },
+    /// Write a parameter with a raw value.
+    Set { id: u16, value: u32 },
+    /// Read parameters, starting from an index in the list of all parameters.
+    ReadAll { start_index: u16 },
+    /// Read the device information.
+    DeviceInfo,
+    /// Enable or disable notifications of parameter changes.
+    Subscribe { enabled: bool },
+    /// Reboot the device, into the bootloader or the firmware.
+    Reboot { bootloader: bool },
+    /// Read the protocol versions and features.
+    Handshake,
+    /// Switch between the configuration slots, or only query them.
+    Slot { action: Option<SlotAction> },
+}
+
+impl Request {
+    /// Decode a request report. Returns the sequence number and the request.
+    pub fn decode(report: &[u8]) -> Result<(u8, Self), Status> {
+        if report.len() < 8 {
+            return Err(Status::UnknownCommand);
+        }
+
+        let sequence = report[1];
+        let argument = u16::from_le_bytes([report[2], report[3]]);
+        let value = u32::from_le_bytes([report[4], report[5], report[6], report[7]]);
+
+        let request = match Command::try_from(report[0]).map_err(|_| Status::UnknownCommand)? {
+            Command::GetParameter => Request::Get { id: argument },
+            Command::SetParameter => Request::Set { id: argument, value },
+            Command::ReadAll => Request::ReadAll { start_index: argument },
+            Command::DeviceInfo => Request::DeviceInfo,
+            Command::Subscribe => Request::Subscribe { enabled: argument != 0 },
+            Command::Reboot => Request::Reboot {
+                bootloader: argument == 1,
+            },
+            Command::Handshake => Request::Handshake,
+            Command::Slot => Request::Slot {
+                action: match argument {
+                    0 => None,
+                    action => Some(
+                        u8::try_from(action)
+                            .ok()
+                            .and_then(|action| SlotAction::try_from(action).ok())
+                            .ok_or(Status::InvalidValue)?,
+                    ),
+                },
+            },
+            Command::Notification => return Err(Status::UnknownCommand),
+        };
+
+        Ok((sequence, request))
+    }
+
+    /// Encode the request into a report.
+    pub fn encode(&self, sequence: u8) -> [u8; REPORT_SIZE] {
+        let mut report = [0u8; REPORT_SIZE];
+
+        let (command, argument, value) = match *self {
+            Request::Get { id } => (Command::GetParameter, id, 0),
+            Request::Set { id, value } => (Command::SetParameter, id, value),
+            Request::ReadAll { start_index } => (Command::ReadAll, start_index, 0),
+            Request::DeviceInfo => (Command::DeviceInfo, 0, 0),
+            Request::Subscribe { enabled } => (Command::Subscribe, enabled as u16, 0),
+            Request::Reboot { bootloader } => (Command::Reboot, bootloader as u16, 0),
+            Request::Handshake => (Command::Handshake, 0, 0),
+            Request::Slot { action } => (Command::Slot, action.map_or(0, |action| action as u16), 0),
+        };
+
+        report[0] = command as u8;
+        report[1] = sequence;
+        report[2..4].copy_from_slice(&argument.to_le_bytes());
+        report[4..8].copy_from_slice(&value.to_le_bytes());
+
+        report
+    }
+
+    /// The command of the request.
+    pub fn command(&self) -> Command {
+        match self {
+            Request::Get { .. } => Command::GetParameter,
+            Request::Set { .. } => Command::SetParameter,
+            Request::ReadAll { .. } => Command::ReadAll,
+            Request::DeviceInfo => Command::DeviceInfo,
+            Request::Subscribe { .. } => Command::Subscribe,
+            Request::Reboot { .. } => Command::Reboot,
+            Request::Handshake => Command::Handshake,
+            Request::Slot { .. } => Command::Slot,
+        }
+    }
+}
+
+/// Assembles a response report.
+pub struct Response {
+    report: [u8; REPORT_SIZE],
+    entry_count: usize,
+}
+
+impl Response {
+    /// Create an empty response to a request.
+    pub fn new(command: u8, sequence: u8) -> Self {
+        let mut report = [0u8; REPORT_SIZE];
+
+        report[0] = command;
+        report[1] = sequence;
+        report[4..6].copy_from_slice(&END_OF_PARAMETERS.to_le_bytes());
+
+        Response { report, entry_count: 0 }
+    }
+
+    /// Set the status of the response.
+    pub fn set_status(&mut self, status: Status) {
+        self.report[2] = status as u8;
+    }
+
+    /// Set the index of the next parameter in a read all response.
+    pub fn set_next_index(&mut self, next_index: u16) {
+        self.report[4..6].copy_from_slice(&next_index.to_le_bytes());
+    }
+
+    /// Append a parameter entry. Returns `false`, if the response is full.
+    pub fn push(&mut self, parameter: Parameter, value: u32) -> bool {
+        if self.entry_count >= MAX_ENTRY_COUNT {
+            return false;
+        }
+
+        let offset = RESPONSE_HEADER_SIZE + self.entry_count * ENTRY_SIZE;
+        self.report[offset..offset + 2].copy_from_slice(&parameter.id().to_le_bytes());
+        self.report[offset + 2..offset + 6].copy_from_slice(&value.to_le_bytes());
+
+        self.entry_count += 1;
+        self.report[3] = self.entry_count as u8;
+
+        true
+    }
+
+    /// Set the data that follows the header, instead of parameter entries. Excess data is truncated.
+    pub fn set_data(&mut self, data: &[u8]) {
+        let data = &data[..data.len().min(REPORT_SIZE - RESPONSE_HEADER_SIZE)];
+        self.report[RESPONSE_HEADER_SIZE..RESPONSE_HEADER_SIZE + data.len()].copy_from_slice(data);
+    }
+
+    /// The encoded report.
+    pub fn report(&self) -> &[u8; REPORT_SIZE] {
+        &self.report
+    }
+
+    /// The status of a response report.
+    pub fn status(report: &[u8; REPORT_SIZE]) -> u8 {
+        report[2]
+    }
+
+    /// The index of the next parameter in a read all response report.
+    pub fn next_index(report: &[u8; REPORT_SIZE]) -> u16 {
+        u16::from_le_bytes([report[4], report[5]])
+    }
+
+    /// The data that follows the header of a response report (e.g. device info, or a handshake).
+    pub fn data(report: &[u8; REPORT_SIZE]) -> &[u8] {
+        &report[RESPONSE_HEADER_SIZE..]
+    }
+
+    /// The entries of a response report, as pairs of parameter identifier and raw value.
+    pub fn entries(report: &[u8; REPORT_SIZE]) -> impl Iterator<Item = (u16, u32)> + '_ {
+        let entry_count = (report[3] as usize).min(MAX_ENTRY_COUNT);
+
+        report[RESPONSE_HEADER_SIZE..]
+            .chunks_exact(ENTRY_SIZE)
+            .take(entry_count)
+            .map(|entry| {
+                (
+                    u16::from_le_bytes([entry[0], entry[1]]),
+                    u32::from_le_bytes([entry[2], entry[3], entry[4], entry[5]]),
+                )
+            })
+    }
+}
@@ -0,0 +1,81 @@
+//! Codes of infrared remote controls, and the device actions that they are mapped to.
+
+/// The number of actions that can be mapped to remote codes.
+pub const ACTION_COUNT: usize = 6;
+
+/// The protocol of a remote code.
+#[repr(u8)]
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum IrProtocol {
+    /// The NEC protocol, with 8 or 16 bit address and 8 bit command.
+    Nec = 0x01,
+    /// The (extended) Philips RC5 protocol, with 5 bit address and 7 bit command.
+    Rc5 = 0x02,
+}
+
+impl TryFrom<u8> for IrProtocol {
+    type Error = u8;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0x01 => Ok(IrProtocol::Nec),
+            0x02 => Ok(IrProtocol::Rc5),
+            _ => Err(value),
+        }
+    }
+}
+
+/// A code that is sent by a remote control button.
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct IrCode {
+    /// The protocol of the code.
+    pub protocol: IrProtocol,
+    /// The device address.
+    pub address: u16,
+    /// The command.
+    pub command: u8,
+}
+
+/// A device action that is triggered by a remote code.
+#[repr(u8)]
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum IrAction {
+    /// Increase the master volume. Repeats while the button is held.
+    VolumeUp = 0,
+    /// Decrease the master volume. Repeats while the button is held.
+    VolumeDown = 1,
+    /// Toggle the master mute.
+    Mute = 2,
+    /// Select the next source.
+    Source = 3,
+    /// Toggle standby.
+    Standby = 4,
+    /// Select the next preset.
+    NextPreset = 5,
+}
+
+impl IrAction {
+    /// All actions, in order of their identifiers.
+    pub const ALL: [IrAction; ACTION_COUNT] = [
+        IrAction::VolumeUp,
+        IrAction::VolumeDown,
+        IrAction::Mute,
+        IrAction::Source,
+        IrAction::Standby,
+        IrAction::NextPreset,
+    ];
+}
+
+impl TryFrom<u8> for IrAction {
+    type Error = u8;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        IrAction::ALL.get(value as usize).copied().ok_or(value)
+    }
+}
+
+/// The remote codes of all actions, indexed by action.
+pub type IrCodes = [Option<IrCode>; ACTION_COUNT];
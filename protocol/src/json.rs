@@ -0,0 +1,296 @@
+//! A minimal pull parser for JSON documents, which works without allocation.
+//!
+//! The caller walks the document in the order of its structure, and may skip values that it does not know.
+//! Strings with escape sequences are not supported.
+
+/// Errors that occur while parsing. All of them end parsing.
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// The document ended early.
+    UnexpectedEnd,
+    /// A character does not fit the expected structure.
+    UnexpectedCharacter,
+    /// A string contains an escape sequence.
+    UnsupportedEscape,
+    /// Values are nested too deeply.
+    TooDeep,
+    /// A value has a different type than expected. The value was skipped, so parsing can continue.
+    WrongType,
+    /// A number is not representable in the expected type. The number was skipped, so parsing can continue.
+    InvalidNumber,
+}
+
+impl Error {
+    /// Whether parsing can continue after the error.
+    pub fn is_recoverable(&self) -> bool {
+        matches!(self, Error::WrongType | Error::InvalidNumber)
+    }
+}
+
+/// The type of the next value.
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[allow(missing_docs)]
+pub enum ValueKind {
+    Object,
+    Array,
+    String,
+    Number,
+    Boolean,
+    Null,
+}
+
+/// The maximum nesting depth of objects and arrays.
+const MAX_DEPTH: u8 = 32;
+
+/// A pull parser over a JSON document.
+pub struct Parser<'a> {
+    input: &'a [u8],
+    position: usize,
+    depth: u8,
+    /// One bit per nesting level, which is set until the first member of the object or array was parsed.
+    first: u32,
+}
+
+impl<'a> Parser<'a> {
+    /// Start parsing a document.
+    pub fn new(input: &'a str) -> Self {
+        Parser {
+            input: input.as_bytes(),
+            position: 0,
+            depth: 0,
+            first: 0,
+        }
+    }
+
+    /// The byte offset of the parser in the document.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(b' ' | b'\t' | b'\r' | b'\n') = self.input.get(self.position) {
+            self.position += 1;
+        }
+    }
+
+    fn peek(&mut self) -> Result<u8, Error> {
+        self.skip_whitespace();
+        self.input.get(self.position).copied().ok_or(Error::UnexpectedEnd)
+    }
+
+    fn expect(&mut self, character: u8) -> Result<(), Error> {
+        if self.peek()? != character {
+            return Err(Error::UnexpectedCharacter);
+        }
+
+        self.position += 1;
+        Ok(())
+    }
+
+    fn expect_literal(&mut self, literal: &[u8]) -> Result<(), Error> {
+        self.skip_whitespace();
+
+        if !self.input[self.position..].starts_with(literal) {
+            return Err(Error::UnexpectedCharacter);
+        }
+
+        self.position += literal.len();
+        Ok(())
+    }
+
+    fn push(&mut self) -> Result<(), Error> {
+        if self.depth >= MAX_DEPTH {
+            return Err(Error::TooDeep);
+        }
+
+        self.first |= 1 << self.depth;
+        self.depth += 1;
+        Ok(())
+    }
+
+    /// Handles the separator before the next member of an object or array.
+    /// Returns `false`, if the closing character was consumed instead.
+    fn next_member(&mut self, closing: u8) -> Result<bool, Error> {
+        let level = 1 << (self.depth - 1);
+        let first = self.first & level != 0;
+
+        // Empty objects and arrays, or the end after the last member.
+        if self.peek()? == closing {
+            self.position += 1;
+            self.depth -= 1;
+            return Ok(false);
+        }
+
+        if !first {
+            self.expect(b',')?;
+        }
+
+        self.first &= !level;
+        Ok(true)
+    }
+
+    /// The type of the next value.
+    pub fn peek_kind(&mut self) -> Result<ValueKind, Error> {
+        let kind = match self.peek()? {
+            b'{' => ValueKind::Object,
+            b'[' => ValueKind::Array,
+            b'"' => ValueKind::String,
+            b't' | b'f' => ValueKind::Boolean,
+            b'n' => ValueKind::Null,
+            b'-' | b'0'..=b'9' => ValueKind::Number,
+            _ => return Err(Error::UnexpectedCharacter),
+        };
+
+        Ok(kind)
+    }
+
+    /// Skip the next value, if it does not have the expected type.
+    fn check_kind(&mut self, kind: ValueKind) -> Result<(), Error> {
+        if self.peek_kind()? != kind {
+            self.skip_value()?;
+            return Err(Error::WrongType);
+        }
+
+        Ok(())
+    }
+
+    /// Enter an object. Members are read with [`Self::next_key`].
+    pub fn begin_object(&mut self) -> Result<(), Error> {
+        self.check_kind(ValueKind::Object)?;
+        self.expect(b'{')?;
+        self.push()
+    }
+
+    /// Get the key of the next member of the current object, or `None` at its end.
+    ///
+    /// The member's value must be read or skipped before the next key.
+    pub fn next_key(&mut self) -> Result<Option<&'a str>, Error> {
+        if !self.next_member(b'}')? {
+            return Ok(None);
+        }
+
+        if self.peek()? != b'"' {
+            return Err(Error::UnexpectedCharacter);
+        }
+
+        let key = self.raw_string()?;
+        self.expect(b':')?;
+
+        Ok(Some(key))
+    }
+
+    /// Enter an array. Elements are read after each call to [`Self::next_element`].
+    pub fn begin_array(&mut self) -> Result<(), Error> {
+        self.check_kind(ValueKind::Array)?;
+        self.expect(b'[')?;
+        self.push()
+    }
+
+    /// Whether the current array has another element, or ended.
+    pub fn next_element(&mut self) -> Result<bool, Error> {
+        self.next_member(b']')
+    }
+
+    fn raw_string(&mut self) -> Result<&'a str, Error> {
+        self.expect(b'"')?;
+        let start = self.position;
+
+        loop {
+            match self.input.get(self.position) {
+                None => return Err(Error::UnexpectedEnd),
+                Some(b'\\') => return Err(Error::UnsupportedEscape),
+                Some(b'"') => break,
+                Some(_) => self.position += 1,
+            }
+        }
+
+        let string = &self.input[start..self.position];
+        self.position += 1;
+
+        // The input was a string slice, and the closing quote is always at a character boundary.
+        core::str::from_utf8(string).map_err(|_| Error::UnexpectedCharacter)
+    }
+
+    /// Read a string.
+    pub fn string(&mut self) -> Result<&'a str, Error> {
+        self.check_kind(ValueKind::String)?;
+        self.raw_string()
+    }
+
+    fn raw_number(&mut self) -> Result<&'a str, Error> {
+        self.skip_whitespace();
+        let start = self.position;
+
+        while let Some(b'-' | b'+' | b'.' | b'e' | b'E' | b'0'..=b'9') = self.input.get(self.position) {
+            self.position += 1;
+        }
+
+        core::str::from_utf8(&self.input[start..self.position]).map_err(|_| Error::UnexpectedCharacter)
+    }
+
+    /// Read a number, and convert it to the expected type (e.g. `f32`, or `u32`).
+    pub fn number<T: core::str::FromStr>(&mut self) -> Result<T, Error> {
+        self.check_kind(ValueKind::Number)?;
+        self.raw_number()?.parse().map_err(|_| Error::InvalidNumber)
+    }
+
+    /// Read a boolean.
+    pub fn boolean(&mut self) -> Result<bool, Error> {
+        self.check_kind(ValueKind::Boolean)?;
+
+        if self.peek()? == b't' {
+            self.expect_literal(b"true")?;
+            Ok(true)
+        } else {
+            self.expect_literal(b"false")?;
+            Ok(false)
+        }
+    }
+
+    /// Skip the next value, including all nested values.
+    pub fn skip_value(&mut self) -> Result<(), Error> {
+        match self.peek_kind()? {
+            ValueKind::Object => {
+                self.expect(b'{')?;
+                self.push()?;
+
+                while self.next_key()?.is_some() {
+                    self.skip_value()?;
+                }
+            }
+            ValueKind::Array => {
+                self.expect(b'[')?;
+                self.push()?;
+
+                while self.next_element()? {
+                    self.skip_value()?;
+                }
+            }
+            ValueKind::String => _ = self.raw_string()?,
+            ValueKind::Number => _ = self.raw_number()?,
+            ValueKind::Boolean => {
+                if self.peek()? == b't' {
+                    self.expect_literal(b"true")?;
+                } else {
+                    self.expect_literal(b"false")?;
+                }
+            }
+            ValueKind::Null => self.expect_literal(b"null")?,
+        }
+
+        Ok(())
+    }
+
+    /// Check that the document ends after the last value.
+    pub fn end(&mut self) -> Result<(), Error> {
+        self.skip_whitespace();
+
+        if self.position < self.input.len() {
+            return Err(Error::UnexpectedCharacter);
+        }
+
+        Ok(())
+    }
+}
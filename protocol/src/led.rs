@@ -0,0 +1,102 @@
+//! The indicator LEDs of the device, and the functions that they are mapped to.
+//!
+//! The LEDs are on the pins of the microcontroller (dimmed by PWM), or on the outputs of an optional GPIO expander.
+//! Every function is shown on at most one LED. Functions that share an LED light it up, while any of them is on.
+
+/// The number of LEDs on pins of the microcontroller.
+pub const MCU_LED_COUNT: u8 = 4;
+
+/// The number of LEDs on the GPIO expander.
+pub const EXPANDER_LED_COUNT: u8 = 8;
+
+/// The number of LED functions.
+pub const FUNCTION_COUNT: usize = 8;
+
+/// A function that is shown on an LED.
+#[repr(u8)]
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum LedFunction {
+    /// USB is the active source.
+    UsbSource = 0,
+    /// S/PDIF is the active source.
+    SpdifSource = 1,
+    /// The Raspberry Pi is the active source.
+    RpiSource = 2,
+    /// The master mute.
+    Mute = 3,
+    /// Standby.
+    Standby = 4,
+    /// The master volume was reduced by the clip protection.
+    Clip = 5,
+    /// The blink codes of active faults.
+    Fault = 6,
+    /// The health of the firmware tasks.
+    Heartbeat = 7,
+}
+
+impl LedFunction {
+    /// All functions, in order of their identifiers.
+    pub const ALL: [LedFunction; FUNCTION_COUNT] = [
+        LedFunction::UsbSource,
+        LedFunction::SpdifSource,
+        LedFunction::RpiSource,
+        LedFunction::Mute,
+        LedFunction::Standby,
+        LedFunction::Clip,
+        LedFunction::Fault,
+        LedFunction::Heartbeat,
+    ];
+}
+
+/// A physical LED.
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum LedOutput {
+    /// No LED.
+    None,
+    /// An LED on a pin of the microcontroller, by its index (below [`MCU_LED_COUNT`]).
+    Mcu(u8),
+    /// An LED on an output of the GPIO expander (below [`EXPANDER_LED_COUNT`]).
+    Expander(u8),
+}
+
+impl LedOutput {
+    /// The encoding of the LED: `0x00` for none, `0x10` plus the index on the microcontroller, and `0x20` plus the
+    /// output on the GPIO expander.
+    pub fn encode(self) -> u8 {
+        match self {
+            LedOutput::None => 0x00,
+            LedOutput::Mcu(index) => 0x10 | index,
+            LedOutput::Expander(output) => 0x20 | output,
+        }
+    }
+
+    /// Decode an LED. Returns `None` for unknown LEDs.
+    pub fn decode(value: u8) -> Option<Self> {
+        let index = value & 0x0F;
+
+        match value & 0xF0 {
+            0x00 if index == 0 => Some(LedOutput::None),
+            0x10 if index < MCU_LED_COUNT => Some(LedOutput::Mcu(index)),
+            0x20 if index < EXPANDER_LED_COUNT => Some(LedOutput::Expander(index)),
+            _ => None,
+        }
+    }
+}
+
+/// The LEDs of all functions, indexed by function.
+pub type LedMap = [LedOutput; FUNCTION_COUNT];
+
+/// The mapping of functions to LEDs, unless configured otherwise: the sources on the blue (USB), yellow (S/PDIF), and
+/// red (Raspberry Pi) LEDs of the microcontroller, and the others on the first outputs of the GPIO expander.
+pub const DEFAULT_LED_MAP: LedMap = [
+    LedOutput::Mcu(0),
+    LedOutput::Mcu(2),
+    LedOutput::Mcu(3),
+    LedOutput::Expander(0),
+    LedOutput::Expander(1),
+    LedOutput::Expander(2),
+    LedOutput::Expander(3),
+    LedOutput::Expander(4),
+];
@@ -0,0 +1,37 @@
+//! Definitions that are shared between the firmware and host tools.
+//!
+//! Does not depend on any target specifics, such that host tools (e.g. a configuration GUI) can use it directly.
+#![no_std]
+
+pub mod backup;
+pub mod bulk;
+pub mod button;
+pub mod calibration;
+pub mod crc;
+pub mod device_info;
+pub mod event_log;
+pub mod handshake;
+pub mod hid;
+pub mod ir;
+pub mod json;
+pub mod led;
+pub mod parameter;
+pub mod provisioning;
+pub mod settings;
+pub mod thermal_log;
+
+/// The number of output channels that can be configured. Boards with other output channel counts need their own
+/// formats, which they check at compile time.
+pub const CHANNEL_COUNT: usize = 4;
+
+/// The maximum number of biquad stages per output channel.
+pub const MAX_STAGE_COUNT: usize = 12;
+
+/// The maximum delay of an output channel in samples.
+pub const MAX_DELAY: usize = 32;
+
+/// The lowest gain of an output channel in dB.
+pub const MIN_GAIN_DB: f32 = -60.0;
+
+/// The highest gain of an output channel in dB.
+pub const MAX_GAIN_DB: f32 = 24.0;
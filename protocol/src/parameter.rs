@@ -0,0 +1,383 @@
+//! Device parameters, addressed by a 16 bit identifier.
+//!
+//! Identifier layout:
+//! - `0x00nn`: Global parameters.
+//! - `0x1c00 | field`: Filter parameters of output channel `c`.
+//! - `(0x1c10 + (stage << 3)) | field`: Parameters of biquad stage `stage` of output channel `c`.
+//!
+//! Every parameter is described by a [`Descriptor`]: its value type, unit, valid range, and whether it is writable
+//! and stored with the settings.
+use crate::{CHANNEL_COUNT, MAX_DELAY, MAX_GAIN_DB, MAX_STAGE_COUNT, MIN_GAIN_DB};
+
+/// The number of parameters per biquad stage: the stage type and five values.
+pub const STAGE_PARAMETER_COUNT: usize = 6;
+
+/// The number of parameters per output channel.
+const CHANNEL_PARAMETER_COUNT: usize = 4 + MAX_STAGE_COUNT * STAGE_PARAMETER_COUNT;
+
+/// The global parameters, in enumeration order.
+const GLOBAL_PARAMETERS: [Parameter; 7] = [
+    Parameter::Volume,
+    Parameter::Mute,
+    Parameter::SourceSelect,
+    Parameter::ActiveSource,
+    Parameter::Status,
+    Parameter::Standby,
+    Parameter::InputVolume,
+];
+
+/// The total number of parameters.
+pub const PARAMETER_COUNT: usize = GLOBAL_PARAMETERS.len() + CHANNEL_COUNT * CHANNEL_PARAMETER_COUNT;
+
+const CHANNEL_BASE: u16 = 0x1000;
+const STAGE_BASE: u16 = 0x10;
+
+/// The type of a biquad stage, as given by the [`Parameter::StageType`] parameter.
+///
+/// Determines the meaning of the stage values:
+/// - Parametric stages: value 0 is the frequency in Hz, value 1 the quality factor, value 2 the gain in dB.
+/// - Coefficient stages: values 0 to 4 are the normalized coefficients `b0`, `b1`, `b2`, `a1`, and `a2`.
+#[repr(u8)]
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[allow(missing_docs)]
+pub enum StageType {
+    LowPass = 0,
+    HighPass = 1,
+    BandPass = 2,
+    Notch = 3,
+    AllPass = 4,
+    PeakingEq = 5,
+    LowShelf = 6,
+    HighShelf = 7,
+    Coefficients = 8,
+}
+
+impl TryFrom<u8> for StageType {
+    type Error = u8;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(StageType::LowPass),
+            1 => Ok(StageType::HighPass),
+            2 => Ok(StageType::BandPass),
+            3 => Ok(StageType::Notch),
+            4 => Ok(StageType::AllPass),
+            5 => Ok(StageType::PeakingEq),
+            6 => Ok(StageType::LowShelf),
+            7 => Ok(StageType::HighShelf),
+            8 => Ok(StageType::Coefficients),
+            _ => Err(value),
+        }
+    }
+}
+
+/// A device parameter.
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Parameter {
+    /// The master attenuation in steps of 0.5 dB (integer, 0 to 255). 255 mutes the output.
+    Volume,
+    /// The master mute (boolean).
+    Mute,
+    /// The selected source (integer): 0 for automatic selection, or the source's identifier.
+    SourceSelect,
+    /// The currently playing source (integer, read-only).
+    ActiveSource,
+    /// The device status flags (integer, read-only).
+    Status,
+    /// Standby, in which no source plays (boolean).
+    Standby,
+    /// The attenuation of the volume input (potentiometer or rotary encoder) in steps of 0.5 dB (integer, read-only).
+    InputVolume,
+    /// The gain of an output channel in dB (float).
+    ChannelGain { channel: u8 },
+    /// Whether an output channel is inverted (boolean).
+    ChannelInverted { channel: u8 },
+    /// The delay of an output channel in samples (integer).
+    ChannelDelay { channel: u8 },
+    /// The number of active biquad stages of an output channel (integer).
+    ///
+    /// Increasing the count appends stages that pass samples unchanged.
+    StageCount { channel: u8 },
+    /// The type of a biquad stage (integer, see [`StageType`]).
+    StageType { channel: u8, stage: u8 },
+    /// A value of a biquad stage (float). Its meaning depends on the [`StageType`].
+    StageValue { channel: u8, stage: u8, index: u8 },
+}
+
+/// The type of a parameter's value.
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ValueType {
+    /// A signed integer.
+    Integer,
+    /// A single-precision float.
+    Float,
+    /// A boolean, encoded as 0 or 1.
+    Boolean,
+}
+
+/// The unit of a parameter's value.
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Unit {
+    /// Without unit (e.g. flags, selections, and counts), or depending on other parameters.
+    None,
+    /// Attenuation in steps of 0.5 dB.
+    HalfDecibel,
+    /// Gain in dB.
+    Decibel,
+    /// A number of samples.
+    Samples,
+}
+
+/// The properties of a parameter.
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Descriptor {
+    /// The type of the value.
+    pub value_type: ValueType,
+    /// The unit of the value.
+    pub unit: Unit,
+    /// The smallest valid value (inclusive). Booleans range from 0 to 1.
+    pub minimum: f32,
+    /// The largest valid value (inclusive).
+    pub maximum: f32,
+    /// Whether the parameter can be written.
+    pub writable: bool,
+    /// Whether the parameter is stored with the settings.
+    pub persistent: bool,
+}
+
+impl Descriptor {
+    const fn new(value_type: ValueType, unit: Unit, minimum: f32, maximum: f32) -> Self {
+        Descriptor {
+            value_type,
+            unit,
+            minimum,
+            maximum,
+            writable: true,
+            persistent: true,
+        }
+    }
+
+    const fn boolean() -> Self {
+        Self::new(ValueType::Boolean, Unit::None, 0.0, 1.0)
+    }
+
+    const fn float(unit: Unit, minimum: f32, maximum: f32) -> Self {
+        Self::new(ValueType::Float, unit, minimum, maximum)
+    }
+
+    const fn integer(unit: Unit, minimum: i32, maximum: i32) -> Self {
+        Self::new(ValueType::Integer, unit, minimum as f32, maximum as f32)
+    }
+
+    /// A parameter that is neither writable, nor stored.
+    const fn read_only(self) -> Self {
+        Descriptor {
+            writable: false,
+            persistent: false,
+            ..self
+        }
+    }
+
+    /// A parameter that is writable, but not stored.
+    const fn volatile(self) -> Self {
+        Descriptor {
+            persistent: false,
+            ..self
+        }
+    }
+
+    /// Whether a value has the parameter's type, and lies within its range. Rejects NaN.
+    pub fn accepts(&self, value: Value) -> bool {
+        let value = match value {
+            Value::Integer(value) if self.value_type == ValueType::Integer => value as f32,
+            Value::Float(value) if self.value_type == ValueType::Float => value,
+            Value::Boolean(_) if self.value_type == ValueType::Boolean => return true,
+            _ => return false,
+        };
+
+        (self.minimum..=self.maximum).contains(&value)
+    }
+}
+
+/// A parameter value.
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[allow(missing_docs)]
+pub enum Value {
+    Integer(i32),
+    Float(f32),
+    Boolean(bool),
+}
+
+impl Value {
+    /// Decode a value from its 32 bit wire representation.
+    pub fn from_raw(raw: u32, value_type: ValueType) -> Option<Self> {
+        match value_type {
+            ValueType::Integer => Some(Value::Integer(raw as i32)),
+            ValueType::Float => Some(Value::Float(f32::from_bits(raw))),
+            ValueType::Boolean => match raw {
+                0 => Some(Value::Boolean(false)),
+                1 => Some(Value::Boolean(true)),
+                _ => None,
+            },
+        }
+    }
+
+    /// Encode the value to its 32 bit wire representation.
+    pub fn to_raw(self) -> u32 {
+        match self {
+            Value::Integer(value) => value as u32,
+            Value::Float(value) => value.to_bits(),
+            Value::Boolean(value) => value as u32,
+        }
+    }
+
+    /// The type of the value.
+    pub fn value_type(&self) -> ValueType {
+        match self {
+            Value::Integer(_) => ValueType::Integer,
+            Value::Float(_) => ValueType::Float,
+            Value::Boolean(_) => ValueType::Boolean,
+        }
+    }
+}
+
+impl Parameter {
+    /// The identifier of the parameter.
+    pub fn id(&self) -> u16 {
+        let channel_id = |channel: u8| CHANNEL_BASE | ((channel as u16) << 8);
+        let stage_id = |channel: u8, stage: u8| channel_id(channel) | (STAGE_BASE + ((stage as u16) << 3));
+
+        match *self {
+            Parameter::Volume => 0x0001,
+            Parameter::Mute => 0x0002,
+            Parameter::SourceSelect => 0x0003,
+            Parameter::ActiveSource => 0x0004,
+            Parameter::Status => 0x0005,
+            Parameter::Standby => 0x0006,
+            Parameter::InputVolume => 0x0007,
+            Parameter::ChannelGain { channel } => channel_id(channel),
+            Parameter::ChannelInverted { channel } => channel_id(channel) | 0x01,
+            Parameter::ChannelDelay { channel } => channel_id(channel) | 0x02,
+            Parameter::StageCount { channel } => channel_id(channel) | 0x03,
+            Parameter::StageType { channel, stage } => stage_id(channel, stage),
+            Parameter::StageValue { channel, stage, index } => stage_id(channel, stage) | (1 + index as u16),
+        }
+    }
+
+    /// Find a parameter by its identifier.
+    pub fn from_id(id: u16) -> Option<Self> {
+        if id & CHANNEL_BASE == 0 {
+            return GLOBAL_PARAMETERS.into_iter().find(|parameter| parameter.id() == id);
+        }
+
+        let channel = ((id >> 8) & 0x0F) as u8;
+        if id & 0xF000 != CHANNEL_BASE || channel as usize >= CHANNEL_COUNT {
+            return None;
+        }
+
+        let field = (id & 0xFF) as u8;
+        let parameter = match field {
+            0x00 => Parameter::ChannelGain { channel },
+            0x01 => Parameter::ChannelInverted { channel },
+            0x02 => Parameter::ChannelDelay { channel },
+            0x03 => Parameter::StageCount { channel },
+            _ if field >= STAGE_BASE as u8 => {
+                let stage = (field - STAGE_BASE as u8) >> 3;
+                let index = field & 0x07;
+
+                if stage as usize >= MAX_STAGE_COUNT || index as usize >= STAGE_PARAMETER_COUNT {
+                    return None;
+                }
+
+                match index {
+                    0 => Parameter::StageType { channel, stage },
+                    _ => Parameter::StageValue {
+                        channel,
+                        stage,
+                        index: index - 1,
+                    },
+                }
+            }
+            _ => return None,
+        };
+
+        Some(parameter)
+    }
+
+    /// Get a parameter by its position in the list of all parameters.
+    ///
+    /// Used for enumerating all parameters, in the range `0..PARAMETER_COUNT`.
+    pub fn from_index(index: usize) -> Option<Self> {
+        if index < GLOBAL_PARAMETERS.len() {
+            return Some(GLOBAL_PARAMETERS[index]);
+        }
+
+        let index = index - GLOBAL_PARAMETERS.len();
+        let channel = index / CHANNEL_PARAMETER_COUNT;
+        let field = index % CHANNEL_PARAMETER_COUNT;
+
+        if channel >= CHANNEL_COUNT {
+            return None;
+        }
+
+        let channel = channel as u8;
+        let parameter = match field {
+            0 => Parameter::ChannelGain { channel },
+            1 => Parameter::ChannelInverted { channel },
+            2 => Parameter::ChannelDelay { channel },
+            3 => Parameter::StageCount { channel },
+            _ => {
+                let stage = ((field - 4) / STAGE_PARAMETER_COUNT) as u8;
+
+                match (field - 4) % STAGE_PARAMETER_COUNT {
+                    0 => Parameter::StageType { channel, stage },
+                    index => Parameter::StageValue {
+                        channel,
+                        stage,
+                        index: index as u8 - 1,
+                    },
+                }
+            }
+        };
+
+        Some(parameter)
+    }
+
+    /// The properties of the parameter.
+    pub const fn descriptor(&self) -> Descriptor {
+        match self {
+            Parameter::Volume => Descriptor::integer(Unit::HalfDecibel, 0, 255),
+            Parameter::Mute => Descriptor::boolean(),
+            Parameter::SourceSelect => Descriptor::integer(Unit::None, 0, 255),
+            Parameter::ActiveSource => Descriptor::integer(Unit::None, 0, 255).read_only(),
+            Parameter::Status => Descriptor::integer(Unit::None, 0, 255).read_only(),
+            Parameter::Standby => Descriptor::boolean().volatile(),
+            Parameter::InputVolume => Descriptor::integer(Unit::HalfDecibel, 0, 255).read_only(),
+            Parameter::ChannelGain { .. } => Descriptor::float(Unit::Decibel, MIN_GAIN_DB, MAX_GAIN_DB),
+            Parameter::ChannelInverted { .. } => Descriptor::boolean(),
+            Parameter::ChannelDelay { .. } => Descriptor::integer(Unit::Samples, 0, MAX_DELAY as i32),
+            Parameter::StageCount { .. } => Descriptor::integer(Unit::None, 0, MAX_STAGE_COUNT as i32),
+            Parameter::StageType { .. } => {
+                Descriptor::integer(Unit::None, StageType::LowPass as i32, StageType::Coefficients as i32)
+            }
+            // Validated with the stage type by the filter configuration.
+            Parameter::StageValue { .. } => Descriptor::float(Unit::None, f32::MIN, f32::MAX),
+        }
+    }
+
+    /// The type of the parameter's value.
+    pub fn value_type(&self) -> ValueType {
+        self.descriptor().value_type
+    }
+
+    /// Whether the parameter can be written.
+    pub fn writable(&self) -> bool {
+        self.descriptor().writable
+    }
+}
@@ -0,0 +1,134 @@
+//! Provisioning data of a single device, which is written once during manufacturing.
+//!
+//! Like calibration data (see [`crate::calibration`]), provisioning data describes the unit, and survives factory
+//! resets. Unlike calibration data, it is never changed once written.
+//!
+//! Layout (see [`Provisioning::encode`]):
+//! - Header (8 byte): magic `PROV`, version (1 byte), and three reserved bytes.
+//! - The hardware revision of the board (1 byte), and a reserved byte.
+//! - The device ID ([`DEVICE_ID_SIZE`] byte).
+//! - The serial number ([`MAX_SERIAL_NUMBER_LENGTH`] byte, ASCII). Shorter serial numbers are padded with zeros.
+//! - The CRC-32 of all preceding bytes (`u32`, see [`crate::crc`]).
+use crate::crc::crc32;
+
+/// Identifies encoded provisioning data.
+pub const MAGIC: [u8; 4] = *b"PROV";
+
+/// The version of the format.
+pub const VERSION: u8 = 1;
+
+/// The size of the device ID.
+pub const DEVICE_ID_SIZE: usize = 6;
+
+/// The maximum length of a serial number.
+pub const MAX_SERIAL_NUMBER_LENGTH: usize = 16;
+
+const HEADER_SIZE: usize = 8;
+const DEVICE_ID_OFFSET: usize = HEADER_SIZE + 2;
+const SERIAL_NUMBER_OFFSET: usize = DEVICE_ID_OFFSET + DEVICE_ID_SIZE;
+
+/// The size of encoded provisioning data.
+pub const ENCODED_SIZE: usize = SERIAL_NUMBER_OFFSET + MAX_SERIAL_NUMBER_LENGTH + size_of::<u32>();
+
+/// Errors of decoding and validating provisioning data.
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// The data is shorter than [`ENCODED_SIZE`].
+    TooShort,
+    /// The data does not start with the magic.
+    InvalidMagic,
+    /// The data has another version.
+    IncompatibleVersion,
+    /// The checksum does not match.
+    CrcMismatch,
+    /// The serial number is empty, too long, or holds other characters than ASCII letters, digits, and `-`.
+    InvalidSerialNumber,
+}
+
+/// The provisioning data.
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Provisioning {
+    /// The hardware revision of the board.
+    pub hardware_revision: u8,
+    /// An ID of the unit in the format of a MAC address (EUI-48), e.g. for network bridges.
+    pub device_id: [u8; DEVICE_ID_SIZE],
+    /// The serial number in ASCII, padded with zeros.
+    serial_number: [u8; MAX_SERIAL_NUMBER_LENGTH],
+}
+
+/// Whether a serial number can be provisioned.
+pub fn is_valid_serial_number(serial_number: &str) -> bool {
+    (1..=MAX_SERIAL_NUMBER_LENGTH).contains(&serial_number.len())
+        && serial_number
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || byte == b'-')
+}
+
+impl Provisioning {
+    /// Create provisioning data, if the serial number is valid.
+    pub fn new(hardware_revision: u8, device_id: [u8; DEVICE_ID_SIZE], serial_number: &str) -> Result<Self, Error> {
+        if !is_valid_serial_number(serial_number) {
+            return Err(Error::InvalidSerialNumber);
+        }
+
+        let mut padded = [0u8; MAX_SERIAL_NUMBER_LENGTH];
+        padded[..serial_number.len()].copy_from_slice(serial_number.as_bytes());
+
+        Ok(Provisioning {
+            hardware_revision,
+            device_id,
+            serial_number: padded,
+        })
+    }
+
+    /// The serial number.
+    pub fn serial_number(&self) -> &str {
+        core::str::from_utf8(&self.serial_number)
+            .unwrap_or_default()
+            .trim_end_matches('\0')
+    }
+
+    /// Encode the provisioning data.
+    pub fn encode(&self) -> [u8; ENCODED_SIZE] {
+        let mut data = [0u8; ENCODED_SIZE];
+
+        data[0..4].copy_from_slice(&MAGIC);
+        data[4] = VERSION;
+        data[HEADER_SIZE] = self.hardware_revision;
+        data[DEVICE_ID_OFFSET..SERIAL_NUMBER_OFFSET].copy_from_slice(&self.device_id);
+        data[SERIAL_NUMBER_OFFSET..ENCODED_SIZE - 4].copy_from_slice(&self.serial_number);
+
+        let crc = crc32(&data[..ENCODED_SIZE - 4]);
+        data[ENCODED_SIZE - 4..].copy_from_slice(&crc.to_le_bytes());
+
+        data
+    }
+
+    /// Decode and validate provisioning data.
+    pub fn decode(data: &[u8]) -> Result<Self, Error> {
+        let data = data.get(..ENCODED_SIZE).ok_or(Error::TooShort)?;
+
+        if data[0..4] != MAGIC {
+            return Err(Error::InvalidMagic);
+        }
+
+        if data[4] != VERSION {
+            return Err(Error::IncompatibleVersion);
+        }
+
+        if crc32(&data[..ENCODED_SIZE - 4]).to_le_bytes() != data[ENCODED_SIZE - 4..] {
+            return Err(Error::CrcMismatch);
+        }
+
+        let serial_number = core::str::from_utf8(&data[SERIAL_NUMBER_OFFSET..ENCODED_SIZE - 4])
+            .map_err(|_| Error::InvalidSerialNumber)?
+            .trim_end_matches('\0');
+
+        let mut device_id = [0u8; DEVICE_ID_SIZE];
+        device_id.copy_from_slice(&data[DEVICE_ID_OFFSET..SERIAL_NUMBER_OFFSET]);
+
+        Provisioning::new(data[HEADER_SIZE], device_id, serial_number)
+    }
+}
@@ -0,0 +1,734 @@
+//! A versioned binary format for the device settings, which is shared by the settings storage and the host protocols.
+//!
+//! Layout:
+//! - Header (12 byte): magic `BLUS`, major version (1 byte), minor version (1 byte), length of the records (`u16`),
+//!   and the CRC-32 of the records (`u32`, see [`crate::crc`]).
+//! - Records: tag (1 byte), value length (`u16`), and value. Channel records contain nested records in their value,
+//!   after the channel index: gain, polarity, delay, the stages, and optionally a crossover (side, order, and
+//!   frequency) and a limiter (threshold and release time). The remote code record holds entries of action,
+//!   protocol, address (`u16`), and command (see [`crate::ir`]). The button record holds the action of every button
+//!   and press type, in order (see [`crate::button`]). The LED record holds the encoded LED of every function, in
+//!   order (see [`crate::led`]). The source volume record holds whether volumes are remembered per source, followed
+//!   by the remembered attenuation of every source. The supply threshold record holds the threshold in mV (`u16`),
+//!   and the amplifier gain record the gain setting (1 byte).
+//!
+//! Multi-byte fields are little-endian.
+//!
+//! Compatibility rules, by which settings survive firmware updates in both directions:
+//! - The major version only changes with incompatible changes. Settings with another major version are rejected.
+//! - The minor version increases, when records or fields are added.
+//! - Tags are never reused, and the meaning of a record never changes.
+//! - Records with unknown tags are skipped, so older firmware reads settings of newer firmware.
+//! - Fields are only ever appended to a record. Trailing fields that are unknown are ignored, and missing fields keep
+//!   their previous values, so newer firmware reads settings of older firmware.
+//! - Records that are missing keep their previous values. Unknown stage types are decoded as pass-through stages.
+//!   Within a channel record, the stages, the crossover, and the limiter are always replaced: a missing crossover or
+//!   limiter record means that the channel has none.
+//! - Settings of an older minor version are migrated: values that did not exist in that version get their defaults
+//!   (as in [`Settings::new`]), instead of keeping their previous values. Every minor version has a migration step.
+use crate::button::{ButtonAction, ButtonMap, BUTTON_COUNT, DEFAULT_BUTTON_MAP, PRESS_COUNT};
+use crate::crc::crc32;
+use crate::ir::{IrAction, IrCode, IrCodes, IrProtocol, ACTION_COUNT};
+use crate::led::{LedMap, LedOutput, DEFAULT_LED_MAP, FUNCTION_COUNT};
+use crate::parameter::StageType;
+use crate::{CHANNEL_COUNT, MAX_STAGE_COUNT};
+
+/// Identifies encoded settings.
+pub const MAGIC: [u8; 4] = *b"BLUS";
+
+/// The major version of the format, which changes with incompatible changes.
+pub const MAJOR_VERSION: u8 = 1;
+
+/// The minor version of the format, which increases with compatible additions. Every increase comes with a migration
+/// step, which provides the defaults of the added values for older settings.
+///
+/// - 1: Remote codes.
+/// - 2: Button actions.
+/// - 3: Trigger output mode.
+/// - 4: Remote code for selecting the next preset.
+/// - 5: Volumes per source.
+/// - 6: Supply undervoltage threshold.
+/// - 7: Amplifier gain.
+/// - 8: LED functions.
+/// - 9: Crossovers and limiters of the channels.
+pub const MINOR_VERSION: u8 = 9;
+
+/// The size of the header.
+pub const HEADER_SIZE: usize = 12;
+
+/// A migration step, which provides the defaults of values that were added by a minor version.
+type Migration = fn(&mut Settings);
+
+/// Migrations of settings with older minor versions, in order: the minor version that added values, and the step
+/// that provides their defaults.
+const MIGRATIONS: [(u8, Migration); MINOR_VERSION as usize] = [
+    (1, migrate_ir_codes),
+    (2, migrate_buttons),
+    (3, migrate_trigger_mode),
+    (4, migrate_next_preset_code),
+    (5, migrate_source_volumes),
+    (6, migrate_supply_threshold),
+    (7, migrate_amplifier_gain),
+    (8, migrate_leds),
+    (9, migrate_crossovers_and_limiters),
+];
+
+/// The number of sources, by their identifiers as for [`crate::parameter::Parameter::ActiveSource`], including `0`
+/// for none.
+pub const SOURCE_COUNT: usize = 5;
+
+/// Marks that no volume is remembered for a source. Equals the muted attenuation, which is not restored.
+pub const NO_SOURCE_ATTENUATION: u8 = 0xFF;
+
+/// The number of values of a biquad stage.
+pub const STAGE_VALUE_COUNT: usize = 5;
+
+const RECORD_HEADER_SIZE: usize = 3;
+const STAGE_RECORD_SIZE: usize = RECORD_HEADER_SIZE + 1 + STAGE_VALUE_COUNT * size_of::<f32>();
+const CROSSOVER_RECORD_SIZE: usize = RECORD_HEADER_SIZE + 2 + size_of::<f32>();
+const LIMITER_RECORD_SIZE: usize = RECORD_HEADER_SIZE + 2 * size_of::<f32>();
+const CHANNEL_RECORD_SIZE: usize = RECORD_HEADER_SIZE
+    + 1
+    + 3 * RECORD_HEADER_SIZE
+    + 9
+    + MAX_STAGE_COUNT * STAGE_RECORD_SIZE
+    + CROSSOVER_RECORD_SIZE
+    + LIMITER_RECORD_SIZE;
+const IR_CODE_ENTRY_SIZE: usize = 5;
+const IR_CODES_RECORD_SIZE: usize = RECORD_HEADER_SIZE + ACTION_COUNT * IR_CODE_ENTRY_SIZE;
+const BUTTONS_RECORD_SIZE: usize = RECORD_HEADER_SIZE + BUTTON_COUNT * PRESS_COUNT;
+const LEDS_RECORD_SIZE: usize = RECORD_HEADER_SIZE + FUNCTION_COUNT;
+const SOURCE_VOLUMES_RECORD_SIZE: usize = RECORD_HEADER_SIZE + 1 + SOURCE_COUNT;
+
+/// The maximum size of encoded settings.
+pub const MAX_ENCODED_SIZE: usize = HEADER_SIZE
+    + 5 * (RECORD_HEADER_SIZE + 1)
+    + RECORD_HEADER_SIZE
+    + 2
+    + CHANNEL_COUNT * CHANNEL_RECORD_SIZE
+    + IR_CODES_RECORD_SIZE
+    + BUTTONS_RECORD_SIZE
+    + LEDS_RECORD_SIZE
+    + SOURCE_VOLUMES_RECORD_SIZE;
+
+/// Record tags at the top level.
+mod tag {
+    pub const ATTENUATION: u8 = 0x01;
+    pub const MUTED: u8 = 0x02;
+    pub const SOURCE_SELECTION: u8 = 0x03;
+    pub const TRIGGER_MODE: u8 = 0x04;
+    pub const SUPPLY_THRESHOLD: u8 = 0x05;
+    pub const AMPLIFIER_GAIN: u8 = 0x06;
+    pub const CHANNEL: u8 = 0x10;
+    pub const IR_CODES: u8 = 0x20;
+    pub const BUTTONS: u8 = 0x21;
+    pub const LEDS: u8 = 0x22;
+    pub const SOURCE_VOLUMES: u8 = 0x30;
+}
+
+/// Record tags within channel records.
+mod channel_tag {
+    pub const GAIN: u8 = 0x01;
+    pub const INVERTED: u8 = 0x02;
+    pub const DELAY: u8 = 0x03;
+    pub const STAGE: u8 = 0x04;
+    pub const CROSSOVER: u8 = 0x05;
+    pub const LIMITER: u8 = 0x06;
+}
+
+/// Errors of encoding and decoding.
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// The buffer is too small for the encoded settings.
+    BufferTooSmall,
+    /// The data ends before the end of the header or the records.
+    TooShort,
+    /// The data does not start with the magic.
+    InvalidMagic,
+    /// The settings have another major version.
+    IncompatibleVersion,
+    /// The checksum of the records does not match.
+    CrcMismatch,
+    /// A record exceeds its enclosing record, or refers to a channel that does not exist.
+    InvalidRecord,
+}
+
+/// The settings of a biquad stage.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct StageSettings {
+    /// The type of the stage.
+    pub stage_type: StageType,
+    /// The values of the stage. See [`StageType`] for their meaning.
+    pub values: [f32; STAGE_VALUE_COUNT],
+}
+
+impl StageSettings {
+    /// A stage that passes samples unchanged.
+    pub const IDENTITY: Self = StageSettings {
+        stage_type: StageType::Coefficients,
+        values: [1.0, 0.0, 0.0, 0.0, 0.0],
+    };
+}
+
+/// The settings of a Linkwitz-Riley crossover.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct CrossoverSettings {
+    /// Whether the crossover passes the frequencies above (high-pass), or below (low-pass) its frequency.
+    pub high_pass: bool,
+    /// The order of the filter (2 or 4). Interpreted by the device firmware.
+    pub order: u8,
+    /// The crossover frequency in Hz.
+    pub frequency_hz: f32,
+}
+
+/// The settings of a peak limiter.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct LimiterSettings {
+    /// The highest output level in dBFS.
+    pub threshold_db: f32,
+    /// The release time in ms.
+    pub release_ms: f32,
+}
+
+/// The settings of an output channel.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct ChannelSettings {
+    /// The gain in dB.
+    pub gain_db: f32,
+    /// Whether the channel's polarity is inverted.
+    pub inverted: bool,
+    /// The delay in samples.
+    pub delay: u32,
+    stage_count: usize,
+    stages: [StageSettings; MAX_STAGE_COUNT],
+    /// The crossover after the stages, if any.
+    pub crossover: Option<CrossoverSettings>,
+    /// The limiter at the end of the channel, if any.
+    pub limiter: Option<LimiterSettings>,
+}
+
+impl ChannelSettings {
+    /// Create channel settings without stages, crossover, or limiter.
+    pub const fn new() -> Self {
+        ChannelSettings {
+            gain_db: 0.0,
+            inverted: false,
+            delay: 0,
+            stage_count: 0,
+            stages: [StageSettings::IDENTITY; MAX_STAGE_COUNT],
+            crossover: None,
+            limiter: None,
+        }
+    }
+
+    /// The biquad stages.
+    pub fn stages(&self) -> &[StageSettings] {
+        &self.stages[..self.stage_count]
+    }
+
+    /// Remove all stages.
+    pub fn clear_stages(&mut self) {
+        self.stage_count = 0;
+    }
+
+    /// Append a stage. Returns `false`, if there are [`MAX_STAGE_COUNT`] stages already.
+    pub fn push_stage(&mut self, stage: StageSettings) -> bool {
+        if self.stage_count >= MAX_STAGE_COUNT {
+            return false;
+        }
+
+        self.stages[self.stage_count] = stage;
+        self.stage_count += 1;
+        true
+    }
+}
+
+impl Default for ChannelSettings {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The device settings.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Settings {
+    /// The master attenuation in steps of 0.5 dB.
+    pub attenuation: u8,
+    /// The master mute.
+    pub muted: bool,
+    /// The selected source, as for [`crate::parameter::Parameter::SourceSelect`].
+    pub source_selection: u8,
+    /// The condition for the trigger output to switch on. Interpreted by the device firmware.
+    pub trigger_mode: u8,
+    /// The settings of all output channels.
+    pub channels: [ChannelSettings; CHANNEL_COUNT],
+    /// The remote codes of all actions.
+    pub ir_codes: IrCodes,
+    /// The actions of all buttons.
+    pub buttons: ButtonMap,
+    /// The LEDs of all functions.
+    pub leds: LedMap,
+    /// Whether the volume is remembered per source, and restored when the source becomes active.
+    pub source_volume: bool,
+    /// The remembered attenuation of every source in steps of 0.5 dB, or [`NO_SOURCE_ATTENUATION`].
+    pub source_attenuations: [u8; SOURCE_COUNT],
+    /// The supply voltage in mV, below which the amplifiers are shut down, or `0` for no threshold. Interpreted by
+    /// the device firmware.
+    pub supply_threshold_mv: u16,
+    /// The analog gain of the amplifiers in steps of 0.5 dB above the lowest gain. Interpreted by the device firmware.
+    pub amplifier_gain: u8,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Writes records into a buffer.
+struct Writer<'a> {
+    buffer: &'a mut [u8],
+    position: usize,
+}
+
+impl Writer<'_> {
+    fn bytes(&mut self, data: &[u8]) -> Result<(), Error> {
+        let end = self.position + data.len();
+        self.buffer
+            .get_mut(self.position..end)
+            .ok_or(Error::BufferTooSmall)?
+            .copy_from_slice(data);
+        self.position = end;
+
+        Ok(())
+    }
+
+    /// Write the header of a record, and return the position of its value.
+    fn begin(&mut self, tag: u8) -> Result<usize, Error> {
+        self.bytes(&[tag, 0, 0])?;
+        Ok(self.position)
+    }
+
+    /// Write the value length of a record that was started at `start`.
+    fn end(&mut self, start: usize) {
+        let length = (self.position - start) as u16;
+        self.buffer[start - 2..start].copy_from_slice(&length.to_le_bytes());
+    }
+
+    fn record(&mut self, tag: u8, value: &[u8]) -> Result<(), Error> {
+        let start = self.begin(tag)?;
+        self.bytes(value)?;
+        self.end(start);
+
+        Ok(())
+    }
+}
+
+/// Iterates over the records in a buffer.
+struct Records<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Iterator for Records<'a> {
+    type Item = Result<(u8, &'a [u8]), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.data.is_empty() {
+            return None;
+        }
+
+        if self.data.len() < RECORD_HEADER_SIZE {
+            self.data = &[];
+            return Some(Err(Error::InvalidRecord));
+        }
+
+        let tag = self.data[0];
+        let length = u16::from_le_bytes([self.data[1], self.data[2]]) as usize;
+        let Some(value) = self.data.get(RECORD_HEADER_SIZE..RECORD_HEADER_SIZE + length) else {
+            self.data = &[];
+            return Some(Err(Error::InvalidRecord));
+        };
+
+        self.data = &self.data[RECORD_HEADER_SIZE + length..];
+        Some(Ok((tag, value)))
+    }
+}
+
+/// Reads the fields of a record, which may be shorter or longer than expected.
+struct Fields<'a> {
+    data: &'a [u8],
+}
+
+impl Fields<'_> {
+    fn take<const N: usize>(&mut self) -> Option<[u8; N]> {
+        let bytes = self.data.get(..N)?.try_into().ok()?;
+        self.data = &self.data[N..];
+        Some(bytes)
+    }
+
+    fn u8(&mut self, value: &mut u8) {
+        if let Some(bytes) = self.take::<1>() {
+            *value = bytes[0];
+        }
+    }
+
+    fn bool(&mut self, value: &mut bool) {
+        if let Some(bytes) = self.take::<1>() {
+            *value = bytes[0] != 0;
+        }
+    }
+
+    fn u16(&mut self, value: &mut u16) {
+        if let Some(bytes) = self.take() {
+            *value = u16::from_le_bytes(bytes);
+        }
+    }
+
+    fn u32(&mut self, value: &mut u32) {
+        if let Some(bytes) = self.take() {
+            *value = u32::from_le_bytes(bytes);
+        }
+    }
+
+    fn f32(&mut self, value: &mut f32) {
+        if let Some(bytes) = self.take() {
+            *value = f32::from_le_bytes(bytes);
+        }
+    }
+}
+
+impl Settings {
+    /// Create settings at full volume, with automatic source selection, without stages, without remote codes,
+    /// with the default button actions and LEDs, without volumes per source, without supply threshold, and with the
+    /// lowest amplifier gain.
+    pub const fn new() -> Self {
+        Settings {
+            attenuation: 0,
+            muted: false,
+            source_selection: 0,
+            trigger_mode: 0,
+            channels: [ChannelSettings::new(); CHANNEL_COUNT],
+            ir_codes: [None; ACTION_COUNT],
+            buttons: DEFAULT_BUTTON_MAP,
+            leds: DEFAULT_LED_MAP,
+            source_volume: false,
+            source_attenuations: [NO_SOURCE_ATTENUATION; SOURCE_COUNT],
+            supply_threshold_mv: 0,
+            amplifier_gain: 0,
+        }
+    }
+
+    /// Encode the settings into a buffer, and return the encoded length.
+    ///
+    /// A buffer of [`MAX_ENCODED_SIZE`] always suffices.
+    pub fn encode(&self, buffer: &mut [u8]) -> Result<usize, Error> {
+        let mut writer = Writer { buffer, position: 0 };
+        writer.bytes(&[0; HEADER_SIZE])?;
+
+        writer.record(tag::ATTENUATION, &[self.attenuation])?;
+        writer.record(tag::MUTED, &[self.muted as u8])?;
+        writer.record(tag::SOURCE_SELECTION, &[self.source_selection])?;
+        writer.record(tag::TRIGGER_MODE, &[self.trigger_mode])?;
+        writer.record(tag::SUPPLY_THRESHOLD, &self.supply_threshold_mv.to_le_bytes())?;
+        writer.record(tag::AMPLIFIER_GAIN, &[self.amplifier_gain])?;
+
+        for (index, channel) in self.channels.iter().enumerate() {
+            let start = writer.begin(tag::CHANNEL)?;
+            writer.bytes(&[index as u8])?;
+
+            writer.record(channel_tag::GAIN, &channel.gain_db.to_le_bytes())?;
+            writer.record(channel_tag::INVERTED, &[channel.inverted as u8])?;
+            writer.record(channel_tag::DELAY, &channel.delay.to_le_bytes())?;
+
+            for stage in channel.stages() {
+                let start = writer.begin(channel_tag::STAGE)?;
+                writer.bytes(&[stage.stage_type as u8])?;
+
+                for value in stage.values {
+                    writer.bytes(&value.to_le_bytes())?;
+                }
+
+                writer.end(start);
+            }
+
+            if let Some(crossover) = channel.crossover {
+                let start = writer.begin(channel_tag::CROSSOVER)?;
+                writer.bytes(&[crossover.high_pass as u8, crossover.order])?;
+                writer.bytes(&crossover.frequency_hz.to_le_bytes())?;
+                writer.end(start);
+            }
+
+            if let Some(limiter) = channel.limiter {
+                let start = writer.begin(channel_tag::LIMITER)?;
+                writer.bytes(&limiter.threshold_db.to_le_bytes())?;
+                writer.bytes(&limiter.release_ms.to_le_bytes())?;
+                writer.end(start);
+            }
+
+            writer.end(start);
+        }
+
+        let start = writer.begin(tag::IR_CODES)?;
+        for (action, code) in self.ir_codes.iter().enumerate() {
+            if let Some(code) = code {
+                let [address_low, address_high] = code.address.to_le_bytes();
+                writer.bytes(&[
+                    action as u8,
+                    code.protocol as u8,
+                    address_low,
+                    address_high,
+                    code.command,
+                ])?;
+            }
+        }
+        writer.end(start);
+
+        let start = writer.begin(tag::BUTTONS)?;
+        for action in self.buttons.iter().flatten() {
+            writer.bytes(&[*action as u8])?;
+        }
+        writer.end(start);
+
+        let start = writer.begin(tag::LEDS)?;
+        for led in self.leds {
+            writer.bytes(&[led.encode()])?;
+        }
+        writer.end(start);
+
+        let start = writer.begin(tag::SOURCE_VOLUMES)?;
+        writer.bytes(&[self.source_volume as u8])?;
+        writer.bytes(&self.source_attenuations)?;
+        writer.end(start);
+
+        let length = writer.position;
+        let records = &writer.buffer[HEADER_SIZE..length];
+        let crc = crc32(records);
+
+        let header = &mut writer.buffer[..HEADER_SIZE];
+        header[0..4].copy_from_slice(&MAGIC);
+        header[4] = MAJOR_VERSION;
+        header[5] = MINOR_VERSION;
+        header[6..8].copy_from_slice(&((length - HEADER_SIZE) as u16).to_le_bytes());
+        header[8..12].copy_from_slice(&crc.to_le_bytes());
+
+        Ok(length)
+    }
+
+    /// Decode encoded settings on top of these settings. See the module documentation for the handling of missing
+    /// and unknown records.
+    ///
+    /// The settings are only modified, if decoding succeeds. Returns the encoded length.
+    pub fn decode(&mut self, data: &[u8]) -> Result<usize, Error> {
+        let header = data.get(..HEADER_SIZE).ok_or(Error::TooShort)?;
+
+        if header[0..4] != MAGIC {
+            return Err(Error::InvalidMagic);
+        }
+
+        if header[4] != MAJOR_VERSION {
+            return Err(Error::IncompatibleVersion);
+        }
+
+        let length = u16::from_le_bytes([header[6], header[7]]) as usize;
+        let crc = u32::from_le_bytes([header[8], header[9], header[10], header[11]]);
+        let records = data.get(HEADER_SIZE..HEADER_SIZE + length).ok_or(Error::TooShort)?;
+
+        if crc32(records) != crc {
+            return Err(Error::CrcMismatch);
+        }
+
+        let mut settings = *self;
+        let minor_version = header[5];
+
+        for record in (Records { data: records }) {
+            let (tag, value) = record?;
+            let mut fields = Fields { data: value };
+
+            match tag {
+                tag::ATTENUATION => fields.u8(&mut settings.attenuation),
+                tag::MUTED => fields.bool(&mut settings.muted),
+                tag::SOURCE_SELECTION => fields.u8(&mut settings.source_selection),
+                tag::TRIGGER_MODE => fields.u8(&mut settings.trigger_mode),
+                tag::SUPPLY_THRESHOLD => fields.u16(&mut settings.supply_threshold_mv),
+                tag::AMPLIFIER_GAIN => fields.u8(&mut settings.amplifier_gain),
+                tag::CHANNEL => {
+                    let (&index, records) = value.split_first().ok_or(Error::InvalidRecord)?;
+                    let channel = settings.channels.get_mut(index as usize).ok_or(Error::InvalidRecord)?;
+
+                    decode_channel(channel, records)?;
+                }
+                tag::IR_CODES => decode_ir_codes(&mut settings.ir_codes, value),
+                tag::BUTTONS => decode_buttons(&mut settings.buttons, value),
+                tag::LEDS => decode_leds(&mut settings.leds, value),
+                tag::SOURCE_VOLUMES => {
+                    fields.bool(&mut settings.source_volume);
+
+                    for attenuation in settings.source_attenuations.iter_mut() {
+                        fields.u8(attenuation);
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        for (version, migrate) in MIGRATIONS {
+            if minor_version < version {
+                migrate(&mut settings);
+            }
+        }
+
+        *self = settings;
+        Ok(HEADER_SIZE + length)
+    }
+}
+
+/// The major and minor version of encoded settings, if the data starts with a header.
+pub fn version(data: &[u8]) -> Option<(u8, u8)> {
+    let header = data.get(..HEADER_SIZE).filter(|header| header[0..4] == MAGIC)?;
+    Some((header[4], header[5]))
+}
+
+/// Remote codes were added with minor version 1. There were none before.
+fn migrate_ir_codes(settings: &mut Settings) {
+    settings.ir_codes = [None; ACTION_COUNT];
+}
+
+/// Button actions were added with minor version 2. The buttons had their default actions before.
+fn migrate_buttons(settings: &mut Settings) {
+    settings.buttons = DEFAULT_BUTTON_MAP;
+}
+
+/// The trigger output mode was added with minor version 3. The trigger output followed standby before.
+fn migrate_trigger_mode(settings: &mut Settings) {
+    settings.trigger_mode = 0;
+}
+
+/// The remote code for selecting the next preset was added with minor version 4.
+fn migrate_next_preset_code(settings: &mut Settings) {
+    settings.ir_codes[IrAction::NextPreset as usize] = None;
+}
+
+/// Volumes per source were added with minor version 5. The volume was the same for all sources before.
+fn migrate_source_volumes(settings: &mut Settings) {
+    settings.source_volume = false;
+    settings.source_attenuations = [NO_SOURCE_ATTENUATION; SOURCE_COUNT];
+}
+
+/// The supply threshold was added with minor version 6. The supply was not monitored before.
+fn migrate_supply_threshold(settings: &mut Settings) {
+    settings.supply_threshold_mv = 0;
+}
+
+/// The amplifier gain was added with minor version 7. The amplifiers ran at their lowest gain before.
+fn migrate_amplifier_gain(settings: &mut Settings) {
+    settings.amplifier_gain = 0;
+}
+
+/// LED functions were added with minor version 8. The LEDs had their default functions before.
+fn migrate_leds(settings: &mut Settings) {
+    settings.leds = DEFAULT_LED_MAP;
+}
+
+/// Crossovers and limiters were added with minor version 9. The channels had neither before.
+fn migrate_crossovers_and_limiters(settings: &mut Settings) {
+    for channel in settings.channels.iter_mut() {
+        channel.crossover = None;
+        channel.limiter = None;
+    }
+}
+
+/// Decode the nested records of a channel record. Stages, crossover, and limiter are always replaced.
+fn decode_channel(channel: &mut ChannelSettings, records: &[u8]) -> Result<(), Error> {
+    channel.clear_stages();
+    channel.crossover = None;
+    channel.limiter = None;
+
+    for record in (Records { data: records }) {
+        let (tag, value) = record?;
+        let mut fields = Fields { data: value };
+
+        match tag {
+            channel_tag::GAIN => fields.f32(&mut channel.gain_db),
+            channel_tag::INVERTED => fields.bool(&mut channel.inverted),
+            channel_tag::DELAY => fields.u32(&mut channel.delay),
+            channel_tag::STAGE => {
+                let mut stage_type = StageType::Coefficients as u8;
+                let mut stage = StageSettings {
+                    stage_type: StageType::Coefficients,
+                    values: [0.0; STAGE_VALUE_COUNT],
+                };
+
+                fields.u8(&mut stage_type);
+                for value in stage.values.iter_mut() {
+                    fields.f32(value);
+                }
+
+                if let Ok(stage_type) = StageType::try_from(stage_type) {
+                    stage.stage_type = stage_type;
+                } else {
+                    stage = StageSettings::IDENTITY;
+                }
+
+                // Stages beyond the maximum stage count are dropped.
+                _ = channel.push_stage(stage);
+            }
+            channel_tag::CROSSOVER => {
+                let mut crossover = CrossoverSettings {
+                    high_pass: false,
+                    order: 4,
+                    frequency_hz: 0.0,
+                };
+
+                fields.bool(&mut crossover.high_pass);
+                fields.u8(&mut crossover.order);
+                fields.f32(&mut crossover.frequency_hz);
+                channel.crossover = Some(crossover);
+            }
+            channel_tag::LIMITER => {
+                let mut limiter = LimiterSettings {
+                    threshold_db: 0.0,
+                    release_ms: 0.0,
+                };
+
+                fields.f32(&mut limiter.threshold_db);
+                fields.f32(&mut limiter.release_ms);
+                channel.limiter = Some(limiter);
+            }
+            _ => (),
+        }
+    }
+
+    Ok(())
+}
+
+/// Decode the entries of a remote code record. All codes are replaced, and unknown entries are skipped.
+fn decode_ir_codes(ir_codes: &mut IrCodes, entries: &[u8]) {
+    *ir_codes = [None; ACTION_COUNT];
+
+    for entry in entries.chunks_exact(IR_CODE_ENTRY_SIZE) {
+        let (Ok(action), Ok(protocol)) = (IrAction::try_from(entry[0]), IrProtocol::try_from(entry[1])) else {
+            continue;
+        };
+
+        ir_codes[action as usize] = Some(IrCode {
+            protocol,
+            address: u16::from_le_bytes([entry[2], entry[3]]),
+            command: entry[4],
+        });
+    }
+}
+
+/// Decode the actions of a button record. Missing actions keep their values, and unknown actions are disabled.
+fn decode_buttons(buttons: &mut ButtonMap, actions: &[u8]) {
+    for (action, value) in buttons.iter_mut().flatten().zip(actions) {
+        *action = ButtonAction::try_from(*value).unwrap_or(ButtonAction::None);
+    }
+}
+
+/// Decode the LED record. Unknown LEDs are decoded as no LED.
+fn decode_leds(leds: &mut LedMap, values: &[u8]) {
+    for (led, value) in leds.iter_mut().zip(values) {
+        *led = LedOutput::decode(*value).unwrap_or(LedOutput::None);
+    }
+}
@@ -0,0 +1,111 @@
+//! Records of the thermal log, which summarizes the temperatures of a device over its operating time, e.g. for
+//! warranty claims, and feedback on enclosure designs.
+//!
+//! Every record covers a period of operation, with the lowest and highest temperature of every sensor, and the time
+//! during which the amplifiers throttled the output. Records of consecutive periods combine into one (see
+//! [`Record::merge`]), which summarizes the whole log.
+//!
+//! Layout of a record (see [`Record::encode`]):
+//!
+//! | Offset | Size | Content                                                                    |
+//! |--------|------|----------------------------------------------------------------------------|
+//! | 0      | 4    | Covered operating time in seconds (`u32`)                                  |
+//! | 4      | 4    | Time during which any amplifier throttled the output in seconds (`u32`)    |
+//! | 8      | 5    | Lowest temperature in °C by sensor (`i8`), [`NO_TEMPERATURE`] if unmeasured |
+//! | 13     | 5    | Highest temperature in °C by sensor (`i8`), as above                        |
+//! | 18     | 2    | Reserved (zero)                                                            |
+//! | 20     | 4    | CRC-32 of the preceding bytes (`u32`)                                      |
+//!
+//! The sensors are the microcontroller, followed by the amplifiers of the output channels. All values are
+//! little-endian.
+use crate::crc::crc32;
+use crate::CHANNEL_COUNT;
+
+/// The size of an encoded record.
+pub const ENTRY_SIZE: usize = 24;
+
+/// The number of temperature sensors: the microcontroller, and the amplifier of every output channel.
+pub const SENSOR_COUNT: usize = 1 + CHANNEL_COUNT;
+
+/// The encoding of a sensor that was not measured during the period (e.g. an amplifier that was off).
+pub const NO_TEMPERATURE: i8 = i8::MIN;
+
+/// The temperatures during a period of operation.
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Record {
+    /// The covered operating time in seconds.
+    pub duration_s: u32,
+    /// The time during which any amplifier throttled the output in seconds.
+    pub throttled_s: u32,
+    /// The lowest temperature in °C by sensor, unless the sensor was not measured.
+    pub minimum_c: [Option<i8>; SENSOR_COUNT],
+    /// The highest temperature in °C by sensor, unless the sensor was not measured.
+    pub maximum_c: [Option<i8>; SENSOR_COUNT],
+}
+
+impl Record {
+    /// Add a temperature reading of a sensor in °C.
+    pub fn add_reading(&mut self, sensor: usize, temperature_c: f32) {
+        // Keeps clear of the encoding of unmeasured sensors.
+        let temperature_c = temperature_c.clamp(NO_TEMPERATURE as f32 + 1.0, i8::MAX as f32) as i8;
+
+        if let (Some(minimum), Some(maximum)) = (self.minimum_c.get_mut(sensor), self.maximum_c.get_mut(sensor)) {
+            *minimum = Some(minimum.map_or(temperature_c, |minimum| minimum.min(temperature_c)));
+            *maximum = Some(maximum.map_or(temperature_c, |maximum| maximum.max(temperature_c)));
+        }
+    }
+
+    /// Combine the record with the record of another period.
+    pub fn merge(&mut self, other: &Record) {
+        self.duration_s = self.duration_s.saturating_add(other.duration_s);
+        self.throttled_s = self.throttled_s.saturating_add(other.throttled_s);
+
+        for sensor in 0..SENSOR_COUNT {
+            self.minimum_c[sensor] = match (self.minimum_c[sensor], other.minimum_c[sensor]) {
+                (Some(a), Some(b)) => Some(a.min(b)),
+                (a, b) => a.or(b),
+            };
+            self.maximum_c[sensor] = match (self.maximum_c[sensor], other.maximum_c[sensor]) {
+                (Some(a), Some(b)) => Some(a.max(b)),
+                (a, b) => a.or(b),
+            };
+        }
+    }
+
+    /// Encode the record.
+    pub fn encode(&self) -> [u8; ENTRY_SIZE] {
+        let mut data = [0u8; ENTRY_SIZE];
+
+        data[0..4].copy_from_slice(&self.duration_s.to_le_bytes());
+        data[4..8].copy_from_slice(&self.throttled_s.to_le_bytes());
+
+        for sensor in 0..SENSOR_COUNT {
+            data[8 + sensor] = self.minimum_c[sensor].unwrap_or(NO_TEMPERATURE) as u8;
+            data[8 + SENSOR_COUNT + sensor] = self.maximum_c[sensor].unwrap_or(NO_TEMPERATURE) as u8;
+        }
+
+        let crc = crc32(&data[..20]);
+        data[20..24].copy_from_slice(&crc.to_le_bytes());
+
+        data
+    }
+
+    /// Decode a record. Returns `None` for damaged records.
+    pub fn decode(data: &[u8]) -> Option<Self> {
+        let data: &[u8; ENTRY_SIZE] = data.get(..ENTRY_SIZE)?.try_into().ok()?;
+
+        if crc32(&data[..20]).to_le_bytes() != data[20..24] {
+            return None;
+        }
+
+        let temperature = |offset: usize| Some(data[offset] as i8).filter(|value| *value != NO_TEMPERATURE);
+
+        Some(Record {
+            duration_s: u32::from_le_bytes([data[0], data[1], data[2], data[3]]),
+            throttled_s: u32::from_le_bytes([data[4], data[5], data[6], data[7]]),
+            minimum_c: core::array::from_fn(|sensor| temperature(8 + sensor)),
+            maximum_c: core::array::from_fn(|sensor| temperature(8 + SENSOR_COUNT + sensor)),
+        })
+    }
+}
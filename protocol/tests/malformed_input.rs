@@ -0,0 +1,147 @@
+//! Decoding of malformed control frames and settings, which must never panic.
+//!
+//! Runs the checks of the fuzz targets (see `fuzz/fuzz_targets`) on pseudo-random data on the stable toolchain. The
+//! fuzz targets explore much more data, and find their way through the formats.
+use protocol::bulk::{self, Begin};
+use protocol::crc::crc32;
+use protocol::hid::{self, Request};
+use protocol::parameter::{Parameter, Value};
+use protocol::settings::{Settings, MAGIC, MAJOR_VERSION, MAX_ENCODED_SIZE, MINOR_VERSION};
+
+const ITERATIONS: usize = 20_000;
+
+/// A xorshift generator, such that failures are reproducible.
+struct Random(u32);
+
+impl Random {
+    fn next(&mut self) -> u32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        self.0
+    }
+
+    /// Random bytes of a random length up to `max_length`.
+    fn bytes(&mut self, max_length: usize) -> Vec<u8> {
+        let length = self.next() as usize % (max_length + 1);
+        (0..length).map(|_| self.next() as u8).collect()
+    }
+}
+
+/// A settings document with a valid header around records.
+fn document(minor_version: u8, records: &[u8]) -> Vec<u8> {
+    let mut document = MAGIC.to_vec();
+    document.extend_from_slice(&[MAJOR_VERSION, minor_version]);
+    document.extend_from_slice(&(records.len() as u16).to_le_bytes());
+    document.extend_from_slice(&crc32(records).to_le_bytes());
+    document.extend_from_slice(records);
+    document
+}
+
+/// Records of known tags with random values, including channel records with nested records.
+fn records(random: &mut Random) -> Vec<u8> {
+    const TAGS: [u8; 12] = [0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x10, 0x20, 0x21, 0x22, 0x30, 0x7F];
+    let mut records = Vec::new();
+
+    for _ in 0..random.next() % 8 {
+        let tag = TAGS[random.next() as usize % TAGS.len()];
+        let mut value = random.bytes(48);
+
+        if tag == 0x10 {
+            value.insert(0, (random.next() % 5) as u8);
+
+            for _ in 0..random.next() % 4 {
+                let nested = random.bytes(24);
+                value.push((random.next() % 8) as u8);
+                value.extend_from_slice(&(nested.len() as u16).to_le_bytes());
+                value.extend_from_slice(&nested);
+            }
+        }
+
+        // Lengths are off sometimes, to exercise truncated records.
+        let length = match random.next() % 8 {
+            0 => value.len() + 1 + (random.next() % 4) as usize,
+            _ => value.len(),
+        };
+
+        records.push(tag);
+        records.extend_from_slice(&(length as u16).to_le_bytes());
+        records.extend_from_slice(&value);
+    }
+
+    records
+}
+
+#[test]
+fn control_frames() {
+    let mut random = Random(0x1234_5678);
+
+    for _ in 0..ITERATIONS {
+        let mut data = random.bytes(hid::REPORT_SIZE + 4);
+        if let Some(command) = data.first_mut() {
+            // Mostly known commands.
+            *command %= 16;
+        }
+
+        if let Ok((sequence, request)) = Request::decode(&data) {
+            assert_eq!(Request::decode(&request.encode(sequence)), Ok((sequence, request)));
+
+            if let Request::Get { id } | Request::Set { id, .. } = request {
+                if let Some(parameter) = Parameter::from_id(id) {
+                    assert_eq!(parameter.id(), id);
+                    _ = Value::from_raw(random.next(), parameter.value_type());
+                }
+            }
+        }
+
+        if let Ok(begin) = Begin::decode(&data) {
+            assert_eq!(Begin::decode(&begin.encode()), Ok(begin));
+        }
+
+        _ = bulk::Response::decode(&data);
+
+        if let Ok(report) = <&[u8; hid::REPORT_SIZE]>::try_from(data.as_slice()) {
+            hid::Response::entries(report).for_each(drop);
+        }
+    }
+}
+
+#[test]
+fn all_parameter_identifiers() {
+    for id in 0..=u16::MAX {
+        if let Some(parameter) = Parameter::from_id(id) {
+            assert_eq!(parameter.id(), id);
+        }
+    }
+}
+
+#[test]
+fn settings() {
+    let mut random = Random(0x9ABC_DEF0);
+
+    for _ in 0..ITERATIONS {
+        let minor_version = (random.next() % (MINOR_VERSION as u32 + 2)) as u8;
+        let mut document = document(minor_version, &records(&mut random));
+
+        // Truncate or extend some documents.
+        match random.next() % 8 {
+            0 => document.truncate(random.next() as usize % (document.len() + 1)),
+            1 => document.extend(random.bytes(8)),
+            _ => (),
+        }
+
+        let mut settings = Settings::new();
+        if settings.decode(&document).is_ok() {
+            let mut buffer = [0u8; MAX_ENCODED_SIZE];
+            let length = settings
+                .encode(&mut buffer)
+                .expect("decoded settings fit the encoded size");
+
+            Settings::new()
+                .decode(&buffer[..length])
+                .expect("encoded settings decode");
+        }
+
+        _ = Settings::new().decode(&random.bytes(64));
+    }
+}
@@ -0,0 +1,329 @@
+//! Migration of settings with older minor versions.
+use protocol::button::{ButtonAction, DEFAULT_BUTTON_MAP};
+use protocol::crc::crc32;
+use protocol::ir::{IrAction, IrCode, IrProtocol};
+use protocol::led::{LedFunction, LedOutput, DEFAULT_LED_MAP};
+use protocol::settings::{
+    self, CrossoverSettings, LimiterSettings, Settings, MAGIC, MAJOR_VERSION, MINOR_VERSION, NO_SOURCE_ATTENUATION,
+    SOURCE_COUNT,
+};
+
+const ATTENUATION: u8 = 0x01;
+const MUTED: u8 = 0x02;
+const TRIGGER_MODE: u8 = 0x04;
+const SUPPLY_THRESHOLD: u8 = 0x05;
+const AMPLIFIER_GAIN: u8 = 0x06;
+const IR_CODES: u8 = 0x20;
+const BUTTONS: u8 = 0x21;
+const LEDS: u8 = 0x22;
+const SOURCE_VOLUMES: u8 = 0x30;
+const CHANNEL: u8 = 0x10;
+const CROSSOVER: u8 = 0x05;
+const LIMITER: u8 = 0x06;
+
+const CROSSOVER_SETTINGS: CrossoverSettings = CrossoverSettings {
+    high_pass: true,
+    order: 4,
+    frequency_hz: 2500.0,
+};
+
+const LIMITER_SETTINGS: LimiterSettings = LimiterSettings {
+    threshold_db: -3.0,
+    release_ms: 100.0,
+};
+
+const CODE: IrCode = IrCode {
+    protocol: IrProtocol::Nec,
+    address: 0x1234,
+    command: 0x56,
+};
+
+/// Encode a record.
+fn record(tag: u8, value: &[u8]) -> Vec<u8> {
+    let mut record = vec![tag];
+    record.extend_from_slice(&(value.len() as u16).to_le_bytes());
+    record.extend_from_slice(value);
+    record
+}
+
+/// Encode settings of a minor version from records.
+fn document(minor_version: u8, records: &[Vec<u8>]) -> Vec<u8> {
+    let records = records.concat();
+
+    let mut document = MAGIC.to_vec();
+    document.extend_from_slice(&[MAJOR_VERSION, minor_version]);
+    document.extend_from_slice(&(records.len() as u16).to_le_bytes());
+    document.extend_from_slice(&crc32(&records).to_le_bytes());
+    document.extend_from_slice(&records);
+    document
+}
+
+/// An entry of the remote code record.
+fn ir_entry(action: IrAction) -> [u8; 5] {
+    let [address_low, address_high] = CODE.address.to_le_bytes();
+    [
+        action as u8,
+        CODE.protocol as u8,
+        address_low,
+        address_high,
+        CODE.command,
+    ]
+}
+
+/// Settings that differ from the defaults in every value that was added by a migration step.
+fn modified() -> Settings {
+    let mut settings = Settings::new();
+
+    settings.attenuation = 40;
+    settings.muted = true;
+    settings.trigger_mode = 1;
+    settings.ir_codes = [Some(CODE); protocol::ir::ACTION_COUNT];
+    settings.buttons[0] = [ButtonAction::Mute; protocol::button::PRESS_COUNT];
+    settings.source_volume = true;
+    settings.source_attenuations = [30; SOURCE_COUNT];
+    settings.supply_threshold_mv = 10_000;
+    settings.amplifier_gain = 8;
+    settings.leds = [LedOutput::Expander(7); protocol::led::FUNCTION_COUNT];
+
+    for channel in settings.channels.iter_mut() {
+        channel.crossover = Some(CROSSOVER_SETTINGS);
+        channel.limiter = Some(LIMITER_SETTINGS);
+    }
+
+    settings
+}
+
+#[test]
+fn version_0_gets_default_remote_codes_buttons_and_trigger_mode() {
+    let mut settings = modified();
+    settings.decode(&document(0, &[record(ATTENUATION, &[10])])).unwrap();
+
+    assert_eq!(settings.attenuation, 10);
+    assert!(settings.muted, "values of version 0 keep their previous values");
+    assert_eq!(settings.ir_codes, [None; protocol::ir::ACTION_COUNT]);
+    assert_eq!(settings.buttons, DEFAULT_BUTTON_MAP);
+    assert_eq!(settings.trigger_mode, 0);
+}
+
+#[test]
+fn version_1_keeps_remote_codes() {
+    let mut settings = modified();
+    settings
+        .decode(&document(1, &[record(IR_CODES, &ir_entry(IrAction::Mute))]))
+        .unwrap();
+
+    assert_eq!(settings.ir_codes[IrAction::Mute as usize], Some(CODE));
+    assert_eq!(settings.ir_codes[IrAction::VolumeUp as usize], None);
+    assert_eq!(settings.buttons, DEFAULT_BUTTON_MAP);
+    assert_eq!(settings.trigger_mode, 0);
+}
+
+#[test]
+fn version_2_keeps_buttons() {
+    let actions = [ButtonAction::Standby as u8; 3];
+
+    let mut settings = modified();
+    settings.decode(&document(2, &[record(BUTTONS, &actions)])).unwrap();
+
+    assert_eq!(settings.buttons[0], [ButtonAction::Standby; 3]);
+    assert_eq!(
+        settings.buttons[1], DEFAULT_BUTTON_MAP[1],
+        "missing actions keep their migrated defaults"
+    );
+    assert_eq!(settings.trigger_mode, 0);
+}
+
+#[test]
+fn version_3_keeps_trigger_mode_and_gets_no_next_preset_code() {
+    let mut settings = modified();
+    settings.decode(&document(3, &[record(TRIGGER_MODE, &[1])])).unwrap();
+
+    assert_eq!(settings.trigger_mode, 1);
+    assert_eq!(settings.ir_codes[IrAction::Mute as usize], Some(CODE));
+    assert_eq!(settings.ir_codes[IrAction::NextPreset as usize], None);
+    assert_eq!(settings.buttons, modified().buttons);
+}
+
+#[test]
+fn version_4_gets_no_source_volumes() {
+    let mut settings = modified();
+    settings.decode(&document(4, &[record(ATTENUATION, &[10])])).unwrap();
+
+    assert!(!settings.source_volume);
+    assert_eq!(settings.source_attenuations, [NO_SOURCE_ATTENUATION; SOURCE_COUNT]);
+    assert_eq!(settings.ir_codes, modified().ir_codes);
+}
+
+#[test]
+fn version_5_keeps_source_volumes() {
+    let mut settings = modified();
+    settings
+        .decode(&document(5, &[record(SOURCE_VOLUMES, &[1, 0xFF, 20, 40])]))
+        .unwrap();
+
+    assert!(settings.source_volume);
+    assert_eq!(
+        settings.source_attenuations,
+        [0xFF, 20, 40, 30, 30],
+        "missing fields keep their previous values"
+    );
+    assert_eq!(settings.supply_threshold_mv, 0);
+}
+
+#[test]
+fn version_6_keeps_supply_threshold() {
+    let mut settings = modified();
+    settings
+        .decode(&document(6, &[record(SUPPLY_THRESHOLD, &9_500u16.to_le_bytes())]))
+        .unwrap();
+
+    assert_eq!(settings.supply_threshold_mv, 9_500);
+    assert_eq!(settings.source_attenuations, modified().source_attenuations);
+    assert_eq!(settings.amplifier_gain, 0);
+}
+
+#[test]
+fn version_7_keeps_amplifier_gain() {
+    let mut settings = modified();
+    settings.decode(&document(7, &[record(AMPLIFIER_GAIN, &[4])])).unwrap();
+
+    assert_eq!(settings.amplifier_gain, 4);
+    assert_eq!(settings.supply_threshold_mv, modified().supply_threshold_mv);
+    assert_eq!(settings.leds, DEFAULT_LED_MAP);
+}
+
+#[test]
+fn version_8_keeps_leds_and_gets_no_crossovers_or_limiters() {
+    let mut settings = modified();
+    settings
+        .decode(&document(8, &[record(LEDS, &[0x00, 0x13, 0x7F])]))
+        .unwrap();
+
+    assert_eq!(settings.leds[LedFunction::UsbSource as usize], LedOutput::None);
+    assert_eq!(settings.leds[LedFunction::SpdifSource as usize], LedOutput::Mcu(3));
+    assert_eq!(
+        settings.leds[LedFunction::RpiSource as usize],
+        LedOutput::None,
+        "unknown LEDs are disabled"
+    );
+    assert_eq!(
+        settings.leds[LedFunction::Mute as usize],
+        LedOutput::Expander(7),
+        "missing LEDs keep their values"
+    );
+    assert_eq!(settings.amplifier_gain, modified().amplifier_gain);
+    assert!(settings
+        .channels
+        .iter()
+        .all(|channel| channel.crossover.is_none() && channel.limiter.is_none()));
+}
+
+#[test]
+fn version_9_keeps_crossovers_and_limiters() {
+    let mut crossover = vec![0, 2];
+    crossover.extend_from_slice(&300.0f32.to_le_bytes());
+    let mut limiter = (-1.0f32).to_le_bytes().to_vec();
+    limiter.extend_from_slice(&50.0f32.to_le_bytes());
+
+    let mut channel = vec![1];
+    channel.extend(record(CROSSOVER, &crossover));
+    channel.extend(record(LIMITER, &limiter));
+
+    let mut settings = modified();
+    settings
+        .decode(&document(9, &[record(CHANNEL, &channel), record(CHANNEL, &[2])]))
+        .unwrap();
+
+    let expected_crossover = CrossoverSettings {
+        high_pass: false,
+        order: 2,
+        frequency_hz: 300.0,
+    };
+    let expected_limiter = LimiterSettings {
+        threshold_db: -1.0,
+        release_ms: 50.0,
+    };
+    assert_eq!(settings.channels[1].crossover, Some(expected_crossover));
+    assert_eq!(settings.channels[1].limiter, Some(expected_limiter));
+    assert_eq!(
+        settings.channels[2].crossover, None,
+        "channel records replace crossovers"
+    );
+    assert_eq!(settings.channels[2].limiter, None, "channel records replace limiters");
+    assert_eq!(
+        settings.channels[0].crossover,
+        Some(CROSSOVER_SETTINGS),
+        "missing channels keep their values"
+    );
+    assert_eq!(settings.channels[0].limiter, Some(LIMITER_SETTINGS));
+}
+
+#[test]
+fn current_version_keeps_missing_values() {
+    let mut settings = modified();
+    settings
+        .decode(&document(MINOR_VERSION, &[record(MUTED, &[0])]))
+        .unwrap();
+
+    let mut expected = modified();
+    expected.muted = false;
+
+    assert_eq!(settings.attenuation, expected.attenuation);
+    assert_eq!(settings.muted, expected.muted);
+    assert_eq!(settings.trigger_mode, expected.trigger_mode);
+    assert_eq!(settings.ir_codes, expected.ir_codes);
+    assert_eq!(settings.buttons, expected.buttons);
+    assert_eq!(settings.source_volume, expected.source_volume);
+    assert_eq!(settings.source_attenuations, expected.source_attenuations);
+    assert_eq!(settings.supply_threshold_mv, expected.supply_threshold_mv);
+    assert_eq!(settings.amplifier_gain, expected.amplifier_gain);
+    assert_eq!(settings.leds, expected.leds);
+    assert_eq!(settings.channels, expected.channels);
+}
+
+#[test]
+fn newer_version_is_not_migrated() {
+    let mut settings = modified();
+    settings
+        .decode(&document(
+            MINOR_VERSION + 1,
+            &[record(0x7F, &[1, 2, 3]), record(ATTENUATION, &[20])],
+        ))
+        .unwrap();
+
+    assert_eq!(settings.attenuation, 20);
+    assert_eq!(settings.ir_codes, modified().ir_codes);
+    assert_eq!(settings.trigger_mode, 1);
+}
+
+#[test]
+fn migrated_settings_encode_with_the_current_version() {
+    let mut settings = modified();
+    settings.decode(&document(0, &[])).unwrap();
+
+    let mut buffer = [0u8; settings::MAX_ENCODED_SIZE];
+    let length = settings.encode(&mut buffer).unwrap();
+
+    assert_eq!(
+        settings::version(&buffer[..length]),
+        Some((MAJOR_VERSION, MINOR_VERSION))
+    );
+
+    let mut decoded = modified();
+    decoded.decode(&buffer[..length]).unwrap();
+
+    assert_eq!(decoded.ir_codes, settings.ir_codes);
+    assert_eq!(decoded.buttons, settings.buttons);
+    assert_eq!(decoded.trigger_mode, settings.trigger_mode);
+    assert_eq!(decoded.source_attenuations, settings.source_attenuations);
+    assert_eq!(decoded.supply_threshold_mv, settings.supply_threshold_mv);
+    assert_eq!(decoded.amplifier_gain, settings.amplifier_gain);
+    assert_eq!(decoded.leds, settings.leds);
+    assert_eq!(decoded.channels, settings.channels);
+}
+
+#[test]
+fn version_of_invalid_data() {
+    assert_eq!(settings::version(b"BLUS"), None);
+    assert_eq!(settings::version(&[0; settings::HEADER_SIZE]), None);
+}
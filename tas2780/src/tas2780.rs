@@ -15,6 +15,27 @@ const PAGE_REGISTER: RegisterAddress = 0x00;
 /// The currently active book
 const BOOK_REGISTER: RegisterAddress = 0x7F;
 
+/// Mode control
+const MODE_CTRL_REGISTER: RegisterAddress = 0x02;
+
+/// Channel configuration, with the amplifier level
+const CHNL_0_REGISTER: RegisterAddress = 0x03;
+
+/// The die temperature exceeded its limit.
+pub const FAULT_OVER_TEMPERATURE: u8 = 1 << 0;
+
+/// The output current exceeded its limit.
+pub const FAULT_OVER_CURRENT: u8 = 1 << 1;
+
+/// The TDM clocks are missing or invalid.
+pub const FAULT_CLOCK_ERROR: u8 = 1 << 2;
+
+/// A DC voltage was detected at the output.
+pub const FAULT_DC_DETECTION: u8 = 1 << 3;
+
+/// The faults that endanger the amplifier or the speaker.
+pub const PROTECTIVE_FAULTS: u8 = FAULT_OVER_TEMPERATURE | FAULT_OVER_CURRENT | FAULT_DC_DETECTION;
+
 #[repr(u8)]
 #[derive(Clone, Copy)]
 pub enum TdmWordLength {
@@ -59,6 +80,47 @@ pub enum Gain {
     Gain21_0dBV = 0x14,
 }
 
+impl Gain {
+    /// All gain settings, in order of their register values.
+    pub const ALL: [Gain; 21] = [
+        Gain::Gain11_0dBV,
+        Gain::Gain11_5dBV,
+        Gain::Gain12_0dBV,
+        Gain::Gain12_5dBV,
+        Gain::Gain13_0dBV,
+        Gain::Gain13_5dBV,
+        Gain::Gain14_0dBV,
+        Gain::Gain14_5dBV,
+        Gain::Gain15_0dBV,
+        Gain::Gain15_5dBV,
+        Gain::Gain16_0dBV,
+        Gain::Gain16_5dBV,
+        Gain::Gain17_0dBV,
+        Gain::Gain17_5dBV,
+        Gain::Gain18_0dBV,
+        Gain::Gain18_5dBV,
+        Gain::Gain19_0dBV,
+        Gain::Gain19_5dBV,
+        Gain::Gain20_0dBV,
+        Gain::Gain20_5dBV,
+        Gain::Gain21_0dBV,
+    ];
+
+    /// The output level at full-scale input in dBV.
+    pub fn dbv(self) -> f32 {
+        11.0 + self as u8 as f32 * 0.5
+    }
+}
+
+impl TryFrom<u8> for Gain {
+    type Error = u8;
+
+    /// Convert a register value (steps of 0.5 dB above 11 dBV) to a gain setting.
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        Gain::ALL.get(value as usize).copied().ok_or(value)
+    }
+}
+
 #[derive(Clone, Copy)]
 /// The amplifier playback channel.
 pub enum Channel {
@@ -67,6 +129,18 @@ pub enum Channel {
     StereoMix,
 }
 
+#[repr(u8)]
+#[derive(Clone, Copy, PartialEq)]
+/// The operating mode of the amplifier.
+pub enum Mode {
+    /// Playback.
+    Active = 0b00,
+    /// Powered up, but with muted output.
+    Muted = 0b01,
+    /// Powered down, with the configuration kept.
+    Shutdown = 0b10,
+}
+
 #[derive(Clone, Copy)]
 /// The amplifier power mode.
 pub enum PowerMode {
@@ -76,6 +150,10 @@ pub enum PowerMode {
     Three,
 }
 
+#[derive(Clone, Copy, PartialEq, Debug, defmt::Format)]
+/// The power mode of the configuration is not supported by the driver, which only supports [`PowerMode::Two`].
+pub struct UnsupportedPowerMode;
+
 #[repr(u8)]
 #[derive(Clone, Copy)]
 /// The duration of the noise gate hysteresis.
@@ -193,7 +271,7 @@ where
         self.write_register(BOOK_REGISTER, value)
     }
 
-    fn _read(&mut self, address: u8, read: &mut [u8]) {
+    fn read(&mut self, address: u8, read: &mut [u8]) {
         let address: [u8; 1] = [address];
 
         self.i2c
@@ -234,20 +312,127 @@ where
 
     pub fn enable(&mut self) {
         debug!("Enabling TAS2780 at address {}", self.address);
+        self.power_up(Mode::Active);
+    }
+
+    /// Power up the amplifier like [`Tas2780::enable`], but with a muted output.
+    pub fn enable_muted(&mut self) {
+        debug!("Enabling TAS2780 at address {} (muted)", self.address);
+        self.power_up(Mode::Muted);
+    }
 
+    fn power_up(&mut self, mode: Mode) {
         // Set up power mode, and activate
         match self.config.power_mode {
             PowerMode::Two => {
                 self.set_page(0x00);
-                self.write_register(0x03, 0b11 << 6 | (self.config.gain as u8) << 1); // PWR_MODE2
+                self.write_register(CHNL_0_REGISTER, 0b11 << 6 | (self.config.gain as u8) << 1); // PWR_MODE2
                 self.write_register(0x04, 0xA1); // Use internal LDO
                 self.write_register(0x71, 0x0E); // PVDD undervoltage lockout 6.5 V
-                self.write_register(0x02, 0x80); // Power up playback with I-sense, V-sense enabled
+                self.set_mode(mode); // Power up playback with I-sense, V-sense enabled
             }
             _ => todo!("Unsupported power mode"),
         }
     }
 
+    /// Set the operating mode: playback, muted, or shut down.
+    pub fn set_mode(&mut self, mode: Mode) {
+        self.set_page(0);
+
+        // Keep I-sense and V-sense enabled.
+        self.write_register(MODE_CTRL_REGISTER, 0x80 | mode as u8);
+    }
+
+    /// Mute or unmute the output, while the amplifier stays powered up.
+    pub fn set_muted(&mut self, muted: bool) {
+        self.set_mode(if muted { Mode::Muted } else { Mode::Active });
+    }
+
+    /// Power down the amplifier. It keeps its configuration, and resumes with [`Tas2780::enable`].
+    pub fn shutdown(&mut self) {
+        debug!("Shutting down TAS2780 at address {}", self.address);
+        self.set_mode(Mode::Shutdown);
+    }
+
+    /// Set the analog gain of the amplifier. Fails in unsupported power modes, and keeps the gain then.
+    pub fn set_gain(&mut self, gain: Gain) -> Result<(), UnsupportedPowerMode> {
+        match self.config.power_mode {
+            PowerMode::Two => {
+                self.config.gain = gain;
+                self.set_page(0);
+                self.write_register(CHNL_0_REGISTER, 0b11 << 6 | (gain as u8) << 1); // PWR_MODE2
+                Ok(())
+            }
+            _ => Err(UnsupportedPowerMode),
+        }
+    }
+
+    /// Read the faults that were latched since the last call, and clear them.
+    ///
+    /// Returns the fault bits [`FAULT_OVER_TEMPERATURE`], [`FAULT_OVER_CURRENT`], [`FAULT_CLOCK_ERROR`], and
+    /// [`FAULT_DC_DETECTION`].
+    pub fn take_faults(&mut self) -> u8 {
+        self.set_page(0);
+
+        /// Latched interrupts 0: over-temperature (bit 0), over-current (bit 1), and TDM clock error (bit 2)
+        const INT_LTCH0_REGISTER: RegisterAddress = 0x49;
+
+        /// Latched interrupts 1, with the DC detection error
+        const INT_LTCH1_REGISTER: RegisterAddress = 0x4A;
+
+        /// The DC detection error in the latched interrupts 1
+        const IT_DC_DETECT: RegisterValue = 1 << 2;
+
+        /// Interrupt and clock configuration
+        const INT_CLK_CFG_REGISTER: RegisterAddress = 0x5C;
+
+        /// Clears all latched interrupts
+        const CLR_INT: RegisterValue = 1 << 2;
+
+        let mut latched = [0u8; 2];
+        self.read(INT_LTCH0_REGISTER, &mut latched[..1]);
+        self.read(INT_LTCH1_REGISTER, &mut latched[1..]);
+
+        let mut int_clk_cfg = [0u8];
+        self.read(INT_CLK_CFG_REGISTER, &mut int_clk_cfg);
+        self.write_register(INT_CLK_CFG_REGISTER, int_clk_cfg[0] | CLR_INT);
+
+        let mut faults = latched[0] & (FAULT_OVER_TEMPERATURE | FAULT_OVER_CURRENT | FAULT_CLOCK_ERROR);
+
+        if latched[1] & IT_DC_DETECT != 0 {
+            faults |= FAULT_DC_DETECTION;
+        }
+
+        faults
+    }
+
+    /// Read the die temperature in °C. Only valid while the amplifier is powered up.
+    pub fn temperature(&mut self) -> f32 {
+        self.set_page(0);
+
+        /// Die temperature, upper eight of twelve bits (followed by the lower four bits in the next register)
+        const TEMP_MSB_REGISTER: RegisterAddress = 0x29;
+
+        /// The temperature at a reading of zero
+        const TEMPERATURE_OFFSET: f32 = -93.0;
+
+        let mut temperature = [0u8; 2];
+        self.read(TEMP_MSB_REGISTER, &mut temperature);
+
+        let raw = (temperature[0] as u16) << 4 | (temperature[1] >> 4) as u16;
+        raw as f32 / 16.0 + TEMPERATURE_OFFSET
+    }
+
+    /// Check that the amplifier responds, e.g. after releasing its hardware shutdown. Forgets the selected page and
+    /// book, which the amplifier resets when it powers up.
+    pub fn probe(&mut self) -> Result<(), I2C::Error> {
+        self.page = None;
+        self.book = None;
+
+        let mut page = [0u8; 1];
+        self.i2c.write_read(self.address, &[PAGE_REGISTER], &mut page)
+    }
+
     pub fn config(&self) -> Config {
         self.config
     }
@@ -0,0 +1,93 @@
+//! The explicit feedback of the USB audio class, which tells the host the rate at which the device plays samples.
+//!
+//! The device counts the ticks of a timer, which runs from the audio clock, over a refresh period of some USB
+//! (micro)frames. The feedback is the number of samples per (micro)frame as a fixed-point number: 10.14 in three bytes
+//! for full-speed, and 16.16 in four bytes for high-speed devices. The fraction that is lost by rounding carries over
+//! to the next value, such that the host sees the exact rate on average.
+use crate::StreamConfig;
+
+/// The maximum size of a feedback value.
+pub const MAX_FEEDBACK_SIZE: usize = 4;
+
+/// The format of feedback values, by the speed of the USB device.
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum FeedbackFormat {
+    /// 10.14 in three bytes.
+    FullSpeed,
+    /// 16.16 in four bytes.
+    HighSpeed,
+}
+
+impl FeedbackFormat {
+    /// The number of fractional bits.
+    pub const fn fraction_bits(&self) -> u32 {
+        match self {
+            FeedbackFormat::FullSpeed => 14,
+            FeedbackFormat::HighSpeed => 16,
+        }
+    }
+
+    /// The size of a value in bytes.
+    pub const fn size(&self) -> usize {
+        match self {
+            FeedbackFormat::FullSpeed => 3,
+            FeedbackFormat::HighSpeed => 4,
+        }
+    }
+
+    /// The number of (micro)frames per second: frames of 1 ms at full-speed, and microframes of 125 µs at high-speed.
+    pub const fn frames_per_second(&self) -> u32 {
+        match self {
+            FeedbackFormat::FullSpeed => 1000,
+            FeedbackFormat::HighSpeed => 8000,
+        }
+    }
+}
+
+/// The feedback value of a stream at its nominal sample rate, rounded down. The values of a [`FeedbackEncoder`]
+/// average to it, if the feedback counter runs at its nominal tick rate.
+pub const fn nominal_value(config: &StreamConfig) -> u32 {
+    let format = config.feedback_format;
+    (((config.sample_rate_hz as u64) << format.fraction_bits()) / format.frames_per_second() as u64) as u32
+}
+
+/// Converts counted ticks into feedback values.
+pub struct FeedbackEncoder {
+    format: FeedbackFormat,
+    /// The feedback value per tick is `numerator / denominator`.
+    numerator: u64,
+    denominator: u64,
+    /// The remainder of the last value, in units of `1 / denominator`.
+    rest: u64,
+}
+
+impl FeedbackEncoder {
+    /// Create an encoder for the feedback of a stream.
+    pub const fn new(config: &StreamConfig) -> Self {
+        FeedbackEncoder {
+            format: config.feedback_format,
+            numerator: (config.sample_rate_hz as u64) << config.feedback_format.fraction_bits(),
+            denominator: config.feedback_counter_tick_rate_hz as u64 * config.feedback_refresh_frame_count as u64,
+            rest: 0,
+        }
+    }
+
+    /// The format of the values.
+    pub fn format(&self) -> FeedbackFormat {
+        self.format
+    }
+
+    /// The feedback value for the ticks of a refresh period.
+    pub fn value(&mut self, ticks: u32) -> u32 {
+        let total = ticks as u64 * self.numerator + self.rest;
+        self.rest = total % self.denominator;
+
+        (total / self.denominator) as u32
+    }
+
+    /// The feedback packet for the ticks of a refresh period. Only the first [`FeedbackFormat::size`] bytes are sent.
+    pub fn encode(&mut self, ticks: u32) -> [u8; MAX_FEEDBACK_SIZE] {
+        self.value(ticks).to_le_bytes()
+    }
+}
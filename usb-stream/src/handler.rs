@@ -0,0 +1,103 @@
+//! Handlers of the endpoints and controls of a USB audio speaker, generic over the USB driver of the board.
+use audio::db_to_linear;
+use embassy_sync::blocking_mutex::raw::RawMutex;
+use embassy_sync::signal::Signal;
+use embassy_usb::class::uac1::{self, speaker};
+use embassy_usb::driver::{Driver, EndpointError};
+
+use crate::{samples, FeedbackEncoder, StreamConfig};
+
+/// The endpoints were disabled, as the host deconfigured the device, or it was disconnected.
+struct Disconnected {}
+
+impl From<EndpointError> for Disconnected {
+    fn from(val: EndpointError) -> Self {
+        match val {
+            EndpointError::BufferOverflow => panic!("Buffer overflow"),
+            EndpointError::Disabled => Disconnected {},
+        }
+    }
+}
+
+/// The receiver of the sample blocks of a stream, e.g. a channel or ring buffer to the audio routing of a board.
+#[allow(async_fn_in_trait)]
+pub trait SampleSink {
+    /// Take the samples of a packet, interleaved by channel.
+    async fn send(&mut self, samples: impl ExactSizeIterator<Item = u32>);
+
+    /// Learn about a packet of `size` bytes, which was skipped, since it did not hold whole samples.
+    fn skip(&mut self, _size: usize) {}
+}
+
+async fn stream_handler<'d, D: Driver<'d>>(
+    stream: &mut speaker::Stream<'d, D>,
+    packet: &mut [u8],
+    sink: &mut impl SampleSink,
+) -> Result<(), Disconnected> {
+    loop {
+        let size = stream.read_packet(packet).await?;
+
+        match samples(&packet[..size]) {
+            Some(samples) => sink.send(samples).await,
+            None => sink.skip(size),
+        }
+    }
+}
+
+async fn feedback_handler<'d, D: Driver<'d>, M: RawMutex>(
+    feedback: &mut speaker::Feedback<'d, D>,
+    ticks: &Signal<M, u32>,
+    encoder: &mut FeedbackEncoder,
+) -> Result<(), Disconnected> {
+    loop {
+        let packet = encoder.encode(ticks.wait().await);
+        feedback.write_packet(&packet[..encoder.format().size()]).await?;
+    }
+}
+
+/// Stream samples from the host into a sink, whenever the host is connected.
+///
+/// Packets are read into `packet`, which must hold the maximum packet size of the endpoint.
+pub async fn stream<'d, D: Driver<'d>>(
+    stream: &mut speaker::Stream<'d, D>,
+    packet: &mut [u8],
+    sink: &mut impl SampleSink,
+) -> ! {
+    loop {
+        stream.wait_connection().await;
+        _ = stream_handler(stream, packet, sink).await;
+    }
+}
+
+/// Send feedback to the host, whenever it is connected, from the feedback counter `ticks` of every refresh period.
+pub async fn feedback<'d, D: Driver<'d>, M: RawMutex>(
+    feedback: &mut speaker::Feedback<'d, D>,
+    ticks: &Signal<M, u32>,
+    config: &StreamConfig,
+) -> ! {
+    loop {
+        feedback.wait_connection().await;
+
+        let mut encoder = FeedbackEncoder::new(config);
+        _ = feedback_handler(feedback, ticks, &mut encoder).await;
+    }
+}
+
+/// The linear gains of the host's volumes of some channels. Muted channels have a gain of zero.
+///
+/// Panics, if the host sets a positive volume, which the volume range of the speaker excludes.
+pub fn channel_gains<const N: usize>(
+    control_monitor: &speaker::ControlMonitor<'_>,
+    channels: [uac1::Channel; N],
+) -> [f32; N] {
+    channels.map(|channel| match control_monitor.volume(channel).unwrap() {
+        speaker::Volume::Muted => 0.0,
+        speaker::Volume::DeciBel(volume_db) => {
+            if volume_db > 0.0 {
+                panic!("Volume must not be positive.")
+            }
+
+            db_to_linear(volume_db)
+        }
+    })
+}
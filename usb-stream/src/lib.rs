@@ -0,0 +1,48 @@
+//! The board-agnostic USB audio streaming: samples from the host, explicit feedback, and the host's volume.
+//!
+//! Boards differ in their peripherals (pins, the USB instance, and SAI or I2S outputs), and in where the samples go.
+//! They describe their stream in a [`StreamConfig`], and receive sample blocks through a [`handler::SampleSink`]. Since
+//! embassy tasks cannot be generic, every board keeps thin tasks for its USB driver, which run the handlers of this
+//! crate.
+//!
+//! The handlers build on the embassy USB stack, and are left out without the `handler` feature (enabled by default),
+//! for boards that bring their own embassy and only use the feedback encoding.
+#![no_std]
+
+pub mod feedback;
+#[cfg(feature = "handler")]
+pub mod handler;
+
+pub use feedback::{FeedbackEncoder, FeedbackFormat};
+
+/// The size of a sample in the stream, which carries 32 bit samples.
+pub const SAMPLE_SIZE: usize = 4;
+
+/// The parameters of a board's stream.
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct StreamConfig {
+    /// The sample rate in Hz.
+    pub sample_rate_hz: u32,
+    /// The tick rate of the feedback counter, which runs from the audio clock, in Hz.
+    pub feedback_counter_tick_rate_hz: u32,
+    /// The number of (micro)frames between feedback values.
+    pub feedback_refresh_frame_count: u32,
+    /// The format of the feedback, by the speed of the USB device.
+    pub feedback_format: FeedbackFormat,
+}
+
+/// The samples of a packet of the stream, interleaved by channel.
+///
+/// Gives `None` for packets that do not hold whole samples.
+pub fn samples(packet: &[u8]) -> Option<impl ExactSizeIterator<Item = u32> + '_> {
+    if packet.len() % SAMPLE_SIZE != 0 {
+        return None;
+    }
+
+    Some(
+        packet
+            .chunks_exact(SAMPLE_SIZE)
+            .map(|bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])),
+    )
+}
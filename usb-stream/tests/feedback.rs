@@ -0,0 +1,133 @@
+use usb_stream::feedback::nominal_value;
+use usb_stream::{samples, FeedbackEncoder, FeedbackFormat, StreamConfig};
+
+/// The stream of the Blus Mini Mk2 at full-speed: 24.576 MHz ticks, refreshed every 8 frames.
+const FULL_SPEED: StreamConfig = StreamConfig {
+    sample_rate_hz: 48_000,
+    feedback_counter_tick_rate_hz: 24_576_000,
+    feedback_refresh_frame_count: 8,
+    feedback_format: FeedbackFormat::FullSpeed,
+};
+
+#[test]
+fn encodes_nominal_rate() {
+    let mut encoder = FeedbackEncoder::new(&FULL_SPEED);
+
+    // 48 samples per frame in 10.14.
+    assert_eq!(encoder.value(24_576 * 8), 48 << 14);
+    assert_eq!(
+        encoder.encode(24_576 * 8)[..FeedbackFormat::FullSpeed.size()],
+        [0x00, 0x00, 0x0C]
+    );
+
+    let mut encoder = FeedbackEncoder::new(&StreamConfig {
+        feedback_format: FeedbackFormat::HighSpeed,
+        ..FULL_SPEED
+    });
+
+    // 48 samples per frame in 16.16, for four bytes.
+    assert_eq!(encoder.encode(24_576 * 8), (48u32 << 16).to_le_bytes());
+}
+
+#[test]
+fn carries_rounding_over() {
+    let mut encoder = FeedbackEncoder::new(&StreamConfig {
+        feedback_counter_tick_rate_hz: 21_000_000,
+        ..FULL_SPEED
+    });
+
+    // One tick more than nominal gives 48.0003 samples per frame, between two values, which alternate, such that
+    // their sum stays exact.
+    let values: Vec<u32> = (0..1000).map(|_| encoder.value(21_000 * 8 + 1)).collect();
+    let exact = (21_000 * 8 + 1) as u64 * (48_000 << 14) / (21_000_000 * 8);
+
+    assert!(values
+        .iter()
+        .all(|value| *value as u64 == exact || *value as u64 == exact + 1));
+    assert_eq!(
+        values.iter().map(|value| *value as u64).sum::<u64>(),
+        1000 * (21_000 * 8 + 1) * (48_000 << 14) / (21_000_000 * 8)
+    );
+}
+
+/// Sample rates with the tick rate of their audio clock, which is a multiple of the sample rate.
+const RATES: [(u32, u32); 3] = [(44_100, 22_579_200), (48_000, 24_576_000), (96_000, 24_576_000)];
+
+/// Both formats, with refresh periods of 8 ms.
+const FORMATS: [(FeedbackFormat, u32); 2] = [(FeedbackFormat::FullSpeed, 8), (FeedbackFormat::HighSpeed, 64)];
+
+/// The configurations of all sample rates at both speeds.
+fn configs() -> impl Iterator<Item = StreamConfig> {
+    RATES
+        .into_iter()
+        .flat_map(|(sample_rate_hz, feedback_counter_tick_rate_hz)| {
+            FORMATS
+                .into_iter()
+                .map(move |(feedback_format, feedback_refresh_frame_count)| StreamConfig {
+                    sample_rate_hz,
+                    feedback_counter_tick_rate_hz,
+                    feedback_refresh_frame_count,
+                    feedback_format,
+                })
+        })
+}
+
+/// The ticks that a counter at the nominal tick rate counts in every refresh period. Periods of a fractional tick
+/// count alternate between the neighboring integers.
+fn nominal_ticks(config: &StreamConfig, period_count: u64) -> impl Iterator<Item = u32> {
+    let ticks_per_period = config.feedback_counter_tick_rate_hz as u64 * config.feedback_refresh_frame_count as u64;
+    let frames_per_second = config.feedback_format.frames_per_second() as u64;
+    let total = move |period: u64| period * ticks_per_period / frames_per_second;
+
+    (1..=period_count).map(move |period| (total(period) - total(period - 1)) as u32)
+}
+
+#[test]
+fn computes_nominal_values() {
+    // 44.1 and 5.5125, 48 and 6, and 96 and 12 samples per (micro)frame.
+    let expected = [722_534, 361_267, 48 << 14, 6 << 16, 96 << 14, 12 << 16];
+
+    for (config, expected) in configs().zip(expected) {
+        assert_eq!(nominal_value(&config), expected, "{:?}", config);
+    }
+}
+
+#[test]
+fn encodes_nominal_rates() {
+    const PERIOD_COUNT: u64 = 1000;
+
+    for config in configs() {
+        let mut encoder = FeedbackEncoder::new(&config);
+        let nominal = nominal_value(&config) as u64;
+        let max_value = (1u64 << (8 * config.feedback_format.size())) - 1;
+
+        let tick_value = ((config.sample_rate_hz as u64) << config.feedback_format.fraction_bits())
+            .div_ceil(config.feedback_counter_tick_rate_hz as u64 * config.feedback_refresh_frame_count as u64);
+
+        let values: Vec<u64> = nominal_ticks(&config, PERIOD_COUNT)
+            .map(|ticks| encoder.value(ticks) as u64)
+            .collect();
+
+        // Values only vary by the rounded tick count of their period and the rounded fraction, and fit their format.
+        assert!(
+            values.iter().all(|value| value.abs_diff(nominal) <= tick_value),
+            "{:?}",
+            config
+        );
+        assert!(values.iter().all(|value| *value <= max_value), "{:?}", config);
+
+        // On average, they are the exact number of samples per (micro)frame, in the format.
+        let exact = ((config.sample_rate_hz as u64 * PERIOD_COUNT) << config.feedback_format.fraction_bits())
+            / config.feedback_format.frames_per_second() as u64;
+        assert_eq!(values.iter().sum::<u64>(), exact, "{:?}", config);
+    }
+}
+
+#[test]
+fn decodes_samples() {
+    let packet = [0x01, 0x00, 0x00, 0x80, 0xFF, 0xFF, 0xFF, 0x7F];
+    let decoded: Vec<u32> = samples(&packet).unwrap().collect();
+
+    assert_eq!(decoded, [0x8000_0001, 0x7FFF_FFFF]);
+    assert!(samples(&packet[..6]).is_none());
+}